@@ -0,0 +1,19 @@
+//! Feeds arbitrary text to the SQL parser: it must return Ok or Err, never
+//! panic, overflow the stack or allocate without bound.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pardusdb::{ParseLimits, is_complete_statement, parse_with_limits, split_statements};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(sql) = std::str::from_utf8(data) else {
+        return;
+    };
+    let limits = ParseLimits { max_sql_len: 64 * 1024, ..ParseLimits::default() };
+    let _ = parse_with_limits(sql, &limits);
+    let _ = is_complete_statement(sql);
+    for statement in split_statements(sql) {
+        let _ = parse_with_limits(&statement, &limits);
+    }
+});
@@ -0,0 +1,468 @@
+//! Text analyzers: split text into the terms keyword matching compares.
+//!
+//! An analyzer lowercases text, splits it into words, drops stopwords and
+//! stems what is left for its language. Runs of CJK characters, which are
+//! written without spaces, become overlapping two-character terms. With
+//! `ngram=n`, words become their character n-grams instead, which matches
+//! word parts and tolerates typos.
+//!
+//! Analyzers are written as a comma-separated spec, such as `'english'`
+//! or `'german, stopwords=off, ngram=3'`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::error::{MarsError, Result};
+
+/// Languages with stopword lists and stemmers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    /// No stopwords or stemming
+    #[default]
+    Simple,
+    English,
+    French,
+    German,
+    Spanish,
+}
+
+impl Language {
+    /// Look up a language by (case-insensitive) name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "simple" => Language::Simple,
+            "english" | "en" => Language::English,
+            "french" | "fr" => Language::French,
+            "german" | "de" => Language::German,
+            "spanish" | "es" => Language::Spanish,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Simple => "simple",
+            Language::English => "english",
+            Language::French => "french",
+            Language::German => "german",
+            Language::Spanish => "spanish",
+        }
+    }
+
+    fn stopwords(&self) -> &'static [&'static str] {
+        match self {
+            Language::Simple => &[],
+            Language::English => &[
+                "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+                "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+                "they", "this", "to", "was", "will", "with",
+            ],
+            Language::French => &[
+                "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "et", "il", "je",
+                "la", "le", "les", "leur", "lui", "ma", "mais", "me", "mon", "ne", "nous", "on", "ou", "par",
+                "pas", "pour", "qu", "que", "qui", "sa", "se", "ses", "son", "sur", "ta", "te", "un", "une",
+                "vous",
+            ],
+            Language::German => &[
+                "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "das", "dem", "den", "der", "des",
+                "die", "ein", "eine", "einem", "einen", "einer", "es", "für", "im", "in", "ist", "mit",
+                "nicht", "oder", "sich", "sie", "sind", "und", "von", "war", "wie", "zu", "zum", "zur",
+            ],
+            Language::Spanish => &[
+                "a", "al", "como", "con", "de", "del", "el", "en", "es", "la", "las", "lo", "los", "más",
+                "mi", "no", "o", "para", "pero", "por", "que", "se", "si", "su", "sus", "un", "una", "y",
+            ],
+        }
+    }
+
+    /// Light stemming: strips common inflections, not derivations
+    fn stem(&self, word: &str) -> String {
+        match self {
+            Language::Simple => word.to_string(),
+            Language::English => stem_english(word),
+            Language::French => stem_french(word),
+            Language::German => stem_german(word),
+            Language::Spanish => stem_spanish(word),
+        }
+    }
+}
+
+/// A term and where it came from in the analyzed text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub term: String,
+    /// Byte range of the source text the term was made from
+    pub start: usize,
+    pub end: usize,
+}
+
+/// How text is turned into terms
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analyzer {
+    pub language: Language,
+    /// Drop the language's stopwords
+    pub stopwords: bool,
+    /// Reduce words to their stems
+    pub stem: bool,
+    /// Index words as character n-grams of this length instead
+    pub ngram: Option<usize>,
+    /// Split CJK runs into overlapping bigrams; otherwise each run is one term
+    pub cjk_bigrams: bool,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Analyzer { language: Language::Simple, stopwords: true, stem: true, ngram: None, cjk_bigrams: true }
+    }
+}
+
+impl Analyzer {
+    /// Parse a spec such as `'english, stopwords=off, ngram=3'`: an
+    /// optional language followed by `stopwords`, `stem` and `cjk` (`on` or
+    /// `off`) and `ngram` (a length from 1 to 16)
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut analyzer = Analyzer::default();
+        for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let Some((key, value)) = item.split_once('=') else {
+                analyzer.language = Language::from_name(item)
+                    .ok_or_else(|| MarsError::InvalidFormat(format!("Unknown analyzer language: {}", item)))?;
+                continue;
+            };
+            let (key, value) = (key.trim().to_lowercase(), value.trim().to_lowercase());
+            let invalid = || MarsError::InvalidFormat(format!("Invalid value for analyzer option {}: {}", key, value));
+            let switch = || match value.as_str() {
+                "on" | "true" => Ok(true),
+                "off" | "false" => Ok(false),
+                _ => Err(invalid()),
+            };
+            match key.as_str() {
+                "stopwords" => analyzer.stopwords = switch()?,
+                "stem" => analyzer.stem = switch()?,
+                "cjk" => analyzer.cjk_bigrams = switch()?,
+                "ngram" => analyzer.ngram = Some(value.parse().ok().filter(|n| (1..=16).contains(n)).ok_or_else(invalid)?),
+                _ => return Err(MarsError::InvalidFormat(format!("Unknown analyzer option: {}", key))),
+            }
+        }
+        Ok(analyzer)
+    }
+
+    /// The terms of `text`, in order
+    pub fn terms(&self, text: &str) -> Vec<String> {
+        self.tokens(text).into_iter().map(|token| token.term).collect()
+    }
+
+    /// The terms of `text` with their positions, in order
+    pub fn tokens(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for (start, word, cjk) in words(text) {
+            let end = start + word.len();
+            if cjk {
+                self.cjk_tokens(word, start, &mut tokens);
+                continue;
+            }
+            let lower = word.to_lowercase();
+            if self.stopwords && self.language.stopwords().contains(&lower.as_str()) {
+                continue;
+            }
+            match self.ngram {
+                Some(n) => ngram_tokens(word, start, n, &mut tokens),
+                None => {
+                    let term = if self.stem { self.language.stem(&lower) } else { lower };
+                    tokens.push(Token { term, start, end });
+                }
+            }
+        }
+        tokens
+    }
+
+    /// An excerpt of `text` of up to `max_words` words around the terms
+    /// of `query`, which are wrapped in `<b>` and `</b>`. `...` marks text
+    /// left out at either end. The excerpt is the stretch with the most
+    /// distinct query terms, or the start of the text if none occur.
+    pub fn snippet(&self, text: &str, query: &str, max_words: usize) -> String {
+        let max_words = max_words.max(1);
+        let wanted: HashSet<String> = self.terms(query).into_iter().collect();
+        let hits: Vec<Token> = self.tokens(text).into_iter().filter(|t| wanted.contains(&t.term)).collect();
+        // Window positions: words, and single characters of CJK runs
+        let words: Vec<(usize, usize)> = words(text).into_iter()
+            .flat_map(|(start, word, cjk)| -> Vec<(usize, usize)> {
+                if cjk {
+                    word.char_indices().map(|(i, c)| (start + i, start + i + c.len_utf8())).collect()
+                } else {
+                    vec![(start, start + word.len())]
+                }
+            })
+            .collect();
+        if words.is_empty() {
+            return String::new();
+        }
+
+        // Query terms found in each word
+        let mut found: Vec<Vec<&str>> = vec![Vec::new(); words.len()];
+        for hit in &hits {
+            let first = words.partition_point(|w| w.1 <= hit.start);
+            for i in (first..words.len()).take_while(|&i| words[i].0 < hit.end) {
+                found[i].push(&hit.term);
+            }
+        }
+
+        let len = max_words.min(words.len());
+        let mut best = (0, 0, 0);
+        for start in 0..=words.len() - len {
+            let window = &found[start..start + len];
+            let distinct: HashSet<&str> = window.iter().flatten().copied().collect();
+            let total = window.iter().filter(|terms| !terms.is_empty()).count();
+            if (distinct.len(), total) > (best.0, best.1) {
+                best = (distinct.len(), total, start);
+            }
+        }
+        // Center the matches in the window
+        let mut start = best.2;
+        let window = &found[start..start + len];
+        if let (Some(first), Some(last)) = (window.iter().position(|t| !t.is_empty()), window.iter().rposition(|t| !t.is_empty())) {
+            let slack = len - (last - first + 1);
+            start = (start + first).saturating_sub(slack / 2).min(words.len() - len);
+        }
+        let end = start + len;
+
+        // Keep punctuation around the text's first and last words
+        let from = if start == 0 { 0 } else { words[start].0 };
+        let to = if end == words.len() { text.len() } else { words[end - 1].1 };
+        let mut marks: Vec<(usize, usize)> = hits.iter()
+            .filter(|hit| hit.start >= from && hit.end <= to)
+            .map(|hit| (hit.start, hit.end))
+            .collect();
+        marks.sort_unstable();
+        // Overlapping terms such as CJK bigrams share one highlight
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (s, e) in marks {
+            match merged.last_mut() {
+                Some(last) if s <= last.1 => last.1 = last.1.max(e),
+                _ => merged.push((s, e)),
+            }
+        }
+
+        let mut out = String::new();
+        if start > 0 {
+            out.push_str("...");
+        }
+        let mut pos = from;
+        for (s, e) in merged {
+            out.push_str(&text[pos..s]);
+            out.push_str("<b>");
+            out.push_str(&text[s..e]);
+            out.push_str("</b>");
+            pos = e;
+        }
+        out.push_str(&text[pos..to]);
+        if end < words.len() {
+            out.push_str("...");
+        }
+        out
+    }
+
+    fn cjk_tokens(&self, run: &str, start: usize, tokens: &mut Vec<Token>) {
+        let chars: Vec<(usize, char)> = run.char_indices().collect();
+        if !self.cjk_bigrams || chars.len() == 1 {
+            tokens.push(Token { term: run.to_string(), start, end: start + run.len() });
+            return;
+        }
+        for pair in chars.windows(2) {
+            let (from, to) = (pair[0].0, pair[1].0 + pair[1].1.len_utf8());
+            tokens.push(Token { term: run[from..to].to_string(), start: start + from, end: start + to });
+        }
+    }
+}
+
+impl fmt::Display for Analyzer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let switch = |on: bool| if on { "on" } else { "off" };
+        write!(f, "{}, stopwords={}, stem={}, cjk={}", self.language.name(), switch(self.stopwords), switch(self.stem), switch(self.cjk_bigrams))?;
+        if let Some(n) = self.ngram {
+            write!(f, ", ngram={}", n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `c` belongs to a script written without spaces between words
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF     // Hiragana, Katakana
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF   // Hangul syllables
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0x20000..=0x2FA1F // CJK Extensions B and later
+    )
+}
+
+/// Runs of letters and digits as `(byte offset, run, is CJK)`
+fn words(text: &str) -> Vec<(usize, &str, bool)> {
+    let mut words = Vec::new();
+    let mut current: Option<(usize, bool)> = None;
+    for (i, c) in text.char_indices() {
+        let kind = c.is_alphanumeric().then(|| is_cjk(c));
+        match (current, kind) {
+            (Some((_, cjk)), Some(kind)) if cjk == kind => {}
+            (Some((start, cjk)), _) => {
+                words.push((start, &text[start..i], cjk));
+                current = kind.map(|kind| (i, kind));
+            }
+            (None, _) => current = kind.map(|kind| (i, kind)),
+        }
+    }
+    if let Some((start, cjk)) = current {
+        words.push((start, &text[start..], cjk));
+    }
+    words
+}
+
+fn ngram_tokens(word: &str, start: usize, n: usize, tokens: &mut Vec<Token>) {
+    let bounds: Vec<usize> = word.char_indices().map(|(i, _)| i).chain([word.len()]).collect();
+    if bounds.len() <= n + 1 {
+        tokens.push(Token { term: word.to_lowercase(), start, end: start + word.len() });
+        return;
+    }
+    for window in bounds.windows(n + 1) {
+        let (from, to) = (window[0], window[n]);
+        tokens.push(Token { term: word[from..to].to_lowercase(), start: start + from, end: start + to });
+    }
+}
+
+/// `word` without `suffix` if at least `min` characters would be left
+fn strip<'a>(word: &'a str, suffix: &str, min: usize) -> Option<&'a str> {
+    word.strip_suffix(suffix).filter(|stem| stem.chars().count() >= min)
+}
+
+fn has_vowel(word: &str) -> bool {
+    word.chars().any(|c| "aeiouy".contains(c))
+}
+
+fn stem_english(word: &str) -> String {
+    let mut stem = if let Some(s) = strip(word, "ies", 2) {
+        format!("{}y", s)
+    } else if word.ends_with("ss") || word.ends_with("us") || word.ends_with("is") {
+        word.to_string()
+    } else if let Some(s) = strip(word, "es", 3).filter(|s| ["sh", "ch", "x", "z"].iter().any(|end| s.ends_with(end))) {
+        s.to_string()
+    } else {
+        strip(word, "s", 3).unwrap_or(word).to_string()
+    };
+
+    for suffix in ["ing", "ed"] {
+        if let Some(s) = strip(&stem, suffix, 3).filter(|s| has_vowel(s)) {
+            let mut s = s.to_string();
+            // running -> run, stopped -> stop
+            let bytes = s.as_bytes();
+            if bytes.len() >= 2 && bytes[bytes.len() - 1] == bytes[bytes.len() - 2] && !b"lsz".contains(&bytes[bytes.len() - 1]) {
+                s.pop();
+            }
+            stem = s;
+            break;
+        }
+    }
+    if let Some(s) = strip(&stem, "ly", 4) {
+        stem = s.to_string();
+    }
+    stem
+}
+
+fn stem_french(word: &str) -> String {
+    let word = strip(word, "x", 3).or_else(|| strip(word, "s", 3)).unwrap_or(word);
+    let word = strip(word, "ement", 3).unwrap_or(word);
+    strip(word, "e", 3).unwrap_or(word).to_string()
+}
+
+fn stem_german(word: &str) -> String {
+    let word: String = word.chars()
+        .flat_map(|c| match c {
+            'ä' => vec!['a'],
+            'ö' => vec!['o'],
+            'ü' => vec!['u'],
+            'ß' => vec!['s', 's'],
+            c => vec![c],
+        })
+        .collect();
+    for suffix in ["ern", "em", "en", "er", "es", "e", "s", "n"] {
+        if let Some(stem) = strip(&word, suffix, 3) {
+            return stem.to_string();
+        }
+    }
+    word
+}
+
+fn stem_spanish(word: &str) -> String {
+    if let Some(stem) = strip(word, "ces", 2) {
+        return format!("{}z", stem);
+    }
+    let word = strip(word, "es", 3).or_else(|| strip(word, "s", 3)).unwrap_or(word);
+    for suffix in ["a", "o", "e"] {
+        if let Some(stem) = strip(word, suffix, 3) {
+            return stem.to_string();
+        }
+    }
+    word.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_english() {
+        let analyzer = Analyzer::parse("english").unwrap();
+        assert_eq!(
+            analyzer.terms("The cats were running to the Policies, quickly!"),
+            vec!["cat", "were", "run", "policy", "quick"],
+        );
+        assert_eq!(analyzer.terms("boxes stopped glass"), vec!["box", "stop", "glass"]);
+
+        let tokens = analyzer.tokens("Hello, wörld");
+        assert_eq!(tokens[1], Token { term: "wörld".into(), start: 7, end: 13 });
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(Analyzer::parse("english, stopwords=off, stem=off").unwrap().terms("The Cats"), vec!["the", "cats"]);
+        assert_eq!(Analyzer::parse("german").unwrap().terms("Die Häuser und Bücher"), vec!["haus", "buch"]);
+        assert_eq!(Analyzer::parse("spanish").unwrap().terms("las luces rojas"), vec!["luz", "roj"]);
+        assert_eq!(Analyzer::parse("ngram=3").unwrap().terms("Vector db"), vec!["vec", "ect", "cto", "tor", "db"]);
+
+        assert!(Analyzer::parse("klingon").is_err());
+        assert!(Analyzer::parse("ngram=0").is_err());
+        assert!(Analyzer::parse("stem=maybe").is_err());
+        assert_eq!(Analyzer::parse("").unwrap(), Analyzer::default());
+        let analyzer = Analyzer::parse("french, ngram=2").unwrap();
+        assert_eq!(Analyzer::parse(&analyzer.to_string()).unwrap(), analyzer);
+    }
+
+    #[test]
+    fn test_cjk() {
+        let analyzer = Analyzer::default();
+        assert_eq!(analyzer.terms("向量数据库 search"), vec!["向量", "量数", "数据", "据库", "search"]);
+        let tokens = analyzer.tokens("a東京");
+        assert_eq!(tokens[1], Token { term: "東京".into(), start: 1, end: 7 });
+
+        let whole = Analyzer::parse("cjk=off").unwrap();
+        assert_eq!(whole.terms("東京タワー"), vec!["東京タワー"]);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let text = "Refunds are issued within 30 days. Shipping takes a week. Returned items must be unused; \
+                    refunds for used items are refused. Contact support with questions.";
+        let english = Analyzer::parse("english").unwrap();
+        assert_eq!(
+            english.snippet(text, "refund used items", 8),
+            "...<b>items</b> must be unused; <b>refunds</b> for <b>used</b> <b>items</b>...",
+        );
+        assert_eq!(english.snippet(text, "refund", 4), "<b>Refunds</b> are issued within...");
+        assert_eq!(english.snippet(text, "warranty", 3), "Refunds are issued...");
+        assert_eq!(english.snippet("short text", "text", 40), "short <b>text</b>");
+        assert_eq!(english.snippet("", "text", 40), "");
+
+        let cjk = Analyzer::default().snippet("今日は東京の天気が良いです", "東京 天気", 6);
+        assert_eq!(cjk, "...<b>東京</b>の<b>天気</b>が...");
+    }
+}
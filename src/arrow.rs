@@ -0,0 +1,364 @@
+//! Conversion between query results and Arrow `RecordBatch`es, so results
+//! can be handed to DataFusion, Polars and other Arrow-based tools, and
+//! batches produced by them can be loaded with `Database::ingest`.
+//!
+//! Column types map as follows:
+//!
+//! | PardusDB            | Arrow                                          |
+//! |---------------------|------------------------------------------------|
+//! | `VECTOR(n)`         | `FixedSizeList<Float32>(n)`                    |
+//! | `VECTOR(n) MULTI`   | `List<FixedSizeList<Float32>(n)>`              |
+//! | `SPARSE_VECTOR`     | `List<Struct<index: UInt32, weight: Float32>>` |
+//! | `TEXT`, `UUID`      | `Utf8`                                         |
+//! | `INTEGER`           | `Int64`                                        |
+//! | `FLOAT`             | `Float64`                                      |
+//! | `BOOLEAN`           | `Boolean`                                      |
+//! | `BLOB`              | `Binary`                                       |
+
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BinaryBuilder, BooleanBuilder, FixedSizeListBuilder, Float32Builder, Float64Builder,
+    Int64Builder, ListBuilder, StringBuilder, StructBuilder, UInt32Builder,
+};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int8Type, Int16Type, Int32Type, Int64Type, UInt8Type, UInt16Type, UInt32Type,
+};
+use arrow_array::{Array, ArrayRef, NullArray, RecordBatch, RecordBatchOptions};
+use arrow_schema::{DataType, Field, Fields, Schema as ArrowSchema};
+
+use crate::database::ExecuteResult;
+use crate::distance::metric_score;
+use crate::error::{MarsError, Result};
+use crate::schema::{ColumnType, ResultColumn, ResultSchema, Row, Value};
+use crate::table::IngestRow;
+
+/// Convert the rows of a `Select`, `SelectSimilar` or `Aggregate` result
+/// to a record batch. Similarity results get a trailing Float32 column
+/// named and scaled as `metric_score` reports the result's metric, e.g.
+/// `similarity` for cosine tables; an aggregate result is a single row.
+/// Computed columns without a declared type take the type of their first
+/// non-NULL value.
+pub fn to_record_batch(result: &ExecuteResult) -> Result<RecordBatch> {
+    match result {
+        ExecuteResult::Select { rows, schema } => batch_from_rows(schema, rows.iter(), None),
+        ExecuteResult::SelectSimilar { results, schema, metric } => {
+            let name = metric_score(metric, 0.0).0;
+            let scores: Vec<f32> = results.iter().map(|(_, d)| metric_score(metric, *d).1).collect();
+            batch_from_rows(schema, results.iter().map(|(row, _)| row), Some((name, scores)))
+        }
+        ExecuteResult::Aggregate { results } => {
+            let schema = ResultSchema {
+                columns: results.iter().map(|(name, _)| ResultColumn::new(name, None)).collect(),
+            };
+            let row = Row::new(0, results.iter().map(|(_, value)| value.clone()).collect());
+            batch_from_rows(&schema, std::iter::once(&row), None)
+        }
+        other => Err(MarsError::InvalidFormat(format!("{:?} has no rows to convert", other))),
+    }
+}
+
+fn batch_from_rows<'a>(
+    schema: &ResultSchema,
+    rows: impl Iterator<Item = &'a Row> + Clone,
+    scores: Option<(&str, Vec<f32>)>,
+) -> Result<RecordBatch> {
+    let mut fields = Vec::with_capacity(schema.columns.len() + 1);
+    let mut columns = Vec::with_capacity(schema.columns.len() + 1);
+    for (idx, column) in schema.columns.iter().enumerate() {
+        let values = rows.clone().map(|row| row.values.get(idx).unwrap_or(&Value::Null));
+        let data_type = match &column.data_type {
+            Some(data_type) => Some(data_type.clone()),
+            None => values.clone().find_map(value_type),
+        };
+        let array = match data_type {
+            Some(data_type) => column_array(&column.name, &data_type, values)?,
+            None => Arc::new(NullArray::new(values.count())) as ArrayRef,
+        };
+        fields.push(Field::new(&column.name, array.data_type().clone(), true));
+        columns.push(array);
+    }
+    if let Some((name, scores)) = scores {
+        fields.push(Field::new(name, DataType::Float32, false));
+        columns.push(Arc::new(arrow_array::Float32Array::from(scores)));
+    }
+
+    let row_count = rows.count();
+    let options = RecordBatchOptions::new().with_row_count(Some(row_count));
+    RecordBatch::try_new_with_options(Arc::new(ArrowSchema::new(fields)), columns, &options)
+        .map_err(|e| MarsError::InvalidFormat(format!("Cannot build record batch: {}", e)))
+}
+
+/// Column type a computed value is exported as
+fn value_type(value: &Value) -> Option<ColumnType> {
+    match value {
+        Value::Null => None,
+        Value::Vector(v) => Some(ColumnType::Vector(v.len())),
+        Value::Text(_) => Some(ColumnType::Text),
+        Value::Integer(_) => Some(ColumnType::Integer),
+        Value::Float(_) => Some(ColumnType::Float),
+        Value::Boolean(_) => Some(ColumnType::Boolean),
+        Value::Blob(_) => Some(ColumnType::Blob),
+        Value::SparseVector(_) => Some(ColumnType::SparseVector),
+        Value::MultiVector(v) => Some(ColumnType::MultiVector(v.first().map_or(0, Vec::len))),
+        Value::Uuid(_) => Some(ColumnType::Uuid),
+    }
+}
+
+fn type_mismatch(column: &str, data_type: &ColumnType, value: &Value) -> MarsError {
+    MarsError::InvalidFormat(format!("Cannot export {:?} in column '{}' as {:?}", value, column, data_type))
+}
+
+fn column_array<'a>(
+    name: &str,
+    data_type: &ColumnType,
+    values: impl Iterator<Item = &'a Value>,
+) -> Result<ArrayRef> {
+    macro_rules! build {
+        ($builder:expr, $($pattern:pat => $append:expr),+ $(,)?) => {{
+            let mut builder = $builder;
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    $($pattern => builder.append_value($append),)+
+                    other => return Err(type_mismatch(name, data_type, other)),
+                }
+            }
+            Arc::new(builder.finish()) as ArrayRef
+        }};
+    }
+
+    let array = match data_type {
+        ColumnType::Text | ColumnType::Uuid => build!(StringBuilder::new(),
+            Value::Text(s) => s,
+            Value::Uuid(u) => u.to_string(),
+        ),
+        ColumnType::Integer => build!(Int64Builder::new(), Value::Integer(i) => *i),
+        ColumnType::Float => build!(Float64Builder::new(),
+            Value::Float(f) => *f,
+            Value::Integer(i) => *i as f64,
+        ),
+        ColumnType::Boolean => build!(BooleanBuilder::new(), Value::Boolean(b) => *b),
+        ColumnType::Blob => build!(BinaryBuilder::new(), Value::Blob(b) => b),
+        ColumnType::Vector(dim) => {
+            let mut builder = FixedSizeListBuilder::new(Float32Builder::new(), *dim as i32);
+            for value in values {
+                match value {
+                    Value::Vector(v) => append_vector(&mut builder, *dim, v)?,
+                    Value::Null => {
+                        builder.values().append_nulls(*dim);
+                        builder.append(false);
+                    }
+                    other => return Err(type_mismatch(name, data_type, other)),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::MultiVector(dim) => {
+            let mut builder = ListBuilder::new(FixedSizeListBuilder::new(Float32Builder::new(), *dim as i32));
+            for value in values {
+                match value {
+                    Value::MultiVector(vectors) => {
+                        for v in vectors {
+                            append_vector(builder.values(), *dim, v)?;
+                        }
+                        builder.append(true);
+                    }
+                    Value::Null => builder.append(false),
+                    other => return Err(type_mismatch(name, data_type, other)),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::SparseVector => {
+            let entry = StructBuilder::from_fields(sparse_fields(), 0);
+            let mut builder = ListBuilder::new(entry);
+            for value in values {
+                match value {
+                    Value::SparseVector(pairs) => {
+                        let entries = builder.values();
+                        for &(index, weight) in pairs {
+                            entries.field_builder::<UInt32Builder>(0).unwrap().append_value(index);
+                            entries.field_builder::<Float32Builder>(1).unwrap().append_value(weight);
+                            entries.append(true);
+                        }
+                        builder.append(true);
+                    }
+                    Value::Null => builder.append(false),
+                    other => return Err(type_mismatch(name, data_type, other)),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+    };
+    Ok(array)
+}
+
+fn sparse_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("index", DataType::UInt32, false),
+        Field::new("weight", DataType::Float32, false),
+    ])
+}
+
+fn append_vector(builder: &mut FixedSizeListBuilder<Float32Builder>, dim: usize, v: &[f32]) -> Result<()> {
+    if v.len() != dim {
+        return Err(MarsError::DimensionMismatch { expected: dim, actual: v.len() });
+    }
+    builder.values().append_slice(v);
+    builder.append(true);
+    Ok(())
+}
+
+/// Read a record batch as rows for `Database::ingest`. `vector_column`
+/// must be a `FixedSizeList<Float32>` without NULLs; every other column
+/// becomes metadata of the same name, with NULLs left out. Columns the
+/// table lacks are ignored by `ingest`.
+///
+/// ```ignore
+/// let rows = pardusdb::arrow::from_record_batch(&batch, "embedding")?;
+/// db.ingest("docs", rows, IngestOptions::default())?;
+/// ```
+pub fn from_record_batch<'b>(batch: &'b RecordBatch, vector_column: &str) -> Result<Vec<IngestRow<'b>>> {
+    let schema = batch.schema_ref();
+    let vector_idx = schema.index_of(vector_column)
+        .map_err(|_| MarsError::ColumnNotFound(vector_column.to_string()))?;
+    let vectors = match batch.column(vector_idx).data_type() {
+        DataType::FixedSizeList(item, _) if item.data_type() == &DataType::Float32 => {
+            batch.column(vector_idx).as_fixed_size_list()
+        }
+        other => return Err(MarsError::InvalidFormat(format!(
+            "Vector column '{}' is {}, expected FixedSizeList<Float32>", vector_column, other
+        ))),
+    };
+
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    for row in 0..batch.num_rows() {
+        if vectors.is_null(row) {
+            return Err(MarsError::ConstraintViolation(format!(
+                "NULL vector in column '{}' at row {}", vector_column, row
+            )));
+        }
+        let vector = vectors.value(row).as_primitive::<Float32Type>().values().to_vec();
+
+        let mut metadata = Vec::with_capacity(batch.num_columns() - 1);
+        for (idx, field) in schema.fields().iter().enumerate() {
+            if idx == vector_idx {
+                continue;
+            }
+            let value = arrow_value(batch.column(idx).as_ref(), row, field.name())?;
+            if value != Value::Null {
+                metadata.push((field.name().as_str(), value));
+            }
+        }
+        rows.push((vector, metadata));
+    }
+    Ok(rows)
+}
+
+/// The value at `row` of an Arrow column
+pub(crate) fn arrow_value(array: &dyn Array, row: usize, column: &str) -> Result<Value> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+    let value = match array.data_type() {
+        DataType::Null => Value::Null,
+        DataType::Utf8 => Value::Text(array.as_string::<i32>().value(row).to_string()),
+        DataType::LargeUtf8 => Value::Text(array.as_string::<i64>().value(row).to_string()),
+        DataType::Int8 => Value::Integer(array.as_primitive::<Int8Type>().value(row) as i64),
+        DataType::Int16 => Value::Integer(array.as_primitive::<Int16Type>().value(row) as i64),
+        DataType::Int32 => Value::Integer(array.as_primitive::<Int32Type>().value(row) as i64),
+        DataType::Int64 => Value::Integer(array.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => Value::Integer(array.as_primitive::<UInt8Type>().value(row) as i64),
+        DataType::UInt16 => Value::Integer(array.as_primitive::<UInt16Type>().value(row) as i64),
+        DataType::UInt32 => Value::Integer(array.as_primitive::<UInt32Type>().value(row) as i64),
+        DataType::Float32 => Value::Float(array.as_primitive::<Float32Type>().value(row) as f64),
+        DataType::Float64 => Value::Float(array.as_primitive::<Float64Type>().value(row)),
+        DataType::Boolean => Value::Boolean(array.as_boolean().value(row)),
+        DataType::Binary => Value::Blob(array.as_binary::<i32>().value(row).to_vec()),
+        DataType::LargeBinary => Value::Blob(array.as_binary::<i64>().value(row).to_vec()),
+        DataType::FixedSizeList(item, _) if item.data_type() == &DataType::Float32 => {
+            Value::Vector(float_list(array.as_fixed_size_list().value(row).as_ref()))
+        }
+        DataType::List(item) => {
+            let entries = array.as_list::<i32>().value(row);
+            match item.data_type() {
+                DataType::FixedSizeList(inner, _) if inner.data_type() == &DataType::Float32 => {
+                    let vectors = entries.as_fixed_size_list();
+                    Value::MultiVector((0..vectors.len()).map(|i| float_list(vectors.value(i).as_ref())).collect())
+                }
+                DataType::Struct(fields) if fields == &sparse_fields() => {
+                    let entries = entries.as_struct();
+                    let indices = entries.column(0).as_primitive::<UInt32Type>();
+                    let weights = entries.column(1).as_primitive::<Float32Type>();
+                    Value::SparseVector(indices.values().iter().copied().zip(weights.values().iter().copied()).collect())
+                }
+                DataType::Float32 => Value::Vector(float_list(entries.as_ref())),
+                other => return Err(unsupported(column, &DataType::List(Arc::new(Field::new("item", other.clone(), true))))),
+            }
+        }
+        other => return Err(unsupported(column, other)),
+    };
+    Ok(value)
+}
+
+fn float_list(array: &dyn Array) -> Vec<f32> {
+    array.as_primitive::<Float32Type>().values().to_vec()
+}
+
+fn unsupported(column: &str, data_type: &DataType) -> MarsError {
+    MarsError::InvalidFormat(format!("Cannot import Arrow type {} of column '{}'", data_type, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, IngestOptions};
+
+    #[test]
+    fn test_round_trip() {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, views INTEGER, tags SPARSE_VECTOR);").unwrap();
+        db.execute("INSERT INTO docs (embedding, title, views) VALUES ([1.0, 0.0], 'a', 3);").unwrap();
+        db.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'b');").unwrap();
+
+        let result = db.execute("SELECT * FROM docs;").unwrap();
+        let batch = to_record_batch(&result).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(0).data_type(),
+            &DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), 2));
+        assert_eq!(batch.column(2).null_count(), 1);
+
+        let mut copy = Database::in_memory();
+        copy.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, views INTEGER, tags SPARSE_VECTOR);").unwrap();
+        let rows = from_record_batch(&batch, "embedding").unwrap();
+        copy.ingest("docs", rows, IngestOptions::default()).unwrap();
+        let result = copy.execute("SELECT * FROM docs ORDER BY title;").unwrap();
+        let rows = result.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get::<String>("title").unwrap(), "a");
+        assert_eq!(rows[0].get::<Option<i64>>("views").unwrap(), Some(3));
+        assert_eq!(rows[1].get::<Option<i64>>("views").unwrap(), None);
+    }
+
+    #[test]
+    fn test_similarity_score_column() {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT) METRIC cosine;").unwrap();
+        db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+        let result = db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] LIMIT 1;").unwrap();
+        let batch = to_record_batch(&result).unwrap();
+        let schema = batch.schema();
+        assert_eq!(schema.field(batch.num_columns() - 1).name(), "similarity");
+    }
+
+    #[test]
+    fn test_vector_column_must_be_fixed_size_list() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("embedding", Arc::new(arrow_array::Float32Array::from(vec![1.0])) as ArrayRef),
+        ]).unwrap();
+        assert!(from_record_batch(&batch, "embedding").is_err());
+        assert!(matches!(from_record_batch(&batch, "missing"), Err(MarsError::ColumnNotFound(_))));
+    }
+}
@@ -0,0 +1,53 @@
+//! Filter and aggregate latency on row vs columnar metadata storage
+
+use std::time::Instant;
+
+use pardusdb::{Database, Value};
+
+const NUM_ROWS: usize = 200_000;
+const RUNS: u32 = 10;
+
+fn main() {
+    println!("=== Columnar Benchmark ({} rows) ===\n", NUM_ROWS);
+
+    let mut db = Database::in_memory();
+    for (table, storage) in [("rows", "ROW"), ("cols", "COLUMNAR")] {
+        db.execute(&format!(
+            "CREATE TABLE {} (embedding VECTOR(4), n INTEGER, price FLOAT, tag TEXT) STORAGE {};",
+            table, storage
+        )).unwrap();
+        let rows = (0..NUM_ROWS)
+            .map(|i| {
+                let vector = vec![(i % 7) as f32, (i % 11) as f32, (i % 13) as f32, 1.0];
+                let metadata = vec![
+                    ("n", Value::Integer(i as i64)),
+                    ("price", Value::Float((i % 1000) as f64 / 10.0)),
+                    ("tag", Value::Text(format!("t{}", i % 16))),
+                ];
+                (vector, metadata)
+            });
+        for (vector, metadata) in rows {
+            db.insert_direct(table, vector, metadata).unwrap();
+        }
+    }
+
+    let queries = [
+        "SELECT COUNT(*) FROM {} WHERE price > 50;",
+        "SELECT SUM(price), AVG(n) FROM {} WHERE n BETWEEN 1000 AND 150000;",
+        "SELECT MIN(price), MAX(price) FROM {} WHERE n < 5000 OR price < 10;",
+        "SELECT tag, COUNT(*), AVG(price) FROM {} WHERE price >= 90 GROUP BY tag;",
+        "SELECT n FROM {} WHERE price = 12.5 AND n > 100000;",
+    ];
+
+    for sql in queries {
+        for table in ["rows", "cols"] {
+            let sql = sql.replace("{}", table);
+            db.execute(&sql).unwrap();
+            let start = Instant::now();
+            for _ in 0..RUNS {
+                db.execute(&sql).unwrap();
+            }
+            println!("{:<80} {:?}", sql, start.elapsed() / RUNS);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Graph query latency on random vectors, without SQL or table overhead
+
+use std::time::Instant;
+
+use pardusdb::{Euclidean, Graph, GraphConfig};
+
+const NUM_VECTORS: usize = 10_000;
+const NUM_QUERIES: usize = 1_000;
+const DIM: usize = 128;
+const K: usize = 10;
+
+/// Deterministic pseudo-random vectors (64-bit LCG)
+fn random_vectors(count: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..DIM)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (state >> 40) as f32 / (1u64 << 24) as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn main() {
+    println!("=== Graph Query Benchmark ({} vectors, {} dims) ===\n", NUM_VECTORS, DIM);
+
+    let vectors = random_vectors(NUM_VECTORS, 42);
+    let queries = random_vectors(NUM_QUERIES, 7);
+
+    let mut graph: Graph<f32, Euclidean> = Graph::new(DIM, GraphConfig::default());
+    let start = Instant::now();
+    for vector in vectors {
+        graph.insert(vector);
+    }
+    println!("Build:         {:?}", start.elapsed());
+
+    // Warm up caches before timing
+    for query in queries.iter().take(100) {
+        graph.query(query, K, 64);
+    }
+
+    for ef in [64, 128, 256] {
+        let start = Instant::now();
+        for query in &queries {
+            graph.query(query, K, ef);
+        }
+        let per_query = start.elapsed() / NUM_QUERIES as u32;
+        println!("Query ef={:<4} {:?} per query", ef, per_query);
+    }
+}
@@ -0,0 +1,77 @@
+//! Parallel ingestion vs sequential direct inserts: load time and recall
+
+use std::time::Instant;
+
+use pardusdb::{Database, IngestOptions, Value};
+
+const NUM_VECTORS: usize = 20_000;
+const NUM_QUERIES: usize = 200;
+const DIM: usize = 32;
+const K: usize = 10;
+
+/// Deterministic pseudo-random vectors (64-bit LCG)
+fn random_vectors(count: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..DIM)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (state >> 40) as f32 / (1u64 << 24) as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn recall(db: &Database, vectors: &[Vec<f32>], queries: &[Vec<f32>]) -> f64 {
+    let mut hits = 0;
+    for query in queries {
+        let mut exact: Vec<(usize, f32)> = vectors.iter()
+            .map(|v| v.iter().zip(query).map(|(a, b)| (a - b) * (a - b)).sum::<f32>())
+            .enumerate()
+            .collect();
+        exact.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let truth: Vec<u64> = exact.iter().take(K).map(|(i, _)| *i as u64 + 1).collect();
+        hits += db.search_similar("docs", query, K, 64).unwrap()
+            .iter()
+            .filter(|(id, _, _)| truth.contains(id))
+            .count();
+    }
+    hits as f64 / (queries.len() * K) as f64
+}
+
+fn load(options: Option<IngestOptions>, vectors: &[Vec<f32>], queries: &[Vec<f32>]) {
+    let mut db = Database::in_memory();
+    db.execute(&format!("CREATE TABLE docs (embedding VECTOR({}), n INTEGER);", DIM)).unwrap();
+    let rows = vectors.iter().enumerate().map(|(i, v)| (v.clone(), vec![("n", Value::Integer(i as i64))]));
+
+    let start = Instant::now();
+    let label = match options {
+        None => {
+            for (vector, metadata) in rows {
+                db.insert_direct("docs", vector, metadata).unwrap();
+            }
+            "insert_direct".to_string()
+        }
+        Some(options) => {
+            let label = format!("ingest x{} batch {}", options.parallelism, options.batch_size);
+            db.ingest("docs", rows, options).unwrap();
+            label
+        }
+    };
+    let elapsed = start.elapsed();
+    println!("{:<28} {:>10.2?}  {:>8.0} rows/s  recall@{} {:.3}",
+        label, elapsed, NUM_VECTORS as f64 / elapsed.as_secs_f64(), K, recall(&db, vectors, queries));
+}
+
+fn main() {
+    println!("=== Ingest Benchmark ({} vectors, {} dims) ===\n", NUM_VECTORS, DIM);
+    let vectors = random_vectors(NUM_VECTORS, 42);
+    let queries = random_vectors(NUM_QUERIES, 7);
+
+    load(None, &vectors, &queries);
+    for parallelism in [1, 2, 4, 8] {
+        load(Some(IngestOptions { parallelism, batch_size: 1000 }), &vectors, &queries);
+    }
+}
@@ -0,0 +1,39 @@
+//! SELECT latency on a large table with 768-dim vectors
+
+use std::time::Instant;
+
+use pardusdb::{Database, Value};
+
+const NUM_ROWS: usize = 10_000;
+const DIM: usize = 768;
+const RUNS: u32 = 20;
+
+fn main() {
+    println!("=== SELECT Benchmark ({} rows, {} dims) ===\n", NUM_ROWS, DIM);
+
+    let mut db = Database::in_memory();
+    db.execute(&format!("CREATE TABLE docs (embedding VECTOR({}), title TEXT, n INTEGER);", DIM)).unwrap();
+    for i in 0..NUM_ROWS {
+        let vector: Vec<f32> = (0..DIM).map(|j| ((i + j) % 97) as f32 / 97.0).collect();
+        let metadata = vec![("title", Value::Text(format!("doc {}", i % 100))), ("n", Value::Integer(i as i64))];
+        db.insert_direct("docs", vector, metadata).unwrap();
+    }
+
+    let queries = [
+        "SELECT * FROM docs LIMIT 10;",
+        "SELECT title FROM docs WHERE n > 100 LIMIT 10;",
+        "SELECT * FROM docs WHERE n > 100;",
+        "SELECT title, n FROM docs WHERE n > 100;",
+        "SELECT * FROM docs ORDER BY n DESC LIMIT 10;",
+        "SELECT DISTINCT title FROM docs LIMIT 5;",
+    ];
+
+    for sql in queries {
+        db.execute(sql).unwrap();
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            db.execute(sql).unwrap();
+        }
+        println!("{:<48} {:?}", sql, start.elapsed() / RUNS);
+    }
+}
@@ -0,0 +1,174 @@
+//! LRU cache of similarity search results.
+//!
+//! Entries are keyed by table, query vector, k and a hash of everything
+//! else that shapes the result (the rest of the statement, ef_search).
+//! Query components are quantized so that float noise from re-embedding
+//! the same text still hits. All entries of a table are dropped whenever
+//! the table changes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hasher;
+
+use crate::database::ExecuteResult;
+use crate::parser::{Command, ConditionValue};
+use crate::schema::{Fnv1a, Value};
+
+/// Quantization steps per unit of a query component
+const STEPS_PER_UNIT: f32 = 65536.0;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    table: String,
+    query: Vec<i32>,
+    k: usize,
+    /// Hash of the other inputs that affect the result
+    filter: u64,
+}
+
+impl CacheKey {
+    pub(crate) fn new(table: &str, query: &[f32], k: usize, filter: &str) -> Self {
+        let mut hasher = Fnv1a::new();
+        hasher.write(filter.as_bytes());
+        CacheKey {
+            table: table.to_string(),
+            query: query.iter().map(|x| (x * STEPS_PER_UNIT).round() as i32).collect(),
+            k,
+            filter: hasher.finish(),
+        }
+    }
+
+    /// Key of a SQL SELECT with a dense SIMILARITY condition, or None for
+    /// other statements
+    pub(crate) fn for_select(command: &Command, ef_search: Option<usize>) -> Option<Self> {
+        let Command::Select { table, where_clause: Some(wc), limit, .. } = command else {
+            return None;
+        };
+        let (index, query) = wc.conditions.iter().enumerate().find_map(|(i, c)| match &c.value {
+            ConditionValue::Single(Value::Vector(query)) if c.operator.is_similarity() => Some((i, query)),
+            _ => None,
+        })?;
+
+        // The statement without its query vector stands for the filter
+        let mut rest = command.clone();
+        if let Command::Select { where_clause: Some(wc), .. } = &mut rest {
+            wc.conditions[index].value = ConditionValue::Single(Value::Null);
+        }
+        Some(CacheKey::new(table, query, limit.unwrap_or(10), &format!("{:?} {:?}", rest, ef_search)))
+    }
+}
+
+/// A cached result: `Database::search_similar` matches or a SQL result
+#[derive(Clone)]
+pub(crate) enum Cached {
+    Matches(Vec<(u64, Vec<Value>, f32)>),
+    Result(ExecuteResult),
+}
+
+pub(crate) struct ResultCache {
+    capacity: usize,
+    /// Entry and the tick it was last used at
+    entries: HashMap<CacheKey, (Cached, u64)>,
+    /// Last-use tick -> key, oldest first
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl ResultCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ResultCache { capacity, entries: HashMap::new(), order: BTreeMap::new(), tick: 0 }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Look up a result, marking it most recently used
+    pub(crate) fn get(&mut self, key: &CacheKey) -> Option<Cached> {
+        self.tick += 1;
+        let (cached, used) = self.entries.get_mut(key)?;
+        let key = self.order.remove(used).expect("cache order out of sync");
+        *used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(cached.clone())
+    }
+
+    /// Add a result, evicting the least recently used entry when full
+    pub(crate) fn insert(&mut self, key: CacheKey, cached: Cached) {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.remove(&key) {
+            self.order.remove(&used);
+        }
+        if self.entries.len() >= self.capacity
+            && let Some((_, oldest)) = self.order.pop_first()
+        {
+            self.entries.remove(&oldest);
+        }
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (cached, self.tick));
+    }
+
+    /// Drop every entry of a table
+    pub(crate) fn invalidate(&mut self, table: &str) {
+        self.entries.retain(|key, (_, used)| {
+            let keep = key.table != table;
+            if !keep {
+                self.order.remove(used);
+            }
+            keep
+        });
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn matches(id: u64) -> Cached {
+        Cached::Matches(vec![(id, Vec::new(), 0.0)])
+    }
+
+    fn id(cached: Option<Cached>) -> Option<u64> {
+        match cached? {
+            Cached::Matches(m) => Some(m[0].0),
+            Cached::Result(_) => None,
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let key = |n: f32| CacheKey::new("docs", &[n], 5, "");
+        let mut cache = ResultCache::new(2);
+        cache.insert(key(1.0), matches(1));
+        cache.insert(key(2.0), matches(2));
+        assert_eq!(id(cache.get(&key(1.0))), Some(1));
+        cache.insert(key(3.0), matches(3));
+
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(id(cache.get(&key(2.0))), None);
+        assert_eq!(id(cache.get(&key(1.0))), Some(1));
+        assert_eq!(id(cache.get(&key(3.0))), Some(3));
+
+        cache.insert(CacheKey::new("other", &[1.0], 5, ""), matches(4));
+        cache.invalidate("docs");
+        assert_eq!(cache.entries.len(), 1);
+        assert_eq!(id(cache.get(&CacheKey::new("other", &[1.0], 5, ""))), Some(4));
+    }
+
+    #[test]
+    fn test_select_keys() {
+        let key = |sql: &str| CacheKey::for_select(&parse(sql).unwrap(), None);
+        let base = key("SELECT * FROM docs WHERE embedding SIMILARITY [0.5, 0.25] LIMIT 5;").unwrap();
+        assert_eq!(key("SELECT * FROM docs WHERE embedding SIMILARITY [0.5000001, 0.25] LIMIT 5;"), Some(base.clone()));
+        assert_ne!(key("SELECT * FROM docs WHERE embedding SIMILARITY [0.6, 0.25] LIMIT 5;"), Some(base.clone()));
+        assert_ne!(key("SELECT * FROM docs WHERE embedding SIMILARITY [0.5, 0.25] LIMIT 6;"), Some(base.clone()));
+        assert_ne!(key("SELECT * FROM docs WHERE embedding SIMILARITY [0.5, 0.25] EXACT LIMIT 5;"), Some(base.clone()));
+        assert_ne!(CacheKey::for_select(&parse("SELECT * FROM docs WHERE embedding SIMILARITY [0.5, 0.25] LIMIT 5;").unwrap(), Some(64)), Some(base));
+        assert_eq!(key("SELECT * FROM docs WHERE title = 'a';"), None);
+    }
+}
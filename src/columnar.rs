@@ -0,0 +1,356 @@
+//! Columnar copy of a table's numeric metadata.
+//!
+//! Tables created with `STORAGE COLUMNAR` keep their INTEGER and FLOAT
+//! columns in contiguous arrays next to the row store. WHERE clauses over
+//! those columns are evaluated a column at a time into a selection mask,
+//! and COUNT/SUM/AVG/MIN/MAX read the arrays directly instead of walking
+//! rows. Anything the columns can't answer exactly (text conditions,
+//! function calls, NULL literals, ...) returns `None` and the caller falls
+//! back to the row path.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::parser::{AggregateFunc, BoolConnector, ComparisonOp, Condition, ConditionValue, WhereClause};
+use crate::schema::{ColumnType, Row, Schema, Value};
+
+/// A numeric cell. Columns may still hold values of another type, which
+/// compare like they do in the row store: unequal and unordered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Num {
+    Null,
+    Int(i64),
+    Float(f64),
+    Other,
+}
+
+impl Num {
+    fn from_value(value: &Value) -> Self {
+        match value {
+            Value::Null => Num::Null,
+            Value::Integer(i) => Num::Int(*i),
+            Value::Float(f) => Num::Float(*f),
+            _ => Num::Other,
+        }
+    }
+
+    /// Non-null numeric literal of a condition
+    fn literal(value: &Value) -> Option<Self> {
+        match Self::from_value(value) {
+            n @ (Num::Int(_) | Num::Float(_)) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn as_f64(self) -> Option<f64> {
+        match self {
+            Num::Int(i) => Some(i as f64),
+            Num::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// Same ordering as `Table::values_compare` for numbers
+    fn compare(self, other: Num) -> Option<Ordering> {
+        match (self, other) {
+            (Num::Int(a), Num::Int(b)) => Some(a.cmp(&b)),
+            (a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        }
+    }
+
+    fn to_value(self) -> Value {
+        match self {
+            Num::Int(i) => Value::Integer(i),
+            Num::Float(f) => Value::Float(f),
+            _ => Value::Null,
+        }
+    }
+}
+
+struct NumericColumn {
+    name: String,
+    /// Position of the column in the table schema
+    index: usize,
+    values: Vec<Num>,
+}
+
+/// INTEGER and FLOAT columns of a table's live rows, one array per column
+pub struct ColumnStore {
+    ids: Vec<u64>,
+    /// Row ID -> position in the arrays
+    positions: HashMap<u64, usize>,
+    columns: Vec<NumericColumn>,
+}
+
+impl ColumnStore {
+    pub fn new(schema: &Schema) -> Self {
+        let columns = schema.columns.iter().enumerate()
+            .filter(|(_, c)| matches!(c.data_type, ColumnType::Integer | ColumnType::Float))
+            .map(|(index, c)| NumericColumn { name: c.name.clone(), index, values: Vec::new() })
+            .collect();
+        ColumnStore { ids: Vec::new(), positions: HashMap::new(), columns }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Row IDs in column order
+    pub fn ids(&self) -> &[u64] {
+        &self.ids
+    }
+
+    /// Whether a column is stored here
+    pub fn has_column(&self, name: &str) -> bool {
+        self.column(name).is_some()
+    }
+
+    fn column(&self, name: &str) -> Option<&NumericColumn> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    /// Add a row, or overwrite it if its ID is already stored
+    pub fn insert(&mut self, row: &Row) {
+        match self.positions.get(&row.id) {
+            Some(&pos) => {
+                for column in &mut self.columns {
+                    column.values[pos] = Num::from_value(&row.values[column.index]);
+                }
+            }
+            None => {
+                self.positions.insert(row.id, self.ids.len());
+                self.ids.push(row.id);
+                for column in &mut self.columns {
+                    column.values.push(Num::from_value(&row.values[column.index]));
+                }
+            }
+        }
+    }
+
+    /// Drop a row; the last row moves into its place
+    pub fn remove(&mut self, id: u64) {
+        let Some(pos) = self.positions.remove(&id) else { return };
+        self.ids.swap_remove(pos);
+        for column in &mut self.columns {
+            column.values.swap_remove(pos);
+        }
+        if let Some(&moved) = self.ids.get(pos) {
+            self.positions.insert(moved, pos);
+        }
+    }
+
+    /// Estimated heap memory, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.ids.len() * (size_of::<u64>() + size_of::<(u64, usize)>() + self.columns.len() * size_of::<Num>())
+    }
+
+    /// Memory one more row adds
+    pub fn row_memory(&self) -> usize {
+        size_of::<u64>() + size_of::<(u64, usize)>() + self.columns.len() * size_of::<Num>()
+    }
+
+    /// Evaluate a WHERE clause over the columns. Returns one flag per
+    /// stored row (in `ids()` order), or `None` if the clause references
+    /// something the columns can't evaluate exactly.
+    pub fn filter(&self, where_clause: Option<&WhereClause>) -> Option<Vec<bool>> {
+        let Some(wc) = where_clause.filter(|wc| !wc.conditions.is_empty()) else {
+            return Some(vec![true; self.len()]);
+        };
+
+        // UNKNOWN and false both fail the final test, and AND/OR are
+        // monotone, so a two-valued mask gives the same rows as
+        // `Table::matches_where`
+        let mut mask = self.condition_mask(&wc.conditions[0])?;
+        for (connector, cond) in wc.connectors.iter().zip(&wc.conditions[1..]) {
+            let next = self.condition_mask(cond)?;
+            match connector {
+                BoolConnector::And => mask.iter_mut().zip(next).for_each(|(m, n)| *m &= n),
+                BoolConnector::Or => mask.iter_mut().zip(next).for_each(|(m, n)| *m |= n),
+            }
+        }
+        Some(mask)
+    }
+
+    /// Whether `filter` can evaluate this clause, without running it
+    pub fn supports(&self, where_clause: Option<&WhereClause>) -> bool {
+        where_clause.is_none_or(|wc| wc.conditions.iter().all(|cond| self.supports_condition(cond)))
+    }
+
+    /// Whether `condition_mask` handles a condition
+    fn supports_condition(&self, cond: &Condition) -> bool {
+        if cond.expr.is_some() || self.column(&cond.column).is_none() {
+            return false;
+        }
+        match (&cond.operator, &cond.value) {
+            (ComparisonOp::IsNull | ComparisonOp::IsNotNull, _) => true,
+            (ComparisonOp::Eq | ComparisonOp::Ne | ComparisonOp::Lt | ComparisonOp::Le | ComparisonOp::Gt | ComparisonOp::Ge,
+                ConditionValue::Single(literal)) => Num::literal(literal).is_some(),
+            (ComparisonOp::In | ComparisonOp::NotIn, ConditionValue::List(list)) => list.iter().all(|v| Num::literal(v).is_some()),
+            (ComparisonOp::Between | ComparisonOp::NotBetween, ConditionValue::Range(low, high)) => {
+                Num::literal(low).is_some() && Num::literal(high).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Flags rows for which a condition is true
+    fn condition_mask(&self, cond: &Condition) -> Option<Vec<bool>> {
+        if cond.expr.is_some() {
+            return None;
+        }
+        let values = &self.column(&cond.column)?.values;
+
+        let mask = match (&cond.operator, &cond.value) {
+            (ComparisonOp::IsNull, _) => values.iter().map(|v| *v == Num::Null).collect(),
+            (ComparisonOp::IsNotNull, _) => values.iter().map(|v| *v != Num::Null).collect(),
+            (op, ConditionValue::Single(literal)) => {
+                let literal = Num::literal(literal)?;
+                let test: fn(Option<Ordering>) -> bool = match op {
+                    ComparisonOp::Eq => |o| o == Some(Ordering::Equal),
+                    ComparisonOp::Ne => |o| o != Some(Ordering::Equal),
+                    ComparisonOp::Lt => |o| o == Some(Ordering::Less),
+                    ComparisonOp::Le => |o| matches!(o, Some(Ordering::Less | Ordering::Equal)),
+                    ComparisonOp::Gt => |o| o == Some(Ordering::Greater),
+                    ComparisonOp::Ge => |o| matches!(o, Some(Ordering::Greater | Ordering::Equal)),
+                    _ => return None,
+                };
+                values.iter().map(|v| *v != Num::Null && test(v.compare(literal))).collect()
+            }
+            (op @ (ComparisonOp::In | ComparisonOp::NotIn), ConditionValue::List(list)) => {
+                let list: Vec<Num> = list.iter().map(Num::literal).collect::<Option<_>>()?;
+                let negate = *op == ComparisonOp::NotIn;
+                values.iter()
+                    .map(|v| {
+                        *v != Num::Null
+                            && negate != list.iter().any(|l| v.compare(*l) == Some(Ordering::Equal))
+                    })
+                    .collect()
+            }
+            (op @ (ComparisonOp::Between | ComparisonOp::NotBetween), ConditionValue::Range(low, high)) => {
+                let (low, high) = (Num::literal(low)?, Num::literal(high)?);
+                let negate = *op == ComparisonOp::NotBetween;
+                values.iter()
+                    .map(|v| {
+                        let inside = matches!(v.compare(low), Some(Ordering::Greater | Ordering::Equal))
+                            && matches!(v.compare(high), Some(Ordering::Less | Ordering::Equal));
+                        *v != Num::Null && negate != inside
+                    })
+                    .collect()
+            }
+            _ => return None,
+        };
+        Some(mask)
+    }
+
+    /// Compute an aggregate over the selected rows of a stored column, with
+    /// the same results as the row path. `None` if the function or column
+    /// isn't supported.
+    pub fn aggregate(&self, func: &AggregateFunc, column: &str, selection: &[bool]) -> Option<Value> {
+        if *func == AggregateFunc::Count && column == "*" {
+            return Some(Value::Integer(selection.iter().filter(|s| **s).count() as i64));
+        }
+
+        let values = &self.column(column)?.values;
+        let selected = || values.iter().zip(selection).filter(|(_, s)| **s).map(|(v, _)| *v);
+
+        let value = match func {
+            AggregateFunc::Count => Value::Integer(selected().filter(|v| *v != Num::Null).count() as i64),
+            AggregateFunc::Sum => Value::Float(selected().filter_map(Num::as_f64).sum()),
+            AggregateFunc::Avg => {
+                let (sum, count) = selected().filter_map(Num::as_f64).fold((0.0, 0usize), |(s, c), v| (s + v, c + 1));
+                if count == 0 { Value::Null } else { Value::Float(sum / count as f64) }
+            }
+            AggregateFunc::Min | AggregateFunc::Max => {
+                // Non-numeric values order differently; leave them to the row path
+                if selected().any(|v| v == Num::Other) {
+                    return None;
+                }
+                let numbers = selected().filter(|v| *v != Num::Null);
+                let cmp = |a: &Num, b: &Num| a.compare(*b).unwrap_or(Ordering::Equal);
+                let best = if *func == AggregateFunc::Min { numbers.min_by(cmp) } else { numbers.max_by(cmp) };
+                best.map_or(Value::Null, Num::to_value)
+            }
+            AggregateFunc::ApproxCountDistinct | AggregateFunc::VecAvg => return None,
+        };
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, Command};
+    use crate::schema::Column;
+
+    fn store() -> ColumnStore {
+        let mut schema = Schema::new("t");
+        schema.columns.push(Column::new("n", ColumnType::Integer));
+        schema.columns.push(Column::new("title", ColumnType::Text));
+        schema.columns.push(Column::new("score", ColumnType::Float));
+
+        let mut store = ColumnStore::new(&schema);
+        let rows = [
+            (1, Value::Integer(1), Value::Float(0.5)),
+            (2, Value::Integer(2), Value::Null),
+            (3, Value::Null, Value::Float(1.5)),
+            (4, Value::Integer(4), Value::Integer(3)),
+        ];
+        for (id, n, score) in rows {
+            store.insert(&Row::new(id, vec![n, Value::Text(format!("t{}", id)), score]));
+        }
+        store
+    }
+
+    fn where_clause(condition: &str) -> WhereClause {
+        match parse(&format!("SELECT * FROM t WHERE {};", condition)).unwrap() {
+            Command::Select { where_clause, .. } => where_clause.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn selected(store: &ColumnStore, condition: &str) -> Option<Vec<u64>> {
+        let mask = store.filter(Some(&where_clause(condition)))?;
+        let mut ids: Vec<u64> = store.ids().iter().zip(mask).filter(|(_, m)| *m).map(|(id, _)| *id).collect();
+        ids.sort_unstable();
+        Some(ids)
+    }
+
+    #[test]
+    fn test_filter() {
+        let store = store();
+        assert_eq!(selected(&store, "n > 1"), Some(vec![2, 4]));
+        assert_eq!(selected(&store, "n != 2"), Some(vec![1, 4]));
+        assert_eq!(selected(&store, "score >= 1"), Some(vec![3, 4]));
+        assert_eq!(selected(&store, "n BETWEEN 2 AND 4 AND score IS NULL"), Some(vec![2]));
+        assert_eq!(selected(&store, "n NOT IN (1, 4) OR score = 3.0"), Some(vec![2, 4]));
+        assert_eq!(selected(&store, "n IS NULL OR score < 1"), Some(vec![1, 3]));
+
+        // Text columns and NULL literals go through the row path
+        assert_eq!(selected(&store, "title = 't1'"), None);
+        assert_eq!(selected(&store, "n = NULL"), None);
+    }
+
+    #[test]
+    fn test_aggregate_and_remove() {
+        let mut store = store();
+        let all = store.filter(None).unwrap();
+        assert_eq!(store.aggregate(&AggregateFunc::Count, "*", &all), Some(Value::Integer(4)));
+        assert_eq!(store.aggregate(&AggregateFunc::Count, "score", &all), Some(Value::Integer(3)));
+        assert_eq!(store.aggregate(&AggregateFunc::Sum, "n", &all), Some(Value::Float(7.0)));
+        assert_eq!(store.aggregate(&AggregateFunc::Max, "score", &all), Some(Value::Integer(3)));
+        assert_eq!(store.aggregate(&AggregateFunc::Min, "score", &all), Some(Value::Float(0.5)));
+        assert_eq!(store.aggregate(&AggregateFunc::Sum, "title", &all), None);
+
+        store.remove(1);
+        store.insert(&Row::new(4, vec![Value::Integer(10), Value::Null, Value::Null]));
+        let all = store.filter(None).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.aggregate(&AggregateFunc::Avg, "n", &all), Some(Value::Float(6.0)));
+        assert_eq!(store.aggregate(&AggregateFunc::Avg, "score", &all), Some(Value::Float(1.5)));
+        assert_eq!(selected(&store, "n >= 2"), Some(vec![2, 4]));
+    }
+}
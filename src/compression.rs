@@ -0,0 +1,167 @@
+//! Per-value compression of long TEXT values in saved tables.
+//!
+//! Each table gets a zstd dictionary trained on its own long values, so
+//! short chunks that share vocabulary and boilerplate still compress.
+//! Values are compressed when a table is written and restored when it is
+//! read back; rows in memory always hold plain text.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MarsError, Result};
+use crate::schema::{Row, Value};
+
+/// TEXT values at least this many bytes long are compressed on save
+/// unless `ALTER TABLE ... SET (compress_text = n)` says otherwise
+pub const DEFAULT_COMPRESS_TEXT: usize = 1024;
+
+/// Largest dictionary trained for a table
+const DICTIONARY_LEN: usize = 32 * 1024;
+
+/// Bytes sampled from each value for the dictionary
+const SAMPLE_LEN: usize = 512;
+
+/// Longest TEXT value that is compressed. A saved length above this is
+/// treated as corrupt rather than allocated.
+const MAX_TEXT_LEN: usize = 256 * 1024 * 1024;
+
+/// zstd compression level
+const LEVEL: i32 = 3;
+
+/// The compressed TEXT values of a saved table, taken out of its rows
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CompressedTexts {
+    pub dictionary: Vec<u8>,
+    pub values: Vec<CompressedText>,
+}
+
+/// One compressed value, left as NULL in its row
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct CompressedText {
+    pub row: u32,
+    pub column: u32,
+    /// Length of the plain text in bytes
+    pub len: u32,
+    pub data: Vec<u8>,
+}
+
+/// Compress the TEXT values of `rows` that are at least `threshold` bytes
+/// long, replacing them with NULL. A threshold of 0 compresses nothing.
+/// Values that would not get smaller are left alone.
+pub(crate) fn compress(rows: &mut [Row], threshold: usize) -> CompressedTexts {
+    if threshold == 0 {
+        return CompressedTexts::default();
+    }
+    let long: Vec<(usize, usize)> = rows.iter().enumerate()
+        .flat_map(|(r, row)| row.values.iter().enumerate().filter_map(move |(c, value)| match value {
+            Value::Text(s) if s.len() >= threshold && s.len() <= MAX_TEXT_LEN => Some((r, c)),
+            _ => None,
+        }))
+        .collect();
+    if long.is_empty() {
+        return CompressedTexts::default();
+    }
+
+    let text = |rows: &[Row], (r, c): (usize, usize)| match &rows[r].values[c] {
+        Value::Text(s) => s.as_bytes().to_vec(),
+        _ => Vec::new(),
+    };
+
+    // Sample evenly across the table rather than from its first rows
+    let count = (4 * DICTIONARY_LEN / SAMPLE_LEN).min(long.len());
+    let samples: Vec<Vec<u8>> = (0..count)
+        .map(|i| {
+            let mut sample = text(rows, long[i * long.len() / count]);
+            sample.truncate(SAMPLE_LEN);
+            sample
+        })
+        .collect();
+    // Training needs a fair number of samples; with too few, the samples
+    // themselves serve as a raw content dictionary
+    let dictionary = zstd::dict::from_samples(&samples, DICTIONARY_LEN)
+        .unwrap_or_else(|_| samples.concat().into_iter().take(DICTIONARY_LEN).collect());
+    let Ok(mut compressor) = zstd::bulk::Compressor::with_dictionary(LEVEL, &dictionary) else {
+        return CompressedTexts::default();
+    };
+
+    let mut values = Vec::new();
+    for (r, c) in long {
+        let plain = text(rows, (r, c));
+        let Ok(data) = compressor.compress(&plain) else { continue };
+        if data.len() < plain.len() {
+            rows[r].values[c] = Value::Null;
+            values.push(CompressedText { row: r as u32, column: c as u32, len: plain.len() as u32, data });
+        }
+    }
+    if values.is_empty() {
+        return CompressedTexts::default();
+    }
+    CompressedTexts { dictionary, values }
+}
+
+/// Put the values taken out by `compress` back into `rows`
+pub(crate) fn decompress(rows: &mut [Row], texts: CompressedTexts) -> Result<()> {
+    if texts.values.is_empty() {
+        return Ok(());
+    }
+    let mut decompressor = zstd::bulk::Decompressor::with_dictionary(&texts.dictionary)
+        .map_err(|_| MarsError::InvalidFormat("corrupt text compression dictionary".into()))?;
+    for value in texts.values {
+        let corrupt = || MarsError::InvalidFormat(format!("corrupt compressed text in row {}", value.row));
+        let slot = rows.get_mut(value.row as usize)
+            .and_then(|row| row.values.get_mut(value.column as usize))
+            .ok_or_else(corrupt)?;
+        let len = value.len as usize;
+        if len > MAX_TEXT_LEN {
+            return Err(corrupt());
+        }
+        let plain = decompressor.decompress(&value.data, len).map_err(|_| corrupt())?;
+        if plain.len() != len {
+            return Err(corrupt());
+        }
+        *slot = Value::Text(String::from_utf8(plain).map_err(|_| corrupt())?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let long = |i: usize| format!("Chunk {} of the handbook. The quick brown fox jumps over the lazy dog. {}", i, "lorem ipsum ".repeat(20));
+        let original: Vec<Row> = (0..50)
+            .map(|i| Row::new(i, vec![Value::Text(long(i as usize)), Value::Text("short".into()), Value::Integer(i as i64)]))
+            .collect();
+
+        let mut rows = original.clone();
+        let texts = compress(&mut rows, 64);
+        assert_eq!(texts.values.len(), 50);
+        assert!(rows.iter().all(|row| row.values[0] == Value::Null && row.values[1] == Value::Text("short".into())));
+        let packed: usize = texts.values.iter().map(|v| v.data.len()).sum();
+        let plain: usize = (0..50).map(|i| long(i).len()).sum();
+        assert!(packed < plain / 2, "{} of {} bytes", packed, plain);
+
+        decompress(&mut rows, texts).unwrap();
+        assert_eq!(rows.iter().map(|r| &r.values).collect::<Vec<_>>(), original.iter().map(|r| &r.values).collect::<Vec<_>>());
+
+        let mut rows = original.clone();
+        assert!(compress(&mut rows, 0).values.is_empty());
+        assert!(compress(&mut rows, 10_000).values.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_oversized_length() {
+        let mut rows: Vec<Row> = (0..20)
+            .map(|i| Row::new(i, vec![Value::Text(format!("{} {}", i, "the same boilerplate text ".repeat(10)))]))
+            .collect();
+        let mut texts = compress(&mut rows, 64);
+        assert!(!texts.values.is_empty());
+        texts.values[0].len = u32::MAX;
+        assert!(matches!(decompress(&mut rows, texts.clone()), Err(MarsError::InvalidFormat(_))));
+
+        // A length that is merely wrong is caught as well
+        texts.values[0].len = 3;
+        assert!(matches!(decompress(&mut rows, texts), Err(MarsError::InvalidFormat(_))));
+    }
+}
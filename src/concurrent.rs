@@ -14,6 +14,8 @@
 //! - **Read operations**: Multiple threads can read simultaneously (shared lock)
 //! - **Write operations**: Exclusive access required (exclusive lock)
 //! - **Transactions**: All operations in a transaction are atomic
+//! - **Panics**: A panic while writing leaves reads working but refuses
+//!   writes with `MarsError::LockPoisoned` until `clear_poison()`
 //!
 //! # Example
 //!
@@ -50,18 +52,183 @@
 //! }
 //! ```
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
-use crate::database::{ExecuteResult, TableInfo};
+use crate::database::{copy_table, ExecuteResult, TableData, TableInfo, DEFAULT_EF_SEARCH, FORMAT_VERSION};
+use crate::engine::{self, inserted_id, InsertHooks, SelectQuery};
+use crate::external::TextFileWriter;
+use crate::interrupt::InterruptHandle;
 use crate::error::{MarsError, Result};
+use crate::functions::ScalarExpr;
+use crate::db::SNAPSHOT_MIN_BATCH;
 use crate::graph::GraphConfig;
-use crate::parser::{parse, Command, ComparisonOp};
-use crate::schema::{Column, ColumnType, Row, Schema, Value};
-use crate::table::Table;
+use crate::lock::{self, WriterLock, DEFAULT_BUSY_TIMEOUT};
+use crate::parser::{parse_with_limits, split_statements, AggregateFunc, AlterAction, Command, OnConflict, ParseLimits, SelectColumn};
+use crate::prepared::PreparedStatement;
+use crate::schema::{IntoVector, Mask, Row, Value};
+use crate::storage::Synchronous;
+use crate::table::{Duplicate, SearchCursor, SearchGroup, SearchPage, Table};
+
+/// Lock a mutex over settings or pool counters. A panic cannot leave them
+/// half-updated, so a poisoned lock is simply taken over.
+fn lock_plain<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Write combining state behind `ConcurrentDatabase::set_group_commit`
+#[derive(Default)]
+struct GroupCommit {
+    window: Mutex<Option<Duration>>,
+    queue: Mutex<GroupQueue>,
+    /// Signalled when a group's results are ready
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct GroupQueue {
+    /// Inserts waiting for the next group
+    pending: Vec<QueuedInsert>,
+    /// Whether a caller is inserting a group
+    leading: bool,
+    next_ticket: u64,
+    /// Row IDs (or errors) by ticket, until their callers collect them
+    results: HashMap<u64, Result<u64>>,
+}
+
+struct QueuedInsert {
+    ticket: u64,
+    table: String,
+    vector: Vec<f32>,
+    metadata: Vec<(String, Value)>,
+}
+
+/// Publishes a group's results when its leader is done. If the leader
+/// panicked (or the lock was poisoned) the group's inserts without a result
+/// fail with `MarsError::LockPoisoned` rather than wait forever.
+struct GroupLeader<'a> {
+    group: &'a GroupCommit,
+    tickets: Vec<u64>,
+    results: Vec<(u64, Result<u64>)>,
+}
+
+impl Drop for GroupLeader<'_> {
+    fn drop(&mut self) {
+        let mut queue = lock_plain(&self.group.queue);
+        queue.results.extend(self.results.drain(..));
+        for &ticket in &self.tickets {
+            queue.results.entry(ticket).or_insert_with(|| Err(poisoned()));
+        }
+        queue.leading = false;
+        self.group.done.notify_all();
+    }
+}
+
+/// Insert a group of queued rows, one batch per table. A batch that fails
+/// as a whole (say, on a UNIQUE column) is retried row by row so each insert
+/// gets its own result.
+fn insert_group(inner: &mut DatabaseInner, inserts: Vec<QueuedInsert>) -> Vec<(u64, Result<u64>)> {
+    let mut by_table: Vec<(String, Vec<QueuedInsert>)> = Vec::new();
+    for insert in inserts {
+        match by_table.iter_mut().find(|(name, _)| *name == insert.table) {
+            Some((_, group)) => group.push(insert),
+            None => by_table.push((insert.table.clone(), vec![insert])),
+        }
+    }
+
+    let mut results = Vec::new();
+    for (name, inserts) in by_table {
+        let Some(table) = inner.tables.get(&name) else {
+            results.extend(inserts.iter().map(|insert| (insert.ticket, Err(MarsError::TableNotFound(name.clone())))));
+            continue;
+        };
+        let mut tickets = Vec::with_capacity(inserts.len());
+        let mut rows = Vec::with_capacity(inserts.len());
+        for insert in inserts {
+            let metadata: Vec<(&str, Value)> = insert.metadata.iter().map(|(column, value)| (column.as_str(), value.clone())).collect();
+            let row = table.direct_row_values(insert.vector, metadata)
+                .and_then(|row| engine::check_references(&inner.tables, table, &row).map(|_| row));
+            match row {
+                Ok(row) => {
+                    tickets.push(insert.ticket);
+                    rows.push(row);
+                }
+                Err(e) => results.push((insert.ticket, Err(e))),
+            }
+        }
+        let table = inner.tables.get_mut(&name).unwrap();
+        match table.insert_batch(rows.clone()) {
+            Ok(ids) => results.extend(tickets.into_iter().zip(ids.into_iter().map(Ok))),
+            Err(_) => {
+                for (ticket, row) in tickets.into_iter().zip(rows) {
+                    results.push((ticket, table.insert_row(row)));
+                }
+            }
+        }
+    }
+    results
+}
+
+/// How current the rows a search reads must be
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Consistency {
+    /// The tables as they are now, waiting for a writer holding the lock
+    #[default]
+    Latest,
+    /// The snapshot last sealed with `ConcurrentDatabase::seal_snapshot`,
+    /// without waiting for writers. Without a snapshot this reads `Latest`.
+    Snapshot,
+}
+
+/// Options for `Connection::search_similar_with`
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    pub consistency: Consistency,
+}
+
+/// Results of `Connection::search_similar_snapshot`
+#[derive(Clone, Debug)]
+pub struct SnapshotSearch {
+    pub results: Vec<(u64, Vec<Value>, f32)>,
+    /// False if a large `insert_batch_direct` into the table was in
+    /// progress, so the results come from the table as it was before it
+    pub fresh: bool,
+}
+
+/// Marks a table as taking a large batch until dropped
+struct Ingesting<'a> {
+    db: &'a ConcurrentDatabase,
+    table: &'a str,
+}
+
+impl Drop for Ingesting<'_> {
+    fn drop(&mut self) {
+        let mut ingesting = lock_plain(&self.db.ingesting);
+        if let Some((_, batches)) = ingesting.get_mut(self.table) {
+            *batches -= 1;
+            if *batches == 0 {
+                ingesting.remove(self.table);
+            }
+        }
+    }
+}
+
+/// Worker threads for REINDEX, END BULK LOAD and INSERT ... SELECT
+fn thread_count() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Error for writes after a write panicked
+fn poisoned() -> MarsError {
+    MarsError::LockPoisoned("an earlier write panicked; call clear_poison to resume writes".into())
+}
 
 /// File header with database metadata
 #[derive(Serialize, Deserialize)]
@@ -70,20 +237,13 @@ struct DbHeader {
     pub table_count: u32,
 }
 
-/// Serialized table data
-#[derive(Serialize, Deserialize)]
-struct TableData {
-    pub schema: Schema,
-    pub rows: Vec<Row>,
-    pub centroid: Vec<f32>,
-    pub next_id: u64,
-}
-
 /// Internal database state
 pub struct DatabaseInner {
     pub tables: HashMap<String, Table>,
     pub config: GraphConfig,
     pub path: Option<PathBuf>,
+    /// Between BEGIN BULK LOAD and END BULK LOAD
+    pub bulk_load: bool,
 }
 
 /// A thread-safe database that can be shared across threads.
@@ -92,6 +252,29 @@ pub struct DatabaseInner {
 /// or one exclusive writer.
 pub struct ConcurrentDatabase {
     inner: RwLock<DatabaseInner>,
+    /// Reject writes and saves with `MarsError::ReadOnly`
+    readonly: bool,
+    /// How long `save` waits for other processes' file locks
+    busy_timeout: Mutex<Duration>,
+    synchronous: Mutex<Synchronous>,
+    /// Bounds on the SQL connections accept
+    parse_limits: Mutex<ParseLimits>,
+    /// Queue combining `insert_direct` calls from several connections
+    group_commit: GroupCommit,
+    /// Tables as of the last `seal_snapshot`, for `Consistency::Snapshot`
+    snapshot: Mutex<Option<Arc<HashMap<String, Table>>>>,
+    /// Copy tables before a large `insert_batch_direct`; see
+    /// `set_snapshot_batches`
+    snapshot_batches: AtomicBool,
+    /// Copies of tables taken before a large `insert_batch_direct`, with
+    /// the number of such batches running, for `search_similar_snapshot`
+    ingesting: Mutex<HashMap<String, (Arc<Table>, usize)>>,
+    /// Keeps other processes from opening the file for writing
+    _writer_lock: Option<WriterLock>,
+    /// Taken by the async methods before `inner`, so async tasks queue for
+    /// the database without blocking their executor thread
+    #[cfg(feature = "async")]
+    gate: tokio::sync::RwLock<()>,
 }
 
 impl ConcurrentDatabase {
@@ -102,7 +285,19 @@ impl ConcurrentDatabase {
                 tables: HashMap::new(),
                 config: GraphConfig::default(),
                 path: None,
+                bulk_load: false,
             }),
+            readonly: false,
+            busy_timeout: Mutex::new(DEFAULT_BUSY_TIMEOUT),
+            synchronous: Mutex::new(Synchronous::default()),
+            parse_limits: Mutex::new(ParseLimits::default()),
+            group_commit: GroupCommit::default(),
+            snapshot: Mutex::new(None),
+            snapshot_batches: AtomicBool::new(false),
+            ingesting: Mutex::new(HashMap::new()),
+            _writer_lock: None,
+            #[cfg(feature = "async")]
+            gate: tokio::sync::RwLock::new(()),
         }
     }
 
@@ -113,19 +308,137 @@ impl ConcurrentDatabase {
                 tables: HashMap::new(),
                 config,
                 path: None,
+                bulk_load: false,
             }),
+            ..Self::in_memory()
         }
     }
 
-    /// Open or create a concurrent database file.
+    /// Open or create a concurrent database file. As with `Database::open`,
+    /// other processes cannot open it for writing until this is dropped.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
+        let writer_lock = Some(WriterLock::acquire(&path)?);
 
-        if path.exists() {
-            Self::load(&path)
+        let db = if path.exists() {
+            Self::load(&path)?
         } else {
-            Self::create_new(&path)
+            Self::create_new(&path)?
+        };
+        Ok(ConcurrentDatabase { _writer_lock: writer_lock, ..db })
+    }
+
+    /// Open an existing database file for reading only, e.g. on a serving
+    /// replica. Write statements, the direct write APIs and `save` fail with
+    /// `MarsError::ReadOnly`.
+    pub fn open_readonly<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(ConcurrentDatabase {
+            readonly: true,
+            ..Self::load(path.as_ref())?
+        })
+    }
+
+    /// Check whether the database was opened with `open_readonly`
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// How long `save` waits for other processes to finish reading the
+    /// file before failing with `MarsError::Locked` (default 5 s)
+    pub fn set_busy_timeout(&self, timeout: Duration) {
+        *lock_plain(&self.busy_timeout) = timeout;
+    }
+
+    pub fn busy_timeout(&self) -> Duration {
+        *lock_plain(&self.busy_timeout)
+    }
+
+    /// Durability of `save` (see `Database::set_synchronous`)
+    pub fn set_synchronous(&self, synchronous: Synchronous) {
+        *lock_plain(&self.synchronous) = synchronous;
+    }
+
+    pub fn synchronous(&self) -> Synchronous {
+        *lock_plain(&self.synchronous)
+    }
+
+    /// Bounds on the SQL every connection accepts
+    /// (see `Database::set_parse_limits`)
+    pub fn set_parse_limits(&self, limits: ParseLimits) {
+        *lock_plain(&self.parse_limits) = limits;
+    }
+
+    pub fn parse_limits(&self) -> ParseLimits {
+        *lock_plain(&self.parse_limits)
+    }
+
+    /// Copy every table into a read-only snapshot that searches with
+    /// `Consistency::Snapshot` read without taking the database lock. The
+    /// copy takes a read lock and time proportional to the data, so seal
+    /// after write batches or on a timer, not per query.
+    pub fn seal_snapshot(&self) -> Result<()> {
+        let tables = self.read().tables.iter()
+            .map(|(name, table)| Ok((name.clone(), copy_table(table)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        *lock_plain(&self.snapshot) = Some(Arc::new(tables));
+        Ok(())
+    }
+
+    /// Drop the sealed snapshot; `Consistency::Snapshot` reads the latest
+    /// data again until the next `seal_snapshot`
+    pub fn clear_snapshot(&self) {
+        *lock_plain(&self.snapshot) = None;
+    }
+
+    /// Have large `insert_batch_direct` calls first copy the table, so
+    /// `search_similar_snapshot` reads the copy instead of waiting for the
+    /// batch. Off by default, since each such batch copies the whole table.
+    pub fn set_snapshot_batches(&self, enabled: bool) {
+        self.snapshot_batches.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn snapshot_batches(&self) -> bool {
+        self.snapshot_batches.load(Ordering::Relaxed)
+    }
+
+    /// Publish a copy of a table for `search_similar_snapshot` to read
+    /// while a large batch is inserted into it
+    fn begin_ingest<'a>(&'a self, table_name: &'a str) -> Result<Ingesting<'a>> {
+        if let Some((_, batches)) = lock_plain(&self.ingesting).get_mut(table_name) {
+            *batches += 1;
+            return Ok(Ingesting { db: self, table: table_name });
+        }
+        let copy = {
+            let guard = self.read();
+            let table = guard.tables.get(table_name)
+                .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+            copy_table(table)?
+        };
+        lock_plain(&self.ingesting).entry(table_name.to_string())
+            .and_modify(|(_, batches)| *batches += 1)
+            .or_insert((Arc::new(copy), 1));
+        Ok(Ingesting { db: self, table: table_name })
+    }
+
+    /// Combine `insert_direct` calls from concurrent connections: the first
+    /// caller waits `window` for others to queue theirs, then inserts them
+    /// all under one write lock with one batch insert into each table's
+    /// index. Inserts queued while that runs form the next group, so even a
+    /// zero window batches under contention. None (the default) inserts
+    /// each row on its own.
+    pub fn set_group_commit(&self, window: Option<Duration>) {
+        *lock_plain(&self.group_commit.window) = window;
+    }
+
+    pub fn group_commit(&self) -> Option<Duration> {
+        *lock_plain(&self.group_commit.window)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.readonly {
+            return Err(MarsError::ReadOnly);
         }
+        Ok(())
     }
 
     fn create_new(path: &Path) -> Result<Self> {
@@ -134,7 +447,9 @@ impl ConcurrentDatabase {
                 tables: HashMap::new(),
                 config: GraphConfig::default(),
                 path: Some(path.to_path_buf()),
+                bulk_load: false,
             }),
+            ..Self::in_memory()
         };
 
         // Write empty database
@@ -148,13 +463,17 @@ impl ConcurrentDatabase {
         use std::io::{BufReader, Read};
 
         let mut file = File::open(path)?;
+        lock::lock_file(&file, false, DEFAULT_BUSY_TIMEOUT)?;
         let mut reader = BufReader::new(&mut file);
 
         // Read header
         let mut header_buf = [0u8; 8];
         reader.read_exact(&mut header_buf)?;
+        if crate::crypto::is_encrypted(&header_buf) {
+            return Err(MarsError::Encryption("encrypted databases can only be opened with Database::open_encrypted".into()));
+        }
 
-        let _version =
+        let version =
             u32::from_le_bytes([header_buf[0], header_buf[1], header_buf[2], header_buf[3]]);
         let table_count =
             u32::from_le_bytes([header_buf[4], header_buf[5], header_buf[6], header_buf[7]]);
@@ -170,24 +489,9 @@ impl ConcurrentDatabase {
             let mut table_buf = vec![0u8; size];
             reader.read_exact(&mut table_buf)?;
 
-            let table_data: TableData = bincode::deserialize(&table_buf)
-                .map_err(|e| MarsError::InvalidFormat(format!("Failed to deserialize table: {}", e)))?;
-
-            let mut table = Table::new(table_data.schema, GraphConfig::default())?;
-
-            for row in table_data.rows {
-                if let Some(vec_idx) = table.schema.columns.iter().position(|c| {
-                    matches!(c.data_type, ColumnType::Vector(_))
-                }) {
-                    if let Some(vec) = row.values.get(vec_idx).and_then(|v| v.as_vector()) {
-                        table.graph.insert(vec.to_vec());
-                    }
-                }
-                let id = row.id;
-                table.rows.insert(id, row);
-            }
-
-            table.next_id = table_data.next_id;
+            let mut data = TableData::decode(version, &table_buf)?;
+            data.read_external(Some(path))?;
+            let table = data.into_table()?;
             tables.insert(table.name().to_string(), table);
         }
 
@@ -196,7 +500,9 @@ impl ConcurrentDatabase {
                 tables,
                 config: GraphConfig::default(),
                 path: Some(path.to_path_buf()),
+                bulk_load: false,
             }),
+            ..Self::in_memory()
         })
     }
 
@@ -207,45 +513,49 @@ impl ConcurrentDatabase {
         use std::fs::OpenOptions;
         use std::io::{BufWriter, Write};
 
-        let inner = self.inner.read().unwrap();
+        self.check_writable()?;
+        if self.is_poisoned() {
+            return Err(poisoned());
+        }
+        let inner = self.read();
 
         let path = match &inner.path {
             Some(p) => p,
             None => return Ok(()),
         };
 
+        // Truncate only once no reader holds the lock
         let file = OpenOptions::new()
             .write(true)
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .open(path)?;
+        lock::lock_file(&file, true, self.busy_timeout())?;
+        file.set_len(0)?;
 
         let mut writer = BufWriter::new(file);
 
         let header = DbHeader {
-            version: 1,
+            version: FORMAT_VERSION,
             table_count: inner.tables.len() as u32,
         };
         writer.write_all(&header.version.to_le_bytes())?;
         writer.write_all(&header.table_count.to_le_bytes())?;
 
+        let mut texts = TextFileWriter::new(path)?;
         for table in inner.tables.values() {
-            let table_data = TableData {
-                schema: table.schema.clone(),
-                rows: table.rows.values().cloned().collect(),
-                centroid: table.graph.centroid().to_vec(),
-                next_id: table.next_id,
-            };
-
-            let serialized = bincode::serialize(&table_data)
+            let data = TableData::from_table(table).write_external(&mut texts)?;
+            let serialized = bincode::serialize(&data.compress_text().encode_vectors())
                 .map_err(|e| MarsError::InvalidFormat(format!("Failed to serialize table: {}", e)))?;
 
             writer.write_all(&(serialized.len() as u64).to_le_bytes())?;
             writer.write_all(&serialized)?;
         }
+        texts.finish(self.synchronous())?;
 
-        writer.flush()?;
-        Ok(())
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        self.synchronous().sync_file(&file, path)?;
+        texts.remove_old()
     }
 
     /// Create a new connection to this database.
@@ -254,19 +564,51 @@ impl ConcurrentDatabase {
     /// maintains its own transaction state.
     pub fn connect(&self) -> Connection<'_> {
         Connection {
-            db: self,
+            db: DbHandle::Borrowed(self),
+            transaction: None,
+            restricted: false,
+            settings: SessionSettings::default(),
+            last_insert_id: None,
+            attached: HashMap::new(),
+        }
+    }
+
+    /// Create a connection that owns a share of the database, so it is
+    /// `'static` and can be moved into spawned threads or async tasks.
+    pub fn connect_owned(self: &Arc<Self>) -> Connection<'static> {
+        Connection {
+            db: DbHandle::Shared(Arc::clone(self)),
             transaction: None,
+            restricted: false,
+            settings: SessionSettings::default(),
+            last_insert_id: None,
+            attached: HashMap::new(),
+        }
+    }
+
+    /// Create a connection that sees masked columns (`MASK ...`) only in
+    /// masked form. It cannot filter, sort, group or compute on masked
+    /// columns, or change masks, so the raw values cannot be inferred;
+    /// such statements fail with `MarsError::AccessDenied`.
+    pub fn connect_restricted(&self) -> Connection<'_> {
+        Connection {
+            restricted: true,
+            ..self.connect()
         }
     }
 
     /// Get a read guard for direct access.
+    ///
+    /// Reads go on after an operation panicked while writing (see
+    /// `is_poisoned`), showing the tables as that operation left them.
     pub fn read(&self) -> RwLockReadGuard<'_, DatabaseInner> {
-        self.inner.read().unwrap()
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
     }
 
-    /// Get a write guard for direct access.
-    pub fn write(&self) -> RwLockWriteGuard<'_, DatabaseInner> {
-        self.inner.write().unwrap()
+    /// Get a write guard for direct access, or `MarsError::LockPoisoned`
+    /// once a write has panicked.
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, DatabaseInner>> {
+        self.lock_write()
     }
 
     /// Execute a read operation with a read lock.
@@ -274,27 +616,144 @@ impl ConcurrentDatabase {
     where
         F: FnOnce(&DatabaseInner) -> T,
     {
-        let guard = self.inner.read().unwrap();
-        f(&guard)
+        f(&self.read())
     }
 
     /// Execute a write operation with a write lock.
-    pub fn with_write<F, T>(&self, f: F) -> T
+    pub fn with_write<F, T>(&self, f: F) -> Result<T>
     where
         F: FnOnce(&mut DatabaseInner) -> T,
     {
-        let mut guard = self.inner.write().unwrap();
-        f(&mut guard)
+        Ok(f(&mut *self.lock_write()?))
+    }
+
+    /// Whether an operation panicked while holding the write lock. Its
+    /// change may be half-applied (a row stored but not indexed, say), so
+    /// from then on writes and `save` fail with `MarsError::LockPoisoned`
+    /// while reads carry on.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Accept the tables as the panicking operation left them and allow
+    /// writes again, for instance after checking them or dropping a table
+    /// that may be damaged.
+    pub fn clear_poison(&self) {
+        self.inner.clear_poison();
+    }
+
+    /// Take the write lock, refusing once a write has panicked
+    fn lock_write(&self) -> Result<RwLockWriteGuard<'_, DatabaseInner>> {
+        self.inner.write().map_err(|_| poisoned())
+    }
+
+    /// Repair the vector index of every table that has seen enough deletes.
+    ///
+    /// Each table is repaired under its own short write lock so readers are
+    /// not blocked for the whole pass. Returns the number of nodes repaired.
+    pub fn repair_indexes(&self) -> usize {
+        let names: Vec<String> = self.read().tables.iter()
+            .filter(|(_, t)| t.graph.needs_repair())
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        names.iter()
+            .map(|name| {
+                self.lock_write().map_or(0, |mut guard| {
+                    guard.tables.get_mut(name).map_or(0, |t| t.repair_index())
+                })
+            })
+            .sum()
+    }
+
+    /// Run `repair_indexes()` on a background thread every `interval`.
+    ///
+    /// The worker stops when the returned handle is stopped or dropped.
+    pub fn spawn_repair_worker(db: Arc<ConcurrentDatabase>, interval: Duration) -> RepairWorker {
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            while !worker_stop.load(Ordering::Acquire) {
+                db.repair_indexes();
+                thread::park_timeout(interval);
+            }
+        });
+
+        RepairWorker { stop, handle: Some(handle) }
+    }
+}
+
+/// Handle to a background index repair thread.
+pub struct RepairWorker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RepairWorker {
+    /// Stop the worker and wait for it to finish its current pass.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for RepairWorker {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
 
 /// A connection to a concurrent database.
 ///
 /// Connections can execute operations and manage transactions.
-/// Each connection maintains its own transaction state.
+/// Each connection maintains its own transaction state and settings.
 pub struct Connection<'a> {
-    db: &'a ConcurrentDatabase,
+    db: DbHandle<'a>,
     transaction: Option<TransactionState>,
+    /// Return masked columns masked and refuse queries that would reveal them
+    restricted: bool,
+    settings: SessionSettings,
+    /// ID of the row this connection last inserted
+    last_insert_id: Option<u64>,
+    /// Databases attached with ATTACH DATABASE, by name
+    attached: HashMap<String, ConcurrentDatabase>,
+}
+
+/// Settings changed with SET on one connection, leaving other connections
+/// of the same database alone
+#[derive(Clone, Debug, Default)]
+struct SessionSettings {
+    /// ef_search of SQL similarity queries; None uses `DEFAULT_EF_SEARCH`
+    ef_search: Option<usize>,
+    statement_timeout: Option<Duration>,
+    /// Most rows a SELECT returns, whatever its LIMIT
+    max_rows: Option<usize>,
+}
+
+/// The database behind a connection
+#[derive(Clone)]
+enum DbHandle<'a> {
+    Borrowed(&'a ConcurrentDatabase),
+    Shared(Arc<ConcurrentDatabase>),
+}
+
+impl Deref for DbHandle<'_> {
+    type Target = ConcurrentDatabase;
+
+    fn deref(&self) -> &ConcurrentDatabase {
+        match self {
+            DbHandle::Borrowed(db) => db,
+            DbHandle::Shared(db) => db,
+        }
+    }
 }
 
 /// Transaction state for a connection.
@@ -304,38 +763,101 @@ struct TransactionState {
 
 /// A pending operation in a transaction.
 enum PendingOperation {
-    CreateTable { name: String, columns: Vec<crate::parser::ColumnDef> },
+    CreateTable { name: String, columns: Vec<crate::parser::ColumnDef>, metric: Option<String>, normalize: bool, columnar: bool },
     DropTable { name: String, if_exists: bool },
-    Insert { table: String, columns: Vec<String>, values: Vec<Vec<Value>> },
-    Update { table: String, assignments: Vec<(String, Value)>, where_clause: Option<crate::parser::WhereClause> },
-    Delete { table: String, where_clause: Option<crate::parser::WhereClause> },
+    AlterTable { name: String, action: AlterAction },
+    Insert { table: String, columns: Vec<String>, values: Vec<Vec<Value>>, on_conflict: OnConflict },
+    Update { table: String, assignments: Vec<(String, ScalarExpr)>, where_clause: Option<crate::parser::WhereClause> },
+    Delete { table: String, where_clause: Option<crate::parser::WhereClause>, soft: bool },
 }
 
 impl<'a> Connection<'a> {
     /// Execute a SQL command.
     ///
-    /// If a transaction is active, the operation is queued for later execution.
-    /// Otherwise, it's executed immediately.
+    /// If a transaction is active, writes are queued for later execution and
+    /// return `ExecuteResult::Queued`. Otherwise, they're executed immediately.
     pub fn execute(&mut self, sql: &str) -> Result<ExecuteResult> {
-        let command = parse(sql)?;
+        self.execute_parsed(parse_with_limits(sql, &self.db.parse_limits())?)
+    }
+
+    /// Execute a prepared statement with `params` bound to its placeholders;
+    /// see `execute`
+    pub fn execute_prepared(&mut self, statement: &PreparedStatement, params: &[Value]) -> Result<ExecuteResult> {
+        self.execute_parsed(statement.bind(params)?)
+    }
+
+    /// `execute` for async tasks. While other async connections hold the
+    /// database, this waits without blocking the executor thread; writes
+    /// wait for exclusive access, reads share it.
+    #[cfg(feature = "async")]
+    pub async fn execute_async(&mut self, sql: &str) -> Result<ExecuteResult> {
+        let command = parse_with_limits(sql, &self.db.parse_limits())?;
+        let db = self.db.clone();
+        if command.is_write() && self.transaction.is_none() {
+            let _gate = db.gate.write().await;
+            self.execute_parsed(command)
+        } else {
+            let _gate = db.gate.read().await;
+            self.execute_parsed(command)
+        }
+    }
+
+    /// `commit` for async tasks; see `execute_async`
+    #[cfg(feature = "async")]
+    pub async fn commit_async(&mut self) -> Result<Vec<ExecuteResult>> {
+        let db = self.db.clone();
+        let _gate = db.gate.write().await;
+        self.commit()
+    }
+
+    /// Run a statement under this connection's statement timeout
+    fn execute_parsed(&mut self, command: Command) -> Result<ExecuteResult> {
+        let Some(timeout) = self.settings.statement_timeout else {
+            return self.execute_statement(command);
+        };
+        let scope = InterruptHandle::default().enter(Some(Instant::now() + timeout));
+        let result = self.execute_statement(command);
+        if !scope.timed_out() {
+            return result;
+        }
+        match result {
+            // Queries cut short return partial rows, so they fail too
+            Err(MarsError::Interrupted)
+            | Ok(ExecuteResult::Select { .. } | ExecuteResult::SelectSimilar { .. } | ExecuteResult::Aggregate { .. }) => {
+                Err(MarsError::Timeout(format!("statement exceeded statement_timeout of {} ms", timeout.as_millis())))
+            }
+            result => result,
+        }
+    }
+
+    fn execute_statement(&mut self, command: Command) -> Result<ExecuteResult> {
+        if command.is_write() {
+            self.db.check_writable()?;
+        }
+        if self.restricted {
+            check_masked_access(&self.db.read(), &command)?;
+        }
 
         if let Some(ref mut tx) = self.transaction {
             // Queue operation for transaction
             let pending = match command {
-                Command::CreateTable { name, columns } => {
-                    PendingOperation::CreateTable { name, columns }
+                Command::CreateTable { name, columns, metric, normalize, columnar } => {
+                    PendingOperation::CreateTable { name, columns, metric, normalize, columnar }
                 }
                 Command::DropTable { name, if_exists } => {
                     PendingOperation::DropTable { name, if_exists }
                 }
-                Command::Insert { table, columns, values } => {
-                    PendingOperation::Insert { table, columns, values }
+                Command::AlterTable { name, action } => {
+                    PendingOperation::AlterTable { name, action }
+                }
+                Command::Insert { table, columns, values, on_conflict } => {
+                    PendingOperation::Insert { table, columns, values, on_conflict }
                 }
                 Command::Update { table, assignments, where_clause } => {
                     PendingOperation::Update { table, assignments, where_clause }
                 }
-                Command::Delete { table, where_clause } => {
-                    PendingOperation::Delete { table, where_clause }
+                Command::Delete { table, where_clause, soft } => {
+                    PendingOperation::Delete { table, where_clause, soft }
                 }
                 Command::Select { .. } => {
                     // SELECT is immediate even in transaction
@@ -345,36 +867,190 @@ impl<'a> Connection<'a> {
                     // JOIN is immediate even in transaction
                     return self.execute_command(command);
                 }
-                Command::ShowTables => {
+                Command::ShowTables | Command::Set { .. } | Command::Pragma { .. }
+                | Command::InsertSelect { .. } | Command::Attach { .. } | Command::Detach { .. }
+                | Command::CreateMaterializedView { .. } | Command::DropMaterializedView { .. } | Command::CreateProjection { .. }
+                | Command::Explain { .. } | Command::Analyze { .. } | Command::Vacuum { .. } | Command::LastInsertRowid
+                | Command::Reindex { .. } | Command::CheckRecall { .. } | Command::BeginBulkLoad | Command::EndBulkLoad => {
                     return self.execute_command(command);
                 }
             };
+            let index = tx.operations.len();
             tx.operations.push(pending);
-            Ok(ExecuteResult::Insert { id: 0 }) // Placeholder
+            Ok(ExecuteResult::Queued { index })
         } else {
             self.execute_command(command)
         }
     }
 
+    /// Execute a script of `;`-separated statements in order, returning one
+    /// result per statement. Stops at the first error; inside a transaction
+    /// the writes are queued as with `execute`.
+    pub fn execute_script(&mut self, sql: &str) -> Result<Vec<ExecuteResult>> {
+        split_statements(sql).iter()
+            .map(|statement| self.execute(statement))
+            .collect()
+    }
+
     fn execute_command(&mut self, command: Command) -> Result<ExecuteResult> {
         match command {
-            Command::CreateTable { name, columns } => self.create_table(name, columns),
+            Command::CreateTable { name, columns, metric, normalize, columnar } => {
+                self.create_table(name, columns, metric, normalize, columnar)
+            }
             Command::DropTable { name, if_exists } => self.drop_table(name, if_exists),
-            Command::Insert { table, columns, values } => self.insert_multi(table, columns, values),
-            Command::Select { table, columns, where_clause, group_by, having, order_by, limit, offset, distinct } => {
-                // GROUP BY not yet supported in concurrent module, ignoring for now
-                let _ = group_by;
-                let _ = having;
-                self.select(table, columns, where_clause.as_ref(), order_by.as_ref(), limit, offset, distinct)
+            Command::AlterTable { name, action } => self.alter_table(name, action),
+            Command::Insert { table, columns, values, on_conflict } => self.insert_multi(table, columns, values, on_conflict),
+            Command::Select { table, columns, where_clause, group_by, having, order_by, limit, offset, distinct, as_of, diversity, score } => {
+                let query = SelectQuery {
+                    columns,
+                    where_clause: where_clause.as_ref(),
+                    group_by: group_by.as_deref(),
+                    having: having.as_ref(),
+                    order_by: order_by.as_ref(),
+                    limit,
+                    offset,
+                    distinct,
+                    as_of,
+                    diversity: diversity.as_ref(),
+                    score: score.as_ref(),
+                };
+                self.select(&table, query)
             }
             Command::Update { table, assignments, where_clause } => {
                 self.update(table, assignments, where_clause.as_ref())
             }
-            Command::Delete { table, where_clause } => self.delete(table, where_clause.as_ref()),
+            Command::Delete { table, where_clause, soft } => self.delete(table, where_clause.as_ref(), soft),
             Command::ShowTables => self.show_tables(),
-            Command::Join { .. } => {
-                Err(MarsError::InvalidFormat("JOIN not supported in concurrent module yet".into()))
+            Command::Join { left_table, right_table, join_type, left_column, right_column, columns, where_clause, order_by, limit, offset } => {
+                let guard = self.db.read();
+                let limit = self.row_limit(limit);
+                let result = engine::join(&guard.tables, left_table.clone(), right_table.clone(), join_type, left_column, right_column, columns, where_clause.as_ref(), order_by.as_ref(), limit, offset)?;
+                Ok(self.masked_join(&guard.tables, &left_table, &right_table, result))
+            }
+            Command::Set { name, value } => self.set_option(name, value),
+            Command::Pragma { name, value } => self.pragma(name, value),
+            Command::InsertSelect { table, source_database, source } => self.insert_select(table, source_database, source),
+            Command::Attach { path, name } => self.attach(path, name),
+            Command::Detach { name } => {
+                self.attached.remove(&name).ok_or_else(|| MarsError::InvalidConfig(format!("No attached database '{}'", name)))?;
+                Ok(ExecuteResult::Detach { name })
+            }
+            Command::CreateMaterializedView { name, select } => {
+                let mut guard = self.db.lock_write()?;
+                engine::create_view(&mut guard.tables, name, &select)
+            }
+            Command::DropMaterializedView { name, if_exists } => {
+                let mut guard = self.db.lock_write()?;
+                engine::drop_view(&mut guard.tables, name, if_exists)
+            }
+            Command::CreateProjection { name, table, column, dimension, method, sample } => {
+                let mut guard = self.db.lock_write()?;
+                engine::create_projection(&mut guard.tables, name, table, &column, dimension, method, sample)
+            }
+            Command::Explain { statement } => engine::explain(&self.db.read().tables, *statement),
+            Command::Analyze { table } => {
+                let mut guard = self.db.lock_write()?;
+                engine::analyze(&mut guard.tables, table)
+            }
+            Command::Vacuum { table } => {
+                let mut guard = self.db.lock_write()?;
+                engine::vacuum(&mut guard.tables, table)
+            }
+            Command::LastInsertRowid => Ok(engine::last_insert_rowid(self.last_insert_id)),
+            Command::Reindex { table } => {
+                let mut guard = self.db.lock_write()?;
+                engine::reindex(&mut guard.tables, table, thread_count())
+            }
+            Command::CheckRecall { table, sample, k } => {
+                engine::check_recall(&self.db.read().tables, table, sample, k, self.settings.ef_search)
+            }
+            Command::BeginBulkLoad => {
+                let mut guard = self.db.lock_write()?;
+                let DatabaseInner { tables, bulk_load, .. } = &mut *guard;
+                engine::begin_bulk_load(tables, bulk_load)
+            }
+            Command::EndBulkLoad => {
+                let mut guard = self.db.lock_write()?;
+                let DatabaseInner { tables, bulk_load, .. } = &mut *guard;
+                engine::end_bulk_load(tables, bulk_load, thread_count())
+            }
+        }
+    }
+
+    /// Whether this connection was opened with `connect_restricted`
+    pub fn is_restricted(&self) -> bool {
+        self.restricted
+    }
+
+    /// Row values as this connection may see them
+    fn visible(&self, table: &Table, values: Vec<Value>) -> Vec<Value> {
+        if self.restricted {
+            table.mask_row(values)
+        } else {
+            values
+        }
+    }
+
+    /// Mask a SELECT result for a restricted connection
+    fn masked(&self, table: &Table, result: ExecuteResult) -> ExecuteResult {
+        if !self.restricted || table.masks().is_empty() {
+            return result;
+        }
+        match result {
+            ExecuteResult::Select { mut rows, schema } => {
+                let masks: Vec<Option<Mask>> = schema.columns.iter().map(|c| table.mask(&c.name)).collect();
+                mask_rows(&mut rows, &masks);
+                ExecuteResult::Select { rows, schema }
+            }
+            ExecuteResult::SelectSimilar { results, schema, metric } => {
+                let results = results.into_iter()
+                    .map(|(mut row, dist)| {
+                        row.values = table.mask_row(row.values);
+                        (row, dist)
+                    })
+                    .collect();
+                ExecuteResult::SelectSimilar { results, schema, metric }
+            }
+            ExecuteResult::Aggregate { results } => {
+                let results = results.into_iter()
+                    .map(|(name, value)| {
+                        let value = match table.mask(&name) {
+                            Some(mask) => mask.apply(&value),
+                            None => value,
+                        };
+                        (name, value)
+                    })
+                    .collect();
+                ExecuteResult::Aggregate { results }
+            }
+            other => other,
+        }
+    }
+
+    /// Mask a JOIN result for a restricted connection; its columns are
+    /// named `table.column`
+    fn masked_join(&self, tables: &HashMap<String, Table>, left: &str, right: &str, result: ExecuteResult) -> ExecuteResult {
+        match result {
+            ExecuteResult::Select { mut rows, schema } if self.restricted => {
+                let masks: Vec<Option<Mask>> = schema.columns.iter()
+                    .map(|c| {
+                        let (table, column) = c.name.split_once('.')?;
+                        let name = [left, right].into_iter().find(|n| n.eq_ignore_ascii_case(table))?;
+                        tables.get(name)?.mask(column)
+                    })
+                    .collect();
+                mask_rows(&mut rows, &masks);
+                ExecuteResult::Select { rows, schema }
             }
+            other => other,
+        }
+    }
+
+    /// A statement's LIMIT, capped by this connection's `max_rows`
+    fn row_limit(&self, limit: Option<usize>) -> Option<usize> {
+        match (limit, self.settings.max_rows) {
+            (Some(limit), Some(max)) => Some(limit.min(max)),
+            (limit, max) => limit.or(max),
         }
     }
 
@@ -394,15 +1070,18 @@ impl<'a> Connection<'a> {
     /// Commit the current transaction.
     ///
     /// All queued operations are executed atomically with an exclusive lock.
+    /// The returned results are in statement order, so the result for a write
+    /// that returned `ExecuteResult::Queued { index }` is at `results[index]`.
     pub fn commit(&mut self) -> Result<Vec<ExecuteResult>> {
         let tx = self.transaction.take()
             .ok_or_else(|| MarsError::InvalidFormat("No transaction in progress".into()))?;
 
         let mut results = Vec::new();
-        let mut guard = self.db.inner.write().unwrap();
+        let mut guard = self.db.lock_write()?;
 
         for op in tx.operations {
             let result = self.execute_pending(&mut guard, op)?;
+            self.last_insert_id = inserted_id(&result).or(self.last_insert_id);
             results.push(result);
         }
 
@@ -428,20 +1107,23 @@ impl<'a> Connection<'a> {
         op: PendingOperation,
     ) -> Result<ExecuteResult> {
         match op {
-            PendingOperation::CreateTable { name, columns } => {
-                Self::create_table_inner(inner, name, columns)
+            PendingOperation::CreateTable { name, columns, metric, normalize, columnar } => {
+                Self::create_table_inner(inner, name, columns, metric, normalize, columnar)
             }
             PendingOperation::DropTable { name, if_exists } => {
-                Self::drop_table_inner(inner, name, if_exists)
+                engine::drop_table(&mut inner.tables, name, if_exists)
+            }
+            PendingOperation::AlterTable { name, action } => {
+                engine::alter_table(&mut inner.tables, name, action)
             }
-            PendingOperation::Insert { table, columns, values } => {
-                Self::insert_inner(inner, table, columns, values)
+            PendingOperation::Insert { table, columns, values, on_conflict } => {
+                engine::insert(&mut inner.tables, &table, &columns, values, on_conflict, InsertHooks::default())
             }
             PendingOperation::Update { table, assignments, where_clause } => {
-                Self::update_inner(inner, table, assignments, where_clause.as_ref())
+                engine::update(&mut inner.tables, &table, &assignments, where_clause.as_ref())
             }
-            PendingOperation::Delete { table, where_clause } => {
-                Self::delete_inner(inner, table, where_clause.as_ref())
+            PendingOperation::Delete { table, where_clause, soft } => {
+                engine::delete(&mut inner.tables, &table, where_clause.as_ref(), soft)
             }
         }
     }
@@ -450,165 +1132,112 @@ impl<'a> Connection<'a> {
         inner: &mut DatabaseInner,
         name: String,
         columns: Vec<crate::parser::ColumnDef>,
+        metric: Option<String>,
+        normalize: bool,
+        columnar: bool,
     ) -> Result<ExecuteResult> {
-        if inner.tables.contains_key(&name) {
-            return Err(MarsError::InvalidConfig(format!("Table '{}' already exists", name)));
-        }
-
-        let mut schema = Schema::new(&name);
-        for col_def in columns {
-            let is_vector = matches!(col_def.data_type, ColumnType::Vector(_));
-            let col_name = col_def.name.clone();
-
-            let mut col = Column::new(&col_def.name, col_def.data_type);
-            col.primary_key = col_def.primary_key;
-            col.nullable = !col_def.not_null;
-            schema.columns.push(col);
-
-            if is_vector {
-                schema.vector_column = Some(col_name);
-            }
+        let mut table = engine::new_table(&inner.tables, &inner.config, &name, columns, metric, normalize, columnar)?;
+        if inner.bulk_load {
+            table.defer_index();
         }
-
-        let table = Table::new(schema, inner.config.clone())?;
         inner.tables.insert(name.clone(), table);
-
         Ok(ExecuteResult::CreateTable { name })
     }
 
-    fn drop_table_inner(inner: &mut DatabaseInner, name: String, if_exists: bool) -> Result<ExecuteResult> {
-        if inner.tables.remove(&name).is_none() && !if_exists {
-            return Err(MarsError::InvalidFormat(format!("Table '{}' does not exist", name)));
-        }
-        Ok(ExecuteResult::DropTable { name })
-    }
-
-    fn insert_inner(
-        inner: &mut DatabaseInner,
-        table_name: String,
-        columns: Vec<String>,
-        values: Vec<Vec<Value>>,
-    ) -> Result<ExecuteResult> {
-        let table = inner.tables.get_mut(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
-
-        let mut last_id = 0u64;
-        for row_values in values {
-            last_id = table.insert(&columns, row_values)?;
-        }
-        Ok(ExecuteResult::Insert { id: last_id })
-    }
-
-    fn update_inner(
-        inner: &mut DatabaseInner,
-        table_name: String,
-        assignments: Vec<(String, Value)>,
-        where_clause: Option<&crate::parser::WhereClause>,
+    fn create_table(
+        &mut self,
+        name: String,
+        columns: Vec<crate::parser::ColumnDef>,
+        metric: Option<String>,
+        normalize: bool,
+        columnar: bool,
     ) -> Result<ExecuteResult> {
-        let table = inner.tables.get_mut(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
-
-        let count = table.update(&assignments, where_clause)?;
-        Ok(ExecuteResult::Update { count })
+        let mut guard = self.db.lock_write()?;
+        Self::create_table_inner(&mut guard, name, columns, metric, normalize, columnar)
     }
 
-    fn delete_inner(
-        inner: &mut DatabaseInner,
-        table_name: String,
-        where_clause: Option<&crate::parser::WhereClause>,
-    ) -> Result<ExecuteResult> {
-        let table = inner.tables.get_mut(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
-
-        let count = table.delete(where_clause)?;
-        Ok(ExecuteResult::Delete { count })
+    fn drop_table(&mut self, name: String, if_exists: bool) -> Result<ExecuteResult> {
+        let mut guard = self.db.lock_write()?;
+        engine::drop_table(&mut guard.tables, name, if_exists)
     }
 
-    fn create_table(&mut self, name: String, columns: Vec<crate::parser::ColumnDef>) -> Result<ExecuteResult> {
-        let mut guard = self.db.inner.write().unwrap();
-        Self::create_table_inner(&mut guard, name, columns)
+    fn alter_table(&mut self, name: String, action: AlterAction) -> Result<ExecuteResult> {
+        let mut guard = self.db.lock_write()?;
+        engine::alter_table(&mut guard.tables, name, action)
     }
 
-    fn drop_table(&mut self, name: String, if_exists: bool) -> Result<ExecuteResult> {
-        let mut guard = self.db.inner.write().unwrap();
-        Self::drop_table_inner(&mut guard, name, if_exists)
+    fn insert_multi(&mut self, table: String, columns: Vec<String>, values: Vec<Vec<Value>>, on_conflict: OnConflict) -> Result<ExecuteResult> {
+        let mut guard = self.db.lock_write()?;
+        let result = engine::insert(&mut guard.tables, &table, &columns, values, on_conflict, InsertHooks::default())?;
+        self.last_insert_id = inserted_id(&result).or(self.last_insert_id);
+        Ok(result)
     }
 
-    fn insert_multi(&mut self, table: String, columns: Vec<String>, values: Vec<Vec<Value>>) -> Result<ExecuteResult> {
-        let mut guard = self.db.inner.write().unwrap();
-        Self::insert_inner(&mut guard, table, columns, values)
+    /// INSERT INTO table SELECT * FROM [database.]source, merging the rows
+    /// as `Database::merge_from` does
+    fn insert_select(&mut self, table_name: String, source_database: Option<String>, source: String) -> Result<ExecuteResult> {
+        let other = match &source_database {
+            Some(name) => Some(self.attached.get(name)
+                .ok_or_else(|| MarsError::InvalidConfig(format!("No attached database '{}'", name)))?.read()),
+            None => None,
+        };
+        let mut guard = self.db.lock_write()?;
+        let source_table = other.as_ref().map_or(&guard.tables, |other| &other.tables).get(&source)
+            .ok_or_else(|| MarsError::TableNotFound(source.clone()))?;
+        let segment = engine::insert_select(&guard.tables, &table_name, source_table)?;
+        let ids = guard.tables.get_mut(&table_name).unwrap().merge_segment(segment, thread_count())?;
+        self.last_insert_id = ids.last().copied().or(self.last_insert_id);
+        Ok(ExecuteResult::Insert { id: ids.last().copied().unwrap_or(0) })
     }
 
-    fn select(
-        &self,
-        table_name: String,
-        columns: Vec<crate::parser::SelectColumn>,
-        where_clause: Option<&crate::parser::WhereClause>,
-        order_by: Option<&crate::parser::OrderBy>,
-        limit: Option<usize>,
-        offset: Option<usize>,
-        distinct: bool,
-    ) -> Result<ExecuteResult> {
-        let guard = self.db.inner.read().unwrap();
-
-        let table = guard.tables.get(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
-
-        // Check for vector similarity query
-        if let Some(wc) = where_clause {
-            for cond in &wc.conditions {
-                if cond.operator == ComparisonOp::Similar {
-                    if let crate::parser::ConditionValue::Single(Value::Vector(query_vec)) = &cond.value {
-                        let k = limit.unwrap_or(10);
-                        let results = table.select_by_similarity(query_vec, k, 100);
-                        return Ok(ExecuteResult::SelectSimilar { results });
-                    }
-                }
-            }
+    /// ATTACH DATABASE 'path' AS name, for this connection only
+    fn attach(&mut self, path: String, name: String) -> Result<ExecuteResult> {
+        if self.attached.contains_key(&name) {
+            return Err(MarsError::InvalidConfig(format!("Database '{}' is already attached", name)));
         }
+        let other = ConcurrentDatabase::open_readonly(&path)?;
+        self.attached.insert(name.clone(), other);
+        Ok(ExecuteResult::Attach { name })
+    }
 
-        // Convert SelectColumn to column names
-        let col_names: Vec<String> = columns.iter()
-            .filter_map(|c| match c {
-                crate::parser::SelectColumn::Column(name) => Some(name.clone()),
-                _ => None,
-            })
-            .collect();
-
-        let is_star = columns.iter().any(|c| matches!(c, crate::parser::SelectColumn::All));
+    fn select(&self, table_name: &str, mut query: SelectQuery) -> Result<ExecuteResult> {
+        let guard = self.db.read();
 
-        let rows = table.select(
-            if is_star { &[] } else { &col_names },
-            where_clause,
-            limit,
-            offset,
-            order_by,
-            distinct,
-        );
-        Ok(ExecuteResult::Select { rows })
+        let view;
+        let table = match guard.tables.get(table_name) {
+            Some(table) => table,
+            None => {
+                view = engine::view(&guard.tables, table_name).ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+                &view
+            }
+        };
+        query.limit = self.row_limit(query.limit);
+        let result = engine::select(table, query, self.ef_search())?;
+        Ok(self.masked(table, result))
     }
 
     fn update(
         &mut self,
         table_name: String,
-        assignments: Vec<(String, Value)>,
+        assignments: Vec<(String, ScalarExpr)>,
         where_clause: Option<&crate::parser::WhereClause>,
     ) -> Result<ExecuteResult> {
-        let mut guard = self.db.inner.write().unwrap();
-        Self::update_inner(&mut guard, table_name, assignments, where_clause)
+        let mut guard = self.db.lock_write()?;
+        engine::update(&mut guard.tables, &table_name, &assignments, where_clause)
     }
 
     fn delete(
         &mut self,
         table_name: String,
         where_clause: Option<&crate::parser::WhereClause>,
+        soft: bool,
     ) -> Result<ExecuteResult> {
-        let mut guard = self.db.inner.write().unwrap();
-        Self::delete_inner(&mut guard, table_name, where_clause)
+        let mut guard = self.db.lock_write()?;
+        engine::delete(&mut guard.tables, &table_name, where_clause, soft)
     }
 
     fn show_tables(&self) -> Result<ExecuteResult> {
-        let guard = self.db.inner.read().unwrap();
+        let guard = self.db.read();
 
         let tables: Vec<TableInfo> = guard.tables.values()
             .map(|t| TableInfo {
@@ -621,81 +1250,243 @@ impl<'a> Connection<'a> {
         Ok(ExecuteResult::ShowTables { tables })
     }
 
-    /// Direct insert without SQL parsing.
-    pub fn insert_direct(
-        &mut self,
-        table_name: &str,
-        vector: Vec<f32>,
-        metadata: Vec<(&str, Value)>,
-    ) -> Result<u64> {
-        let mut guard = self.db.inner.write().unwrap();
+    /// SET name = value for this connection only; 0 restores the default
+    fn set_option(&mut self, name: String, value: Value) -> Result<ExecuteResult> {
+        self.apply_setting(&name, &value)?;
+        Ok(ExecuteResult::Set { name, value })
+    }
 
-        let table = guard.tables.get_mut(table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
+    /// PRAGMA name [= value]; reports the setting's (new) value
+    fn pragma(&mut self, name: String, value: Option<Value>) -> Result<ExecuteResult> {
+        if let Some(value) = value {
+            self.apply_setting(&name, &value)?;
+        }
+        let value = self.setting(&name)?;
+        Ok(ExecuteResult::Pragma { name, value })
+    }
 
-        let mut row_values: Vec<Value> = table.schema.columns.iter()
-            .map(|_| Value::Null)
-            .collect();
+    /// Settings shared by SET and PRAGMA
+    fn apply_setting(&mut self, name: &str, value: &Value) -> Result<()> {
+        let n = value.as_integer().filter(|n| *n >= 0).ok_or_else(|| {
+            MarsError::InvalidConfig(format!("{} must be a non-negative integer", name))
+        })? as usize;
+        let n = (n > 0).then_some(n);
+        match name {
+            "ef_search" => self.set_ef_search(n),
+            "statement_timeout" => self.set_statement_timeout(n.map(|ms| Duration::from_millis(ms as u64))),
+            "max_rows" => self.set_max_rows(n),
+            _ => return Err(MarsError::InvalidConfig(format!("Unknown setting: {}", name))),
+        }
+        Ok(())
+    }
+
+    /// Current value of a setting; defaults read as 0
+    fn setting(&self, name: &str) -> Result<Value> {
+        let value = match name {
+            "ef_search" => self.settings.ef_search,
+            "statement_timeout" => self.settings.statement_timeout.map(|t| t.as_millis() as usize),
+            "max_rows" => self.settings.max_rows,
+            _ => return Err(MarsError::InvalidConfig(format!("Unknown setting: {}", name))),
+        };
+        Ok(Value::Integer(value.unwrap_or(0) as i64))
+    }
+
+    /// Search breadth of this connection's SQL similarity queries; None
+    /// (the default) uses 100. Also settable with `SET ef_search = <n>;`.
+    pub fn set_ef_search(&mut self, ef_search: Option<usize>) {
+        self.settings.ef_search = ef_search.filter(|&ef| ef > 0);
+    }
+
+    pub fn ef_search(&self) -> usize {
+        self.settings.ef_search.unwrap_or(DEFAULT_EF_SEARCH)
+    }
+
+    /// Fail this connection's statements that run longer than `timeout`
+    /// with `MarsError::Timeout`; None disables the limit. Also settable
+    /// with `SET statement_timeout = <ms>;`.
+    pub fn set_statement_timeout(&mut self, timeout: Option<Duration>) {
+        self.settings.statement_timeout = timeout.filter(|t| !t.is_zero());
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.settings.statement_timeout
+    }
+
+    /// Return at most `rows` rows from this connection's SELECTs, even
+    /// with a larger or no LIMIT; None removes the cap. Also settable with
+    /// `SET max_rows = <n>;`.
+    pub fn set_max_rows(&mut self, rows: Option<usize>) {
+        self.settings.max_rows = rows.filter(|&n| n > 0);
+    }
+
+    pub fn max_rows(&self) -> Option<usize> {
+        self.settings.max_rows
+    }
 
-        for (i, col) in table.schema.columns.iter().enumerate() {
-            if matches!(col.data_type, ColumnType::Vector(_)) {
-                row_values[i] = Value::Vector(vector.clone());
+    /// Direct insert without SQL parsing. Like `Database::insert_direct`,
+    /// the vector may be owned, borrowed or an `Arc<[f32]>`.
+    pub fn insert_direct(
+        &mut self,
+        table_name: &str,
+        vector: impl IntoVector,
+        metadata: Vec<(&str, Value)>,
+    ) -> Result<u64> {
+        self.db.check_writable()?;
+        let id = if let Some(window) = self.db.group_commit() {
+            let metadata = metadata.into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+            self.insert_grouped(window, table_name.to_string(), vector.into_vector(), metadata)?
+        } else {
+            let mut guard = self.db.lock_write()?;
+
+            let table = guard.tables.get(table_name)
+                .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+            let row_values = table.direct_row_values(vector.into_vector(), metadata)?;
+            engine::check_references(&guard.tables, table, &row_values)?;
+            guard.tables.get_mut(table_name).unwrap().insert_row(row_values)?
+        };
+        self.last_insert_id = Some(id);
+        Ok(id)
+    }
+
+    /// ID of the row this connection most recently inserted, also read
+    /// with `SELECT last_insert_rowid()`. Other connections' inserts don't
+    /// change it.
+    pub fn last_insert_id(&self) -> Option<u64> {
+        self.last_insert_id
+    }
+
+    /// Queue an insert for group commit and wait for its row ID, leading
+    /// the group's insert when no other caller is
+    fn insert_grouped(&self, window: Duration, table: String, vector: Vec<f32>, metadata: Vec<(String, Value)>) -> Result<u64> {
+        let group = &self.db.group_commit;
+        let mut queue = lock_plain(&group.queue);
+        let ticket = queue.next_ticket;
+        queue.next_ticket += 1;
+        queue.pending.push(QueuedInsert { ticket, table, vector, metadata });
+
+        loop {
+            if let Some(result) = queue.results.remove(&ticket) {
+                return result;
+            }
+            if queue.leading {
+                queue = group.done.wait(queue).unwrap_or_else(PoisonError::into_inner);
+                continue;
             }
-        }
 
-        for (col_name, value) in metadata {
-            if let Some(idx) = table.schema.columns.iter().position(|c| &c.name == col_name) {
-                row_values[idx] = value;
+            queue.leading = true;
+            drop(queue);
+            let mut leader = GroupLeader { group, tickets: Vec::new(), results: Vec::new() };
+            if !window.is_zero() {
+                thread::sleep(window);
+            }
+            // Inserts queued while waiting for the lock join this group
+            let guard = self.db.lock_write();
+            let inserts = std::mem::take(&mut lock_plain(&group.queue).pending);
+            leader.tickets = inserts.iter().map(|insert| insert.ticket).collect();
+            if let Ok(mut guard) = guard {
+                leader.results = insert_group(&mut guard, inserts);
             }
+            drop(leader);
+            queue = lock_plain(&group.queue);
         }
-
-        table.insert_row(row_values)
     }
 
     /// Batch insert without SQL parsing - significantly faster than individual inserts.
-    pub fn insert_batch_direct(
+    ///
+    /// With `ConcurrentDatabase::set_snapshot_batches`, while a large batch
+    /// runs `search_similar_snapshot` reads a copy of the table taken before
+    /// it instead of waiting, at the cost of copying the table first.
+    pub fn insert_batch_direct<V: IntoVector>(
         &mut self,
         table_name: &str,
-        vectors: Vec<Vec<f32>>,
+        vectors: Vec<V>,
         metadata: Vec<Vec<(&str, Value)>>,
     ) -> Result<Vec<u64>> {
+        self.db.check_writable()?;
         if vectors.is_empty() {
             return Ok(Vec::new());
         }
 
-        let mut guard = self.db.inner.write().unwrap();
+        let ingesting = if self.db.snapshot_batches() && vectors.len() >= SNAPSHOT_MIN_BATCH { Some(self.db.begin_ingest(table_name)?) } else { None };
+        let mut guard = self.db.lock_write()?;
 
-        let table = guard.tables.get_mut(table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
-
-        let batch_size = vectors.len();
-        let mut rows: Vec<Vec<Value>> = Vec::with_capacity(batch_size);
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        // Rows without metadata get NULLs
+        let mut metadata = metadata.into_iter();
+        let rows: Vec<Vec<Value>> = vectors.into_iter()
+            .map(|vector| table.direct_row_values(vector.into_vector(), metadata.next().unwrap_or_default()))
+            .collect::<Result<_>>()?;
+        for row_values in &rows {
+            engine::check_references(&guard.tables, table, row_values)?;
+        }
 
-        for (i, vector) in vectors.into_iter().enumerate() {
-            let mut row_values: Vec<Value> = table.schema.columns.iter()
-                .map(|_| Value::Null)
-                .collect();
+        let ids = guard.tables.get_mut(table_name).unwrap().insert_batch(rows);
+        // Cleared before readers can see the new rows
+        drop(ingesting);
+        ids
+    }
 
-            // Set vector column
-            for (j, col) in table.schema.columns.iter().enumerate() {
-                if matches!(col.data_type, ColumnType::Vector(_)) {
-                    row_values[j] = Value::Vector(vector.clone());
-                }
-            }
+    /// Update a row only if it is still at `expected_version` (compare-and-swap).
+    ///
+    /// Like the other direct APIs this applies immediately, even inside a
+    /// transaction. Returns the new version or `MarsError::VersionConflict`.
+    pub fn update_if_version(
+        &mut self,
+        table_name: &str,
+        id: u64,
+        assignments: Vec<(&str, Value)>,
+        expected_version: u64,
+    ) -> Result<u64> {
+        self.db.check_writable()?;
+        let mut guard = self.db.lock_write()?;
 
-            // Set metadata if provided
-            if let Some(meta) = metadata.get(i) {
-                for (col_name, value) in meta {
-                    if let Some(idx) = table.schema.columns.iter().position(|c| &c.name == *col_name) {
-                        row_values[idx] = value.clone();
-                    }
-                }
-            }
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-            rows.push(row_values);
+        let assignments: Vec<(String, Value)> = assignments.into_iter()
+            .map(|(col, value)| (col.to_string(), value))
+            .collect();
+        if table.row_version(id) == Some(expected_version) {
+            engine::check_update_references(&guard.tables, table, &[(id, assignments.clone())])?;
         }
+        guard.tables.get_mut(table_name).unwrap().update_if_version(id, &assignments, expected_version)
+    }
+
+    /// Near-duplicate rows of a table, without changing it; see
+    /// `Database::find_duplicates`.
+    pub fn find_duplicates(&self, table_name: &str, threshold: f32) -> Result<Vec<Duplicate>> {
+        let guard = self.db.read();
+
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        table.near_duplicates(threshold)
+    }
+
+    /// Delete near-duplicate rows, keeping the lowest-id row of each group.
+    ///
+    /// Like the other direct APIs this applies immediately, even inside a
+    /// transaction. Returns the deleted rows.
+    pub fn dedupe(&mut self, table_name: &str, threshold: f32) -> Result<Vec<Duplicate>> {
+        self.db.check_writable()?;
+        let mut guard = self.db.lock_write()?;
 
-        table.insert_batch(rows)
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        let duplicates = table.near_duplicates(threshold)?;
+        engine::delete_ids(&mut guard.tables, table_name, duplicates.iter().map(|d| d.duplicate).collect())?;
+        Ok(duplicates)
+    }
+
+    /// Get the current version of a row, or None if it does not exist.
+    pub fn row_version(&self, table_name: &str, id: u64) -> Result<Option<u64>> {
+        let guard = self.db.read();
+
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        Ok(table.row_version(id))
     }
 
     /// Direct similarity search without SQL parsing.
@@ -706,30 +1497,271 @@ impl<'a> Connection<'a> {
         k: usize,
         ef_search: usize,
     ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
-        let guard = self.db.inner.read().unwrap();
+        self.search_similar_with(table_name, query, k, ef_search, &SearchOptions::default())
+    }
+
+    /// `search_similar` with options; `Consistency::Snapshot` reads the
+    /// last sealed snapshot, so it never waits on an in-flight writer
+    pub fn search_similar_with(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+        ef_search: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let search = |tables: &HashMap<String, Table>| {
+            let table = tables.get(table_name)
+                .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+            Ok(table.select_by_similarity(query, k, ef_search).into_iter()
+                .map(|(row, dist)| (row.id, self.visible(table, row.values), dist))
+                .collect())
+        };
+        let snapshot = match options.consistency {
+            Consistency::Latest => None,
+            Consistency::Snapshot => lock_plain(&self.db.snapshot).clone(),
+        };
+        match snapshot {
+            Some(tables) => search(&tables),
+            None => search(&self.db.read().tables),
+        }
+    }
+
+    /// Similarity search that never waits for a large `insert_batch_direct`
+    /// into the table when `ConcurrentDatabase::set_snapshot_batches` is on:
+    /// while one holds the lock, it reads the table as it was before that
+    /// batch and reports the results as not `fresh`.
+    pub fn search_similar_snapshot(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+        ef_search: usize,
+    ) -> Result<SnapshotSearch> {
+        let search = |table: &Table| -> Vec<(u64, Vec<Value>, f32)> {
+            table.select_by_similarity(query, k, ef_search).into_iter()
+                .map(|(row, dist)| (row.id, self.visible(table, row.values), dist))
+                .collect()
+        };
+        let ingest_copy = || lock_plain(&self.db.ingesting).get(table_name).map(|(copy, _)| copy.clone());
+        // A batch publishes its copy before taking the lock, so if the lock
+        // is held by one, the copy is there
+        let guard = match self.db.inner.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => match ingest_copy() {
+                Some(copy) => return Ok(SnapshotSearch { results: search(&copy), fresh: false }),
+                None => self.db.read(),
+            },
+        };
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        let results = search(table);
+        // Read while still holding the lock, so a batch can't land in between
+        let fresh = ingest_copy().is_none();
+        Ok(SnapshotSearch { results, fresh })
+    }
+
+    /// Page through similarity results with a cursor (see `Database::search_similar_after`).
+    pub fn search_similar_after(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+        cursor: Option<SearchCursor>,
+    ) -> Result<SearchPage> {
+        let guard = self.db.read();
+
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        let (results, next) = table.select_by_similarity_after(query, k, cursor);
+
+        Ok(SearchPage {
+            results: results.into_iter()
+                .map(|(row, dist)| (row.id, self.visible(table, row.values), dist))
+                .collect(),
+            next,
+        })
+    }
+
+    /// Best `k` matches per value of `group_column` (see `Database::search_similar_grouped`).
+    pub fn search_similar_grouped(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+        group_column: &str,
+    ) -> Result<Vec<SearchGroup>> {
+        let guard = self.db.read();
+
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        if self.restricted && table.mask(group_column).is_some() {
+            return Err(MarsError::AccessDenied(format!("cannot group by masked column {}", group_column)));
+        }
+        let mut groups = table.select_by_similarity_grouped(query, k, group_column)?;
+        for group in &mut groups {
+            for (_, values, _) in &mut group.results {
+                *values = self.visible(table, std::mem::take(values));
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Exact brute-force similarity search (ground truth, no graph).
+    pub fn search_exact(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let guard = self.db.read();
+
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        let results = table.select_by_similarity_exact(query, k);
+
+        Ok(results.into_iter()
+            .map(|(row, dist)| (row.id, self.visible(table, row.values), dist))
+            .collect())
+    }
+
+    /// Direct MaxSim search over a multi-vector column.
+    pub fn search_max_sim(
+        &self,
+        table_name: &str,
+        column: &str,
+        query: &[Vec<f32>],
+        k: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let guard = self.db.read();
+
+        let table = guard.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        let results = table.select_by_max_sim(column, query, k)?;
+
+        Ok(results.into_iter()
+            .map(|(row, dist)| (row.id, self.visible(table, row.values), dist))
+            .collect())
+    }
+
+    /// Direct sparse vector search over a SPARSE_VECTOR column.
+    pub fn search_sparse(
+        &self,
+        table_name: &str,
+        column: &str,
+        query: &[(u32, f32)],
+        k: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let guard = self.db.read();
 
         let table = guard.tables.get(table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-        let results = table.select_by_similarity(query, k, ef_search);
+        let results = table.select_by_sparse_similarity(column, query, k)?;
 
         Ok(results.into_iter()
-            .map(|(row, dist)| (row.id, row.values, dist))
+            .map(|(row, dist)| (row.id, self.visible(table, row.values), dist))
             .collect())
     }
 
     /// Get table names.
     pub fn table_names(&self) -> Vec<String> {
-        let guard = self.db.inner.read().unwrap();
+        let guard = self.db.read();
         guard.tables.keys().cloned().collect()
     }
 
     /// Get the underlying database reference.
-    pub fn database(&self) -> &'a ConcurrentDatabase {
-        self.db
+    pub fn database(&self) -> &ConcurrentDatabase {
+        &self.db
+    }
+}
+
+/// Apply per-column masks to result rows
+fn mask_rows(rows: &mut [Row], masks: &[Option<Mask>]) {
+    for row in rows {
+        for (value, mask) in row.values.iter_mut().zip(masks) {
+            if let Some(mask) = mask {
+                *value = mask.apply(value);
+            }
+        }
     }
 }
 
+/// Refuse statements through which a restricted connection could learn
+/// masked values other than from the masked results: filtering, sorting,
+/// grouping, joining or computing on a masked column, or changing masks.
+fn check_masked_access(inner: &DatabaseInner, command: &Command) -> Result<()> {
+    let (table, columns, where_clause, order_by, diversity, group_by, having) = match command {
+        Command::Select { table, columns, where_clause, order_by, diversity, group_by, having, .. } => {
+            (table, columns.as_slice(), where_clause, order_by, diversity, group_by, having)
+        }
+        Command::Update { table, where_clause, .. } | Command::Delete { table, where_clause, .. } => {
+            (table, &[][..], where_clause, &None, &None, &None, &None)
+        }
+        Command::AlterTable { action: AlterAction::SetMask { .. }, .. } => {
+            return Err(MarsError::AccessDenied("restricted connections cannot change masks".into()));
+        }
+        Command::CreateMaterializedView { select, .. } => return check_masked_access(inner, select),
+        Command::InsertSelect { source_database: None, source, .. }
+            if inner.tables.get(source).is_some_and(|t| !t.masks().is_empty()) =>
+        {
+            return Err(MarsError::AccessDenied(format!("cannot copy masked columns of {}", source)));
+        }
+        Command::Join { left_table, right_table, left_column, right_column, .. } => {
+            let joins_masked = [(left_table, left_column), (right_table, right_column)].into_iter()
+                .find(|(table, column)| inner.tables.get(*table).is_some_and(|t| t.mask(column).is_some()));
+            if let Some((_, column)) = joins_masked {
+                return Err(MarsError::AccessDenied(format!("cannot join on masked column {}", column)));
+            }
+            return Ok(());
+        }
+        _ => return Ok(()),
+    };
+    let Some(table) = inner.tables.get(table) else {
+        return Ok(());
+    };
+    let masked = |column: &str| table.mask(column).is_some();
+    let denied = |what: &str, column: &str| {
+        Err(MarsError::AccessDenied(format!("cannot {} masked column {}", what, column)))
+    };
+
+    for column in columns {
+        match column {
+            SelectColumn::Aggregate { func, column, .. } if *func != AggregateFunc::Count && masked(column) => {
+                return denied("aggregate", column);
+            }
+            SelectColumn::Function { expr, .. } if expr.any_column(&masked) => {
+                return denied("compute on", &expr.to_string());
+            }
+            _ => {}
+        }
+    }
+    for condition in where_clause.iter().chain(having).flat_map(|wc| &wc.conditions) {
+        let filters_masked = match &condition.expr {
+            Some(expr) => expr.any_column(&masked),
+            None => masked(&condition.column),
+        };
+        if filters_masked {
+            return denied("filter on", &condition.column);
+        }
+    }
+    if let Some(order_by) = order_by.as_ref().filter(|ob| masked(&ob.column)) {
+        return denied("sort by", &order_by.column);
+    }
+    if let Some(column) = group_by.iter().flatten().find(|c| masked(c)) {
+        return denied("group by", column);
+    }
+    if let Some((column, _)) = diversity.as_ref().and_then(|d| d.max_per.as_ref()).filter(|(c, _)| masked(c)) {
+        return denied("group by", column);
+    }
+    Ok(())
+}
+
 /// A scoped transaction that automatically rolls back if not committed.
 ///
 /// This provides RAII-style transaction management.
@@ -763,43 +1795,217 @@ impl<'a> Drop for ScopedTransaction<'a> {
     }
 }
 
+/// Configuration for a `DatabasePool`.
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// Maximum number of connections checked out at once, at least 1
+    pub max_connections: usize,
+    /// How long `acquire()` waits for a free slot before giving up
+    pub acquire_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 16,
+            acquire_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Point-in-time pool usage metrics.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolStats {
+    pub max_connections: usize,
+    /// Connections currently checked out
+    pub in_use: usize,
+    /// Free slots that can be acquired without waiting
+    pub idle: usize,
+    /// Threads currently blocked in `acquire()`
+    pub waiting: usize,
+    /// Total successful acquisitions
+    pub total_acquired: u64,
+    /// Total acquisitions that gave up after `acquire_timeout`
+    pub total_timeouts: u64,
+}
+
+/// Slot accounting shared by all clones of a pool.
+struct PoolState {
+    in_use: usize,
+    /// Tickets of waiting threads, served strictly in arrival order
+    queue: VecDeque<u64>,
+    next_ticket: u64,
+    total_acquired: u64,
+    total_timeouts: u64,
+}
+
+struct PoolShared {
+    config: PoolConfig,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+impl PoolShared {
+    fn release(&self) {
+        let mut state = lock_plain(&self.state);
+        state.in_use -= 1;
+        drop(state);
+        self.available.notify_all();
+    }
+}
+
 /// A thread-safe database pool for managing multiple connections.
 ///
 /// This is useful when you need to share database access across
-/// many threads without passing references.
+/// many threads without passing references. `acquire()` caps the number
+/// of connections checked out at once and hands out free slots in FIFO
+/// order, so a burst of writers queues up in the pool instead of piling
+/// onto the database lock.
 #[derive(Clone)]
 pub struct DatabasePool {
     db: Arc<ConcurrentDatabase>,
+    shared: Arc<PoolShared>,
 }
 
 impl DatabasePool {
     /// Create a new pool from a concurrent database.
     pub fn new(db: ConcurrentDatabase) -> Self {
+        Self::build(db, PoolConfig::default())
+    }
+
+    /// Create a new pool with explicit limits. Fails if `max_connections`
+    /// is 0, since no connection could ever be acquired.
+    pub fn with_pool_config(db: ConcurrentDatabase, config: PoolConfig) -> Result<Self> {
+        if config.max_connections == 0 {
+            return Err(MarsError::InvalidConfig("max_connections must be at least 1".into()));
+        }
+        Ok(Self::build(db, config))
+    }
+
+    fn build(db: ConcurrentDatabase, config: PoolConfig) -> Self {
         DatabasePool {
             db: Arc::new(db),
+            shared: Arc::new(PoolShared {
+                config,
+                state: Mutex::new(PoolState {
+                    in_use: 0,
+                    queue: VecDeque::new(),
+                    next_ticket: 0,
+                    total_acquired: 0,
+                    total_timeouts: 0,
+                }),
+                available: Condvar::new(),
+            }),
         }
     }
 
     /// Create an in-memory database pool.
     pub fn in_memory() -> Self {
-        DatabasePool {
-            db: Arc::new(ConcurrentDatabase::in_memory()),
-        }
+        Self::new(ConcurrentDatabase::in_memory())
     }
 
     /// Open a database file and create a pool.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let db = ConcurrentDatabase::open(path)?;
-        Ok(DatabasePool {
-            db: Arc::new(db),
-        })
+        Ok(Self::new(db))
     }
 
-    /// Get a connection from the pool.
+    /// Get a connection from the pool without counting against the limit.
+    ///
+    /// Prefer `acquire()` in servers so the connection cap is enforced.
     pub fn connect(&self) -> Connection<'_> {
         self.db.connect()
     }
 
+    /// Acquire a connection, waiting up to `acquire_timeout` for a free slot.
+    ///
+    /// Waiters are served in arrival order. The slot is returned to the
+    /// pool when the `PooledConnection` is dropped.
+    pub fn acquire(&self) -> Result<PooledConnection<'_>> {
+        self.wait_for_slot()?;
+        Ok(PooledConnection {
+            conn: self.db.connect(),
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    /// `acquire` returning a `'static` connection that owns a share of the
+    /// database and pool, to move into spawned threads or async tasks.
+    pub fn acquire_owned(&self) -> Result<PooledConnection<'static>> {
+        self.wait_for_slot()?;
+        Ok(PooledConnection {
+            conn: self.db.connect_owned(),
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    /// Take a pool slot, waiting up to `acquire_timeout`
+    fn wait_for_slot(&self) -> Result<()> {
+        let shared = &self.shared;
+        let deadline = Instant::now() + shared.config.acquire_timeout;
+
+        let mut state = lock_plain(&shared.state);
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.queue.push_back(ticket);
+
+        loop {
+            if state.queue.front() == Some(&ticket) && state.in_use < shared.config.max_connections {
+                state.queue.pop_front();
+                state.in_use += 1;
+                state.total_acquired += 1;
+                drop(state);
+                // Let the next waiter check whether another slot is free
+                shared.available.notify_all();
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                state.queue.retain(|&t| t != ticket);
+                state.total_timeouts += 1;
+                drop(state);
+                // Our ticket may have been blocking the head of the queue
+                shared.available.notify_all();
+                return Err(MarsError::Timeout(format!(
+                    "no pooled connection available after {:?}",
+                    shared.config.acquire_timeout
+                )));
+            }
+
+            state = shared.available.wait_timeout(state, deadline - now).unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+
+    /// Acquire a connection only if a slot is free right now.
+    pub fn try_acquire(&self) -> Option<PooledConnection<'_>> {
+        let shared = &self.shared;
+        let mut state = lock_plain(&shared.state);
+        if !state.queue.is_empty() || state.in_use >= shared.config.max_connections {
+            return None;
+        }
+        state.in_use += 1;
+        state.total_acquired += 1;
+        Some(PooledConnection {
+            conn: self.db.connect(),
+            shared: Arc::clone(shared),
+        })
+    }
+
+    /// Current pool usage.
+    pub fn stats(&self) -> PoolStats {
+        let state = lock_plain(&self.shared.state);
+        let max_connections = self.shared.config.max_connections;
+        PoolStats {
+            max_connections,
+            in_use: state.in_use,
+            idle: max_connections.saturating_sub(state.in_use),
+            waiting: state.queue.len(),
+            total_acquired: state.total_acquired,
+            total_timeouts: state.total_timeouts,
+        }
+    }
+
     /// Get a reference to the underlying database.
     pub fn database(&self) -> &ConcurrentDatabase {
         &self.db
@@ -811,6 +2017,34 @@ impl DatabasePool {
     }
 }
 
+/// A connection checked out of a `DatabasePool`.
+///
+/// Dereferences to `Connection`; the pool slot is released on drop.
+pub struct PooledConnection<'a> {
+    conn: Connection<'a>,
+    shared: Arc<PoolShared>,
+}
+
+impl<'a> Deref for PooledConnection<'a> {
+    type Target = Connection<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        self.shared.release();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -846,7 +2080,7 @@ mod tests {
 
         // Verify all inserts
         let result = conn.execute("SELECT * FROM docs;").unwrap();
-        if let ExecuteResult::Select { rows } = result {
+        if let ExecuteResult::Select { rows, .. } = result {
             assert_eq!(rows.len(), 4);
         } else {
             panic!("Expected Select result");
@@ -873,7 +2107,7 @@ mod tests {
         let reader = thread::spawn(move || {
             let mut conn = db_reader.connect();
             let result = conn.execute("SELECT * FROM docs;").unwrap();
-            if let ExecuteResult::Select { rows } = result {
+            if let ExecuteResult::Select { rows, .. } = result {
                 rows.len()
             } else {
                 0
@@ -901,14 +2135,18 @@ mod tests {
         conn.execute("CREATE TABLE docs (embedding VECTOR(3), value INTEGER);").unwrap();
 
         conn.begin().unwrap();
-        conn.execute("INSERT INTO docs (embedding, value) VALUES ([0.1, 0.2, 0.3], 1);").unwrap();
-        conn.execute("INSERT INTO docs (embedding, value) VALUES ([0.4, 0.5, 0.6], 2);").unwrap();
+        let queued = conn.execute("INSERT INTO docs (embedding, value) VALUES ([0.1, 0.2, 0.3], 1);").unwrap();
+        assert!(matches!(queued, ExecuteResult::Queued { index: 0 }));
+        let queued = conn.execute("INSERT INTO docs (embedding, value) VALUES ([0.4, 0.5, 0.6], 2);").unwrap();
+        assert!(matches!(queued, ExecuteResult::Queued { index: 1 }));
         let results = conn.commit().unwrap();
 
         assert_eq!(results.len(), 2);
+        assert!(matches!(results[0], ExecuteResult::Insert { id: 1 }));
+        assert!(matches!(results[1], ExecuteResult::Insert { id: 2 }));
 
         let result = conn.execute("SELECT * FROM docs;").unwrap();
-        if let ExecuteResult::Select { rows } = result {
+        if let ExecuteResult::Select { rows, .. } = result {
             assert_eq!(rows.len(), 2);
         } else {
             panic!("Expected Select result");
@@ -928,7 +2166,7 @@ mod tests {
         conn.rollback().unwrap();
 
         let result = conn.execute("SELECT * FROM docs;").unwrap();
-        if let ExecuteResult::Select { rows } = result {
+        if let ExecuteResult::Select { rows, .. } = result {
             assert_eq!(rows.len(), 1); // Only the original row
         } else {
             panic!("Expected Select result");
@@ -953,13 +2191,237 @@ mod tests {
 
         let mut conn = pool.connect();
         let result = conn.execute("SELECT * FROM docs;").unwrap();
-        if let ExecuteResult::Select { rows } = result {
+        if let ExecuteResult::Select { rows, .. } = result {
             assert_eq!(rows.len(), 1);
         } else {
             panic!("Expected Select result");
         }
     }
 
+    #[test]
+    fn test_repair_worker() {
+        let db = Arc::new(ConcurrentDatabase::in_memory());
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE docs (id INTEGER, embedding VECTOR(2), title TEXT);").unwrap();
+        for i in 0..20 {
+            conn.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, 0.0], 'Test');", i)).unwrap();
+        }
+        conn.execute("DELETE FROM docs WHERE id < 10;").unwrap();
+        assert!(db.read().tables["docs"].graph.needs_repair());
+
+        let worker = ConcurrentDatabase::spawn_repair_worker(Arc::clone(&db), Duration::from_millis(5));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while db.read().tables["docs"].graph.needs_repair() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        worker.stop();
+
+        assert!(!db.read().tables["docs"].graph.needs_repair());
+        let results = conn.search_similar("docs", &[15.0, 0.0], 1, 64).unwrap();
+        assert_eq!(results[0].0, 16);
+    }
+
+    #[test]
+    fn test_pool_limits() {
+        let pool = DatabasePool::with_pool_config(
+            ConcurrentDatabase::in_memory(),
+            PoolConfig { max_connections: 2, acquire_timeout: Duration::from_millis(50) },
+        ).unwrap();
+
+        let mut first = pool.acquire().unwrap();
+        first.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+        let _second = pool.acquire().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.in_use, 2);
+        assert_eq!(stats.idle, 0);
+
+        // Pool is full
+        assert!(pool.try_acquire().is_none());
+        assert!(matches!(pool.acquire(), Err(MarsError::Timeout(_))));
+        assert_eq!(pool.stats().total_timeouts, 1);
+
+        // Releasing a slot wakes a waiting thread
+        let waiter_pool = pool.clone();
+        let waiter = thread::spawn(move || {
+            let mut conn = waiter_pool.acquire().unwrap();
+            conn.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'Test');").unwrap();
+        });
+        thread::sleep(Duration::from_millis(10));
+        drop(first);
+        waiter.join().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.in_use, 1);
+        assert_eq!(stats.waiting, 0);
+        assert_eq!(stats.total_acquired, 3);
+
+        let config = PoolConfig { max_connections: 0, ..PoolConfig::default() };
+        assert!(matches!(DatabasePool::with_pool_config(ConcurrentDatabase::in_memory(), config), Err(MarsError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_owned_connections() {
+        let pool = DatabasePool::with_pool_config(
+            ConcurrentDatabase::in_memory(),
+            PoolConfig { max_connections: 1, acquire_timeout: Duration::from_millis(50) },
+        ).unwrap();
+        let mut conn = pool.acquire_owned().unwrap();
+        conn.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+
+        // Owned connections outlive the borrow they were made from
+        let writer = thread::spawn(move || {
+            conn.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+        });
+        writer.join().unwrap();
+        assert_eq!(pool.stats().in_use, 0);
+
+        let db = Arc::clone(&pool.db);
+        let mut conn = db.connect_owned();
+        drop(db);
+        let result = thread::spawn(move || conn.execute("SELECT * FROM docs;").unwrap()).join().unwrap();
+        assert!(matches!(result, ExecuteResult::Select { rows, .. } if rows.len() == 1));
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let db = Arc::new(ConcurrentDatabase::in_memory());
+        db.connect().execute("CREATE TABLE docs (embedding VECTOR(2), code INTEGER UNIQUE);").unwrap();
+        db.set_group_commit(Some(Duration::from_millis(2)));
+        assert_eq!(db.group_commit(), Some(Duration::from_millis(2)));
+
+        let writers: Vec<_> = (0..8).map(|t| {
+            let mut conn = db.connect_owned();
+            thread::spawn(move || {
+                (0..25).map(|i| {
+                    let n = t * 25 + i;
+                    conn.insert_direct("docs", vec![(n % 20) as f32, (n / 20) as f32], vec![("code", Value::Integer(n))]).unwrap()
+                }).collect::<Vec<u64>>()
+            })
+        }).collect();
+        let mut ids: Vec<u64> = writers.into_iter().flat_map(|w| w.join().unwrap()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 200);
+
+        let mut conn = db.connect();
+        for n in 0..200 {
+            let results = conn.search_similar("docs", &[(n % 20) as f32, (n / 20) as f32], 1, 64).unwrap();
+            assert_eq!(results[0].2, 0.0);
+        }
+
+        // Each queued insert gets its own error
+        assert!(matches!(conn.insert_direct("docs", vec![0.0, 0.0], vec![("code", Value::Integer(7))]),
+            Err(MarsError::ConstraintViolation(_))));
+        assert!(matches!(conn.insert_direct("missing", vec![0.0, 0.0], vec![]), Err(MarsError::TableNotFound(_))));
+        assert!(matches!(conn.insert_direct("docs", vec![0.0], vec![]), Err(MarsError::ColumnDimensionMismatch { .. })));
+        assert_eq!(db.read().tables["docs"].len(), 200);
+    }
+
+    #[test]
+    fn test_snapshot_consistency() {
+        let db = ConcurrentDatabase::in_memory();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+        conn.insert_direct("docs", vec![1.0, 0.0], vec![("title", Value::Text("a".into()))]).unwrap();
+        let snapshot = SearchOptions { consistency: Consistency::Snapshot };
+
+        // Without a sealed snapshot, snapshot reads see the latest rows
+        assert_eq!(conn.search_similar_with("docs", &[0.0, 1.0], 10, 64, &snapshot).unwrap().len(), 1);
+        db.seal_snapshot().unwrap();
+        conn.insert_direct("docs", vec![0.0, 1.0], vec![("title", Value::Text("b".into()))]).unwrap();
+
+        {
+            // A writer holding the lock does not block snapshot reads
+            let _writer = db.write().unwrap();
+            let results = conn.search_similar_with("docs", &[0.0, 1.0], 10, 64, &snapshot).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].1[1], Value::Text("a".into()));
+        }
+        assert_eq!(conn.search_similar("docs", &[0.0, 1.0], 10, 64).unwrap().len(), 2);
+        assert!(matches!(conn.search_similar_with("missing", &[0.0, 1.0], 1, 64, &snapshot), Err(MarsError::TableNotFound(_))));
+
+        db.clear_snapshot();
+        assert_eq!(conn.search_similar_with("docs", &[0.0, 1.0], 10, 64, &snapshot).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_search_during_batch() {
+        let db = ConcurrentDatabase::in_memory();
+        db.set_snapshot_batches(true);
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+        let vectors: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, 0.0]).collect();
+        conn.insert_batch_direct("docs", vectors, Vec::new()).unwrap();
+        let batch: Vec<Vec<f32>> = (0..2000).map(|i| vec![(i % 50) as f32, (i / 50 + 1) as f32]).collect();
+
+        std::thread::scope(|s| {
+            let mut writer_conn = db.connect();
+            let writer = s.spawn(move || writer_conn.insert_batch_direct("docs", batch, Vec::new()).unwrap());
+            while !writer.is_finished() {
+                let snapshot = conn.search_similar_snapshot("docs", &[3.0, 1.0], 1, 64).unwrap();
+                // A stale snapshot predates the batch, so it can't see (3, 1)
+                if !snapshot.fresh {
+                    assert_eq!(snapshot.results[0].1[0], Value::Vector(vec![3.0, 0.0]));
+                }
+            }
+            assert_eq!(writer.join().unwrap().len(), 2000);
+        });
+
+        let snapshot = conn.search_similar_snapshot("docs", &[3.0, 1.0], 1, 64).unwrap();
+        assert!(snapshot.fresh);
+        assert_eq!(snapshot.results[0].2, 0.0);
+        assert!(db.ingesting.lock().unwrap().is_empty());
+        assert!(matches!(conn.search_similar_snapshot("missing", &[0.0, 1.0], 1, 64), Err(MarsError::TableNotFound(_))));
+
+        // A failed batch leaves no copy behind
+        let bad: Vec<Vec<f32>> = vec![vec![1.0, 2.0]; 300].into_iter().chain([vec![1.0]]).collect();
+        assert!(conn.insert_batch_direct("docs", bad, Vec::new()).is_err());
+        assert!(db.ingesting.lock().unwrap().is_empty());
+        assert_eq!(db.read().tables["docs"].len(), 2010);
+
+        // Without set_snapshot_batches, batches don't copy the table
+        db.set_snapshot_batches(false);
+        let batch: Vec<Vec<f32>> = (0..2000).map(|i| vec![i as f32, 2.0]).collect();
+        std::thread::scope(|s| {
+            let mut writer_conn = db.connect();
+            let writer = s.spawn(move || writer_conn.insert_batch_direct("docs", batch, Vec::new()).unwrap());
+            while !writer.is_finished() {
+                assert!(db.ingesting.lock().unwrap().is_empty());
+            }
+            writer.join().unwrap();
+        });
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_execute() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        fn block_on<F: Future + Send>(future: F) -> F::Output {
+            let mut future = std::pin::pin!(future);
+            let mut cx = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+                thread::yield_now();
+            }
+        }
+
+        let db = Arc::new(ConcurrentDatabase::in_memory());
+        let mut conn = db.connect_owned();
+        block_on(async {
+            conn.execute_async("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").await.unwrap();
+            conn.begin().unwrap();
+            conn.execute_async("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").await.unwrap();
+            assert_eq!(conn.commit_async().await.unwrap().len(), 1);
+            let result = conn.execute_async("SELECT * FROM docs;").await.unwrap();
+            assert!(matches!(result, ExecuteResult::Select { rows, .. } if rows.len() == 1));
+        });
+    }
+
     #[test]
     fn test_direct_api_concurrent() {
         let db = ConcurrentDatabase::in_memory();
@@ -997,7 +2459,7 @@ mod tests {
             let db = ConcurrentDatabase::open(&temp_path).unwrap();
             let mut conn = db.connect();
             let result = conn.execute("SELECT * FROM docs;").unwrap();
-            if let ExecuteResult::Select { rows } = result {
+            if let ExecuteResult::Select { rows, .. } = result {
                 assert_eq!(rows.len(), 1);
             } else {
                 panic!("Expected Select result");
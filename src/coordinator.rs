@@ -0,0 +1,175 @@
+//! Scatter-gather similarity search over several databases.
+//!
+//! A `SearchCoordinator` sends a query to every shard at once, each on its
+//! own thread, and merges the top-k lists by distance. Shards are anything
+//! implementing `SearchShard`: a `ConcurrentDatabase` in this process, or a
+//! client for a database on another machine. Shards that fail or miss the
+//! deadline are reported next to the results instead of failing the query.
+
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::concurrent::ConcurrentDatabase;
+use crate::error::{MarsError, Result};
+use crate::schema::Value;
+use crate::sharded::ShardedResult;
+
+/// A database a `SearchCoordinator` can query
+pub trait SearchShard: Send + Sync {
+    /// Nearest `k` rows of a table as (row id, values, distance), closest
+    /// first, like `Database::search_similar`
+    fn search_similar(&self, table_name: &str, query: &[f32], k: usize, ef_search: usize) -> Result<Vec<(u64, Vec<Value>, f32)>>;
+}
+
+impl SearchShard for ConcurrentDatabase {
+    fn search_similar(&self, table_name: &str, query: &[f32], k: usize, ef_search: usize) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        self.connect().search_similar(table_name, query, k, ef_search)
+    }
+}
+
+/// Merged results of a scatter-gather search
+#[derive(Debug)]
+pub struct GatheredResults {
+    /// (shard, row id, values, distance), closest first
+    pub results: Vec<ShardedResult>,
+    /// Shards left out of `results`, with why: their error, or
+    /// `MarsError::Timeout` if they did not answer in time
+    pub failed: Vec<(usize, MarsError)>,
+}
+
+/// Queries several shards concurrently and merges their top-k results
+pub struct SearchCoordinator {
+    shards: Vec<Arc<dyn SearchShard>>,
+    timeout: Option<Duration>,
+}
+
+impl SearchCoordinator {
+    pub fn new(shards: Vec<Arc<dyn SearchShard>>) -> Self {
+        SearchCoordinator { shards, timeout: None }
+    }
+
+    /// Stop waiting for shards this long after a search starts. Late
+    /// shards are listed in `GatheredResults::failed`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Get the number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Nearest `k` rows over all shards. Fails only if no shard answered;
+    /// otherwise the shards that failed or timed out are in `failed`.
+    pub fn search_similar(&self, table_name: &str, query: &[f32], k: usize, ef_search: usize) -> Result<GatheredResults> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let (sender, receiver) = mpsc::channel();
+        for (index, shard) in self.shards.iter().enumerate() {
+            let shard = Arc::clone(shard);
+            let sender = sender.clone();
+            let table_name = table_name.to_string();
+            let query = query.to_vec();
+            // Detached, so a hung shard cannot hold up the search; its
+            // answer is dropped once the receiver is gone
+            std::thread::spawn(move || {
+                let _ = sender.send((index, shard.search_similar(&table_name, &query, k, ef_search)));
+            });
+        }
+        drop(sender);
+
+        let mut answers: Vec<Option<Result<_>>> = (0..self.shards.len()).map(|_| None).collect();
+        for _ in 0..self.shards.len() {
+            let received = match deadline {
+                Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())).ok(),
+                None => receiver.recv().ok(),
+            };
+            let Some((index, answer)) = received else { break };
+            answers[index] = Some(answer);
+        }
+
+        let mut gathered = GatheredResults { results: Vec::new(), failed: Vec::new() };
+        for (shard, answer) in answers.into_iter().enumerate() {
+            match answer {
+                Some(Ok(results)) => gathered.results.extend(results.into_iter()
+                    .map(|(id, values, distance)| (shard, id, values, distance))),
+                Some(Err(e)) => gathered.failed.push((shard, e)),
+                None => gathered.failed.push((shard, MarsError::Timeout(format!(
+                    "shard {} did not answer within {:?}", shard, self.timeout.unwrap_or_default(),
+                )))),
+            }
+        }
+        if !self.shards.is_empty() && gathered.failed.len() == self.shards.len() {
+            return Err(gathered.failed.swap_remove(0).1);
+        }
+
+        gathered.results.sort_by(|a, b| a.3.total_cmp(&b.3).then(a.0.cmp(&b.0)).then(a.1.cmp(&b.1)));
+        gathered.results.truncate(k);
+        Ok(gathered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A shard that answers after a delay, or fails
+    struct Remote {
+        delay: Duration,
+        results: Result<Vec<(u64, Vec<Value>, f32)>>,
+    }
+
+    impl SearchShard for Remote {
+        fn search_similar(&self, _: &str, _: &[f32], k: usize, _: usize) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+            std::thread::sleep(self.delay);
+            match &self.results {
+                Ok(results) => Ok(results.iter().take(k).cloned().collect()),
+                Err(e) => Err(MarsError::InvalidFormat(e.to_string())),
+            }
+        }
+    }
+
+    fn shard(db: ConcurrentDatabase, vectors: &[[f32; 2]]) -> Arc<dyn SearchShard> {
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE docs (embedding VECTOR(2));").unwrap();
+        for v in vectors {
+            conn.execute(&format!("INSERT INTO docs (embedding) VALUES ([{}, {}]);", v[0], v[1])).unwrap();
+        }
+        drop(conn);
+        Arc::new(db)
+    }
+
+    #[test]
+    fn test_merges_top_k_across_shards() {
+        let coordinator = SearchCoordinator::new(vec![
+            shard(ConcurrentDatabase::in_memory(), &[[0.0, 0.0], [5.0, 5.0]]),
+            shard(ConcurrentDatabase::in_memory(), &[[1.0, 0.0], [9.0, 9.0]]),
+        ]);
+        let gathered = coordinator.search_similar("docs", &[0.9, 0.0], 3, 50).unwrap();
+        assert!(gathered.failed.is_empty());
+        let found: Vec<(usize, u64)> = gathered.results.iter().map(|r| (r.0, r.1)).collect();
+        assert_eq!(found, [(1, 1), (0, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn test_reports_failed_and_late_shards() {
+        let coordinator = SearchCoordinator::new(vec![
+            Arc::new(Remote { delay: Duration::ZERO, results: Ok(vec![(7, Vec::new(), 0.5)]) }),
+            Arc::new(Remote { delay: Duration::ZERO, results: Err(MarsError::InvalidFormat("down".into())) }),
+            Arc::new(Remote { delay: Duration::from_secs(5), results: Ok(vec![(1, Vec::new(), 0.0)]) }),
+        ]).with_timeout(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let gathered = coordinator.search_similar("docs", &[0.0], 10, 50).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(gathered.results.len(), 1);
+        assert_eq!(gathered.results[0].1, 7);
+        assert!(matches!(gathered.failed[..], [(1, MarsError::InvalidFormat(_)), (2, MarsError::Timeout(_))]));
+
+        let down = SearchCoordinator::new(vec![
+            Arc::new(Remote { delay: Duration::ZERO, results: Err(MarsError::InvalidFormat("down".into())) }),
+        ]);
+        assert!(down.search_similar("docs", &[0.0], 10, 50).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Encryption at rest for database files.
+//!
+//! An encrypted file is `MAGIC | salt | nonce | ciphertext`: the plain file
+//! format sealed with AES-256-GCM under a key derived from a passphrase
+//! with Argon2id. Every save uses a fresh nonce; the salt changes only when
+//! the passphrase does.
+
+/// First bytes of an encrypted database file. Never a valid plain header,
+/// whose first four bytes are a small format version.
+pub(crate) const MAGIC: &[u8; 8] = b"PARDUSE\x01";
+
+/// Check whether file contents start like an encrypted database
+pub(crate) fn is_encrypted(header: &[u8]) -> bool {
+    header.starts_with(MAGIC)
+}
+
+#[cfg(feature = "encryption")]
+pub(crate) use sealed::FileKey;
+
+#[cfg(feature = "encryption")]
+mod sealed {
+    use aes_gcm::aead::rand_core::RngCore;
+    use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+    use aes_gcm::{Aes256Gcm, Key, Nonce};
+    use argon2::Argon2;
+
+    use super::{is_encrypted, MAGIC};
+    use crate::error::{MarsError, Result};
+
+    const SALT_LEN: usize = 16;
+    const NONCE_LEN: usize = 12;
+    const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+    /// A cipher keyed from a passphrase, with the salt the key was derived with
+    pub(crate) struct FileKey {
+        cipher: Aes256Gcm,
+        salt: [u8; SALT_LEN],
+    }
+
+    impl FileKey {
+        /// Derive a key from `passphrase` with a fresh random salt
+        pub(crate) fn new(passphrase: &str) -> Result<Self> {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            Self::derive(passphrase, salt)
+        }
+
+        fn derive(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<Self> {
+            let mut key = [0u8; 32];
+            Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+                .map_err(|e| MarsError::Encryption(format!("key derivation failed: {}", e)))?;
+            Ok(FileKey { cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)), salt })
+        }
+
+        /// Seal the plain file format into encrypted file contents
+        pub(crate) fn encrypt(&self, plain: &[u8]) -> Result<Vec<u8>> {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = self.cipher.encrypt(&nonce, plain)
+                .map_err(|_| MarsError::Encryption("encryption failed".into()))?;
+
+            let mut file = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+            file.extend_from_slice(MAGIC);
+            file.extend_from_slice(&self.salt);
+            file.extend_from_slice(&nonce);
+            file.extend_from_slice(&ciphertext);
+            Ok(file)
+        }
+
+        /// Derive the key of an encrypted file and decrypt it. Returns the
+        /// key, for saving the file again, and the plain file format.
+        pub(crate) fn open(passphrase: &str, file: &[u8]) -> Result<(Self, Vec<u8>)> {
+            if !is_encrypted(file) || file.len() < HEADER_LEN {
+                return Err(MarsError::Encryption("not an encrypted database file".into()));
+            }
+            let salt = file[MAGIC.len()..MAGIC.len() + SALT_LEN].try_into().unwrap();
+            let nonce = Nonce::from_slice(&file[MAGIC.len() + SALT_LEN..HEADER_LEN]);
+
+            let key = Self::derive(passphrase, salt)?;
+            let plain = key.cipher.decrypt(nonce, &file[HEADER_LEN..])
+                .map_err(|_| MarsError::Encryption("wrong passphrase or corrupted file".into()))?;
+            Ok((key, plain))
+        }
+
+        /// Decrypt a file sealed under this key, as when reloading a file
+        /// another process saved. Fails if it was re-keyed since.
+        pub(crate) fn decrypt(&self, file: &[u8]) -> Result<Vec<u8>> {
+            if !is_encrypted(file) || file.len() < HEADER_LEN {
+                return Err(MarsError::Encryption("not an encrypted database file".into()));
+            }
+            if file[MAGIC.len()..MAGIC.len() + SALT_LEN] != self.salt {
+                return Err(MarsError::Encryption("file was re-keyed; open it again with the new passphrase".into()));
+            }
+            let nonce = Nonce::from_slice(&file[MAGIC.len() + SALT_LEN..HEADER_LEN]);
+            self.cipher.decrypt(nonce, &file[HEADER_LEN..])
+                .map_err(|_| MarsError::Encryption("corrupted file".into()))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_round_trip() {
+            let key = FileKey::new("correct horse").unwrap();
+            let sealed = key.encrypt(b"plain file").unwrap();
+            assert!(is_encrypted(&sealed));
+            assert_ne!(key.encrypt(b"plain file").unwrap(), sealed);
+
+            let (opened, plain) = FileKey::open("correct horse", &sealed).unwrap();
+            assert_eq!(plain, b"plain file");
+            assert_eq!(opened.decrypt(&key.encrypt(b"saved again").unwrap()).unwrap(), b"saved again");
+            let rekeyed = FileKey::new("correct horse").unwrap().encrypt(b"plain file").unwrap();
+            assert!(matches!(opened.decrypt(&rekeyed), Err(MarsError::Encryption(_))));
+        }
+
+        #[test]
+        fn test_rejects_wrong_passphrase_and_tampering() {
+            let mut sealed = FileKey::new("correct horse").unwrap().encrypt(b"plain file").unwrap();
+            assert!(matches!(FileKey::open("battery staple", &sealed), Err(MarsError::Encryption(_))));
+
+            *sealed.last_mut().unwrap() ^= 1;
+            assert!(matches!(FileKey::open("correct horse", &sealed), Err(MarsError::Encryption(_))));
+            assert!(FileKey::open("correct horse", b"plain file").is_err());
+        }
+    }
+}
@@ -1,15 +1,47 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::fs::{File, Metadata, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
+use crate::cache::{CacheKey, Cached, ResultCache};
+use crate::compression::{self, CompressedTexts, DEFAULT_COMPRESS_TEXT};
+use crate::crypto;
+use crate::external::{self, ExternalText, TextFileWriter};
+use crate::db::VectorDB;
+use crate::delta::{self, Checkpoint, Delta};
+use crate::distance::{self, Distance};
+use crate::engine::{self, InsertHooks, SelectQuery};
 use crate::error::{MarsError, Result};
-use crate::graph::GraphConfig;
-use crate::parser::{BoolConnector, Command, ComparisonOp, Condition, ConditionValue, JoinColumn, JoinType, OrderBy, SelectColumn, WhereClause, parse};
-use crate::schema::{Column, ColumnType, Row, Schema, Value};
-use crate::table::Table;
+use crate::graph::{CsrGraph, EdgeStats, Graph, GraphConfig, GraphStats, take_nodes_visited};
+use crate::node::NodeId;
+use crate::interrupt::InterruptHandle;
+use crate::lock::{self, WriterLock, DEFAULT_BUSY_TIMEOUT};
+use crate::parser::{Command, OnConflict, ParseLimits, WhereClause, parse_with_limits, split_statements};
+use crate::planner::{AccessPath, QueryPlan};
+use crate::prepared::{PreparedStatement, StatementCache, StatementCacheStats};
+use crate::projection::Projection;
+use crate::quantize::{self, CalibrationReport, QuantizedVector, Quantizer};
+use crate::sealed;
+use crate::schema::{AutoIncrement, ForeignKey, IntoVector, Mask, ResultRow, ResultSchema, Row, Schema, SchemaV1, Value};
+use crate::stats::TableStats;
+use crate::storage::Synchronous;
+use crate::table::{Duplicate, IngestRow, MemoryUsage, RecallReport, RowHistory, SearchCursor, SearchGroup, SearchPage, Segment, Table};
+use crate::view::ViewDef;
+#[cfg(feature = "watch")]
+use crate::watch::FileWatcher;
+#[cfg(feature = "object-store")]
+use crate::remote::RemoteFile;
+
+/// ef_search of SQL similarity queries unless set with `PRAGMA ef_search`
+pub(crate) const DEFAULT_EF_SEARCH: usize = 100;
+
+/// Current on-disk format version
+pub(crate) const FORMAT_VERSION: u32 = 2;
 
 /// File header with database metadata
 #[derive(Serialize, Deserialize)]
@@ -20,11 +52,340 @@ struct DbHeader {
 
 /// Serialized table data
 #[derive(Serialize, Deserialize)]
-struct TableData {
+pub(crate) struct TableData {
     pub schema: Schema,
     pub rows: Vec<Row>,
     pub centroid: Vec<f32>,
     pub next_id: u64,
+    pub tombstones: Vec<Row>,
+    pub history: Vec<(u64, RowHistory)>,
+    pub metric: String,
+    pub normalize: bool,
+    pub columnar: bool,
+    pub config: GraphConfig,
+    pub masks: Vec<(String, Mask)>,
+    pub views: Vec<ViewDef>,
+    pub stats: Option<TableStats>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub auto_increment: Option<AutoIncrement>,
+    pub compress_text: usize,
+    pub keep_history: usize,
+    /// Long TEXT values taken out of `rows` by `compress_text()`
+    pub texts: CompressedTexts,
+    pub external_columns: Vec<String>,
+    /// Generation of the text file holding `external`
+    pub text_file: u64,
+    /// Values of external columns taken out of `rows` by `write_external()`
+    pub external: Vec<ExternalText>,
+    /// Scale and offset of INT8 vector storage
+    pub quantizer: Option<Quantizer>,
+    /// Vectors taken out of `rows` by `encode_vectors()`
+    pub vectors: Vec<QuantizedVector>,
+    /// Maps new vectors to the smaller ones `rows` hold
+    pub projection: Option<Projection>,
+}
+
+/// Serialized table data for format version 1, the original layout
+#[derive(Deserialize)]
+struct TableDataV1 {
+    pub schema: SchemaV1,
+    pub rows: Vec<Row>,
+    pub centroid: Vec<f32>,
+    pub next_id: u64,
+}
+
+impl From<TableDataV1> for TableData {
+    fn from(v1: TableDataV1) -> Self {
+        TableData {
+            schema: v1.schema.into(),
+            rows: v1.rows,
+            centroid: v1.centroid,
+            next_id: v1.next_id,
+            tombstones: Vec::new(),
+            history: Vec::new(),
+            metric: "euclidean".to_string(),
+            normalize: false,
+            columnar: false,
+            config: GraphConfig::default(),
+            masks: Vec::new(),
+            views: Vec::new(),
+            stats: None,
+            foreign_keys: Vec::new(),
+            auto_increment: None,
+            compress_text: DEFAULT_COMPRESS_TEXT,
+            keep_history: 0,
+            texts: CompressedTexts::default(),
+            external_columns: Vec::new(),
+            text_file: 0,
+            external: Vec::new(),
+            quantizer: None,
+            vectors: Vec::new(),
+            projection: None,
+        }
+    }
+}
+
+impl TableData {
+    /// Deserialize a table written with format `version`
+    pub(crate) fn decode(version: u32, buf: &[u8]) -> Result<Self> {
+        match version {
+            1 => bincode::deserialize::<TableDataV1>(buf).map(TableData::from),
+            FORMAT_VERSION => bincode::deserialize(buf),
+            _ => return Err(MarsError::InvalidFormat(format!("Unsupported format version: {}", version))),
+        }
+        .map_err(|e| MarsError::InvalidFormat(format!("Failed to deserialize table: {}", e)))
+    }
+
+    pub(crate) fn from_table(table: &Table) -> Self {
+        TableData {
+            schema: table.schema.clone(),
+            rows: table.rows.values().cloned().collect(),
+            centroid: table.graph.centroid().to_vec(),
+            next_id: table.next_id,
+            tombstones: table.tombstones.values().cloned().collect(),
+            history: table.history.iter().map(|(id, h)| (*id, h.clone())).collect(),
+            metric: table.metric().to_string(),
+            normalize: table.is_normalized(),
+            columnar: table.is_columnar(),
+            config: table.graph_config().clone(),
+            masks: table.masks().iter().map(|(c, m)| (c.clone(), *m)).collect(),
+            views: table.view_defs().into_iter().cloned().collect(),
+            stats: table.stats().cloned(),
+            foreign_keys: table.foreign_keys().to_vec(),
+            auto_increment: table.auto_increment().cloned(),
+            compress_text: table.compress_text(),
+            keep_history: table.keep_history(),
+            texts: CompressedTexts::default(),
+            external_columns: table.external_columns().to_vec(),
+            text_file: 0,
+            external: Vec::new(),
+            quantizer: table.quantizer().cloned(),
+            vectors: Vec::new(),
+            projection: table.projection().cloned(),
+        }
+    }
+
+    /// Compress long TEXT values for writing to a file
+    pub(crate) fn compress_text(mut self) -> Self {
+        self.texts = compression::compress(&mut self.rows, self.compress_text);
+        self
+    }
+
+    /// Encode the vectors of INT8 storage as one byte per component for
+    /// writing to a file
+    pub(crate) fn encode_vectors(mut self) -> Self {
+        if let (Some(quantizer), Some(column)) = (&self.quantizer, self.vector_column()) {
+            self.vectors = quantize::encode(&mut self.rows, column, quantizer);
+        }
+        self
+    }
+
+    fn vector_column(&self) -> Option<usize> {
+        let name = self.schema.vector_column.as_ref()?;
+        self.schema.columns.iter().position(|c| &c.name == name)
+    }
+
+    /// Move the values of external columns to `file`
+    pub(crate) fn write_external(mut self, file: &mut TextFileWriter) -> Result<Self> {
+        let columns: Vec<usize> = self.external_columns.iter()
+            .filter_map(|name| self.schema.columns.iter().position(|c| &c.name == name))
+            .collect();
+        self.external = external::write(&mut self.rows, &columns, file)?;
+        self.text_file = file.generation();
+        Ok(self)
+    }
+
+    /// Read the values of external columns back from the text file next to
+    /// the database at `path`
+    pub(crate) fn read_external(&mut self, path: Option<&Path>) -> Result<()> {
+        external::read(&mut self.rows, std::mem::take(&mut self.external), path, self.text_file)
+    }
+
+    /// Reconstruct the table, re-indexing its rows
+    pub(crate) fn into_table(self) -> Result<Table> {
+        self.into_table_with_index(None)
+    }
+
+    /// Reconstruct the table around a stored vector index, given in CSR
+    /// layout with the row id of each node, or re-index its rows with None
+    pub(crate) fn into_table_with_index(mut self, index: Option<(CsrGraph<f32>, Vec<u64>)>) -> Result<Table> {
+        compression::decompress(&mut self.rows, std::mem::take(&mut self.texts))?;
+        if let (Some(quantizer), Some(column)) = (&self.quantizer, self.vector_column()) {
+            quantize::decode(&mut self.rows, column, quantizer, std::mem::take(&mut self.vectors))?;
+        }
+        let mut table = Table::with_metric(self.schema, self.config, &self.metric)?;
+        if let Some(projection) = self.projection {
+            table.restore_projection(projection);
+        }
+        table.set_compress_text(self.compress_text);
+        table.set_keep_history(self.keep_history);
+        table.set_quantizer(self.quantizer);
+        for column in &self.external_columns {
+            table.set_external(column)?;
+        }
+        if self.normalize {
+            table = table.normalized()?;
+        }
+        if self.columnar {
+            table = table.columnar()?;
+        }
+
+        for (column, mask) in self.masks {
+            table.set_mask(&column, Some(mask))?;
+        }
+        match index {
+            Some((csr, node_rows)) => {
+                if csr.centroid.len() != table.graph.dimension() {
+                    return Err(MarsError::InvalidFormat(format!("stored index of '{}' has the wrong dimension", table.name())));
+                }
+                table.graph = Graph::from_csr(csr, table.graph_config().clone(), table.graph.metric());
+                let nodes: HashMap<u64, NodeId> = node_rows.into_iter()
+                    .enumerate()
+                    .map(|(node, row)| (row, node as NodeId))
+                    .collect();
+                // Rows saved during a bulk load may not be indexed yet
+                for row in self.rows {
+                    match nodes.get(&row.id).copied() {
+                        Some(node) => table.restore_indexed_row(row, Some(node)),
+                        None => table.restore_row(row),
+                    }
+                }
+            }
+            None => {
+                for row in self.rows {
+                    table.restore_row(row);
+                }
+            }
+        }
+
+        if let Some(auto_increment) = self.auto_increment {
+            table.set_auto_increment(auto_increment)?;
+        }
+        table.next_id = self.next_id;
+        table.tombstones = self.tombstones.into_iter().map(|r| (r.id, r)).collect();
+        table.history = self.history.into_iter().collect();
+        for view in self.views {
+            table.add_view(view)?;
+        }
+        table.set_stats(self.stats);
+        for foreign_key in self.foreign_keys {
+            table.add_foreign_key(foreign_key)?;
+        }
+        Ok(table)
+    }
+}
+
+/// How a query found its rows
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryStats {
+    /// Rows examined, or graph nodes visited for an index search
+    pub rows_scanned: usize,
+    pub rows_returned: usize,
+    /// Index used, None for a full table scan
+    pub index: Option<String>,
+}
+
+/// Progress of a long-running operation, passed to the progress handler
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// "insert", "script" or "repair_index"
+    pub operation: &'static str,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Progress callback; returning false cancels the operation with
+/// `MarsError::Interrupted`
+pub type ProgressHandler = Box<dyn FnMut(&Progress) -> bool + Send>;
+
+pub(crate) struct ProgressState {
+    interval: usize,
+    handler: ProgressHandler,
+}
+
+/// Call the progress handler, if any, every `interval` steps
+pub(crate) fn report_progress(state: &mut Option<ProgressState>, operation: &'static str, done: usize, total: usize) -> Result<()> {
+    if let Some(p) = state
+        && done > 0
+        && done.is_multiple_of(p.interval)
+        && !(p.handler)(&Progress { operation, done, total })
+    {
+        return Err(MarsError::Interrupted);
+    }
+    Ok(())
+}
+
+/// Charge the estimated size of a new row against a `(limit, used)` budget
+pub(crate) fn reserve_memory(table: &Table, values: &[Value], (limit, used): &mut (usize, usize)) -> Result<()> {
+    let needed = table.row_memory(values).total();
+    if *used + needed > *limit {
+        return Err(MarsError::MemoryLimit(format!(
+            "inserting into '{}' needs about {} bytes with {} of {} bytes in use",
+            table.name(), needed, used, limit,
+        )));
+    }
+    *used += needed;
+    Ok(())
+}
+
+/// Copy a table with its history, reusing its vector index
+pub(crate) fn copy_table(table: &Table) -> Result<Table> {
+    let csr = table.graph.to_csr();
+    let node_rows = csr.ids.iter().map(|&id| table.node_row(id).unwrap_or(u64::MAX)).collect();
+    TableData::from_table(table).into_table_with_index(Some((csr, node_rows)))
+}
+
+/// Options for `Database::ingest`
+#[derive(Clone, Debug)]
+pub struct IngestOptions {
+    /// Worker threads validating rows and building graph segments
+    pub parallelism: usize,
+    /// Rows per graph segment
+    pub batch_size: usize,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        IngestOptions {
+            parallelism: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            batch_size: 1000,
+        }
+    }
+}
+
+/// Options for `Database::merge_from`
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    /// Tables to merge; None merges every table of the other database
+    pub tables: Option<Vec<String>>,
+    /// Copy tables this database lacks; otherwise they are an error
+    pub create_missing: bool,
+    /// Threads merging each table's vector index
+    pub parallelism: usize,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            tables: None,
+            create_missing: true,
+            parallelism: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+/// Modification time and length of the database file when it was last read
+/// or written, to notice saves by other processes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<std::time::SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(metadata: &Metadata) -> Self {
+        FileStamp { modified: metadata.modified().ok(), len: metadata.len() }
+    }
 }
 
 /// The main database - manages multiple tables in a single file
@@ -32,6 +393,55 @@ pub struct Database {
     tables: HashMap<String, Table>,
     config: GraphConfig,
     path: Option<PathBuf>,
+    /// Statistics of the last SELECT or JOIN
+    last_stats: Option<QueryStats>,
+    /// ID of the last row inserted, by SQL or the direct insert APIs
+    last_insert_id: Option<u64>,
+    /// Between BEGIN BULK LOAD and END BULK LOAD
+    bulk_load: bool,
+    /// Threads for internal parallel work; None uses every core
+    threads: Option<usize>,
+    progress: Option<ProgressState>,
+    interrupt: InterruptHandle,
+    statement_timeout: Option<Duration>,
+    /// Inserts beyond this many bytes of estimated memory are rejected
+    memory_limit: Option<usize>,
+    /// Running estimate of memory in use while a limit is set; None until
+    /// recomputed after statements that may free memory
+    memory_used: Option<usize>,
+    /// ef_search for SQL similarity queries; None uses the table's default
+    ef_search: Option<usize>,
+    /// Bounds on the SQL `execute` accepts
+    parse_limits: ParseLimits,
+    /// Recently executed statements, parsed; None when disabled
+    statement_cache: Option<StatementCache>,
+    /// Save after SQL writes once this long has passed since the last autosave
+    autosave_interval: Option<Duration>,
+    last_autosave: Instant,
+    synchronous: Synchronous,
+    /// Opened with `open_readonly`: writes and saves fail with `MarsError::ReadOnly`
+    readonly: bool,
+    /// How long reads and saves wait for another process's file lock
+    busy_timeout: Duration,
+    /// Keeps other processes from opening the file for writing
+    _writer_lock: Option<WriterLock>,
+    /// File as last read or saved, for `reload_if_changed`
+    stamp: Cell<Option<FileStamp>>,
+    /// Contents of the file and its delta files, for `save_delta`
+    checkpoint: RefCell<Checkpoint>,
+    /// Databases opened read-only with ATTACH, by name
+    attached: HashMap<String, Database>,
+    /// Recent similarity search results; None when disabled
+    result_cache: Option<RefCell<ResultCache>>,
+    /// Set by `watch`: reload before the next statement after a change
+    #[cfg(feature = "watch")]
+    watcher: Option<FileWatcher>,
+    /// Object the database is saved to instead of a local file
+    #[cfg(feature = "object-store")]
+    remote: Option<RemoteFile>,
+    /// Key the file is encrypted with, if any
+    #[cfg(feature = "encryption")]
+    key: Option<crypto::FileKey>,
 }
 
 impl Database {
@@ -41,6 +451,34 @@ impl Database {
             tables: HashMap::new(),
             config: GraphConfig::default(),
             path: None,
+            last_stats: None,
+            last_insert_id: None,
+            bulk_load: false,
+            threads: None,
+            progress: None,
+            interrupt: InterruptHandle::default(),
+            statement_timeout: None,
+            memory_limit: None,
+            memory_used: None,
+            ef_search: None,
+            parse_limits: ParseLimits::default(),
+            statement_cache: None,
+            autosave_interval: None,
+            last_autosave: Instant::now(),
+            synchronous: Synchronous::default(),
+            readonly: false,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            _writer_lock: None,
+            stamp: Cell::new(None),
+            checkpoint: RefCell::default(),
+            attached: HashMap::new(),
+            result_cache: None,
+            #[cfg(feature = "watch")]
+            watcher: None,
+            #[cfg(feature = "object-store")]
+            remote: None,
+            #[cfg(feature = "encryption")]
+            key: None,
         }
     }
 
@@ -50,23 +488,48 @@ impl Database {
         self
     }
 
-    /// Open or create a database file
+    /// Open or create a database file. Only one process can have a file
+    /// open this way at a time; others fail with `MarsError::Locked` until
+    /// it is dropped. Read-only opens are not affected.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
+        let writer_lock = Some(WriterLock::acquire(&path)?);
 
-        if path.exists() {
-            Self::load(&path)
+        let db = if path.exists() {
+            Self::load(&path)?
         } else {
-            Self::create_new(&path)
+            Self::create_new(&path)?
+        };
+        Ok(Database { _writer_lock: writer_lock, ..db })
+    }
+
+    /// Open an existing database file for reading only. Statements that
+    /// change tables or rows, the direct write APIs and `save` fail with
+    /// `MarsError::ReadOnly`, so the file is never written.
+    pub fn open_readonly<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Database {
+            readonly: true,
+            ..Self::load(path.as_ref())?
+        })
+    }
+
+    /// Check whether the database was opened with `open_readonly`
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.readonly {
+            return Err(MarsError::ReadOnly);
         }
+        Ok(())
     }
 
     /// Create a new database file
     fn create_new(path: &Path) -> Result<Self> {
         let db = Database {
-            tables: HashMap::new(),
-            config: GraphConfig::default(),
             path: Some(path.to_path_buf()),
+            ..Self::in_memory()
         };
 
         // Write empty database
@@ -77,9 +540,59 @@ impl Database {
 
     /// Load database from file
     fn load(path: &Path) -> Result<Self> {
-        let mut file = File::open(path)?;
-        let mut reader = BufReader::new(&mut file);
+        let file = File::open(path)?;
+        lock::lock_file(&file, false, DEFAULT_BUSY_TIMEOUT)?;
+        let stamp = FileStamp::of(&file.metadata()?);
+        let mut reader = BufReader::new(delta::Hashing::new(file));
+        let mut header_buf = [0u8; 8];
+        reader.read_exact(&mut header_buf)?;
+        if crypto::is_encrypted(&header_buf) {
+            return Err(MarsError::Encryption("database is encrypted; open it with Database::open_encrypted".into()));
+        }
+        if sealed::is_sealed(&header_buf) {
+            return Err(MarsError::InvalidFormat("file is a sealed snapshot; open it with Database::open_readonly_mmap".into()));
+        }
 
+        let tables = Self::read_tables(header_buf.chain(&mut reader), Some(path))?;
+        std::io::copy(&mut reader, &mut std::io::sink())?;
+        let mut db = Database {
+            tables,
+            path: Some(path.to_path_buf()),
+            stamp: Cell::new(Some(stamp)),
+            ..Self::in_memory()
+        };
+        // Still under the shared lock, so no delta is half-written
+        db.apply_deltas(reader.get_ref().hash(), 0)?;
+        Ok(db)
+    }
+
+    /// Apply the delta files after the first `applied` written on top of
+    /// the base file hashing to `base_hash`, stopping at one left over from
+    /// an earlier base file
+    fn apply_deltas(&mut self, base_hash: u64, applied: u32) -> Result<()> {
+        let path = self.path.clone().expect("deltas belong to a database file");
+        let mut deltas = applied;
+        loop {
+            let contents = match std::fs::read(delta::delta_path(&path, deltas + 1)) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
+            };
+            let (sequence, hash, body) = delta::parse(&contents)?;
+            if hash != base_hash || sequence != deltas + 1 {
+                break;
+            }
+            let body = if crypto::is_encrypted(body) { self.decrypt(body)? } else { body.to_vec() };
+            Delta::decode(&body)?.apply(&mut self.tables)?;
+            deltas += 1;
+        }
+        *self.checkpoint.get_mut() = Checkpoint::new(base_hash, deltas, &self.tables);
+        Ok(())
+    }
+
+    /// Read the tables of a plain (decrypted) database file, with external
+    /// values from the text file next to `path`
+    fn read_tables(mut reader: impl Read, path: Option<&Path>) -> Result<HashMap<String, Table>> {
         // Read header
         let mut header_buf = [0u8; 8];
         reader.read_exact(&mut header_buf)?;
@@ -100,55 +613,313 @@ impl Database {
             let mut table_buf = vec![0u8; size];
             reader.read_exact(&mut table_buf)?;
 
-            let table_data: TableData = bincode::deserialize(&table_buf)
-                .map_err(|e| MarsError::InvalidFormat(format!("Failed to deserialize table: {}", e)))?;
-
-            // Reconstruct table
-            let mut table = Table::new(table_data.schema, GraphConfig::default())?;
+            let mut data = TableData::decode(version, &table_buf)?;
+            data.read_external(path)?;
+            let table = data.into_table()?;
+            tables.insert(table.name().to_string(), table);
+        }
 
-            // Restore rows and graph
-            for row in table_data.rows {
-                // Extract vector and insert into graph
-                if let Some(vec_idx) = table.schema.columns.iter().position(|c| {
-                    matches!(c.data_type, ColumnType::Vector(_))
-                }) {
-                    if let Some(vec) = row.values.get(vec_idx).and_then(|v| v.as_vector()) {
-                        table.graph.insert(vec.to_vec());
-                    }
-                }
-                let id = row.id;
-                table.rows.insert(id, row);
-            }
+        Ok(tables)
+    }
 
-            table.next_id = table_data.next_id;
-            tables.insert(table.name().to_string(), table);
+    /// Write an immutable snapshot of all tables in the sealed format, which
+    /// stores each vector index as flat arrays (vectors back to back, graph
+    /// edges in CSR layout) next to compressed row data. Open it with
+    /// `open_readonly_mmap`; the regular file is not affected.
+    pub fn export_sealed<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.path.as_deref() == Some(path) {
+            return Err(MarsError::InvalidConfig("cannot export a sealed snapshot over the database's own file".into()));
         }
+        let mut writer = BufWriter::new(File::create(path)?);
+        sealed::write(&self.tables, &mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        self.synchronous.sync_file(&file, path)
+    }
 
+    /// Open a snapshot written by `export_sealed`, read-only. The file is
+    /// memory-mapped and its vector indexes are used as stored instead of
+    /// being rebuilt from the rows, so large tables open quickly.
+    pub fn open_readonly_mmap<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        lock::lock_file(&file, false, DEFAULT_BUSY_TIMEOUT)?;
+        let map = unsafe { memmap2::Mmap::map(&file)? };
         Ok(Database {
-            tables,
-            config: GraphConfig::default(),
-            path: Some(path.to_path_buf()),
+            tables: sealed::read(&map)?,
+            readonly: true,
+            ..Self::in_memory()
         })
     }
 
+    /// Open or create a database file encrypted with a key derived from
+    /// `passphrase`. Fails with `MarsError::Encryption` if the passphrase is
+    /// wrong or the file is not encrypted; see `rekey` to encrypt one.
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let writer_lock = Some(WriterLock::acquire(path)?);
+        if !path.exists() {
+            let db = Database {
+                path: Some(path.to_path_buf()),
+                _writer_lock: writer_lock,
+                key: Some(crypto::FileKey::new(passphrase)?),
+                ..Self::in_memory()
+            };
+            db.save()?;
+            return Ok(db);
+        }
+
+        let mut file = File::open(path)?;
+        lock::lock_file(&file, false, DEFAULT_BUSY_TIMEOUT)?;
+        let stamp = FileStamp::of(&file.metadata()?);
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let (key, plain) = crypto::FileKey::open(passphrase, &contents)?;
+        let mut db = Database {
+            tables: Self::read_tables(plain.as_slice(), Some(path))?,
+            path: Some(path.to_path_buf()),
+            _writer_lock: writer_lock,
+            stamp: Cell::new(Some(stamp)),
+            key: Some(key),
+            ..Self::in_memory()
+        };
+        db.apply_deltas(delta::hash(&contents), 0)?;
+        Ok(db)
+    }
+
+    /// Re-read the file if another process saved it since this database
+    /// last read or saved it, or apply its new delta files, and return
+    /// whether anything changed. Tables are replaced with the file's;
+    /// settings are kept. Meant for read-only opens following a file that a
+    /// batch job rewrites.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        let Some(path) = self.path.clone() else {
+            return Ok(false);
+        };
+        let Checkpoint { base_hash, deltas, .. } = *self.checkpoint.get_mut();
+        let base_changed = Some(FileStamp::of(&std::fs::metadata(&path)?)) != self.stamp.get();
+        if !base_changed && !delta::delta_path(&path, deltas + 1).exists() {
+            return Ok(false);
+        }
+
+        let mut file = File::open(&path)?;
+        lock::lock_file(&file, false, self.busy_timeout)?;
+        let stamp = FileStamp::of(&file.metadata()?);
+        if Some(stamp) == self.stamp.get() {
+            // Only new delta files
+            self.apply_deltas(base_hash, deltas)?;
+            if self.checkpoint.get_mut().deltas == deltas {
+                return Ok(false);
+            }
+        } else {
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            self.tables = if crypto::is_encrypted(&contents) {
+                Self::read_tables(self.decrypt(&contents)?.as_slice(), Some(&path))?
+            } else {
+                Self::read_tables(contents.as_slice(), Some(&path))?
+            };
+            self.stamp.set(Some(stamp));
+            self.apply_deltas(delta::hash(&contents), 0)?;
+        }
+        self.memory_used = None;
+        self.last_stats = None;
+        if let Some(cache) = &self.result_cache {
+            cache.borrow_mut().clear();
+        }
+        Ok(true)
+    }
+
+    /// Decrypt file contents with the key the database was opened with
+    fn decrypt(&self, contents: &[u8]) -> Result<Vec<u8>> {
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.key {
+            return key.decrypt(contents);
+        }
+        let _ = contents;
+        Err(MarsError::Encryption("database is encrypted; open it with Database::open_encrypted".into()))
+    }
+
+    /// Watch the file and reload it before the first statement after
+    /// another process saves it, so a long-running reader picks up new
+    /// data without calling `reload_if_changed` itself
+    #[cfg(feature = "watch")]
+    pub fn watch(&mut self) -> Result<()> {
+        let path = self.path.as_ref()
+            .ok_or_else(|| MarsError::InvalidConfig("an in-memory database has no file to watch".into()))?;
+        self.watcher = Some(FileWatcher::start(path)?);
+        Ok(())
+    }
+
+    /// Open a database stored in an object store: `s3://bucket/key`,
+    /// `gs://bucket/key` (or `gcs://`) or `az://container/key`, with
+    /// credentials from the environment. The file is downloaded now and
+    /// uploaded by `save`; a missing object opens an empty database.
+    #[cfg(feature = "object-store")]
+    pub fn open_url(url: &str) -> Result<Self> {
+        Self::open_remote(RemoteFile::parse(url)?)
+    }
+
+    /// Open a database stored at `location` in an already configured store
+    #[cfg(feature = "object-store")]
+    pub fn open_object_store(
+        store: std::sync::Arc<dyn object_store::ObjectStore>,
+        location: object_store::path::Path,
+    ) -> Result<Self> {
+        Self::open_remote(RemoteFile::new(store, location)?)
+    }
+
+    #[cfg(feature = "object-store")]
+    fn open_remote(remote: RemoteFile) -> Result<Self> {
+        let tables = match remote.download()? {
+            Some(contents) if crypto::is_encrypted(&contents) => {
+                return Err(MarsError::Encryption("encrypted databases cannot be opened from an object store".into()));
+            }
+            Some(contents) => Self::read_tables(contents.as_slice(), None)?,
+            None => HashMap::new(),
+        };
+        Ok(Database { tables, remote: Some(remote), ..Self::in_memory() })
+    }
+
+    /// Make `save` fail with `MarsError::SaveConflict` instead of
+    /// overwriting the object if another writer saved it since it was
+    /// opened or last saved here. Saves become single conditional puts
+    /// keyed on the object's ETag rather than multipart uploads. Has no
+    /// effect on local files, which `open` already locks.
+    #[cfg(feature = "object-store")]
+    pub fn with_conflict_detection(mut self) -> Self {
+        if let Some(remote) = &mut self.remote {
+            remote.detect_conflicts = true;
+        }
+        self
+    }
+
+    /// Encrypt the file under a new passphrase, or store it in plain form
+    /// with None, and save it right away
+    #[cfg(feature = "encryption")]
+    pub fn rekey(&mut self, passphrase: Option<&str>) -> Result<()> {
+        self.check_writable()?;
+        #[cfg(feature = "object-store")]
+        if self.remote.is_some() {
+            return Err(MarsError::InvalidConfig("databases in an object store cannot be encrypted".into()));
+        }
+        self.key = passphrase.map(crypto::FileKey::new).transpose()?;
+        self.save()
+    }
+
+    /// Check whether the file is saved encrypted
+    pub fn is_encrypted(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        return self.key.is_some();
+        #[cfg(not(feature = "encryption"))]
+        false
+    }
+
     /// Save database to file
     pub fn save(&self) -> Result<()> {
+        self.check_writable()?;
+        #[cfg(feature = "object-store")]
+        if let Some(remote) = &self.remote {
+            let mut contents = Vec::new();
+            self.write_tables(&mut contents, None)?;
+            return remote.upload(contents);
+        }
         let path = match &self.path {
             Some(p) => p,
             None => return Ok(()), // In-memory, no save needed
         };
 
+        // Truncate only once no reader holds the lock
         let file = OpenOptions::new()
             .write(true)
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .open(path)?;
+        lock::lock_file(&file, true, self.busy_timeout)?;
+        file.set_len(0)?;
+
+        // External values of encrypted databases stay in the encrypted file
+        let mut texts = TextFileWriter::new(path)?;
+        let mut writer = BufWriter::new(delta::Hashing::new(file));
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.key {
+            let mut plain = Vec::new();
+            self.write_tables(&mut plain, None)?;
+            writer.write_all(&key.encrypt(&plain)?)?;
+        } else {
+            self.write_tables(&mut writer, Some(&mut texts))?;
+        }
+        #[cfg(not(feature = "encryption"))]
+        self.write_tables(&mut writer, Some(&mut texts))?;
+        texts.finish(self.synchronous)?;
+
+        let hashing = writer.into_inner().map_err(|e| e.into_error())?;
+        let base_hash = hashing.hash();
+        let file = hashing.into_inner();
+        self.synchronous.sync_file(&file, path)?;
+        self.stamp.set(Some(FileStamp::of(&file.metadata()?)));
+
+        // The new file holds everything the deltas did
+        delta::remove_deltas(path)?;
+        texts.remove_old()?;
+        self.checkpoint.replace(Checkpoint::new(base_hash, 0, &self.tables));
+        Ok(())
+    }
 
-        let mut writer = BufWriter::new(file);
+    /// Write only what changed since the last `save` or `save_delta` to a
+    /// delta file next to the database file, which opening the database
+    /// applies on top of it. Much cheaper than `save` for large databases
+    /// with few changes; `save` folds the deltas back into the file.
+    pub fn save_delta(&self) -> Result<()> {
+        self.check_writable()?;
+        #[cfg(feature = "object-store")]
+        if self.remote.is_some() {
+            return Err(MarsError::InvalidConfig("delta files need a local database file".into()));
+        }
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let mut checkpoint = self.checkpoint.borrow_mut();
+        let changes = checkpoint.diff(&self.tables);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let body = changes.encode()?;
+        #[cfg(feature = "encryption")]
+        let body = match &self.key {
+            Some(key) => key.encrypt(&body)?,
+            None => body,
+        };
+
+        // Readers load the file and its deltas under a shared lock
+        let base = File::open(path)?;
+        lock::lock_file(&base, true, self.busy_timeout)?;
+
+        // Written aside and renamed, so a crash never leaves a torn delta
+        let sequence = checkpoint.deltas + 1;
+        let delta_path = delta::delta_path(path, sequence);
+        let mut temp_name = delta_path.clone().into_os_string();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&delta::header(sequence, checkpoint.base_hash))?;
+        file.write_all(&body)?;
+        self.synchronous.sync_file(&file, &temp_path)?;
+        std::fs::rename(&temp_path, &delta_path)?;
+        self.synchronous.sync_file(&file, &delta_path)?;
+
+        checkpoint.deltas = sequence;
+        checkpoint.record(&self.tables);
+        Ok(())
+    }
 
+    /// Write the plain file format
+    fn write_tables(&self, writer: &mut impl Write, mut texts: Option<&mut TextFileWriter>) -> Result<()> {
         // Write header
         let header = DbHeader {
-            version: 1,
+            version: FORMAT_VERSION,
             table_count: self.tables.len() as u32,
         };
         writer.write_all(&header.version.to_le_bytes())?;
@@ -156,890 +927,1108 @@ impl Database {
 
         // Write tables
         for table in self.tables.values() {
-            let table_data = TableData {
-                schema: table.schema.clone(),
-                rows: table.rows.values().cloned().collect(),
-                centroid: table.graph.centroid().to_vec(),
-                next_id: table.next_id,
-            };
-
-            let serialized = bincode::serialize(&table_data)
+            let mut data = TableData::from_table(table);
+            if let Some(texts) = texts.as_deref_mut() {
+                data = data.write_external(texts)?;
+            }
+            let serialized = bincode::serialize(&data.compress_text().encode_vectors())
                 .map_err(|e| MarsError::InvalidFormat(format!("Failed to serialize table: {}", e)))?;
 
             writer.write_all(&(serialized.len() as u64).to_le_bytes())?;
             writer.write_all(&serialized)?;
         }
-
-        writer.flush()?;
         Ok(())
     }
 
     /// Execute a SQL-like command
     pub fn execute(&mut self, sql: &str) -> Result<ExecuteResult> {
-        let command = parse(sql)?;
-        self.execute_command(command)
+        self.interrupt.clear();
+        self.run_statement(sql)
     }
 
-    /// Direct insert without SQL parsing - much faster
-    pub fn insert_direct(
-        &mut self,
-        table_name: &str,
-        vector: Vec<f32>,
-        metadata: Vec<(&str, Value)>,
-    ) -> Result<u64> {
-        let table = self.tables.get_mut(table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
+    /// Execute a prepared statement with `params` bound to its placeholders,
+    /// without parsing SQL again
+    pub fn execute_prepared(&mut self, statement: &PreparedStatement, params: &[Value]) -> Result<ExecuteResult> {
+        self.interrupt.clear();
+        let command = statement.bind(params)?;
+        self.run_command(command)
+    }
 
-        // Build row values
-        let mut row_values: Vec<Value> = table.schema.columns.iter()
-            .map(|_| Value::Null)
-            .collect();
+    /// Handle for cancelling the running statement from another thread;
+    /// it then fails with `MarsError::Interrupted`
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
 
-        // Find vector column and set it
-        for (i, col) in table.schema.columns.iter().enumerate() {
-            if matches!(col.data_type, ColumnType::Vector(_)) {
-                row_values[i] = Value::Vector(vector.clone());
-            }
-        }
+    /// Run one statement with this database's interrupt flag current.
+    /// Interrupted queries fail rather than return partial rows.
+    fn run_statement(&mut self, sql: &str) -> Result<ExecuteResult> {
+        let command = match &mut self.statement_cache {
+            Some(cache) => cache.command(sql, &self.parse_limits)?,
+            None => parse_with_limits(sql, &self.parse_limits)?,
+        };
+        self.run_command(command)
+    }
 
-        // Set metadata
-        for (col_name, value) in metadata {
-            if let Some(idx) = table.schema.columns.iter().position(|c| &c.name == col_name) {
-                row_values[idx] = value;
-            }
+    /// `run_statement` for an already parsed command
+    fn run_command(&mut self, command: Command) -> Result<ExecuteResult> {
+        #[cfg(feature = "watch")]
+        if self.watcher.as_ref().is_some_and(FileWatcher::take_changed) {
+            self.reload_if_changed()?;
+        }
+        let scope = self.interrupt.enter(self.statement_timeout.map(|t| Instant::now() + t));
+        let result = self.execute_command(command);
+        let timeout = || MarsError::Timeout(format!(
+            "statement exceeded statement_timeout of {} ms",
+            self.statement_timeout.unwrap_or_default().as_millis(),
+        ));
+
+        let result = match result {
+            Err(MarsError::Interrupted) if scope.timed_out() => return Err(timeout()),
+            result => result?,
+        };
+        let is_query = matches!(result, ExecuteResult::Select { .. } | ExecuteResult::SelectSimilar { .. } | ExecuteResult::Aggregate { .. });
+        if is_query && scope.timed_out() {
+            return Err(timeout());
         }
+        if is_query && self.interrupt.is_interrupted() {
+            return Err(MarsError::Interrupted);
+        }
+        let is_write = matches!(result,
+            ExecuteResult::Insert { .. } | ExecuteResult::Update { .. } | ExecuteResult::Delete { .. }
+            | ExecuteResult::CreateTable { .. } | ExecuteResult::DropTable { .. } | ExecuteResult::AlterTable { .. }
+            | ExecuteResult::CreateView { .. } | ExecuteResult::DropView { .. } | ExecuteResult::CreateProjection { .. } | ExecuteResult::Analyze { .. }
+            | ExecuteResult::Vacuum { .. } | ExecuteResult::Reindex { .. } | ExecuteResult::Calibrate { .. } | ExecuteResult::EndBulkLoad { .. });
+        if is_write && self.autosave_interval.is_some_and(|interval| self.last_autosave.elapsed() >= interval) {
+            self.save()?;
+            self.last_autosave = Instant::now();
+        }
+        Ok(result)
+    }
 
-        // Insert
-        table.insert_row(row_values)
+    /// Abort statements running longer than `timeout` with
+    /// `MarsError::Timeout`; None disables the limit. Also settable with
+    /// `SET statement_timeout = <ms>;` (0 disables it).
+    pub fn set_statement_timeout(&mut self, timeout: Option<Duration>) {
+        self.statement_timeout = timeout.filter(|t| !t.is_zero());
     }
 
-    /// Direct similarity search without SQL parsing
-    pub fn search_similar(
-        &self,
-        table_name: &str,
-        query: &[f32],
-        k: usize,
-        ef_search: usize,
-    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
-        let table = self.tables.get(table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout
+    }
 
-        let results = table.select_by_similarity(query, k, ef_search);
+    /// Builder form of `set_statement_timeout`
+    pub fn with_statement_timeout(mut self, timeout: Duration) -> Self {
+        self.set_statement_timeout(Some(timeout));
+        self
+    }
 
-        Ok(results.into_iter()
-            .map(|(row, dist)| (row.id, row.values, dist))
-            .collect())
+    /// Reject inserts once the estimated memory of all tables would exceed
+    /// `bytes`; None removes the limit. Also settable with
+    /// `SET memory_limit = <bytes>;` (0 removes it). Existing rows are kept
+    /// when the limit is lowered below current usage.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.memory_limit = bytes.filter(|b| *b > 0);
+        self.memory_used = None;
     }
 
-    fn execute_command(&mut self, command: Command) -> Result<ExecuteResult> {
-        match command {
-            Command::CreateTable { name, columns } => {
-                self.create_table(name, columns)
-            }
-            Command::DropTable { name, if_exists } => {
-                self.drop_table(name, if_exists)
-            }
-            Command::Insert { table, columns, values } => {
-                self.insert_multi(table, columns, values)
-            }
-            Command::Select { table, columns, where_clause, group_by, having, order_by, limit, offset, distinct } => {
-                self.select(table, columns, where_clause.as_ref(), group_by.as_ref(), having.as_ref(), order_by.as_ref(), limit, offset, distinct)
-            }
-            Command::Update { table, assignments, where_clause } => {
-                self.update(table, assignments, where_clause.as_ref())
-            }
-            Command::Delete { table, where_clause } => {
-                self.delete(table, where_clause.as_ref())
-            }
-            Command::ShowTables => {
-                self.show_tables()
-            }
-            Command::Join { left_table, right_table, join_type, left_column, right_column, columns, where_clause, order_by, limit, offset } => {
-                self.execute_join(left_table, right_table, join_type, left_column, right_column, columns, where_clause.as_ref(), order_by.as_ref(), limit, offset)
-            }
-        }
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
     }
 
-    fn create_table(&mut self, name: String, columns: Vec<crate::parser::ColumnDef>) -> Result<ExecuteResult> {
-        if self.tables.contains_key(&name) {
-            return Err(MarsError::InvalidConfig(format!("Table '{}' already exists", name)));
-        }
+    /// Builder form of `set_memory_limit`
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.set_memory_limit(Some(bytes));
+        self
+    }
 
-        let mut schema = Schema::new(&name);
-        for col_def in columns {
-            let is_vector = matches!(col_def.data_type, ColumnType::Vector(_));
-            let col_name = col_def.name.clone();
+    /// Search breadth for SQL similarity queries; None (the default) uses
+    /// the larger of 100 and the table's `search_buffer`. Also settable with
+    /// `PRAGMA ef_search = <n>;` (0 restores the default).
+    pub fn set_ef_search(&mut self, ef_search: Option<usize>) {
+        self.ef_search = ef_search.filter(|ef| *ef > 0);
+    }
 
-            let mut col = Column::new(&col_def.name, col_def.data_type);
-            col.primary_key = col_def.primary_key;
-            col.nullable = !col_def.not_null;
-            col.unique = col_def.unique;  // NEW: pass unique constraint
-            schema.columns.push(col);
+    pub fn ef_search(&self) -> Option<usize> {
+        self.ef_search
+    }
 
-            if is_vector {
-                schema.vector_column = Some(col_name);
-            }
+    /// Reject SQL longer, more deeply nested, or with larger vector literals
+    /// and IN lists than `limits`, e.g. when statements come from untrusted
+    /// clients. The defaults only stop pathological input.
+    pub fn set_parse_limits(&mut self, limits: ParseLimits) {
+        self.parse_limits = limits;
+        if let Some(cache) = &mut self.statement_cache {
+            cache.clear();
         }
+    }
 
-        let table = Table::new(schema, self.config.clone())?;
-        self.tables.insert(name.clone(), table);
+    pub fn parse_limits(&self) -> &ParseLimits {
+        &self.parse_limits
+    }
 
-        Ok(ExecuteResult::CreateTable { name })
+    /// Builder form of `set_parse_limits`
+    pub fn with_parse_limits(mut self, limits: ParseLimits) -> Self {
+        self.set_parse_limits(limits);
+        self
     }
 
-    fn drop_table(&mut self, name: String, if_exists: bool) -> Result<ExecuteResult> {
-        if self.tables.remove(&name).is_none() {
-            if if_exists {
-                return Ok(ExecuteResult::DropTable { name });
-            }
-            return Err(MarsError::InvalidFormat(format!("Table '{}' does not exist", name)));
+    /// Keep up to `entries` recently executed statements parsed, keyed by
+    /// their SQL text, so repeating one skips the parser. 0 (the default)
+    /// disables the cache. Also settable with
+    /// `PRAGMA statement_cache_size = <n>;`.
+    pub fn set_statement_cache_size(&mut self, entries: usize) {
+        if entries != self.statement_cache_size() {
+            self.statement_cache = (entries > 0).then(|| StatementCache::with_capacity(entries));
         }
-        Ok(ExecuteResult::DropTable { name })
     }
 
-    fn insert_multi(&mut self, table_name: String, columns: Vec<String>, values: Vec<Vec<Value>>) -> Result<ExecuteResult> {
-        let table = self.tables.get_mut(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
+    pub fn statement_cache_size(&self) -> usize {
+        self.statement_cache.as_ref().map_or(0, StatementCache::capacity)
+    }
 
-        let mut last_id = 0u64;
-        for row_values in values {
-            last_id = table.insert(&columns, row_values)?;
-        }
-        Ok(ExecuteResult::Insert { id: last_id })
+    /// Builder form of `set_statement_cache_size`
+    pub fn with_statement_cache(mut self, entries: usize) -> Self {
+        self.set_statement_cache_size(entries);
+        self
     }
 
-    fn select(
-        &self,
-        table_name: String,
-        columns: Vec<SelectColumn>,
-        where_clause: Option<&WhereClause>,
-        group_by: Option<&Vec<String>>,
-        having: Option<&WhereClause>,
-        order_by: Option<&OrderBy>,
-        limit: Option<usize>,
-        offset: Option<usize>,
-        distinct: bool,
-    ) -> Result<ExecuteResult> {
-        let table = self.tables.get(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
-
-        // Check for vector similarity query
-        if let Some(wc) = where_clause {
-            for cond in &wc.conditions {
-                if cond.operator == ComparisonOp::Similar {
-                    if let ConditionValue::Single(Value::Vector(query_vec)) = &cond.value {
-                        let k = limit.unwrap_or(10);
-                        let results = table.select_by_similarity(query_vec, k, 100);
-                        return Ok(ExecuteResult::SelectSimilar { results });
-                    }
-                }
-            }
-        }
+    /// Hits and misses of the statement cache since it was last resized
+    pub fn statement_cache_stats(&self) -> StatementCacheStats {
+        self.statement_cache.as_ref().map(StatementCache::stats).unwrap_or_default()
+    }
 
-        // Check for GROUP BY with aggregates
-        if group_by.is_some() {
-            return self.execute_group_by(table, &columns, where_clause, group_by.unwrap(), having, order_by, limit, offset);
+    /// Bound the threads used by internal parallel work: END BULK LOAD,
+    /// REINDEX and merging inserted segments. None (the default) uses every
+    /// core. Also settable with `PRAGMA threads = <n>;` (0 restores the
+    /// default). `IngestOptions` and `MergeOptions` set their own
+    /// parallelism.
+    pub fn set_threads(&mut self, threads: Option<usize>) {
+        self.threads = threads.filter(|n| *n > 0);
+    }
+
+    pub fn threads(&self) -> Option<usize> {
+        self.threads
+    }
+
+    /// Threads parallel work may use: the configured limit or every core
+    fn thread_count(&self) -> usize {
+        self.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Keep the results of up to `entries` recent similarity searches, from
+    /// `search_similar` and SQL SIMILARITY queries, and answer repeats from
+    /// memory. A table's entries are dropped whenever it changes. 0 (the
+    /// default) disables the cache. Also settable with
+    /// `PRAGMA result_cache_size = <n>;`.
+    pub fn set_result_cache_size(&mut self, entries: usize) {
+        self.result_cache = (entries > 0).then(|| RefCell::new(ResultCache::new(entries)));
+    }
+
+    pub fn result_cache_size(&self) -> usize {
+        self.result_cache.as_ref().map_or(0, |cache| cache.borrow().capacity())
+    }
+
+    /// Builder form of `set_result_cache_size`
+    pub fn with_result_cache(mut self, entries: usize) -> Self {
+        self.set_result_cache_size(entries);
+        self
+    }
+
+    /// Drop cached results of a table that is about to change
+    fn invalidate_results(&self, table_name: &str) {
+        if let Some(cache) = &self.result_cache {
+            cache.borrow_mut().invalidate(table_name);
         }
+    }
 
-        // Check for aggregate functions (without GROUP BY)
-        let has_aggregates = columns.iter().any(|c| matches!(c, SelectColumn::Aggregate { .. }));
-        if has_aggregates {
-            return self.execute_aggregates(table, &columns, where_clause);
+    fn cached_result(&self, key: &CacheKey) -> Option<Cached> {
+        self.result_cache.as_ref()?.borrow_mut().get(key)
+    }
+
+    fn cache_result(&self, key: CacheKey, cached: Cached) {
+        if let Some(cache) = &self.result_cache {
+            cache.borrow_mut().insert(key, cached);
         }
+    }
 
-        // Convert SelectColumn to column names
-        let col_names: Vec<String> = columns.iter()
-            .filter_map(|c| match c {
-                SelectColumn::Column(name) => Some(name.clone()),
-                _ => None,
-            })
-            .collect();
+    /// Save a file-backed database after SQL writes, at most once per
+    /// `interval`; None disables it. Also settable with
+    /// `PRAGMA autosave_interval = <ms>;` (0 disables it).
+    pub fn set_autosave_interval(&mut self, interval: Option<Duration>) {
+        self.autosave_interval = interval.filter(|i| !i.is_zero());
+        self.last_autosave = Instant::now();
+    }
 
-        let is_star = columns.iter().any(|c| matches!(c, SelectColumn::All));
+    pub fn autosave_interval(&self) -> Option<Duration> {
+        self.autosave_interval
+    }
 
-        let rows = table.select(
-            if is_star { &[] } else { &col_names },
-            where_clause,
-            limit,
-            offset,
-            order_by,
-            distinct,
-        );
-        Ok(ExecuteResult::Select { rows })
+    /// Durability of `save`. Also settable with
+    /// `PRAGMA synchronous = OFF | NORMAL | FULL;`
+    pub fn set_synchronous(&mut self, synchronous: Synchronous) {
+        self.synchronous = synchronous;
     }
 
-    fn execute_aggregates(&self, table: &Table, columns: &[SelectColumn], where_clause: Option<&WhereClause>) -> Result<ExecuteResult> {
-        use crate::parser::AggregateFunc;
+    pub fn synchronous(&self) -> Synchronous {
+        self.synchronous
+    }
 
-        // Get matching rows
-        let matching_rows: Vec<&Row> = table.rows.values()
-            .filter(|row| table.matches_where(row, where_clause))
-            .collect();
+    /// How long `save` waits for other processes to finish reading the
+    /// file before failing with `MarsError::Locked` (default 5 s). Also
+    /// settable with `PRAGMA busy_timeout = <ms>;`
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.busy_timeout = timeout;
+    }
 
-        let mut results = Vec::new();
-
-        for col in columns {
-            match col {
-                SelectColumn::Aggregate { func, column, alias } => {
-                    let value = match func {
-                        AggregateFunc::Count => {
-                            if column == "*" {
-                                Value::Integer(matching_rows.len() as i64)
-                            } else {
-                                let idx = table.column_index(column).unwrap_or(0);
-                                let count = matching_rows.iter()
-                                    .filter(|r| !matches!(r.values.get(idx), Some(Value::Null) | None))
-                                    .count();
-                                Value::Integer(count as i64)
-                            }
-                        }
-                        AggregateFunc::Sum => {
-                            let idx = table.column_index(column).unwrap_or(0);
-                            let sum: f64 = matching_rows.iter()
-                                .filter_map(|r| match r.values.get(idx) {
-                                    Some(Value::Integer(i)) => Some(*i as f64),
-                                    Some(Value::Float(f)) => Some(*f),
-                                    _ => None,
-                                })
-                                .sum();
-                            Value::Float(sum)
-                        }
-                        AggregateFunc::Avg => {
-                            let idx = table.column_index(column).unwrap_or(0);
-                            let values: Vec<f64> = matching_rows.iter()
-                                .filter_map(|r| match r.values.get(idx) {
-                                    Some(Value::Integer(i)) => Some(*i as f64),
-                                    Some(Value::Float(f)) => Some(*f),
-                                    _ => None,
-                                })
-                                .collect();
-                            if values.is_empty() {
-                                Value::Null
-                            } else {
-                                Value::Float(values.iter().sum::<f64>() / values.len() as f64)
-                            }
-                        }
-                        AggregateFunc::Min => {
-                            let idx = table.column_index(column).unwrap_or(0);
-                            matching_rows.iter()
-                                .filter_map(|r| r.values.get(idx))
-                                .filter(|v| !matches!(v, Value::Null))
-                                .min_by(|a, b| table.values_compare(a, b).unwrap_or(std::cmp::Ordering::Equal))
-                                .cloned()
-                                .unwrap_or(Value::Null)
-                        }
-                        AggregateFunc::Max => {
-                            let idx = table.column_index(column).unwrap_or(0);
-                            matching_rows.iter()
-                                .filter_map(|r| r.values.get(idx))
-                                .filter(|v| !matches!(v, Value::Null))
-                                .max_by(|a, b| table.values_compare(a, b).unwrap_or(std::cmp::Ordering::Equal))
-                                .cloned()
-                                .unwrap_or(Value::Null)
-                        }
-                    };
+    pub fn busy_timeout(&self) -> Duration {
+        self.busy_timeout
+    }
 
-                    let name = alias.clone().unwrap_or_else(|| format!("{:?}({})", func, column));
-                    results.push((name, value));
-                }
-                SelectColumn::Column(name) => {
-                    // For non-aggregate columns in aggregate query, take first value
-                    if let Some(row) = matching_rows.first() {
-                        if let Some(idx) = table.column_index(name) {
-                            results.push((name.clone(), row.values.get(idx).cloned().unwrap_or(Value::Null)));
-                        }
-                    }
-                }
-                SelectColumn::All => {}
+    /// Estimated memory held by all tables
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for table in self.tables.values() {
+            usage += table.memory_usage();
+        }
+        usage
+    }
+
+    /// Limit and current usage for an insert, if a limit is set. The cached
+    /// usage is taken so that a failed insert leaves it to be recomputed.
+    fn take_memory_budget(&mut self) -> Option<(usize, usize)> {
+        let limit = self.memory_limit?;
+        let used = self.memory_used.take().unwrap_or_else(|| self.memory_usage().total());
+        Some((limit, used))
+    }
+
+    fn set_option(&mut self, name: String, value: Value) -> Result<ExecuteResult> {
+        self.apply_setting(&name, &value)?;
+        Ok(ExecuteResult::Set { name, value })
+    }
+
+    /// PRAGMA name [= value]; reports the setting's (new) value
+    fn pragma(&mut self, name: String, value: Option<Value>) -> Result<ExecuteResult> {
+        if let Some(value) = value {
+            self.apply_setting(&name, &value)?;
+        }
+        let value = self.setting(&name)?;
+        Ok(ExecuteResult::Pragma { name, value })
+    }
+
+    /// Settings shared by SET and PRAGMA
+    fn apply_setting(&mut self, name: &str, value: &Value) -> Result<()> {
+        let non_negative = |unit: &str| value.as_integer().filter(|n| *n >= 0).ok_or_else(|| {
+            MarsError::InvalidConfig(format!("{} must be a non-negative number of {}", name, unit))
+        });
+        match name {
+            "statement_timeout" => {
+                let ms = non_negative("milliseconds")?;
+                self.set_statement_timeout(Some(Duration::from_millis(ms as u64)));
+            }
+            "memory_limit" => {
+                let bytes = non_negative("bytes")?;
+                self.set_memory_limit(Some(bytes as usize));
+            }
+            "ef_search" => {
+                let ef = non_negative("candidates")?;
+                self.set_ef_search(Some(ef as usize));
             }
+            "threads" => {
+                let threads = non_negative("threads")?;
+                self.set_threads(Some(threads as usize));
+            }
+            "autosave_interval" => {
+                let ms = non_negative("milliseconds")?;
+                self.set_autosave_interval(Some(Duration::from_millis(ms as u64)));
+            }
+            "busy_timeout" => {
+                let ms = non_negative("milliseconds")?;
+                self.set_busy_timeout(Duration::from_millis(ms as u64));
+            }
+            "synchronous" => {
+                let synchronous = Synchronous::from_value(value).ok_or_else(|| {
+                    MarsError::InvalidConfig("synchronous must be OFF, NORMAL or FULL".into())
+                })?;
+                self.set_synchronous(synchronous);
+            }
+            "result_cache_size" => {
+                let entries = non_negative("entries")?;
+                self.set_result_cache_size(entries as usize);
+            }
+            "statement_cache_size" => {
+                let entries = non_negative("entries")?;
+                self.set_statement_cache_size(entries as usize);
+            }
+            _ => return Err(MarsError::InvalidConfig(format!("Unknown setting: {}", name))),
         }
+        Ok(())
+    }
 
-        Ok(ExecuteResult::Aggregate { results })
+    /// Current value of a setting; disabled limits read as 0
+    fn setting(&self, name: &str) -> Result<Value> {
+        let millis = |d: Option<Duration>| Value::Integer(d.map_or(0, |d| d.as_millis() as i64));
+        Ok(match name {
+            "statement_timeout" => millis(self.statement_timeout),
+            "memory_limit" => Value::Integer(self.memory_limit.unwrap_or(0) as i64),
+            "ef_search" => Value::Integer(self.ef_search.unwrap_or(0) as i64),
+            "threads" => Value::Integer(self.threads.unwrap_or(0) as i64),
+            "autosave_interval" => millis(self.autosave_interval),
+            "busy_timeout" => millis(Some(self.busy_timeout)),
+            "synchronous" => Value::Text(self.synchronous.name().to_string()),
+            "result_cache_size" => Value::Integer(self.result_cache_size() as i64),
+            "statement_cache_size" => Value::Integer(self.statement_cache_size() as i64),
+            _ => return Err(MarsError::InvalidConfig(format!("Unknown setting: {}", name))),
+        })
     }
 
-    /// Execute GROUP BY with aggregates using hash aggregation
-    fn execute_group_by(
-        &self,
-        table: &Table,
-        columns: &[SelectColumn],
-        where_clause: Option<&WhereClause>,
-        group_by: &[String],
-        having: Option<&WhereClause>,
-        order_by: Option<&OrderBy>,
-        limit: Option<usize>,
-        offset: Option<usize>,
-    ) -> Result<ExecuteResult> {
-        use crate::parser::AggregateFunc;
-        use std::collections::HashMap as StdHashMap;
+    /// Rows scanned, rows returned and index used by the last SELECT or
+    /// JOIN, if any
+    pub fn last_query_stats(&self) -> Option<&QueryStats> {
+        self.last_stats.as_ref()
+    }
 
-        // Get matching rows
-        let matching_rows: Vec<&Row> = table.rows.values()
-            .filter(|row| table.matches_where(row, where_clause))
-            .collect();
+    /// ID of the row most recently inserted into any table, also read with
+    /// `SELECT last_insert_rowid()`. Rows of a multi-row insert count as
+    /// inserted one after another; `merge_from` does not change it.
+    pub fn last_insert_id(&self) -> Option<u64> {
+        self.last_insert_id
+    }
 
-        // Get column indices for GROUP BY columns
-        let group_indices: Vec<(String, usize)> = group_by.iter()
-            .filter_map(|name| table.column_index(name).map(|idx| (name.clone(), idx)))
-            .collect();
+    /// Execute a script of `;`-separated statements in order, returning one
+    /// result per statement. Stops at the first error; statements before it
+    /// stay applied.
+    pub fn execute_script(&mut self, sql: &str) -> Result<Vec<ExecuteResult>> {
+        self.interrupt.clear();
+        let statements = split_statements(sql);
+        let mut results = Vec::with_capacity(statements.len());
+        for (i, statement) in statements.iter().enumerate() {
+            if self.interrupt.is_interrupted() {
+                return Err(MarsError::Interrupted);
+            }
+            results.push(self.run_statement(statement)?);
+            report_progress(&mut self.progress, "script", i + 1, statements.len())?;
+        }
+        Ok(results)
+    }
 
-        // Hash aggregation: group_key -> list of rows
-        let mut groups: StdHashMap<Vec<String>, Vec<&Row>> = StdHashMap::new();
+    /// Call `handler` every `interval` rows of a multi-row INSERT, statements
+    /// of a script, or nodes of an index repair. Returning false cancels the
+    /// operation with `MarsError::Interrupted`; work done up to that point
+    /// is kept.
+    pub fn set_progress_handler<F>(&mut self, interval: usize, handler: F)
+    where
+        F: FnMut(&Progress) -> bool + Send + 'static,
+    {
+        self.progress = Some(ProgressState { interval: interval.max(1), handler: Box::new(handler) });
+    }
 
-        for row in &matching_rows {
-            // Create group key from GROUP BY column values
-            let key: Vec<String> = group_indices.iter()
-                .map(|(_, idx)| Table::value_to_string(&row.values[*idx]))
-                .collect();
-            groups.entry(key).or_default().push(*row);
+    pub fn clear_progress_handler(&mut self) {
+        self.progress = None;
+    }
+
+    /// Direct insert without SQL parsing - much faster.
+    ///
+    /// The vector may be owned, borrowed or an `Arc<[f32]>` (see
+    /// `IntoVector`).
+    pub fn insert_direct(
+        &mut self,
+        table_name: &str,
+        vector: impl IntoVector,
+        metadata: Vec<(&str, Value)>,
+    ) -> Result<u64> {
+        self.check_writable()?;
+        self.invalidate_results(table_name);
+        let mut budget = self.take_memory_budget();
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        let row_values = table.direct_row_values(vector.into_vector(), metadata)?;
+        engine::check_references(&self.tables, table, &row_values)?;
+        let table = self.tables.get_mut(table_name).unwrap();
+        if let Some(budget) = &mut budget {
+            reserve_memory(table, &row_values, budget)?;
         }
+        let id = table.insert_row(row_values)?;
+        self.last_insert_id = Some(id);
+        self.memory_used = budget.map(|(_, used)| used);
+        Ok(id)
+    }
+
+    /// Bulk-load a stream of (vector, metadata) rows, like repeated
+    /// `insert_direct` calls but parallel. Rows are read in waves of
+    /// `parallelism * batch_size`; worker threads validate each batch and
+    /// index it as a separate graph segment, then the segments are merged
+    /// into the table. The iterator is only advanced once the previous wave
+    /// is merged, so memory stays bounded however long the stream is.
+    ///
+    /// Returns the IDs given to the rows, in input order; with an
+    /// AUTOINCREMENT STEP they are every step-th value of the range. On
+    /// error, batches merged before the failing one stay inserted.
+    pub fn ingest<'m, I>(&mut self, table_name: &str, rows: I, options: IngestOptions) -> Result<Range<u64>>
+    where
+        I: IntoIterator<Item = IngestRow<'m>>,
+    {
+        self.check_writable()?;
+        self.invalidate_results(table_name);
+        let parallelism = options.parallelism.max(1);
+        let batch_size = options.batch_size.max(1);
+        let mut budget = self.take_memory_budget();
+        let first_id = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?
+            .next_id;
+
+        let mut rows = rows.into_iter();
+        loop {
+            let wave: Vec<Vec<_>> = (0..parallelism)
+                .map(|_| rows.by_ref().take(batch_size).collect::<Vec<_>>())
+                .take_while(|batch| !batch.is_empty())
+                .collect();
+            if wave.is_empty() {
+                break;
+            }
+
+            let shared = &self.tables[table_name];
+            let segments: Vec<Result<Segment>> = std::thread::scope(|scope| {
+                let workers: Vec<_> = wave.into_iter()
+                    .map(|batch| scope.spawn(move || shared.prepare_segment(batch)))
+                    .collect();
+                workers.into_iter().map(|w| w.join().unwrap()).collect()
+            });
 
-        // Pre-compute column names from the SELECT columns (same for all groups)
-        let col_names: Vec<String> = columns.iter()
-            .flat_map(|col| match col {
-                SelectColumn::Column(name) => vec![name.clone()],
-                SelectColumn::Aggregate { func, column, alias } => {
-                    vec![alias.clone().unwrap_or_else(|| format!("{:?}({})", func, column))]
+            for segment in segments {
+                let segment = segment?;
+                for row_values in &segment.rows {
+                    engine::check_references(&self.tables, &self.tables[table_name], row_values)?;
                 }
-                SelectColumn::All => {
-                    table.schema.columns.iter()
-                        .map(|c| c.name.clone())
-                        .collect()
+                let table = self.tables.get_mut(table_name).unwrap();
+                if let Some(budget) = &mut budget {
+                    for row_values in &segment.rows {
+                        reserve_memory(table, row_values, budget)?;
+                    }
                 }
-            })
-            .collect();
+                let ids = table.merge_segment(segment, parallelism)?;
+                self.last_insert_id = ids.last().copied().or(self.last_insert_id);
+            }
+        }
 
-        // Process each group and compute aggregates
-        let mut result_rows: Vec<Row> = Vec::new();
+        let ids = first_id..self.tables[table_name].next_id;
+        self.memory_used = budget.map(|(_, used)| used);
+        Ok(ids)
+    }
 
-        for (_group_key, group_rows) in groups.iter() {
-            let mut values = Vec::new();
+    /// Add the rows of another database's tables to this one, such as
+    /// shards ingested separately. Merged rows get new IDs after the
+    /// existing ones, and each table's vector index is merged in bulk.
+    /// Returns the number of rows added.
+    pub fn merge_from(&mut self, other: &Database, options: MergeOptions) -> Result<usize> {
+        self.check_writable()?;
+        let names = match options.tables {
+            Some(names) => names,
+            None => {
+                let mut names: Vec<String> = other.tables.keys().cloned().collect();
+                names.sort();
+                names
+            }
+        };
+        for name in &names {
+            if !other.tables.contains_key(name) {
+                return Err(MarsError::TableNotFound(name.clone()));
+            }
+            if !options.create_missing && !self.tables.contains_key(name) {
+                return Err(MarsError::TableNotFound(name.clone()));
+            }
+        }
 
-            for col in columns {
-                match col {
-                    SelectColumn::Column(name) => {
-                        // Take value from first row in group
-                        if let Some(row) = group_rows.first() {
-                            if let Some(idx) = table.column_index(name) {
-                                values.push(row.values.get(idx).cloned().unwrap_or(Value::Null));
-                            }
+        let mut budget = self.take_memory_budget();
+        let mut merged = 0;
+        for name in &names {
+            self.invalidate_results(name);
+            let source = &other.tables[name];
+            match self.tables.get_mut(name) {
+                Some(table) => {
+                    let segment = table.segment_from(source)?;
+                    if let Some(budget) = &mut budget {
+                        for row_values in &segment.rows {
+                            reserve_memory(table, row_values, budget)?;
                         }
                     }
-                    SelectColumn::Aggregate { func, column, alias: _ } => {
-                        let value = match func {
-                            AggregateFunc::Count => {
-                                if column == "*" {
-                                    Value::Integer(group_rows.len() as i64)
-                                } else {
-                                    let idx = table.column_index(column).unwrap_or(0);
-                                    let count = group_rows.iter()
-                                        .filter(|r| !matches!(r.values.get(idx), Some(Value::Null) | None))
-                                        .count();
-                                    Value::Integer(count as i64)
-                                }
-                            }
-                            AggregateFunc::Sum => {
-                                let idx = table.column_index(column).unwrap_or(0);
-                                let sum: f64 = group_rows.iter()
-                                    .filter_map(|r| match r.values.get(idx) {
-                                        Some(Value::Integer(i)) => Some(*i as f64),
-                                        Some(Value::Float(f)) => Some(*f),
-                                        _ => None,
-                                    })
-                                    .sum();
-                                Value::Float(sum)
-                            }
-                            AggregateFunc::Avg => {
-                                let idx = table.column_index(column).unwrap_or(0);
-                                let vals: Vec<f64> = group_rows.iter()
-                                    .filter_map(|r| match r.values.get(idx) {
-                                        Some(Value::Integer(i)) => Some(*i as f64),
-                                        Some(Value::Float(f)) => Some(*f),
-                                        _ => None,
-                                    })
-                                    .collect();
-                                if vals.is_empty() {
-                                    Value::Null
-                                } else {
-                                    Value::Float(vals.iter().sum::<f64>() / vals.len() as f64)
-                                }
-                            }
-                            AggregateFunc::Min => {
-                                let idx = table.column_index(column).unwrap_or(0);
-                                group_rows.iter()
-                                    .filter_map(|r| r.values.get(idx))
-                                    .filter(|v| !matches!(v, Value::Null))
-                                    .min_by(|a, b| table.values_compare(a, b).unwrap_or(std::cmp::Ordering::Equal))
-                                    .cloned()
-                                    .unwrap_or(Value::Null)
-                            }
-                            AggregateFunc::Max => {
-                                let idx = table.column_index(column).unwrap_or(0);
-                                group_rows.iter()
-                                    .filter_map(|r| r.values.get(idx))
-                                    .filter(|v| !matches!(v, Value::Null))
-                                    .max_by(|a, b| table.values_compare(a, b).unwrap_or(std::cmp::Ordering::Equal))
-                                    .cloned()
-                                    .unwrap_or(Value::Null)
-                            }
-                        };
-                        values.push(value);
-                    }
-                    SelectColumn::All => {
-                        // Include all columns from first row
-                        if let Some(row) = group_rows.first() {
-                            for val in row.values.iter() {
-                                values.push(val.clone());
-                            }
+                    merged += table.merge_segment(segment, options.parallelism.max(1))?.len();
+                }
+                None => {
+                    let table = copy_table(source)?;
+                    if let Some(budget) = &mut budget {
+                        for row in table.rows.values() {
+                            reserve_memory(&table, &row.values, budget)?;
                         }
                     }
+                    merged += table.len();
+                    self.tables.insert(name.clone(), table);
                 }
             }
+        }
+        self.memory_used = budget.map(|(_, used)| used);
+        Ok(merged)
+    }
 
-            // Create a temporary row for HAVING evaluation
-            let temp_row = Row::new(0, values.clone());
+    /// Repair a table's vector index after heavy deletes.
+    /// Returns the number of graph nodes reconnected.
+    pub fn repair_index(&mut self, table_name: &str) -> Result<usize> {
+        self.interrupt.clear();
+        self.invalidate_results(table_name);
+        let table = self.tables.get_mut(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        let progress = &mut self.progress;
+        let interrupt = &self.interrupt;
+        let mut cancelled = false;
+        let repaired = table.repair_index_with_progress(&mut |done, total| {
+            cancelled = interrupt.is_interrupted()
+                || report_progress(progress, "repair_index", done, total).is_err();
+            !cancelled
+        });
+        if cancelled {
+            return Err(MarsError::Interrupted);
+        }
+        Ok(repaired)
+    }
 
-            // Apply HAVING clause if present
-            let passes_having = if let Some(having_clause) = having {
-                // For HAVING, we need to match against the computed values
-                // This is a simplified implementation
-                self.matches_having(&temp_row, &col_names, having_clause, table)
-            } else {
-                true
-            };
+    /// Near-duplicate rows of a table, without changing it: each row whose
+    /// vector lies within `threshold` of a lower-id row, paired with the
+    /// row `dedupe` would keep. Distances are as SQL reports them, e.g.
+    /// 1 - similarity on cosine tables.
+    pub fn find_duplicates(&self, table_name: &str, threshold: f32) -> Result<Vec<Duplicate>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        table.near_duplicates(threshold)
+    }
 
-            if passes_having {
-                result_rows.push(temp_row);
-            }
-        }
+    /// Delete the near-duplicates `find_duplicates` reports, keeping the
+    /// lowest-id row of each group. ON DELETE actions of referencing
+    /// tables apply. Returns the deleted rows.
+    pub fn dedupe(&mut self, table_name: &str, threshold: f32) -> Result<Vec<Duplicate>> {
+        self.check_writable()?;
+        self.memory_used = None;
+        self.invalidate_results(table_name);
+        let duplicates = self.find_duplicates(table_name, threshold)?;
+        engine::delete_ids(&mut self.tables, table_name, duplicates.iter().map(|d| d.duplicate).collect())?;
+        Ok(duplicates)
+    }
 
-        // Apply ORDER BY
-        if let Some(ob) = order_by {
-            if let Some(idx) = col_names.iter().position(|n| n == &ob.column) {
-                result_rows.sort_by(|a, b| {
-                    let cmp = table.values_compare(&a.values[idx], &b.values[idx])
-                        .unwrap_or(std::cmp::Ordering::Equal);
-                    if ob.ascending { cmp } else { cmp.reverse() }
-                });
-            }
-        }
+    /// Update a row only if it is still at `expected_version` (compare-and-swap).
+    /// Returns the row's new version or `MarsError::VersionConflict`.
+    pub fn update_if_version(
+        &mut self,
+        table_name: &str,
+        id: u64,
+        assignments: Vec<(&str, Value)>,
+        expected_version: u64,
+    ) -> Result<u64> {
+        self.check_writable()?;
+        self.memory_used = None;
+        self.invalidate_results(table_name);
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-        // Apply OFFSET
-        if let Some(n) = offset {
-            result_rows = result_rows.into_iter().skip(n).collect();
+        let assignments: Vec<(String, Value)> = assignments.into_iter()
+            .map(|(col, value)| (col.to_string(), value))
+            .collect();
+        if table.row_version(id) == Some(expected_version) {
+            engine::check_update_references(&self.tables, table, &[(id, assignments.clone())])?;
         }
+        self.tables.get_mut(table_name).unwrap().update_if_version(id, &assignments, expected_version)
+    }
 
-        // Apply LIMIT
-        if let Some(n) = limit {
-            result_rows.truncate(n);
+    /// Get the current version of a row, or None if it does not exist
+    pub fn row_version(&self, table_name: &str, id: u64) -> Result<Option<u64>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        Ok(table.row_version(id))
+    }
+
+    /// Direct similarity search without SQL parsing
+    pub fn search_similar(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+        ef_search: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let key = self.result_cache.as_ref()
+            .map(|_| CacheKey::new(table_name, query, k, &format!("search_similar {}", ef_search)));
+        if let Some(Cached::Matches(matches)) = key.as_ref().and_then(|key| self.cached_result(key)) {
+            return Ok(matches);
         }
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-        // Create aggregate results format
-        let results: Vec<(String, Value)> = result_rows.into_iter()
-            .flat_map(|row| col_names.iter().cloned().zip(row.values.into_iter()))
+        let results: Vec<_> = table.select_by_similarity(query, k, ef_search).into_iter()
+            .map(|(row, dist)| (row.id, row.values, dist))
             .collect();
+        if let Some(key) = key {
+            self.cache_result(key, Cached::Matches(results.clone()));
+        }
+        Ok(results)
+    }
 
-        // For GROUP BY, return as aggregate results grouped
-        Ok(ExecuteResult::Aggregate { results })
+    /// Page through similarity results: pass `None` for the first page, then
+    /// the returned `next` cursor to get the following `k` rows without repeats.
+    pub fn search_similar_after(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+        cursor: Option<SearchCursor>,
+    ) -> Result<SearchPage> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        let (results, next) = table.select_by_similarity_after(query, k, cursor);
+
+        Ok(SearchPage {
+            results: results.into_iter()
+                .map(|(row, dist)| (row.id, row.values, dist))
+                .collect(),
+            next,
+        })
     }
 
-    /// Helper to match HAVING clause against grouped results
-    fn matches_having(&self, row: &Row, col_names: &[String], having: &WhereClause, table: &Table) -> bool {
-        if having.conditions.is_empty() {
-            return true;
-        }
+    /// Best `k` matches for each distinct value of `group_column`, e.g. the
+    /// top chunks per document, in one exact pass over the table
+    pub fn search_similar_grouped(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+        group_column: &str,
+    ) -> Result<Vec<SearchGroup>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-        let mut result = self.matches_having_condition(row, col_names, &having.conditions[0], table);
+        table.select_by_similarity_grouped(query, k, group_column)
+    }
 
-        for (i, connector) in having.connectors.iter().enumerate() {
-            let cond_result = self.matches_having_condition(row, col_names, &having.conditions[i + 1], table);
-            result = match connector {
-                BoolConnector::And => result && cond_result,
-                BoolConnector::Or => result || cond_result,
-            };
+    /// Touch a table's vector index (adjacency lists and vectors) so the
+    /// first queries after opening are not slowed by page faults. Returns
+    /// the bytes touched.
+    pub fn warmup(&self, table_name: &str) -> Result<usize> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        Ok(table.warmup())
+    }
+
+    /// Check every table's vector index for parts that searches cannot
+    /// reach and bridge them to the rest of the graph with a few edges.
+    /// Indexes read from a regular file are rebuilt from the rows, but
+    /// sealed snapshots keep the graph they were exported with, islands
+    /// included. Returns the number of edges added.
+    pub fn bridge_components(&mut self) -> usize {
+        let added: usize = self.tables.values_mut().map(Table::bridge_components).sum();
+        if added > 0 {
+            self.memory_used = None;
+            if let Some(cache) = &self.result_cache {
+                cache.borrow_mut().clear();
+            }
         }
+        added
+    }
 
-        result
+    /// Builder form of `bridge_components`, to repair a database as it is
+    /// opened: `Database::open_readonly_mmap(path)?.with_component_repair()`
+    pub fn with_component_repair(mut self) -> Self {
+        self.bridge_components();
+        self
+    }
+
+    /// Degree distribution, connected components and mean neighbor distance
+    /// of a table's vector index. Several components or unreachable nodes
+    /// explain rows that similarity searches never return; REINDEX rebuilds
+    /// a connected graph.
+    pub fn graph_stats(&self, table_name: &str) -> Result<GraphStats> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        Ok(table.graph_stats())
     }
 
-    fn matches_having_condition(&self, row: &Row, col_names: &[String], cond: &Condition, _table: &Table) -> bool {
-        // Find column index in the result row
-        let idx = col_names.iter().position(|n| n == &cond.column);
-        if idx.is_none() {
-            return false;
+    /// Wrap a `VectorDB` as an in-memory database with one table, keeping
+    /// its index. `schema` needs one VECTOR column of the same dimension;
+    /// each vector becomes the row whose id is its node ID + 1, with the
+    /// other columns NULL until set with UPDATE. The `VectorDB` must use a
+    /// metric tables know: `Euclidean`, `Cosine`, `DotProduct`, `Manhattan`
+    /// or `Hamming`.
+    pub fn from_vector_db<D: Distance<f32>>(schema: Schema, vectors: &VectorDB<f32, D>) -> Result<Self> {
+        let metric = D::NAME.ok_or_else(|| MarsError::InvalidConfig(
+            "the VectorDB's distance has no metric name tables can use".into(),
+        ))?;
+        let graph = vectors.graph();
+        let name = schema.name.clone();
+        let mut table = Table::with_metric(schema, graph.config().clone(), metric)?;
+        let dimension = table.schema.get_vector_dimension();
+        if dimension != Some(graph.dimension()) {
+            return Err(MarsError::DimensionMismatch { expected: graph.dimension(), actual: dimension.unwrap_or(0) });
+        }
+        let vector_idx = table.vector_column_index()
+            .ok_or_else(|| MarsError::InvalidConfig("Table must have a VECTOR column".into()))?;
+        let id_idx = table.row_id_column();
+        if let Some(column) = table.schema.columns.iter().enumerate()
+            .find(|(idx, column)| !column.nullable && *idx != vector_idx && Some(*idx) != id_idx)
+            .map(|(_, column)| column)
+        {
+            return Err(MarsError::ConstraintViolation(format!("column '{}' is NOT NULL but has no values", column.name)));
         }
-        let idx = idx.unwrap();
-        let row_val = &row.values[idx];
 
-        match &cond.value {
-            ConditionValue::Single(value) => {
-                match cond.operator {
-                    ComparisonOp::Eq => self.values_equal_for_having(row_val, value),
-                    ComparisonOp::Ne => !self.values_equal_for_having(row_val, value),
-                    ComparisonOp::Gt => self.values_compare_for_having(row_val, value) == Some(std::cmp::Ordering::Greater),
-                    ComparisonOp::Ge => self.values_compare_for_having(row_val, value).map(|o| o != std::cmp::Ordering::Less).unwrap_or(false),
-                    ComparisonOp::Lt => self.values_compare_for_having(row_val, value) == Some(std::cmp::Ordering::Less),
-                    ComparisonOp::Le => self.values_compare_for_having(row_val, value).map(|o| o != std::cmp::Ordering::Greater).unwrap_or(false),
-                    _ => true,
+        let rows: Vec<(Row, NodeId)> = graph.node_ids()
+            .map(|node| {
+                let id = node as u64 + 1;
+                let mut values = vec![Value::Null; table.schema.columns.len()];
+                values[vector_idx] = Value::Vector(graph.vector(node).unwrap_or_default().to_vec());
+                if let Some(idx) = id_idx {
+                    values[idx] = Value::Integer(id as i64);
                 }
-            }
-            _ => true,
+                (Row::new(id, values), node)
+            })
+            .collect();
+        table.next_id = rows.last().map_or(1, |(row, _)| row.id + 1);
+        table.graph = graph.with_marker();
+        for (row, node) in rows {
+            table.restore_indexed_row(row, Some(node));
         }
-    }
 
-    fn values_equal_for_having(&self, a: &Value, b: &Value) -> bool {
-        match (a, b) {
-            (Value::Integer(i1), Value::Integer(i2)) => i1 == i2,
-            (Value::Float(f1), Value::Float(f2)) => (f1 - f2).abs() < 1e-10,
-            (Value::Text(s1), Value::Text(s2)) => s1 == s2,
-            (Value::Integer(i), Value::Float(f)) => (*i as f64 - f).abs() < 1e-10,
-            (Value::Float(f), Value::Integer(i)) => (*f - *i as f64).abs() < 1e-10,
-            _ => false,
-        }
+        let mut db = Database::in_memory();
+        db.tables.insert(name, table);
+        Ok(db)
     }
 
-    fn values_compare_for_having(&self, a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
-        match (a, b) {
-            (Value::Integer(i1), Value::Integer(i2)) => i1.partial_cmp(i2),
-            (Value::Float(f1), Value::Float(f2)) => f1.partial_cmp(f2),
-            (Value::Integer(i), Value::Float(f)) => (*i as f64).partial_cmp(f),
-            (Value::Float(f), Value::Integer(i)) => f.partial_cmp(&(*i as f64)),
-            (Value::Text(s1), Value::Text(s2)) => s1.partial_cmp(s2),
-            _ => None,
+    /// A table's vector index as a standalone `VectorDB`, for raw ANN
+    /// searches next to SQL over the rows. The index is a copy: later
+    /// writes to either side don't reach the other. `D` must be the
+    /// table's metric.
+    pub fn vector_index<D: Distance<f32>>(&self, table_name: &str) -> Result<TableIndex<D>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+        if D::NAME != Some(table.metric()) {
+            return Err(MarsError::InvalidConfig(format!(
+                "table '{}' uses the {} metric, not {}", table_name, table.metric(), D::NAME.unwrap_or("a custom one"),
+            )));
         }
+        let graph = table.graph.clone().with_marker::<D>();
+        let rows = graph.node_ids()
+            .filter_map(|node| Some((node, table.node_row(node)?)))
+            .collect();
+        Ok(TableIndex { vectors: VectorDB::from_graph(graph), rows })
     }
 
-    fn update(
-        &mut self,
-        table_name: String,
-        assignments: Vec<(String, Value)>,
-        where_clause: Option<&crate::parser::WhereClause>,
-    ) -> Result<ExecuteResult> {
-        let table = self.tables.get_mut(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
+    /// Exact brute-force similarity search: scans every vector instead of
+    /// walking the graph, for ground-truth results on small tables or evaluation
+    pub fn search_exact(
+        &self,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-        let count = table.update(&assignments, where_clause)?;
-        Ok(ExecuteResult::Update { count })
+        let results = table.select_by_similarity_exact(query, k);
+
+        Ok(results.into_iter()
+            .map(|(row, dist)| (row.id, row.values, dist))
+            .collect())
     }
 
-    fn delete(
-        &mut self,
-        table_name: String,
-        where_clause: Option<&crate::parser::WhereClause>,
-    ) -> Result<ExecuteResult> {
-        let table = self.tables.get_mut(&table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", table_name)))?;
+    /// Exact search with distances computed on the GPU
+    #[cfg(feature = "gpu")]
+    pub fn search_exact_gpu(
+        &self,
+        gpu: &crate::gpu::GpuDistance,
+        table_name: &str,
+        query: &[f32],
+        k: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+
+        let results = table.select_by_similarity_gpu(gpu, query, k)?;
 
-        let count = table.delete(where_clause)?;
-        Ok(ExecuteResult::Delete { count })
+        Ok(results.into_iter()
+            .map(|(row, dist)| (row.id, row.values, dist))
+            .collect())
     }
 
-    fn show_tables(&self) -> Result<ExecuteResult> {
-        let tables: Vec<TableInfo> = self.tables.values()
-            .map(|t| TableInfo {
-                name: t.name().to_string(),
-                rows: t.len(),
-                dimension: t.schema.get_vector_dimension().unwrap_or(0),
-            })
-            .collect();
+    /// Direct MaxSim search over a multi-vector column
+    pub fn search_max_sim(
+        &self,
+        table_name: &str,
+        column: &str,
+        query: &[Vec<f32>],
+        k: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-        Ok(ExecuteResult::ShowTables { tables })
+        let results = table.select_by_max_sim(column, query, k)?;
+
+        Ok(results.into_iter()
+            .map(|(row, dist)| (row.id, row.values, dist))
+            .collect())
     }
 
-    /// Execute JOIN using hash join algorithm O(n+m)
-    fn execute_join(
+    /// Direct sparse vector search over a SPARSE_VECTOR column
+    pub fn search_sparse(
         &self,
-        left_table_name: String,
-        right_table_name: String,
-        join_type: JoinType,
-        left_column: String,
-        right_column: String,
-        columns: Vec<JoinColumn>,
-        where_clause: Option<&WhereClause>,
-        order_by: Option<&OrderBy>,
-        limit: Option<usize>,
-        offset: Option<usize>,
-    ) -> Result<ExecuteResult> {
-        use std::collections::HashMap as StdHashMap;
-
-        let left_table = self.tables.get(&left_table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", left_table_name)))?;
-        let right_table = self.tables.get(&right_table_name)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Table '{}' does not exist", right_table_name)))?;
-
-        // Get column indices
-        let left_col_idx = left_table.column_index(&left_column)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Column '{}' not found in table '{}'", left_column, left_table_name)))?;
-        let right_col_idx = right_table.column_index(&right_column)
-            .ok_or_else(|| MarsError::InvalidFormat(format!("Column '{}' not found in table '{}'", right_column, right_table_name)))?;
-
-        // Build phase: Create hash map from right table (smaller table ideally)
-        // Key: join column value as string, Value: list of rows
-        let mut right_hash: StdHashMap<String, Vec<&Row>> = StdHashMap::new();
-        for row in right_table.rows.values() {
-            if let Some(val) = row.values.get(right_col_idx) {
-                let key = Table::value_to_string(val);
-                right_hash.entry(key).or_default().push(row);
-            }
-        }
-
-        // Probe phase: For each row in left table, look up in hash map
-        let mut result_rows: Vec<Row> = Vec::new();
+        table_name: &str,
+        column: &str,
+        query: &[(u32, f32)],
+        k: usize,
+    ) -> Result<Vec<(u64, Vec<Value>, f32)>> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
 
-        for left_row in left_table.rows.values() {
-            let left_key = left_row.values.get(left_col_idx)
-                .map(|v| Table::value_to_string(v))
-                .unwrap_or_default();
+        let results = table.select_by_sparse_similarity(column, query, k)?;
 
-            let matching_right_rows = right_hash.get(&left_key);
+        Ok(results.into_iter()
+            .map(|(row, dist)| (row.id, row.values, dist))
+            .collect())
+    }
 
-            match join_type {
-                JoinType::Inner => {
-                    if let Some(right_rows) = matching_right_rows {
-                        for right_row in right_rows {
-                            let joined = self.create_joined_row(
-                                left_row, right_row,
-                                left_table, right_table,
-                                &columns,
-                                &left_table_name, &right_table_name,
-                            );
-                            result_rows.push(joined);
-                        }
-                    }
-                }
-                JoinType::Left => {
-                    if let Some(right_rows) = matching_right_rows {
-                        for right_row in right_rows {
-                            let joined = self.create_joined_row(
-                                left_row, right_row,
-                                left_table, right_table,
-                                &columns,
-                                &left_table_name, &right_table_name,
-                            );
-                            result_rows.push(joined);
-                        }
-                    } else {
-                        // No match - include left row with NULLs for right columns
-                        let joined = self.create_joined_row_with_nulls(
-                            left_row,
-                            left_table, right_table,
-                            &columns,
-                            &left_table_name, &right_table_name,
-                        );
-                        result_rows.push(joined);
-                    }
-                }
-                JoinType::Right => {
-                    if let Some(right_rows) = matching_right_rows {
-                        for right_row in right_rows {
-                            let joined = self.create_joined_row(
-                                left_row, right_row,
-                                left_table, right_table,
-                                &columns,
-                                &left_table_name, &right_table_name,
-                            );
-                            result_rows.push(joined);
-                        }
-                    }
+    fn execute_command(&mut self, command: Command) -> Result<ExecuteResult> {
+        if command.is_write() {
+            self.check_writable()?;
+            if let Some(table) = command.target_table() {
+                self.invalidate_results(table);
+                for dependent in engine::dependent_tables(&self.tables, table) {
+                    self.invalidate_results(&dependent);
                 }
             }
         }
-
-        // For RIGHT JOIN, also include unmatched right rows
-        if join_type == JoinType::Right {
-            let mut left_matched: StdHashMap<String, bool> = StdHashMap::new();
-            for left_row in left_table.rows.values() {
-                if let Some(val) = left_row.values.get(left_col_idx) {
-                    let key = Table::value_to_string(val);
-                    left_matched.insert(key, true);
+        let cache_key = self.result_cache.as_ref().and_then(|_| CacheKey::for_select(&command, self.ef_search));
+        if let Some(Cached::Result(result)) = cache_key.as_ref().and_then(|key| self.cached_result(key)) {
+            self.last_stats = Some(QueryStats {
+                rows_scanned: 0,
+                rows_returned: result_len(&result),
+                index: Some("result cache".to_string()),
+            });
+            return Ok(result);
+        }
+        if matches!(command, Command::Update { .. } | Command::Delete { .. } | Command::DropTable { .. } | Command::AlterTable { .. } | Command::CreateProjection { .. } | Command::Reindex { .. } | Command::Vacuum { .. }) {
+            self.memory_used = None;
+        }
+        match command {
+            Command::CreateTable { name, columns, metric, normalize, columnar } => {
+                self.create_table(name, columns, metric, normalize, columnar)
+            }
+            Command::DropTable { name, if_exists } => {
+                engine::drop_table(&mut self.tables, name, if_exists)
+            }
+            Command::AlterTable { name, action } => {
+                engine::alter_table(&mut self.tables, name, action)
+            }
+            Command::CreateMaterializedView { name, select } => {
+                engine::create_view(&mut self.tables, name, &select)
+            }
+            Command::DropMaterializedView { name, if_exists } => {
+                engine::drop_view(&mut self.tables, name, if_exists)
+            }
+            Command::CreateProjection { name, table, column, dimension, method, sample } => {
+                engine::create_projection(&mut self.tables, name, table, &column, dimension, method, sample)
+            }
+            Command::Insert { table, columns, values, on_conflict } => {
+                self.insert_multi(table, columns, values, on_conflict)
+            }
+            Command::InsertSelect { table, source_database, source } => {
+                self.insert_select(table, source_database, source)
+            }
+            Command::Select { table, columns, where_clause, group_by, having, order_by, limit, offset, distinct, as_of, diversity, score } => {
+                take_nodes_visited();
+                let query = SelectQuery {
+                    columns,
+                    where_clause: where_clause.as_ref(),
+                    group_by: group_by.as_deref(),
+                    having: having.as_ref(),
+                    order_by: order_by.as_ref(),
+                    limit,
+                    offset,
+                    distinct,
+                    as_of,
+                    diversity: diversity.as_ref(),
+                    score: score.as_ref(),
+                };
+                let result = self.select(&table, query)?;
+                self.last_stats = Some(self.select_stats(&table, where_clause.as_ref(), as_of.is_some(), &result));
+                if let Some(key) = cache_key {
+                    self.cache_result(key, Cached::Result(result.clone()));
                 }
+                Ok(result)
+            }
+            Command::Update { table, assignments, where_clause } => {
+                engine::update(&mut self.tables, &table, &assignments, where_clause.as_ref())
             }
-            for right_row in right_table.rows.values() {
-                let right_key = right_row.values.get(right_col_idx)
-                    .map(|v| Table::value_to_string(v))
-                    .unwrap_or_default();
-                if !left_matched.contains_key(&right_key) {
-                    let joined = self.create_joined_row_left_nulls(
-                        right_row,
-                        left_table, right_table,
-                        &columns,
-                        &left_table_name, &right_table_name,
-                    );
-                    result_rows.push(joined);
+            Command::Delete { table, where_clause, soft } => {
+                engine::delete(&mut self.tables, &table, where_clause.as_ref(), soft)
+            }
+            Command::ShowTables => {
+                self.show_tables()
+            }
+            Command::Set { name, value } => self.set_option(name, value),
+            Command::Pragma { name, value } => self.pragma(name, value),
+            Command::Attach { path, name } => self.attach(path, name),
+            Command::Explain { statement } => engine::explain(&self.tables, *statement),
+            Command::Analyze { table } => engine::analyze(&mut self.tables, table),
+            Command::Vacuum { table } => engine::vacuum(&mut self.tables, table),
+            Command::Reindex { table } => {
+                let threads = self.thread_count();
+                engine::reindex(&mut self.tables, table, threads)
+            }
+            Command::CheckRecall { table, sample, k } => engine::check_recall(&self.tables, table, sample, k, self.ef_search),
+            Command::BeginBulkLoad => engine::begin_bulk_load(&mut self.tables, &mut self.bulk_load),
+            Command::EndBulkLoad => {
+                let threads = self.thread_count();
+                let result = engine::end_bulk_load(&mut self.tables, &mut self.bulk_load, threads)?;
+                if let Some(cache) = &self.result_cache {
+                    for name in self.tables.keys() {
+                        cache.borrow_mut().invalidate(name);
+                    }
                 }
+                Ok(result)
+            }
+            Command::LastInsertRowid => Ok(engine::last_insert_rowid(self.last_insert_id)),
+            Command::Detach { name } => {
+                self.attached.remove(&name).ok_or_else(|| MarsError::InvalidConfig(format!("No attached database '{}'", name)))?;
+                Ok(ExecuteResult::Detach { name })
+            }
+            Command::Join { left_table, right_table, join_type, left_column, right_column, columns, where_clause, order_by, limit, offset } => {
+                let scanned = [&left_table, &right_table].iter()
+                    .filter_map(|name| self.tables.get(*name))
+                    .map(Table::len)
+                    .sum();
+                let result = engine::join(&self.tables, left_table, right_table, join_type, left_column, right_column, columns, where_clause.as_ref(), order_by.as_ref(), limit, offset)?;
+                self.last_stats = Some(QueryStats {
+                    rows_scanned: scanned,
+                    rows_returned: result_len(&result),
+                    index: Some("hash join".to_string()),
+                });
+                Ok(result)
             }
         }
+    }
 
-        // Apply WHERE clause if present
-        if let Some(wc) = where_clause {
-            // For joined rows, we need to handle table.column references
-            result_rows = result_rows.into_iter()
-                .filter(|row| self.matches_join_where(row, wc))
-                .collect();
-        }
-
-        // Apply ORDER BY
-        if let Some(ob) = order_by {
-            result_rows.sort_by(|a, b| {
-                // Find column index for ordering - simplified, just sort by first column
-                let a_val = a.values.get(0).unwrap_or(&Value::Null);
-                let b_val = b.values.get(0).unwrap_or(&Value::Null);
-                let cmp = Table::value_to_string(a_val).cmp(&Table::value_to_string(b_val));
-                if ob.ascending { cmp } else { cmp.reverse() }
-            });
+    /// Statistics for a finished SELECT, following the same choice of
+    /// search path as `select`
+    fn select_stats(&self, table_name: &str, where_clause: Option<&WhereClause>, as_of: bool, result: &ExecuteResult) -> QueryStats {
+        let Some(table) = self.tables.get(table_name) else {
+            // A materialized view: its groups are read, not the base table
+            return QueryStats {
+                rows_scanned: result_len(result),
+                rows_returned: result_len(result),
+                index: Some("materialized view".to_string()),
+            };
+        };
+        if as_of {
+            return QueryStats {
+                rows_scanned: table.len() + table.tombstones.len(),
+                rows_returned: result_len(result),
+                index: None,
+            };
         }
 
-        // Apply OFFSET
-        let mut result_rows = if let Some(n) = offset {
-            result_rows.into_iter().skip(n).collect()
-        } else {
-            result_rows
+        let access = table.plan(where_clause).access;
+        let rows_scanned = match &access {
+            AccessPath::VectorIndex { .. } => take_nodes_visited(),
+            AccessPath::RowIdLookup { ids } | AccessPath::UniqueIndex { ids, .. } => ids.len(),
+            _ => table.len(),
         };
+        QueryStats { rows_scanned, rows_returned: result_len(result), index: access.index_name() }
+    }
 
-        // Apply LIMIT
-        if let Some(n) = limit {
-            result_rows.truncate(n);
+    fn create_table(
+        &mut self,
+        name: String,
+        columns: Vec<crate::parser::ColumnDef>,
+        metric: Option<String>,
+        normalize: bool,
+        columnar: bool,
+    ) -> Result<ExecuteResult> {
+        let mut table = engine::new_table(&self.tables, &self.config, &name, columns, metric, normalize, columnar)?;
+        if self.bulk_load {
+            table.defer_index();
         }
-
-        Ok(ExecuteResult::Select { rows: result_rows })
+        self.tables.insert(name.clone(), table);
+        Ok(ExecuteResult::CreateTable { name })
     }
 
-    /// Create a joined row from left and right rows
-    fn create_joined_row(
-        &self,
-        left_row: &Row,
-        right_row: &Row,
-        left_table: &Table,
-        right_table: &Table,
-        columns: &[JoinColumn],
-        left_table_name: &str,
-        right_table_name: &str,
-    ) -> Row {
-        let mut values = Vec::new();
-
-        for col in columns {
-            match col {
-                JoinColumn::All => {
-                    // Add all columns from left table
-                    for val in &left_row.values {
-                        values.push(val.clone());
-                    }
-                    // Add all columns from right table
-                    for val in &right_row.values {
-                        values.push(val.clone());
-                    }
-                }
-                JoinColumn::TableColumn { table, column } => {
-                    if table.to_lowercase() == left_table_name.to_lowercase() {
-                        if let Some(idx) = left_table.column_index(column) {
-                            values.push(left_row.values.get(idx).cloned().unwrap_or(Value::Null));
-                        } else {
-                            values.push(Value::Null);
-                        }
-                    } else if table.to_lowercase() == right_table_name.to_lowercase() {
-                        if let Some(idx) = right_table.column_index(column) {
-                            values.push(right_row.values.get(idx).cloned().unwrap_or(Value::Null));
-                        } else {
-                            values.push(Value::Null);
-                        }
-                    } else {
-                        values.push(Value::Null);
-                    }
-                }
+    fn select(&self, table_name: &str, query: SelectQuery) -> Result<ExecuteResult> {
+        let view;
+        let table = match self.tables.get(table_name) {
+            Some(table) => table,
+            None => {
+                view = engine::view(&self.tables, table_name).ok_or_else(|| MarsError::TableNotFound(table_name.to_string()))?;
+                &view
             }
-        }
+        };
+        let ef_search = self.ef_search.unwrap_or_else(|| table.graph_config().search_buffer.max(DEFAULT_EF_SEARCH));
+        engine::select(table, query, ef_search)
+    }
 
-        Row::new(0, values)
+    fn insert_multi(
+        &mut self,
+        table_name: String,
+        columns: Vec<String>,
+        values: Vec<Vec<Value>>,
+        on_conflict: OnConflict,
+    ) -> Result<ExecuteResult> {
+        let mut budget = self.take_memory_budget();
+        let hooks = InsertHooks { budget: budget.as_mut(), progress: Some(&mut self.progress) };
+        let result = engine::insert(&mut self.tables, &table_name, &columns, values, on_conflict, hooks)?;
+        self.last_insert_id = engine::inserted_id(&result).or(self.last_insert_id);
+        self.memory_used = budget.map(|(_, used)| used);
+        Ok(result)
     }
 
-    /// Create a joined row with NULLs for right table columns (LEFT JOIN no match)
-    fn create_joined_row_with_nulls(
-        &self,
-        left_row: &Row,
-        left_table: &Table,
-        right_table: &Table,
-        columns: &[JoinColumn],
-        left_table_name: &str,
-        right_table_name: &str,
-    ) -> Row {
-        let mut values = Vec::new();
-
-        for col in columns {
-            match col {
-                JoinColumn::All => {
-                    // Add all columns from left table
-                    for val in &left_row.values {
-                        values.push(val.clone());
-                    }
-                    // Add NULLs for right table columns
-                    for _ in &right_table.schema.columns {
-                        values.push(Value::Null);
-                    }
-                }
-                JoinColumn::TableColumn { table, column } => {
-                    if table.to_lowercase() == left_table_name.to_lowercase() {
-                        if let Some(idx) = left_table.column_index(column) {
-                            values.push(left_row.values.get(idx).cloned().unwrap_or(Value::Null));
-                        } else {
-                            values.push(Value::Null);
-                        }
-                    } else {
-                        // Right table column - NULL
-                        values.push(Value::Null);
-                    }
-                }
+    /// INSERT INTO table SELECT * FROM [database.]source, merging the rows
+    /// as `merge_from` does
+    fn insert_select(&mut self, table_name: String, source_database: Option<String>, source: String) -> Result<ExecuteResult> {
+        let other = match &source_database {
+            Some(name) => self.attached.get(name)
+                .ok_or_else(|| MarsError::InvalidConfig(format!("No attached database '{}'", name)))?,
+            None => self,
+        };
+        let source_table = other.tables.get(&source)
+            .ok_or_else(|| MarsError::TableNotFound(source.clone()))?;
+        let segment = engine::insert_select(&self.tables, &table_name, source_table)?;
+
+        let mut budget = self.take_memory_budget();
+        let threads = self.thread_count();
+        let table = self.tables.get_mut(&table_name).unwrap();
+        if let Some(budget) = &mut budget {
+            for row_values in &segment.rows {
+                reserve_memory(table, row_values, budget)?;
             }
         }
-
-        Row::new(0, values)
+        let ids = table.merge_segment(segment, threads)?;
+        self.last_insert_id = ids.last().copied().or(self.last_insert_id);
+        self.memory_used = budget.map(|(_, used)| used);
+        Ok(ExecuteResult::Insert { id: ids.last().copied().unwrap_or(0) })
     }
 
-    /// Create a joined row with NULLs for left table columns (RIGHT JOIN no match)
-    fn create_joined_row_left_nulls(
-        &self,
-        right_row: &Row,
-        left_table: &Table,
-        right_table: &Table,
-        columns: &[JoinColumn],
-        left_table_name: &str,
-        right_table_name: &str,
-    ) -> Row {
-        let mut values = Vec::new();
-
-        for col in columns {
-            match col {
-                JoinColumn::All => {
-                    // Add NULLs for left table columns
-                    for _ in &left_table.schema.columns {
-                        values.push(Value::Null);
-                    }
-                    // Add all columns from right table
-                    for val in &right_row.values {
-                        values.push(val.clone());
-                    }
-                }
-                JoinColumn::TableColumn { table, column } => {
-                    if table.to_lowercase() == right_table_name.to_lowercase() {
-                        if let Some(idx) = right_table.column_index(column) {
-                            values.push(right_row.values.get(idx).cloned().unwrap_or(Value::Null));
-                        } else {
-                            values.push(Value::Null);
-                        }
-                    } else {
-                        // Left table column - NULL
-                        values.push(Value::Null);
-                    }
-                }
-            }
+    /// ATTACH DATABASE 'path' AS name
+    fn attach(&mut self, path: String, name: String) -> Result<ExecuteResult> {
+        if self.attached.contains_key(&name) {
+            return Err(MarsError::InvalidConfig(format!("Database '{}' is already attached", name)));
         }
-
-        Row::new(0, values)
+        let other = Database::open_readonly(&path)?;
+        self.attached.insert(name.clone(), other);
+        Ok(ExecuteResult::Attach { name })
     }
 
-    /// Check if a joined row matches a WHERE clause
-    fn matches_join_where(&self, _row: &Row, _where_clause: &WhereClause) -> bool {
-        // Simplified - always returns true for now
-        // Full implementation would need to handle table.column references
-        true
+    fn show_tables(&self) -> Result<ExecuteResult> {
+        let tables: Vec<TableInfo> = self.tables.values()
+            .map(|t| TableInfo {
+                name: t.name().to_string(),
+                rows: t.len(),
+                dimension: t.schema.get_vector_dimension().unwrap_or(0),
+            })
+            .collect();
+
+        Ok(ExecuteResult::ShowTables { tables })
     }
 
     /// Get table names
@@ -1053,45 +2042,154 @@ impl Database {
     }
 }
 
-/// Result of executing a command
-#[derive(Debug)]
+/// Result of executing a command. Serializes as an object whose `type`
+/// names the variant in snake case (`"select_similar"`), next to its fields.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum ExecuteResult {
     CreateTable { name: String },
     DropTable { name: String },
+    CreateView { name: String },
+    DropView { name: String },
+    /// `retained` is the share of the sampled vectors' squared length a
+    /// PCA projection keeps
+    CreateProjection { name: String, table: String, retained: Option<f64> },
+    /// `rebuilt` is true if the vector index was rebuilt for the new settings
+    AlterTable { name: String, rebuilt: bool },
     Insert { id: u64 },
-    Select { rows: Vec<Row> },
-    SelectSimilar { results: Vec<(Row, f32)> },
+    /// INSERT OR IGNORE whose rows all conflicted, so none was written
+    Ignored { count: usize },
+    Select { rows: Vec<Row>, schema: ResultSchema },
+    /// Rows with their distance to the query, closest first. `metric`
+    /// names how distances were measured: the table's metric, or `dot` and
+    /// `maxsim` for sparse and multi-vector columns.
+    SelectSimilar { results: Vec<(Row, f32)>, schema: ResultSchema, metric: String },
     Aggregate { results: Vec<(String, Value)> },
     Update { count: usize },
     Delete { count: usize },
     ShowTables { tables: Vec<TableInfo> },
+    Set { name: String, value: Value },
+    /// Value of a setting after a PRAGMA
+    Pragma { name: String, value: Value },
+    Attach { name: String },
+    Detach { name: String },
+    /// How a statement would find its rows
+    Explain { plan: QueryPlan },
+    /// Tables whose statistics were collected
+    Analyze { tables: Vec<String> },
+    /// Number of earlier row versions dropped by VACUUM
+    Vacuum { versions: usize },
+    /// Graph search quality measured by CHECK RECALL
+    CheckRecall { table: String, report: RecallReport },
+    /// Expected recall of a table switched to INT8 vector storage
+    Calibrate { table: String, report: CalibrationReport },
+    /// Edge statistics of a table's vector index before and after REINDEX
+    Reindex { table: String, before: EdgeStats, after: EdgeStats },
+    BeginBulkLoad,
+    /// Rows whose vectors END BULK LOAD indexed
+    EndBulkLoad { indexed: usize },
+    /// A write queued inside a transaction; `index` is its position in the
+    /// results returned by `commit()`
+    Queued { index: usize },
+}
+
+/// Number of rows (or aggregate values) in a query result
+fn result_len(result: &ExecuteResult) -> usize {
+    match result {
+        ExecuteResult::Select { rows, .. } => rows.len(),
+        ExecuteResult::SelectSimilar { results, .. } => results.len(),
+        ExecuteResult::Aggregate { results } => results.len(),
+        _ => 0,
+    }
+}
+
+impl ExecuteResult {
+    /// Column metadata of a row-returning result
+    pub fn schema(&self) -> Option<&ResultSchema> {
+        match self {
+            ExecuteResult::Select { schema, .. } | ExecuteResult::SelectSimilar { schema, .. } => Some(schema),
+            _ => None,
+        }
+    }
+
+    /// Rows with typed, by-name column access; empty for results
+    /// without rows
+    pub fn rows(&self) -> Vec<ResultRow<'_>> {
+        match self {
+            ExecuteResult::Select { rows, schema } => rows.iter()
+                .map(|row| ResultRow { row, distance: None, score: None, schema })
+                .collect(),
+            ExecuteResult::SelectSimilar { results, schema, metric } => results.iter()
+                .map(|(row, dist)| {
+                    let score = Some(distance::metric_score(metric, *dist).1);
+                    ResultRow { row, distance: Some(*dist), score, schema }
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 /// Table information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TableInfo {
     pub name: String,
     pub rows: usize,
     pub dimension: usize,
 }
 
+/// A table's vector index as a `VectorDB`, from `Database::vector_index`
+pub struct TableIndex<D: Distance<f32>> {
+    vectors: VectorDB<f32, D>,
+    /// Graph node -> row id
+    rows: HashMap<NodeId, u64>,
+}
+
+impl<D: Distance<f32>> TableIndex<D> {
+    pub fn vector_db(&self) -> &VectorDB<f32, D> {
+        &self.vectors
+    }
+
+    /// Row whose vector is `node`
+    pub fn row_id(&self, node: NodeId) -> Option<u64> {
+        self.rows.get(&node).copied()
+    }
+
+    /// k nearest rows to `query`: (row id, distance), closest first
+    pub fn query(&self, query: &[f32], k: usize) -> Result<Vec<(u64, f32)>> {
+        Ok(self.vectors.query(query, k)?.into_iter()
+            .filter_map(|result| Some((self.row_id(result.id)?, result.distance)))
+            .collect())
+    }
+}
+
 impl std::fmt::Display for ExecuteResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ExecuteResult::CreateTable { name } => write!(f, "Table '{}' created", name),
             ExecuteResult::DropTable { name } => write!(f, "Table '{}' dropped", name),
+            ExecuteResult::CreateView { name } => write!(f, "Materialized view '{}' created", name),
+            ExecuteResult::CreateProjection { name, table, retained: Some(retained) } => {
+                write!(f, "Projection '{}' created on '{}', keeping {:.1}% of the sampled variance", name, table, retained * 100.0)
+            }
+            ExecuteResult::CreateProjection { name, table, retained: None } => write!(f, "Projection '{}' created on '{}'", name, table),
+            ExecuteResult::DropView { name } => write!(f, "Materialized view '{}' dropped", name),
+            ExecuteResult::AlterTable { name, rebuilt: true } => write!(f, "Table '{}' altered, index rebuilt", name),
+            ExecuteResult::AlterTable { name, rebuilt: false } => write!(f, "Table '{}' altered", name),
             ExecuteResult::Insert { id } => write!(f, "Inserted row with id={}", id),
-            ExecuteResult::Select { rows } => {
+            ExecuteResult::Ignored { count } => write!(f, "Skipped {} conflicting row(s)", count),
+            ExecuteResult::Select { rows, .. } => {
                 writeln!(f, "Found {} rows:", rows.len())?;
                 for row in rows {
                     writeln!(f, "  id={}, values={:?}", row.id, row.values)?;
                 }
                 Ok(())
             }
-            ExecuteResult::SelectSimilar { results } => {
-                writeln!(f, "Found {} similar rows:", results.len())?;
+            ExecuteResult::SelectSimilar { results, metric, .. } => {
+                writeln!(f, "Found {} similar rows ({}):", results.len(), metric)?;
                 for (row, dist) in results {
-                    writeln!(f, "  id={}, distance={:.4}, values={:?}", row.id, dist, row.values)?;
+                    let (name, score) = distance::metric_score(metric, *dist);
+                    writeln!(f, "  id={}, {}={:.4}, values={:?}", row.id, name, score, row.values)?;
                 }
                 Ok(())
             }
@@ -1111,6 +2209,27 @@ impl std::fmt::Display for ExecuteResult {
                 }
                 Ok(())
             }
+            ExecuteResult::Queued { index } => write!(f, "Queued as transaction operation #{}", index),
+            ExecuteResult::Set { name, value } => write!(f, "Set {} = {:?}", name, value),
+            ExecuteResult::Pragma { name, value } => write!(f, "{} = {:?}", name, value),
+            ExecuteResult::Attach { name } => write!(f, "Attached database '{}'", name),
+            ExecuteResult::Detach { name } => write!(f, "Detached database '{}'", name),
+            ExecuteResult::Explain { plan } => write!(f, "{}", plan),
+            ExecuteResult::Analyze { tables } => write!(f, "Analyzed {} table(s)", tables.len()),
+            ExecuteResult::Vacuum { versions } => write!(f, "Dropped {} row version(s)", versions),
+            ExecuteResult::CheckRecall { table, report } => write!(f,
+                "Recall@{} on '{}' over {} queries: {:.3} (worst {:.3}), graph {:.3} ms, exact {:.3} ms per query",
+                report.k, table, report.queries, report.recall, report.min_recall,
+                report.graph_latency.as_secs_f64() * 1000.0, report.exact_latency.as_secs_f64() * 1000.0),
+            ExecuteResult::Calibrate { table, report } => write!(f,
+                "Table '{}' stores INT8 vectors, calibrated on {} vectors: estimated recall@{} {:.3} (worst {:.3}) over {} queries, mean error {:.5}",
+                table, report.sampled, report.k, report.recall, report.min_recall, report.queries, report.mean_error),
+            ExecuteResult::Reindex { table, before, after } => write!(f,
+                "Reindexed '{}': {} nodes, {} -> {} edges, mean degree {:.1} -> {:.1}, {} -> {} isolated",
+                table, after.nodes, before.edges, after.edges, before.mean_degree(), after.mean_degree(),
+                before.isolated, after.isolated),
+            ExecuteResult::BeginBulkLoad => write!(f, "Bulk load started"),
+            ExecuteResult::EndBulkLoad { indexed } => write!(f, "Bulk load ended, indexed {} rows", indexed),
         }
     }
 }
@@ -1141,7 +2260,7 @@ mod tests {
         db.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0, 0.0], 'Second');").unwrap();
 
         let result = db.execute("SELECT * FROM docs;").unwrap();
-        assert!(matches!(result, ExecuteResult::Select { rows } if rows.len() == 2));
+        assert!(matches!(result, ExecuteResult::Select { rows, .. } if rows.len() == 2));
     }
 
     #[test]
@@ -1154,4 +2273,261 @@ mod tests {
         let result = db.execute("DELETE FROM docs WHERE id = 1;").unwrap();
         assert!(matches!(result, ExecuteResult::Delete { count: 1 }));
     }
+
+    #[test]
+    fn test_approx_count_distinct() {
+        let mut db = Database::in_memory();
+
+        db.execute("CREATE TABLE docs (embedding VECTOR(1), category TEXT, shard INTEGER);").unwrap();
+        for i in 0..2000 {
+            db.execute(&format!(
+                "INSERT INTO docs (embedding, category, shard) VALUES ([{}.0], 'c{}', {});",
+                i, i % 700, i % 2
+            )).unwrap();
+        }
+
+        let result = db.execute("SELECT APPROX_COUNT(DISTINCT category) FROM docs;").unwrap();
+        match result {
+            ExecuteResult::Aggregate { results } => {
+                assert_eq!(results[0].0, "ApproxCountDistinct(category)");
+                let Value::Integer(n) = results[0].1 else { panic!("Expected integer") };
+                assert!((n - 700).abs() <= 14, "estimate {}", n);
+            }
+            _ => panic!("Expected Aggregate"),
+        }
+
+        // Per group: each shard sees the 350 categories of its parity
+        let result = db.execute("SELECT shard, APPROX_COUNT(DISTINCT category) FROM docs GROUP BY shard;").unwrap();
+        match result {
+            ExecuteResult::Aggregate { results } => {
+                let estimates: Vec<i64> = results.iter()
+                    .filter_map(|(name, v)| match v {
+                        Value::Integer(n) if name.starts_with("Approx") => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                assert_eq!(estimates.len(), 2);
+                assert!(estimates.iter().all(|n| (n - 350).abs() <= 7), "estimates {:?}", estimates);
+            }
+            _ => panic!("Expected Aggregate"),
+        }
+
+        assert!(db.execute("SELECT APPROX_COUNT(category) FROM docs;").is_err());
+    }
+
+    #[test]
+    fn test_vector_average() {
+        let mut db = Database::in_memory();
+
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), category TEXT);").unwrap();
+        db.execute("INSERT INTO docs (embedding, category) VALUES ([1.0, 0.0], 'a');").unwrap();
+        db.execute("INSERT INTO docs (embedding, category) VALUES ([3.0, 2.0], 'a');").unwrap();
+        db.execute("INSERT INTO docs (embedding, category) VALUES ([0.0, 5.0], 'b');").unwrap();
+
+        let result = db.execute("SELECT VEC_AVG(embedding) FROM docs;").unwrap();
+        match result {
+            ExecuteResult::Aggregate { results } => {
+                assert_eq!(results[0].1, Value::Vector(vec![4.0 / 3.0, 7.0 / 3.0]));
+            }
+            _ => panic!("Expected Aggregate"),
+        }
+
+        let result = db.execute(
+            "SELECT category, VEC_AVG(embedding) FROM docs GROUP BY category ORDER BY category;"
+        ).unwrap();
+        match result {
+            ExecuteResult::Aggregate { results } => {
+                assert_eq!(results[0].1, Value::Text("a".to_string()));
+                assert_eq!(results[1].1, Value::Vector(vec![2.0, 1.0]));
+                assert_eq!(results[2].1, Value::Text("b".to_string()));
+                assert_eq!(results[3].1, Value::Vector(vec![0.0, 5.0]));
+            }
+            _ => panic!("Expected Aggregate"),
+        }
+
+        assert!(db.execute("SELECT VEC_AVG(category) FROM docs;").is_err());
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let mut db = Database::in_memory();
+
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), id INTEGER, title TEXT);").unwrap();
+        db.execute("INSERT INTO docs (embedding, id, title) VALUES ([1.0, 0.0], 1, '  Rust ');").unwrap();
+        db.execute("INSERT INTO docs (embedding, id, title) VALUES ([0.0, 1.0], 2, 'Python');").unwrap();
+
+        let result = db.execute(
+            "SELECT id, UPPER(TRIM(title)), CONCAT(SUBSTR(title, 3, 2), '-', id) AS label FROM docs WHERE LOWER(TRIM(title)) = 'rust';"
+        ).unwrap();
+        match result {
+            ExecuteResult::Select { rows, .. } => {
+                assert_eq!(rows.len(), 1);
+                assert_eq!(rows[0].values, vec![
+                    Value::Integer(1),
+                    Value::Text("RUST".to_string()),
+                    Value::Text("Ru-1".to_string()),
+                ]);
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        let result = db.execute("SELECT REPLACE(title, 'th', 'TH') FROM docs WHERE LENGTH(title) = 6;").unwrap();
+        match result {
+            ExecuteResult::Select { rows, .. } => {
+                assert_eq!(rows.len(), 1);
+                assert_eq!(rows[0].values[0], Value::Text("PyTHon".to_string()));
+            }
+            _ => panic!("Expected Select"),
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regexp_filter() {
+        let mut db = Database::in_memory();
+
+        db.execute("CREATE TABLE tickets (embedding VECTOR(2), code TEXT);").unwrap();
+        for code in ["ABC-12", "abc-13", "XYZ-7", "AB-99", "note"] {
+            db.execute(&format!("INSERT INTO tickets (embedding, code) VALUES ([1.0, 0.0], '{}');", code)).unwrap();
+        }
+
+        let codes = |db: &mut Database, sql: &str| -> Vec<Value> {
+            match db.execute(sql).unwrap() {
+                ExecuteResult::Select { rows, .. } => {
+                    let mut codes: Vec<Value> = rows.into_iter().map(|r| r.values[0].clone()).collect();
+                    codes.sort_by_key(|v| format!("{:?}", v));
+                    codes
+                }
+                _ => panic!("Expected Select"),
+            }
+        };
+        let text = |s: &str| Value::Text(s.to_string());
+
+        assert_eq!(
+            codes(&mut db, r"SELECT code FROM tickets WHERE code REGEXP '^[A-Z]{3}-\d+$';"),
+            vec![text("ABC-12"), text("XYZ-7")]
+        );
+        assert_eq!(
+            codes(&mut db, r"SELECT code FROM tickets WHERE code NOT REGEXP '\d';"),
+            vec![text("note")]
+        );
+        assert!(db.execute("SELECT code FROM tickets WHERE code REGEXP '[';").is_err());
+    }
+
+    #[test]
+    fn test_collation() {
+        let mut db = Database::in_memory();
+
+        db.execute(
+            "CREATE TABLE people (embedding VECTOR(2), name TEXT COLLATE NOCASE UNIQUE, city TEXT COLLATE UNICODE-CI, tag TEXT);"
+        ).unwrap();
+        for (name, city, tag) in [("alice", "Zürich", "x"), ("Bob", "ZÜRICH", "X"), ("carol", "Bern", "x")] {
+            db.execute(&format!(
+                "INSERT INTO people (embedding, name, city, tag) VALUES ([1.0, 0.0], '{}', '{}', '{}');", name, city, tag
+            )).unwrap();
+        }
+
+        // NOCASE UNIQUE rejects a value differing only in case
+        assert!(db.execute("INSERT INTO people (embedding, name) VALUES ([1.0, 0.0], 'ALICE');").is_err());
+
+        let count = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+            ExecuteResult::Select { rows, .. } => rows.len(),
+            _ => panic!("Expected Select"),
+        };
+        assert_eq!(count(&mut db, "SELECT * FROM people WHERE name = 'BOB';"), 1);
+        assert_eq!(count(&mut db, "SELECT * FROM people WHERE city = 'zürich';"), 2);
+        assert_eq!(count(&mut db, "SELECT * FROM people WHERE tag = 'x';"), 2);
+
+        match db.execute("SELECT name FROM people ORDER BY name;").unwrap() {
+            ExecuteResult::Select { rows, .. } => {
+                let names: Vec<Value> = rows.into_iter().map(|r| r.values[0].clone()).collect();
+                let text = |s: &str| Value::Text(s.to_string());
+                assert_eq!(names, vec![text("alice"), text("Bob"), text("carol")]);
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        match db.execute("SELECT city, COUNT(*) FROM people GROUP BY city;").unwrap() {
+            ExecuteResult::Aggregate { results } => assert_eq!(results.len(), 4),
+            _ => panic!("Expected Aggregate"),
+        }
+
+        assert!(db.execute("CREATE TABLE t (embedding VECTOR(2), name TEXT COLLATE LATIN1);").is_err());
+    }
+
+    #[test]
+    fn test_distinct_before_limit() {
+        let mut db = Database::in_memory();
+
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), category TEXT COLLATE NOCASE, score INTEGER);").unwrap();
+        for (i, category) in ["a", "a", "A", "b", "a", "c"].iter().enumerate() {
+            db.execute(&format!(
+                "INSERT INTO docs (embedding, category, score) VALUES ([1.0, 0.0], '{}', {});", category, i
+            )).unwrap();
+        }
+
+        // Which of 'a' and 'A' survives depends on row order, so compare lowercased
+        let values = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+            ExecuteResult::Select { rows, .. } => rows.into_iter()
+                .map(|r| r.values.into_iter()
+                    .map(|v| match v {
+                        Value::Text(s) => Value::Text(s.to_lowercase()),
+                        v => v,
+                    })
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            _ => panic!("Expected Select"),
+        };
+        let text = |s: &str| vec![Value::Text(s.to_string())];
+
+        // Duplicates are removed on the selected column, then LIMIT applies
+        assert_eq!(
+            values(&mut db, "SELECT DISTINCT category FROM docs ORDER BY category LIMIT 2;"),
+            vec![text("a"), text("b")]
+        );
+        assert_eq!(
+            values(&mut db, "SELECT DISTINCT category FROM docs ORDER BY category LIMIT 2 OFFSET 1;"),
+            vec![text("b"), text("c")]
+        );
+        assert_eq!(values(&mut db, "SELECT DISTINCT UPPER(category) FROM docs;").len(), 3);
+        assert_eq!(values(&mut db, "SELECT category FROM docs;").len(), 6);
+    }
+
+    #[test]
+    fn test_null_three_valued_logic() {
+        let mut db = Database::in_memory();
+
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), id INTEGER, tag TEXT, score INTEGER);").unwrap();
+        db.execute("INSERT INTO docs (embedding, id, tag, score) VALUES ([1.0, 0.0], 1, 'a', 5);").unwrap();
+        db.execute("INSERT INTO docs (embedding, id, tag, score) VALUES ([1.0, 0.0], 2, 'b', NULL);").unwrap();
+        db.execute("INSERT INTO docs (embedding, id, tag, score) VALUES ([1.0, 0.0], 3, NULL, 7);").unwrap();
+
+        let ids = |db: &mut Database, sql: &str| -> Vec<i64> {
+            match db.execute(sql).unwrap() {
+                ExecuteResult::Select { rows, .. } => {
+                    let mut ids: Vec<i64> = rows.iter().filter_map(|r| r.values[0].as_integer()).collect();
+                    ids.sort();
+                    ids
+                }
+                _ => panic!("Expected Select"),
+            }
+        };
+
+        // Comparisons with NULL are UNKNOWN, so neither side matches
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE tag != 'a';"), vec![2]);
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE tag NOT LIKE 'a%';"), vec![2]);
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE score = NULL;"), Vec::<i64>::new());
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE score IS NULL;"), vec![2]);
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE tag NOT IN ('a', NULL);"), Vec::<i64>::new());
+
+        // UNKNOWN AND false is false, UNKNOWN OR true is true
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE score > 6 OR tag = 'a';"), vec![1, 3]);
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE score < 6 OR id = 2;"), vec![1, 2]);
+
+        assert_eq!(ids(&mut db, "SELECT id FROM docs WHERE COALESCE(score, 0) < 6;"), vec![1, 2]);
+        match db.execute("SELECT IFNULL(tag, 'none') FROM docs WHERE id = 3;").unwrap() {
+            ExecuteResult::Select { rows, .. } => assert_eq!(rows[0].values[0], Value::Text("none".to_string())),
+            _ => panic!("Expected Select"),
+        }
+    }
 }
@@ -1,11 +1,20 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use crate::distance::{Distance, Numeric};
 use crate::error::{MarsError, Result};
-use crate::graph::{Graph, GraphConfig};
+use crate::graph::{CsrGraph, Graph, GraphConfig};
+use crate::interrupt::InterruptHandle;
 use crate::node::{Candidate, NodeId};
-use crate::storage::Storage;
+use crate::storage::{Storage, Synchronous, VectorFile};
+
+use serde::{Deserialize, Serialize};
 
 /// Configuration for the vector database.
 #[derive(Clone, Debug)]
@@ -16,6 +25,17 @@ pub struct Config {
     pub graph: GraphConfig,
     /// Path to database file (None for in-memory)
     pub path: Option<PathBuf>,
+    /// Queries running longer than this fail with `MarsError::Timeout`
+    pub statement_timeout: Option<Duration>,
+    /// How `sync` flushes the file: trade durability for write throughput
+    pub synchronous: Synchronous,
+    /// Threads for internal parallel work such as segment compaction; None
+    /// uses every core
+    pub threads: Option<usize>,
+    /// Build large `insert_batch` calls on a copy of the graph, so
+    /// `query_snapshot` doesn't wait for them. Off by default, since each
+    /// such batch copies the whole graph.
+    pub snapshot_batches: bool,
 }
 
 impl Config {
@@ -24,6 +44,10 @@ impl Config {
             dimension,
             graph: GraphConfig::default(),
             path: None,
+            statement_timeout: None,
+            synchronous: Synchronous::default(),
+            threads: None,
+            snapshot_batches: false,
         }
     }
 
@@ -47,6 +71,31 @@ impl Config {
         self.graph.search_buffer = buffer;
         self
     }
+
+    pub fn with_statement_timeout(mut self, timeout: Duration) -> Self {
+        self.statement_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_synchronous(mut self, synchronous: Synchronous) -> Self {
+        self.synchronous = synchronous;
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    pub fn with_snapshot_batches(mut self) -> Self {
+        self.snapshot_batches = true;
+        self
+    }
+
+    /// Threads parallel work may use: `threads`, or the number of cores
+    pub fn thread_count(&self) -> usize {
+        self.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
 }
 
 /// Search result containing the node ID and distance.
@@ -65,6 +114,19 @@ impl From<Candidate> for SearchResult {
     }
 }
 
+/// Results of `VectorDB::query_snapshot`
+#[derive(Clone, Debug)]
+pub struct SnapshotResults {
+    pub results: Vec<SearchResult>,
+    /// False if a large `insert_batch` was in progress, so the results
+    /// come from the graph as it was before that batch
+    pub fresh: bool,
+}
+
+/// Batches at least this large are built on a copy of the graph so
+/// searches aren't blocked for the duration of the batch
+pub(crate) const SNAPSHOT_MIN_BATCH: usize = 256;
+
 /// The main vector database interface.
 pub struct VectorDB<T, D>
 where
@@ -74,6 +136,10 @@ where
     graph: Arc<RwLock<Graph<T, D>>>,
     storage: Option<Arc<RwLock<Storage>>>,
     config: Config,
+    /// Serializes writers, so a batch built on a copy can't drop other writes
+    writer: Mutex<()>,
+    /// Set while a batch is being built on a copy of the graph
+    ingesting: AtomicBool,
 }
 
 impl<T, D> VectorDB<T, D>
@@ -87,6 +153,8 @@ where
             graph: Arc::new(RwLock::new(Graph::new(dimension, GraphConfig::default()))),
             storage: None,
             config: Config::new(dimension),
+            writer: Mutex::new(()),
+            ingesting: AtomicBool::new(false),
         }
     }
 
@@ -99,31 +167,45 @@ where
             ))),
             storage: None,
             config,
+            writer: Mutex::new(()),
+            ingesting: AtomicBool::new(false),
         }
     }
 
     /// Open or create a persistent vector database.
     pub fn open<P: AsRef<Path>>(path: P, dimension: usize) -> Result<Self> {
+        Self::open_with_config(path, Config::new(dimension))
+    }
+
+    /// Open or create a persistent vector database with custom configuration.
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: Config) -> Result<Self> {
         let path = path.as_ref();
 
         let storage = if path.exists() {
             Storage::open(path)?
         } else {
-            Storage::create(path, dimension as u32)?
+            Storage::create(path, config.dimension as u32)?
         };
 
-        let config = Config::new(dimension).with_path(path);
+        let config = config.with_path(path);
 
         Ok(VectorDB {
-            graph: Arc::new(RwLock::new(Graph::new(dimension, config.graph.clone()))),
+            graph: Arc::new(RwLock::new(Graph::new(config.dimension, config.graph.clone()))),
             storage: Some(Arc::new(RwLock::new(storage))),
             config,
+            writer: Mutex::new(()),
+            ingesting: AtomicBool::new(false),
         })
     }
 
     /// Insert a vector into the database.
     /// Returns the ID of the inserted node.
-    pub fn insert(&self, vector: Vec<T>) -> Result<NodeId> {
+    ///
+    /// The vector is copied straight into the graph's storage, so it can be
+    /// borrowed: a slice of a larger buffer, an `Arc<[T]>` or a `Vec<T>`.
+    pub fn insert(&self, vector: impl AsRef<[T]>) -> Result<NodeId> {
+        let vector = vector.as_ref();
+        let _writer = self.writer.lock().unwrap();
         let mut graph = self.graph.write().unwrap();
 
         if vector.len() != self.config.dimension {
@@ -138,20 +220,33 @@ where
 
     /// Insert multiple vectors in batch.
     /// Returns the IDs of the inserted nodes.
-    pub fn insert_batch(&self, vectors: Vec<Vec<T>>) -> Result<Vec<NodeId>> {
-        let mut graph = self.graph.write().unwrap();
-        let mut ids = Vec::with_capacity(vectors.len());
+    ///
+    /// With `Config::with_snapshot_batches`, large batches are inserted into
+    /// a copy of the graph that replaces the live one when done. Searches
+    /// meanwhile run against the graph as it was before the batch (see
+    /// `query_snapshot`), at the cost of copying the graph for every batch.
+    pub fn insert_batch<V: AsRef<[T]>>(&self, vectors: Vec<V>) -> Result<Vec<NodeId>> {
+        if let Some(vector) = vectors.iter().map(AsRef::as_ref).find(|v| v.len() != self.config.dimension) {
+            return Err(MarsError::DimensionMismatch {
+                expected: self.config.dimension,
+                actual: vector.len(),
+            });
+        }
 
-        for vector in vectors {
-            if vector.len() != self.config.dimension {
-                return Err(MarsError::DimensionMismatch {
-                    expected: self.config.dimension,
-                    actual: vector.len(),
-                });
-            }
-            ids.push(graph.insert(vector));
+        let _writer = self.writer.lock().unwrap();
+        if !self.config.snapshot_batches || vectors.len() < SNAPSHOT_MIN_BATCH {
+            let mut graph = self.graph.write().unwrap();
+            return Ok(vectors.into_iter().map(|vector| graph.insert(vector)).collect());
         }
 
+        self.ingesting.store(true, Ordering::Release);
+        let mut next = self.graph.read().unwrap().clone();
+        let ids = vectors.into_iter().map(|vector| next.insert(vector)).collect();
+
+        let mut graph = self.graph.write().unwrap();
+        *graph = next;
+        // Cleared before readers can see the new graph
+        self.ingesting.store(false, Ordering::Release);
         Ok(ids)
     }
 
@@ -171,9 +266,26 @@ where
         }
 
         let ef_search = self.config.graph.search_buffer.max(k);
-        let candidates = graph.query(vector, k, ef_search);
+        self.search_graph(&graph, vector, k, ef_search)
+    }
 
-        Ok(candidates.into_iter().map(SearchResult::from).collect())
+    /// Query for k nearest neighbors, reporting whether the results reflect
+    /// every write. Never waits for a large `insert_batch` to finish when
+    /// `Config::snapshot_batches` is set.
+    pub fn query_snapshot(&self, vector: &[T], k: usize) -> Result<SnapshotResults> {
+        if vector.len() != self.config.dimension {
+            return Err(MarsError::DimensionMismatch {
+                expected: self.config.dimension,
+                actual: vector.len(),
+            });
+        }
+
+        let graph = self.graph.read().unwrap();
+        let ef_search = self.config.graph.search_buffer.max(k);
+        let results = if graph.is_empty() { Vec::new() } else { self.search_graph(&graph, vector, k, ef_search)? };
+        // Read while still holding the graph, so a batch can't land in between
+        let fresh = !self.ingesting.load(Ordering::Acquire);
+        Ok(SnapshotResults { results, fresh })
     }
 
     /// Query with custom ef_search parameter.
@@ -191,17 +303,39 @@ where
             return Ok(Vec::new());
         }
 
+        self.search_graph(&graph, vector, k, ef_search)
+    }
+
+    /// Graph query bounded by `Config::statement_timeout`
+    fn search_graph(&self, graph: &Graph<T, D>, vector: &[T], k: usize, ef_search: usize) -> Result<Vec<SearchResult>> {
+        let deadline = self.config.statement_timeout.map(|t| Instant::now() + t);
+        let scope = InterruptHandle::default().enter(deadline);
         let candidates = graph.query(vector, k, ef_search);
+        if scope.timed_out() {
+            return Err(MarsError::Timeout(format!(
+                "query exceeded statement_timeout of {} ms",
+                self.config.statement_timeout.unwrap_or_default().as_millis(),
+            )));
+        }
 
         Ok(candidates.into_iter().map(SearchResult::from).collect())
     }
 
     /// Delete a node by ID.
     pub fn delete(&self, id: NodeId) -> Result<bool> {
+        let _writer = self.writer.lock().unwrap();
         let mut graph = self.graph.write().unwrap();
         Ok(graph.delete(id))
     }
 
+    /// Reconnect nodes that lost neighbors to deletions.
+    /// Returns the number of nodes repaired.
+    pub fn repair(&self) -> Result<usize> {
+        let _writer = self.writer.lock().unwrap();
+        let mut graph = self.graph.write().unwrap();
+        Ok(graph.repair())
+    }
+
     /// Update a node's vector.
     pub fn update(&self, id: NodeId, new_vector: Vec<T>) -> Result<bool> {
         if new_vector.len() != self.config.dimension {
@@ -211,6 +345,7 @@ where
             });
         }
 
+        let _writer = self.writer.lock().unwrap();
         let mut graph = self.graph.write().unwrap();
         Ok(graph.update(id, new_vector))
     }
@@ -221,7 +356,7 @@ where
         T: Clone,
     {
         let graph = self.graph.read().unwrap();
-        graph.get(id).map(|n| n.vector.as_ref().clone())
+        graph.vector(id).map(<[T]>::to_vec)
     }
 
     /// Get the number of vectors in the database.
@@ -239,11 +374,353 @@ where
         self.graph.read().unwrap().centroid().to_vec()
     }
 
-    /// Sync to disk (if persistent).
+    /// Sync to disk (if persistent), as `Config::synchronous` asks.
     pub fn sync(&self) -> Result<()> {
         if let Some(storage) = &self.storage {
             let storage = storage.read().unwrap();
-            storage.sync()?;
+            storage.sync(self.config.synchronous)?;
+        }
+        Ok(())
+    }
+
+    /// Get the dimension of vectors.
+    pub fn dimension(&self) -> usize {
+        self.config.dimension
+    }
+
+    /// An in-memory database around an existing graph
+    pub(crate) fn from_graph(graph: Graph<T, D>) -> Self {
+        let mut config = Config::new(graph.dimension());
+        config.graph = graph.config().clone();
+        VectorDB {
+            graph: Arc::new(RwLock::new(graph)),
+            storage: None,
+            config,
+            writer: Mutex::new(()),
+            ingesting: AtomicBool::new(false),
+        }
+    }
+
+    /// Copy of the graph as of now
+    pub(crate) fn graph(&self) -> Graph<T, D> {
+        self.graph.read().unwrap().clone()
+    }
+}
+
+/// Default number of sketch candidates re-ranked per requested result
+const DEFAULT_RERANK: usize = 4;
+
+/// Vector database for collections larger than RAM. Full vectors live in an
+/// mmapped file next to the index; memory holds only the graph adjacency and
+/// an 8-bit sketch of each vector. Queries search the sketches, then re-rank
+/// the best `k * rerank` candidates with exact distances read from disk.
+///
+/// Sketches are `round(x / range * 127)`, so components should lie within
+/// `[-range, range]` (default 1.0, suitable for normalized embeddings);
+/// larger values are clamped, which only costs recall. The range is stored
+/// in the vector file. `sync` also saves the index to `<path>-index`, so
+/// `open` can load it instead of rebuilding it from every vector.
+pub struct DiskVectorDB<D>
+where
+    D: Distance<f32> + Distance<i8>,
+{
+    graph: RwLock<Graph<i8, D>>,
+    vectors: RwLock<VectorFile>,
+    config: Config,
+    range: f32,
+    rerank: usize,
+    index_path: PathBuf,
+    /// Set while the index file may exist; cleared when the first change
+    /// after a `sync` deletes it
+    index_saved: AtomicBool,
+}
+
+/// The in-memory index of a `DiskVectorDB` as `sync` saves it
+#[derive(Serialize, Deserialize)]
+struct DiskIndex {
+    /// Slots of the vector file the index was saved with
+    slots: u64,
+    ids: Vec<NodeId>,
+    sketches: Vec<i8>,
+    offsets: Vec<u32>,
+    neighbors: Vec<NodeId>,
+    entry_points: Vec<NodeId>,
+    centroid: Vec<f32>,
+}
+
+impl DiskIndex {
+    /// Whether the index fits a vector file with `slots` slots of
+    /// `dimension` components
+    fn is_valid(&self, slots: usize, dimension: usize) -> bool {
+        let nodes = self.ids.len();
+        self.slots as usize == slots
+            && self.centroid.len() == dimension
+            && Some(self.sketches.len()) == nodes.checked_mul(dimension)
+            && self.ids.windows(2).all(|w| w[0] < w[1])
+            && self.ids.last().is_none_or(|&id| (id as usize) < slots)
+            && self.offsets.len() == nodes + 1
+            && self.offsets.first() == Some(&0)
+            && self.offsets.windows(2).all(|w| w[0] <= w[1])
+            && self.offsets.last().map(|&end| end as usize) == Some(self.neighbors.len())
+            && self.neighbors.iter().chain(&self.entry_points).all(|&n| (n as usize) < nodes)
+    }
+}
+
+/// `<path>-index`
+fn disk_index_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("-index");
+    PathBuf::from(name)
+}
+
+/// Delete a file if it exists
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+impl<D> DiskVectorDB<D>
+where
+    D: Distance<f32> + Distance<i8>,
+{
+    /// Create a new vector file at `path`, replacing any existing one
+    pub fn create<P: AsRef<Path>>(path: P, config: Config) -> Result<Self> {
+        let vectors = VectorFile::create(path.as_ref(), config.dimension, 1.0)?;
+        let index_path = disk_index_path(path.as_ref());
+        remove_if_exists(&index_path)?;
+        Ok(DiskVectorDB {
+            graph: RwLock::new(Graph::new(config.dimension, config.graph.clone())),
+            vectors: RwLock::new(vectors),
+            config: config.with_path(path.as_ref()),
+            range: 1.0,
+            rerank: DEFAULT_RERANK,
+            index_path,
+            index_saved: AtomicBool::new(false),
+        })
+    }
+
+    /// Open an existing vector file with the sketch range it was created
+    /// with. The index saved by the last `sync` is loaded if nothing changed
+    /// since; otherwise it is rebuilt from the vectors.
+    pub fn open<P: AsRef<Path>>(path: P, graph_config: GraphConfig) -> Result<Self> {
+        let vectors = VectorFile::open(path.as_ref())?;
+        let range = vectors.range();
+        let mut config = Config::new(vectors.dimension()).with_path(path.as_ref());
+        config.graph = graph_config;
+        let index_path = disk_index_path(path.as_ref());
+
+        let saved = std::fs::read(&index_path).ok()
+            .and_then(|bytes| bincode::deserialize::<DiskIndex>(&bytes).ok())
+            .filter(|index| index.is_valid(vectors.slots(), config.dimension));
+        let index_saved = saved.is_some();
+        let graph = match saved {
+            Some(index) => {
+                let csr = CsrGraph {
+                    ids: index.ids,
+                    vectors: index.sketches,
+                    offsets: index.offsets,
+                    neighbors: index.neighbors,
+                    entry_points: index.entry_points,
+                    centroid: index.centroid,
+                };
+                Graph::from_csr_at_ids(csr, vectors.slots(), config.graph.clone(), <D as Distance<i8>>::compute)
+            }
+            None => {
+                remove_if_exists(&index_path)?;
+                Self::rebuild(&vectors, range, &config)?
+            }
+        };
+
+        Ok(DiskVectorDB {
+            graph: RwLock::new(graph),
+            vectors: RwLock::new(vectors),
+            config,
+            range,
+            rerank: DEFAULT_RERANK,
+            index_path,
+            index_saved: AtomicBool::new(index_saved),
+        })
+    }
+
+    /// Build the index from every vector in the file
+    fn rebuild(vectors: &VectorFile, range: f32, config: &Config) -> Result<Graph<i8, D>> {
+        // Node IDs are allocated in order, so inserting every slot and then
+        // deleting the free ones keeps IDs equal to slot numbers
+        let mut graph = Graph::new(config.dimension, config.graph.clone());
+        let mut free = Vec::new();
+        for slot in 0..vectors.slots() as NodeId {
+            match vectors.get(slot)? {
+                Some(vector) => graph.insert(Self::sketch(range, &vector)),
+                None => {
+                    free.push(slot);
+                    graph.insert(vec![0; config.dimension])
+                }
+            };
+        }
+        for &slot in &free {
+            graph.delete(slot);
+        }
+        if !free.is_empty() {
+            graph.repair();
+        }
+        Ok(graph)
+    }
+
+    /// Set the sketch range; fails once vectors have been inserted
+    pub fn with_range(mut self, range: f32) -> Result<Self> {
+        self.vectors.get_mut().unwrap().set_range(range)?;
+        self.range = range;
+        Ok(self)
+    }
+
+    /// Set how many sketch candidates are re-ranked per requested result
+    pub fn with_rerank(mut self, rerank: usize) -> Self {
+        self.rerank = rerank.max(1);
+        self
+    }
+
+    /// Set how `sync` flushes the vector file (see `Config::synchronous`)
+    pub fn with_synchronous(mut self, synchronous: Synchronous) -> Self {
+        self.config.synchronous = synchronous;
+        self
+    }
+
+    fn sketch(range: f32, vector: &[f32]) -> Vec<i8> {
+        let scale = 127.0 / range;
+        vector.iter().map(|&x| (x * scale).round().clamp(-127.0, 127.0) as i8).collect()
+    }
+
+    fn check_dimension(&self, vector: &[f32]) -> Result<()> {
+        if vector.len() != self.config.dimension {
+            return Err(MarsError::DimensionMismatch {
+                expected: self.config.dimension,
+                actual: vector.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Insert a vector; it is written to disk before it becomes searchable.
+    /// Returns the ID of the inserted node. The vector is only read, so it
+    /// can be borrowed.
+    pub fn insert(&self, vector: impl AsRef<[f32]>) -> Result<NodeId> {
+        let vector = vector.as_ref();
+        self.check_dimension(vector)?;
+        let mut graph = self.graph.write().unwrap();
+        self.invalidate_index()?;
+        let id = graph.insert(Self::sketch(self.range, vector));
+        if let Err(e) = self.vectors.write().unwrap().put(id, vector) {
+            graph.delete(id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Query for the k nearest neighbors, ranked by exact distance
+    pub fn query(&self, vector: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        self.check_dimension(vector)?;
+        let graph = self.graph.read().unwrap();
+        if graph.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let candidates = k.saturating_mul(self.rerank);
+        let ef_search = self.config.graph.search_buffer.max(candidates);
+        let sketches = graph.query(&Self::sketch(self.range, vector), candidates, ef_search);
+
+        let vectors = self.vectors.read().unwrap();
+        let mut results = Vec::with_capacity(sketches.len());
+        for candidate in sketches {
+            if let Some(full) = vectors.get(candidate.id)? {
+                results.push(SearchResult {
+                    id: candidate.id,
+                    distance: <D as Distance<f32>>::compute(vector, &full),
+                });
+            }
+        }
+        results.sort_by(|a, b| a.distance.total_cmp(&b.distance).then(a.id.cmp(&b.id)));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    /// Delete a node by ID.
+    pub fn delete(&self, id: NodeId) -> Result<bool> {
+        let mut graph = self.graph.write().unwrap();
+        if graph.get(id).is_none() {
+            return Ok(false);
+        }
+        self.invalidate_index()?;
+        graph.delete(id);
+        self.vectors.write().unwrap().remove(id)?;
+        Ok(true)
+    }
+
+    /// Read a node's full vector from disk
+    pub fn get(&self, id: NodeId) -> Result<Option<Vec<f32>>> {
+        if self.graph.read().unwrap().get(id).is_none() {
+            return Ok(None);
+        }
+        self.vectors.read().unwrap().get(id)
+    }
+
+    /// Get the number of vectors in the database.
+    pub fn len(&self) -> usize {
+        self.graph.read().unwrap().len()
+    }
+
+    /// Check if the database is empty.
+    pub fn is_empty(&self) -> bool {
+        self.graph.read().unwrap().is_empty()
+    }
+
+    /// Estimated memory held by the index; full vectors are not counted
+    pub fn memory_usage(&self) -> usize {
+        self.graph.read().unwrap().memory_usage()
+    }
+
+    /// Load the index and every page of the vector file into memory before
+    /// serving, so the first queries do not wait on page faults. Returns the
+    /// bytes touched.
+    pub fn warmup(&self) -> Result<usize> {
+        let graph = self.graph.read().unwrap().warmup();
+        Ok(graph + self.vectors.read().unwrap().prefetch()?)
+    }
+
+    /// Sync vectors to disk, as `Config::synchronous` asks, and save the
+    /// index next to them for `open`
+    pub fn sync(&self) -> Result<()> {
+        let graph = self.graph.read().unwrap();
+        let vectors = self.vectors.read().unwrap();
+        vectors.sync(self.config.synchronous)?;
+
+        let csr = graph.to_csr();
+        let index = DiskIndex {
+            slots: vectors.slots() as u64,
+            ids: csr.ids,
+            sketches: csr.vectors,
+            offsets: csr.offsets,
+            neighbors: csr.neighbors,
+            entry_points: csr.entry_points,
+            centroid: csr.centroid,
+        };
+        let bytes = bincode::serialize(&index)
+            .map_err(|e| MarsError::InvalidFormat(format!("Failed to serialize index: {}", e)))?;
+        let mut temp = self.index_path.clone().into_os_string();
+        temp.push(".tmp");
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(&bytes)?;
+        self.config.synchronous.sync_file(&file, Path::new(&temp))?;
+        std::fs::rename(&temp, &self.index_path)?;
+        self.index_saved.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Delete the index saved by `sync` before the first change after it
+    fn invalidate_index(&self) -> Result<()> {
+        if self.index_saved.swap(false, Ordering::AcqRel) {
+            remove_if_exists(&self.index_path)?;
         }
         Ok(())
     }
@@ -254,6 +731,341 @@ where
     }
 }
 
+/// Default number of vectors the memtable takes before it is sealed
+const DEFAULT_MEMTABLE_SIZE: usize = 10_000;
+/// Default number of sealed segments that triggers a background compaction
+const DEFAULT_MAX_SEGMENTS: usize = 4;
+
+/// A graph and the database IDs of its nodes, in ascending order. Sealed
+/// segments are never modified.
+struct Segment<T, D>
+where
+    T: Numeric,
+    D: Distance<T>,
+{
+    graph: Graph<T, D>,
+    ids: Vec<NodeId>,
+}
+
+impl<T, D> Segment<T, D>
+where
+    T: Numeric,
+    D: Distance<T>,
+{
+    /// Graph node holding a database ID, if it is still in this segment
+    fn node(&self, id: NodeId) -> Option<NodeId> {
+        let node = self.ids.binary_search(&id).ok()? as NodeId;
+        self.graph.get(node).map(|_| node)
+    }
+}
+
+/// State shared with the background compaction thread
+struct Shared<T, D>
+where
+    T: Numeric,
+    D: Distance<T>,
+{
+    state: RwLock<SegmentState<T, D>>,
+    /// Held for the duration of a compaction
+    merging: Mutex<()>,
+}
+
+struct SegmentState<T, D>
+where
+    T: Numeric,
+    D: Distance<T>,
+{
+    /// Mutable graph taking new inserts
+    memtable: Segment<T, D>,
+    /// Sealed segments, oldest first
+    segments: Vec<Arc<Segment<T, D>>>,
+    /// Deleted IDs not yet purged by a compaction
+    deleted: HashSet<NodeId>,
+    next_id: NodeId,
+    len: usize,
+    /// Whether a background compaction thread is running
+    compacting: bool,
+}
+
+/// Vector database for write-heavy workloads, organized like an LSM tree.
+/// Inserts go to a small mutable memtable graph; when it fills up it is
+/// sealed into an immutable segment. Queries search the memtable and every
+/// segment and merge the results. Deletes are tombstones until the sealed
+/// segments are compacted into one, which happens on a background thread
+/// once more than `max_segments` have accumulated, so inserts never pay
+/// for re-linking a large graph.
+pub struct SegmentedVectorDB<T, D>
+where
+    T: Numeric,
+    D: Distance<T> + 'static,
+{
+    shared: Arc<Shared<T, D>>,
+    config: Config,
+    memtable_size: usize,
+    max_segments: usize,
+    compaction: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl<T, D> SegmentedVectorDB<T, D>
+where
+    T: Numeric,
+    D: Distance<T> + 'static,
+{
+    /// Create an empty in-memory database
+    pub fn new(config: Config) -> Self {
+        let state = SegmentState {
+            memtable: Segment { graph: Graph::new(config.dimension, config.graph.clone()), ids: Vec::new() },
+            segments: Vec::new(),
+            deleted: HashSet::new(),
+            next_id: 0,
+            len: 0,
+            compacting: false,
+        };
+        SegmentedVectorDB {
+            shared: Arc::new(Shared { state: RwLock::new(state), merging: Mutex::new(()) }),
+            config,
+            memtable_size: DEFAULT_MEMTABLE_SIZE,
+            max_segments: DEFAULT_MAX_SEGMENTS,
+            compaction: Mutex::new(None),
+        }
+    }
+
+    /// Seal the memtable once it holds this many vectors
+    pub fn with_memtable_size(mut self, size: usize) -> Self {
+        self.memtable_size = size.max(1);
+        self
+    }
+
+    /// Compact in the background once more than this many segments are sealed
+    pub fn with_max_segments(mut self, segments: usize) -> Self {
+        self.max_segments = segments.max(1);
+        self
+    }
+
+    /// Insert a vector, which is copied into the memtable and can be
+    /// borrowed. Returns its ID.
+    pub fn insert(&self, vector: impl AsRef<[T]>) -> Result<NodeId> {
+        let vector = vector.as_ref();
+        self.check_dimension(vector)?;
+
+        let mut state = self.shared.state.write().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.len += 1;
+        state.memtable.graph.insert(vector);
+        state.memtable.ids.push(id);
+
+        if state.memtable.ids.len() >= self.memtable_size {
+            self.seal(&mut state);
+        }
+        Ok(id)
+    }
+
+    /// Query for the k nearest neighbors across all segments
+    pub fn query(&self, vector: &[T], k: usize) -> Result<Vec<SearchResult>> {
+        self.query_with_ef(vector, k, self.config.graph.search_buffer.max(k))
+    }
+
+    /// Query with a custom ef_search, applied to each segment
+    pub fn query_with_ef(&self, vector: &[T], k: usize, ef_search: usize) -> Result<Vec<SearchResult>> {
+        self.check_dimension(vector)?;
+
+        let deadline = self.config.statement_timeout.map(|t| Instant::now() + t);
+        let scope = InterruptHandle::default().enter(deadline);
+
+        let state = self.shared.state.read().unwrap();
+        // Over-fetch so tombstoned matches don't leave the result short
+        let fetch = k + state.deleted.len();
+        let mut results: Vec<SearchResult> = std::iter::once(&state.memtable)
+            .chain(state.segments.iter().map(|s| s.as_ref()))
+            .flat_map(|segment| {
+                segment.graph.query(vector, fetch, ef_search.max(fetch))
+                    .into_iter()
+                    .map(|c| SearchResult { id: segment.ids[c.id as usize], distance: c.distance })
+            })
+            .filter(|r| !state.deleted.contains(&r.id))
+            .collect();
+
+        if scope.timed_out() {
+            return Err(MarsError::Timeout(format!(
+                "query exceeded statement_timeout of {} ms",
+                self.config.statement_timeout.unwrap_or_default().as_millis(),
+            )));
+        }
+
+        results.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    /// Delete a vector by ID. It stops appearing in results at once and is
+    /// dropped from the index by the next compaction.
+    pub fn delete(&self, id: NodeId) -> Result<bool> {
+        let mut state = self.shared.state.write().unwrap();
+        if state.deleted.contains(&id) || Self::locate(&state, id).is_none() {
+            return Ok(false);
+        }
+        state.deleted.insert(id);
+        state.len -= 1;
+        Ok(true)
+    }
+
+    /// Get a vector by ID
+    pub fn get(&self, id: NodeId) -> Option<Vec<T>> {
+        let state = self.shared.state.read().unwrap();
+        if state.deleted.contains(&id) {
+            return None;
+        }
+        let (segment, node) = Self::locate(&state, id)?;
+        segment.graph.vector(node).map(<[T]>::to_vec)
+    }
+
+    fn locate(state: &SegmentState<T, D>, id: NodeId) -> Option<(&Segment<T, D>, NodeId)> {
+        std::iter::once(&state.memtable)
+            .chain(state.segments.iter().map(|s| s.as_ref()))
+            .find_map(|segment| segment.node(id).map(|node| (segment, node)))
+    }
+
+    /// Seal the memtable now, even if it is not full
+    pub fn flush(&self) {
+        let mut state = self.shared.state.write().unwrap();
+        if !state.memtable.ids.is_empty() {
+            self.seal(&mut state);
+        }
+    }
+
+    /// Seal the memtable and merge every segment into one, purging deleted
+    /// vectors. Blocks until done.
+    pub fn compact(&self) {
+        self.wait_for_compaction();
+        self.flush();
+        compact_segments(&self.shared, &self.config.graph, self.config.thread_count());
+    }
+
+    /// Block until a running background compaction finishes
+    pub fn wait_for_compaction(&self) {
+        // Release the handle's lock before joining; `seal` takes it while
+        // holding the state lock the compaction needs to finish
+        let handle = self.compaction.lock().unwrap().take();
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+    }
+
+    /// Number of sealed segments
+    pub fn segment_count(&self) -> usize {
+        self.shared.state.read().unwrap().segments.len()
+    }
+
+    /// Get the number of vectors in the database.
+    pub fn len(&self) -> usize {
+        self.shared.state.read().unwrap().len
+    }
+
+    /// Check if the database is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the dimension of vectors.
+    pub fn dimension(&self) -> usize {
+        self.config.dimension
+    }
+
+    fn check_dimension(&self, vector: &[T]) -> Result<()> {
+        if vector.len() != self.config.dimension {
+            return Err(MarsError::DimensionMismatch {
+                expected: self.config.dimension,
+                actual: vector.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Move the memtable into the sealed segments, starting a background
+    /// compaction if there are too many and none is running
+    fn seal(&self, state: &mut SegmentState<T, D>) {
+        let fresh = Segment { graph: Graph::new(self.config.dimension, self.config.graph.clone()), ids: Vec::new() };
+        let sealed = std::mem::replace(&mut state.memtable, fresh);
+        state.segments.push(Arc::new(sealed));
+
+        if state.segments.len() > self.max_segments && !state.compacting {
+            state.compacting = true;
+            let shared = Arc::clone(&self.shared);
+            let config = self.config.graph.clone();
+            let threads = self.config.thread_count();
+            let max_segments = self.max_segments;
+            let handle = std::thread::spawn(move || loop {
+                compact_segments(&shared, &config, threads);
+                // Decide under the state lock so a concurrent seal either
+                // sees this thread running or is picked up by the next pass
+                let mut state = shared.state.write().unwrap();
+                if state.segments.len() <= max_segments {
+                    state.compacting = false;
+                    break;
+                }
+            });
+            // The previous thread has cleared `compacting`, so it is done
+            if let Some(previous) = self.compaction.lock().unwrap().replace(handle) {
+                let _ = previous.join();
+            }
+        }
+    }
+}
+
+impl<T, D> Drop for SegmentedVectorDB<T, D>
+where
+    T: Numeric,
+    D: Distance<T> + 'static,
+{
+    fn drop(&mut self) {
+        self.wait_for_compaction();
+    }
+}
+
+/// Merge the currently sealed segments into one, leaving out deleted
+/// vectors. The merge runs without holding the state lock; segments sealed
+/// in the meantime stay after the merged one.
+fn compact_segments<T, D>(shared: &Shared<T, D>, config: &GraphConfig, threads: usize)
+where
+    T: Numeric,
+    D: Distance<T>,
+{
+    // One compaction at a time, so the snapshot stays a prefix of `segments`
+    let _merging = shared.merging.lock().unwrap();
+    let (snapshot, deleted) = {
+        let state = shared.state.read().unwrap();
+        (state.segments.clone(), state.deleted.clone())
+    };
+    if snapshot.is_empty() || (snapshot.len() == 1 && deleted.is_empty()) {
+        return;
+    }
+
+    let first = &snapshot[0].graph;
+    let mut merged = Segment { graph: Graph::with_metric(first.dimension(), config.clone(), first.metric()), ids: Vec::new() };
+    for segment in &snapshot {
+        // Merging into a fresh graph numbers the live nodes sequentially
+        merged.graph.merge(&segment.graph, threads);
+        let live = segment.ids.iter().enumerate().filter(|(node, _)| segment.graph.get(*node as NodeId).is_some());
+        merged.ids.extend(live.map(|(_, &id)| id));
+    }
+
+    // Delete through the graph so neighbors of dropped nodes get relinked;
+    // the slots themselves are dropped by the next compaction
+    let (purged_nodes, purged): (Vec<NodeId>, Vec<NodeId>) = merged.ids.iter()
+        .enumerate()
+        .filter(|(_, id)| deleted.contains(id))
+        .map(|(node, &id)| (node as NodeId, id))
+        .unzip();
+    merged.graph.delete_batch(&purged_nodes);
+
+    let mut state = shared.state.write().unwrap();
+    state.segments.splice(0..snapshot.len(), [Arc::new(merged)]);
+    for id in purged {
+        state.deleted.remove(&id);
+    }
+}
+
 /// Type aliases for common configurations
 pub type CosineDB<T> = VectorDB<T, crate::distance::Cosine>;
 pub type DotProductDB<T> = VectorDB<T, crate::distance::DotProduct>;
@@ -306,6 +1118,49 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_segmented_db() {
+        let config = Config::new(2).with_threads(2);
+        assert_eq!(config.thread_count(), 2);
+        let db: SegmentedVectorDB<f32, Euclidean> = SegmentedVectorDB::new(config)
+            .with_memtable_size(50)
+            .with_max_segments(3);
+        let point = |i: u64| vec![(i % 20) as f32, (i / 20) as f32];
+
+        for i in 0..400 {
+            assert_eq!(db.insert(point(i)).unwrap(), i as NodeId);
+        }
+        db.wait_for_compaction();
+        assert!(db.segment_count() <= 3);
+        assert_eq!(db.len(), 400);
+
+        // Every vector is found wherever it lives
+        for i in (0..400).step_by(7) {
+            let results = db.query(&point(i), 1).unwrap();
+            assert_eq!((results[0].id, results[0].distance), (i as NodeId, 0.0));
+        }
+
+        // Tombstones hide deleted vectors until a compaction drops them
+        for i in (0..400).step_by(2) {
+            assert!(db.delete(i as NodeId).unwrap());
+        }
+        assert!(!db.delete(0).unwrap());
+        assert_eq!(db.len(), 200);
+        assert_eq!(db.get(0), None);
+        assert_eq!(db.get(1), Some(point(1)));
+        assert_eq!(db.query(&point(10), 1).unwrap()[0].distance, 1.0);
+
+        db.compact();
+        assert_eq!(db.segment_count(), 1);
+        assert!(db.shared.state.read().unwrap().deleted.is_empty());
+        assert_eq!(db.len(), 200);
+        for i in (1..400).step_by(6) {
+            assert_eq!(db.query(&point(i), 1).unwrap()[0].id, i as NodeId);
+        }
+        assert!(!db.delete(0).unwrap());
+        assert!(db.insert(vec![1.0]).is_err());
+    }
+
     #[test]
     fn test_insert_batch() {
         let db: EuclideanDB<f32> = VectorDB::in_memory(2);
@@ -318,5 +1173,142 @@ mod tests {
 
         assert_eq!(ids.len(), 3);
         assert_eq!(db.len(), 3);
+
+        // Borrowed vectors are copied straight into the graph
+        let buffer = [3.0, 3.0, 4.0, 4.0];
+        let shared: Arc<[f32]> = Arc::from(&buffer[2..]);
+        assert_eq!(db.insert_batch(buffer.chunks(2).collect()).unwrap(), [3, 4]);
+        assert_eq!(db.insert(shared).unwrap(), 5);
+        assert_eq!(db.query(&[4.0, 4.0], 2).unwrap()[1].distance, 0.0);
+    }
+
+    #[test]
+    fn test_query_snapshot_during_batch() {
+        let db: EuclideanDB<f32> = VectorDB::with_config(Config::new(2).with_snapshot_batches());
+        db.insert_batch((0..10).map(|i| vec![i as f32, 0.0]).collect()).unwrap();
+        let batch: Vec<Vec<f32>> = (0..2000).map(|i| vec![(i % 50) as f32, (i / 50 + 1) as f32]).collect();
+
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| db.insert_batch(batch).unwrap());
+            while !writer.is_finished() {
+                let snapshot = db.query_snapshot(&[3.0, 1.0], 1).unwrap();
+                // A stale snapshot predates the batch, so it can't see (3, 1)
+                if !snapshot.fresh {
+                    assert_eq!(snapshot.results[0].id, 3);
+                }
+            }
+            assert_eq!(writer.join().unwrap().len(), 2000);
+        });
+
+        let snapshot = db.query_snapshot(&[3.0, 1.0], 1).unwrap();
+        assert!(snapshot.fresh);
+        assert_eq!(snapshot.results[0].distance, 0.0);
+        assert_eq!(db.len(), 2010);
+        assert!(db.insert_batch(vec![vec![1.0, 2.0]; 300].into_iter().chain([vec![1.0]]).collect()).is_err());
+        assert_eq!(db.len(), 2010);
+    }
+
+    #[test]
+    fn test_batches_skip_copy_by_default() {
+        let db: EuclideanDB<f32> = VectorDB::in_memory(2);
+        let batch: Vec<Vec<f32>> = (0..2000).map(|i| vec![(i % 50) as f32, (i / 50) as f32]).collect();
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| db.insert_batch(batch).unwrap());
+            while !writer.is_finished() {
+                assert!(!db.ingesting.load(Ordering::Acquire));
+            }
+            writer.join().unwrap();
+        });
+        assert!(db.query_snapshot(&[3.0, 1.0], 1).unwrap().fresh);
+    }
+
+    #[test]
+    fn test_statement_timeout() {
+        let db: EuclideanDB<f32> = VectorDB::with_config(Config::new(2).with_statement_timeout(Duration::from_nanos(1)));
+        for i in 0..10 {
+            db.insert(vec![i as f32, 0.0]).unwrap();
+        }
+        assert!(matches!(db.query(&[1.0, 0.0], 3), Err(MarsError::Timeout(_))));
+
+        let db: EuclideanDB<f32> = VectorDB::with_config(Config::new(2).with_statement_timeout(Duration::from_secs(60)));
+        db.insert(vec![1.0, 0.0]).unwrap();
+        assert_eq!(db.query(&[1.0, 0.0], 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_disk_vector_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("v.pvec");
+        let db: DiskVectorDB<Euclidean> = DiskVectorDB::create(&path, Config::new(4)).unwrap();
+
+        let vector = |i: usize| (0..4).map(|d| ((i * 7 + d * 13) % 19) as f32 / 19.0 - 0.5).collect::<Vec<f32>>();
+        for i in 0..200 {
+            assert_eq!(db.insert(vector(i)).unwrap(), i as NodeId);
+        }
+
+        let results = db.query(&vector(42), 3).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].distance, 0.0);
+        assert!(results.windows(2).all(|w| w[0].distance <= w[1].distance));
+        assert_eq!(db.get(5).unwrap(), Some(vector(5)));
+
+        assert!(db.delete(5).unwrap());
+        assert!(!db.delete(5).unwrap());
+        db.sync().unwrap();
+        drop(db);
+
+        // The index saved by sync is loaded rather than rebuilt
+        let db: DiskVectorDB<Euclidean> = DiskVectorDB::open(&path, GraphConfig::default()).unwrap();
+        assert!(db.index_saved.load(Ordering::Acquire));
+        assert_eq!(db.len(), 199);
+        assert!(db.warmup().unwrap() > 200 * 4 * size_of::<f32>());
+        assert_eq!(db.get(5).unwrap(), None);
+        assert_eq!(db.get(6).unwrap(), Some(vector(6)));
+        let results = db.query(&vector(6), 1).unwrap();
+        assert_eq!(results[0].distance, 0.0);
+        assert!(db.query(&vector(5), 10).unwrap().iter().all(|r| r.id != 5));
+        assert_eq!(db.insert(vector(300)).unwrap(), 5);
+
+        // A change after the last sync drops the saved index, so open rebuilds it
+        assert!(!disk_index_path(&path).exists());
+        assert!(db.delete(7).unwrap());
+        drop(db);
+        let db: DiskVectorDB<Euclidean> = DiskVectorDB::open(&path, GraphConfig::default()).unwrap();
+        assert!(!db.index_saved.load(Ordering::Acquire));
+        assert_eq!(db.len(), 199);
+        assert_eq!(db.get(5).unwrap(), Some(vector(300)));
+        assert_eq!(db.get(7).unwrap(), None);
+    }
+
+    #[test]
+    fn test_disk_vector_db_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("v.pvec");
+        assert!(DiskVectorDB::<Euclidean>::create(&path, Config::new(2)).unwrap().with_range(0.0).is_err());
+        let db: DiskVectorDB<Euclidean> = DiskVectorDB::create(&path, Config::new(2)).unwrap().with_range(8.0).unwrap();
+        db.insert([6.0, -4.0]).unwrap();
+        drop(db);
+
+        // The range is read back from the file
+        let db: DiskVectorDB<Euclidean> = DiskVectorDB::open(&path, GraphConfig::default()).unwrap();
+        assert_eq!(db.range, 8.0);
+        assert_eq!(db.query(&[6.0, -4.0], 1).unwrap()[0].distance, 0.0);
+        assert!(db.with_range(2.0).is_err());
+    }
+
+    #[test]
+    fn test_synchronous_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::new(3).with_synchronous(Synchronous::Full);
+        let db: VectorDB<f32, Euclidean> = VectorDB::open_with_config(dir.path().join("v.mars"), config).unwrap();
+        db.insert(vec![1.0, 0.0, 0.0]).unwrap();
+        db.sync().unwrap();
+        assert_eq!(db.config.synchronous, Synchronous::Full);
+
+        let db: DiskVectorDB<Euclidean> = DiskVectorDB::create(dir.path().join("v.pvec"), Config::new(3)).unwrap()
+            .with_synchronous(Synchronous::Off);
+        db.insert(vec![1.0, 0.0, 0.0]).unwrap();
+        db.sync().unwrap();
+        assert_eq!(db.config.synchronous, Synchronous::Off);
     }
 }
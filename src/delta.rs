@@ -0,0 +1,374 @@
+//! Delta files: checkpoints holding only what changed since the last one.
+//!
+//! `Database::save_delta` writes `<path>-delta-1`, `<path>-delta-2`, ...
+//! next to the base file that `save` writes, each with the rows inserted,
+//! updated and deleted since the previous checkpoint. Tables that are new
+//! or whose settings changed are written whole. Opening the database
+//! applies the deltas in order; a full `save` rewrites the base file and
+//! removes them.
+//!
+//! A delta file is `MAGIC | format_version: u32 | sequence: u32 |
+//! base_hash: u64 | body`, the body being the bincode-encoded changes,
+//! encrypted like the base file if it is. `base_hash` identifies the base
+//! file the delta extends, so deltas left behind by a crash during a full
+//! save are never applied to the new base file.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::database::{FORMAT_VERSION, TableData};
+use crate::error::{MarsError, Result};
+use crate::schema::{Fnv1a, Row};
+use crate::table::{RowHistory, Table};
+
+/// First bytes of a delta file
+const MAGIC: &[u8; 8] = b"PARDUSD\x01";
+
+const HEADER_LEN: usize = MAGIC.len() + 4 + 4 + 8;
+
+/// `<path>-delta-<sequence>`
+pub(crate) fn delta_path(path: &Path, sequence: u32) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!("-delta-{}", sequence));
+    PathBuf::from(name)
+}
+
+/// Remove the delta files of `path`, returning how many there were
+pub(crate) fn remove_deltas(path: &Path) -> Result<u32> {
+    let mut removed = 0;
+    loop {
+        match std::fs::remove_file(delta_path(path, removed + 1)) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(removed),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Header of delta file `sequence` on the base file hashing to `base_hash`
+pub(crate) fn header(sequence: u32, base_hash: u64) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header.extend_from_slice(&sequence.to_le_bytes());
+    header.extend_from_slice(&base_hash.to_le_bytes());
+    header
+}
+
+/// Split a delta file into its sequence number, base hash and body
+pub(crate) fn parse(file: &[u8]) -> Result<(u32, u64, &[u8])> {
+    if file.len() < HEADER_LEN || !file.starts_with(MAGIC) {
+        return Err(MarsError::InvalidFormat("not a delta file".into()));
+    }
+    let version = u32::from_le_bytes(file[8..12].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(MarsError::InvalidFormat(format!(
+            "delta file has format version {}, expected {}; open and save the database with the version that wrote it",
+            version, FORMAT_VERSION,
+        )));
+    }
+    let sequence = u32::from_le_bytes(file[12..16].try_into().unwrap());
+    let base_hash = u64::from_le_bytes(file[16..24].try_into().unwrap());
+    Ok((sequence, base_hash, &file[HEADER_LEN..]))
+}
+
+/// Changes to one table since the previous checkpoint
+#[derive(Serialize, Deserialize)]
+enum TableDelta {
+    /// The table is new or its settings changed
+    Table(Box<TableData>),
+    Rows {
+        name: String,
+        next_id: u64,
+        /// Rows inserted or updated
+        upserts: Vec<(Row, RowHistory)>,
+        /// Rows deleted for good
+        deleted: Vec<u64>,
+        /// Rows soft-deleted, still readable with AS OF
+        tombstones: Vec<(Row, RowHistory)>,
+        /// Soft-deleted rows since deleted for good
+        purged: Vec<u64>,
+    },
+}
+
+/// Everything a delta file records
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Delta {
+    dropped: Vec<String>,
+    tables: Vec<TableDelta>,
+}
+
+impl Delta {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.dropped.is_empty() && self.tables.is_empty()
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self)
+            .map_err(|e| MarsError::InvalidFormat(format!("Failed to serialize delta: {}", e)))
+    }
+
+    pub(crate) fn decode(body: &[u8]) -> Result<Self> {
+        bincode::deserialize(body)
+            .map_err(|e| MarsError::InvalidFormat(format!("Failed to deserialize delta: {}", e)))
+    }
+
+    /// Replay the changes onto the tables of the previous checkpoint
+    pub(crate) fn apply(self, tables: &mut HashMap<String, Table>) -> Result<()> {
+        for name in &self.dropped {
+            tables.remove(name);
+        }
+        for change in self.tables {
+            match change {
+                TableDelta::Table(data) => {
+                    let table = data.into_table()?;
+                    tables.insert(table.name().to_string(), table);
+                }
+                TableDelta::Rows { name, next_id, upserts, deleted, tombstones, purged } => {
+                    let table = tables.get_mut(&name).ok_or_else(|| MarsError::InvalidFormat(
+                        format!("delta file changes rows of missing table '{}'", name)
+                    ))?;
+                    for id in deleted {
+                        table.discard_row(id);
+                        table.history.remove(&id);
+                    }
+                    for id in purged {
+                        table.tombstones.remove(&id);
+                        table.history.remove(&id);
+                    }
+                    for (row, history) in tombstones {
+                        table.discard_row(row.id);
+                        table.history.insert(row.id, history);
+                        table.tombstones.insert(row.id, row);
+                    }
+                    for (row, history) in upserts {
+                        table.replace_row(row, history);
+                    }
+                    table.next_id = next_id;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the base file and its deltas hold, to find the next delta's changes
+#[derive(Default)]
+pub(crate) struct Checkpoint {
+    /// Hash of the base file
+    pub(crate) base_hash: u64,
+    /// Delta files written on top of it
+    pub(crate) deltas: u32,
+    tables: HashMap<String, SavedTable>,
+}
+
+/// A table as last checkpointed
+struct SavedTable {
+    settings: u64,
+    next_id: u64,
+    /// Version of each live row
+    rows: HashMap<u64, u64>,
+    tombstones: HashSet<u64>,
+}
+
+impl Checkpoint {
+    pub(crate) fn new(base_hash: u64, deltas: u32, tables: &HashMap<String, Table>) -> Self {
+        let mut checkpoint = Checkpoint { base_hash, deltas, tables: HashMap::new() };
+        checkpoint.record(tables);
+        checkpoint
+    }
+
+    /// Remember `tables` as saved
+    pub(crate) fn record(&mut self, tables: &HashMap<String, Table>) {
+        self.tables = tables.iter()
+            .map(|(name, table)| {
+                let saved = SavedTable {
+                    settings: settings_hash(table),
+                    next_id: table.next_id,
+                    rows: table.rows.keys().map(|&id| (id, row_version(table, id))).collect(),
+                    tombstones: table.tombstones.keys().copied().collect(),
+                };
+                (name.clone(), saved)
+            })
+            .collect();
+    }
+
+    /// Changes from the checkpoint to `tables`
+    pub(crate) fn diff(&self, tables: &HashMap<String, Table>) -> Delta {
+        let mut delta = Delta {
+            dropped: self.tables.keys().filter(|name| !tables.contains_key(*name)).cloned().collect(),
+            tables: Vec::new(),
+        };
+
+        for (name, table) in tables {
+            let Some(saved) = self.tables.get(name).filter(|saved| saved.settings == settings_hash(table)) else {
+                delta.tables.push(TableDelta::Table(Box::new(TableData::from_table(table).compress_text().encode_vectors())));
+                continue;
+            };
+            let with_history = |row: &Row| (row.clone(), table.history.get(&row.id).cloned().unwrap_or_default());
+
+            let upserts: Vec<_> = table.rows.values()
+                .filter(|row| saved.rows.get(&row.id) != Some(&row_version(table, row.id)))
+                .map(with_history)
+                .collect();
+            let tombstones: Vec<_> = table.tombstones.values()
+                .filter(|row| !saved.tombstones.contains(&row.id))
+                .map(with_history)
+                .collect();
+            let deleted: Vec<u64> = saved.rows.keys()
+                .filter(|id| !table.rows.contains_key(id) && !table.tombstones.contains_key(id))
+                .copied()
+                .collect();
+            let purged: Vec<u64> = saved.tombstones.iter()
+                .filter(|id| !table.tombstones.contains_key(id))
+                .copied()
+                .collect();
+
+            let unchanged = upserts.is_empty() && tombstones.is_empty() && deleted.is_empty() && purged.is_empty();
+            if !unchanged || saved.next_id != table.next_id {
+                delta.tables.push(TableDelta::Rows {
+                    name: name.clone(),
+                    next_id: table.next_id,
+                    upserts,
+                    deleted,
+                    tombstones,
+                    purged,
+                });
+            }
+        }
+        delta
+    }
+}
+
+fn row_version(table: &Table, id: u64) -> u64 {
+    table.history.get(&id).map_or(0, |h| h.version)
+}
+
+/// Hash of everything about a table but its rows; a change means the next
+/// delta carries the whole table
+fn settings_hash(table: &Table) -> u64 {
+    let mut masks: Vec<_> = table.masks().iter().collect();
+    masks.sort_by_key(|(column, _)| *column);
+    let settings = (&table.schema, table.graph_config(), masks, table.metric(), table.is_normalized(), table.is_columnar(), table.view_defs(), table.stats(), table.foreign_keys(), table.auto_increment(), table.compress_text(), table.keep_history(), table.external_columns(), table.quantizer(), table.projection());
+
+    let mut hasher = Fnv1a::new();
+    hasher.write(&bincode::serialize(&settings).unwrap_or_default());
+    hasher.finish()
+}
+
+/// Hashes the bytes read from or written to a base file as they pass
+pub(crate) struct Hashing<T> {
+    inner: T,
+    hasher: Fnv1a,
+}
+
+impl<T> Hashing<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Hashing { inner, hasher: Fnv1a::new() }
+    }
+
+    pub(crate) fn hash(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// Hash of a whole base file held in memory
+pub(crate) fn hash(contents: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(contents);
+    hasher.finish()
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::schema::Value;
+
+    fn tables_of(db: &Database) -> HashMap<String, Table> {
+        db.table_names().into_iter()
+            .map(|name| {
+                let data = TableData::from_table(db.get_table(name).unwrap());
+                (name.to_string(), data.into_table().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_and_apply() {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+        db.execute("CREATE TABLE old (embedding VECTOR(2));").unwrap();
+        for i in 0..10 {
+            db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, 1.0], 't{}');", i, i)).unwrap();
+        }
+        let mut base = tables_of(&db);
+        let checkpoint = Checkpoint::new(0, 0, &base);
+        assert!(checkpoint.diff(&base).is_empty());
+
+        db.execute("INSERT INTO docs (embedding, title) VALUES ([20.0, 1.0], 'new');").unwrap();
+        db.execute("UPDATE docs SET title = 'changed' WHERE title = 't1';").unwrap();
+        db.execute("DELETE FROM docs WHERE title = 't2';").unwrap();
+        db.execute("DROP TABLE old;").unwrap();
+        db.execute("CREATE TABLE fresh (embedding VECTOR(3));").unwrap();
+
+        let delta = Delta::decode(&checkpoint.diff(&tables_of(&db)).encode().unwrap()).unwrap();
+        assert_eq!(delta.dropped, ["old"]);
+        assert_eq!(delta.tables.len(), 2);
+        delta.apply(&mut base).unwrap();
+
+        let mut names: Vec<&String> = base.keys().collect();
+        names.sort();
+        assert_eq!(names, ["docs", "fresh"]);
+        let docs = &base["docs"];
+        assert_eq!(docs.len(), 10);
+        assert_eq!(docs.graph.len(), 10);
+        let titles: HashSet<&str> = docs.rows.values()
+            .flat_map(|row| row.values.iter())
+            .filter_map(|value| match value {
+                Value::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert!(titles.contains("new") && titles.contains("changed") && !titles.contains("t2"));
+        assert_eq!(docs.next_id, db.get_table("docs").unwrap().next_id);
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let mut file = header(3, 42);
+        file.extend_from_slice(b"body");
+        assert_eq!(parse(&file).unwrap(), (3, 42, &b"body"[..]));
+        assert!(parse(b"PARDUSD").is_err());
+        assert!(parse(&[0; HEADER_LEN]).is_err());
+    }
+}
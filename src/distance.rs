@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use crate::error::{MarsError, Result};
+
 /// Numeric trait for vector elements.
 /// Supports both floating point and integer types for flexibility.
 pub trait Numeric: Copy + Clone + Send + Sync + 'static {
@@ -25,6 +30,11 @@ impl Numeric for i64 {
     fn zero() -> Self { 0 }
 }
 
+impl Numeric for i8 {
+    fn to_f32(self) -> f32 { self as f32 }
+    fn zero() -> Self { 0 }
+}
+
 impl Numeric for u32 {
     fn to_f32(self) -> f32 { self as f32 }
     fn zero() -> Self { 0 }
@@ -38,6 +48,9 @@ impl Numeric for u64 {
 /// Distance metric trait for vector similarity.
 /// Generic over the numeric type for both float and integer vectors.
 pub trait Distance<T: Numeric>: Send + Sync {
+    /// Name of the metric in `lookup_metric`, for metrics a table can use
+    const NAME: Option<&'static str> = None;
+
     fn compute(a: &[T], b: &[T]) -> f32;
 }
 
@@ -256,6 +269,8 @@ fn euclidean_f32_optimized(a: &[f32], b: &[f32]) -> f32 {
 pub struct Cosine;
 
 impl Distance<f32> for Cosine {
+    const NAME: Option<&'static str> = Some("cosine");
+
     #[inline]
     fn compute(a: &[f32], b: &[f32]) -> f32 {
         cosine_f32_optimized(a, b)
@@ -313,12 +328,33 @@ impl Distance<i64> for Cosine {
     }
 }
 
+/// 8-bit sketches, see `DiskVectorDB`
+impl Distance<i8> for Cosine {
+    fn compute(a: &[i8], b: &[i8]) -> f32 {
+        let (dot, norm_a, norm_b) = a.iter()
+            .zip(b.iter())
+            .fold((0i32, 0i32, 0i32), |(d, na, nb), (&x, &y)| {
+                let (x, y) = (x as i32, y as i32);
+                (d + x * y, na + x * x, nb + y * y)
+            });
+
+        let denom = (norm_a as f32).sqrt() * (norm_b as f32).sqrt();
+        if denom == 0.0 {
+            return 0.0;
+        }
+
+        1.0 - (dot as f32 / denom)
+    }
+}
+
 /// Dot product distance: -dot(a, b)
 /// Negative so that smaller values = more similar.
 /// Use this when vectors are already normalized.
 pub struct DotProduct;
 
 impl Distance<f32> for DotProduct {
+    const NAME: Option<&'static str> = Some("dot");
+
     #[inline]
     fn compute(a: &[f32], b: &[f32]) -> f32 {
         dot_product_f32_optimized(a, b)
@@ -343,11 +379,19 @@ impl Distance<i64> for DotProduct {
     }
 }
 
+impl Distance<i8> for DotProduct {
+    fn compute(a: &[i8], b: &[i8]) -> f32 {
+        -(a.iter().zip(b.iter()).map(|(&x, &y)| x as i32 * y as i32).sum::<i32>() as f32)
+    }
+}
+
 /// Euclidean (L2) distance: sqrt(sum((a-b)^2))
 /// Returns squared distance to avoid sqrt for comparisons.
 pub struct Euclidean;
 
 impl Distance<f32> for Euclidean {
+    const NAME: Option<&'static str> = Some("euclidean");
+
     #[inline]
     fn compute(a: &[f32], b: &[f32]) -> f32 {
         euclidean_f32_optimized(a, b)
@@ -381,6 +425,166 @@ impl Distance<i64> for Euclidean {
     }
 }
 
+impl Distance<i8> for Euclidean {
+    fn compute(a: &[i8], b: &[i8]) -> f32 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| (x as i32 - y as i32).pow(2))
+            .sum::<i32>() as f32
+    }
+}
+
+/// Cosine distance for vectors that are already L2-normalized: 1 - dot(a, b).
+/// Skips the per-pair magnitude computation done by `Cosine`.
+pub struct UnitCosine;
+
+impl Distance<f32> for UnitCosine {
+    #[inline]
+    fn compute(a: &[f32], b: &[f32]) -> f32 {
+        1.0 - a.iter().zip(b.iter()).map(|(&x, &y)| x * y).sum::<f32>()
+    }
+}
+
+/// Scale a vector to unit L2 length in place. Zero vectors are left as is.
+pub fn normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Manhattan (L1) distance: sum(|a-b|)
+pub struct Manhattan;
+
+impl<T: Numeric> Distance<T> for Manhattan {
+    const NAME: Option<&'static str> = Some("manhattan");
+
+    fn compute(a: &[T], b: &[T]) -> f32 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| (x.to_f32() - y.to_f32()).abs())
+            .sum()
+    }
+}
+
+/// Hamming distance: number of positions where the vectors differ.
+/// Intended for binary embeddings stored as 0/1 components.
+pub struct Hamming;
+
+impl<T: Numeric> Distance<T> for Hamming {
+    const NAME: Option<&'static str> = Some("hamming");
+
+    fn compute(a: &[T], b: &[T]) -> f32 {
+        a.iter()
+            .zip(b.iter())
+            .filter(|(x, y)| x.to_f32() != y.to_f32())
+            .count() as f32
+    }
+}
+
+/// Dot product of two sparse vectors given as (index, weight) pairs
+/// sorted by index.
+pub fn sparse_dot(a: &[(u32, f32)], b: &[(u32, f32)]) -> f32 {
+    let (mut i, mut j) = (0, 0);
+    let mut dot = 0.0;
+    while i < a.len() && j < b.len() {
+        match a[i].0.cmp(&b[j].0) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                dot += a[i].1 * b[j].1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    dot
+}
+
+/// Late-interaction (ColBERT-style) MaxSim score: for each query vector,
+/// the best dot product with any document vector, summed over the query.
+pub fn max_sim(query: &[Vec<f32>], doc: &[Vec<f32>]) -> f32 {
+    query
+        .iter()
+        .map(|q| {
+            doc.iter()
+                .map(|d| q.iter().zip(d).map(|(a, b)| a * b).sum::<f32>())
+                .fold(f32::NEG_INFINITY, f32::max)
+        })
+        .filter(|s| s.is_finite())
+        .sum()
+}
+
+/// Distance function usable by tables, selected by name with `METRIC`.
+pub type MetricFn = fn(&[f32], &[f32]) -> f32;
+
+/// Marker for graphs whose distance function is a `MetricFn` chosen at
+/// runtime and passed to `Graph::with_metric`, as table indexes are.
+/// `Graph::new` falls back to Euclidean distance.
+pub struct DynamicMetric;
+
+impl Distance<f32> for DynamicMetric {
+    #[inline]
+    fn compute(a: &[f32], b: &[f32]) -> f32 {
+        euclidean_f32_optimized(a, b)
+    }
+}
+
+const BUILTIN_METRICS: [&str; 5] = ["euclidean", "cosine", "dot", "manhattan", "hamming"];
+
+/// Metric registry shared by every database in the process
+fn metric_registry() -> &'static RwLock<HashMap<String, MetricFn>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, MetricFn>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtins: [(&str, MetricFn); 5] = [
+            ("euclidean", Euclidean::compute),
+            ("cosine", Cosine::compute),
+            ("dot", DotProduct::compute),
+            ("manhattan", Manhattan::compute),
+            ("hamming", Hamming::compute),
+        ];
+        RwLock::new(builtins.into_iter().map(|(name, f)| (name.to_string(), f)).collect())
+    })
+}
+
+/// Register a custom metric so tables can use it with `METRIC name`.
+///
+/// Names are case-insensitive. Built-in metrics (euclidean, cosine, dot,
+/// manhattan, hamming) cannot be replaced. Custom metrics must be registered
+/// before opening a database file whose tables use them.
+pub fn register_metric(name: &str, metric: MetricFn) -> Result<()> {
+    let name = name.to_lowercase();
+    if BUILTIN_METRICS.contains(&name.as_str()) {
+        return Err(MarsError::InvalidConfig(format!("Cannot replace built-in metric '{}'", name)));
+    }
+    metric_registry().write().unwrap().insert(name, metric);
+    Ok(())
+}
+
+/// The score reported for a search distance under a metric, with its
+/// name. Cosine tables report the cosine similarity, in [-1, 1]; `dot`
+/// tables, sparse and multi-vector (`maxsim`) searches report the dot
+/// product or MaxSim score. For these, higher is closer. Euclidean
+/// distances are searched squared and reported as the L2 distance, and
+/// other metrics, like results re-ranked by a `SCORE` expression, report
+/// their distance as is.
+pub fn metric_score(metric: &str, distance: f32) -> (&'static str, f32) {
+    match metric {
+        "cosine" => ("similarity", 1.0 - distance),
+        "dot" => ("dot", -distance),
+        "maxsim" => ("maxsim", -distance),
+        "euclidean" => ("distance", distance.max(0.0).sqrt()),
+        "score" => ("score", distance),
+        "weighted" => ("weighted", distance),
+        _ => ("distance", distance),
+    }
+}
+
+/// Look up a built-in or registered metric by name.
+pub fn lookup_metric(name: &str) -> Option<MetricFn> {
+    metric_registry().read().unwrap().get(&name.to_lowercase()).copied()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -473,4 +677,59 @@ mod tests {
         let dist = Euclidean::compute(&a, &b);
         assert!((dist - 25.0).abs() < 1e-6);
     }
+
+    #[test]
+    fn test_manhattan_and_hamming() {
+        let a = vec![1.0f32, 0.0, 1.0, 1.0];
+        let b = vec![0.0f32, 0.0, 1.0, -1.0];
+        assert_eq!(Manhattan::compute(&a, &b), 3.0);
+        assert_eq!(Hamming::compute(&a, &b), 2.0);
+    }
+
+    #[test]
+    fn test_sparse_dot() {
+        let a = vec![(1, 2.0), (5, 1.0), (9, 3.0)];
+        let b = vec![(0, 4.0), (5, 2.0), (9, 1.0), (12, 7.0)];
+        assert_eq!(sparse_dot(&a, &b), 5.0);
+        assert_eq!(sparse_dot(&a, &[]), 0.0);
+    }
+
+    #[test]
+    fn test_normalize_and_unit_cosine() {
+        let mut a = vec![3.0f32, 4.0];
+        let mut b = vec![0.0f32, 2.0];
+        normalize(&mut a);
+        normalize(&mut b);
+        assert_eq!(a, vec![0.6, 0.8]);
+        assert!((UnitCosine::compute(&a, &b) - Cosine::compute(&[3.0, 4.0], &[0.0, 2.0])).abs() < 1e-6);
+
+        let mut zero = vec![0.0f32, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_max_sim() {
+        let query = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let doc = vec![vec![0.5, 0.0], vec![2.0, 0.1], vec![0.0, 3.0]];
+        // max(0.5, 2.0, 0.0) + max(0.0, 0.1, 3.0)
+        assert_eq!(max_sim(&query, &doc), 5.0);
+        assert_eq!(max_sim(&query, &[]), 0.0);
+    }
+
+    #[test]
+    fn test_metric_registry() {
+        fn chebyshev(a: &[f32], b: &[f32]) -> f32 {
+            a.iter().zip(b).map(|(x, y)| (x - y).abs()).fold(0.0, f32::max)
+        }
+
+        assert!(lookup_metric("Cosine").is_some());
+        assert!(lookup_metric("chebyshev").is_none());
+
+        register_metric("Chebyshev", chebyshev).unwrap();
+        let f = lookup_metric("chebyshev").unwrap();
+        assert_eq!(f(&[1.0, 5.0], &[2.0, 1.0]), 4.0);
+
+        assert!(register_metric("euclidean", chebyshev).is_err());
+    }
 }
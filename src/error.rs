@@ -14,11 +14,71 @@ pub enum MarsError {
     #[error("Dimension mismatch: expected {expected}, got {actual}")]
     DimensionMismatch { expected: usize, actual: usize },
 
+    /// A vector written to a column of another dimension
+    #[error("Vector dimension mismatch in column '{column}': expected {expected}, got {actual}")]
+    ColumnDimensionMismatch { column: String, expected: usize, actual: usize },
+
+    #[error("Table '{0}' does not exist")]
+    TableNotFound(String),
+
+    #[error("Column '{0}' does not exist")]
+    ColumnNotFound(String),
+
+    /// UNIQUE, NOT NULL and similar constraint failures
+    #[error("Constraint violation: {0}")]
+    ConstraintViolation(String),
+
+    /// SQL syntax error; `snippet` is the offending line with a caret
+    /// under `column`
+    #[error("{message} at line {line}, column {column}\n{snippet}")]
+    ParseError { message: String, line: usize, column: usize, snippet: String },
+
     #[error("Database is empty")]
     EmptyDatabase,
 
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
+
+    #[error("Version conflict on row {id}: expected version {expected}, found {actual}")]
+    VersionConflict { id: u64, expected: u64, actual: u64 },
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("Memory limit exceeded: {0}")]
+    MemoryLimit(String),
+
+    /// Encrypted file opened without (or with the wrong) passphrase
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    /// Another process holds the database file's lock
+    #[error("Database is locked: {0}")]
+    Locked(String),
+
+    /// Another writer replaced an object-store file since it was loaded
+    #[error("Save conflict: {0}")]
+    SaveConflict(String),
+
+    /// A write on a database opened read-only
+    #[error("Database is read-only")]
+    ReadOnly,
+
+    /// A restricted connection tried to read or change masked columns
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+
+    /// Division by zero, or arithmetic on values that aren't numbers
+    #[error("Arithmetic error: {0}")]
+    Arithmetic(String),
+
+    #[error("Operation interrupted")]
+    Interrupted,
+
+    /// An operation panicked while changing a `ConcurrentDatabase`, so its
+    /// tables may be half-updated; see `ConcurrentDatabase::clear_poison`
+    #[error("Lock poisoned: {0}")]
+    LockPoisoned(String),
 }
 
 pub type Result<T> = std::result::Result<T, MarsError>;
@@ -0,0 +1,163 @@
+//! Sidecar files holding the values of `TEXT EXTERNAL` columns.
+//!
+//! Saving a local database writes every external value to a new
+//! `<path>-text-<n>` file and keeps only its offset and length in the
+//! database file, then removes the text files of earlier saves. Loading
+//! reads the values back into the rows.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MarsError, Result};
+use crate::schema::{Row, Value};
+use crate::storage::Synchronous;
+
+/// A value moved to a text file, left as NULL in its row
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ExternalText {
+    pub row: u32,
+    pub column: u32,
+    pub offset: u64,
+    pub len: u32,
+}
+
+/// `<path>-text-<generation>`
+pub(crate) fn text_path(path: &Path, generation: u64) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!("-text-{}", generation));
+    PathBuf::from(name)
+}
+
+/// Generations of the text files next to `path`
+fn generations(path: &Path) -> Result<Vec<u64>> {
+    let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}-text-", file_name);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut found = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        if let Some(generation) = name.to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|n| n.parse().ok())
+        {
+            found.push(generation);
+        }
+    }
+    Ok(found)
+}
+
+/// Writes the external values of one save. The file is only created once
+/// there is a value to write.
+pub(crate) struct TextFileWriter {
+    path: PathBuf,
+    generation: u64,
+    writer: Option<BufWriter<File>>,
+    len: u64,
+}
+
+impl TextFileWriter {
+    /// Start the text file of the next save of the database at `path`
+    pub(crate) fn new(path: &Path) -> Result<Self> {
+        let generation = generations(path)?.into_iter().max().unwrap_or(0) + 1;
+        Ok(TextFileWriter { path: path.to_path_buf(), generation, writer: None, len: 0 })
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Append `text`, returning its offset
+    pub(crate) fn append(&mut self, text: &str) -> Result<u64> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self.writer.insert(BufWriter::new(File::create(text_path(&self.path, self.generation))?)),
+        };
+        writer.write_all(text.as_bytes())?;
+        let offset = self.len;
+        self.len += text.len() as u64;
+        Ok(offset)
+    }
+
+    /// Flush and sync the file. Call before the database file is synced,
+    /// so a saved database never points into a missing text file.
+    pub(crate) fn finish(&mut self, synchronous: Synchronous) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            synchronous.sync_file(&file, &text_path(&self.path, self.generation))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the text files of earlier saves, once the database file no
+    /// longer refers to them
+    pub(crate) fn remove_old(&self) -> Result<()> {
+        for generation in generations(&self.path)? {
+            if generation != self.generation {
+                match std::fs::remove_file(text_path(&self.path, generation)) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Move the values of `columns` in `rows` to `file`, replacing them with NULL
+pub(crate) fn write(rows: &mut [Row], columns: &[usize], file: &mut TextFileWriter) -> Result<Vec<ExternalText>> {
+    let mut moved = Vec::new();
+    for (r, row) in rows.iter_mut().enumerate() {
+        for &c in columns {
+            let Some(Value::Text(text)) = row.values.get(c) else { continue };
+            let len = u32::try_from(text.len())
+                .map_err(|_| MarsError::InvalidConfig(format!("external text in row {} is over 4 GiB", row.id)))?;
+            let offset = file.append(text)?;
+            moved.push(ExternalText { row: r as u32, column: c as u32, offset, len });
+            row.values[c] = Value::Null;
+        }
+    }
+    Ok(moved)
+}
+
+/// Read the values moved out by `write` back from the text file
+/// `generation` of the database at `path`
+pub(crate) fn read(rows: &mut [Row], values: Vec<ExternalText>, path: Option<&Path>, generation: u64) -> Result<()> {
+    if values.is_empty() {
+        return Ok(());
+    }
+    let path = path.map(|path| text_path(path, generation))
+        .ok_or_else(|| MarsError::InvalidFormat("external text needs the database's text file".into()))?;
+    let file = File::open(&path)
+        .map_err(|e| MarsError::InvalidFormat(format!("cannot read external text from {}: {}", path.display(), e)))?;
+    let mut reader = BufReader::new(file);
+    let mut position = 0;
+    for value in values {
+        let corrupt = || MarsError::InvalidFormat(format!("corrupt external text in row {} of {}", value.row, path.display()));
+        if value.offset != position {
+            reader.seek(SeekFrom::Start(value.offset))?;
+        }
+        let mut bytes = vec![0; value.len as usize];
+        reader.read_exact(&mut bytes).map_err(|_| corrupt())?;
+        position = value.offset + value.len as u64;
+        let slot = rows.get_mut(value.row as usize)
+            .and_then(|row| row.values.get_mut(value.column as usize))
+            .ok_or_else(corrupt)?;
+        *slot = Value::Text(String::from_utf8(bytes).map_err(|_| corrupt())?);
+    }
+    Ok(())
+}
@@ -0,0 +1,496 @@
+//! Built-in scalar functions usable in SELECT columns and WHERE conditions.
+//!
+//! String functions return NULL when a required argument is NULL or not
+//! text-like; COALESCE and IFNULL exist to replace NULLs. String positions
+//! are 1-based and counted in characters.
+//!
+//! Arithmetic (`+ - * / %`) keeps integers integral and turns anything
+//! involving a float into a float. It yields NULL for NULL or non-numeric
+//! operands, division by zero and integer overflow.
+
+use std::fmt;
+
+use crate::analyzer::Analyzer;
+use crate::error::{MarsError, Result};
+use crate::schema::{ColumnType, Value};
+
+/// Scalar function names
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScalarFunc {
+    Upper,
+    Lower,
+    Length,
+    Trim,
+    Ltrim,
+    Rtrim,
+    Substr,   // SUBSTR(s, start [, length])
+    Replace,  // REPLACE(s, from, to)
+    Concat,   // CONCAT(a, b, ...), NULL arguments are skipped
+    Coalesce, // COALESCE(a, b, ...): first non-NULL argument
+    IfNull,   // IFNULL(a, b)
+    Tokenize, // TOKENIZE(s [, analyzer]): the analyzed terms of s, space-separated
+    Snippet,  // SNIPPET(s, query [, words [, analyzer]]): excerpt with query terms highlighted
+}
+
+impl ScalarFunc {
+    /// Look up a function by (case-insensitive) name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_uppercase().as_str() {
+            "UPPER" => ScalarFunc::Upper,
+            "LOWER" => ScalarFunc::Lower,
+            "LENGTH" => ScalarFunc::Length,
+            "TRIM" => ScalarFunc::Trim,
+            "LTRIM" => ScalarFunc::Ltrim,
+            "RTRIM" => ScalarFunc::Rtrim,
+            "SUBSTR" | "SUBSTRING" => ScalarFunc::Substr,
+            "REPLACE" => ScalarFunc::Replace,
+            "CONCAT" => ScalarFunc::Concat,
+            "COALESCE" => ScalarFunc::Coalesce,
+            "IFNULL" => ScalarFunc::IfNull,
+            "TOKENIZE" => ScalarFunc::Tokenize,
+            "SNIPPET" => ScalarFunc::Snippet,
+            _ => return None,
+        })
+    }
+
+    /// SQL name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScalarFunc::Upper => "UPPER",
+            ScalarFunc::Lower => "LOWER",
+            ScalarFunc::Length => "LENGTH",
+            ScalarFunc::Trim => "TRIM",
+            ScalarFunc::Ltrim => "LTRIM",
+            ScalarFunc::Rtrim => "RTRIM",
+            ScalarFunc::Substr => "SUBSTR",
+            ScalarFunc::Replace => "REPLACE",
+            ScalarFunc::Concat => "CONCAT",
+            ScalarFunc::Coalesce => "COALESCE",
+            ScalarFunc::IfNull => "IFNULL",
+            ScalarFunc::Tokenize => "TOKENIZE",
+            ScalarFunc::Snippet => "SNIPPET",
+        }
+    }
+
+    /// Accepted argument counts (min, max)
+    pub fn arity(&self) -> (usize, usize) {
+        match self {
+            ScalarFunc::Substr => (2, 3),
+            ScalarFunc::Replace => (3, 3),
+            ScalarFunc::Concat | ScalarFunc::Coalesce => (1, usize::MAX),
+            ScalarFunc::IfNull => (2, 2),
+            ScalarFunc::Tokenize => (1, 2),
+            ScalarFunc::Snippet => (2, 4),
+            _ => (1, 1),
+        }
+    }
+
+    /// Position of the analyzer spec argument, for functions that take one
+    pub fn analyzer_arg(&self) -> Option<usize> {
+        match self {
+            ScalarFunc::Tokenize => Some(1),
+            ScalarFunc::Snippet => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Type of the returned value, None when it depends on the arguments
+    pub fn result_type(&self) -> Option<ColumnType> {
+        match self {
+            ScalarFunc::Length => Some(ColumnType::Integer),
+            ScalarFunc::Coalesce | ScalarFunc::IfNull => None,
+            _ => Some(ColumnType::Text),
+        }
+    }
+}
+
+/// Arithmetic operators
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl ArithOp {
+    /// SQL symbol
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            ArithOp::Add => "+",
+            ArithOp::Sub => "-",
+            ArithOp::Mul => "*",
+            ArithOp::Div => "/",
+            ArithOp::Rem => "%",
+        }
+    }
+
+    /// Binding strength: multiplicative operators bind tighter than additive
+    fn precedence(&self) -> u8 {
+        match self {
+            ArithOp::Add | ArithOp::Sub => 1,
+            ArithOp::Mul | ArithOp::Div | ArithOp::Rem => 2,
+        }
+    }
+}
+
+/// A scalar expression: a column, a literal, a function call, or
+/// arithmetic on two expressions
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScalarExpr {
+    Column(String),
+    Literal(Value),
+    Call { func: ScalarFunc, args: Vec<ScalarExpr> },
+    Binary { op: ArithOp, left: Box<ScalarExpr>, right: Box<ScalarExpr> },
+}
+
+impl ScalarExpr {
+    /// Whether any column referenced by the expression matches `pred`
+    pub fn any_column(&self, pred: &dyn Fn(&str) -> bool) -> bool {
+        self.find_column(pred).is_some()
+    }
+
+    /// The first column referenced by the expression that matches `pred`
+    pub fn find_column(&self, pred: &dyn Fn(&str) -> bool) -> Option<&str> {
+        match self {
+            ScalarExpr::Column(name) => Some(name.as_str()).filter(|name| pred(name)),
+            ScalarExpr::Literal(_) => None,
+            ScalarExpr::Call { args, .. } => args.iter().find_map(|arg| arg.find_column(pred)),
+            ScalarExpr::Binary { left, right, .. } => left.find_column(pred).or_else(|| right.find_column(pred)),
+        }
+    }
+
+    /// Evaluate against a row; `column` resolves column names to values.
+    /// Arithmetic that fails (see `try_eval`) yields NULL.
+    pub fn eval(&self, column: &dyn Fn(&str) -> Value) -> Value {
+        match self {
+            ScalarExpr::Column(name) => column(name),
+            ScalarExpr::Literal(v) => v.clone(),
+            ScalarExpr::Call { func, args } => {
+                let args: Vec<Value> = args.iter().map(|a| a.eval(column)).collect();
+                call(*func, &args).unwrap_or(Value::Null)
+            }
+            ScalarExpr::Binary { op, left, right } => {
+                arithmetic(*op, &left.eval(column), &right.eval(column)).unwrap_or(Value::Null)
+            }
+        }
+    }
+
+    /// Evaluate against a row, failing with `MarsError::Arithmetic` on
+    /// division by zero and on arithmetic over values that aren't numbers.
+    /// Used where a NULL would be silently stored, such as UPDATE.
+    pub fn try_eval(&self, column: &dyn Fn(&str) -> Value) -> Result<Value> {
+        Ok(match self {
+            ScalarExpr::Column(name) => column(name),
+            ScalarExpr::Literal(v) => v.clone(),
+            ScalarExpr::Call { func, args } => {
+                let args: Vec<Value> = args.iter().map(|a| a.try_eval(column)).collect::<Result<_>>()?;
+                call(*func, &args).unwrap_or(Value::Null)
+            }
+            ScalarExpr::Binary { op, left, right } => arithmetic(*op, &left.try_eval(column)?, &right.try_eval(column)?)?,
+        })
+    }
+
+    /// Precedence of the top-level operator; operands bind tightest
+    fn precedence(&self) -> u8 {
+        match self {
+            ScalarExpr::Binary { op, .. } => op.precedence(),
+            _ => u8::MAX,
+        }
+    }
+}
+
+impl fmt::Display for ScalarExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScalarExpr::Column(name) => write!(f, "{}", name),
+            ScalarExpr::Literal(Value::Text(s)) => write!(f, "'{}'", s.replace('\'', "''")),
+            ScalarExpr::Literal(Value::Uuid(u)) => write!(f, "'{}'", u),
+            ScalarExpr::Literal(v) => write!(f, "{}", text(v).unwrap_or_else(|| "NULL".to_string())),
+            ScalarExpr::Call { func, args } => {
+                write!(f, "{}(", func.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            ScalarExpr::Binary { op, left, right } => {
+                // Parenthesize operands that would otherwise regroup: a
+                // looser left operand, and a right one that is not tighter
+                if left.precedence() < op.precedence() {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", op.symbol())?;
+                if right.precedence() <= op.precedence() {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+/// Text form of a scalar value; NULL and vector types have none
+pub(crate) fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Text(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::Uuid(u) => Some(u.to_string()),
+        _ => None,
+    }
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => Some(*i),
+        Value::Float(f) => Some(*f as i64),
+        _ => None,
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+/// `a op b`. NULL operands give NULL; integer results that overflow are
+/// computed as floats instead.
+fn arithmetic(op: ArithOp, a: &Value, b: &Value) -> Result<Value> {
+    if a.is_null() || b.is_null() {
+        return Ok(Value::Null);
+    }
+    let (Some(x), Some(y)) = (number(a), number(b)) else {
+        return Err(MarsError::Arithmetic(format!("cannot compute {:?} {} {:?}", a, op.symbol(), b)));
+    };
+    if matches!(op, ArithOp::Div | ArithOp::Rem) && y == 0.0 {
+        return Err(MarsError::Arithmetic("division by zero".to_string()));
+    }
+    if let (Value::Integer(x), Value::Integer(y)) = (a, b) {
+        let result = match op {
+            ArithOp::Add => x.checked_add(*y),
+            ArithOp::Sub => x.checked_sub(*y),
+            ArithOp::Mul => x.checked_mul(*y),
+            ArithOp::Div => x.checked_div(*y),
+            ArithOp::Rem => x.checked_rem(*y),
+        };
+        if let Some(result) = result {
+            return Ok(Value::Integer(result));
+        }
+    }
+    Ok(Value::Float(match op {
+        ArithOp::Add => x + y,
+        ArithOp::Sub => x - y,
+        ArithOp::Mul => x * y,
+        ArithOp::Div => x / y,
+        ArithOp::Rem => x % y,
+    }))
+}
+
+/// Words in a SNIPPET excerpt when the call does not say
+const DEFAULT_SNIPPET_WORDS: usize = 32;
+
+/// The analyzer named by a spec argument, or the default one
+fn analyzer(spec: Option<&Value>) -> Option<Analyzer> {
+    match spec {
+        Some(spec) => Analyzer::parse(&text(spec)?).ok(),
+        None => Some(Analyzer::default()),
+    }
+}
+
+fn call(func: ScalarFunc, args: &[Value]) -> Option<Value> {
+    match func {
+        ScalarFunc::Concat => return Some(Value::Text(args.iter().filter_map(text).collect())),
+        ScalarFunc::Coalesce | ScalarFunc::IfNull => return args.iter().find(|v| !v.is_null()).cloned(),
+        _ => {}
+    }
+
+    let s = text(args.first()?)?;
+    let result = match func {
+        ScalarFunc::Upper => s.to_uppercase(),
+        ScalarFunc::Lower => s.to_lowercase(),
+        ScalarFunc::Length => return Some(Value::Integer(s.chars().count() as i64)),
+        ScalarFunc::Trim => s.trim().to_string(),
+        ScalarFunc::Ltrim => s.trim_start().to_string(),
+        ScalarFunc::Rtrim => s.trim_end().to_string(),
+        ScalarFunc::Substr => {
+            let start = (integer(args.get(1)?)?.max(1) - 1) as usize;
+            let chars = s.chars().skip(start);
+            match args.get(2) {
+                Some(len) => chars.take(integer(len)?.max(0) as usize).collect(),
+                None => chars.collect(),
+            }
+        }
+        ScalarFunc::Replace => {
+            let from = text(args.get(1)?)?;
+            let to = text(args.get(2)?)?;
+            if from.is_empty() { s } else { s.replace(&from, &to) }
+        }
+        ScalarFunc::Tokenize => analyzer(args.get(1))?.terms(&s).join(" "),
+        ScalarFunc::Snippet => {
+            let query = text(args.get(1)?)?;
+            let words = match args.get(2) {
+                Some(words) => integer(words)?.max(1) as usize,
+                None => DEFAULT_SNIPPET_WORDS,
+            };
+            analyzer(args.get(3))?.snippet(&s, &query, words)
+        }
+        ScalarFunc::Concat | ScalarFunc::Coalesce | ScalarFunc::IfNull => unreachable!(),
+    };
+    Some(Value::Text(result))
+}
+
+/// Compiled REGEXP patterns, keyed by pattern text. Cleared when full.
+#[cfg(feature = "regex")]
+static REGEX_CACHE: std::sync::LazyLock<std::sync::Mutex<std::collections::HashMap<String, regex::Regex>>> =
+    std::sync::LazyLock::new(Default::default);
+
+#[cfg(feature = "regex")]
+const REGEX_CACHE_SIZE: usize = 256;
+
+/// Compile a REGEXP pattern, reusing a cached copy when possible
+#[cfg(feature = "regex")]
+pub fn compile_regex(pattern: &str) -> Result<regex::Regex> {
+    let mut cache = REGEX_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(re) = cache.get(pattern) {
+        return Ok(re.clone());
+    }
+    let re = regex::Regex::new(pattern)
+        .map_err(|e| MarsError::InvalidFormat(format!("Invalid REGEXP pattern: {}", e)))?;
+    if cache.len() >= REGEX_CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(pattern.to_string(), re.clone());
+    Ok(re)
+}
+
+#[cfg(not(feature = "regex"))]
+pub fn compile_regex(_pattern: &str) -> Result<()> {
+    Err(MarsError::InvalidFormat("REGEXP requires the `regex` feature".into()))
+}
+
+/// Unanchored regular expression match; invalid patterns match nothing
+pub fn regex_match(text: &str, pattern: &str) -> bool {
+    #[cfg(feature = "regex")]
+    {
+        compile_regex(pattern).is_ok_and(|re| re.is_match(text))
+    }
+    #[cfg(not(feature = "regex"))]
+    {
+        let _ = (text, pattern);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_text(func: ScalarFunc, args: Vec<Value>) -> Value {
+        let expr = ScalarExpr::Call { func, args: args.into_iter().map(ScalarExpr::Literal).collect() };
+        expr.eval(&|_| Value::Null)
+    }
+
+    fn t(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_string_functions() {
+        assert_eq!(call_text(ScalarFunc::Upper, vec![t("abc")]), t("ABC"));
+        assert_eq!(call_text(ScalarFunc::Lower, vec![t("ÀB")]), t("àb"));
+        assert_eq!(call_text(ScalarFunc::Length, vec![t("héllo")]), Value::Integer(5));
+        assert_eq!(call_text(ScalarFunc::Trim, vec![t("  x  ")]), t("x"));
+        assert_eq!(call_text(ScalarFunc::Ltrim, vec![t("  x  ")]), t("x  "));
+        assert_eq!(call_text(ScalarFunc::Rtrim, vec![t("  x  ")]), t("  x"));
+        assert_eq!(call_text(ScalarFunc::Substr, vec![t("abcdef"), Value::Integer(2), Value::Integer(3)]), t("bcd"));
+        assert_eq!(call_text(ScalarFunc::Substr, vec![t("abcdef"), Value::Integer(4)]), t("def"));
+        assert_eq!(call_text(ScalarFunc::Replace, vec![t("a-b-c"), t("-"), t("+")]), t("a+b+c"));
+        assert_eq!(call_text(ScalarFunc::Concat, vec![t("id-"), Value::Integer(7), Value::Null]), t("id-7"));
+        assert_eq!(call_text(ScalarFunc::Upper, vec![Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn test_null_functions() {
+        assert_eq!(call_text(ScalarFunc::Coalesce, vec![Value::Null, Value::Null, Value::Integer(3)]), Value::Integer(3));
+        assert_eq!(call_text(ScalarFunc::Coalesce, vec![Value::Null]), Value::Null);
+        assert_eq!(call_text(ScalarFunc::IfNull, vec![t("x"), t("y")]), t("x"));
+        assert_eq!(call_text(ScalarFunc::IfNull, vec![Value::Null, Value::Float(0.5)]), Value::Float(0.5));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(call_text(ScalarFunc::Tokenize, vec![t("Hello, World!")]), t("hello world"));
+        assert_eq!(call_text(ScalarFunc::Tokenize, vec![t("The running cats"), t("english")]), t("run cat"));
+        assert_eq!(call_text(ScalarFunc::Tokenize, vec![t("cats"), t("klingon")]), Value::Null);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let body = t("one two three four five six seven eight nine ten");
+        assert_eq!(call_text(ScalarFunc::Snippet, vec![body.clone(), t("Five"), Value::Integer(3)]), t("...four <b>five</b> six..."));
+        assert_eq!(call_text(ScalarFunc::Snippet, vec![t("The cats ran"), t("cat"), Value::Integer(5), t("english")]), t("The <b>cats</b> ran"));
+        assert_eq!(call_text(ScalarFunc::Snippet, vec![body, Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn test_nested_expression() {
+        let expr = ScalarExpr::Call {
+            func: ScalarFunc::Upper,
+            args: vec![ScalarExpr::Call {
+                func: ScalarFunc::Trim,
+                args: vec![ScalarExpr::Column("title".to_string())],
+            }],
+        };
+        assert_eq!(expr.eval(&|name| if name == "title" { t(" rust ") } else { Value::Null }), t("RUST"));
+        assert_eq!(expr.to_string(), "UPPER(TRIM(title))");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let binary = |op, left, right| ScalarExpr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        let views = ScalarExpr::Column("views".to_string());
+        let lit = ScalarExpr::Literal;
+        let row = |name: &str| if name == "views" { Value::Integer(7) } else { Value::Null };
+
+        let expr = binary(ArithOp::Mul, binary(ArithOp::Add, views.clone(), lit(Value::Integer(1))), lit(Value::Float(0.5)));
+        assert_eq!(expr.eval(&row), Value::Float(4.0));
+        assert_eq!(expr.to_string(), "(views + 1) * 0.5");
+        let expr = binary(ArithOp::Sub, views.clone(), binary(ArithOp::Sub, lit(Value::Integer(2)), lit(Value::Integer(1))));
+        assert_eq!(expr.eval(&row), Value::Integer(6));
+        assert_eq!(expr.to_string(), "views - (2 - 1)");
+
+        assert_eq!(binary(ArithOp::Div, views.clone(), lit(Value::Integer(2))).eval(&row), Value::Integer(3));
+        assert_eq!(binary(ArithOp::Rem, views.clone(), lit(Value::Integer(4))).eval(&row), Value::Integer(3));
+        assert_eq!(binary(ArithOp::Div, views.clone(), lit(Value::Integer(0))).eval(&row), Value::Null);
+        assert_eq!(binary(ArithOp::Add, views.clone(), lit(Value::Null)).eval(&row), Value::Null);
+        assert_eq!(binary(ArithOp::Add, views.clone(), lit(t("1"))).eval(&row), Value::Null);
+        assert_eq!(binary(ArithOp::Mul, lit(Value::Integer(i64::MAX)), lit(Value::Integer(2))).eval(&row), Value::Float(i64::MAX as f64 * 2.0));
+
+        // try_eval reports what eval turns into NULL
+        assert!(matches!(binary(ArithOp::Div, views.clone(), lit(Value::Integer(0))).try_eval(&row), Err(MarsError::Arithmetic(_))));
+        assert!(matches!(binary(ArithOp::Add, views.clone(), lit(t("1"))).try_eval(&row), Err(MarsError::Arithmetic(_))));
+        assert_eq!(binary(ArithOp::Add, views, lit(Value::Null)).try_eval(&row).unwrap(), Value::Null);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_match() {
+        assert!(regex_match("ABC-123", r"^[A-Z]{3}-\d+$"));
+        assert!(!regex_match("AB-123", r"^[A-Z]{3}-\d+$"));
+        assert!(regex_match("see ABC-1 here", r"[A-Z]{3}-\d"));
+        assert!(compile_regex("(unclosed").is_err());
+        assert!(!regex_match("anything", "(unclosed"));
+    }
+}
@@ -1,10 +1,35 @@
-use std::collections::{BinaryHeap, HashSet};
+use std::cell::Cell;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
 
 use crate::distance::{Distance, Numeric};
+use crate::interrupt::interrupted;
 use crate::node::{Candidate, Node, NodeId};
 
+/// Entry points are first recomputed once the graph reaches this many nodes,
+/// then again every time it doubles.
+const ENTRY_REFRESH_MIN: usize = 64;
+/// Maximum number of nodes sampled when choosing entry points.
+const ENTRY_SAMPLE: usize = 1024;
+/// Stride of `warmup` through the vector arena
+const PAGE_SIZE: usize = 4096;
+/// Edges each way between an unreachable island and the reachable graph
+const BRIDGE_EDGES: usize = 2;
+
+thread_local! {
+    /// Nodes visited by searches on this thread, for query statistics
+    static NODES_VISITED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Number of graph nodes visited by searches on the current thread since
+/// the last call; resets the counter
+pub fn take_nodes_visited() -> usize {
+    NODES_VISITED.with(|n| n.replace(0))
+}
+
 /// Configuration for the graph.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct GraphConfig {
     /// Maximum number of neighbors per node
     pub max_neighbors: usize,
@@ -14,6 +39,12 @@ pub struct GraphConfig {
     pub alpha_relaxed: f32,
     /// Buffer size for candidate search
     pub search_buffer: usize,
+    /// Number of spread-out entry points searches start from
+    pub entry_points: usize,
+    /// Seed for randomized choices while building the index; `None` uses
+    /// a fixed default, so builds are reproducible either way. Set it to
+    /// vary the choices between graphs.
+    pub seed: Option<u64>,
 }
 
 impl Default for GraphConfig {
@@ -23,10 +54,15 @@ impl Default for GraphConfig {
             alpha_strict: 1.0,
             alpha_relaxed: 1.2,
             search_buffer: 64,  // Reduced from 200 - enough for good recall
+            entry_points: 8,
+            seed: None,
         }
     }
 }
 
+/// Seed of graphs whose config does not set one
+const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
 /// The vector graph structure.
 pub struct Graph<T, D>
 where
@@ -34,31 +70,137 @@ where
     D: Distance<T>,
 {
     /// All nodes in the graph
-    nodes: Vec<Node<T>>,
+    nodes: Vec<Node>,
+    /// Vectors of all nodes back to back; node `id` owns
+    /// `vectors[id * dimension..(id + 1) * dimension]`
+    vectors: Vec<T>,
     /// Centroid vector (running average)
     centroid: Vec<f32>,
     /// Number of active (non-deleted) nodes
     active_count: usize,
     /// Free list for deleted node slots
     free_list: Vec<NodeId>,
+    /// Deletions since the last `repair()`
+    deletes_since_repair: usize,
+    /// Nodes that lost an edge to a single `delete()` and await `repair()`
+    repair_queue: HashSet<NodeId>,
+    /// Medoids spread across the data; searches start from these
+    entry_points: Vec<NodeId>,
+    /// Active node count at which the entry points are next recomputed
+    entry_refresh_at: usize,
     /// Configuration
     config: GraphConfig,
-    /// Distance metric (zero-sized marker type)
+    /// State of the generator behind randomized choices, from `config.seed`
+    rng: u64,
+    /// Distance function; `D::compute` unless replaced via `with_metric`
+    metric: fn(&[T], &[T]) -> f32,
+    /// Default distance metric (zero-sized marker type)
     _metric: std::marker::PhantomData<D>,
 }
 
+/// A graph's live nodes in compressed sparse row layout, renumbered
+/// densely: node `i` has vector `vectors[i * dimension..(i + 1) * dimension]`
+/// and neighbors `neighbors[offsets[i]..offsets[i + 1]]`
+pub(crate) struct CsrGraph<T> {
+    /// Id of each node in the graph it was taken from
+    pub ids: Vec<NodeId>,
+    pub vectors: Vec<T>,
+    pub offsets: Vec<u32>,
+    pub neighbors: Vec<NodeId>,
+    pub entry_points: Vec<NodeId>,
+    pub centroid: Vec<f32>,
+}
+
+/// Edge statistics of a graph's live nodes
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct EdgeStats {
+    pub nodes: usize,
+    pub edges: usize,
+    pub min_degree: usize,
+    pub max_degree: usize,
+    /// Live nodes without any neighbor
+    pub isolated: usize,
+    /// Edges still pointing at deleted nodes, awaiting `repair()`
+    pub dangling: usize,
+    /// Deleted node slots kept for reuse
+    pub free_slots: usize,
+}
+
+impl EdgeStats {
+    /// Average number of neighbors per live node
+    pub fn mean_degree(&self) -> f64 {
+        if self.nodes == 0 { 0.0 } else { self.edges as f64 / self.nodes as f64 }
+    }
+}
+
+/// Shape and connectivity of a graph's live nodes
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GraphStats {
+    pub edges: EdgeStats,
+    /// `degree_histogram[d]` is the number of live nodes with `d` neighbors
+    pub degree_histogram: Vec<usize>,
+    /// Connected components, ignoring edge direction. More than one means
+    /// some clusters can never be reached from the others.
+    pub components: usize,
+    /// Live nodes in the largest component
+    pub largest_component: usize,
+    /// Live nodes a search cannot reach by following edges from the entry
+    /// points; they are never returned by a graph search
+    pub unreachable: usize,
+    /// Mean distance from a node to its live neighbors, in the graph's metric
+    pub mean_neighbor_distance: f64,
+}
+
+// Manual impl: a derive would needlessly require `D: Clone`
+impl<T, D> Clone for Graph<T, D>
+where
+    T: Numeric,
+    D: Distance<T>,
+{
+    fn clone(&self) -> Self {
+        Graph {
+            nodes: self.nodes.clone(),
+            vectors: self.vectors.clone(),
+            centroid: self.centroid.clone(),
+            active_count: self.active_count,
+            free_list: self.free_list.clone(),
+            deletes_since_repair: self.deletes_since_repair,
+            repair_queue: self.repair_queue.clone(),
+            entry_points: self.entry_points.clone(),
+            entry_refresh_at: self.entry_refresh_at,
+            config: self.config.clone(),
+            rng: self.rng,
+            metric: self.metric,
+            _metric: std::marker::PhantomData,
+        }
+    }
+}
+
 impl<T, D> Graph<T, D>
 where
     T: Numeric,
     D: Distance<T>,
 {
     pub fn new(dimension: usize, config: GraphConfig) -> Self {
+        Self::with_metric(dimension, config, D::compute)
+    }
+
+    /// Create a graph that uses a runtime-selected distance function
+    /// instead of `D`, e.g. one looked up with `distance::lookup_metric`.
+    pub fn with_metric(dimension: usize, config: GraphConfig, metric: fn(&[T], &[T]) -> f32) -> Self {
         Graph {
             nodes: Vec::new(),
+            vectors: Vec::new(),
             centroid: vec![0.0; dimension],
             active_count: 0,
             free_list: Vec::new(),
+            deletes_since_repair: 0,
+            repair_queue: HashSet::new(),
+            entry_points: Vec::new(),
+            entry_refresh_at: ENTRY_REFRESH_MIN,
+            rng: config.seed.unwrap_or(DEFAULT_SEED),
             config,
+            metric,
             _metric: std::marker::PhantomData,
         }
     }
@@ -68,6 +210,35 @@ where
         self.centroid.len()
     }
 
+    /// Get the graph configuration.
+    pub fn config(&self) -> &GraphConfig {
+        &self.config
+    }
+
+    /// Replace the configuration. Existing edges are kept, so changes to
+    /// `max_neighbors` or the alphas only shape nodes inserted afterwards.
+    /// A new `seed` restarts the random choices from it.
+    pub fn set_config(&mut self, config: GraphConfig) {
+        if config.seed != self.config.seed {
+            self.rng = config.seed.unwrap_or(DEFAULT_SEED);
+        }
+        self.config = config;
+    }
+
+    /// Next value of the graph's generator (splitmix64)
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Get the distance function this graph ranks with.
+    pub fn metric(&self) -> fn(&[T], &[T]) -> f32 {
+        self.metric
+    }
+
     /// Get the number of active nodes.
     pub fn len(&self) -> usize {
         self.active_count
@@ -78,31 +249,338 @@ where
         self.active_count == 0
     }
 
+    /// Estimated heap memory held by nodes, vectors and edges, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let edges: usize = self.nodes.iter()
+            .map(|n| n.neighbors.len() * size_of::<NodeId>())
+            .sum();
+        edges
+            + self.nodes.len() * size_of::<Node>()
+            + self.vectors.len() * size_of::<T>()
+            + self.free_list.len() * size_of::<NodeId>()
+    }
+
+    /// Read every adjacency list and every page of the vector arena, so the
+    /// first searches after loading do not pay for page faults. Returns the
+    /// bytes touched.
+    pub fn warmup(&self) -> usize {
+        let per_page = (PAGE_SIZE / size_of::<T>()).max(1);
+        let mut checksum = 0u32;
+        for node in &self.nodes {
+            checksum = node.neighbors.iter().fold(checksum, |sum, &n| sum.wrapping_add(n));
+        }
+        let mut touched = 0.0f32;
+        for i in (0..self.vectors.len()).step_by(per_page) {
+            touched += self.vectors[i].to_f32();
+        }
+        std::hint::black_box((checksum, touched));
+        self.memory_usage()
+    }
+
+    /// Degree statistics over the live nodes.
+    pub fn edge_stats(&self) -> EdgeStats {
+        let mut stats = EdgeStats { free_slots: self.free_list.len(), ..EdgeStats::default() };
+        for node in self.nodes.iter().filter(|n| !n.deleted) {
+            let degree = node.neighbors.len();
+            stats.min_degree = if stats.nodes == 0 { degree } else { stats.min_degree.min(degree) };
+            stats.max_degree = stats.max_degree.max(degree);
+            stats.nodes += 1;
+            stats.edges += degree;
+            stats.isolated += usize::from(degree == 0);
+            stats.dangling += node.neighbors.iter().filter(|&&n| self.get(n).is_none()).count();
+        }
+        stats
+    }
+
+    /// Degree distribution, connectivity and neighbor distances over the
+    /// live nodes.
+    pub fn graph_stats(&self) -> GraphStats {
+        let edges = self.edge_stats();
+        let mut degree_histogram = vec![0; edges.max_degree + usize::from(edges.nodes > 0)];
+        let (mut total, mut count) = (0.0f64, 0usize);
+        for (id, node) in self.nodes.iter().enumerate().filter(|(_, n)| !n.deleted) {
+            degree_histogram[node.neighbors.len()] += 1;
+            for &neighbor in node.neighbors.iter().filter(|&&n| self.get(n).is_some()) {
+                total += self.distance(self.slot(id as NodeId), self.slot(neighbor)) as f64;
+                count += 1;
+            }
+        }
+        let components = self.components();
+        GraphStats {
+            edges,
+            degree_histogram,
+            components: components.len(),
+            largest_component: components.first().map_or(0, Vec::len),
+            unreachable: self.unreachable().len(),
+            mean_neighbor_distance: if count == 0 { 0.0 } else { total / count as f64 },
+        }
+    }
+
+    /// The live nodes grouped into connected components, ignoring edge
+    /// direction, largest first.
+    pub fn components(&self) -> Vec<Vec<NodeId>> {
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let mut parent: Vec<usize> = (0..self.nodes.len()).collect();
+        for (id, node) in self.nodes.iter().enumerate().filter(|(_, n)| !n.deleted) {
+            for &neighbor in node.neighbors.iter().filter(|&&n| self.get(n).is_some()) {
+                let (a, b) = (root(&mut parent, id), root(&mut parent, neighbor as usize));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+        let mut groups: HashMap<usize, Vec<NodeId>> = HashMap::new();
+        for id in (0..self.nodes.len()).filter(|&i| !self.nodes[i].deleted) {
+            groups.entry(root(&mut parent, id)).or_default().push(id as NodeId);
+        }
+        let mut components: Vec<Vec<NodeId>> = groups.into_values().collect();
+        components.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+        components
+    }
+
+    /// Live nodes that following edges from the entry points (where every
+    /// search starts) never reaches.
+    pub fn unreachable(&self) -> Vec<NodeId> {
+        let reached = self.reachable();
+        (0..self.nodes.len())
+            .filter(|&i| !self.nodes[i].deleted && !reached[i])
+            .map(|i| i as NodeId)
+            .collect()
+    }
+
+    /// Link every island that searches cannot reach back into the graph:
+    /// each gets edges to and from its nearest reachable nodes. Graphs left
+    /// with unlinked nodes by older builds need this before every vector
+    /// can be found. Returns the number of edges added.
+    pub fn bridge_components(&mut self) -> usize {
+        let mut reached = self.reachable();
+        let ef_search = self.config.search_buffer.max(BRIDGE_EDGES);
+        let mut added = 0;
+        for id in 0..self.nodes.len() {
+            if self.nodes[id].deleted || reached[id] {
+                continue;
+            }
+            let island = id as NodeId;
+            let vector = self.slot(island).to_vec();
+            // Searches only visit reachable nodes, so these are the closest
+            // points the island can be joined to
+            let bridges: Vec<NodeId> = self.search(&vector, ef_search)
+                .into_iter()
+                .map(|c| c.id)
+                .take(BRIDGE_EDGES)
+                .collect();
+            for near in bridges {
+                for (from, to) in [(near, island), (island, near)] {
+                    let neighbors = &mut self.nodes[from as usize].neighbors;
+                    if !neighbors.contains(&to) {
+                        neighbors.push(to);
+                        added += 1;
+                    }
+                }
+            }
+            self.mark_reachable(&mut reached, vec![island]);
+        }
+        added
+    }
+
+    /// Which node slots a search can reach by following edges from the
+    /// entry points (or the fallback start node)
+    fn reachable(&self) -> Vec<bool> {
+        let mut start: Vec<NodeId> = self.entry_points.iter().copied().filter(|&id| self.get(id).is_some()).collect();
+        if start.is_empty() {
+            start.extend(self.find_start_node());
+        }
+        let mut reached = vec![false; self.nodes.len()];
+        self.mark_reachable(&mut reached, start);
+        reached
+    }
+
+    /// Mark the live nodes reachable from `stack` in `reached`
+    fn mark_reachable(&self, reached: &mut [bool], mut stack: Vec<NodeId>) {
+        while let Some(id) = stack.pop() {
+            if std::mem::replace(&mut reached[id as usize], true) {
+                continue;
+            }
+            stack.extend(self.nodes[id as usize].neighbors.iter().filter(|&&n| self.get(n).is_some() && !reached[n as usize]));
+        }
+    }
+
+    /// Estimated memory one more node of this graph's dimension takes.
+    /// A node reusing a deleted slot only adds its edges.
+    pub fn node_memory(&self, dimension: usize) -> usize {
+        let edges = self.config.max_neighbors * size_of::<NodeId>();
+        if self.free_list.is_empty() {
+            size_of::<Node>() + dimension * size_of::<T>() + edges
+        } else {
+            edges
+        }
+    }
+
     /// Get a node by ID.
-    pub fn get(&self, id: NodeId) -> Option<&Node<T>> {
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
         self.nodes.get(id as usize).filter(|n| !n.deleted)
     }
 
     /// Get a mutable reference to a node by ID.
-    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node<T>> {
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
         self.nodes.get_mut(id as usize).filter(|n| !n.deleted)
     }
 
+    /// Get a live node's vector.
+    pub fn vector(&self, id: NodeId) -> Option<&[T]> {
+        self.get(id).map(|_| self.slot(id))
+    }
+
+    /// IDs of the live nodes, in ascending order
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes.iter().enumerate().filter(|(_, node)| !node.deleted).map(|(id, _)| id as NodeId)
+    }
+
+    /// The same graph under another distance marker type; its distance
+    /// function stays the same
+    pub(crate) fn with_marker<E: Distance<T>>(self) -> Graph<T, E> {
+        Graph {
+            nodes: self.nodes,
+            vectors: self.vectors,
+            centroid: self.centroid,
+            active_count: self.active_count,
+            free_list: self.free_list,
+            deletes_since_repair: self.deletes_since_repair,
+            repair_queue: self.repair_queue,
+            entry_points: self.entry_points,
+            entry_refresh_at: self.entry_refresh_at,
+            config: self.config,
+            rng: self.rng,
+            metric: self.metric,
+            _metric: std::marker::PhantomData,
+        }
+    }
+
+    /// Arena slot of a node, live or not
+    #[inline]
+    fn slot(&self, id: NodeId) -> &[T] {
+        let dim = self.dimension();
+        let start = id as usize * dim;
+        &self.vectors[start..start + dim]
+    }
+
+    /// Copy `vector` into the arena slot of `id`, growing the arena as needed
+    fn set_slot(&mut self, id: NodeId, vector: &[T]) {
+        let dim = self.dimension();
+        let start = id as usize * dim;
+        if self.vectors.len() < start + dim {
+            self.vectors.resize(start + dim, T::zero());
+        }
+        self.vectors[start..start + dim].copy_from_slice(vector);
+    }
+
     /// Get the centroid vector.
     pub fn centroid(&self) -> &[f32] {
         &self.centroid
     }
 
+    /// Get the current search entry points.
+    pub fn entry_points(&self) -> &[NodeId] {
+        &self.entry_points
+    }
+
+    /// Copy the live nodes into CSR layout, dropping deleted slots
+    pub(crate) fn to_csr(&self) -> CsrGraph<T> {
+        let mut renumbered = vec![None; self.nodes.len()];
+        let ids: Vec<NodeId> = self.nodes.iter()
+            .enumerate()
+            .filter(|(_, node)| !node.deleted)
+            .map(|(id, _)| id as NodeId)
+            .collect();
+        for (new_id, &id) in ids.iter().enumerate() {
+            renumbered[id as usize] = Some(new_id as NodeId);
+        }
+
+        let mut csr = CsrGraph {
+            vectors: Vec::with_capacity(ids.len() * self.dimension()),
+            offsets: Vec::with_capacity(ids.len() + 1),
+            neighbors: Vec::new(),
+            entry_points: self.entry_points.iter().filter_map(|&id| renumbered[id as usize]).collect(),
+            centroid: self.centroid.clone(),
+            ids: Vec::new(),
+        };
+        csr.offsets.push(0);
+        for &id in &ids {
+            csr.vectors.extend_from_slice(self.slot(id));
+            csr.neighbors.extend(self.nodes[id as usize].neighbors.iter().filter_map(|&n| renumbered[n as usize]));
+            csr.offsets.push(csr.neighbors.len() as u32);
+        }
+        csr.ids = ids;
+        csr
+    }
+
+    /// Rebuild a graph from CSR layout without re-inserting its vectors
+    pub(crate) fn from_csr(csr: CsrGraph<T>, config: GraphConfig, metric: fn(&[T], &[T]) -> f32) -> Self {
+        let nodes: Vec<Node> = csr.offsets.windows(2)
+            .map(|w| Node { neighbors: csr.neighbors[w[0] as usize..w[1] as usize].to_vec(), deleted: false })
+            .collect();
+        Graph {
+            active_count: nodes.len(),
+            entry_refresh_at: (nodes.len() * 2).max(ENTRY_REFRESH_MIN),
+            nodes,
+            vectors: csr.vectors,
+            centroid: csr.centroid,
+            free_list: Vec::new(),
+            deletes_since_repair: 0,
+            repair_queue: HashSet::new(),
+            entry_points: csr.entry_points,
+            rng: config.seed.unwrap_or(DEFAULT_SEED),
+            config,
+            metric,
+            _metric: std::marker::PhantomData,
+        }
+    }
+
+    /// Rebuild a graph from CSR layout keeping each node at its id in
+    /// `csr.ids` rather than renumbering; the other ids below `len` become
+    /// free slots. Ids must be ascending and below `len`.
+    pub(crate) fn from_csr_at_ids(csr: CsrGraph<T>, len: usize, config: GraphConfig, metric: fn(&[T], &[T]) -> f32) -> Self {
+        let dimension = csr.centroid.len();
+        let mut nodes = vec![Node { neighbors: Vec::new(), deleted: true }; len];
+        let mut vectors = vec![T::zero(); len * dimension];
+        for (i, &id) in csr.ids.iter().enumerate() {
+            let neighbors = &csr.neighbors[csr.offsets[i] as usize..csr.offsets[i + 1] as usize];
+            nodes[id as usize] = Node { neighbors: neighbors.iter().map(|&n| csr.ids[n as usize]).collect(), deleted: false };
+            vectors[id as usize * dimension..(id as usize + 1) * dimension]
+                .copy_from_slice(&csr.vectors[i * dimension..(i + 1) * dimension]);
+        }
+        let free_list = (0..len as NodeId).rev().filter(|&id| nodes[id as usize].deleted).collect();
+        Graph {
+            active_count: csr.ids.len(),
+            entry_refresh_at: (csr.ids.len() * 2).max(ENTRY_REFRESH_MIN),
+            nodes,
+            vectors,
+            centroid: csr.centroid,
+            free_list,
+            deletes_since_repair: 0,
+            repair_queue: HashSet::new(),
+            entry_points: csr.entry_points.iter().map(|&n| csr.ids[n as usize]).collect(),
+            rng: config.seed.unwrap_or(DEFAULT_SEED),
+            config,
+            metric,
+            _metric: std::marker::PhantomData,
+        }
+    }
+
     /// Compute distance between two vectors.
     #[inline]
-    fn distance(a: &[T], b: &[T]) -> f32 {
-        D::compute(a, b)
+    fn distance(&self, a: &[T], b: &[T]) -> f32 {
+        (self.metric)(a, b)
     }
 
     /// Compute distance from a node to a vector.
     #[inline]
-    fn distance_to_vector(node: &Node<T>, vector: &[T]) -> f32 {
-        Self::distance(&node.vector, vector)
+    fn distance_to_vector(&self, id: NodeId, vector: &[T]) -> f32 {
+        self.distance(self.slot(id), vector)
     }
 
     /// Update centroid after inserting a new node.
@@ -139,7 +617,80 @@ where
             });
     }
 
-    /// Find the best starting node for search (first active node).
+    /// Recompute the entry points from a sample of live nodes.
+    ///
+    /// The first entry point is the sampled node closest to the centroid;
+    /// the rest are picked farthest-first (k-center greedy), so every
+    /// cluster in the data ends up with a nearby starting node. Large
+    /// graphs are sampled at a stride from a random offset.
+    pub fn refresh_entry_points(&mut self) {
+        let live: Vec<NodeId> = self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| !n.deleted)
+            .map(|(id, _)| id as NodeId)
+            .collect();
+        self.entry_refresh_at = (live.len() * 2).max(ENTRY_REFRESH_MIN);
+        self.entry_points.clear();
+
+        let step = (live.len() / ENTRY_SAMPLE).max(1);
+        let offset = if step > 1 { (self.next_random() % step as u64) as usize } else { 0 };
+        let sample: Vec<NodeId> = live.into_iter().skip(offset).step_by(step).collect();
+
+        let centroid_distance = |id: NodeId| -> f32 {
+            self.slot(id)
+                .iter()
+                .zip(self.centroid.iter())
+                .map(|(&v, &c)| (v.to_f32() - c) * (v.to_f32() - c))
+                .sum()
+        };
+        let first = match sample
+            .iter()
+            .copied()
+            .min_by(|&a, &b| centroid_distance(a).total_cmp(&centroid_distance(b)))
+        {
+            Some(id) => id,
+            None => return,
+        };
+
+        let mut points = vec![first];
+        let mut nearest: Vec<f32> = sample
+            .iter()
+            .map(|&id| self.distance(self.slot(id), self.slot(first)))
+            .collect();
+
+        while points.len() < self.config.entry_points {
+            let (index, &farthest) = match nearest.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)) {
+                Some(pair) => pair,
+                None => break,
+            };
+            if farthest <= 0.0 {
+                break;
+            }
+
+            let id = sample[index];
+            points.push(id);
+            for (d, &other) in nearest.iter_mut().zip(sample.iter()) {
+                *d = d.min(self.distance(self.slot(other), self.slot(id)));
+            }
+        }
+
+        self.entry_points = points;
+    }
+
+    /// Keep entry points current after inserting `new_ids`: recompute them
+    /// when the graph has doubled, otherwise adopt new nodes while there
+    /// are fewer than configured.
+    fn track_entry_points(&mut self, new_ids: &[NodeId]) {
+        if self.active_count >= self.entry_refresh_at {
+            self.refresh_entry_points();
+            return;
+        }
+        let room = self.config.entry_points.saturating_sub(self.entry_points.len());
+        self.entry_points.extend(new_ids.iter().take(room));
+    }
+
+    /// Fallback starting node when no entry point is live (first active node).
     fn find_start_node(&self) -> Option<NodeId> {
         self.nodes
             .iter()
@@ -148,38 +699,55 @@ where
             .map(|(id, _)| id as NodeId)
     }
 
-    /// Greedy search from centroid to find candidates close to target.
+    /// Greedy search from the entry points to find candidates close to target.
     /// Returns candidates sorted by distance.
     pub fn search(&self, target: &[T], ef_search: usize) -> Vec<Candidate> {
+        self.search_until(target, ef_search, &|| false)
+    }
+
+    /// `search` that returns the candidates found so far once `stop` is true
+    fn search_until(&self, target: &[T], ef_search: usize, stop: &dyn Fn() -> bool) -> Vec<Candidate> {
         if self.is_empty() {
             return Vec::new();
         }
 
-        // Find starting point
-        let start = match self.find_start_node() {
-            Some(id) => id,
-            None => return Vec::new(),
-        };
-
-        let start_node = &self.nodes[start as usize];
-        let start_dist = Self::distance_to_vector(start_node, target);
+        // Seed from every live entry point, closest first
+        let seed = |id: NodeId| self.get(id).map(|_| Candidate::new(id, self.distance_to_vector(id, target)));
+        let mut seeds: Vec<Candidate> = self.entry_points.iter().filter_map(|&id| seed(id)).collect();
+        if seeds.is_empty() {
+            seeds.extend(self.find_start_node().and_then(seed));
+        }
+        seeds.sort_by(|a, b| a.distance.total_cmp(&b.distance));
 
         // Min-heap for candidates (BinaryHeap is max-heap, so we reverse the ordering)
         let mut candidates: BinaryHeap<Candidate> = BinaryHeap::new();
-        candidates.push(Candidate::new(start, start_dist));
 
         // Set of visited nodes - use a Vec for small graphs, HashSet for larger
         let mut visited = vec![false; self.nodes.len()];
-        visited[start as usize] = true;
+        let mut visited_count = 0;
 
         // Result buffer - use Vec and sort at the end
-        let mut results: Vec<Candidate> = Vec::with_capacity(ef_search);
-        results.push(Candidate::new(start, start_dist));
+        let mut results: Vec<Candidate> = Vec::with_capacity(ef_search.min(self.active_count));
+
+        for seed in seeds {
+            if visited[seed.id as usize] {
+                continue;
+            }
+            visited[seed.id as usize] = true;
+            visited_count += 1;
+            candidates.push(Candidate::new(seed.id, -seed.distance));
+            if results.len() < ef_search.max(1) {
+                results.push(seed);
+            }
+        }
 
         // Track worst distance in results for early termination
-        let mut worst_dist = start_dist;
+        let mut worst_dist = results.iter().map(|c| c.distance).fold(f32::NEG_INFINITY, f32::max);
 
         while let Some(current) = candidates.pop() {
+            if stop() {
+                break;
+            }
             // Reverse because BinaryHeap is max-heap
             let current = Candidate::new(current.id, -current.distance);
             let current_dist = current.distance;
@@ -197,9 +765,10 @@ where
                         continue;
                     }
                     visited[nid] = true;
+                    visited_count += 1;
 
-                    if let Some(neighbor) = self.get(neighbor_id) {
-                        let dist = Self::distance_to_vector(neighbor, target);
+                    if self.get(neighbor_id).is_some() {
+                        let dist = self.distance_to_vector(neighbor_id, target);
 
                         // Add to candidates (negate for min-heap behavior)
                         candidates.push(Candidate::new(neighbor_id, -dist));
@@ -223,6 +792,8 @@ where
             }
         }
 
+        NODES_VISITED.with(|n| n.set(n.get() + visited_count));
+
         // Sort by distance
         results.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
         results
@@ -248,17 +819,17 @@ where
                 break;
             }
 
-            let candidate_node = match self.get(candidate.id) {
-                Some(n) => n,
-                None => continue,
-            };
+            if self.get(candidate.id).is_none() {
+                continue;
+            }
+            let candidate_vector = self.slot(candidate.id);
 
             // Check if candidate is redundant with already selected neighbors
             let is_redundant = selected.iter().any(|&selected_id| {
-                self.get(selected_id).map_or(false, |selected_node| {
+                self.get(selected_id).is_some_and(|_| {
                     // Rule: prune if dist(target, candidate) >= (1/alpha) * dist(selected, candidate)
-                    let dist_to_target = Self::distance(target_vector, &candidate_node.vector);
-                    let dist_to_selected = Self::distance(&selected_node.vector, &candidate_node.vector);
+                    let dist_to_target = self.distance(target_vector, candidate_vector);
+                    let dist_to_selected = self.distance(self.slot(selected_id), candidate_vector);
 
                     dist_to_target >= (1.0 / alpha) * dist_to_selected
                 })
@@ -273,8 +844,10 @@ where
     }
 
     /// Insert a new vector into the graph.
-    /// Returns the ID of the newly inserted node.
-    pub fn insert(&mut self, vector: Vec<T>) -> NodeId {
+    /// Returns the ID of the newly inserted node. The vector is copied into
+    /// the arena, so callers can pass a borrowed slice.
+    pub fn insert(&mut self, vector: impl AsRef<[T]>) -> NodeId {
+        let vector = vector.as_ref();
         let max_neighbors = self.config.max_neighbors;
         let alpha = self.config.alpha_strict;
         let search_buffer = self.config.search_buffer;
@@ -283,22 +856,24 @@ where
         let node_id = self.allocate_node_id();
 
         // Create new node
-        let mut new_node = Node::with_capacity(vector.clone(), max_neighbors);
+        let mut new_node = Node::with_capacity(max_neighbors);
+        self.set_slot(node_id, vector);
 
         // Update centroid
-        self.update_centroid_insert(&vector);
+        self.update_centroid_insert(vector);
 
         // If this is the first node, just add it
         if self.active_count == 1 {
             self.insert_node_at(node_id, new_node);
+            self.track_entry_points(&[node_id]);
             return node_id;
         }
 
         // Search for candidates
-        let candidates = self.search(&vector, search_buffer);
+        let candidates = self.search(vector, search_buffer);
 
         // Prune candidates to get neighbors
-        let neighbors = self.robust_prune(&vector, &candidates, alpha, max_neighbors);
+        let neighbors = self.robust_prune(vector, &candidates, alpha, max_neighbors);
 
         // Set neighbors for new node
         new_node.neighbors = neighbors.clone();
@@ -323,6 +898,7 @@ where
             self.reverse_prune(neighbor_id, max_neighbors, alpha);
         }
 
+        self.track_entry_points(&[node_id]);
         node_id
     }
 
@@ -332,7 +908,7 @@ where
     /// 2. Computes centroid in bulk
     /// 3. Defers neighbor linking until all nodes are added
     /// Returns the IDs of all newly inserted nodes.
-    pub fn insert_batch(&mut self, vectors: Vec<Vec<T>>) -> Vec<NodeId> {
+    pub fn insert_batch<V: AsRef<[T]>>(&mut self, vectors: Vec<V>) -> Vec<NodeId> {
         if vectors.is_empty() {
             return Vec::new();
         }
@@ -357,7 +933,7 @@ where
         let n_total = self.active_count;
 
         // Insert all nodes first (without connecting edges)
-        for (i, vector) in vectors.into_iter().enumerate() {
+        for (i, vector) in vectors.iter().map(AsRef::as_ref).enumerate() {
             let node_id = node_ids[i];
 
             // Update centroid incrementally
@@ -371,12 +947,17 @@ where
                 });
 
             // Create node without neighbors
-            let node = Node::with_capacity(vector, max_neighbors);
-            self.nodes.push(node);
+            self.nodes.push(Node::with_capacity(max_neighbors));
+            self.set_slot(node_id, vector);
         }
 
-        // If this is the first batch, just return - no edges to connect
+        // A first batch has no existing nodes to search: link its nodes one
+        // after another, each to the ones before it
         if n_existing == 0 {
+            for &node_id in &node_ids[1..] {
+                self.link(node_id, max_neighbors, alpha, search_buffer);
+            }
+            self.track_entry_points(&node_ids);
             return node_ids;
         }
 
@@ -385,13 +966,13 @@ where
         let mut edge_updates: Vec<(NodeId, Vec<NodeId>)> = Vec::with_capacity(batch_size);
 
         for &node_id in &node_ids {
-            let vector = self.nodes[node_id as usize].vector.as_ref().clone();
+            let vector = self.slot(node_id);
 
             // Search for candidates among existing nodes
-            let candidates = self.search(&vector, search_buffer);
+            let candidates = self.search(vector, search_buffer);
 
             // Prune candidates to get neighbors
-            let neighbors = self.robust_prune(&vector, &candidates, alpha, max_neighbors);
+            let neighbors = self.robust_prune(vector, &candidates, alpha, max_neighbors);
             edge_updates.push((node_id, neighbors));
         }
 
@@ -436,9 +1017,106 @@ where
             self.reverse_prune(neighbor_id, max_neighbors, alpha);
         }
 
+        self.track_entry_points(&node_ids);
         node_ids
     }
 
+    /// Connect a stored node that has no edges yet to its nearest
+    /// reachable nodes, and them back to it
+    fn link(&mut self, node_id: NodeId, max_neighbors: usize, alpha: f32, search_buffer: usize) {
+        let vector = self.slot(node_id);
+        let candidates = self.search(vector, search_buffer);
+        let neighbors = self.robust_prune(vector, &candidates, alpha, max_neighbors);
+
+        let mut to_prune = Vec::new();
+        for &neighbor_id in &neighbors {
+            if let Some(neighbor) = self.get_mut(neighbor_id) {
+                neighbor.add_neighbor(node_id);
+                if neighbor.neighbors.len() > max_neighbors {
+                    to_prune.push(neighbor_id);
+                }
+            }
+        }
+        self.nodes[node_id as usize].neighbors = neighbors;
+        for neighbor_id in to_prune {
+            self.reverse_prune(neighbor_id, max_neighbors, alpha);
+        }
+    }
+
+    /// Merge a separately built graph (e.g. one built on another thread)
+    /// into this one. Segment nodes keep their edges to each other and are
+    /// linked to nearby existing nodes; the searches for those links run on
+    /// up to `threads` threads. Returns the new ID of each live segment node,
+    /// in segment order.
+    pub fn merge(&mut self, segment: &Graph<T, D>, threads: usize) -> Vec<NodeId>
+    where
+        T: Sync,
+    {
+        let max_neighbors = self.config.max_neighbors;
+        let alpha = self.config.alpha_strict;
+        let search_buffer = self.config.search_buffer;
+
+        let segment_ids: Vec<NodeId> = (0..segment.nodes.len() as NodeId)
+            .filter(|&id| segment.get(id).is_some())
+            .collect();
+
+        // Nearest existing nodes, found before any segment node is added
+        let existing: Vec<Vec<Candidate>> = parallel_map(&segment_ids, threads, |&id| {
+            self.search(segment.slot(id), search_buffer)
+        });
+
+        let mut new_ids = HashMap::with_capacity(segment_ids.len());
+        for &id in &segment_ids {
+            let node_id = self.allocate_node_id();
+            self.insert_node_at(node_id, Node::with_capacity(max_neighbors));
+            self.set_slot(node_id, segment.slot(id));
+            self.update_centroid_insert(segment.slot(id));
+            new_ids.insert(id, node_id);
+        }
+
+        // Choose each node's neighbors among its segment edges and the
+        // existing nodes near it
+        let inputs: Vec<(NodeId, &Vec<Candidate>)> = segment_ids.iter().zip(&existing).map(|(&id, c)| (id, c)).collect();
+        let neighbors: Vec<Vec<NodeId>> = parallel_map(&inputs, threads, |&(id, existing)| {
+            let vector = segment.slot(id);
+            let mut candidates = existing.clone();
+            candidates.extend(segment.nodes[id as usize].neighbors.iter()
+                .filter_map(|n| new_ids.get(n))
+                .map(|&n| Candidate::new(n, self.distance_to_vector(n, vector))));
+            candidates.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+            self.robust_prune(vector, &candidates, alpha, max_neighbors)
+        });
+
+        // Link both ways, then trim nodes that gained too many edges, in
+        // id order so the result doesn't depend on hashing
+        let mut to_prune = BTreeSet::new();
+        for (&id, neighbors) in segment_ids.iter().zip(neighbors) {
+            let node_id = new_ids[&id];
+            for &neighbor_id in &neighbors {
+                if let Some(neighbor) = self.get_mut(neighbor_id) {
+                    neighbor.add_neighbor(node_id);
+                    if neighbor.neighbors.len() > max_neighbors {
+                        to_prune.insert(neighbor_id);
+                    }
+                }
+            }
+            let node = &mut self.nodes[node_id as usize];
+            for neighbor_id in neighbors {
+                node.add_neighbor(neighbor_id);
+            }
+            if node.neighbors.len() > max_neighbors {
+                to_prune.insert(node_id);
+            }
+        }
+        for node_id in to_prune {
+            self.reverse_prune(node_id, max_neighbors, alpha);
+        }
+
+        let merged: Vec<NodeId> = segment_ids.iter().map(|id| new_ids[id]).collect();
+        self.track_entry_points(&merged);
+        merged
+    }
+
     /// Reverse prune a node that may have too many neighbors.
     fn reverse_prune(&mut self, node_id: NodeId, max_neighbors: usize, alpha: f32) {
         let neighbor_ids: Vec<NodeId> = match self.get(node_id) {
@@ -451,26 +1129,16 @@ where
         }
 
         // Compute distances to all neighbors
-        let candidates: Vec<Candidate> = {
-            let node = match self.get(node_id) {
-                Some(n) => n,
-                None => return,
-            };
-
-            neighbor_ids
-                .iter()
-                .filter_map(|&nid| {
-                    self.get(nid).map(|n| {
-                        let dist = Self::distance(&node.vector, &n.vector);
-                        Candidate::new(nid, dist)
-                    })
-                })
-                .collect()
-        };
+        let node_vector = self.slot(node_id);
+        let candidates: Vec<Candidate> = neighbor_ids
+            .iter()
+            .filter_map(|&nid| {
+                self.get(nid).map(|_| Candidate::new(nid, self.distance(node_vector, self.slot(nid))))
+            })
+            .collect();
 
         // Re-prune using the node's vector
-        let node_vector: Vec<T> = self.get(node_id).unwrap().vector.as_ref().clone();
-        let new_neighbors = self.robust_prune(&node_vector, &candidates, alpha, max_neighbors);
+        let new_neighbors = self.robust_prune(node_vector, &candidates, alpha, max_neighbors);
 
         // Update neighbor list
         if let Some(node) = self.get_mut(node_id) {
@@ -484,16 +1152,15 @@ where
 
         self.free_list.pop().unwrap_or_else(|| {
             let id = self.nodes.len() as NodeId;
-            self.nodes.push(Node::new(Vec::new())); // placeholder
+            self.nodes.push(Node::new()); // placeholder
             id
         })
     }
 
     /// Insert a node at a specific ID.
-    fn insert_node_at(&mut self, id: NodeId, node: Node<T>) {
+    fn insert_node_at(&mut self, id: NodeId, node: Node) {
         if id as usize >= self.nodes.len() {
-            self.nodes
-                .resize_with((id + 1) as usize, || Node::new(Vec::new()));
+            self.nodes.resize_with((id + 1) as usize, Node::new);
         }
         self.nodes[id as usize] = node;
     }
@@ -501,8 +1168,8 @@ where
     /// Delete a node by ID.
     /// Returns true if successful.
     pub fn delete(&mut self, id: NodeId) -> bool {
-        let vector = match self.get(id) {
-            Some(n) => n.vector.as_ref().clone(),
+        let vector = match self.vector(id) {
+            Some(v) => v.to_vec(),
             None => return false,
         };
 
@@ -518,6 +1185,8 @@ where
                 neighbor.remove_neighbor(id);
             }
         }
+        self.repair_queue.extend(neighbors);
+        self.entry_points.retain(|&ep| ep != id);
 
         // Update centroid
         self.update_centroid_delete(&vector);
@@ -530,10 +1199,176 @@ where
         // Add to free list
         self.free_list.push(id);
         self.active_count -= 1;
+        self.deletes_since_repair += 1;
 
         true
     }
 
+    /// Delete many nodes at once.
+    ///
+    /// All nodes are marked first and neighbor repair runs once at the end:
+    /// every surviving node that pointed at a deleted node is re-pruned from
+    /// its remaining neighbors plus the deleted nodes' neighbors, so the graph
+    /// stays connected without re-walking neighbor lists per deletion.
+    /// Returns the number of nodes deleted.
+    pub fn delete_batch(&mut self, ids: &[NodeId]) -> usize {
+        let mut deleted: HashMap<NodeId, Vec<NodeId>> = HashMap::with_capacity(ids.len());
+
+        // Mark phase
+        for &id in ids {
+            let (vector, neighbors) = match self.get(id) {
+                Some(n) => (self.slot(id).to_vec(), n.neighbors.clone()),
+                None => continue,
+            };
+
+            self.update_centroid_delete(&vector);
+            if let Some(node) = self.nodes.get_mut(id as usize) {
+                node.mark_deleted();
+            }
+            self.free_list.push(id);
+            self.active_count -= 1;
+            self.deletes_since_repair += 1;
+            deleted.insert(id, neighbors);
+        }
+
+        if deleted.is_empty() {
+            return 0;
+        }
+        self.entry_points.retain(|ep| !deleted.contains_key(ep));
+
+        // Repair phase: one pass over surviving nodes with dangling edges
+        let max_neighbors = self.config.max_neighbors;
+        let alpha = self.config.alpha_relaxed;
+
+        let affected: Vec<NodeId> = self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| !n.deleted && n.neighbors.iter().any(|nid| deleted.contains_key(nid)))
+            .map(|(id, _)| id as NodeId)
+            .collect();
+
+        for node_id in affected {
+            let node = &self.nodes[node_id as usize];
+
+            let mut candidate_ids: HashSet<NodeId> = HashSet::new();
+            for &nid in &node.neighbors {
+                match deleted.get(&nid) {
+                    Some(second_hop) => candidate_ids.extend(second_hop.iter().copied()),
+                    None => {
+                        candidate_ids.insert(nid);
+                    }
+                }
+            }
+            candidate_ids.remove(&node_id);
+
+            let node_vector = self.slot(node_id);
+            let mut candidates: Vec<Candidate> = candidate_ids
+                .into_iter()
+                .filter_map(|nid| {
+                    self.get(nid).map(|_| Candidate::new(nid, self.distance(node_vector, self.slot(nid))))
+                })
+                .collect();
+            candidates.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+
+            let new_neighbors = self.robust_prune(node_vector, &candidates, alpha, max_neighbors);
+            self.nodes[node_id as usize].neighbors = new_neighbors;
+        }
+
+        deleted.len()
+    }
+
+    /// Number of deletions since the graph was last repaired.
+    pub fn deletes_since_repair(&self) -> usize {
+        self.deletes_since_repair
+    }
+
+    /// Check whether enough nodes were deleted that a `repair()` is worthwhile
+    /// (at least 10% of the live nodes).
+    pub fn needs_repair(&self) -> bool {
+        self.deletes_since_repair > 0 && self.deletes_since_repair * 10 >= self.active_count.max(1)
+    }
+
+    /// Reconnect nodes that lost edges to deletions.
+    ///
+    /// Targets nodes that were neighbors of a deleted node or still point at
+    /// one. Each gets a fresh candidate search and a relaxed robust prune,
+    /// and is back-linked from its new neighbors, which restores
+    /// navigability (and recall) after heavy churn.
+    /// Returns the number of nodes repaired.
+    pub fn repair(&mut self) -> usize {
+        self.repair_with_progress(&mut |_, _| true)
+    }
+
+    /// `repair`, calling `progress(done, total)` after each repaired node.
+    /// If it returns false the repair stops early and the remaining nodes
+    /// stay queued for the next repair.
+    pub fn repair_with_progress(&mut self, progress: &mut dyn FnMut(usize, usize) -> bool) -> usize {
+        let max_neighbors = self.config.max_neighbors;
+        let alpha = self.config.alpha_relaxed;
+        let search_buffer = self.config.search_buffer;
+
+        self.deletes_since_repair = 0;
+        let queued = std::mem::take(&mut self.repair_queue);
+        if self.active_count < 2 {
+            return 0;
+        }
+
+        let targets: Vec<NodeId> = self.nodes
+            .iter()
+            .enumerate()
+            .filter(|&(id, n)| {
+                !n.deleted
+                    && (queued.contains(&(id as NodeId))
+                        || n.neighbors.iter().any(|&nid| self.get(nid).is_none()))
+            })
+            .map(|(id, _)| id as NodeId)
+            .collect();
+
+        for (done, &node_id) in targets.iter().enumerate() {
+            if done > 0 && !progress(done, targets.len()) {
+                self.repair_queue.extend(&targets[done..]);
+                self.refresh_entry_points();
+                return done;
+            }
+            let vector = self.slot(node_id);
+
+            let mut candidates: Vec<Candidate> = self.search(vector, search_buffer)
+                .into_iter()
+                .filter(|c| c.id != node_id)
+                .collect();
+
+            // Keep existing live neighbors in the running
+            for &nid in &self.nodes[node_id as usize].neighbors {
+                if candidates.iter().any(|c| c.id == nid) {
+                    continue;
+                }
+                if self.get(nid).is_some() {
+                    candidates.push(Candidate::new(nid, self.distance(vector, self.slot(nid))));
+                }
+            }
+            candidates.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+
+            let neighbors = self.robust_prune(vector, &candidates, alpha, max_neighbors);
+            self.nodes[node_id as usize].neighbors = neighbors.clone();
+
+            for neighbor_id in neighbors {
+                let overflow = match self.get_mut(neighbor_id) {
+                    Some(neighbor) => {
+                        neighbor.add_neighbor(node_id);
+                        neighbor.neighbors.len() > max_neighbors
+                    }
+                    None => false,
+                };
+                if overflow {
+                    self.reverse_prune(neighbor_id, max_neighbors, alpha);
+                }
+            }
+        }
+
+        self.refresh_entry_points();
+        targets.len()
+    }
+
     /// Update a node's vector (delete + reinsert).
     /// Returns true if successful.
     pub fn update(&mut self, id: NodeId, new_vector: Vec<T>) -> bool {
@@ -549,12 +1384,147 @@ where
     /// Query for k nearest neighbors.
     pub fn query(&self, vector: &[T], k: usize, ef_search: usize) -> Vec<Candidate> {
         let ef = ef_search.max(k);
-        let mut results = self.search(vector, ef);
+        // Queries stop early when the running statement is interrupted
+        let mut results = self.search_until(vector, ef, &interrupted);
         results.truncate(k);
         results
     }
 }
 
+/// Exact k-nearest-neighbor search by scanning every vector.
+/// Returned candidate IDs are indices into `vectors`.
+/// Apply `f` to every item on up to `threads` scoped threads, keeping order
+fn parallel_map<I, O, F>(items: &[I], threads: usize, f: F) -> Vec<O>
+where
+    I: Sync,
+    O: Send,
+    F: Fn(&I) -> O + Sync,
+{
+    let threads = threads.clamp(1, items.len().max(1));
+    if threads == 1 {
+        return items.iter().map(f).collect();
+    }
+    let chunk_size = items.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items.chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| chunk.iter().map(&f).collect::<Vec<O>>()))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+pub fn brute_force<T, D>(vectors: &[Vec<T>], query: &[T], k: usize) -> Vec<Candidate>
+where
+    T: Numeric,
+    D: Distance<T>,
+{
+    let mut results: Vec<Candidate> = vectors
+        .iter()
+        .enumerate()
+        .map(|(i, v)| Candidate::new(i as NodeId, D::compute(query, v)))
+        .collect();
+    results.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+    results.truncate(k);
+    results
+}
+
+/// Fraction of the true top-k neighbors present in `results`.
+pub fn recall_at_k(results: &[Candidate], ground_truth: &[Candidate]) -> f64 {
+    if ground_truth.is_empty() {
+        return 1.0;
+    }
+    let truth: HashSet<NodeId> = ground_truth.iter().map(|c| c.id).collect();
+    let hits = results.iter().filter(|c| truth.contains(&c.id)).count();
+    hits as f64 / ground_truth.len() as f64
+}
+
+/// Build a graph over `vectors` with `config` and measure mean recall@k of
+/// `queries` against brute force, searching with `config.search_buffer`.
+pub fn measure_recall<D: Distance<f32>>(
+    config: &GraphConfig,
+    vectors: &[Vec<f32>],
+    queries: &[Vec<f32>],
+    k: usize,
+) -> f64 {
+    if vectors.is_empty() || queries.is_empty() {
+        return 1.0;
+    }
+
+    let mut graph: Graph<f32, D> = Graph::new(vectors[0].len(), config.clone());
+    for vector in vectors {
+        graph.insert(vector.clone());
+    }
+
+    let total: f64 = queries
+        .iter()
+        .map(|query| {
+            let truth = brute_force::<f32, D>(vectors, query, k);
+            let found = graph.query(query, k, config.search_buffer);
+            recall_at_k(&found, &truth)
+        })
+        .sum();
+    total / queries.len() as f64
+}
+
+/// Recommend a Euclidean `GraphConfig` for data like `vectors`.
+/// See `autotune_with_metric`.
+pub fn autotune(vectors: &[Vec<f32>], target_recall: f64) -> GraphConfig {
+    autotune_with_metric::<crate::distance::Euclidean>(vectors, target_recall)
+}
+
+/// Recommend the cheapest `GraphConfig` that reaches `target_recall` at k=10.
+///
+/// Sweeps max_neighbors, search_buffer and alpha on a sample of at most
+/// 2000 vectors (every 10th held out as a query). If no setting reaches the
+/// target, the one with the best recall is returned.
+pub fn autotune_with_metric<D: Distance<f32>>(vectors: &[Vec<f32>], target_recall: f64) -> GraphConfig {
+    const SAMPLE: usize = 2000;
+    const K: usize = 10;
+
+    let stride = vectors.len().div_ceil(SAMPLE).max(1);
+    let sample: Vec<&Vec<f32>> = vectors.iter().step_by(stride).collect();
+
+    let (queries, base): (Vec<_>, Vec<_>) = sample
+        .into_iter()
+        .enumerate()
+        .partition(|(i, _)| i % 10 == 9);
+    let queries: Vec<Vec<f32>> = queries.into_iter().map(|(_, v)| v.clone()).collect();
+    let base: Vec<Vec<f32>> = base.into_iter().map(|(_, v)| v.clone()).collect();
+
+    if base.len() <= K || queries.is_empty() {
+        return GraphConfig::default();
+    }
+
+    // Candidates ordered from cheapest to most expensive
+    let mut candidates: Vec<GraphConfig> = Vec::new();
+    for &max_neighbors in &[8, 12, 16, 24, 32] {
+        for &search_buffer in &[32, 64, 128, 256] {
+            for &alpha in &[1.0, 1.2] {
+                candidates.push(GraphConfig {
+                    max_neighbors,
+                    alpha_strict: alpha,
+                    alpha_relaxed: alpha.max(1.2),
+                    search_buffer,
+                    ..GraphConfig::default()
+                });
+            }
+        }
+    }
+    candidates.sort_by_key(|c| c.max_neighbors * c.search_buffer);
+
+    let mut best = (GraphConfig::default(), f64::NEG_INFINITY);
+    for config in candidates {
+        let recall = measure_recall::<D>(&config, &base, &queries, K);
+        if recall >= target_recall {
+            return config;
+        }
+        if recall > best.1 {
+            best = (config, recall);
+        }
+    }
+    best.0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -611,4 +1581,255 @@ mod tests {
         assert!(graph.delete(id));
         assert_eq!(graph.len(), 0);
     }
+
+    #[test]
+    fn test_delete_batch_keeps_graph_searchable() {
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+
+        for i in 0..200 {
+            graph.insert(vec![(i % 20) as f32, (i / 20) as f32]);
+        }
+
+        // Delete every other node
+        let to_delete: Vec<NodeId> = (0..200).step_by(2).collect();
+        assert_eq!(graph.delete_batch(&to_delete), 100);
+        assert_eq!(graph.len(), 100);
+
+        // Deleting again is a no-op
+        assert_eq!(graph.delete_batch(&to_delete), 0);
+
+        // No surviving node points at a deleted node
+        for id in (1..200).step_by(2) {
+            let node = graph.get(id).unwrap();
+            assert!(node.neighbors.iter().all(|&nid| graph.get(nid).is_some()));
+        }
+
+        // Every surviving node is still found by an exact-match query
+        for id in (1..200).step_by(2) {
+            let vector = graph.vector(id).unwrap().to_vec();
+            let results = graph.query(&vector, 1, 64);
+            assert_eq!(results[0].distance, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_edge_stats() {
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+        assert_eq!(graph.edge_stats(), EdgeStats::default());
+        graph.insert(vec![0.0, 0.0]);
+        assert_eq!(graph.edge_stats().isolated, 1);
+
+        for i in 1..100 {
+            graph.insert(vec![(i % 10) as f32, (i / 10) as f32]);
+        }
+        let stats = graph.edge_stats();
+        assert_eq!((stats.nodes, stats.isolated, stats.dangling), (100, 0, 0));
+        assert!(stats.min_degree >= 1 && stats.max_degree <= GraphConfig::default().max_neighbors);
+        assert!((stats.mean_degree() - stats.edges as f64 / 100.0).abs() < 1e-9);
+
+        graph.delete(5);
+        let stats = graph.edge_stats();
+        assert_eq!((stats.nodes, stats.free_slots), (99, 1));
+    }
+
+    #[test]
+    fn test_graph_stats() {
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+        assert_eq!(graph.graph_stats(), GraphStats::default());
+        for i in 0..100 {
+            graph.insert(vec![(i % 10) as f32, (i / 10) as f32]);
+        }
+        let stats = graph.graph_stats();
+        assert_eq!((stats.components, stats.largest_component, stats.unreachable), (1, 100, 0));
+        assert_eq!(stats.degree_histogram.iter().sum::<usize>(), 100);
+        assert_eq!(stats.degree_histogram.len(), stats.edges.max_degree + 1);
+        assert!(stats.mean_neighbor_distance >= 1.0 && stats.mean_neighbor_distance < 10.0);
+
+        // Cut a node loose: it forms its own component no search reaches
+        let loose = (0..100).find(|id| !graph.entry_points().contains(id)).unwrap();
+        for id in 0..100 {
+            graph.get_mut(id).unwrap().neighbors.retain(|&n| n != loose);
+        }
+        graph.get_mut(loose).unwrap().neighbors.clear();
+        let stats = graph.graph_stats();
+        assert_eq!((stats.components, stats.largest_component, stats.unreachable), (2, 99, 1));
+        assert_eq!(stats.degree_histogram[0], 1);
+        assert_eq!(graph.components()[1], vec![loose]);
+        assert_eq!(graph.unreachable(), vec![loose]);
+    }
+
+    #[test]
+    fn test_insert_batch_into_empty_graph() {
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+        let grid: Vec<Vec<f32>> = (0..200).map(|i| vec![(i % 20) as f32, (i / 20) as f32]).collect();
+        assert_eq!(graph.insert_batch(grid.clone()).len(), 200);
+
+        let stats = graph.graph_stats();
+        assert_eq!((stats.components, stats.unreachable, stats.edges.isolated), (1, 0, 0));
+        for vector in &grid {
+            assert_eq!(graph.query(vector, 1, 64)[0].distance, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_bridge_components() {
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+        assert_eq!(graph.bridge_components(), 0);
+        for i in 0..100 {
+            graph.insert(vec![(i % 10) as f32, (i / 10) as f32]);
+        }
+        assert_eq!(graph.bridge_components(), 0);
+
+        // Two clusters whose nodes lost all their edges, as a batch left
+        // unlinked would be
+        let far: Vec<NodeId> = (0..20).map(|i| graph.insert(vec![100.0 + (i % 5) as f32, (i / 5) as f32])).collect();
+        for &id in &far {
+            graph.get_mut(id).unwrap().neighbors.clear();
+        }
+        for id in 0..100 {
+            graph.get_mut(id).unwrap().neighbors.retain(|n| !far.contains(n));
+        }
+        assert_eq!(graph.unreachable().len(), 20);
+        assert!(graph.query(&[102.0, 1.0], 1, 64)[0].distance > 1.0);
+
+        let added = graph.bridge_components();
+        assert!(added > 0 && added <= 20 * 2 * BRIDGE_EDGES);
+        assert!(graph.unreachable().is_empty());
+        assert_eq!(graph.components().len(), 1);
+        assert_eq!(graph.query(&[102.0, 1.0], 1, 64)[0].distance, 0.0);
+        assert_eq!(graph.bridge_components(), 0);
+    }
+
+    #[test]
+    fn test_warmup() {
+        let mut graph: Graph<f32, Euclidean> = Graph::new(8, GraphConfig::default());
+        assert_eq!(graph.warmup(), 0);
+        for i in 0..300 {
+            graph.insert(vec![i as f32; 8]);
+        }
+        assert_eq!(graph.warmup(), graph.memory_usage());
+        assert!(graph.warmup() >= 300 * 8 * size_of::<f32>());
+    }
+
+    #[test]
+    fn test_merge_segments() {
+        let grid = |range: std::ops::Range<i32>| range.map(|i| vec![(i % 20) as f32, (i / 20) as f32]).collect::<Vec<_>>();
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+        for vector in grid(0..100) {
+            graph.insert(vector);
+        }
+
+        let mut segment: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+        for vector in grid(100..300) {
+            segment.insert(vector);
+        }
+        segment.delete(0);
+
+        let ids = graph.merge(&segment, 3);
+        assert_eq!(ids.len(), 199);
+        assert_eq!(graph.len(), 299);
+        assert_eq!(graph.vector(ids[0]), segment.vector(1));
+
+        // Merged nodes are reachable from old ones and vice versa
+        for id in (0..100).chain(ids.iter().copied()) {
+            let node = graph.get(id).unwrap();
+            assert!(node.neighbors.len() <= GraphConfig::default().max_neighbors);
+            let vector = graph.vector(id).unwrap().to_vec();
+            assert_eq!(graph.query(&vector, 1, 64)[0].distance, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_autotune() {
+        let vectors: Vec<Vec<f32>> = (0..400)
+            .map(|i| vec![(i % 20) as f32, (i / 20) as f32, ((i * 7) % 13) as f32])
+            .collect();
+
+        let config = autotune(&vectors, 0.9);
+        let queries: Vec<Vec<f32>> = vectors.iter().step_by(17).cloned().collect();
+        assert!(measure_recall::<Euclidean>(&config, &vectors, &queries, 10) >= 0.9);
+
+        // Too few vectors to tune falls back to the default
+        let config = autotune(&vectors[..5], 0.9);
+        assert_eq!(config.max_neighbors, GraphConfig::default().max_neighbors);
+    }
+
+    #[test]
+    fn test_repair_after_churn() {
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+
+        for i in 0..300 {
+            graph.insert(vec![(i % 20) as f32, (i / 20) as f32]);
+        }
+
+        // Single deletes leave dangling in-edges behind
+        for id in 0..300 {
+            if id % 3 != 0 {
+                graph.delete(id);
+            }
+        }
+        assert_eq!(graph.deletes_since_repair(), 200);
+        assert!(graph.needs_repair());
+
+        assert!(graph.repair() > 0);
+        assert!(!graph.needs_repair());
+
+        for id in (0..300).step_by(3) {
+            let node = graph.get(id).unwrap();
+            assert!(!node.neighbors.is_empty());
+            assert!(node.neighbors.iter().all(|&nid| graph.get(nid).is_some()));
+
+            let vector = graph.vector(id).unwrap().to_vec();
+            let results = graph.query(&vector, 1, 64);
+            assert_eq!(results[0].distance, 0.0);
+        }
+
+        // Nothing left to fix
+        assert_eq!(graph.repair(), 0);
+    }
+
+    #[test]
+    fn test_entry_points_cover_clusters() {
+        let corners = [[0.0, 0.0], [100.0, 0.0], [0.0, 100.0], [100.0, 100.0]];
+        let mut graph: Graph<f32, Euclidean> = Graph::new(2, GraphConfig::default());
+        for i in 0..200 {
+            let [x, y] = corners[i % 4];
+            let jitter = (i / 4) as f32 * 0.1;
+            graph.insert(vec![x + jitter, y - jitter]);
+        }
+
+        // Every cluster has an entry point
+        let cluster = |id: NodeId| id as usize % 4;
+        let covered: HashSet<usize> = graph.entry_points().iter().map(|&id| cluster(id)).collect();
+        assert_eq!(covered.len(), 4);
+
+        for (c, &[x, y]) in corners.iter().enumerate() {
+            let results = graph.query(&[x + 1.0, y - 1.0], 5, 16);
+            assert!(results.iter().all(|r| cluster(r.id) == c));
+        }
+
+        // Deleted nodes stop being entry points
+        let first = graph.entry_points()[0];
+        graph.delete(first);
+        assert!(!graph.entry_points().contains(&first));
+    }
+
+    #[test]
+    fn test_seeded_builds_are_reproducible() {
+        let build = |seed: Option<u64>| {
+            let config = GraphConfig { seed, ..GraphConfig::default() };
+            let mut graph: Graph<f32, Euclidean> = Graph::new(2, config);
+            for i in 0..3000 {
+                graph.insert(vec![(i * 37 % 101) as f32, (i * 53 % 89) as f32]);
+            }
+            graph
+        };
+
+        // Without a seed, builds are reproducible too
+        for seed in [Some(7), None] {
+            let (a, b) = (build(seed), build(seed));
+            assert_eq!(a.entry_points(), b.entry_points());
+            assert!((0..3000).all(|id| a.get(id).unwrap().neighbors == b.get(id).unwrap().neighbors));
+        }
+    }
 }
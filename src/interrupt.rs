@@ -0,0 +1,141 @@
+//! Cooperative cancellation of running statements.
+//!
+//! While a `Database` executes a statement, its interrupt flag is current
+//! for the executing thread. Long loops (table scans, graph searches, bulk
+//! inserts) poll `interrupted()` at safe points and stop early; the database
+//! then returns `MarsError::Interrupted` instead of a partial result.
+//! A statement deadline (see `Database::set_statement_timeout`) is checked
+//! at the same points and reported as `MarsError::Timeout`.
+
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// The clock is read once per this many polls
+const DEADLINE_POLL_INTERVAL: u32 = 64;
+
+/// Cancellation state of the statement running on a thread
+struct Current {
+    flag: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    polls: Cell<u32>,
+    expired: Cell<bool>,
+}
+
+impl Current {
+    fn stopped(&self) -> bool {
+        if self.flag.load(Ordering::Relaxed) || self.expired.get() {
+            return true;
+        }
+        if let Some(deadline) = self.deadline {
+            let polls = self.polls.get();
+            self.polls.set(polls.wrapping_add(1));
+            if polls.is_multiple_of(DEADLINE_POLL_INTERVAL) && Instant::now() >= deadline {
+                self.expired.set(true);
+            }
+        }
+        self.expired.get()
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Current>> = const { RefCell::new(None) };
+}
+
+/// Check whether the statement running on this thread has been interrupted
+/// or has run past its deadline
+pub fn interrupted() -> bool {
+    CURRENT.with(|c| c.borrow().as_ref().is_some_and(Current::stopped))
+}
+
+/// Cancels the statement running on a database. Cloneable and usable from
+/// any thread, e.g. a Ctrl-C handler.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Make the running statement stop at its next checkpoint. Has no effect
+    /// on statements started afterwards.
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.flag.store(false, Ordering::Relaxed);
+    }
+
+    /// Make this flag, and an optional deadline, current for the calling
+    /// thread until the guard drops
+    pub(crate) fn enter(&self, deadline: Option<Instant>) -> InterruptScope {
+        let current = Current {
+            flag: Arc::clone(&self.flag),
+            deadline,
+            polls: Cell::new(0),
+            expired: Cell::new(false),
+        };
+        let previous = CURRENT.with(|c| c.replace(Some(current)));
+        InterruptScope { previous }
+    }
+}
+
+/// Restores the previous cancellation state on drop
+pub(crate) struct InterruptScope {
+    previous: Option<Current>,
+}
+
+impl InterruptScope {
+    /// Check whether a checkpoint found the deadline passed
+    pub(crate) fn timed_out(&self) -> bool {
+        CURRENT.with(|c| c.borrow().as_ref().is_some_and(|cur| cur.expired.get()))
+    }
+}
+
+impl Drop for InterruptScope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|c| *c.borrow_mut() = previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_scope() {
+        let handle = InterruptHandle::default();
+        handle.interrupt();
+        assert!(!interrupted());
+
+        {
+            let _scope = handle.enter(None);
+            assert!(interrupted());
+            handle.clear();
+            assert!(!interrupted());
+        }
+
+        handle.interrupt();
+        assert!(!interrupted());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let handle = InterruptHandle::default();
+        let scope = handle.enter(Some(Instant::now()));
+        assert!(interrupted());
+        assert!(scope.timed_out());
+        assert!(!handle.is_interrupted());
+        drop(scope);
+
+        let scope = handle.enter(Some(Instant::now() + std::time::Duration::from_secs(60)));
+        assert!(!interrupted());
+        assert!(!scope.timed_out());
+    }
+}
@@ -55,36 +55,82 @@
 //! # Ok::<(), pardusdb::MarsError>(())
 //! ```
 
+pub mod analyzer;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+mod cache;
+pub mod columnar;
+mod compression;
 pub mod concurrent;
+pub mod coordinator;
+mod crypto;
 pub mod database;
+mod delta;
 pub mod db;
 pub mod distance;
+mod engine;
 pub mod error;
+mod external;
+pub mod functions;
 pub mod graph;
+pub mod interrupt;
+mod lock;
 pub mod node;
 pub mod parser;
+pub mod planner;
+#[cfg(feature = "polars")]
+pub mod polars;
 pub mod prepared;
+pub mod projection;
+pub mod quantize;
+#[cfg(feature = "object-store")]
+mod remote;
 pub mod schema;
+mod sealed;
+pub mod sharded;
+pub mod sketch;
+pub mod stats;
 pub mod storage;
 pub mod table;
+mod uuid;
+pub mod view;
+#[cfg(feature = "watch")]
+mod watch;
 
 #[cfg(feature = "gpu")]
 pub mod gpu;
 
 // Re-exports for convenience
-pub use database::{Database, ExecuteResult, TableInfo};
-pub use db::{Config, SearchResult, VectorDB, CosineDB, DotProductDB, EuclideanDB};
-pub use distance::{Distance, Numeric, Cosine, DotProduct, Euclidean};
+pub use analyzer::{Analyzer, Language, Token};
+pub use columnar::ColumnStore;
+pub use database::{Database, ExecuteResult, IngestOptions, MergeOptions, Progress, ProgressHandler, QueryStats, TableIndex, TableInfo};
+pub use coordinator::{GatheredResults, SearchCoordinator, SearchShard};
+pub use db::{Config, DiskVectorDB, SearchResult, SegmentedVectorDB, SnapshotResults, VectorDB, CosineDB, DotProductDB, EuclideanDB};
+pub use distance::{Distance, Numeric, Cosine, DotProduct, DynamicMetric, Euclidean, Hamming, Manhattan, MetricFn, UnitCosine, lookup_metric, metric_score, register_metric};
 pub use error::{MarsError, Result};
-pub use graph::{Graph, GraphConfig};
+pub use functions::{ArithOp, ScalarExpr, ScalarFunc};
+pub use graph::{EdgeStats, Graph, GraphConfig, GraphStats};
+pub use interrupt::InterruptHandle;
 pub use node::{Candidate, Node, NodeId};
-pub use parser::{AggregateFunc, BoolConnector, Command, ComparisonOp, Condition, ConditionValue, ColumnDef, OrderBy, SelectColumn, WhereClause, parse};
-pub use prepared::{BatchInserter, PreparedStatement, StatementCache};
-pub use schema::{Column, ColumnType, Row, Schema, Value};
-pub use table::Table;
+pub use parser::{AggregateFunc, AlterAction, BoolConnector, Command, ComparisonOp, Condition, ConditionValue, ColumnDef, Diversity, OnConflict, OrderBy, ParseLimits, SelectColumn, VectorStorage, WhereClause, is_complete_statement, parse, parse_with_limits, split_statements};
+pub use planner::{AccessPath, QueryPlan};
+pub use prepared::{BatchInserter, PreparedStatement, StatementCache, StatementCacheStats};
+pub use projection::{Projection, ProjectionMethod};
+pub use quantize::{CalibrationReport, Quantizer};
+pub use schema::{AutoIncrement, Collation, Column, ColumnType, ForeignKey, FromValue, IntoVector, Mask, OnDelete, ResultColumn, ResultRow, ResultSchema, Row, Schema, Value};
+pub use sharded::{ShardedDatabase, ShardedResult};
+pub use sketch::HyperLogLog;
+pub use stats::{ColumnStats, TableStats};
+pub use storage::Synchronous;
+pub use table::{Duplicate, IngestRow, MemoryUsage, RecallReport, SearchCursor, SearchGroup, SearchPage, SelectOptions, Table};
+pub use uuid::Uuid;
+pub use view::{ViewAggregate, ViewColumn, ViewDef};
+
+#[cfg(feature = "object-store")]
+pub use object_store;
 
 #[cfg(feature = "gpu")]
 pub use gpu::{GpuDistance, GpuError};
 
 // Concurrent module re-exports
-pub use concurrent::{ConcurrentDatabase, Connection, DatabaseInner, DatabasePool, ScopedTransaction};
+pub use concurrent::{Consistency, ConcurrentDatabase, Connection, DatabaseInner, DatabasePool, PoolConfig, PoolStats, PooledConnection, RepairWorker, ScopedTransaction, SearchOptions, SnapshotSearch};
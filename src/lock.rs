@@ -0,0 +1,109 @@
+//! Advisory file locks between processes sharing a database file.
+//!
+//! A database opened for writing holds an exclusive lock on a `-lock` file
+//! next to it for as long as it is open, so a second writer fails to open
+//! instead of overwriting the first one's saves. The database file itself
+//! is locked shared while it is read and exclusively while it is written,
+//! so readers never see a half-written file.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{MarsError, Result};
+
+/// How long reads and saves wait for another process's lock by default
+pub(crate) const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Held while a database file is open for writing; released on drop
+pub(crate) struct WriterLock {
+    _file: File,
+}
+
+impl WriterLock {
+    /// Lock `path` for writing, failing at once if another writer has it
+    pub(crate) fn acquire(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path(path))?;
+        match file.try_lock() {
+            Ok(()) => Ok(WriterLock { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(MarsError::Locked(
+                format!("{} is already open for writing", path.display())
+            )),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+}
+
+/// `<path>-lock`
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("-lock");
+    PathBuf::from(name)
+}
+
+/// Lock an open database file shared (to read it) or exclusively (to write
+/// it), retrying for up to `timeout` while another process holds it. The
+/// lock is released when the file is closed.
+pub(crate) fn lock_file(file: &File, exclusive: bool, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let attempt = if exclusive { file.try_lock() } else { file.try_lock_shared() };
+        match attempt {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+            Err(TryLockError::WouldBlock) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(MarsError::Locked(
+                        format!("gave up after waiting {} ms for the file lock", timeout.as_millis())
+                    ));
+                }
+                thread::sleep((deadline - now).min(RETRY_INTERVAL));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.pardus");
+
+        let lock = WriterLock::acquire(&path).unwrap();
+        assert!(matches!(WriterLock::acquire(&path), Err(MarsError::Locked(_))));
+        drop(lock);
+        assert!(WriterLock::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn test_lock_file_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.pardus");
+        let open = || OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+
+        let (a, b) = (open(), open());
+        lock_file(&a, false, Duration::ZERO).unwrap();
+        lock_file(&b, false, Duration::ZERO).unwrap();
+        drop(b);
+
+        let c = open();
+        let start = Instant::now();
+        assert!(matches!(lock_file(&c, true, Duration::from_millis(50)), Err(MarsError::Locked(_))));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        drop(a);
+        lock_file(&c, true, Duration::ZERO).unwrap();
+    }
+}
@@ -1,20 +1,126 @@
 //! PardusDB - A single-file embedded vector database with SQL-like interface.
 
-use std::io::{self, Write};
+use std::io::IsTerminal;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
 use std::time::Instant;
 
-use pardusdb::{Database, ExecuteResult};
+use pardusdb::{Database, EdgeStats, ExecuteResult, InterruptHandle, Progress, Value, is_complete_statement, split_statements};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+const USAGE: &str = "\
+Usage: pardusdb [OPTIONS] [FILE]
+
+Opens FILE (or an in-memory database) in the interactive REPL.
+
+Options:
+  -c, --command <SQL>  Run SQL and exit; may be given several times
+      --init <SCRIPT>  Run a SQL script file before anything else
+      --json           Print results as JSON, one object per statement
+      --demo           Run the demo workload against FILE
+  -h, --help           Show this message";
+
+/// How statement results are printed
+#[derive(Clone, Copy, Debug, Default)]
+struct Output {
+    json: bool,
+    /// Print execution time per statement (`.timer on`)
+    timer: bool,
+    /// Print rows scanned and index used per query (`.stats on`)
+    stats: bool,
+}
+
+/// Command-line options
+#[derive(Debug, Default)]
+struct Options {
+    path: Option<String>,
+    commands: Vec<String>,
+    init: Option<String>,
+    json: bool,
+    demo: bool,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--command" => {
+                options.commands.push(args.next().ok_or("-c requires a SQL argument")?);
+            }
+            "--init" => options.init = Some(args.next().ok_or("--init requires a script path")?),
+            "--json" => options.json = true,
+            "--demo" => options.demo = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ if options.path.is_none() => options.path = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    Ok(options)
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if options.demo {
+        match options.path {
+            Some(ref path) => run_with_file(path),
+            None => eprintln!("Error: --demo requires a database file"),
+        }
+        return;
+    }
 
-    if args.len() > 1 {
-        let path = &args[1];
-        run_with_file(path);
-    } else {
-        run_repl();
+    let db = match options.path {
+        Some(ref path) => Database::open(path),
+        None => Ok(Database::in_memory()),
+    };
+    let mut db = match db {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Error opening database: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let output = Output { json: options.json, ..Output::default() };
+    attach_terminal(&mut db);
+
+    // Non-interactive: run the init script and commands, then exit with
+    // a non-zero status on the first error
+    let mut ok = true;
+    if let Some(ref script) = options.init {
+        ok = match std::fs::read_to_string(script) {
+            Ok(sql) => run_sql(&mut db, &sql, &output),
+            Err(e) => {
+                eprintln!("Error reading {}: {}", script, e);
+                false
+            }
+        };
     }
+    if !options.commands.is_empty() || !ok {
+        ok = ok && options.commands.iter().all(|sql| run_sql(&mut db, sql, &output));
+        if let Err(e) = db.save() {
+            eprintln!("Error saving database: {}", e);
+            ok = false;
+        }
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    let current_file = options.path.map(PathBuf::from);
+    run_repl(db, current_file, output);
 }
 
 fn run_with_file(path: &str) {
@@ -36,27 +142,80 @@ fn run_with_file(path: &str) {
     }
 }
 
-fn run_repl() {
+/// History file in the user's home directory
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".pardusdb_history"))
+}
+
+fn run_repl(mut db: Database, mut current_file: Option<PathBuf>, mut output: Output) {
     print_welcome();
 
-    let mut db = Database::in_memory();
-    let mut current_file: Option<PathBuf> = None;
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            println!("Error starting line editor: {}", e);
+            return;
+        }
+    };
+    let history = history_path();
+    if let Some(ref path) = history {
+        // A missing history file is normal on first start
+        let _ = editor.load_history(path);
+    }
+
+    // SQL typed so far; statements may span several lines until `;`
+    let mut buffer = String::new();
 
     loop {
-        if current_file.is_some() {
-            print!("pardusdb [{}]> ", current_file.as_ref().unwrap().display());
+        let prompt = match current_file {
+            Some(ref path) => format!("pardusdb [{}]> ", path.display()),
+            None => "pardusdb [memory]> ".to_string(),
+        };
+        let prompt = if buffer.is_empty() {
+            prompt
         } else {
-            print!("pardusdb [memory]> ");
-        }
-        io::stdout().flush().unwrap();
+            format!("{:>width$}> ", "...", width = prompt.chars().count() - 2)
+        };
+
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // Ctrl-C drops the statement being typed
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                if let Some(ref path) = current_file {
+                    match db.save() {
+                        Ok(()) => println!("Saved to: {}", path.display()),
+                        Err(e) => println!("Error saving: {}", e),
+                    }
+                }
+                break;
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                break;
+            }
+        };
 
-        let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            break;
+        if !buffer.is_empty() {
+            buffer.push('\n');
+            buffer.push_str(&line);
+            if is_complete_statement(&buffer) {
+                let _ = editor.add_history_entry(buffer.as_str());
+                run_sql(&mut db, &buffer, &output);
+                buffer.clear();
+            }
+            continue;
         }
 
-        let input = input.trim();
+        let input = line.trim();
         if input.is_empty() { continue; }
+        // Partial statements enter history once complete
+        if input.starts_with('.') || is_complete_statement(input) {
+            let _ = editor.add_history_entry(input);
+        }
 
         // Handle both "help" and ".help", "quit" and ".quit", etc.
         let cmd = if input.starts_with('.') {
@@ -107,11 +266,29 @@ fn run_repl() {
         }
 
         // Handle commands with arguments
+        if let Some(arg) = cmd.strip_prefix("timer ").or(cmd.strip_prefix("stats ")) {
+            let enabled = match arg.trim() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    println!("Usage: .{} on|off", &cmd[..5]);
+                    continue;
+                }
+            };
+            if cmd.starts_with("timer") {
+                output.timer = enabled;
+            } else {
+                output.stats = enabled;
+            }
+            continue;
+        }
+
         if cmd.starts_with("open ") {
             let path = cmd[5..].trim();
             match Database::open(path) {
                 Ok(new_db) => {
                     db = new_db;
+                    attach_terminal(&mut db);
                     current_file = Some(PathBuf::from(path));
                     println!("Opened: {}", path);
                 }
@@ -126,6 +303,7 @@ fn run_repl() {
             match Database::open(path) {
                 Ok(new_db) => {
                     db = new_db;
+                    attach_terminal(&mut db);
                     current_file = Some(PathBuf::from(path));
                     println!("Created and opened: {}", path);
                     println!("Now you can create tables with: CREATE TABLE ...");
@@ -142,15 +320,250 @@ fn run_repl() {
             continue;
         }
 
-        // Execute SQL
-        match db.execute(input) {
-            Ok(result) => println!("{}", result),
-            Err(e) => println!("Error: {}", e),
+        // Execute SQL once terminated by `;`, otherwise keep reading lines
+        if is_complete_statement(input) {
+            run_sql(&mut db, input, &output);
+        } else {
+            buffer.push_str(input);
         }
     }
+
+    if let Some(ref path) = history
+        && let Err(e) = editor.save_history(path)
+    {
+        println!("Error saving history: {}", e);
+    }
     println!("Goodbye!");
 }
 
+/// Rows between progress bar updates
+const PROGRESS_INTERVAL: usize = 1000;
+
+/// Set while a progress bar is on screen, so it can be cleared
+static PROGRESS_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Show a progress bar for long operations when stderr is a terminal
+/// Interrupt handle of the open database, used by the Ctrl-C handler
+static INTERRUPT: Mutex<Option<InterruptHandle>> = Mutex::new(None);
+
+/// Let Ctrl-C cancel the running statement, and show a progress bar for
+/// long operations when stderr is a terminal
+fn attach_terminal(db: &mut Database) {
+    static CTRLC: Once = Once::new();
+    CTRLC.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            if let Some(handle) = INTERRUPT.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+                handle.interrupt();
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: Ctrl-C will not cancel statements: {}", e);
+        }
+    });
+    *INTERRUPT.lock().unwrap_or_else(|e| e.into_inner()) = Some(db.interrupt_handle());
+
+    if std::io::stderr().is_terminal() {
+        db.set_progress_handler(PROGRESS_INTERVAL, draw_progress);
+    }
+}
+
+/// Progress handler drawing a bar on stderr
+fn draw_progress(progress: &Progress) -> bool {
+    const WIDTH: usize = 30;
+    let filled = progress.done * WIDTH / progress.total.max(1);
+    eprint!(
+        "\r{} [{}{}] {}/{}",
+        progress.operation,
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        progress.done,
+        progress.total,
+    );
+    PROGRESS_SHOWN.store(true, Ordering::Relaxed);
+    true
+}
+
+fn clear_progress() {
+    if PROGRESS_SHOWN.swap(false, Ordering::Relaxed) {
+        eprint!("\r\x1B[K");
+    }
+}
+
+/// Execute SQL that may hold several statements and print each result.
+/// Returns false after printing the first error.
+fn run_sql(db: &mut Database, sql: &str, output: &Output) -> bool {
+    for statement in split_statements(sql) {
+        let start = Instant::now();
+        let result = db.execute(&statement);
+        clear_progress();
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return false;
+            }
+        };
+        let elapsed = start.elapsed();
+
+        if output.json {
+            println!("{}", result_to_json(&result));
+        } else {
+            println!("{}", result);
+        }
+        if output.timer {
+            println!("Run time: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        let is_query = matches!(result, ExecuteResult::Select { .. } | ExecuteResult::SelectSimilar { .. } | ExecuteResult::Aggregate { .. });
+        if output.stats && is_query && let Some(stats) = db.last_query_stats() {
+            println!(
+                "Rows scanned: {}, returned: {}, index: {}",
+                stats.rows_scanned,
+                stats.rows_returned,
+                stats.index.as_deref().unwrap_or("none (full scan)"),
+            );
+        }
+    }
+    true
+}
+
+/// JSON object for one statement result. Rows are objects keyed by column
+/// name, with the row id under `_id` and, for similarity results, the
+/// distance under `_distance` and the metric's score under `_score`, next
+/// to a top-level `metric`.
+fn result_to_json(result: &ExecuteResult) -> String {
+    match result {
+        ExecuteResult::Select { .. } | ExecuteResult::SelectSimilar { .. } => {
+            let rows: Vec<String> = result.rows().iter().map(|row| {
+                let mut fields = vec![format!("\"_id\":{}", row.id())];
+                if let Some(dist) = row.distance {
+                    fields.push(format!("\"_distance\":{}", float_to_json(dist as f64)));
+                }
+                if let Some(score) = row.score {
+                    fields.push(format!("\"_score\":{}", float_to_json(score as f64)));
+                }
+                for (col, value) in row.schema.columns.iter().zip(&row.row.values) {
+                    fields.push(format!("{}:{}", json_string(&col.name), value_to_json(value)));
+                }
+                format!("{{{}}}", fields.join(","))
+            }).collect();
+            match result {
+                ExecuteResult::SelectSimilar { metric, .. } => {
+                    format!("{{\"metric\":{},\"rows\":[{}]}}", json_string(metric), rows.join(","))
+                }
+                _ => format!("{{\"rows\":[{}]}}", rows.join(",")),
+            }
+        }
+        ExecuteResult::Aggregate { results } => {
+            let items: Vec<String> = results.iter()
+                .map(|(name, value)| format!("{{\"name\":{},\"value\":{}}}", json_string(name), value_to_json(value)))
+                .collect();
+            format!("{{\"aggregates\":[{}]}}", items.join(","))
+        }
+        ExecuteResult::ShowTables { tables } => {
+            let items: Vec<String> = tables.iter()
+                .map(|t| format!("{{\"name\":{},\"rows\":{},\"dimension\":{}}}", json_string(&t.name), t.rows, t.dimension))
+                .collect();
+            format!("{{\"tables\":[{}]}}", items.join(","))
+        }
+        ExecuteResult::CreateTable { name } => format!("{{\"created\":{}}}", json_string(name)),
+        ExecuteResult::DropTable { name } => format!("{{\"dropped\":{}}}", json_string(name)),
+        ExecuteResult::CreateView { name } => format!("{{\"created\":{}}}", json_string(name)),
+        ExecuteResult::DropView { name } => format!("{{\"dropped\":{}}}", json_string(name)),
+        ExecuteResult::CreateProjection { name, table, retained } => format!(
+            "{{\"created\":{},\"table\":{},\"retained\":{}}}",
+            json_string(name), json_string(table), retained.map_or("null".to_string(), float_to_json),
+        ),
+        ExecuteResult::AlterTable { name, rebuilt } => format!("{{\"altered\":{},\"rebuilt\":{}}}", json_string(name), rebuilt),
+        ExecuteResult::Insert { id } => format!("{{\"inserted_id\":{}}}", id),
+        ExecuteResult::Ignored { count } => format!("{{\"ignored\":{}}}", count),
+        ExecuteResult::Update { count } => format!("{{\"updated\":{}}}", count),
+        ExecuteResult::Delete { count } => format!("{{\"deleted\":{}}}", count),
+        ExecuteResult::Queued { index } => format!("{{\"queued\":{}}}", index),
+        ExecuteResult::Set { name, value } => format!("{{\"set\":{},\"value\":{}}}", json_string(name), value_to_json(value)),
+        ExecuteResult::Pragma { name, value } => format!("{{\"pragma\":{},\"value\":{}}}", json_string(name), value_to_json(value)),
+        ExecuteResult::Attach { name } => format!("{{\"attached\":{}}}", json_string(name)),
+        ExecuteResult::Detach { name } => format!("{{\"detached\":{}}}", json_string(name)),
+        ExecuteResult::Explain { plan } => format!("{{\"plan\":{}}}", json_string(&plan.to_string())),
+        ExecuteResult::Analyze { tables } => {
+            let names: Vec<String> = tables.iter().map(|name| json_string(name)).collect();
+            format!("{{\"analyzed\":[{}]}}", names.join(","))
+        }
+        ExecuteResult::Vacuum { versions } => format!("{{\"vacuumed\":{}}}", versions),
+        ExecuteResult::CheckRecall { table, report } => format!(
+            "{{\"table\":{},\"queries\":{},\"k\":{},\"recall\":{},\"min_recall\":{},\"graph_ms\":{},\"exact_ms\":{}}}",
+            json_string(table), report.queries, report.k, float_to_json(report.recall), float_to_json(report.min_recall),
+            float_to_json(report.graph_latency.as_secs_f64() * 1000.0), float_to_json(report.exact_latency.as_secs_f64() * 1000.0),
+        ),
+        ExecuteResult::Calibrate { table, report } => format!(
+            "{{\"table\":{},\"sampled\":{},\"queries\":{},\"k\":{},\"recall\":{},\"min_recall\":{},\"mean_error\":{}}}",
+            json_string(table), report.sampled, report.queries, report.k, float_to_json(report.recall),
+            float_to_json(report.min_recall), float_to_json(report.mean_error),
+        ),
+        ExecuteResult::Reindex { table, before, after } => {
+            let stats = |s: &EdgeStats| format!(
+                "{{\"nodes\":{},\"edges\":{},\"min_degree\":{},\"max_degree\":{},\"isolated\":{},\"dangling\":{},\"free_slots\":{}}}",
+                s.nodes, s.edges, s.min_degree, s.max_degree, s.isolated, s.dangling, s.free_slots,
+            );
+            format!("{{\"reindexed\":{},\"before\":{},\"after\":{}}}", json_string(table), stats(before), stats(after))
+        }
+        ExecuteResult::BeginBulkLoad => "{\"bulk_load\":true}".to_string(),
+        ExecuteResult::EndBulkLoad { indexed } => format!("{{\"bulk_load\":false,\"indexed\":{}}}", indexed),
+    }
+}
+
+fn value_to_json(value: &Value) -> String {
+    let floats = |v: &[f32]| {
+        let items: Vec<String> = v.iter().map(|x| float_to_json(*x as f64)).collect();
+        format!("[{}]", items.join(","))
+    };
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Text(s) => json_string(s),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => float_to_json(*f),
+        Value::Boolean(b) => b.to_string(),
+        Value::Uuid(u) => json_string(&u.to_string()),
+        Value::Vector(v) => floats(v),
+        Value::MultiVector(vs) => {
+            let items: Vec<String> = vs.iter().map(|v| floats(v)).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::SparseVector(pairs) => {
+            let items: Vec<String> = pairs.iter()
+                .map(|(i, w)| format!("[{},{}]", i, float_to_json(*w as f64)))
+                .collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Blob(bytes) => {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            json_string(&hex)
+        }
+    }
+}
+
+/// NaN and infinities have no JSON form and become null
+fn float_to_json(f: f64) -> String {
+    if f.is_finite() { f.to_string() } else { "null".to_string() }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
 fn print_welcome() {
     println!(r#"
 ╔═══════════════════════════════════════════════════════════════╗
@@ -186,13 +599,16 @@ fn print_help() {
 │   help              Show this help message                     │
 │                                                                  │
 │ OTHER                                                           │
+│   .timer on|off     Print execution time per statement         │
+│   .stats on|off     Print rows scanned and index used          │
 │   .clear            Clear screen                               │
 │   quit / exit       Exit REPL (auto-saves if file open)        │
 ├─────────────────────────────────────────────────────────────────┤
 │ SQL COMMANDS                                                    │
 ├─────────────────────────────────────────────────────────────────┤
 │ CREATE TABLE <name> (<column> <type>, ...);                    │
-│   Types: VECTOR(n), TEXT, INTEGER, FLOAT, BOOLEAN              │
+│   Types: VECTOR(n), TEXT, INTEGER, FLOAT, BOOLEAN, UUID        │
+│   Optional: ... ) METRIC cosine|dot|manhattan|hamming;         │
 │                                                                  │
 │ INSERT INTO <table> (<columns>) VALUES (<values>);             │
 │   Values: 'text', 123, 1.5, [0.1, 0.2, ...], true, null        │
@@ -204,6 +620,8 @@ fn print_help() {
 │ DELETE FROM <table> [WHERE ...];                               │
 │ SHOW TABLES;                                                    │
 │ DROP TABLE <name>;                                              │
+│ ALTER TABLE <name> SET (max_neighbors = 32, ...);               │
+│ ALTER TABLE <name> ALTER <col> SET MASK HASH | DROP MASK;       │
 ├─────────────────────────────────────────────────────────────────┤
 │ EXAMPLE WORKFLOW                                                │
 ├─────────────────────────────────────────────────────────────────┤
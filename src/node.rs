@@ -1,32 +1,23 @@
-use std::sync::Arc;
-
 /// Unique identifier for a node in the graph.
 pub type NodeId = u32;
 
-/// A node in the vector graph.
-/// Stores the vector data and its neighbors (edges).
-#[derive(Clone, Debug)]
-pub struct Node<T> {
-    /// The vector data
-    pub vector: Arc<Vec<T>>,
+/// A node in the vector graph: its edges and liveness.
+/// The vector itself lives in the graph's contiguous arena, see `Graph::vector`.
+#[derive(Clone, Debug, Default)]
+pub struct Node {
     /// Indices of neighboring nodes
     pub neighbors: Vec<NodeId>,
     /// Whether this node has been deleted
     pub deleted: bool,
 }
 
-impl<T> Node<T> {
-    pub fn new(vector: Vec<T>) -> Self {
-        Node {
-            vector: Arc::new(vector),
-            neighbors: Vec::new(),
-            deleted: false,
-        }
+impl Node {
+    pub fn new() -> Self {
+        Node::default()
     }
 
-    pub fn with_capacity(vector: Vec<T>, capacity: usize) -> Self {
+    pub fn with_capacity(capacity: usize) -> Self {
         Node {
-            vector: Arc::new(vector),
             neighbors: Vec::with_capacity(capacity),
             deleted: false,
         }
@@ -95,15 +86,14 @@ mod tests {
 
     #[test]
     fn test_node_creation() {
-        let node: Node<f32> = Node::new(vec![1.0, 2.0, 3.0]);
-        assert_eq!(node.vector.len(), 3);
+        let node = Node::new();
         assert!(node.neighbors.is_empty());
         assert!(!node.deleted);
     }
 
     #[test]
     fn test_add_neighbor() {
-        let mut node: Node<f32> = Node::new(vec![1.0, 2.0]);
+        let mut node = Node::new();
         assert!(node.add_neighbor(1));
         assert!(node.add_neighbor(2));
         assert!(!node.add_neighbor(1)); // Already exists
@@ -112,7 +102,7 @@ mod tests {
 
     #[test]
     fn test_remove_neighbor() {
-        let mut node: Node<f32> = Node::new(vec![1.0, 2.0]);
+        let mut node = Node::new();
         node.add_neighbor(1);
         node.add_neighbor(2);
         assert!(node.remove_neighbor(1));
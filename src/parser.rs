@@ -1,16 +1,20 @@
 //! Enhanced SQL parser for PardusDB
 //!
 //! Supports a comprehensive subset of SQL including:
-//! - CREATE TABLE, DROP TABLE
+//! - CREATE TABLE (with optional `METRIC name [NORMALIZE]`), DROP TABLE
 //! - INSERT (single and multi-row)
 //! - SELECT with WHERE, ORDER BY, LIMIT, OFFSET, DISTINCT
-//! - UPDATE, DELETE
+//! - UPDATE, DELETE (including `DELETE ... SOFT`)
+//! - Time-travel reads with `SELECT ... FROM t AS OF '2024-01-01'`
 //! - Aggregate functions: COUNT, SUM, AVG, MIN, MAX
-//! - LIKE, IN, BETWEEN, IS NULL, IS NOT NULL
+//! - LIKE, REGEXP, IN, BETWEEN, IS NULL, IS NOT NULL
 //! - AND, OR in WHERE clauses
 
+use crate::analyzer::Analyzer;
 use crate::error::{MarsError, Result};
-use crate::schema::{ColumnType, Value};
+use crate::functions::{ArithOp, ScalarExpr, ScalarFunc};
+use crate::projection::ProjectionMethod;
+use crate::schema::{AutoIncrement, Collation, ColumnType, ForeignKey, Mask, OnDelete, Value};
 
 /// SQL command types
 #[derive(Clone, Debug)]
@@ -18,15 +22,53 @@ pub enum Command {
     CreateTable {
         name: String,
         columns: Vec<ColumnDef>,
+        metric: Option<String>,  // METRIC name for the vector index
+        normalize: bool,         // METRIC cosine NORMALIZE
+        columnar: bool,          // STORAGE COLUMNAR
     },
     DropTable {
         name: String,
         if_exists: bool,
     },
+    /// CREATE MATERIALIZED VIEW name AS SELECT ... GROUP BY ...: aggregates
+    /// kept up to date as the base table changes
+    CreateMaterializedView {
+        name: String,
+        select: Box<Command>,
+    },
+    /// DROP MATERIALIZED VIEW [IF EXISTS] name
+    DropMaterializedView {
+        name: String,
+        if_exists: bool,
+    },
+    /// CREATE PROJECTION name ON table(column) DIM n [METHOD PCA | RANDOM]
+    /// [SAMPLE n]: shrink the table's vectors to n dimensions
+    CreateProjection {
+        name: String,
+        table: String,
+        column: String,
+        dimension: usize,
+        method: ProjectionMethod,
+        /// Stored vectors PCA trains on
+        sample: usize,
+    },
+    /// ALTER TABLE name ...: change index settings or a column mask
+    AlterTable {
+        name: String,
+        action: AlterAction,
+    },
     Insert {
         table: String,
         columns: Vec<String>,
         values: Vec<Vec<Value>>,  // Support multiple rows
+        on_conflict: OnConflict,  // INSERT OR IGNORE / OR REPLACE
+    },
+    /// INSERT INTO table SELECT * FROM [database.]source: copy every row
+    /// of another table, possibly of an attached database
+    InsertSelect {
+        table: String,
+        source_database: Option<String>,
+        source: String,
     },
     Select {
         table: String,
@@ -38,6 +80,9 @@ pub enum Command {
         limit: Option<usize>,
         offset: Option<usize>,
         distinct: bool,
+        as_of: Option<i64>,  // Unix milliseconds for time-travel reads
+        diversity: Option<Diversity>,  // MAX PER / MMR for similarity queries
+        score: Option<ScalarExpr>,  // SCORE expression re-ranking similarity results
     },
     Join {
         left_table: String,
@@ -53,14 +98,111 @@ pub enum Command {
     },
     Update {
         table: String,
-        assignments: Vec<(String, Value)>,
+        assignments: Vec<(String, ScalarExpr)>,  // Evaluated against each matching row
         where_clause: Option<WhereClause>,
     },
     Delete {
         table: String,
         where_clause: Option<WhereClause>,
+        soft: bool,  // DELETE ... SOFT keeps rows for AS OF queries
     },
     ShowTables,
+    /// SET name = value: change a database setting such as statement_timeout
+    Set {
+        name: String,
+        value: Value,
+    },
+    /// PRAGMA name [= value]: read or change a database setting
+    Pragma {
+        name: String,
+        value: Option<Value>,
+    },
+    /// ATTACH DATABASE 'path' AS name: open another file read-only so
+    /// INSERT ... SELECT can copy from it
+    Attach {
+        path: String,
+        name: String,
+    },
+    /// DETACH DATABASE name
+    Detach {
+        name: String,
+    },
+    /// EXPLAIN statement: show how a SELECT, UPDATE or DELETE would find
+    /// its rows, without running it
+    Explain {
+        statement: Box<Command>,
+    },
+    /// ANALYZE [table]: collect the column statistics used by the planner,
+    /// for one table or all of them
+    Analyze {
+        table: Option<String>,
+    },
+    /// VACUUM [table]: drop the earlier row versions kept for AS OF, for
+    /// one table or all of them
+    Vacuum {
+        table: Option<String>,
+    },
+    /// CHECK RECALL ON table [SAMPLE n] [LIMIT k]: compare graph searches
+    /// for `sample` stored vectors with exact searches
+    CheckRecall {
+        table: String,
+        sample: usize,
+        k: usize,
+    },
+    /// REINDEX table: rebuild the table's vector index from its rows
+    Reindex {
+        table: String,
+    },
+    /// SELECT last_insert_rowid(): ID of the row most recently inserted
+    LastInsertRowid,
+    /// BEGIN BULK LOAD: store new rows without indexing their vectors
+    BeginBulkLoad,
+    /// END BULK LOAD: index the rows stored since BEGIN BULK LOAD at once
+    EndBulkLoad,
+}
+
+impl Command {
+    /// Whether the statement changes tables or rows
+    pub fn is_write(&self) -> bool {
+        matches!(self,
+            Command::CreateTable { .. } | Command::DropTable { .. } | Command::AlterTable { .. }
+            | Command::CreateMaterializedView { .. } | Command::DropMaterializedView { .. } | Command::CreateProjection { .. }
+            | Command::Analyze { .. } | Command::Vacuum { .. } | Command::Reindex { .. } | Command::Insert { .. } | Command::InsertSelect { .. } | Command::Update { .. } | Command::Delete { .. }
+            | Command::BeginBulkLoad | Command::EndBulkLoad)
+    }
+
+    /// Table a write statement changes
+    pub fn target_table(&self) -> Option<&str> {
+        match self {
+            Command::CreateTable { name, .. } | Command::DropTable { name, .. } | Command::AlterTable { name, .. }
+            | Command::CreateMaterializedView { name, .. } | Command::DropMaterializedView { name, .. } => Some(name),
+            Command::Insert { table, .. } | Command::InsertSelect { table, .. }
+            | Command::Update { table, .. } | Command::Delete { table, .. }
+            | Command::CreateProjection { table, .. } | Command::Reindex { table } => Some(table),
+            Command::Analyze { table } | Command::Vacuum { table } => table.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// What an ALTER TABLE statement changes
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlterAction {
+    /// SET (setting = value, ...): vector index settings
+    SetIndexOptions(Vec<(String, Value)>),
+    /// ALTER [COLUMN] c SET MASK ... or DROP MASK (`None`)
+    SetMask { column: String, mask: Option<Mask> },
+    /// SET VECTOR STORAGE FLOAT32 | INT8 [CALIBRATE] [SAMPLE n]
+    SetVectorStorage(VectorStorage),
+}
+
+/// How a table stores its vectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VectorStorage {
+    /// Full precision
+    Float32,
+    /// One byte per component, calibrated on up to `sample` stored vectors
+    Int8 { sample: usize },
 }
 
 /// JOIN types
@@ -84,8 +226,10 @@ pub enum SelectColumn {
     All,                           // *
     Column(String),                // column_name
     Aggregate { func: AggregateFunc, column: String, alias: Option<String> },
+    Function { expr: ScalarExpr, alias: Option<String> },  // UPPER(title) AS t
 }
 
+
 /// Aggregate function types
 #[derive(Clone, Debug, PartialEq)]
 pub enum AggregateFunc {
@@ -94,6 +238,8 @@ pub enum AggregateFunc {
     Avg,
     Min,
     Max,
+    ApproxCountDistinct,  // APPROX_COUNT(DISTINCT col), HyperLogLog estimate
+    VecAvg,               // VEC_AVG(embedding), element-wise mean (centroid)
 }
 
 #[derive(Clone, Debug)]
@@ -104,6 +250,11 @@ pub struct ColumnDef {
     pub not_null: bool,
     pub unique: bool,  // NEW: UNIQUE constraint
     pub default: Option<Value>,
+    pub collation: Collation,
+    pub mask: Option<Mask>,  // MASK HASH | REDACT | TRUNCATE(n)
+    pub references: Option<ForeignKey>,  // REFERENCES table[(column)] [ON DELETE ...]
+    pub auto_increment: Option<AutoIncrement>,  // AUTOINCREMENT [START n] [STEP k]
+    pub external: bool,  // TEXT EXTERNAL: values saved in a text file next to the database
 }
 
 #[derive(Clone, Debug, Default)]
@@ -123,6 +274,15 @@ pub struct Condition {
     pub column: String,
     pub operator: ComparisonOp,
     pub value: ConditionValue,
+    /// Function call on the left-hand side, e.g. `LOWER(title) = 'x'`;
+    /// `column` then holds its SQL text
+    pub expr: Option<ScalarExpr>,
+}
+
+impl Condition {
+    pub fn new(column: String, operator: ComparisonOp, value: ConditionValue) -> Self {
+        Condition { column, operator, value, expr: None }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -142,8 +302,19 @@ pub enum ComparisonOp {
     Gt,
     Ge,
     Similar,    // Vector similarity
+    SimilarExact, // Vector similarity, brute force (SIMILARITY [..] EXACT)
+    /// Vector similarity on the first n components, re-ranked at full size
+    /// (SIMILARITY [..] USING DIMS n)
+    SimilarDims(usize),
+    /// Weighted sum of one query's distances to several VECTOR columns
+    /// (SIMILARITY 0.7*title_emb + 0.3*body_emb AGAINST [..])
+    SimilarWeighted(Vec<(String, f32)>),
+    /// Filter on the distance to a vector (SIMILARITY [..] WITHIN d)
+    SimilarWithin(f32),
     Like,       // Pattern matching
     NotLike,
+    Regexp,     // Regular expression match (`regex` feature)
+    NotRegexp,
     In,         // IN clause
     NotIn,
     Between,    // BETWEEN
@@ -152,41 +323,200 @@ pub enum ComparisonOp {
     IsNotNull,  // IS NOT NULL
 }
 
+impl ComparisonOp {
+    /// True for every form of `SIMILARITY`
+    pub fn is_similarity(&self) -> bool {
+        matches!(
+            self,
+            ComparisonOp::Similar | ComparisonOp::SimilarExact | ComparisonOp::SimilarDims(_) | ComparisonOp::SimilarWeighted(_)
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct OrderBy {
     pub column: String,
     pub ascending: bool,
 }
 
+/// What INSERT does with a row whose UNIQUE value is already taken
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnConflict {
+    /// Fail the statement (plain INSERT)
+    #[default]
+    Abort,
+    /// Skip the row
+    Ignore,
+    /// Overwrite the conflicting row, which keeps its id
+    Replace,
+}
+
+/// Diversity constraints on similarity results:
+/// `LIMIT k MAX PER (column) n` and/or `MMR lambda`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diversity {
+    /// At most `n` results per distinct value of the column
+    pub max_per: Option<(String, usize)>,
+    /// Maximal marginal relevance: 1.0 is pure relevance, lower values
+    /// penalize results similar to ones already picked
+    pub mmr_lambda: Option<f32>,
+}
+
+/// Bounds on the input a `Parser` accepts, so hostile or malformed SQL
+/// fails with an error instead of using unbounded memory or stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Longest statement, in bytes
+    pub max_sql_len: usize,
+    /// Most elements in one vector literal, and vectors in a multi-vector
+    pub max_vector_len: usize,
+    /// Most values in an `IN (...)` list
+    pub max_in_list: usize,
+    /// Deepest nesting of parentheses, function calls and EXPLAIN
+    pub max_depth: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_sql_len: 64 * 1024 * 1024,
+            max_vector_len: 65_536,
+            max_in_list: 100_000,
+            max_depth: 64,
+        }
+    }
+}
+
 /// High-performance SQL parser
 pub struct Parser<'a> {
     input: &'a str,
     pos: usize,
+    /// Start of the token being read, reported as the error position
+    token_start: usize,
+    limits: ParseLimits,
+    /// Current nesting depth, bounded by `limits.max_depth`
+    depth: usize,
 }
 
+/// Statement keywords, listed when a command is not recognized
+/// Stored vectors CHECK RECALL samples without SAMPLE
+const DEFAULT_RECALL_SAMPLE: usize = 100;
+/// k of CHECK RECALL without LIMIT
+const DEFAULT_RECALL_K: usize = 10;
+/// Stored vectors INT8 calibration samples without SAMPLE
+const DEFAULT_CALIBRATION_SAMPLE: usize = 10_000;
+/// Stored vectors PCA trains on without SAMPLE
+const DEFAULT_PROJECTION_SAMPLE: usize = 10_000;
+
+const COMMANDS: &str = "CREATE, DROP, INSERT, SELECT, UPDATE, DELETE, ALTER, SHOW, SET, PRAGMA, ATTACH, DETACH, EXPLAIN, ANALYZE, REINDEX, CHECK, BEGIN or END";
+
+/// Operators accepted after a column in WHERE
+const OPERATORS: &str = "a comparison (=, !=, <>, <, <=, >, >=, LIKE, REGEXP, IN, BETWEEN, IS or SIMILARITY)";
+
+/// Literal forms accepted where a value is expected
+const VALUE_TOKENS: &str = "a value (number, 'string', [vector], {sparse vector}, TRUE, FALSE or NULL)";
+
 impl<'a> Parser<'a> {
     pub fn new(input: &'a str) -> Self {
-        Parser { input, pos: 0 }
+        Self::with_limits(input, ParseLimits::default())
     }
 
+    /// A parser that rejects input beyond `limits`
+    pub fn with_limits(input: &'a str, limits: ParseLimits) -> Self {
+        Parser { input, pos: 0, token_start: 0, limits, depth: 0 }
+    }
+
+    /// Parse one statement. Syntax errors are `MarsError::ParseError` with the
+    /// line and column of the offending token and a caret-annotated snippet.
     pub fn parse(&mut self) -> Result<Command> {
+        if self.input.len() > self.limits.max_sql_len {
+            return Err(MarsError::InvalidFormat(format!(
+                "Statement is {} bytes, over the limit of {}", self.input.len(), self.limits.max_sql_len
+            )));
+        }
+        self.parse_command().map_err(|e| self.annotate(e))
+    }
+
+    /// Run `f` one nesting level deeper, failing beyond `limits.max_depth`
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= self.limits.max_depth {
+            return Err(MarsError::InvalidFormat(format!(
+                "Statement nests deeper than the limit of {}", self.limits.max_depth
+            )));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Fail once a list being read has more than `limit` items
+    fn check_len(&self, len: usize, limit: usize, what: &str) -> Result<()> {
+        if len > limit {
+            return Err(MarsError::InvalidFormat(format!("{} has more than the limit of {} items", what, limit)));
+        }
+        Ok(())
+    }
+
+    fn annotate(&self, err: MarsError) -> MarsError {
+        let MarsError::InvalidFormat(message) = err else {
+            return err;
+        };
+
+        let pos = self.token_start.min(self.input.len());
+        let line_start = self.input[..pos].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.input[pos..].find('\n').map_or(self.input.len(), |i| pos + i);
+        let line = self.input[..pos].matches('\n').count() + 1;
+        let column = self.input[line_start..pos].chars().count() + 1;
+
+        // Keep tabs so the caret lines up with the snippet
+        let padding: String = self.input[line_start..pos].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        MarsError::ParseError {
+            message,
+            line,
+            column,
+            snippet: format!("  {}\n  {}^", &self.input[line_start..line_end], padding),
+        }
+    }
+
+    fn parse_command(&mut self) -> Result<Command> {
         self.skip_whitespace();
         let keyword = self.read_keyword_upper()?;
 
         match keyword.as_str() {
             "CREATE" => self.parse_create(),
             "DROP" => self.parse_drop(),
+            "ALTER" => self.parse_alter(),
             "INSERT" => self.parse_insert(),
             "SELECT" => self.parse_select(),
             "UPDATE" => self.parse_update(),
             "DELETE" => self.parse_delete(),
             "SHOW" => self.parse_show(),
-            _ => Err(MarsError::InvalidFormat(format!("Unknown command: {}", keyword))),
+            "SET" => self.parse_set(),
+            "PRAGMA" => self.parse_pragma(),
+            "ATTACH" => self.parse_attach(),
+            "DETACH" => self.parse_detach(),
+            "EXPLAIN" => self.parse_explain(),
+            "ANALYZE" => self.parse_analyze(),
+            "VACUUM" => self.parse_vacuum(),
+            "REINDEX" => self.parse_reindex(),
+            "CHECK" => self.parse_check_recall(),
+            "BEGIN" | "END" => self.parse_bulk_load(keyword == "BEGIN"),
+            _ => Err(MarsError::InvalidFormat(format!("Unknown command: {}, expected {}", keyword, COMMANDS))),
         }
     }
 
     // ==================== CREATE TABLE ====================
     fn parse_create(&mut self) -> Result<Command> {
+        self.skip_whitespace();
+        if self.peek_keyword_upper() == "MATERIALIZED" {
+            return self.parse_create_view();
+        }
+        if self.peek_keyword_upper() == "PROJECTION" {
+            return self.parse_create_projection();
+        }
         self.expect_keyword("TABLE")?;
         self.skip_whitespace();
         let name = self.read_identifier()?;
@@ -206,6 +536,11 @@ impl<'a> Parser<'a> {
             let mut not_null = false;
             let mut unique = false;
             let mut default = None;
+            let mut collation = Collation::Binary;
+            let mut mask = None;
+            let mut references = None;
+            let mut auto_increment = None;
+            let mut external = false;
 
             loop {
                 let keyword = self.peek_keyword_upper();
@@ -227,7 +562,47 @@ impl<'a> Parser<'a> {
                     "DEFAULT" => {
                         self.read_keyword()?;
                         self.skip_whitespace();
-                        default = Some(self.parse_value()?);
+                        if self.peek_keyword_upper() == "GEN_RANDOM_UUID" {
+                            auto_increment = Some(self.parse_gen_random_uuid(&col_name, &col_type)?);
+                            unique = true;
+                        } else {
+                            default = Some(self.parse_value()?);
+                        }
+                    }
+                    "COLLATE" => {
+                        self.read_keyword()?;
+                        self.skip_whitespace();
+                        let mut name = self.read_identifier()?;
+                        // UNICODE-CI: the identifier stops at the dash
+                        if self.peek_char() == Some('-') {
+                            self.advance();
+                            name.push('-');
+                            name.push_str(&self.read_identifier()?);
+                        }
+                        collation = Collation::from_name(&name).ok_or_else(|| {
+                            MarsError::InvalidFormat(format!("Unknown collation: {}", name))
+                        })?;
+                    }
+                    "MASK" => {
+                        self.read_keyword()?;
+                        mask = Some(self.parse_mask()?);
+                    }
+                    "REFERENCES" => {
+                        self.read_keyword()?;
+                        references = Some(self.parse_references(&col_name)?);
+                    }
+                    "AUTOINCREMENT" => {
+                        self.read_keyword()?;
+                        let auto = self.parse_auto_increment(&col_name, &col_type)?;
+                        unique |= matches!(auto, AutoIncrement::Uuid { .. });
+                        if auto_increment.is_some() {
+                            return Err(MarsError::InvalidFormat(format!("column '{}' already has generated values", col_name)));
+                        }
+                        auto_increment = Some(auto);
+                    }
+                    "EXTERNAL" => {
+                        self.read_keyword()?;
+                        external = true;
                     }
                     _ => break,
                 }
@@ -241,23 +616,229 @@ impl<'a> Parser<'a> {
                 not_null,
                 unique,
                 default,
+                collation,
+                mask,
+                references,
+                auto_increment,
+                external,
             });
 
             self.skip_whitespace();
-            if self.peek_char() == Some(')') {
-                self.advance();
+            if self.end_of_list(')')? {
                 break;
             }
-            self.expect_char(',')?;
         }
 
+        // Optional distance metric for the vector index
+        self.skip_whitespace();
+        let metric = if self.peek_keyword_upper() == "METRIC" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            Some(self.read_identifier()?.to_lowercase())
+        } else {
+            None
+        };
+
+        self.skip_whitespace();
+        let normalize = self.peek_keyword_upper() == "NORMALIZE";
+        if normalize {
+            self.read_keyword()?;
+        }
+
+        // Optional metadata layout: STORAGE ROW (default) or STORAGE COLUMNAR
+        self.skip_whitespace();
+        let columnar = if self.peek_keyword_upper() == "STORAGE" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            match self.read_keyword_upper()?.as_str() {
+                "COLUMNAR" => true,
+                "ROW" => false,
+                other => return Err(MarsError::InvalidFormat(format!("Unknown storage layout: {}", other))),
+            }
+        } else {
+            false
+        };
+
+        self.skip_trailing_semicolon();
+        Ok(Command::CreateTable { name, columns, metric, normalize, columnar })
+    }
+
+    /// `parent_table[(parent_column)] [ON DELETE CASCADE | SET NULL |
+    /// RESTRICT | NO ACTION]` after REFERENCES; the parent column defaults
+    /// to `id`
+    fn parse_references(&mut self, column: &str) -> Result<ForeignKey> {
+        self.skip_whitespace();
+        let parent_table = self.read_identifier()?;
+        self.skip_whitespace();
+        let parent_column = if self.peek_char() == Some('(') {
+            self.advance();
+            self.skip_whitespace();
+            let name = self.read_identifier()?;
+            self.skip_whitespace();
+            self.expect_char(')')?;
+            name
+        } else {
+            "id".to_string()
+        };
+
+        self.skip_whitespace();
+        let mut on_delete = OnDelete::Restrict;
+        if self.peek_keyword_upper() == "ON" {
+            self.read_keyword()?;
+            self.expect_keyword("DELETE")?;
+            on_delete = match self.read_keyword_upper()?.as_str() {
+                "CASCADE" => OnDelete::Cascade,
+                "RESTRICT" => OnDelete::Restrict,
+                "SET" => {
+                    self.expect_keyword("NULL")?;
+                    OnDelete::SetNull
+                }
+                "NO" => {
+                    self.expect_keyword("ACTION")?;
+                    OnDelete::Restrict
+                }
+                other => return Err(MarsError::InvalidFormat(format!(
+                    "Expected CASCADE, SET NULL, RESTRICT or NO ACTION after ON DELETE, got '{}'", other,
+                ))),
+            };
+        }
+        Ok(ForeignKey { column: column.to_string(), parent_table, parent_column, on_delete })
+    }
+
+    /// `gen_random_uuid()` after DEFAULT: a random UUID for rows that leave
+    /// the column NULL
+    fn parse_gen_random_uuid(&mut self, column: &str, data_type: &ColumnType) -> Result<AutoIncrement> {
+        self.read_keyword()?;
+        self.skip_whitespace();
+        self.expect_char('(')?;
+        self.skip_whitespace();
+        self.expect_char(')')?;
+        if !matches!(data_type, ColumnType::Uuid | ColumnType::Text) {
+            return Err(MarsError::InvalidFormat(format!("DEFAULT gen_random_uuid() needs a UUID or TEXT column, got '{}'", column)));
+        }
+        Ok(AutoIncrement::Uuid { column: column.to_string() })
+    }
+
+    /// `[START [WITH] n] [STEP k | INCREMENT [BY] k]` after AUTOINCREMENT.
+    /// On the INTEGER `id` column it numbers rows; on a UUID or TEXT
+    /// column it fills in random UUIDs.
+    fn parse_auto_increment(&mut self, column: &str, data_type: &ColumnType) -> Result<AutoIncrement> {
+        let (mut start, mut step) = (1, 1);
+        loop {
+            self.skip_whitespace();
+            match self.peek_keyword_upper().as_str() {
+                "START" => {
+                    self.read_keyword()?;
+                    self.skip_whitespace();
+                    if self.peek_keyword_upper() == "WITH" {
+                        self.read_keyword()?;
+                        self.skip_whitespace();
+                    }
+                    start = self.read_integer()?;
+                }
+                "STEP" | "INCREMENT" => {
+                    let keyword = self.read_keyword_upper()?;
+                    self.skip_whitespace();
+                    if keyword == "INCREMENT" && self.peek_keyword_upper() == "BY" {
+                        self.read_keyword()?;
+                        self.skip_whitespace();
+                    }
+                    step = self.read_integer()?;
+                }
+                _ => break,
+            }
+        }
+
+        match data_type {
+            ColumnType::Integer if column == "id" => {
+                if start < 1 || step < 1 {
+                    return Err(MarsError::InvalidFormat("AUTOINCREMENT START and STEP must be at least 1".into()));
+                }
+                Ok(AutoIncrement::Sequence { start: start as u64, step: step as u64 })
+            }
+            ColumnType::Uuid | ColumnType::Text => {
+                if (start, step) != (1, 1) {
+                    return Err(MarsError::InvalidFormat("START and STEP only apply to an INTEGER AUTOINCREMENT".into()));
+                }
+                Ok(AutoIncrement::Uuid { column: column.to_string() })
+            }
+            _ => Err(MarsError::InvalidFormat(format!(
+                "AUTOINCREMENT needs the INTEGER id column or a UUID or TEXT column, got '{}'", column,
+            ))),
+        }
+    }
+
+    // ==================== CREATE PROJECTION ====================
+    fn parse_create_projection(&mut self) -> Result<Command> {
+        self.expect_keyword("PROJECTION")?;
+        self.skip_whitespace();
+        let name = self.read_identifier()?;
+        self.expect_keyword("ON")?;
+        self.skip_whitespace();
+        let table = self.read_identifier()?;
+        self.skip_whitespace();
+        self.expect_char('(')?;
+        self.skip_whitespace();
+        let column = self.read_identifier()?;
+        self.skip_whitespace();
+        self.expect_char(')')?;
+        self.expect_keyword("DIM")?;
+        self.skip_whitespace();
+        let dimension = usize::try_from(self.read_integer()?)
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| MarsError::InvalidFormat("DIM must be a positive integer".into()))?;
+        self.skip_whitespace();
+
+        let mut method = ProjectionMethod::Pca;
+        if self.peek_keyword_upper() == "METHOD" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            method = match self.read_keyword_upper()?.as_str() {
+                "PCA" => ProjectionMethod::Pca,
+                "RANDOM" => ProjectionMethod::Random,
+                other => return Err(MarsError::InvalidFormat(format!("Unknown projection method: {}", other))),
+            };
+            self.skip_whitespace();
+        }
+        let mut sample = DEFAULT_PROJECTION_SAMPLE;
+        if self.peek_keyword_upper() == "SAMPLE" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            sample = usize::try_from(self.read_integer()?)
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| MarsError::InvalidFormat("SAMPLE must be a positive integer".into()))?;
+        }
         self.skip_trailing_semicolon();
-        Ok(Command::CreateTable { name, columns })
+        Ok(Command::CreateProjection { name, table, column, dimension, method, sample })
+    }
+
+    // ==================== CREATE MATERIALIZED VIEW ====================
+    fn parse_create_view(&mut self) -> Result<Command> {
+        self.expect_keyword("MATERIALIZED")?;
+        self.expect_keyword("VIEW")?;
+        self.skip_whitespace();
+        let name = self.read_identifier()?;
+        self.expect_keyword("AS")?;
+        self.expect_keyword("SELECT")?;
+        let select = self.parse_select()?;
+        if !matches!(select, Command::Select { .. }) {
+            return Err(MarsError::InvalidFormat("a materialized view cannot be defined by a JOIN".into()));
+        }
+        Ok(Command::CreateMaterializedView { name, select: Box::new(select) })
     }
 
     // ==================== DROP TABLE ====================
     fn parse_drop(&mut self) -> Result<Command> {
-        self.expect_keyword("TABLE")?;
+        self.skip_whitespace();
+        let view = self.peek_keyword_upper() == "MATERIALIZED";
+        if view {
+            self.read_keyword()?;
+            self.expect_keyword("VIEW")?;
+        } else {
+            self.expect_keyword("TABLE")?;
+        }
         self.skip_whitespace();
 
         let if_exists = if self.peek_keyword_upper() == "IF" {
@@ -272,11 +853,133 @@ impl<'a> Parser<'a> {
         let name = self.read_identifier()?;
         self.skip_trailing_semicolon();
 
+        if view {
+            return Ok(Command::DropMaterializedView { name, if_exists });
+        }
         Ok(Command::DropTable { name, if_exists })
     }
 
+    // ==================== ALTER TABLE ====================
+    fn parse_alter(&mut self) -> Result<Command> {
+        self.expect_keyword("TABLE")?;
+        self.skip_whitespace();
+        let name = self.read_identifier()?;
+        self.skip_whitespace();
+        if self.peek_keyword_upper() == "ALTER" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            if self.peek_keyword_upper() == "COLUMN" {
+                self.read_keyword()?;
+                self.skip_whitespace();
+            }
+            let column = self.read_identifier()?;
+            self.skip_whitespace();
+            let mask = match self.read_keyword_upper()?.as_str() {
+                "SET" => {
+                    self.expect_keyword("MASK")?;
+                    Some(self.parse_mask()?)
+                }
+                "DROP" => {
+                    self.expect_keyword("MASK")?;
+                    None
+                }
+                other => return Err(MarsError::InvalidFormat(format!("Expected SET MASK or DROP MASK, got {}", other))),
+            };
+            self.skip_trailing_semicolon();
+            return Ok(Command::AlterTable { name, action: AlterAction::SetMask { column, mask } });
+        }
+
+        self.expect_keyword("SET")?;
+        self.skip_whitespace();
+        if self.peek_keyword_upper() == "VECTOR" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            self.expect_keyword("STORAGE")?;
+            self.skip_whitespace();
+            let storage = self.parse_vector_storage()?;
+            self.skip_trailing_semicolon();
+            return Ok(Command::AlterTable { name, action: AlterAction::SetVectorStorage(storage) });
+        }
+        self.expect_char('(')?;
+
+        let mut settings = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let setting = self.read_identifier()?.to_lowercase();
+            self.skip_whitespace();
+            self.expect_char('=')?;
+            self.skip_whitespace();
+            settings.push((setting, self.parse_value()?));
+            self.skip_whitespace();
+            if self.end_of_list(')')? {
+                break;
+            }
+        }
+
+        self.skip_trailing_semicolon();
+        Ok(Command::AlterTable { name, action: AlterAction::SetIndexOptions(settings) })
+    }
+
+    /// FLOAT32 or INT8 [CALIBRATE] [SAMPLE n], after SET VECTOR STORAGE
+    fn parse_vector_storage(&mut self) -> Result<VectorStorage> {
+        match self.read_keyword_upper()?.as_str() {
+            "FLOAT32" | "F32" => Ok(VectorStorage::Float32),
+            "INT8" => {
+                self.skip_whitespace();
+                if self.peek_keyword_upper() == "CALIBRATE" {
+                    self.read_keyword()?;
+                    self.skip_whitespace();
+                }
+                let mut sample = DEFAULT_CALIBRATION_SAMPLE;
+                if self.peek_keyword_upper() == "SAMPLE" {
+                    self.read_keyword()?;
+                    self.skip_whitespace();
+                    sample = usize::try_from(self.read_integer()?)
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| MarsError::InvalidFormat("SAMPLE must be a positive integer".into()))?;
+                }
+                Ok(VectorStorage::Int8 { sample })
+            }
+            other => Err(MarsError::InvalidFormat(format!("Expected FLOAT32 or INT8 vector storage, got {}", other))),
+        }
+    }
+
+    /// HASH, REDACT or TRUNCATE(n), after the MASK keyword
+    fn parse_mask(&mut self) -> Result<Mask> {
+        self.skip_whitespace();
+        match self.read_keyword_upper()?.as_str() {
+            "HASH" => Ok(Mask::Hash),
+            "REDACT" => Ok(Mask::Redact),
+            "TRUNCATE" => {
+                self.skip_whitespace();
+                self.expect_char('(')?;
+                self.skip_whitespace();
+                let n = self.read_integer()?;
+                if n < 0 {
+                    return Err(MarsError::InvalidFormat("TRUNCATE expects a non-negative length".into()));
+                }
+                self.skip_whitespace();
+                self.expect_char(')')?;
+                Ok(Mask::Truncate(n as usize))
+            }
+            other => Err(MarsError::InvalidFormat(format!("Unknown mask: {}, expected HASH, REDACT or TRUNCATE(n)", other))),
+        }
+    }
+
     // ==================== INSERT ====================
     fn parse_insert(&mut self) -> Result<Command> {
+        self.skip_whitespace();
+        let on_conflict = if self.peek_keyword_upper() == "OR" {
+            self.read_keyword()?;
+            match self.read_keyword()?.to_uppercase().as_str() {
+                "IGNORE" => OnConflict::Ignore,
+                "REPLACE" => OnConflict::Replace,
+                other => return Err(MarsError::InvalidFormat(format!("Expected IGNORE or REPLACE, got '{}'", other))),
+            }
+        } else {
+            OnConflict::Abort
+        };
         self.expect_keyword("INTO")?;
         self.skip_whitespace();
         let table = self.read_identifier()?;
@@ -290,15 +993,22 @@ impl<'a> Parser<'a> {
                 self.skip_whitespace();
                 columns.push(self.read_identifier()?);
                 self.skip_whitespace();
-                if self.peek_char() == Some(')') {
-                    self.advance();
+                if self.end_of_list(')')? {
                     break;
                 }
-                self.expect_char(',')?;
             }
         }
 
         self.skip_whitespace();
+        if self.peek_keyword_upper() == "SELECT" {
+            if !columns.is_empty() {
+                return Err(MarsError::InvalidFormat("INSERT ... SELECT does not take a column list".into()));
+            }
+            if on_conflict != OnConflict::Abort {
+                return Err(MarsError::InvalidFormat("INSERT OR IGNORE/REPLACE takes VALUES, not SELECT".into()));
+            }
+            return self.parse_insert_select(table);
+        }
         self.expect_keyword("VALUES")?;
 
         let mut all_values = Vec::new();
@@ -311,11 +1021,9 @@ impl<'a> Parser<'a> {
                 self.skip_whitespace();
                 values.push(self.parse_value()?);
                 self.skip_whitespace();
-                if self.peek_char() == Some(')') {
-                    self.advance();
+                if self.end_of_list(')')? {
                     break;
                 }
-                self.expect_char(',')?;
             }
             all_values.push(values);
 
@@ -328,13 +1036,46 @@ impl<'a> Parser<'a> {
         }
 
         self.skip_trailing_semicolon();
-        Ok(Command::Insert { table, columns, values: all_values })
+        Ok(Command::Insert { table, columns, values: all_values, on_conflict })
+    }
+
+    /// SELECT * FROM [database.]source after INSERT INTO table
+    fn parse_insert_select(&mut self, table: String) -> Result<Command> {
+        self.expect_keyword("SELECT")?;
+        self.skip_whitespace();
+        self.expect_char('*')?;
+        self.expect_keyword("FROM")?;
+        self.skip_whitespace();
+        let mut source_database = None;
+        let mut source = self.read_identifier()?;
+        self.skip_whitespace();
+        if self.peek_char() == Some('.') {
+            self.advance();
+            self.skip_whitespace();
+            source_database = Some(std::mem::replace(&mut source, self.read_identifier()?));
+        }
+        self.skip_trailing_semicolon();
+        Ok(Command::InsertSelect { table, source_database, source })
     }
 
     // ==================== SELECT ====================
     fn parse_select(&mut self) -> Result<Command> {
         self.skip_whitespace();
 
+        // last_insert_rowid() is the one SELECT without FROM
+        if self.peek_keyword_upper() == "LAST_INSERT_ROWID" {
+            let start = self.pos;
+            self.read_keyword()?;
+            self.skip_whitespace();
+            if self.peek_char() == Some('(') {
+                self.advance();
+                self.skip_whitespace();
+                self.expect_char(')')?;
+                return Ok(Command::LastInsertRowid);
+            }
+            self.pos = start;
+        }
+
         // DISTINCT
         let mut distinct = false;
         if self.peek_keyword_upper() == "DISTINCT" {
@@ -374,10 +1115,26 @@ impl<'a> Parser<'a> {
                     // Regular column
                     // Check if it's an aggregate function
                     let col_upper = col.to_uppercase();
-                    if ["COUNT", "SUM", "AVG", "MIN", "MAX"].contains(&col_upper.as_str()) {
+                    let scalar = ScalarFunc::from_name(&col).filter(|_| self.peek_char() == Some('('));
+                    if let Some(func) = scalar {
+                        let expr = self.parse_scalar_call(func)?;
+                        self.skip_whitespace();
+                        let alias = if self.peek_keyword_upper() == "AS" {
+                            self.read_keyword()?;
+                            self.skip_whitespace();
+                            Some(self.read_identifier()?)
+                        } else {
+                            None
+                        };
+                        select_columns.push(SelectColumn::Function { expr, alias });
+                    } else if ["COUNT", "SUM", "AVG", "MIN", "MAX", "APPROX_COUNT", "VEC_AVG"].contains(&col_upper.as_str()) {
                         // Parse aggregate function
                         self.expect_char('(')?;
                         self.skip_whitespace();
+                        if col_upper == "APPROX_COUNT" {
+                            self.expect_keyword("DISTINCT")?;
+                            self.skip_whitespace();
+                        }
                         let agg_col = if self.peek_char() == Some('*') {
                             self.advance();
                             "*".to_string()
@@ -386,6 +1143,14 @@ impl<'a> Parser<'a> {
                         };
                         self.skip_whitespace();
                         self.expect_char(')')?;
+                        self.skip_whitespace();
+                        let alias = if self.peek_keyword_upper() == "AS" {
+                            self.read_keyword()?;
+                            self.skip_whitespace();
+                            Some(self.read_identifier()?)
+                        } else {
+                            None
+                        };
 
                         select_columns.push(SelectColumn::Aggregate {
                             func: match col_upper.as_str() {
@@ -394,10 +1159,12 @@ impl<'a> Parser<'a> {
                                 "AVG" => AggregateFunc::Avg,
                                 "MIN" => AggregateFunc::Min,
                                 "MAX" => AggregateFunc::Max,
+                                "APPROX_COUNT" => AggregateFunc::ApproxCountDistinct,
+                                "VEC_AVG" => AggregateFunc::VecAvg,
                                 _ => return Err(MarsError::InvalidFormat(format!("Unknown aggregate: {}", col))),
                             },
                             column: agg_col,
-                            alias: None,
+                            alias,
                         });
                     } else {
                         select_columns.push(SelectColumn::Column(col));
@@ -426,8 +1193,12 @@ impl<'a> Parser<'a> {
             return self.parse_join(table, join_columns);
         }
 
+        // AS OF for time-travel reads
+        let as_of = self.parse_as_of()?;
+
         // Regular SELECT without JOIN
         let where_clause = self.parse_where()?;
+        let score = self.parse_score()?;
 
         // GROUP BY
         self.skip_whitespace();
@@ -447,6 +1218,8 @@ impl<'a> Parser<'a> {
         self.skip_whitespace();
         let limit = self.parse_limit()?;
 
+        let diversity = self.parse_diversity()?;
+
         self.skip_whitespace();
         let offset = self.parse_offset()?;
 
@@ -462,9 +1235,33 @@ impl<'a> Parser<'a> {
             limit,
             offset,
             distinct,
+            as_of,
+            diversity,
+            score,
         })
     }
 
+    /// Parse `AS OF <timestamp>` after a table name
+    fn parse_as_of(&mut self) -> Result<Option<i64>> {
+        self.skip_whitespace();
+        if self.peek_keyword_upper() != "AS" {
+            return Ok(None);
+        }
+        self.read_keyword()?;
+        self.expect_keyword("OF")?;
+        self.skip_whitespace();
+
+        let as_of = match self.parse_value()? {
+            Value::Text(s) => parse_timestamp(&s)?,
+            Value::Integer(secs) => secs * 1000,
+            Value::Float(secs) => (secs * 1000.0) as i64,
+            other => {
+                return Err(MarsError::InvalidFormat(format!("Invalid AS OF timestamp: {:?}", other)));
+            }
+        };
+        Ok(Some(as_of))
+    }
+
     /// Parse JOIN clause (called from parse_select when JOIN is detected)
     fn parse_join(&mut self, left_table: String, columns: Vec<JoinColumn>) -> Result<Command> {
         self.skip_whitespace();
@@ -600,57 +1397,218 @@ impl<'a> Parser<'a> {
         }
     }
 
-    // ==================== UPDATE ====================
-    fn parse_update(&mut self) -> Result<Command> {
+    // ==================== UPDATE ====================
+    fn parse_update(&mut self) -> Result<Command> {
+        self.skip_whitespace();
+        let table = self.read_identifier()?;
+
+        self.skip_whitespace();
+        self.expect_keyword("SET")?;
+
+        let mut assignments = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let col = self.read_identifier()?;
+            self.skip_whitespace();
+            self.expect_char('=')?;
+            let expr = self.parse_arith_expr()?;
+            assignments.push((col, expr));
+
+            self.skip_whitespace();
+            if self.peek_char() == Some(',') {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+
+        self.skip_whitespace();
+        let where_clause = self.parse_where()?;
+
+        self.skip_trailing_semicolon();
+        Ok(Command::Update { table, assignments, where_clause })
+    }
+
+    // ==================== DELETE ====================
+    fn parse_delete(&mut self) -> Result<Command> {
+        self.expect_keyword("FROM")?;
+        self.skip_whitespace();
+        let table = self.read_identifier()?;
+
+        self.skip_whitespace();
+        let where_clause = self.parse_where()?;
+
+        self.skip_whitespace();
+        let soft = if self.peek_keyword_upper() == "SOFT" {
+            self.read_keyword()?;
+            true
+        } else {
+            false
+        };
+
+        self.skip_trailing_semicolon();
+        Ok(Command::Delete { table, where_clause, soft })
+    }
+
+    // ==================== SHOW ====================
+    fn parse_show(&mut self) -> Result<Command> {
+        self.expect_keyword("TABLES")?;
+        self.skip_trailing_semicolon();
+        Ok(Command::ShowTables)
+    }
+
+    // ==================== SET ====================
+    fn parse_set(&mut self) -> Result<Command> {
         self.skip_whitespace();
-        let table = self.read_identifier()?;
-
+        let name = self.read_identifier()?.to_lowercase();
         self.skip_whitespace();
-        self.expect_keyword("SET")?;
-
-        let mut assignments = Vec::new();
-        loop {
-            self.skip_whitespace();
-            let col = self.read_identifier()?;
-            self.skip_whitespace();
+        if self.peek_keyword_upper() == "TO" {
+            self.read_keyword()?;
+        } else {
             self.expect_char('=')?;
+        }
+        let value = self.parse_setting_value()?;
+        self.skip_trailing_semicolon();
+        Ok(Command::Set { name, value })
+    }
+
+    // ==================== ATTACH / DETACH ====================
+    fn parse_attach(&mut self) -> Result<Command> {
+        self.skip_whitespace();
+        if self.peek_keyword_upper() == "DATABASE" {
+            self.read_keyword()?;
             self.skip_whitespace();
-            let val = self.parse_value()?;
-            assignments.push((col, val));
+        }
+        let path = match self.parse_value()? {
+            Value::Text(path) => path,
+            other => return Err(MarsError::InvalidFormat(format!("Expected a file path string, got {:?}", other))),
+        };
+        self.expect_keyword("AS")?;
+        self.skip_whitespace();
+        let name = self.read_identifier()?;
+        self.skip_trailing_semicolon();
+        Ok(Command::Attach { path, name })
+    }
 
+    fn parse_detach(&mut self) -> Result<Command> {
+        self.skip_whitespace();
+        if self.peek_keyword_upper() == "DATABASE" {
+            self.read_keyword()?;
             self.skip_whitespace();
-            if self.peek_char() == Some(',') {
-                self.advance();
-            } else {
-                break;
-            }
         }
+        let name = self.read_identifier()?;
+        self.skip_trailing_semicolon();
+        Ok(Command::Detach { name })
+    }
+
+    // ==================== EXPLAIN ====================
+    fn parse_explain(&mut self) -> Result<Command> {
+        let statement = self.nested(Self::parse_command)?;
+        if !matches!(statement, Command::Select { .. } | Command::Update { .. } | Command::Delete { .. }) {
+            return Err(MarsError::InvalidFormat("EXPLAIN supports SELECT, UPDATE and DELETE".into()));
+        }
+        Ok(Command::Explain { statement: Box::new(statement) })
+    }
 
+    // ==================== ANALYZE ====================
+    fn parse_analyze(&mut self) -> Result<Command> {
         self.skip_whitespace();
-        let where_clause = self.parse_where()?;
+        let table = if self.peek_char().is_none_or(|c| c == ';') {
+            None
+        } else {
+            Some(self.read_identifier()?)
+        };
+        self.skip_trailing_semicolon();
+        Ok(Command::Analyze { table })
+    }
 
+    // ==================== VACUUM ====================
+    fn parse_vacuum(&mut self) -> Result<Command> {
+        self.skip_whitespace();
+        let table = if self.peek_char().is_none_or(|c| c == ';') {
+            None
+        } else {
+            Some(self.read_identifier()?)
+        };
         self.skip_trailing_semicolon();
-        Ok(Command::Update { table, assignments, where_clause })
+        Ok(Command::Vacuum { table })
     }
 
-    // ==================== DELETE ====================
-    fn parse_delete(&mut self) -> Result<Command> {
-        self.expect_keyword("FROM")?;
+    // ==================== REINDEX ====================
+    fn parse_reindex(&mut self) -> Result<Command> {
         self.skip_whitespace();
         let table = self.read_identifier()?;
+        self.skip_trailing_semicolon();
+        Ok(Command::Reindex { table })
+    }
 
+    /// CHECK RECALL ON table [SAMPLE n] [LIMIT k]
+    fn parse_check_recall(&mut self) -> Result<Command> {
         self.skip_whitespace();
-        let where_clause = self.parse_where()?;
+        self.expect_keyword("RECALL")?;
+        self.skip_whitespace();
+        self.expect_keyword("ON")?;
+        self.skip_whitespace();
+        let table = self.read_identifier()?;
+        self.skip_whitespace();
+        let mut sample = DEFAULT_RECALL_SAMPLE;
+        if self.peek_keyword_upper() == "SAMPLE" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            sample = usize::try_from(self.read_integer()?)
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| MarsError::InvalidFormat("SAMPLE must be a positive integer".into()))?;
+        }
+        let k = self.parse_limit()?.unwrap_or(DEFAULT_RECALL_K);
+        self.skip_trailing_semicolon();
+        Ok(Command::CheckRecall { table, sample, k })
+    }
 
+    /// BULK LOAD after BEGIN or END
+    fn parse_bulk_load(&mut self, begin: bool) -> Result<Command> {
+        self.skip_whitespace();
+        self.expect_keyword("BULK")?;
+        self.skip_whitespace();
+        self.expect_keyword("LOAD")?;
         self.skip_trailing_semicolon();
-        Ok(Command::Delete { table, where_clause })
+        Ok(if begin { Command::BeginBulkLoad } else { Command::EndBulkLoad })
     }
 
-    // ==================== SHOW ====================
-    fn parse_show(&mut self) -> Result<Command> {
-        self.expect_keyword("TABLES")?;
+    // ==================== PRAGMA ====================
+    fn parse_pragma(&mut self) -> Result<Command> {
+        self.skip_whitespace();
+        let name = self.read_identifier()?.to_lowercase();
+        self.skip_whitespace();
+        let value = match self.peek_char() {
+            Some('=') => {
+                self.advance();
+                Some(self.parse_setting_value()?)
+            }
+            // SQLite-style PRAGMA name(value)
+            Some('(') => {
+                self.advance();
+                let value = self.parse_setting_value()?;
+                self.skip_whitespace();
+                self.expect_char(')')?;
+                Some(value)
+            }
+            _ => None,
+        };
         self.skip_trailing_semicolon();
-        Ok(Command::ShowTables)
+        Ok(Command::Pragma { name, value })
+    }
+
+    /// A setting value: a literal, or a bare word such as FULL or OFF,
+    /// which becomes upper-case text
+    fn parse_setting_value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        let word = self.peek_keyword_upper();
+        if word.starts_with(|c: char| c.is_alphabetic()) && !matches!(word.as_str(), "TRUE" | "FALSE" | "NULL") {
+            self.read_keyword()?;
+            return Ok(Value::Text(word));
+        }
+        self.parse_value()
     }
 
     // ==================== WHERE CLAUSE ====================
@@ -689,7 +1647,61 @@ impl<'a> Parser<'a> {
 
     fn parse_condition(&mut self) -> Result<Condition> {
         self.skip_whitespace();
-        let column = self.read_identifier()?;
+        let name = self.read_identifier()?;
+        self.skip_whitespace();
+
+        // A weight can't start a column name, so `similarity` stays usable
+        // as one
+        if name.eq_ignore_ascii_case("SIMILARITY")
+            && self.peek_char().is_some_and(|c| c.is_ascii_digit() || c == '.' || c == '-')
+        {
+            return self.parse_weighted_similarity();
+        }
+
+        match ScalarFunc::from_name(&name) {
+            Some(func) if self.peek_char() == Some('(') => {
+                let expr = self.parse_scalar_call(func)?;
+                let mut condition = self.parse_predicate(expr.to_string())?;
+                condition.expr = Some(expr);
+                Ok(condition)
+            }
+            _ => self.parse_predicate(name),
+        }
+    }
+
+    /// `weight*column [+ weight*column ...] AGAINST [..]`, after SIMILARITY.
+    /// The condition's `column` holds the terms' SQL text.
+    fn parse_weighted_similarity(&mut self) -> Result<Condition> {
+        let mut terms = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let (weight, _) = self.read_number()?;
+            if weight <= 0.0 {
+                return Err(MarsError::InvalidFormat(format!("SIMILARITY weights must be positive, got {}", weight)));
+            }
+            self.skip_whitespace();
+            self.expect_char('*')?;
+            self.skip_whitespace();
+            terms.push((self.read_identifier()?, weight as f32));
+            self.skip_whitespace();
+            if self.peek_char() != Some('+') {
+                break;
+            }
+            self.advance();
+        }
+        self.expect_keyword("AGAINST")?;
+        self.skip_whitespace();
+        let vec = self.parse_value()?;
+
+        let column = terms.iter()
+            .map(|(column, weight)| format!("{}*{}", weight, column))
+            .collect::<Vec<_>>()
+            .join(" + ");
+        Ok(Condition::new(column, ComparisonOp::SimilarWeighted(terms), ConditionValue::Single(vec)))
+    }
+
+    /// Everything after the left-hand side of a condition
+    fn parse_predicate(&mut self, column: String) -> Result<Condition> {
         self.skip_whitespace();
 
         // Check for IS NULL / IS NOT NULL
@@ -708,14 +1720,14 @@ impl<'a> Parser<'a> {
 
             self.expect_keyword("NULL")?;
 
-            return Ok(Condition {
+            return Ok(Condition::new(
                 column,
-                operator: if is_not { ComparisonOp::IsNotNull } else { ComparisonOp::IsNull },
-                value: ConditionValue::NullCheck,
-            });
+                if is_not { ComparisonOp::IsNotNull } else { ComparisonOp::IsNull },
+                ConditionValue::NullCheck,
+            ));
         }
 
-        // Check for NOT prefix (NOT IN, NOT BETWEEN, NOT LIKE)
+        // Check for NOT prefix (NOT IN, NOT BETWEEN, NOT LIKE, NOT REGEXP)
         let negated = if keyword == "NOT" {
             self.read_keyword()?;
             self.skip_whitespace();
@@ -736,19 +1748,18 @@ impl<'a> Parser<'a> {
             loop {
                 self.skip_whitespace();
                 values.push(self.parse_value()?);
+                self.check_len(values.len(), self.limits.max_in_list, "IN list")?;
                 self.skip_whitespace();
-                if self.peek_char() == Some(')') {
-                    self.advance();
+                if self.end_of_list(')')? {
                     break;
                 }
-                self.expect_char(',')?;
             }
 
-            return Ok(Condition {
+            return Ok(Condition::new(
                 column,
-                operator: if negated { ComparisonOp::NotIn } else { ComparisonOp::In },
-                value: ConditionValue::List(values),
-            });
+                if negated { ComparisonOp::NotIn } else { ComparisonOp::In },
+                ConditionValue::List(values),
+            ));
         }
 
         // BETWEEN
@@ -762,11 +1773,11 @@ impl<'a> Parser<'a> {
             self.skip_whitespace();
             let high = self.parse_value()?;
 
-            return Ok(Condition {
+            return Ok(Condition::new(
                 column,
-                operator: if negated { ComparisonOp::NotBetween } else { ComparisonOp::Between },
-                value: ConditionValue::Range(low, high),
-            });
+                if negated { ComparisonOp::NotBetween } else { ComparisonOp::Between },
+                ConditionValue::Range(low, high),
+            ));
         }
 
         // LIKE
@@ -775,11 +1786,29 @@ impl<'a> Parser<'a> {
             self.skip_whitespace();
             let pattern = self.parse_value()?;
 
-            return Ok(Condition {
+            return Ok(Condition::new(
                 column,
-                operator: if negated { ComparisonOp::NotLike } else { ComparisonOp::Like },
-                value: ConditionValue::Single(pattern),
-            });
+                if negated { ComparisonOp::NotLike } else { ComparisonOp::Like },
+                ConditionValue::Single(pattern),
+            ));
+        }
+
+        // REGEXP; the pattern is compiled (and cached) up front so a bad
+        // pattern is reported as a parse error
+        if next_keyword == "REGEXP" {
+            self.read_keyword()?;
+            self.skip_whitespace();
+            let pattern = self.parse_value()?;
+            match &pattern {
+                Value::Text(p) => { crate::functions::compile_regex(p)?; }
+                _ => return Err(MarsError::InvalidFormat("REGEXP pattern must be a string".into())),
+            }
+
+            return Ok(Condition::new(
+                column,
+                if negated { ComparisonOp::NotRegexp } else { ComparisonOp::Regexp },
+                ConditionValue::Single(pattern),
+            ));
         }
 
         // SIMILARITY (for vectors)
@@ -788,11 +1817,35 @@ impl<'a> Parser<'a> {
             self.skip_whitespace();
             let vec = self.parse_value()?;
 
-            return Ok(Condition {
-                column,
-                operator: ComparisonOp::Similar,
-                value: ConditionValue::Single(vec),
-            });
+            self.skip_whitespace();
+            let operator = match self.peek_keyword_upper().as_str() {
+                "EXACT" => {
+                    self.read_keyword()?;
+                    ComparisonOp::SimilarExact
+                }
+                "USING" => {
+                    self.read_keyword()?;
+                    self.expect_keyword("DIMS")?;
+                    self.skip_whitespace();
+                    let dims = usize::try_from(self.read_integer()?)
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| MarsError::InvalidFormat("USING DIMS must be a positive integer".into()))?;
+                    ComparisonOp::SimilarDims(dims)
+                }
+                "WITHIN" => {
+                    self.read_keyword()?;
+                    self.skip_whitespace();
+                    let (radius, _) = self.read_number()?;
+                    if radius < 0.0 {
+                        return Err(MarsError::InvalidFormat("WITHIN distance must not be negative".into()));
+                    }
+                    ComparisonOp::SimilarWithin(radius as f32)
+                }
+                _ => ComparisonOp::Similar,
+            };
+
+            return Ok(Condition::new(column, operator, ConditionValue::Single(vec)));
         }
 
         // Standard comparison operators
@@ -800,16 +1853,125 @@ impl<'a> Parser<'a> {
         self.skip_whitespace();
         let value = self.parse_value()?;
 
-        Ok(Condition {
-            column,
-            operator,
-            value: ConditionValue::Single(value),
-        })
+        Ok(Condition::new(column, operator, ConditionValue::Single(value)))
+    }
+
+    /// Argument list of a scalar function; the name has already been read.
+    /// Each call counts as a level of nesting.
+    fn parse_scalar_call(&mut self, func: ScalarFunc) -> Result<ScalarExpr> {
+        self.nested(|p| p.parse_scalar_call_args(func))
+    }
+
+    fn parse_scalar_call_args(&mut self, func: ScalarFunc) -> Result<ScalarExpr> {
+        self.skip_whitespace();
+        self.expect_char('(')?;
+
+        let mut args = Vec::new();
+        self.skip_whitespace();
+        if self.peek_char() != Some(')') {
+            loop {
+                args.push(self.parse_scalar_arg()?);
+                self.skip_whitespace();
+                if self.peek_char() == Some(',') {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+        self.skip_whitespace();
+        self.expect_char(')')?;
+
+        let (min, max) = func.arity();
+        if args.len() < min || args.len() > max {
+            return Err(MarsError::InvalidFormat(format!(
+                "Wrong number of arguments to {}: got {}", func.name(), args.len()
+            )));
+        }
+        // Catch a misspelled analyzer now rather than returning NULLs
+        if let Some(ScalarExpr::Literal(Value::Text(spec))) = func.analyzer_arg().and_then(|i| args.get(i)) {
+            Analyzer::parse(spec)?;
+        }
+        Ok(ScalarExpr::Call { func, args })
+    }
+
+    /// A function argument: literal, column, or nested call
+    fn parse_scalar_arg(&mut self) -> Result<ScalarExpr> {
+        self.skip_whitespace();
+        match self.peek_char() {
+            Some(c) if c == '\'' || c == '"' || c == '-' || c.is_ascii_digit() => {
+                Ok(ScalarExpr::Literal(self.parse_value()?))
+            }
+            _ => {
+                let name = self.read_identifier()?;
+                self.skip_whitespace();
+                match ScalarFunc::from_name(&name) {
+                    Some(func) if self.peek_char() == Some('(') => self.parse_scalar_call(func),
+                    _ if name.eq_ignore_ascii_case("NULL") => Ok(ScalarExpr::Literal(Value::Null)),
+                    _ => Ok(ScalarExpr::Column(name)),
+                }
+            }
+        }
+    }
+
+    /// An arithmetic expression over scalar operands; `* / %` bind tighter
+    /// than `+ -` and parentheses group
+    fn parse_arith_expr(&mut self) -> Result<ScalarExpr> {
+        let mut expr = self.parse_arith_term()?;
+        loop {
+            self.skip_whitespace();
+            let op = match self.peek_char() {
+                Some('+') => ArithOp::Add,
+                Some('-') => ArithOp::Sub,
+                _ => return Ok(expr),
+            };
+            self.advance();
+            let right = self.parse_arith_term()?;
+            expr = ScalarExpr::Binary { op, left: Box::new(expr), right: Box::new(right) };
+        }
+    }
+
+    fn parse_arith_term(&mut self) -> Result<ScalarExpr> {
+        let mut expr = self.parse_arith_operand()?;
+        loop {
+            self.skip_whitespace();
+            let op = match self.peek_char() {
+                Some('*') => ArithOp::Mul,
+                Some('/') => ArithOp::Div,
+                Some('%') => ArithOp::Rem,
+                _ => return Ok(expr),
+            };
+            self.advance();
+            let right = self.parse_arith_operand()?;
+            expr = ScalarExpr::Binary { op, left: Box::new(expr), right: Box::new(right) };
+        }
+    }
+
+    /// A parenthesized expression, a literal of any type, a column or a
+    /// function call
+    fn parse_arith_operand(&mut self) -> Result<ScalarExpr> {
+        self.skip_whitespace();
+        match self.peek_char() {
+            Some('(') => {
+                self.advance();
+                let expr = self.nested(Self::parse_arith_expr)?;
+                self.skip_whitespace();
+                self.expect_char(')')?;
+                Ok(expr)
+            }
+            Some('[' | '{') => Ok(ScalarExpr::Literal(self.parse_value()?)),
+            _ => match self.parse_scalar_arg()? {
+                ScalarExpr::Column(name) if name.eq_ignore_ascii_case("TRUE") => Ok(ScalarExpr::Literal(Value::Boolean(true))),
+                ScalarExpr::Column(name) if name.eq_ignore_ascii_case("FALSE") => Ok(ScalarExpr::Literal(Value::Boolean(false))),
+                expr => Ok(expr),
+            },
+        }
     }
 
     fn parse_comparison_op(&mut self) -> Result<ComparisonOp> {
+        self.token_start = self.pos;
         let ch = self.peek_char().ok_or_else(|| {
-            MarsError::InvalidFormat("Unexpected end of input".into())
+            MarsError::InvalidFormat(format!("Expected {}, got end of input", OPERATORS))
         })?;
 
         match ch {
@@ -844,7 +2006,7 @@ impl<'a> Parser<'a> {
                     Ok(ComparisonOp::Gt)
                 }
             }
-            _ => Err(MarsError::InvalidFormat(format!("Expected comparison operator, got '{}'", ch)))
+            _ => Err(MarsError::InvalidFormat(format!("Expected {}, got '{}'", OPERATORS, ch)))
         }
     }
 
@@ -947,6 +2109,56 @@ impl<'a> Parser<'a> {
         Ok(Some(n))
     }
 
+    /// `MAX PER (column) n` and `MMR lambda`, in either order
+    fn parse_diversity(&mut self) -> Result<Option<Diversity>> {
+        let mut diversity = Diversity::default();
+
+        loop {
+            self.skip_whitespace();
+            match self.peek_keyword_upper().as_str() {
+                "MAX" if diversity.max_per.is_none() => {
+                    self.read_keyword()?;
+                    self.skip_whitespace();
+                    self.expect_keyword("PER")?;
+                    self.skip_whitespace();
+                    self.expect_char('(')?;
+                    self.skip_whitespace();
+                    let column = self.read_identifier()?;
+                    self.skip_whitespace();
+                    self.expect_char(')')?;
+                    self.skip_whitespace();
+                    let n = self.read_integer()?;
+                    if n < 1 {
+                        return Err(MarsError::InvalidFormat("MAX PER limit must be at least 1".into()));
+                    }
+                    diversity.max_per = Some((column, n as usize));
+                }
+                "MMR" if diversity.mmr_lambda.is_none() => {
+                    self.read_keyword()?;
+                    self.skip_whitespace();
+                    let (lambda, _) = self.read_number()?;
+                    if !(0.0..=1.0).contains(&lambda) {
+                        return Err(MarsError::InvalidFormat("MMR lambda must be between 0 and 1".into()));
+                    }
+                    diversity.mmr_lambda = Some(lambda as f32);
+                }
+                _ => break,
+            }
+        }
+
+        Ok((diversity != Diversity::default()).then_some(diversity))
+    }
+
+    /// `SCORE expr` after a similarity WHERE clause
+    fn parse_score(&mut self) -> Result<Option<ScalarExpr>> {
+        self.skip_whitespace();
+        if self.peek_keyword_upper() != "SCORE" {
+            return Ok(None);
+        }
+        self.read_keyword()?;
+        Ok(Some(self.parse_arith_expr()?))
+    }
+
     fn parse_offset(&mut self) -> Result<Option<usize>> {
         self.skip_whitespace();
         if self.peek_keyword_upper() != "OFFSET" {
@@ -961,9 +2173,10 @@ impl<'a> Parser<'a> {
     // ==================== VALUE PARSING ====================
     fn parse_value(&mut self) -> Result<Value> {
         self.skip_whitespace();
+        self.token_start = self.pos;
 
         let ch = self.peek_char().ok_or_else(|| {
-            MarsError::InvalidFormat("Unexpected end of input".into())
+            MarsError::InvalidFormat(format!("Expected {}, got end of input", VALUE_TOKENS))
         })?;
 
         match ch {
@@ -974,23 +2187,32 @@ impl<'a> Parser<'a> {
             }
             '[' => {
                 self.advance();
+                self.skip_whitespace();
+                if self.peek_char() == Some('[') {
+                    return Ok(Value::MultiVector(self.read_multi_vector_content()?));
+                }
                 let nums = self.read_vector_content()?;
                 Ok(Value::Vector(nums))
             }
+            '{' => {
+                self.advance();
+                let pairs = self.read_sparse_vector_content()?;
+                Ok(Value::SparseVector(pairs))
+            }
             't' | 'T' | 'f' | 'F' => {
-                let kw = self.read_keyword_upper()?;
-                match kw.as_str() {
+                let kw = self.read_keyword()?;
+                match kw.to_uppercase().as_str() {
                     "TRUE" => Ok(Value::Boolean(true)),
                     "FALSE" => Ok(Value::Boolean(false)),
-                    _ => Err(MarsError::InvalidFormat(format!("Unknown keyword: {}", kw))),
+                    _ => Err(MarsError::InvalidFormat(format!("Expected {}, got '{}'", VALUE_TOKENS, kw))),
                 }
             }
             'n' | 'N' => {
-                let kw = self.read_keyword_upper()?;
-                if kw == "NULL" {
+                let kw = self.read_keyword()?;
+                if kw.eq_ignore_ascii_case("NULL") {
                     Ok(Value::Null)
                 } else {
-                    Err(MarsError::InvalidFormat(format!("Unknown keyword: {}", kw)))
+                    Err(MarsError::InvalidFormat(format!("Expected {}, got '{}'", VALUE_TOKENS, kw)))
                 }
             }
             '-' | '0'..='9' => {
@@ -1001,7 +2223,7 @@ impl<'a> Parser<'a> {
                     Ok(Value::Integer(n as i64))
                 }
             }
-            _ => Err(MarsError::InvalidFormat(format!("Unexpected character: {}", ch))),
+            _ => Err(MarsError::InvalidFormat(format!("Expected {}, got '{}'", VALUE_TOKENS, ch))),
         }
     }
 
@@ -1015,6 +2237,7 @@ impl<'a> Parser<'a> {
             }
             let (n, _) = self.read_number()?;
             nums.push(n as f32);
+            self.check_len(nums.len(), self.limits.max_vector_len, "Vector")?;
             self.skip_whitespace();
             if self.peek_char() == Some(',') {
                 self.advance();
@@ -1023,6 +2246,69 @@ impl<'a> Parser<'a> {
         Ok(nums)
     }
 
+    /// Read `[..], [..]]` after the opening bracket of a multi-vector.
+    fn read_multi_vector_content(&mut self) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek_char() {
+                Some('[') => {
+                    self.advance();
+                    vectors.push(self.read_vector_content()?);
+                    self.check_len(vectors.len(), self.limits.max_vector_len, "Multi-vector")?;
+                }
+                Some(']') => {
+                    self.advance();
+                    break;
+                }
+                _ => return Err(MarsError::InvalidFormat("Expected '[' or ']' in multi-vector".into())),
+            }
+            self.skip_whitespace();
+            if self.peek_char() == Some(',') {
+                self.advance();
+            }
+        }
+        Ok(vectors)
+    }
+
+    /// Read `index: weight, ...}` after the opening brace of a sparse vector.
+    /// Pairs are returned sorted by index.
+    fn read_sparse_vector_content(&mut self) -> Result<Vec<(u32, f32)>> {
+        let mut pairs: Vec<(u32, f32)> = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.peek_char() == Some('}') {
+                self.advance();
+                break;
+            }
+
+            let index = self.read_integer()?;
+            let index = u32::try_from(index).map_err(|_| {
+                MarsError::InvalidFormat(format!("Invalid sparse vector index: {}", index))
+            })?;
+            self.skip_whitespace();
+            self.expect_char(':')?;
+            self.skip_whitespace();
+            let (weight, _) = self.read_number()?;
+            pairs.push((index, weight as f32));
+            self.check_len(pairs.len(), self.limits.max_vector_len, "Sparse vector")?;
+
+            self.skip_whitespace();
+            match self.peek_char() {
+                Some(',') => self.advance(),
+                Some('}') => {}
+                _ => return Err(MarsError::InvalidFormat("Expected ',' or '}' in sparse vector".into())),
+            }
+        }
+
+        pairs.sort_by_key(|&(index, _)| index);
+        if pairs.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(MarsError::InvalidFormat("Duplicate index in sparse vector".into()));
+        }
+        Ok(pairs)
+    }
+
     fn parse_column_type(&mut self) -> Result<ColumnType> {
         let type_name = self.read_keyword_upper()?;
 
@@ -1034,6 +2320,11 @@ impl<'a> Parser<'a> {
                 let dim = self.read_integer()? as usize;
                 self.skip_whitespace();
                 self.expect_char(')')?;
+                self.skip_whitespace();
+                if self.peek_keyword_upper() == "MULTI" {
+                    self.read_keyword()?;
+                    return Ok(ColumnType::MultiVector(dim));
+                }
                 Ok(ColumnType::Vector(dim))
             }
             "TEXT" | "VARCHAR" | "STRING" | "CHAR" => Ok(ColumnType::Text),
@@ -1041,7 +2332,11 @@ impl<'a> Parser<'a> {
             "FLOAT" | "REAL" | "DOUBLE" | "DECIMAL" | "NUMERIC" => Ok(ColumnType::Float),
             "BOOLEAN" | "BOOL" => Ok(ColumnType::Boolean),
             "BLOB" | "BINARY" => Ok(ColumnType::Blob),
-            _ => Err(MarsError::InvalidFormat(format!("Unknown type: {}", type_name))),
+            "SPARSE_VECTOR" | "SPARSEVEC" => Ok(ColumnType::SparseVector),
+            "UUID" => Ok(ColumnType::Uuid),
+            _ => Err(MarsError::InvalidFormat(format!(
+                "Unknown type: {}, expected VECTOR(n), TEXT, INTEGER, FLOAT, BOOLEAN, BLOB, SPARSE_VECTOR or UUID", type_name
+            ))),
         }
     }
 
@@ -1074,7 +2369,25 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// After a list item: consume `close` (returning true) or a ','
+    fn end_of_list(&mut self, close: char) -> Result<bool> {
+        self.token_start = self.pos;
+        match self.peek_char() {
+            Some(c) if c == close => {
+                self.advance();
+                Ok(true)
+            }
+            Some(',') => {
+                self.advance();
+                Ok(false)
+            }
+            Some(c) => Err(MarsError::InvalidFormat(format!("Expected ',' or '{}', got '{}'", close, c))),
+            None => Err(MarsError::InvalidFormat(format!("Expected ',' or '{}', got end of input", close))),
+        }
+    }
+
     fn expect_char(&mut self, expected: char) -> Result<()> {
+        self.token_start = self.pos;
         let ch = self.peek_char().ok_or_else(|| {
             MarsError::InvalidFormat(format!("Expected '{}', got end of input", expected))
         })?;
@@ -1101,6 +2414,7 @@ impl<'a> Parser<'a> {
     fn read_keyword(&mut self) -> Result<String> {
         self.skip_whitespace();  // Skip leading whitespace
         let start = self.pos;
+        self.token_start = start;
         let mut end = start;
         for ch in self.input[start..].chars() {
             if ch.is_alphanumeric() || ch == '_' || ch == '*' {
@@ -1110,7 +2424,10 @@ impl<'a> Parser<'a> {
             }
         }
         if end == start {
-            return Err(MarsError::InvalidFormat("Expected keyword".into()));
+            return Err(MarsError::InvalidFormat(match self.peek_char() {
+                Some(c) => format!("Expected a name or keyword, got '{}'", c),
+                None => "Expected a name or keyword, got end of input".into(),
+            }));
         }
         let keyword = self.input[start..end].to_string();
         self.pos = end;
@@ -1199,7 +2516,11 @@ impl<'a> Parser<'a> {
                     '\\' => result.push('\\'),
                     '\'' => result.push('\''),
                     '"' => result.push('"'),
-                    _ => result.push(escaped),
+                    // Keep unknown escapes verbatim so REGEXP classes like `\d` survive
+                    _ => {
+                        result.push('\\');
+                        result.push(escaped);
+                    }
                 }
                 self.advance();
             } else {
@@ -1216,6 +2537,142 @@ pub fn parse(input: &str) -> Result<Command> {
     Parser::new(input).parse()
 }
 
+/// `parse`, rejecting input beyond `limits`
+pub fn parse_with_limits(input: &str, limits: &ParseLimits) -> Result<Command> {
+    Parser::with_limits(input, *limits).parse()
+}
+
+/// Split a script into statements on `;`, ignoring semicolons inside
+/// string literals, brackets and `--` comments. Comments are dropped and
+/// empty statements skipped.
+pub fn split_statements(input: &str) -> Vec<String> {
+    let (mut statements, rest) = split_terminated(input);
+    if !rest.is_empty() {
+        statements.push(rest);
+    }
+    statements
+}
+
+/// Check that input ends at a statement boundary: every statement is
+/// closed by `;` and no string literal is left open. Blank input and bare
+/// comments count as complete.
+pub fn is_complete_statement(input: &str) -> bool {
+    split_terminated(input).1.is_empty()
+}
+
+/// `;`-terminated statements plus the trimmed unterminated remainder
+fn split_terminated(input: &str) -> (Vec<String>, String) {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut chars = input.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\'' | '"' => {
+                current.push(ch);
+                while let Some(c) = chars.next() {
+                    current.push(c);
+                    if c == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            current.push(escaped);
+                        }
+                    } else if c == ch {
+                        // A doubled quote is an escaped quote, not the end
+                        if chars.peek() == Some(&ch) {
+                            current.push(chars.next().unwrap());
+                        } else {
+                            break;
+                        }
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            }
+            '[' | '(' | '{' => {
+                depth += 1;
+                current.push(ch);
+            }
+            ']' | ')' | '}' => {
+                depth = depth.saturating_sub(1);
+                current.push(ch);
+            }
+            ';' if depth == 0 => {
+                if !current.trim().is_empty() {
+                    statements.push(current.trim().to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(ch),
+        }
+    }
+    (statements, current.trim().to_string())
+}
+
+/// Parse a UTC timestamp ('YYYY-MM-DD', 'YYYY-MM-DD HH:MM:SS' or
+/// 'YYYY-MM-DDTHH:MM:SS[Z]') into unix milliseconds
+fn parse_timestamp(s: &str) -> Result<i64> {
+    let invalid = || MarsError::InvalidFormat(format!("Invalid timestamp: '{}'", s));
+    let s = s.trim().trim_end_matches('Z');
+
+    let (date, time) = match s.find([' ', 'T']) {
+        Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+        None => (s, None),
+    };
+
+    let date_parts: Vec<i64> = date.split('-')
+        .map(|p| p.parse().map_err(|_| invalid()))
+        .collect::<Result<_>>()?;
+    let [year, month, day] = date_parts[..] else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+
+    let mut secs_of_day = 0;
+    if let Some(time) = time {
+        let time_parts: Vec<f64> = time.split(':')
+            .map(|p| p.parse::<f64>().map_err(|_| invalid()))
+            .collect::<Result<_>>()?;
+        let (h, m, sec) = match time_parts[..] {
+            [h, m] => (h, m, 0.0),
+            [h, m, sec] => (h, m, sec),
+            _ => return Err(invalid()),
+        };
+        if !(0.0..24.0).contains(&h) || h.fract() != 0.0 || !(0.0..60.0).contains(&m) || m.fract() != 0.0 || !(0.0..60.0).contains(&sec) {
+            return Err(invalid());
+        }
+        secs_of_day = h as i64 * 3600 + m as i64 * 60 + sec as i64;
+    }
+
+    // Days since 1970-01-01 (Howard Hinnant's days_from_civil)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Ok((days * 86400 + secs_of_day) * 1000)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1226,14 +2683,51 @@ mod tests {
         let cmd = parse(sql).unwrap();
 
         match cmd {
-            Command::CreateTable { name, columns } => {
+            Command::CreateTable { name, columns, metric, normalize, columnar } => {
                 assert_eq!(name, "documents");
                 assert_eq!(columns.len(), 3);
                 assert!(columns[0].primary_key);
                 assert_eq!(columns[1].data_type, ColumnType::Vector(768));
+                assert_eq!(metric, None);
+                assert!(!normalize);
+                assert!(!columnar);
+            }
+            _ => panic!("Expected CreateTable"),
+        }
+
+        let sql = "CREATE TABLE terms (id INTEGER, bm25 SPARSE_VECTOR);";
+        match parse(sql).unwrap() {
+            Command::CreateTable { columns, .. } => assert_eq!(columns[1].data_type, ColumnType::SparseVector),
+            _ => panic!("Expected CreateTable"),
+        }
+
+        let sql = "CREATE TABLE codes (embedding VECTOR(64)) METRIC Hamming;";
+        match parse(sql).unwrap() {
+            Command::CreateTable { metric, normalize, .. } => {
+                assert_eq!(metric.as_deref(), Some("hamming"));
+                assert!(!normalize);
+            }
+            _ => panic!("Expected CreateTable"),
+        }
+
+        let sql = "CREATE TABLE docs (embedding VECTOR(64)) METRIC cosine NORMALIZE;";
+        match parse(sql).unwrap() {
+            Command::CreateTable { metric, normalize, .. } => {
+                assert_eq!(metric.as_deref(), Some("cosine"));
+                assert!(normalize);
             }
             _ => panic!("Expected CreateTable"),
         }
+
+        let sql = "CREATE TABLE docs (embedding VECTOR(64), n INTEGER) METRIC cosine STORAGE columnar;";
+        match parse(sql).unwrap() {
+            Command::CreateTable { metric, columnar, .. } => {
+                assert_eq!(metric.as_deref(), Some("cosine"));
+                assert!(columnar);
+            }
+            _ => panic!("Expected CreateTable"),
+        }
+        assert!(parse("CREATE TABLE docs (embedding VECTOR(64)) STORAGE sideways;").is_err());
     }
 
     #[test]
@@ -1242,14 +2736,185 @@ mod tests {
         let cmd = parse(sql).unwrap();
 
         match cmd {
-            Command::Insert { table, columns, values } => {
+            Command::Insert { table, columns, values, on_conflict } => {
                 assert_eq!(table, "docs");
                 assert_eq!(values.len(), 3);
+                assert_eq!(on_conflict, OnConflict::Abort);
             }
             _ => panic!("Expected Insert"),
         }
     }
 
+    #[test]
+    fn test_parse_insert_or() {
+        for (sql, expected) in [
+            ("INSERT OR IGNORE INTO docs (name) VALUES ('a');", OnConflict::Ignore),
+            ("insert or replace into docs (name) values ('a');", OnConflict::Replace),
+        ] {
+            let Command::Insert { on_conflict, .. } = parse(sql).unwrap() else { panic!("expected INSERT") };
+            assert_eq!(on_conflict, expected);
+        }
+        assert!(parse("INSERT OR FAIL INTO docs (name) VALUES ('a');").is_err());
+        assert!(parse("INSERT OR IGNORE INTO docs SELECT * FROM old;").is_err());
+    }
+
+    #[test]
+    fn test_parse_insert_select_and_attach() {
+        match parse("INSERT INTO docs SELECT * FROM shard.docs;").unwrap() {
+            Command::InsertSelect { table, source_database, source } => {
+                assert_eq!(table, "docs");
+                assert_eq!(source_database.as_deref(), Some("shard"));
+                assert_eq!(source, "docs");
+            }
+            _ => panic!("Expected InsertSelect"),
+        }
+        assert!(matches!(parse("insert into a select * from b").unwrap(),
+            Command::InsertSelect { source_database: None, .. }));
+        assert!(parse("INSERT INTO a (x) SELECT * FROM b;").is_err());
+        assert!(parse("INSERT INTO a SELECT x FROM b;").is_err());
+
+        match parse("ATTACH DATABASE 'shard 1.db' AS shard;").unwrap() {
+            Command::Attach { path, name } => assert_eq!((path.as_str(), name.as_str()), ("shard 1.db", "shard")),
+            _ => panic!("Expected Attach"),
+        }
+        assert!(matches!(parse("DETACH shard").unwrap(), Command::Detach { name } if name == "shard"));
+        assert!(parse("ATTACH DATABASE 42 AS shard;").is_err());
+    }
+
+    #[test]
+    fn test_parse_materialized_view() {
+        match parse("CREATE MATERIALIZED VIEW stats AS SELECT category, COUNT(*) FROM docs GROUP BY category;").unwrap() {
+            Command::CreateMaterializedView { name, select } => {
+                assert_eq!(name, "stats");
+                assert!(matches!(*select, Command::Select { ref table, group_by: Some(_), .. } if table == "docs"));
+            }
+            _ => panic!("Expected CreateMaterializedView"),
+        }
+        assert!(matches!(parse("drop materialized view if exists stats").unwrap(),
+            Command::DropMaterializedView { name, if_exists: true } if name == "stats"));
+        assert!(parse("CREATE MATERIALIZED VIEW stats SELECT * FROM docs;").is_err());
+        assert!(parse("CREATE MATERIALIZED VIEW stats AS SELECT * FROM a JOIN b ON a.id = b.id;").is_err());
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        match parse("EXPLAIN SELECT * FROM docs WHERE id = 3;").unwrap() {
+            Command::Explain { statement } => assert!(matches!(*statement, Command::Select { .. })),
+            _ => panic!("Expected Explain"),
+        }
+        assert!(matches!(parse("explain delete from docs").unwrap(), Command::Explain { .. }));
+        assert!(parse("EXPLAIN CREATE TABLE t (v VECTOR(2));").is_err());
+        assert!(parse("EXPLAIN EXPLAIN SELECT * FROM docs;").is_err());
+    }
+
+    #[test]
+    fn test_parse_references() {
+        let sql = "CREATE TABLE chunks (embedding VECTOR(2), doc_id INTEGER REFERENCES docs ON DELETE CASCADE, \
+                   slug TEXT REFERENCES pages(slug) ON DELETE SET NULL, owner INTEGER REFERENCES users(id));";
+        let Command::CreateTable { columns, .. } = parse(sql).unwrap() else { panic!("Expected CreateTable") };
+        let fk = |i: usize| columns[i].references.clone().unwrap();
+        assert_eq!(fk(1), ForeignKey {
+            column: "doc_id".into(), parent_table: "docs".into(), parent_column: "id".into(), on_delete: OnDelete::Cascade,
+        });
+        assert_eq!((fk(2).parent_column.as_str(), fk(2).on_delete), ("slug", OnDelete::SetNull));
+        assert_eq!(fk(3).on_delete, OnDelete::Restrict);
+        assert!(columns[0].references.is_none());
+        assert!(parse("CREATE TABLE c (doc_id INTEGER REFERENCES docs ON DELETE EXPLODE);").is_err());
+    }
+
+    #[test]
+    fn test_parse_analyze() {
+        assert!(matches!(parse("ANALYZE docs;").unwrap(), Command::Analyze { table: Some(t) } if t == "docs"));
+        assert!(matches!(parse("analyze").unwrap(), Command::Analyze { table: None }));
+        assert!(matches!(parse("ANALYZE ;").unwrap(), Command::Analyze { table: None }));
+    }
+
+    #[test]
+    fn test_parse_vacuum() {
+        assert!(matches!(parse("VACUUM docs;").unwrap(), Command::Vacuum { table: Some(t) } if t == "docs"));
+        assert!(matches!(parse("vacuum").unwrap(), Command::Vacuum { table: None }));
+    }
+
+    #[test]
+    fn test_parse_check_recall() {
+        assert!(matches!(parse("CHECK RECALL ON docs SAMPLE 1000;").unwrap(),
+            Command::CheckRecall { table, sample: 1000, k: 10 } if table == "docs"));
+        assert!(matches!(parse("check recall on docs limit 5").unwrap(), Command::CheckRecall { sample: 100, k: 5, .. }));
+        assert!(parse("CHECK RECALL ON docs SAMPLE 0;").is_err());
+        assert!(parse("CHECK docs;").is_err());
+    }
+
+    #[test]
+    fn test_parse_reindex() {
+        assert!(matches!(parse("REINDEX docs;").unwrap(), Command::Reindex { table } if table == "docs"));
+        assert!(parse("REINDEX;").is_err());
+    }
+
+    #[test]
+    fn test_parse_bulk_load() {
+        assert!(matches!(parse("BEGIN BULK LOAD;").unwrap(), Command::BeginBulkLoad));
+        assert!(matches!(parse("end bulk load").unwrap(), Command::EndBulkLoad));
+        assert!(parse("BEGIN TRANSACTION;").is_err());
+        assert!(parse("END BULK;").is_err());
+        assert!(parse("ANALYZE 'docs';").is_err());
+    }
+
+    #[test]
+    fn test_parse_auto_increment() {
+        let Command::CreateTable { columns, .. } = parse(
+            "CREATE TABLE docs (id INTEGER PRIMARY KEY AUTOINCREMENT START WITH 100 INCREMENT BY 10, v VECTOR(2));",
+        ).unwrap() else { panic!("expected CREATE TABLE") };
+        assert_eq!(columns[0].auto_increment, Some(AutoIncrement::Sequence { start: 100, step: 10 }));
+        assert!(columns[1].auto_increment.is_none());
+
+        let Command::CreateTable { columns, .. } = parse("CREATE TABLE t (key TEXT PRIMARY KEY AUTOINCREMENT);").unwrap() else {
+            panic!("expected CREATE TABLE")
+        };
+        assert_eq!(columns[0].auto_increment, Some(AutoIncrement::Uuid { column: "key".into() }));
+        assert!(columns[0].unique);
+
+        assert!(parse("CREATE TABLE t (n INTEGER AUTOINCREMENT);").is_err());
+        assert!(parse("CREATE TABLE t (id INTEGER AUTOINCREMENT STEP 0);").is_err());
+        assert!(parse("CREATE TABLE t (key TEXT AUTOINCREMENT START 5);").is_err());
+
+        assert!(matches!(parse("SELECT last_insert_rowid();").unwrap(), Command::LastInsertRowid));
+        assert!(matches!(parse("SELECT last_insert_rowid FROM t;").unwrap(), Command::Select { .. }));
+    }
+
+    #[test]
+    fn test_parse_uuid_column() {
+        let Command::CreateTable { columns, .. } = parse(
+            "CREATE TABLE docs (id UUID PRIMARY KEY DEFAULT gen_random_uuid(), parent uuid, v VECTOR(2));",
+        ).unwrap() else { panic!("expected CREATE TABLE") };
+        assert_eq!(columns[0].data_type, ColumnType::Uuid);
+        assert_eq!(columns[0].auto_increment, Some(AutoIncrement::Uuid { column: "id".into() }));
+        assert!(columns[0].unique && columns[0].default.is_none());
+        assert_eq!((columns[1].data_type.clone(), columns[1].auto_increment.clone()), (ColumnType::Uuid, None));
+
+        assert!(parse("CREATE TABLE t (n INTEGER DEFAULT gen_random_uuid());").is_err());
+        assert!(parse("CREATE TABLE t (k UUID DEFAULT gen_random_uuid() AUTOINCREMENT);").is_err());
+    }
+
+    #[test]
+    fn test_parse_update_expressions() {
+        let Command::Update { assignments, where_clause, .. } = parse(
+            "UPDATE docs SET views = views + 1, score = score * 0.99 - (views % 3) / 2, tags = UPPER(tags), \
+             flag = true, embedding = [1.0, 0.0] WHERE id = 1;",
+        ).unwrap() else { panic!("expected UPDATE") };
+        let exprs: Vec<String> = assignments[..4].iter().map(|(column, expr)| format!("{} = {}", column, expr)).collect();
+        assert_eq!(exprs, [
+            "views = views + 1",
+            "score = score * 0.99 - views % 3 / 2",
+            "tags = UPPER(tags)",
+            "flag = true",
+        ]);
+        assert_eq!(assignments[4].1, ScalarExpr::Literal(Value::Vector(vec![1.0, 0.0])));
+        assert!(where_clause.is_some());
+
+        assert!(parse("UPDATE docs SET views = views +;").is_err());
+        assert!(parse("UPDATE docs SET views = (views + 1;").is_err());
+    }
+
     #[test]
     fn test_parse_select_aggregate() {
         let sql = "SELECT COUNT(*), AVG(score) FROM users;";
@@ -1263,6 +2928,23 @@ mod tests {
             }
             _ => panic!("Expected Select"),
         }
+
+        match parse("SELECT category, VEC_AVG(embedding) FROM docs GROUP BY category;").unwrap() {
+            Command::Select { columns, .. } => {
+                assert!(matches!(columns[1], SelectColumn::Aggregate { func: AggregateFunc::VecAvg, .. }));
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        match parse("SELECT APPROX_COUNT(DISTINCT category) FROM docs;").unwrap() {
+            Command::Select { columns, .. } => match &columns[0] {
+                SelectColumn::Aggregate { func: AggregateFunc::ApproxCountDistinct, column, .. } => {
+                    assert_eq!(column, "category");
+                }
+                other => panic!("Expected APPROX_COUNT, got {:?}", other),
+            },
+            _ => panic!("Expected Select"),
+        }
     }
 
     #[test]
@@ -1365,6 +3047,86 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_pragma() {
+        let cmd = parse("PRAGMA synchronous = full;").unwrap();
+        assert!(matches!(cmd, Command::Pragma { ref name, value: Some(Value::Text(ref v)) } if name == "synchronous" && v == "FULL"));
+        let cmd = parse("pragma EF_SEARCH(200)").unwrap();
+        assert!(matches!(cmd, Command::Pragma { ref name, value: Some(Value::Integer(200)) } if name == "ef_search"));
+        let cmd = parse("PRAGMA statement_timeout;").unwrap();
+        assert!(matches!(cmd, Command::Pragma { ref name, value: None } if name == "statement_timeout"));
+        let cmd = parse("SET memory_limit = NULL;").unwrap();
+        assert!(matches!(cmd, Command::Set { value: Value::Null, .. }));
+        assert!(parse("PRAGMA ef_search(200;").is_err());
+    }
+
+    #[test]
+    fn test_parse_alter_table() {
+        let cmd = parse("ALTER TABLE docs SET (max_neighbors = 32, Search_Buffer = 128);").unwrap();
+        match cmd {
+            Command::AlterTable { name, action } => {
+                assert_eq!(name, "docs");
+                assert_eq!(action, AlterAction::SetIndexOptions(vec![
+                    ("max_neighbors".to_string(), Value::Integer(32)),
+                    ("search_buffer".to_string(), Value::Integer(128)),
+                ]));
+            }
+            _ => panic!("Expected AlterTable"),
+        }
+
+        assert!(parse("ALTER TABLE docs SET max_neighbors = 32").is_err());
+        assert!(parse("ALTER TABLE docs SET ()").is_err());
+
+        let cmd = parse("ALTER TABLE docs SET VECTOR STORAGE INT8 CALIBRATE SAMPLE 500;").unwrap();
+        assert!(matches!(cmd, Command::AlterTable { action: AlterAction::SetVectorStorage(VectorStorage::Int8 { sample: 500 }), .. }));
+        let cmd = parse("ALTER TABLE docs SET VECTOR STORAGE int8").unwrap();
+        assert!(matches!(cmd, Command::AlterTable { action: AlterAction::SetVectorStorage(VectorStorage::Int8 { sample: 10_000 }), .. }));
+        let cmd = parse("ALTER TABLE docs SET VECTOR STORAGE FLOAT32;").unwrap();
+        assert!(matches!(cmd, Command::AlterTable { action: AlterAction::SetVectorStorage(VectorStorage::Float32), .. }));
+        assert!(parse("ALTER TABLE docs SET VECTOR STORAGE INT4").is_err());
+        assert!(parse("ALTER TABLE docs SET VECTOR STORAGE INT8 SAMPLE 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_create_projection() {
+        let cmd = parse("CREATE PROJECTION small ON docs(embedding) DIM 256 METHOD random;").unwrap();
+        match cmd {
+            Command::CreateProjection { name, table, column, dimension, method, sample } => {
+                assert_eq!((name.as_str(), table.as_str(), column.as_str()), ("small", "docs", "embedding"));
+                assert_eq!((dimension, method, sample), (256, ProjectionMethod::Random, 10_000));
+            }
+            _ => panic!("Expected CreateProjection"),
+        }
+        let cmd = parse("CREATE PROJECTION p ON docs (embedding) DIM 64 SAMPLE 500").unwrap();
+        assert!(matches!(cmd, Command::CreateProjection { dimension: 64, method: ProjectionMethod::Pca, sample: 500, .. }));
+
+        assert!(parse("CREATE PROJECTION p ON docs(embedding) DIM 0").is_err());
+        assert!(parse("CREATE PROJECTION p ON docs(embedding) DIM 64 METHOD SVD").is_err());
+        assert!(parse("CREATE PROJECTION p ON docs DIM 64").is_err());
+    }
+
+    #[test]
+    fn test_parse_masks() {
+        let cmd = parse("CREATE TABLE notes (embedding VECTOR(3), content TEXT MASK TRUNCATE(10) NOT NULL, author TEXT MASK hash);").unwrap();
+        match cmd {
+            Command::CreateTable { columns, .. } => {
+                assert_eq!(columns[0].mask, None);
+                assert_eq!(columns[1].mask, Some(Mask::Truncate(10)));
+                assert!(columns[1].not_null);
+                assert_eq!(columns[2].mask, Some(Mask::Hash));
+            }
+            _ => panic!("Expected CreateTable"),
+        }
+
+        let cmd = parse("ALTER TABLE notes ALTER COLUMN content SET MASK REDACT;").unwrap();
+        assert!(matches!(cmd, Command::AlterTable { action: AlterAction::SetMask { ref column, mask: Some(Mask::Redact) }, .. } if column == "content"));
+        let cmd = parse("ALTER TABLE notes ALTER author DROP MASK").unwrap();
+        assert!(matches!(cmd, Command::AlterTable { action: AlterAction::SetMask { mask: None, .. }, .. }));
+
+        assert!(parse("ALTER TABLE notes ALTER content SET MASK SHUFFLE").is_err());
+        assert!(parse("ALTER TABLE notes ALTER content SET MASK TRUNCATE(-1)").is_err());
+    }
+
     #[test]
     fn test_parse_drop_if_exists() {
         let sql = "DROP TABLE IF EXISTS temp;";
@@ -1379,6 +3141,282 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_similarity_exact() {
+        let sql = "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] EXACT LIMIT 3;";
+        match parse(sql).unwrap() {
+            Command::Select { where_clause: Some(wc), limit, .. } => {
+                assert_eq!(wc.conditions[0].operator, ComparisonOp::SimilarExact);
+                assert_eq!(limit, Some(3));
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        let sql = "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] LIMIT 3;";
+        match parse(sql).unwrap() {
+            Command::Select { where_clause: Some(wc), .. } => {
+                assert_eq!(wc.conditions[0].operator, ComparisonOp::Similar);
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        let sql = "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] USING DIMS 1 LIMIT 3;";
+        match parse(sql).unwrap() {
+            Command::Select { where_clause: Some(wc), limit, .. } => {
+                assert_eq!(wc.conditions[0].operator, ComparisonOp::SimilarDims(1));
+                assert_eq!(limit, Some(3));
+            }
+            _ => panic!("Expected Select"),
+        }
+        assert!(parse("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] USING DIMS 0;").is_err());
+        assert!(parse("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] USING 1;").is_err());
+    }
+
+    #[test]
+    fn test_parse_weighted_similarity() {
+        let sql = "SELECT * FROM docs WHERE SIMILARITY 0.7*title_emb + 0.3 * body_emb AGAINST [1.0, 0.0] LIMIT 3;";
+        match parse(sql).unwrap() {
+            Command::Select { where_clause: Some(wc), limit, .. } => {
+                let cond = &wc.conditions[0];
+                assert_eq!(
+                    cond.operator,
+                    ComparisonOp::SimilarWeighted(vec![("title_emb".into(), 0.7), ("body_emb".into(), 0.3)]),
+                );
+                assert_eq!(cond.column, "0.7*title_emb + 0.3*body_emb");
+                assert_eq!(limit, Some(3));
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        // A column named similarity is still a column
+        match parse("SELECT * FROM docs WHERE similarity > 2;").unwrap() {
+            Command::Select { where_clause: Some(wc), .. } => {
+                assert_eq!((wc.conditions[0].column.as_str(), &wc.conditions[0].operator), ("similarity", &ComparisonOp::Gt));
+            }
+            _ => panic!("Expected Select"),
+        }
+        assert!(parse("SELECT * FROM docs WHERE SIMILARITY 0.7*title_emb [1.0, 0.0];").is_err());
+        assert!(parse("SELECT * FROM docs WHERE SIMILARITY 0.7 title_emb AGAINST [1.0, 0.0];").is_err());
+        assert!(parse("SELECT * FROM docs WHERE SIMILARITY 0.7*title_emb + 0*body_emb AGAINST [1.0, 0.0];").is_err());
+        assert!(parse("SELECT * FROM docs WHERE SIMILARITY -0.5*title_emb AGAINST [1.0, 0.0];").is_err());
+    }
+
+    #[test]
+    fn test_parse_similarity_within() {
+        match parse("DELETE FROM docs WHERE embedding SIMILARITY [1.0, 0.0] WITHIN 0.05;").unwrap() {
+            Command::Delete { where_clause: Some(wc), .. } => {
+                assert_eq!(wc.conditions[0].operator, ComparisonOp::SimilarWithin(0.05));
+                assert!(!wc.conditions[0].operator.is_similarity());
+            }
+            _ => panic!("Expected Delete"),
+        }
+        assert!(parse("DELETE FROM docs WHERE embedding SIMILARITY [1.0, 0.0] WITHIN -1;").is_err());
+    }
+
+    #[test]
+    fn test_parse_score() {
+        let sql = "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] SCORE distance - 0.1 * boost LIMIT 5;";
+        match parse(sql).unwrap() {
+            Command::Select { score: Some(score), limit, .. } => {
+                assert_eq!(score.to_string(), "distance - 0.1 * boost");
+                assert_eq!(limit, Some(5));
+            }
+            _ => panic!("Expected Select with SCORE"),
+        }
+        assert!(parse("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] SCORE;").is_err());
+    }
+
+    #[test]
+    fn test_parse_diversity() {
+        let sql = "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] LIMIT 10 MAX PER (source_id) 2 MMR 0.7;";
+        match parse(sql).unwrap() {
+            Command::Select { limit, diversity: Some(d), .. } => {
+                assert_eq!(limit, Some(10));
+                assert_eq!(d.max_per, Some(("source_id".to_string(), 2)));
+                assert_eq!(d.mmr_lambda, Some(0.7));
+            }
+            _ => panic!("Expected Select with diversity"),
+        }
+
+        match parse("SELECT * FROM docs LIMIT 5;").unwrap() {
+            Command::Select { diversity, .. } => assert_eq!(diversity, None),
+            _ => panic!("Expected Select"),
+        }
+
+        assert!(parse("SELECT * FROM docs WHERE embedding SIMILARITY [1.0] LIMIT 5 MAX PER (src) 0;").is_err());
+        assert!(parse("SELECT * FROM docs WHERE embedding SIMILARITY [1.0] LIMIT 5 MMR 1.5;").is_err());
+    }
+
+    #[test]
+    fn test_parse_scalar_functions() {
+        let sql = "SELECT UPPER(title), SUBSTR(TRIM(title), 1, 3) AS short FROM docs WHERE LOWER(title) = 'rust';";
+        match parse(sql).unwrap() {
+            Command::Select { columns, where_clause: Some(wc), .. } => {
+                assert_eq!(columns.len(), 2);
+                match &columns[1] {
+                    SelectColumn::Function { expr, alias } => {
+                        assert_eq!(expr.to_string(), "SUBSTR(TRIM(title), 1, 3)");
+                        assert_eq!(alias.as_deref(), Some("short"));
+                    }
+                    _ => panic!("Expected function column"),
+                }
+                assert_eq!(wc.conditions[0].column, "LOWER(title)");
+                assert!(wc.conditions[0].expr.is_some());
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        // A column that happens to share a function's name is still a column
+        match parse("SELECT * FROM docs WHERE length > 3;").unwrap() {
+            Command::Select { where_clause: Some(wc), .. } => assert!(wc.conditions[0].expr.is_none()),
+            _ => panic!("Expected Select"),
+        }
+
+        assert!(parse("SELECT REPLACE(title, 'a') FROM docs;").is_err());
+    }
+
+    #[test]
+    fn test_parse_regexp() {
+        let sql = r"SELECT * FROM docs WHERE title NOT REGEXP '^[A-Z]{3}-\d+';";
+        let result = parse(sql);
+        #[cfg(feature = "regex")]
+        match result.unwrap() {
+            Command::Select { where_clause: Some(wc), .. } => {
+                assert_eq!(wc.conditions[0].operator, ComparisonOp::NotRegexp);
+                match &wc.conditions[0].value {
+                    ConditionValue::Single(Value::Text(p)) => assert_eq!(p, r"^[A-Z]{3}-\d+"),
+                    _ => panic!("Expected text pattern"),
+                }
+                assert!(parse("SELECT * FROM docs WHERE title REGEXP '(';").is_err());
+            }
+            _ => panic!("Expected Select"),
+        }
+        #[cfg(not(feature = "regex"))]
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let script = "CREATE TABLE t (v VECTOR(2), s TEXT);\n\
+            -- a comment; with a semicolon\n\
+            INSERT INTO t (v, s) VALUES ([1.0, 2.0], 'a;b');\n\
+            INSERT INTO t (v, s) VALUES ([3.0, 4.0], 'it''s; \\' here') ;;\n\
+            SELECT * FROM t";
+        let statements = split_statements(script);
+        assert_eq!(statements.len(), 4);
+        assert_eq!(statements[1], "INSERT INTO t (v, s) VALUES ([1.0, 2.0], 'a;b')");
+        assert_eq!(statements[2], "INSERT INTO t (v, s) VALUES ([3.0, 4.0], 'it''s; \\' here')");
+        assert_eq!(statements[3], "SELECT * FROM t");
+        assert!(statements.iter().all(|s| parse(s).is_ok()));
+
+        assert!(split_statements("  ;\n-- only a comment\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_set() {
+        let cmd = parse("SET statement_timeout = 500;").unwrap();
+        assert!(matches!(cmd, Command::Set { ref name, value: Value::Integer(500) } if name == "statement_timeout"));
+
+        let cmd = parse("SET Statement_Timeout TO 0").unwrap();
+        assert!(matches!(cmd, Command::Set { ref name, value: Value::Integer(0) } if name == "statement_timeout"));
+
+        assert!(parse("SET statement_timeout 5;").is_err());
+    }
+
+    #[test]
+    fn test_is_complete_statement() {
+        assert!(is_complete_statement("SELECT * FROM t;"));
+        assert!(is_complete_statement("SELECT 1; -- trailing comment\n"));
+        assert!(is_complete_statement("   "));
+        assert!(!is_complete_statement("SELECT * FROM t\nWHERE x = 1"));
+        assert!(!is_complete_statement("INSERT INTO t (a) VALUES ('x;"));
+        assert!(!is_complete_statement("SELECT 1; SELECT"));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let err = parse("SELECT * FROM docs\nWHERE id ~ 3;").unwrap_err().to_string();
+        assert!(err.contains("got '~' at line 2, column 10"), "{}", err);
+        assert!(err.ends_with("\n  WHERE id ~ 3;\n           ^"), "{}", err);
+
+        let err = parse("INSERT INTO docs (a b) VALUES (1);").unwrap_err().to_string();
+        assert!(err.contains("Expected ',' or ')', got 'b' at line 1, column 21"), "{}", err);
+
+        let err = parse("SELEC * FROM docs;").unwrap_err().to_string();
+        assert!(err.contains("expected CREATE, DROP, INSERT, SELECT"), "{}", err);
+
+        let err = parse("INSERT INTO t (a) VALUES (foo);").unwrap_err().to_string();
+        assert!(err.contains("got 'foo' at line 1, column 27"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_multi_vector() {
+        let sql = "CREATE TABLE docs (tokens VECTOR(2) MULTI NOT NULL, title TEXT);";
+        match parse(sql).unwrap() {
+            Command::CreateTable { columns, .. } => {
+                assert_eq!(columns[0].data_type, ColumnType::MultiVector(2));
+                assert!(columns[0].not_null);
+            }
+            _ => panic!("Expected CreateTable"),
+        }
+
+        let sql = "SELECT * FROM docs WHERE tokens SIMILARITY [[1.0, 0.0], [0.5, 0.5]] LIMIT 3;";
+        match parse(sql).unwrap() {
+            Command::Select { where_clause: Some(wc), .. } => match &wc.conditions[0].value {
+                ConditionValue::Single(v) => {
+                    assert_eq!(v, &Value::MultiVector(vec![vec![1.0, 0.0], vec![0.5, 0.5]]));
+                }
+                _ => panic!("Expected single value"),
+            },
+            _ => panic!("Expected Select"),
+        }
+    }
+
+    #[test]
+    fn test_parse_sparse_vector_literal() {
+        let sql = "INSERT INTO terms (bm25) VALUES ({42: 1.5, 3: 0.25, 7: -1});";
+        match parse(sql).unwrap() {
+            Command::Insert { values, .. } => {
+                assert_eq!(values[0][0], Value::SparseVector(vec![(3, 0.25), (7, -1.0), (42, 1.5)]));
+            }
+            _ => panic!("Expected Insert"),
+        }
+
+        assert!(parse("INSERT INTO terms (bm25) VALUES ({1: 1.0, 1: 2.0});").is_err());
+        assert!(parse("INSERT INTO terms (bm25) VALUES ({-1: 1.0});").is_err());
+        assert!(parse("SELECT * FROM terms WHERE bm25 SIMILARITY {} LIMIT 3;").is_ok());
+    }
+
+    #[test]
+    fn test_parse_soft_delete_and_as_of() {
+        match parse("DELETE FROM docs WHERE id = 1 SOFT;").unwrap() {
+            Command::Delete { soft, where_clause, .. } => {
+                assert!(soft);
+                assert!(where_clause.is_some());
+            }
+            _ => panic!("Expected Delete"),
+        }
+
+        match parse("SELECT * FROM docs AS OF '2024-01-01' WHERE id = 1;").unwrap() {
+            Command::Select { as_of, where_clause, .. } => {
+                assert_eq!(as_of, Some(1_704_067_200_000));
+                assert!(where_clause.is_some());
+            }
+            _ => panic!("Expected Select"),
+        }
+
+        assert_eq!(parse_timestamp("2024-01-01 00:00:01").unwrap(), 1_704_067_201_000);
+        assert!(parse_timestamp("yesterday").is_err());
+        assert_eq!(parse_timestamp("2024-02-29T23:59:59.5Z").unwrap(), 1_709_251_199_000);
+        assert!(parse_timestamp("2026-02-30 25:61:61").is_err());
+        assert!(parse_timestamp("2026-02-29").is_err());
+        assert!(parse_timestamp("2026-04-31").is_err());
+        assert!(parse_timestamp("2026-01-01 24:00").is_err());
+        assert!(parse_timestamp("2026-01-01 12:60").is_err());
+        assert!(parse_timestamp("2026-01-01 12:00:60").is_err());
+        assert!(parse_timestamp("2026-01-01 -1:00").is_err());
+    }
+
     #[test]
     fn test_parse_order_by_desc() {
         let sql = "SELECT * FROM products ORDER BY price DESC;";
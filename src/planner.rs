@@ -0,0 +1,376 @@
+//! Query planning.
+//!
+//! Before a SELECT, UPDATE or DELETE reads a table, the planner picks how to
+//! find candidate rows: the vector index, a row id or UNIQUE index lookup,
+//! the columnar arrays, or a full scan, preferring whichever is estimated to
+//! yield the fewest rows. When the WHERE clause only joins conditions with
+//! AND, the conditions left to check on each candidate are ordered so the
+//! most selective and cheapest run first. `EXPLAIN` shows the chosen plan.
+//!
+//! Selectivity comes from the table's `ANALYZE` statistics when it has
+//! them (distinct counts, NULL counts, numeric histograms) and from fixed
+//! guesses otherwise.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::functions::ScalarExpr;
+use crate::parser::{BoolConnector, ComparisonOp, Condition, ConditionValue, WhereClause};
+use crate::schema::Value;
+use crate::table::Table;
+
+/// How a plan finds its candidate rows
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum AccessPath {
+    /// Approximate nearest-neighbor search in the graph index
+    VectorIndex { column: String },
+    /// Distance computed for every row: SIMILARITY EXACT, sparse and
+    /// multi-vector similarity
+    DistanceScan { column: String },
+    /// Rows fetched by id (`id = n`, `id IN (...)`)
+    RowIdLookup { ids: Vec<u64> },
+    /// Rows fetched from the index of a UNIQUE column
+    UniqueIndex { column: String, ids: Vec<u64> },
+    /// Numeric conditions evaluated over the columnar arrays
+    ColumnarScan,
+    /// Every live row is read
+    FullScan,
+}
+
+impl AccessPath {
+    /// Index name reported in `QueryStats::index`; None for scans
+    pub fn index_name(&self) -> Option<String> {
+        match self {
+            AccessPath::VectorIndex { .. } => Some("graph".to_string()),
+            AccessPath::RowIdLookup { .. } => Some("row id".to_string()),
+            AccessPath::UniqueIndex { column, .. } => Some(format!("unique index on {}", column)),
+            AccessPath::ColumnarScan => Some("columnar".to_string()),
+            AccessPath::DistanceScan { .. } | AccessPath::FullScan => None,
+        }
+    }
+}
+
+impl fmt::Display for AccessPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessPath::VectorIndex { column } => write!(f, "vector index on {}", column),
+            AccessPath::DistanceScan { column } => write!(f, "exact distance scan on {}", column),
+            AccessPath::RowIdLookup { ids } => write!(f, "row id lookup ({} ids)", ids.len()),
+            AccessPath::UniqueIndex { column, ids } => write!(f, "unique index on {} ({} ids)", column, ids.len()),
+            AccessPath::ColumnarScan => write!(f, "columnar scan"),
+            AccessPath::FullScan => write!(f, "full scan"),
+        }
+    }
+}
+
+/// The plan chosen for a statement, as shown by EXPLAIN
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueryPlan {
+    pub table: String,
+    pub access: AccessPath,
+    /// Conditions checked on the candidate rows, in evaluation order
+    pub filters: Vec<String>,
+    /// Estimated number of rows the statement produces or changes
+    pub estimated_rows: usize,
+    /// Work done after filtering, such as grouping, sorting and LIMIT
+    pub steps: Vec<String>,
+    /// Indexes of the WHERE conditions left to check, in evaluation order,
+    /// when they are all joined by AND; None evaluates the whole clause
+    #[serde(skip)]
+    pub(crate) order: Option<Vec<usize>>,
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {}", self.table, self.access)?;
+        for filter in &self.filters {
+            writeln!(f, "  filter: {}", filter)?;
+        }
+        for step in &self.steps {
+            writeln!(f, "  {}", step)?;
+        }
+        write!(f, "  estimated rows: {}", self.estimated_rows)
+    }
+}
+
+/// Plan how to find the rows of `table` matching `where_clause`
+pub(crate) fn plan(table: &Table, where_clause: Option<&WhereClause>) -> QueryPlan {
+    let rows = table.len();
+    let conditions = where_clause.map_or(&[][..], |wc| &wc.conditions);
+    let all_and = where_clause.is_none_or(|wc| wc.connectors.iter().all(|c| matches!(c, BoolConnector::And)));
+    let mut plan = QueryPlan {
+        table: table.name().to_string(),
+        access: AccessPath::FullScan,
+        filters: Vec::new(),
+        estimated_rows: rows,
+        steps: Vec::new(),
+        order: None,
+    };
+
+    // Similarity searches rank rows themselves; other conditions are not
+    // applied to them
+    if let Some(cond) = conditions.iter().find(|c| c.operator.is_similarity()) {
+        plan.access = match (&cond.operator, &cond.value) {
+            (ComparisonOp::Similar, ConditionValue::Single(Value::Vector(_))) => AccessPath::VectorIndex { column: cond.column.clone() },
+            _ => AccessPath::DistanceScan { column: cond.column.clone() },
+        };
+        return plan;
+    }
+
+    let selectivities: Vec<f64> = conditions.iter().map(|c| selectivity(table, c)).collect();
+    let mut remaining: Vec<usize> = (0..conditions.len()).collect();
+    let lookup = conditions.iter().enumerate()
+        .filter(|_| all_and)
+        .filter_map(|(i, cond)| Some((i, table.index_lookup(cond)?)))
+        .min_by_key(|(_, access)| match access {
+            AccessPath::RowIdLookup { ids } | AccessPath::UniqueIndex { ids, .. } => ids.len(),
+            _ => usize::MAX,
+        });
+
+    if let Some((used, access)) = lookup {
+        let (AccessPath::RowIdLookup { ids } | AccessPath::UniqueIndex { ids, .. }) = &access else {
+            unreachable!("index lookups fetch row ids");
+        };
+        remaining.retain(|&i| i != used);
+        plan.estimated_rows = estimate(ids.len(), remaining.iter().map(|&i| selectivities[i]).product());
+        plan.access = access;
+    } else if table.column_store().is_some_and(|store| store.supports(where_clause)) {
+        // The columnar filter evaluates the whole clause at once
+        plan.access = AccessPath::ColumnarScan;
+        plan.filters = describe_clause(where_clause);
+        plan.estimated_rows = estimate(rows, combined(where_clause, &selectivities));
+        plan.order = Some(Vec::new());
+        return plan;
+    } else {
+        plan.estimated_rows = estimate(rows, combined(where_clause, &selectivities));
+    }
+
+    if all_and {
+        remaining.sort_by(|&a, &b| {
+            selectivities[a].total_cmp(&selectivities[b]).then(cost(&conditions[a]).cmp(&cost(&conditions[b])))
+        });
+        plan.filters = remaining.iter().map(|&i| describe(&conditions[i])).collect();
+        plan.order = Some(remaining);
+    } else {
+        plan.filters = describe_clause(where_clause);
+    }
+    plan
+}
+
+/// Estimated fraction of rows a condition keeps
+fn selectivity(table: &Table, cond: &Condition) -> f64 {
+    let rows = table.len().max(1) as f64;
+    let unique = cond.expr.is_none() && table.column_index(&cond.column)
+        .is_some_and(|idx| Some(idx) == table.row_id_column() || table.schema.columns[idx].unique);
+    // Statistics of the column, with the row count they were taken at
+    let stats = table.stats()
+        .filter(|_| cond.expr.is_none())
+        .and_then(|stats| Some((stats.rows, stats.columns.get(&cond.column)?)));
+    let equal = match stats {
+        _ if unique => 1.0 / rows,
+        Some((analyzed, column)) => column.equal_fraction(analyzed),
+        None => 0.1,
+    };
+    let nulls = stats.map(|(analyzed, column)| column.null_fraction(analyzed));
+    let listed = |list: &[Value]| (equal * list.len() as f64).min(1.0);
+    // Fraction of rows below a value, from the column's histogram
+    let below = |value: &Value, inclusive: bool| {
+        let (analyzed, column) = stats?;
+        let value = match value {
+            Value::Integer(n) => *n as f64,
+            Value::Float(f) => *f,
+            _ => return None,
+        };
+        column.below_fraction(analyzed, value, inclusive)
+    };
+    let not_null = 1.0 - nulls.unwrap_or(0.0);
+    let between = |low: &Value, high: &Value| Some((below(high, true)? - below(low, false)?).max(0.0));
+
+    let fraction = match (&cond.operator, &cond.value) {
+        (ComparisonOp::Eq, _) => equal,
+        (ComparisonOp::Ne, _) => not_null - equal,
+        (ComparisonOp::In, ConditionValue::List(list)) => listed(list),
+        (ComparisonOp::NotIn, ConditionValue::List(list)) => not_null - listed(list),
+        (ComparisonOp::Lt, ConditionValue::Single(v)) => below(v, false).unwrap_or(1.0 / 3.0),
+        (ComparisonOp::Le, ConditionValue::Single(v)) => below(v, true).unwrap_or(1.0 / 3.0),
+        (ComparisonOp::Gt, ConditionValue::Single(v)) => below(v, true).map_or(1.0 / 3.0, |b| not_null - b),
+        (ComparisonOp::Ge, ConditionValue::Single(v)) => below(v, false).map_or(1.0 / 3.0, |b| not_null - b),
+        (ComparisonOp::Lt | ComparisonOp::Le | ComparisonOp::Gt | ComparisonOp::Ge, _) => 1.0 / 3.0,
+        (ComparisonOp::Between, ConditionValue::Range(low, high)) => between(low, high).unwrap_or(0.25),
+        (ComparisonOp::NotBetween, ConditionValue::Range(low, high)) => between(low, high).map_or(0.75, |b| not_null - b),
+        (ComparisonOp::Between, _) => 0.25,
+        (ComparisonOp::NotBetween, _) => 0.75,
+        (ComparisonOp::Like | ComparisonOp::Regexp, _) => 0.25,
+        (ComparisonOp::NotLike | ComparisonOp::NotRegexp, _) => 0.75,
+        (ComparisonOp::IsNull, _) => nulls.unwrap_or(0.1),
+        (ComparisonOp::IsNotNull, _) => 1.0 - nulls.unwrap_or(0.1),
+        _ => 1.0,
+    };
+    fraction.clamp(0.0, 1.0)
+}
+
+/// Whether a hash join should build its hash table from the left table
+/// rather than the right: the smaller side is built, the larger probed
+pub(crate) fn build_join_left(left: &Table, right: &Table) -> bool {
+    left.len() < right.len()
+}
+
+/// Relative cost of checking a condition on one row
+fn cost(cond: &Condition) -> u32 {
+    let pattern = matches!(cond.operator,
+        ComparisonOp::Like | ComparisonOp::NotLike | ComparisonOp::Regexp | ComparisonOp::NotRegexp);
+    1 + u32::from(pattern) + u32::from(cond.expr.is_some())
+}
+
+/// Fraction of rows a whole clause keeps, combining conditions left to
+/// right like `Table::matches_where`
+fn combined(where_clause: Option<&WhereClause>, selectivities: &[f64]) -> f64 {
+    let Some((first, rest)) = selectivities.split_first() else { return 1.0 };
+    let connectors = where_clause.map_or(&[][..], |wc| &wc.connectors);
+    connectors.iter().zip(rest).fold(*first, |kept, (connector, s)| match connector {
+        BoolConnector::And => kept * s,
+        BoolConnector::Or => kept + s - kept * s,
+    })
+}
+
+fn estimate(rows: usize, fraction: f64) -> usize {
+    (rows as f64 * fraction).ceil() as usize
+}
+
+/// SQL text of a whole WHERE clause, as a single filter
+fn describe_clause(where_clause: Option<&WhereClause>) -> Vec<String> {
+    let Some(wc) = where_clause.filter(|wc| !wc.conditions.is_empty()) else { return Vec::new() };
+    let mut text = describe(&wc.conditions[0]);
+    for (connector, cond) in wc.connectors.iter().zip(&wc.conditions[1..]) {
+        let connector = match connector {
+            BoolConnector::And => "AND",
+            BoolConnector::Or => "OR",
+        };
+        text.push_str(&format!(" {} {}", connector, describe(cond)));
+    }
+    vec![text]
+}
+
+/// SQL text of a condition
+fn describe(cond: &Condition) -> String {
+    let operator = match cond.operator {
+        ComparisonOp::Eq => "=",
+        ComparisonOp::Ne => "!=",
+        ComparisonOp::Lt => "<",
+        ComparisonOp::Le => "<=",
+        ComparisonOp::Gt => ">",
+        ComparisonOp::Ge => ">=",
+        ComparisonOp::Similar
+        | ComparisonOp::SimilarExact
+        | ComparisonOp::SimilarDims(_)
+        | ComparisonOp::SimilarWeighted(_)
+        | ComparisonOp::SimilarWithin(_) => "SIMILARITY",
+        ComparisonOp::Like => "LIKE",
+        ComparisonOp::NotLike => "NOT LIKE",
+        ComparisonOp::Regexp => "REGEXP",
+        ComparisonOp::NotRegexp => "NOT REGEXP",
+        ComparisonOp::In => "IN",
+        ComparisonOp::NotIn => "NOT IN",
+        ComparisonOp::Between => "BETWEEN",
+        ComparisonOp::NotBetween => "NOT BETWEEN",
+        ComparisonOp::IsNull => "IS NULL",
+        ComparisonOp::IsNotNull => "IS NOT NULL",
+    };
+    let value = match &cond.value {
+        ConditionValue::Single(v) => literal(v),
+        ConditionValue::List(list) => format!("({})", list.iter().map(literal).collect::<Vec<_>>().join(", ")),
+        ConditionValue::Range(low, high) => format!("{} AND {}", literal(low), literal(high)),
+        ConditionValue::NullCheck => return format!("{} {}", cond.column, operator),
+    };
+    format!("{} {} {}", cond.column, operator, value)
+}
+
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Vector(v) => format!("[{} dimensions]", v.len()),
+        Value::SparseVector(v) => format!("[{} sparse entries]", v.len()),
+        Value::MultiVector(v) => format!("[{} vectors]", v.len()),
+        Value::Blob(b) => format!("[{} bytes]", b.len()),
+        v => ScalarExpr::Literal(v.clone()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::GraphConfig;
+    use crate::parser::{Command, parse};
+    use crate::schema::{ColumnType, Schema};
+
+    fn table() -> Table {
+        let mut schema = Schema::new("docs")
+            .column("id", ColumnType::Integer)
+            .column("embedding", ColumnType::Vector(2))
+            .column("slug", ColumnType::Text)
+            .column("score", ColumnType::Integer);
+        schema.columns[2].unique = true;
+        let mut table = Table::new(schema, GraphConfig::default()).unwrap();
+        for i in 0..50 {
+            let values = vec![Value::Null, Value::Vector(vec![i as f32, 0.0]), Value::Text(format!("s{}", i)), Value::Integer(i % 5)];
+            table.insert_row(values).unwrap();
+        }
+        table
+    }
+
+    fn plan_for(table: &Table, condition: &str) -> QueryPlan {
+        let Command::Select { where_clause, .. } = parse(&format!("SELECT * FROM docs WHERE {};", condition)).unwrap() else {
+            panic!("expected SELECT");
+        };
+        plan(table, where_clause.as_ref())
+    }
+
+    #[test]
+    fn test_chooses_access_path() {
+        let table = table();
+        let plan = plan_for(&table, "score = 1 AND slug = 's7' AND id IN (3, 4, 99)");
+        assert_eq!(plan.access, AccessPath::UniqueIndex { column: "slug".into(), ids: vec![8] });
+        assert_eq!(plan.filters, ["id IN (3, 4, 99)", "score = 1"]);
+
+        let plan = plan_for(&table, "score = 1 AND id IN (3, 4, 99)");
+        assert_eq!(plan.access, AccessPath::RowIdLookup { ids: vec![3, 4] });
+
+        // An OR can't narrow the rows to one condition's lookup
+        let plan = plan_for(&table, "slug = 's7' OR score = 1");
+        assert_eq!(plan.access, AccessPath::FullScan);
+        assert_eq!(plan.filters, ["slug = 's7' OR score = 1"]);
+        assert_eq!(plan.order, None);
+
+        let plan = plan_for(&table, "embedding SIMILARITY [1.0, 0.0]");
+        assert_eq!(plan.access, AccessPath::VectorIndex { column: "embedding".into() });
+    }
+
+    #[test]
+    fn test_orders_filters_by_selectivity() {
+        let table = table();
+        let plan = plan_for(&table, "slug LIKE 's1%' AND score > 2 AND score = 3");
+        assert_eq!(plan.access, AccessPath::FullScan);
+        assert_eq!(plan.filters, ["score = 3", "slug LIKE 's1%'", "score > 2"]);
+        assert_eq!(plan.order, Some(vec![2, 0, 1]));
+        assert_eq!(plan.estimated_rows, 1);
+    }
+
+    #[test]
+    fn test_uses_analyzed_statistics() {
+        let mut table = table();
+        // Without statistics: 1/3 of rows for a range, 1/10 for equality
+        assert_eq!(plan_for(&table, "score < 1").estimated_rows, 17);
+        assert_eq!(plan_for(&table, "score = 1").estimated_rows, 5);
+
+        table.analyze();
+        // score takes 5 values, evenly spread over 0..=4
+        assert_eq!(plan_for(&table, "score = 1").estimated_rows, 10);
+        assert_eq!(plan_for(&table, "score > 10").estimated_rows, 0);
+        assert_eq!(plan_for(&table, "score BETWEEN 0 AND 4").estimated_rows, 50);
+        assert_eq!(plan_for(&table, "slug IS NULL").estimated_rows, 0);
+
+        // The range is now known to keep fewer rows than the equality
+        let plan = plan_for(&table, "score = 2 AND score <= 0");
+        assert_eq!(plan.filters, ["score <= 0", "score = 2"]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Conversion between query results and Polars `DataFrame`s.
+//!
+//! Vector columns are `Array(Float32, n)`, multi-vector columns
+//! `List(Array(Float32, n))`, TEXT and UUID columns `String`, INTEGER
+//! `Int64`, FLOAT `Float64`, BOOLEAN `Boolean` and BLOB `Binary`.
+//! SPARSE_VECTOR columns have no Polars equivalent and are rejected.
+
+use std::borrow::Cow;
+
+use ::polars::prelude::{
+    AnyValue, Column as PolarsColumn, DataFrame, DataType, IntoColumn, NamedFrom, PolarsError, Series,
+};
+
+use crate::database::ExecuteResult;
+use crate::distance::metric_score;
+use crate::error::{MarsError, Result};
+use crate::schema::{ColumnType, Row, Value};
+use crate::table::IngestRow;
+
+fn polars_error(e: PolarsError) -> MarsError {
+    MarsError::InvalidFormat(format!("Polars: {}", e))
+}
+
+/// Convert the rows of a `Select`, `SelectSimilar` or `Aggregate` result
+/// to a data frame. Similarity results get a trailing Float32 column
+/// named and scaled as `metric_score` reports the result's metric, e.g.
+/// `similarity` for cosine tables; an aggregate result is a single row.
+/// Computed columns without a declared type take the type of their first
+/// non-NULL value.
+pub fn to_polars(result: &ExecuteResult) -> Result<DataFrame> {
+    let (names, types, rows, scores): (Vec<&str>, Vec<Option<ColumnType>>, Vec<Cow<Row>>, _) = match result {
+        ExecuteResult::Select { rows, schema } => (
+            schema.names(),
+            schema.columns.iter().map(|c| c.data_type.clone()).collect(),
+            rows.iter().map(Cow::Borrowed).collect(),
+            None,
+        ),
+        ExecuteResult::SelectSimilar { results, schema, metric } => (
+            schema.names(),
+            schema.columns.iter().map(|c| c.data_type.clone()).collect(),
+            results.iter().map(|(row, _)| Cow::Borrowed(row)).collect(),
+            Some((
+                metric_score(metric, 0.0).0,
+                results.iter().map(|(_, d)| metric_score(metric, *d).1).collect::<Vec<f32>>(),
+            )),
+        ),
+        ExecuteResult::Aggregate { results } => (
+            results.iter().map(|(name, _)| name.as_str()).collect(),
+            vec![None; results.len()],
+            vec![Cow::Owned(Row::new(0, results.iter().map(|(_, value)| value.clone()).collect()))],
+            None,
+        ),
+        other => return Err(MarsError::InvalidFormat(format!("{:?} has no rows to convert", other))),
+    };
+
+    let mut columns: Vec<PolarsColumn> = Vec::with_capacity(names.len() + 1);
+    for (idx, (name, data_type)) in names.iter().zip(types).enumerate() {
+        let values: Vec<&Value> = rows.iter().map(|row| row.values.get(idx).unwrap_or(&Value::Null)).collect();
+        let data_type = data_type.or_else(|| values.iter().find_map(|v| value_type(v)));
+        columns.push(column_series(name, data_type.as_ref(), &values)?.into_column());
+    }
+    if let Some((name, scores)) = scores {
+        columns.push(Series::new(name.into(), scores).into_column());
+    }
+    DataFrame::new(columns).map_err(polars_error)
+}
+
+/// Column type a computed value is exported as
+fn value_type(value: &Value) -> Option<ColumnType> {
+    match value {
+        Value::Null => None,
+        Value::Vector(v) => Some(ColumnType::Vector(v.len())),
+        Value::Text(_) => Some(ColumnType::Text),
+        Value::Integer(_) => Some(ColumnType::Integer),
+        Value::Float(_) => Some(ColumnType::Float),
+        Value::Boolean(_) => Some(ColumnType::Boolean),
+        Value::Blob(_) => Some(ColumnType::Blob),
+        Value::SparseVector(_) => Some(ColumnType::SparseVector),
+        Value::MultiVector(v) => Some(ColumnType::MultiVector(v.first().map_or(0, Vec::len))),
+        Value::Uuid(_) => Some(ColumnType::Uuid),
+    }
+}
+
+fn column_series(name: &str, data_type: Option<&ColumnType>, values: &[&Value]) -> Result<Series> {
+    let mismatch = |value: &Value| {
+        MarsError::InvalidFormat(format!("Cannot export {:?} in column '{}' as {:?}", value, name, data_type))
+    };
+    macro_rules! collect {
+        ($($pattern:pat => $convert:expr),+) => {
+            values.iter()
+                .map(|value| match value {
+                    Value::Null => Ok(None),
+                    $($pattern => Ok(Some($convert)),)+
+                    other => Err(mismatch(other)),
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+    }
+    let vector_series = |v: &Vec<f32>| Series::new("".into(), v);
+
+    let series = match data_type {
+        None => Series::full_null(name.into(), values.len(), &DataType::Null),
+        Some(ColumnType::Text | ColumnType::Uuid) => Series::new(name.into(), collect!(
+            Value::Text(s) => Cow::Borrowed(s.as_str()),
+            Value::Uuid(u) => Cow::Owned(u.to_string())
+        )),
+        Some(ColumnType::Integer) => Series::new(name.into(), collect!(Value::Integer(i) => *i)),
+        Some(ColumnType::Float) => Series::new(name.into(), collect!(
+            Value::Float(f) => *f,
+            Value::Integer(i) => *i as f64
+        )),
+        Some(ColumnType::Boolean) => Series::new(name.into(), collect!(Value::Boolean(b) => *b)),
+        Some(ColumnType::Blob) => Series::new(name.into(), collect!(Value::Blob(b) => b.as_slice())),
+        Some(ColumnType::Vector(dim)) => {
+            let list = Series::new(name.into(), collect!(Value::Vector(v) => vector_series(v)));
+            list.cast(&DataType::Array(Box::new(DataType::Float32), *dim)).map_err(polars_error)?
+        }
+        Some(ColumnType::MultiVector(dim)) => {
+            let list = Series::new(name.into(), collect!(Value::MultiVector(vectors) => {
+                Series::new("".into(), vectors.iter().map(vector_series).collect::<Vec<_>>())
+            }));
+            let vector = DataType::Array(Box::new(DataType::Float32), *dim);
+            list.cast(&DataType::List(Box::new(vector))).map_err(polars_error)?
+        }
+        Some(ColumnType::SparseVector) => {
+            return Err(MarsError::InvalidFormat(format!(
+                "SPARSE_VECTOR column '{}' cannot be converted to Polars", name
+            )));
+        }
+    };
+    Ok(series)
+}
+
+/// Read a data frame as rows for `Database::ingest`. `vector_col` must be
+/// an `Array` or `List` of floats without NULLs; every other column
+/// becomes metadata of the same name, with NULLs left out. Columns the
+/// table lacks are ignored by `ingest`.
+///
+/// ```ignore
+/// let rows = pardusdb::polars::from_polars(&df, "embedding")?;
+/// db.ingest("docs", rows, IngestOptions::default())?;
+/// ```
+pub fn from_polars<'d>(df: &'d DataFrame, vector_col: &str) -> Result<Vec<IngestRow<'d>>> {
+    let vectors = df.column(vector_col)
+        .map_err(|_| MarsError::ColumnNotFound(vector_col.to_string()))?;
+    if !matches!(vectors.dtype(), DataType::Array(item, _) | DataType::List(item) if item.is_float()) {
+        return Err(MarsError::InvalidFormat(format!(
+            "Vector column '{}' is {}, expected an Array or List of floats", vector_col, vectors.dtype()
+        )));
+    }
+    let vectors = vectors.cast(&DataType::List(Box::new(DataType::Float32))).map_err(polars_error)?;
+    let vectors = vectors.list().map_err(polars_error)?;
+
+    let mut rows = Vec::with_capacity(df.height());
+    for (row, vector) in vectors.into_iter().enumerate() {
+        let vector = vector
+            .and_then(|v| v.f32().ok().and_then(|v| v.into_iter().collect::<Option<Vec<f32>>>()))
+            .ok_or_else(|| MarsError::ConstraintViolation(format!(
+                "NULL vector in column '{}' at row {}", vector_col, row
+            )))?;
+
+        let mut metadata = Vec::with_capacity(df.width() - 1);
+        for column in df.get_columns() {
+            if column.name().as_str() == vector_col {
+                continue;
+            }
+            let value = polars_value(column.get(row).map_err(polars_error)?, column.name())?;
+            if value != Value::Null {
+                metadata.push((column.name().as_str(), value));
+            }
+        }
+        rows.push((vector, metadata));
+    }
+    Ok(rows)
+}
+
+fn float_vector(series: &Series) -> Result<Vec<f32>> {
+    let series = series.cast(&DataType::Float32).map_err(polars_error)?;
+    Ok(series.f32().map_err(polars_error)?.into_no_null_iter().collect())
+}
+
+fn polars_value(value: AnyValue<'_>, column: &str) -> Result<Value> {
+    let value = match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(b) => Value::Boolean(b),
+        AnyValue::String(s) => Value::Text(s.to_string()),
+        AnyValue::StringOwned(s) => Value::Text(s.to_string()),
+        AnyValue::Int8(i) => Value::Integer(i as i64),
+        AnyValue::Int16(i) => Value::Integer(i as i64),
+        AnyValue::Int32(i) => Value::Integer(i as i64),
+        AnyValue::Int64(i) => Value::Integer(i),
+        AnyValue::UInt8(i) => Value::Integer(i as i64),
+        AnyValue::UInt16(i) => Value::Integer(i as i64),
+        AnyValue::UInt32(i) => Value::Integer(i as i64),
+        AnyValue::Float32(f) => Value::Float(f as f64),
+        AnyValue::Float64(f) => Value::Float(f),
+        AnyValue::Binary(b) => Value::Blob(b.to_vec()),
+        AnyValue::BinaryOwned(b) => Value::Blob(b),
+        AnyValue::Array(series, _) => Value::Vector(float_vector(&series)?),
+        AnyValue::List(series) => match series.dtype() {
+            DataType::Array(..) => {
+                let vectors = series.array().map_err(polars_error)?;
+                Value::MultiVector(vectors.into_iter().flatten().map(|v| float_vector(&v)).collect::<Result<_>>()?)
+            }
+            dtype if dtype.is_float() => Value::Vector(float_vector(&series)?),
+            dtype => return Err(MarsError::InvalidFormat(format!(
+                "Cannot import Polars type List({}) of column '{}'", dtype, column
+            ))),
+        },
+        other => return Err(MarsError::InvalidFormat(format!(
+            "Cannot import Polars type {} of column '{}'", other.dtype(), column
+        ))),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, IngestOptions};
+
+    #[test]
+    fn test_round_trip() {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, views INTEGER);").unwrap();
+        db.execute("INSERT INTO docs (embedding, title, views) VALUES ([1.0, 0.0], 'a', 3);").unwrap();
+        db.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'b');").unwrap();
+
+        let df = to_polars(&db.execute("SELECT * FROM docs;").unwrap()).unwrap();
+        assert_eq!(df.shape(), (2, 3));
+        assert_eq!(df.column("embedding").unwrap().dtype(), &DataType::Array(Box::new(DataType::Float32), 2));
+        assert_eq!(df.column("views").unwrap().null_count(), 1);
+
+        let mut copy = Database::in_memory();
+        copy.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, views INTEGER);").unwrap();
+        copy.ingest("docs", from_polars(&df, "embedding").unwrap(), IngestOptions::default()).unwrap();
+        let result = copy.execute("SELECT * FROM docs ORDER BY title;").unwrap();
+        let rows = result.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get::<Vec<f32>>("embedding").unwrap(), vec![1.0, 0.0]);
+        assert_eq!(rows[0].get::<String>("title").unwrap(), "a");
+        assert_eq!(rows[1].get::<Option<i64>>("views").unwrap(), None);
+    }
+
+    #[test]
+    fn test_similarity_score_column() {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT) METRIC cosine;").unwrap();
+        db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+        let result = db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] LIMIT 1;").unwrap();
+        let df = to_polars(&result).unwrap();
+        assert_eq!(df.get_column_names().last().unwrap().as_str(), "similarity");
+    }
+
+    #[test]
+    fn test_vector_column_must_hold_floats() {
+        let df = DataFrame::new(vec![Series::new("embedding".into(), vec!["x"]).into_column()]).unwrap();
+        assert!(from_polars(&df, "embedding").is_err());
+        assert!(matches!(from_polars(&df, "missing"), Err(MarsError::ColumnNotFound(_))));
+    }
+}
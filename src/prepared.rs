@@ -2,12 +2,17 @@
 //!
 //! This module provides a way to cache parsed SQL statements and bind
 //! parameters at execution time, avoiding repeated parsing overhead.
+//!
+//! A `?` stands for a value, an element of a vector literal (`[?, 0.5]`),
+//! a whole vector (`SIMILARITY ?`), or the count of `LIMIT ?` / `OFFSET ?`.
+//! Parameters are numbered in the order they appear.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::error::{MarsError, Result};
+use crate::functions::ScalarExpr;
 use crate::schema::Value;
-use crate::parser::{parse, Command, WhereClause, Condition, ComparisonOp, OrderBy, SelectColumn, ConditionValue, BoolConnector};
+use crate::parser::{parse_with_limits, Command, ParseLimits, WhereClause, Condition, ComparisonOp, Diversity, OnConflict, OrderBy, SelectColumn, ConditionValue, BoolConnector};
 
 /// A prepared statement template that can be reused with different parameters
 #[derive(Clone, Debug)]
@@ -18,6 +23,8 @@ pub struct PreparedStatement {
     command: CommandTemplate,
     /// Number of parameters expected
     param_count: usize,
+    /// What each placeholder literal left in scalar expressions stands for
+    slots: Vec<ValueTemplate>,
 }
 
 /// Template representation of a command with parameter placeholders
@@ -26,26 +33,36 @@ pub enum CommandTemplate {
     Insert {
         table: String,
         columns: Vec<String>,
-        value_templates: Vec<ValueTemplate>,
+        rows: Vec<Vec<ValueTemplate>>,
+        on_conflict: OnConflict,
     },
     Select {
         table: String,
         columns: Vec<SelectColumn>,
         where_template: Option<WhereClauseTemplate>,
+        group_by: Option<Vec<String>>,
+        having: Option<WhereClauseTemplate>,
         order_by: Option<OrderBy>,
-        limit: Option<usize>,
-        offset: Option<usize>,
+        limit: Option<CountTemplate>,
+        offset: Option<CountTemplate>,
         distinct: bool,
+        as_of: Option<i64>,
+        // Boxed to keep templates of other commands small
+        diversity: Option<Box<Diversity>>,
+        score: Option<Box<ScalarExpr>>,
     },
     Update {
         table: String,
-        assignment_templates: Vec<(String, ValueTemplate)>,
+        assignments: Vec<(String, ScalarExpr)>,
         where_template: Option<WhereClauseTemplate>,
     },
     Delete {
         table: String,
         where_template: Option<WhereClauseTemplate>,
+        soft: bool,
     },
+    /// Any other statement; these take no parameters
+    Other(Box<Command>),
 }
 
 /// Template for values that may contain parameters
@@ -53,12 +70,22 @@ pub enum CommandTemplate {
 pub enum ValueTemplate {
     Fixed(Value),
     Param(usize), // Index of the parameter
+    /// Vector literal with some `?` elements
+    Vector(Vec<ValueTemplate>),
+}
+
+/// Template for the count of LIMIT or OFFSET
+#[derive(Clone, Copy, Debug)]
+pub enum CountTemplate {
+    Fixed(usize),
+    Param(usize),
 }
 
 /// Template for WHERE clause
 #[derive(Clone, Debug)]
 pub struct WhereClauseTemplate {
     pub conditions: Vec<ConditionTemplate>,
+    pub connectors: Vec<BoolConnector>,
 }
 
 /// Template for conditions
@@ -66,37 +93,117 @@ pub struct WhereClauseTemplate {
 pub struct ConditionTemplate {
     pub column: String,
     pub operator: ComparisonOp,
-    pub value_template: ValueTemplate,
+    pub value_template: ConditionValueTemplate,
+    pub expr: Option<ScalarExpr>,
+}
+
+/// Template for the right-hand side of a condition
+#[derive(Clone, Debug)]
+pub enum ConditionValueTemplate {
+    Single(ValueTemplate),
+    List(Vec<ValueTemplate>),
+    Range(ValueTemplate, ValueTemplate),
+    NullCheck,
 }
 
-/// A statement cache for reusing prepared statements
+/// Hit and miss counts of a `StatementCache`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatementCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Statements currently cached
+    pub entries: usize,
+}
+
+/// A statement cache for reusing prepared statements, keyed by SQL text.
+/// When full, the least recently used statement is dropped.
 pub struct StatementCache {
-    statements: HashMap<String, PreparedStatement>,
+    capacity: usize,
+    /// Statement and the tick it was last used at
+    statements: HashMap<String, (PreparedStatement, u64)>,
+    /// Last-use tick -> SQL, oldest first
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
 }
 
 impl StatementCache {
-    /// Create a new empty cache
+    /// Create a new empty cache without a size limit
     pub fn new() -> Self {
+        Self::with_capacity(usize::MAX)
+    }
+
+    /// Create a new empty cache holding at most `capacity` statements
+    pub fn with_capacity(capacity: usize) -> Self {
         StatementCache {
+            capacity: capacity.max(1),
             statements: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     /// Prepare a statement, caching it for reuse
     pub fn prepare(&mut self, sql: &str) -> Result<&PreparedStatement> {
-        if !self.statements.contains_key(sql) {
-            let stmt = PreparedStatement::new(sql)?;
-            self.statements.insert(sql.to_string(), stmt);
+        self.prepare_with_limits(sql, &ParseLimits::default())
+    }
+
+    /// `prepare`, rejecting SQL beyond `limits`
+    pub fn prepare_with_limits(&mut self, sql: &str, limits: &ParseLimits) -> Result<&PreparedStatement> {
+        self.tick += 1;
+        if let Some((_, used)) = self.statements.get_mut(sql) {
+            self.hits += 1;
+            self.order.remove(used);
+            *used = self.tick;
+            self.order.insert(self.tick, sql.to_string());
+        } else {
+            self.misses += 1;
+            let stmt = PreparedStatement::with_limits(sql, limits)?;
+            if self.statements.len() >= self.capacity
+                && let Some((_, oldest)) = self.order.pop_first()
+            {
+                self.statements.remove(&oldest);
+            }
+            self.order.insert(self.tick, sql.to_string());
+            self.statements.insert(sql.to_string(), (stmt, self.tick));
         }
-        Ok(self.statements.get(sql).unwrap())
+        Ok(&self.statements[sql].0)
     }
 
     /// Execute a prepared statement with parameters
     pub fn execute(&self, sql: &str, params: &[Value]) -> Result<Command> {
-        let stmt = self.statements.get(sql)
+        let (stmt, _) = self.statements.get(sql)
             .ok_or_else(|| MarsError::InvalidFormat("Statement not prepared".into()))?;
         stmt.bind(params)
     }
+
+    /// The command `sql` parses to, prepared once and reused after that.
+    /// SQL with `?` placeholders is parsed as is, so it fails as it would
+    /// without the cache.
+    pub(crate) fn command(&mut self, sql: &str, limits: &ParseLimits) -> Result<Command> {
+        let stmt = self.prepare_with_limits(sql, limits)?;
+        if stmt.param_count > 0 {
+            return parse_with_limits(sql, limits);
+        }
+        stmt.bind(&[])
+    }
+
+    pub fn stats(&self) -> StatementCacheStats {
+        StatementCacheStats { hits: self.hits, misses: self.misses, entries: self.statements.len() }
+    }
+
+    /// Drop every statement; the hit and miss counts are kept
+    pub fn clear(&mut self) {
+        self.statements.clear();
+        self.order.clear();
+    }
 }
 
 impl Default for StatementCache {
@@ -105,24 +212,68 @@ impl Default for StatementCache {
     }
 }
 
+/// Where a `?` was found in the SQL text
+enum Placeholder {
+    /// Stands for a value; replaced by the text literal of `slot`
+    Slot(ValueTemplate),
+    Limit(usize),
+    Offset(usize),
+}
+
 impl PreparedStatement {
     /// Create a new prepared statement from SQL
     pub fn new(sql: &str) -> Result<Self> {
-        // Parse with parameter placeholder support
-        let (template, param_count) = Self::parse_template(sql)?;
+        Self::with_limits(sql, &ParseLimits::default())
+    }
 
-        // Parse the base command structure
-        let base_sql = Self::replace_params_with_defaults(sql);
-        let command = parse(&base_sql)?;
-        let command_template = Self::convert_command(command, &template);
+    /// `new`, rejecting SQL beyond `limits`
+    pub fn with_limits(sql: &str, limits: &ParseLimits) -> Result<Self> {
+        // Swap placeholders for literals the parser accepts
+        let (base_sql, placeholders, param_count) = Self::parse_template(sql)?;
+        let command = parse_with_limits(&base_sql, limits)?;
+
+        let mut slots = Vec::new();
+        let (mut limit, mut offset) = (None, None);
+        for placeholder in placeholders {
+            match placeholder {
+                Placeholder::Slot(template) => slots.push(template),
+                Placeholder::Limit(idx) => limit = Some(CountTemplate::Param(idx)),
+                Placeholder::Offset(idx) => offset = Some(CountTemplate::Param(idx)),
+            }
+        }
+        let mut command_template = Self::convert_command(command, &slots);
+        if param_count > 0 && matches!(command_template, CommandTemplate::Other(_)) {
+            return Err(MarsError::InvalidFormat(
+                "Parameters are only supported in INSERT, SELECT, UPDATE and DELETE".into()
+            ));
+        }
+        if let CommandTemplate::Select { limit: l, offset: o, .. } = &mut command_template {
+            if limit.is_some() {
+                *l = limit;
+            }
+            if offset.is_some() {
+                *o = offset;
+            }
+        }
 
         Ok(PreparedStatement {
             template: sql.to_string(),
             command: command_template,
             param_count,
+            slots,
         })
     }
 
+    /// The SQL the statement was prepared from
+    pub fn sql(&self) -> &str {
+        &self.template
+    }
+
+    /// Number of parameters `bind` expects
+    pub fn param_count(&self) -> usize {
+        self.param_count
+    }
+
     /// Bind parameters to create an executable command
     pub fn bind(&self, params: &[Value]) -> Result<Command> {
         if params.len() != self.param_count {
@@ -133,216 +284,217 @@ impl PreparedStatement {
         }
 
         match &self.command {
-            CommandTemplate::Insert { table, columns, value_templates } => {
-                let values = value_templates.iter()
-                    .map(|vt| Self::resolve_value(vt, params))
+            CommandTemplate::Insert { table, columns, rows, on_conflict } => {
+                let values = rows.iter()
+                    .map(|row| row.iter().map(|vt| Self::resolve_value(vt, params)).collect())
                     .collect::<Result<Vec<_>>>()?;
                 Ok(Command::Insert {
                     table: table.clone(),
                     columns: columns.clone(),
-                    values: vec![values],  // Single row insert
+                    values,
+                    on_conflict: *on_conflict,
                 })
             }
-            CommandTemplate::Select { table, columns, where_template, order_by, limit, offset, distinct } => {
+            CommandTemplate::Select { table, columns, where_template, group_by, having, order_by, limit, offset, distinct, as_of, diversity, score } => {
                 let where_clause = where_template.as_ref()
-                    .map(|wt| Self::resolve_where(wt, params))
+                    .map(|wt| self.resolve_where(wt, params))
+                    .transpose()?;
+                let having = having.as_ref()
+                    .map(|wt| self.resolve_where(wt, params))
                     .transpose()?;
                 Ok(Command::Select {
                     table: table.clone(),
                     columns: columns.clone(),
                     where_clause,
-                    group_by: None, // Not supported in prepared statements yet
-                    having: None,   // Not supported in prepared statements yet
+                    group_by: group_by.clone(),
+                    having,
                     order_by: order_by.clone(),
-                    limit: *limit,
-                    offset: *offset,
+                    limit: limit.map(|ct| Self::resolve_count(ct, params, "LIMIT")).transpose()?,
+                    offset: offset.map(|ct| Self::resolve_count(ct, params, "OFFSET")).transpose()?,
                     distinct: *distinct,
+                    as_of: *as_of,
+                    diversity: diversity.as_deref().cloned(),
+                    score: score.as_deref().cloned(),
                 })
             }
-            CommandTemplate::Update { table, assignment_templates, where_template } => {
-                let assignments = assignment_templates.iter()
-                    .map(|(col, vt)| Ok((col.clone(), Self::resolve_value(vt, params)?)))
-                    .collect::<Result<Vec<_>>>()?;
+            CommandTemplate::Update { table, assignments, where_template } => {
                 let where_clause = where_template.as_ref()
-                    .map(|wt| Self::resolve_where(wt, params))
+                    .map(|wt| self.resolve_where(wt, params))
                     .transpose()?;
+                let assignments = assignments.iter()
+                    .map(|(column, expr)| Ok((column.clone(), self.resolve_expr(expr, params)?)))
+                    .collect::<Result<Vec<_>>>()?;
                 Ok(Command::Update {
                     table: table.clone(),
                     assignments,
                     where_clause,
                 })
             }
-            CommandTemplate::Delete { table, where_template } => {
+            CommandTemplate::Delete { table, where_template, soft } => {
                 let where_clause = where_template.as_ref()
-                    .map(|wt| Self::resolve_where(wt, params))
+                    .map(|wt| self.resolve_where(wt, params))
                     .transpose()?;
                 Ok(Command::Delete {
                     table: table.clone(),
                     where_clause,
+                    soft: *soft,
                 })
             }
+            CommandTemplate::Other(command) => Ok((**command).clone()),
         }
     }
 
-    /// Parse SQL to find parameter placeholders
-    fn parse_template(sql: &str) -> Result<(Vec<ValueTemplate>, usize)> {
-        let mut templates = Vec::new();
-        let mut param_count = 0;
-        let mut chars = sql.chars().peekable();
-
-        while let Some(ch) = chars.next() {
-            if ch == '\'' {
-                // String literal - skip until closing quote
-                while let Some(c) = chars.next() {
-                    if c == '\'' { break; }
-                }
-            } else if ch == '[' {
-                // Vector literal - parse values
-                let mut vec_temps = Vec::new();
-                loop {
-                    // Skip whitespace
-                    while let Some(&c) = chars.peek() {
-                        if c.is_whitespace() { chars.next(); } else { break; }
-                    }
-                    if chars.peek() == Some(&']') {
-                        chars.next();
-                        break;
-                    }
+    /// Text literal standing in for placeholder `slot` while parsing
+    fn slot_literal(slot: usize) -> String {
+        format!("'\u{0}{}'", slot)
+    }
 
-                    // Check for parameter
-                    if chars.peek() == Some(&'?') {
-                        chars.next();
-                        vec_temps.push(ValueTemplate::Param(param_count));
-                        param_count += 1;
-                    } else {
-                        // Fixed number - just mark as needing parsing
-                        vec_temps.push(ValueTemplate::Fixed(Value::Null));
-                        // Skip number
-                        while let Some(&c) = chars.peek() {
-                            if c.is_ascii_digit() || c == '.' || c == '-' {
-                                chars.next();
-                            } else {
-                                break;
-                            }
-                        }
-                    }
+    /// The placeholder slot a parsed value stands for, if any
+    fn slot_of(value: &Value) -> Option<usize> {
+        match value {
+            Value::Text(s) => s.strip_prefix('\u{0}')?.parse().ok(),
+            _ => None,
+        }
+    }
 
-                    // Skip comma or whitespace
-                    while let Some(&c) = chars.peek() {
-                        if c == ',' || c.is_whitespace() {
-                            chars.next();
-                        } else {
-                            break;
+    /// Find the `?` placeholders outside string literals. Returns the SQL
+    /// with each replaced by something the parser accepts, what each one
+    /// was, and the number of parameters.
+    fn parse_template(sql: &str) -> Result<(String, Vec<Placeholder>, usize)> {
+        let mut base = String::with_capacity(sql.len());
+        let mut placeholders = Vec::new();
+        let mut param_count = 0;
+        let mut slot_count = 0;
+        let mut chars = sql.char_indices().peekable();
+
+        while let Some((i, ch)) = chars.next() {
+            if ch == '\'' || ch == '"' {
+                // String literal - copy until the closing quote
+                base.push(ch);
+                while let Some((_, c)) = chars.next() {
+                    base.push(c);
+                    if c == '\\' {
+                        if let Some((_, escaped)) = chars.next() {
+                            base.push(escaped);
                         }
+                    } else if c == ch {
+                        break;
                     }
                 }
-                // For now, just track that this had a param
-                for vt in vec_temps {
-                    if matches!(vt, ValueTemplate::Param(_)) {
-                        templates.push(vt);
+            } else if ch == '[' {
+                // Vector literal - template it if any element is a parameter
+                let end = sql[i..].find(']').map(|e| i + e);
+                let body = end.map(|e| &sql[i + 1..e]).filter(|b| b.contains('?') && !b.contains('['));
+                let (Some(end), Some(body)) = (end, body) else {
+                    base.push(ch);
+                    continue;
+                };
+                let mut elements = Vec::new();
+                for item in body.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                    if item == "?" {
+                        elements.push(ValueTemplate::Param(param_count));
+                        param_count += 1;
+                    } else {
+                        let n: f32 = item.parse().map_err(|_| {
+                            MarsError::InvalidFormat(format!("Invalid vector element '{}'", item))
+                        })?;
+                        elements.push(ValueTemplate::Fixed(Value::Float(n as f64)));
                     }
                 }
+                placeholders.push(Placeholder::Slot(ValueTemplate::Vector(elements)));
+                base.push_str(&Self::slot_literal(slot_count));
+                slot_count += 1;
+                while chars.next_if(|&(j, _)| j <= end).is_some() {}
             } else if ch == '?' {
-                templates.push(ValueTemplate::Param(param_count));
-                param_count += 1;
-            }
-        }
-
-        Ok((templates, param_count))
-    }
-
-    /// Replace ? with default values for parsing
-    fn replace_params_with_defaults(sql: &str) -> String {
-        let mut result = String::new();
-        let mut chars = sql.chars().peekable();
-        let mut in_string = false;
-        let mut in_vector = false;
-
-        while let Some(ch) = chars.next() {
-            if ch == '\'' && !in_vector {
-                in_string = !in_string;
-                result.push(ch);
-            } else if ch == '[' && !in_string {
-                in_vector = true;
-                result.push(ch);
-            } else if ch == ']' && !in_string {
-                in_vector = false;
-                result.push(ch);
-            } else if ch == '?' && !in_string {
-                if in_vector {
-                    result.push_str("0.0"); // Default float for vectors
+                let keyword = base.trim_end().rsplit(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap_or("");
+                if keyword.eq_ignore_ascii_case("LIMIT") {
+                    placeholders.push(Placeholder::Limit(param_count));
+                    base.push('0');
+                } else if keyword.eq_ignore_ascii_case("OFFSET") {
+                    placeholders.push(Placeholder::Offset(param_count));
+                    base.push('0');
                 } else {
-                    result.push_str("0"); // Default integer
+                    placeholders.push(Placeholder::Slot(ValueTemplate::Param(param_count)));
+                    base.push_str(&Self::slot_literal(slot_count));
+                    slot_count += 1;
                 }
+                param_count += 1;
             } else {
-                result.push(ch);
+                base.push(ch);
             }
         }
-        result
+
+        Ok((base, placeholders, param_count))
     }
 
     /// Convert a parsed command to a template
-    fn convert_command(command: Command, _templates: &[ValueTemplate]) -> CommandTemplate {
+    fn convert_command(command: Command, slots: &[ValueTemplate]) -> CommandTemplate {
+        let value = |v: Value| Self::convert_value(v, slots);
         match command {
-            Command::Insert { table, columns, values } => {
-                // For single-row insert, take first row
-                let first_row = values.into_iter().next().unwrap_or_default();
-                let value_templates = first_row.into_iter()
-                    .map(|v| ValueTemplate::Fixed(v))
+            Command::Insert { table, columns, values, on_conflict } => {
+                let rows = values.into_iter()
+                    .map(|row| row.into_iter().map(value).collect())
                     .collect();
-                CommandTemplate::Insert { table, columns, value_templates }
+                CommandTemplate::Insert { table, columns, rows, on_conflict }
             }
-            Command::Select { table, columns, where_clause, group_by, having, order_by, limit, offset, distinct } => {
-                // GROUP BY not yet supported in prepared statements
-                let _ = group_by;
-                let _ = having;
+            Command::Select { table, columns, where_clause, group_by, having, order_by, limit, offset, distinct, as_of, diversity, score } => {
                 CommandTemplate::Select {
                     table,
                     columns,
-                    where_template: where_clause.map(|wc| Self::convert_where(wc)),
+                    where_template: where_clause.map(|wc| Self::convert_where(wc, slots)),
+                    group_by,
+                    having: having.map(|wc| Self::convert_where(wc, slots)),
                     order_by,
-                    limit,
-                    offset,
+                    limit: limit.map(CountTemplate::Fixed),
+                    offset: offset.map(CountTemplate::Fixed),
                     distinct,
+                    as_of,
+                    diversity: diversity.map(Box::new),
+                    score: score.map(Box::new),
                 }
             }
             Command::Update { table, assignments, where_clause } => {
                 CommandTemplate::Update {
                     table,
-                    assignment_templates: assignments.into_iter()
-                        .map(|(col, val)| (col, ValueTemplate::Fixed(val)))
-                        .collect(),
-                    where_template: where_clause.map(|wc| Self::convert_where(wc)),
+                    assignments,
+                    where_template: where_clause.map(|wc| Self::convert_where(wc, slots)),
                 }
             }
-            Command::Delete { table, where_clause } => {
+            Command::Delete { table, where_clause, soft } => {
                 CommandTemplate::Delete {
                     table,
-                    where_template: where_clause.map(|wc| Self::convert_where(wc)),
+                    where_template: where_clause.map(|wc| Self::convert_where(wc, slots)),
+                    soft,
                 }
             }
-            _ => panic!("Unsupported command type for prepared statements"),
+            other => CommandTemplate::Other(Box::new(other)),
+        }
+    }
+
+    fn convert_value(value: Value, slots: &[ValueTemplate]) -> ValueTemplate {
+        match Self::slot_of(&value) {
+            Some(slot) => slots[slot].clone(),
+            None => ValueTemplate::Fixed(value),
         }
     }
 
-    fn convert_where(wc: WhereClause) -> WhereClauseTemplate {
+    fn convert_where(wc: WhereClause, slots: &[ValueTemplate]) -> WhereClauseTemplate {
+        let value = |v: Value| Self::convert_value(v, slots);
         WhereClauseTemplate {
             conditions: wc.conditions.into_iter()
-                .map(|c| {
-                    // Convert ConditionValue to simple value for template
-                    let value = match c.value {
-                        ConditionValue::Single(v) => v,
-                        ConditionValue::List(mut v) => v.pop().unwrap_or(Value::Null),
-                        ConditionValue::Range(low, _) => low,
-                        ConditionValue::NullCheck => Value::Null,
-                    };
-                    ConditionTemplate {
-                        column: c.column,
-                        operator: c.operator,
-                        value_template: ValueTemplate::Fixed(value),
-                    }
+                .map(|c| ConditionTemplate {
+                    column: c.column,
+                    operator: c.operator,
+                    value_template: match c.value {
+                        ConditionValue::Single(v) => ConditionValueTemplate::Single(value(v)),
+                        ConditionValue::List(list) => ConditionValueTemplate::List(list.into_iter().map(value).collect()),
+                        ConditionValue::Range(low, high) => ConditionValueTemplate::Range(value(low), value(high)),
+                        ConditionValue::NullCheck => ConditionValueTemplate::NullCheck,
+                    },
+                    expr: c.expr,
                 })
                 .collect(),
+            connectors: wc.connectors,
         }
     }
 
@@ -353,19 +505,70 @@ impl PreparedStatement {
                 params.get(*idx).cloned()
                     .ok_or_else(|| MarsError::InvalidFormat(format!("Missing parameter {}", idx)))
             }
+            ValueTemplate::Vector(elements) => {
+                let vector = elements.iter()
+                    .map(|vt| match Self::resolve_value(vt, params)? {
+                        Value::Float(f) => Ok(f as f32),
+                        Value::Integer(n) => Ok(n as f32),
+                        other => Err(MarsError::InvalidFormat(format!("Vector element must be a number, got {:?}", other))),
+                    })
+                    .collect::<Result<Vec<f32>>>()?;
+                Ok(Value::Vector(vector))
+            }
+        }
+    }
+
+    /// Resolve the count of LIMIT or OFFSET
+    fn resolve_count(template: CountTemplate, params: &[Value], clause: &str) -> Result<usize> {
+        let idx = match template {
+            CountTemplate::Fixed(n) => return Ok(n),
+            CountTemplate::Param(idx) => idx,
+        };
+        match Self::resolve_value(&ValueTemplate::Param(idx), params)? {
+            Value::Integer(n) if n >= 0 => Ok(n as usize),
+            other => Err(MarsError::InvalidFormat(format!(
+                "{} must be a non-negative integer, got {:?}", clause, other
+            ))),
         }
     }
 
-    fn resolve_where(template: &WhereClauseTemplate, params: &[Value]) -> Result<WhereClause> {
+    /// Replace placeholder literals in an expression with their parameters
+    fn resolve_expr(&self, expr: &ScalarExpr, params: &[Value]) -> Result<ScalarExpr> {
+        Ok(match expr {
+            ScalarExpr::Literal(v) => match Self::slot_of(v) {
+                Some(slot) => ScalarExpr::Literal(Self::resolve_value(&self.slots[slot], params)?),
+                None => expr.clone(),
+            },
+            ScalarExpr::Column(_) => expr.clone(),
+            ScalarExpr::Call { func, args } => ScalarExpr::Call {
+                func: *func,
+                args: args.iter().map(|arg| self.resolve_expr(arg, params)).collect::<Result<_>>()?,
+            },
+            ScalarExpr::Binary { op, left, right } => ScalarExpr::Binary {
+                op: *op,
+                left: Box::new(self.resolve_expr(left, params)?),
+                right: Box::new(self.resolve_expr(right, params)?),
+            },
+        })
+    }
+
+    fn resolve_where(&self, template: &WhereClauseTemplate, params: &[Value]) -> Result<WhereClause> {
+        let value = |vt: &ValueTemplate| Self::resolve_value(vt, params);
         Ok(WhereClause {
             conditions: template.conditions.iter()
                 .map(|c| Ok(Condition {
                     column: c.column.clone(),
                     operator: c.operator.clone(),
-                    value: ConditionValue::Single(Self::resolve_value(&c.value_template, params)?),
+                    value: match &c.value_template {
+                        ConditionValueTemplate::Single(vt) => ConditionValue::Single(value(vt)?),
+                        ConditionValueTemplate::List(list) => ConditionValue::List(list.iter().map(value).collect::<Result<_>>()?),
+                        ConditionValueTemplate::Range(low, high) => ConditionValue::Range(value(low)?, value(high)?),
+                        ConditionValueTemplate::NullCheck => ConditionValue::NullCheck,
+                    },
+                    expr: c.expr.as_ref().map(|e| self.resolve_expr(e, params)).transpose()?,
                 }))
                 .collect::<Result<Vec<_>>>()?,
-            connectors: Vec::new(),  // Simple AND-only for now
+            connectors: template.connectors.clone(),
         })
     }
 }
@@ -422,6 +625,53 @@ mod tests {
         assert!(stmt.param_count == 0);
     }
 
+    #[test]
+    fn test_bind_limit_and_vector() {
+        let stmt = PreparedStatement::new("SELECT * FROM docs WHERE embedding SIMILARITY ? AND n > ? LIMIT ? OFFSET ?;").unwrap();
+        assert_eq!(stmt.param_count(), 4);
+        let params = [Value::Vector(vec![1.0, 0.0]), Value::Integer(3), Value::Integer(5), Value::Integer(2)];
+        match stmt.bind(&params).unwrap() {
+            Command::Select { where_clause, limit, offset, .. } => {
+                let wc = where_clause.unwrap();
+                assert!(matches!(&wc.conditions[0].value, ConditionValue::Single(Value::Vector(v)) if v == &[1.0, 0.0]));
+                assert!(matches!(&wc.conditions[1].value, ConditionValue::Single(Value::Integer(3))));
+                assert_eq!((limit, offset), (Some(5), Some(2)));
+            }
+            other => panic!("Expected Select, got {:?}", other),
+        }
+
+        let bad = [Value::Vector(vec![1.0]), Value::Integer(3), Value::Text("5".into()), Value::Integer(0)];
+        assert!(stmt.bind(&bad).is_err());
+    }
+
+    #[test]
+    fn test_bind_vector_elements_and_strings() {
+        let stmt = PreparedStatement::new("INSERT INTO docs (embedding, title) VALUES ([?, 0.5], 'what?');").unwrap();
+        assert_eq!(stmt.param_count(), 1);
+        match stmt.bind(&[Value::Float(0.25)]).unwrap() {
+            Command::Insert { values, .. } => {
+                assert_eq!(values[0], vec![Value::Vector(vec![0.25, 0.5]), Value::Text("what?".into())]);
+            }
+            other => panic!("Expected Insert, got {:?}", other),
+        }
+
+        let stmt = PreparedStatement::new("UPDATE docs SET n = n + ? WHERE title IN (?, 'b') OR n BETWEEN ? AND 9;").unwrap();
+        let params = [Value::Integer(1), Value::Text("a".into()), Value::Integer(2)];
+        match stmt.bind(&params).unwrap() {
+            Command::Update { assignments, where_clause, .. } => {
+                assert_eq!(assignments[0].1.to_string(), "n + 1");
+                let wc = where_clause.unwrap();
+                assert_eq!(wc.connectors.len(), 1);
+                assert!(matches!(&wc.conditions[0].value, ConditionValue::List(list) if list[0] == Value::Text("a".into())));
+                assert!(matches!(&wc.conditions[1].value, ConditionValue::Range(Value::Integer(2), Value::Integer(9))));
+            }
+            other => panic!("Expected Update, got {:?}", other),
+        }
+
+        assert!(PreparedStatement::new("DROP TABLE docs;").unwrap().bind(&[]).is_ok());
+        assert!(PreparedStatement::new("SET max_rows = ?;").is_err());
+    }
+
     #[test]
     fn test_batch_inserter() {
         let mut db = crate::Database::in_memory();
@@ -0,0 +1,229 @@
+//! Linear projections that shrink a table's vectors.
+//!
+//! `CREATE PROJECTION p ON docs(embedding) DIM 256 METHOD PCA` trains a
+//! projection on the stored vectors, maps them to 256 dimensions and keeps
+//! mapping new vectors and queries on the way in, so the index only ever
+//! sees the smaller vectors.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MarsError, Result};
+
+/// Power iterations of PCA training
+const PCA_ITERATIONS: usize = 12;
+
+/// Seed of the generator behind random matrices. Trained projections are
+/// saved, so a fixed seed only makes training reproducible.
+const SEED: u64 = 0x5EED_0F9A_2D05;
+
+/// How a projection is trained
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProjectionMethod {
+    /// The directions along which the sampled vectors vary most
+    Pca,
+    /// A random ±1 matrix, which roughly preserves distances without
+    /// looking at the data
+    Random,
+}
+
+impl ProjectionMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProjectionMethod::Pca => "PCA",
+            ProjectionMethod::Random => "RANDOM",
+        }
+    }
+}
+
+/// A linear map from `input_dimension()` to `dimension()` components
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Projection {
+    pub name: String,
+    pub method: ProjectionMethod,
+    input: usize,
+    /// `dimension()` rows of `input` weights
+    matrix: Vec<f32>,
+}
+
+impl Projection {
+    /// Train a `dimension`-component projection of `input`-component
+    /// vectors. PCA learns from `sample`, which must not be empty; random
+    /// projections ignore it. Returns the projection with, for PCA, the
+    /// share of the sample's squared length it keeps.
+    pub fn train(
+        name: &str,
+        method: ProjectionMethod,
+        input: usize,
+        dimension: usize,
+        sample: &[&[f32]],
+    ) -> Result<(Self, Option<f64>)> {
+        if dimension == 0 || dimension >= input {
+            return Err(MarsError::InvalidConfig(format!(
+                "Projection dimension must be between 1 and {}, got {}", input.saturating_sub(1), dimension,
+            )));
+        }
+        let mut rng = Rng(SEED);
+        let (matrix, retained) = match method {
+            ProjectionMethod::Random => {
+                let weight = 1.0 / (dimension as f32).sqrt();
+                let matrix = (0..dimension * input)
+                    .map(|_| if rng.next() & 1 == 0 { weight } else { -weight })
+                    .collect();
+                (matrix, None)
+            }
+            ProjectionMethod::Pca => {
+                if sample.is_empty() {
+                    return Err(MarsError::InvalidConfig("PCA needs stored vectors to train on".into()));
+                }
+                let (matrix, retained) = pca(sample, input, dimension, &mut rng);
+                (matrix, Some(retained))
+            }
+        };
+        Ok((Projection { name: name.to_string(), method, input, matrix }, retained))
+    }
+
+    /// Components of the vectors it maps
+    pub fn input_dimension(&self) -> usize {
+        self.input
+    }
+
+    /// Components of the vectors it produces
+    pub fn dimension(&self) -> usize {
+        self.matrix.len() / self.input.max(1)
+    }
+
+    /// Map a vector of `input_dimension()` components
+    pub fn project(&self, vector: &[f32]) -> Vec<f32> {
+        self.matrix.chunks_exact(self.input)
+            .map(|row| row.iter().zip(vector).map(|(w, x)| w * x).sum())
+            .collect()
+    }
+}
+
+/// splitmix64
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [-1, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+/// Top `k` eigenvectors of the sample's second moment matrix, by subspace
+/// iteration, as `k` rows of `d` weights, strongest first. The matrix is
+/// not centered, so dot products and cosines project as well as
+/// distances. Also returns the share of the trace they capture.
+fn pca(sample: &[&[f32]], d: usize, k: usize, rng: &mut Rng) -> (Vec<f32>, f64) {
+    let mut moment = vec![0.0f64; d * d];
+    for vector in sample {
+        for i in 0..d {
+            let xi = vector[i] as f64;
+            let row = &mut moment[i * d..(i + 1) * d];
+            for j in i..d {
+                row[j] += xi * vector[j] as f64;
+            }
+        }
+    }
+    let n = sample.len() as f64;
+    for i in 0..d {
+        for j in i..d {
+            moment[i * d + j] /= n;
+            moment[j * d + i] = moment[i * d + j];
+        }
+    }
+    let trace: f64 = (0..d).map(|i| moment[i * d + i]).sum();
+
+    let mut basis: Vec<Vec<f64>> = (0..k).map(|_| (0..d).map(|_| rng.unit()).collect()).collect();
+    orthonormalize(&mut basis, rng);
+    for _ in 0..PCA_ITERATIONS {
+        basis = basis.iter().map(|q| multiply(&moment, q)).collect();
+        orthonormalize(&mut basis, rng);
+    }
+
+    let mut ranked: Vec<(f64, Vec<f64>)> = basis.into_iter()
+        .map(|q| (dot(&q, &multiply(&moment, &q)), q))
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let captured: f64 = ranked.iter().map(|(variance, _)| variance).sum();
+    let retained = if trace > 0.0 { (captured / trace).min(1.0) } else { 1.0 };
+    let matrix = ranked.into_iter().flat_map(|(_, q)| q.into_iter().map(|w| w as f32)).collect();
+    (matrix, retained)
+}
+
+fn multiply(matrix: &[f64], vector: &[f64]) -> Vec<f64> {
+    matrix.chunks_exact(vector.len()).map(|row| dot(row, vector)).collect()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Modified Gram-Schmidt. Vectors that vanish, as when the sample spans
+/// fewer than k dimensions, are replaced with random ones.
+fn orthonormalize(basis: &mut [Vec<f64>], rng: &mut Rng) {
+    for i in 0..basis.len() {
+        for _attempt in 0..4 {
+            let (done, rest) = basis.split_at_mut(i);
+            let v = &mut rest[0];
+            let before = dot(v, v).sqrt();
+            for q in done.iter() {
+                let along = dot(v, q);
+                v.iter_mut().zip(q).for_each(|(x, y)| *x -= along * y);
+            }
+            let norm = dot(v, v).sqrt();
+            if norm > 1e-9 * before.max(1e-30) {
+                v.iter_mut().for_each(|x| *x /= norm);
+                break;
+            }
+            v.iter_mut().for_each(|x| *x = rng.unit());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pca() {
+        // Points on a plane tilted inside 6 dimensions, plus a little noise
+        let mut rng = Rng(7);
+        let vectors: Vec<Vec<f32>> = (0..300)
+            .map(|_| {
+                let (a, b) = (rng.unit() * 5.0, rng.unit() * 2.0);
+                (0..6).map(|d| (a * (d as f64 + 1.0) / 4.0 + b * if d % 2 == 0 { 1.0 } else { -1.0 } + rng.unit() * 0.01) as f32).collect()
+            })
+            .collect();
+        let sample: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let (projection, retained) = Projection::train("p", ProjectionMethod::Pca, 6, 2, &sample).unwrap();
+        assert_eq!((projection.input_dimension(), projection.dimension()), (6, 2));
+        assert!(retained.unwrap() > 0.999, "{:?}", retained);
+
+        // Distances within the plane survive
+        let distance = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt();
+        for pair in vectors.windows(2).take(20) {
+            let (original, projected) = (distance(&pair[0], &pair[1]), distance(&projection.project(&pair[0]), &projection.project(&pair[1])));
+            assert!((original - projected).abs() < 0.05, "{} vs {}", original, projected);
+        }
+    }
+
+    #[test]
+    fn test_random() {
+        let (projection, retained) = Projection::train("p", ProjectionMethod::Random, 64, 16, &[]).unwrap();
+        assert_eq!((projection.dimension(), retained), (16, None));
+        assert_eq!(projection.project(&[0.0; 64]), vec![0.0; 16]);
+
+        assert!(Projection::train("p", ProjectionMethod::Pca, 64, 16, &[]).is_err());
+        assert!(Projection::train("p", ProjectionMethod::Random, 64, 64, &[]).is_err());
+        assert!(Projection::train("p", ProjectionMethod::Random, 64, 0, &[]).is_err());
+    }
+}
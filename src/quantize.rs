@@ -0,0 +1,213 @@
+//! INT8 scalar quantization of a table's vectors.
+//!
+//! `ALTER TABLE t SET VECTOR STORAGE INT8` samples the stored vectors and
+//! calibrates a scale and offset per dimension, so each component fits in
+//! one byte. Vectors are rounded to those 256 levels on the way in, search
+//! runs on the rounded values, and saved tables keep one byte per
+//! component instead of four.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MarsError, Result};
+use crate::schema::{Row, Value};
+
+/// Highest code of a component
+const LEVELS: f32 = 255.0;
+
+/// Per-dimension scale and offset mapping vector components to bytes:
+/// component = offset + code * scale
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quantizer {
+    offset: Vec<f32>,
+    scale: Vec<f32>,
+}
+
+impl Quantizer {
+    /// Fit each dimension's range to the smallest and largest component of
+    /// `vectors`. None if there are no vectors.
+    pub fn calibrate(vectors: &[&[f32]]) -> Option<Self> {
+        let dimension = vectors.first()?.len();
+        let mut min = vec![f32::INFINITY; dimension];
+        let mut max = vec![f32::NEG_INFINITY; dimension];
+        for vector in vectors {
+            for (d, &x) in vector.iter().enumerate().take(dimension) {
+                min[d] = min[d].min(x);
+                max[d] = max[d].max(x);
+            }
+        }
+        let scale = min.iter().zip(&max).map(|(lo, hi)| (hi - lo) / LEVELS).collect();
+        Some(Quantizer { offset: min, scale })
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.offset.len()
+    }
+
+    /// Smallest value of each dimension
+    pub fn offset(&self) -> &[f32] {
+        &self.offset
+    }
+
+    /// Step between adjacent codes of each dimension
+    pub fn scale(&self) -> &[f32] {
+        &self.scale
+    }
+
+    /// Codes of a vector. Components outside the calibrated range are
+    /// clamped to it.
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        vector.iter().zip(self.offset.iter().zip(&self.scale))
+            .map(|(&x, (&offset, &scale))| {
+                if scale > 0.0 { ((x - offset) / scale).round().clamp(0.0, LEVELS) as u8 } else { 0 }
+            })
+            .collect()
+    }
+
+    /// The vector a list of codes stands for
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        codes.iter().zip(self.offset.iter().zip(&self.scale))
+            .map(|(&code, (&offset, &scale))| offset + code as f32 * scale)
+            .collect()
+    }
+
+    /// Replace a vector with its quantized value
+    pub fn round(&self, vector: &mut [f32]) {
+        let decoded = self.decode(&self.encode(vector));
+        vector[..decoded.len()].copy_from_slice(&decoded);
+    }
+}
+
+/// Expected effect of INT8 storage, measured on the calibration sample
+/// before its vectors were rounded
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CalibrationReport {
+    /// Vectors the ranges were fitted to
+    pub sampled: usize,
+    /// Sampled vectors searched for
+    pub queries: usize,
+    pub k: usize,
+    /// Mean recall@k of exact searches over the rounded sample against the
+    /// original sample. A result counts as a hit when its original
+    /// distance is no farther than the original k-th result.
+    pub recall: f64,
+    /// Worst recall@k of a single query
+    pub min_recall: f64,
+    /// Mean absolute change of a component from rounding
+    pub mean_error: f64,
+}
+
+/// Calibrate on `sample` and estimate the recall@k of searching its rounded
+/// vectors with up to `queries` of its original ones
+pub(crate) fn calibrate(
+    sample: &[&[f32]],
+    metric: fn(&[f32], &[f32]) -> f32,
+    queries: usize,
+    k: usize,
+) -> Option<(Quantizer, CalibrationReport)> {
+    let quantizer = Quantizer::calibrate(sample)?;
+    let rounded: Vec<Vec<f32>> = sample.iter().map(|v| quantizer.decode(&quantizer.encode(v))).collect();
+
+    let components: usize = sample.iter().map(|v| v.len()).sum();
+    let error: f64 = sample.iter().zip(&rounded)
+        .flat_map(|(a, b)| a.iter().zip(b).map(|(x, y)| (x - y).abs() as f64))
+        .sum();
+
+    let step = sample.len().div_ceil(queries.max(1)).max(1);
+    let mut report = CalibrationReport {
+        sampled: sample.len(),
+        queries: 0,
+        k,
+        recall: 1.0,
+        min_recall: 1.0,
+        mean_error: if components > 0 { error / components as f64 } else { 0.0 },
+    };
+    let k = k.min(sample.len());
+    if k == 0 {
+        return Some((quantizer, report));
+    }
+    let mut total = 0.0;
+    for query in sample.iter().step_by(step) {
+        let mut exact: Vec<f32> = sample.iter().map(|v| metric(query, v)).collect();
+        let mut found: Vec<(f32, usize)> = rounded.iter().enumerate().map(|(i, v)| (metric(query, v), i)).collect();
+        found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let hits: Vec<f32> = found[..k].iter().map(|&(_, i)| exact[i]).collect();
+        exact.sort_by(f32::total_cmp);
+        let cutoff = exact[k - 1];
+
+        let recall = hits.iter().filter(|&&dist| dist <= cutoff).count() as f64 / k as f64;
+        total += recall;
+        report.min_recall = report.min_recall.min(recall);
+        report.queries += 1;
+    }
+    report.recall = total / report.queries as f64;
+    Some((quantizer, report))
+}
+
+/// The codes of one row's vector, left as NULL in the row
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct QuantizedVector {
+    pub row: u32,
+    pub codes: Vec<u8>,
+}
+
+/// Replace the vectors in `column` of `rows` with their codes
+pub(crate) fn encode(rows: &mut [Row], column: usize, quantizer: &Quantizer) -> Vec<QuantizedVector> {
+    let mut encoded = Vec::new();
+    for (r, row) in rows.iter_mut().enumerate() {
+        let Some(Value::Vector(vector)) = row.values.get(column) else { continue };
+        encoded.push(QuantizedVector { row: r as u32, codes: quantizer.encode(vector) });
+        row.values[column] = Value::Null;
+    }
+    encoded
+}
+
+/// Put the vectors taken out by `encode` back into `rows`
+pub(crate) fn decode(rows: &mut [Row], column: usize, quantizer: &Quantizer, vectors: Vec<QuantizedVector>) -> Result<()> {
+    for vector in vectors {
+        let slot = rows.get_mut(vector.row as usize)
+            .and_then(|row| row.values.get_mut(column))
+            .ok_or_else(|| MarsError::InvalidFormat(format!("corrupt quantized vector in row {}", vector.row)))?;
+        *slot = Value::Vector(quantizer.decode(&vector.codes));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::{Distance, Euclidean};
+
+    #[test]
+    fn test_quantizer() {
+        let vectors: Vec<Vec<f32>> = (0..100).map(|i| vec![i as f32 / 100.0, -1.0 + i as f32 / 50.0, 3.0]).collect();
+        let sample: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let quantizer = Quantizer::calibrate(&sample).unwrap();
+        assert_eq!(quantizer.offset(), &[0.0, -1.0, 3.0]);
+        assert_eq!(quantizer.scale()[2], 0.0);
+
+        assert_eq!(quantizer.encode(&[0.0, -1.0, 3.0]), vec![0, 0, 0]);
+        assert_eq!(quantizer.encode(&[0.99, 0.98, 3.0]), vec![255, 255, 0]);
+        // Out-of-range components are clamped
+        assert_eq!(quantizer.encode(&[-5.0, 9.0, 7.0]), vec![0, 255, 0]);
+
+        let mut v = vec![0.5, 0.0, 3.0];
+        quantizer.round(&mut v);
+        assert!((v[0] - 0.5).abs() <= quantizer.scale()[0] / 2.0);
+        assert!(v[1].abs() <= quantizer.scale()[1] / 2.0);
+        assert_eq!(v[2], 3.0);
+
+        assert!(Quantizer::calibrate(&[]).is_none());
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let vectors: Vec<Vec<f32>> = (0..200)
+            .map(|i| (0..8).map(|d| ((i * 7 + d * 13) % 31) as f32 / 31.0).collect())
+            .collect();
+        let sample: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let (_, report) = calibrate(&sample, Euclidean::compute, 20, 10).unwrap();
+        assert_eq!((report.sampled, report.queries, report.k), (200, 20, 10));
+        assert!(report.recall > 0.9 && report.min_recall <= report.recall);
+        assert!(report.mean_error > 0.0 && report.mean_error < 1.0 / 255.0);
+    }
+}
@@ -0,0 +1,134 @@
+//! Database files kept in an object store (S3, Google Cloud Storage, Azure
+//! Blob Storage) instead of on local disk.
+//!
+//! The whole file is downloaded when the database is opened and uploaded
+//! again on save. Without conflict detection saves are multipart uploads
+//! that overwrite the object; with it they are conditional puts that fail
+//! if another writer replaced the object since it was downloaded.
+
+use std::sync::{Arc, Mutex};
+
+use object_store::path::Path;
+use object_store::{ObjectStore, PutMode, PutOptions, UpdateVersion, WriteMultipart};
+use tokio::runtime::Runtime;
+use url::Url;
+
+use crate::error::{MarsError, Result};
+
+/// An object holding a database file, with the runtime its async client
+/// is driven on
+pub(crate) struct RemoteFile {
+    store: Arc<dyn ObjectStore>,
+    location: Path,
+    runtime: Runtime,
+    /// Object version last downloaded or uploaded; None if there was none
+    version: Mutex<Option<UpdateVersion>>,
+    /// Saves fail with `MarsError::SaveConflict` if the object changed
+    pub(crate) detect_conflicts: bool,
+}
+
+impl RemoteFile {
+    /// Address an object by URL: `s3://bucket/key`, `gs://bucket/key` (or
+    /// `gcs://`), `az://container/key`. Credentials and regions come from
+    /// the usual environment variables, e.g. `AWS_ACCESS_KEY_ID`.
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let mut url = Url::parse(url)
+            .map_err(|e| MarsError::InvalidConfig(format!("invalid object store URL '{}': {}", url, e)))?;
+        if url.scheme() == "gcs" {
+            url = Url::parse(&url.as_str().replacen("gcs://", "gs://", 1)).expect("same URL with another scheme");
+        }
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, location) = object_store::parse_url_opts(&url, options).map_err(store_error)?;
+        Self::new(Arc::from(store), location)
+    }
+
+    pub(crate) fn new(store: Arc<dyn ObjectStore>, location: Path) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(RemoteFile { store, location, runtime, version: Mutex::new(None), detect_conflicts: false })
+    }
+
+    /// Fetch the file, or None if the object does not exist yet
+    pub(crate) fn download(&self) -> Result<Option<Vec<u8>>> {
+        self.runtime.block_on(async {
+            let result = match self.store.get(&self.location).await {
+                Ok(result) => result,
+                Err(object_store::Error::NotFound { .. }) => return Ok(None),
+                Err(e) => return Err(store_error(e)),
+            };
+            let version = UpdateVersion { e_tag: result.meta.e_tag.clone(), version: result.meta.version.clone() };
+            let contents = result.bytes().await.map_err(store_error)?;
+            *self.version.lock().unwrap() = Some(version);
+            Ok(Some(contents.to_vec()))
+        })
+    }
+
+    /// Replace the object with `contents`
+    pub(crate) fn upload(&self, contents: Vec<u8>) -> Result<()> {
+        let mut version = self.version.lock().unwrap();
+        let result = self.runtime.block_on(async {
+            if self.detect_conflicts {
+                let mode = match version.clone() {
+                    Some(version) => PutMode::Update(version),
+                    None => PutMode::Create,
+                };
+                let options = PutOptions { mode, ..PutOptions::default() };
+                return match self.store.put_opts(&self.location, contents.into(), options).await {
+                    Err(object_store::Error::Precondition { .. } | object_store::Error::AlreadyExists { .. }) => {
+                        Err(MarsError::SaveConflict(format!("{} was changed by another writer", self.location)))
+                    }
+                    result => result.map_err(store_error),
+                };
+            }
+            let mut upload = WriteMultipart::new(self.store.put_multipart(&self.location).await.map_err(store_error)?);
+            upload.write(&contents);
+            upload.finish().await.map_err(store_error)
+        })?;
+        *version = Some(UpdateVersion { e_tag: result.e_tag, version: result.version });
+        Ok(())
+    }
+}
+
+fn store_error(e: object_store::Error) -> MarsError {
+    MarsError::Io(std::io::Error::other(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn test_round_trip() {
+        let remote = RemoteFile::new(Arc::new(InMemory::new()), Path::from("dbs/a.pardus")).unwrap();
+        assert_eq!(remote.download().unwrap(), None);
+        remote.upload(b"first".to_vec()).unwrap();
+        remote.upload(b"second".to_vec()).unwrap();
+        assert_eq!(remote.download().unwrap().as_deref(), Some(&b"second"[..]));
+    }
+
+    #[test]
+    fn test_detects_conflicts() {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let open = || {
+            let mut remote = RemoteFile::new(Arc::clone(&store), Path::from("a.pardus")).unwrap();
+            remote.detect_conflicts = true;
+            remote.download().unwrap();
+            remote
+        };
+
+        let (a, b) = (open(), open());
+        a.upload(b"from a".to_vec()).unwrap();
+        assert!(matches!(b.upload(b"from b".to_vec()), Err(MarsError::SaveConflict(_))));
+        a.upload(b"from a again".to_vec()).unwrap();
+
+        let c = open();
+        c.upload(b"from c".to_vec()).unwrap();
+        assert!(matches!(a.upload(b"stale".to_vec()), Err(MarsError::SaveConflict(_))));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_urls() {
+        assert!(matches!(RemoteFile::parse("not a url"), Err(MarsError::InvalidConfig(_))));
+        assert!(RemoteFile::parse("ftp://host/a.pardus").is_err());
+    }
+}
@@ -1,4 +1,11 @@
-use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::error::{MarsError, Result};
+use crate::uuid::Uuid;
 
 /// Column types for schema definition
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -9,6 +16,9 @@ pub enum ColumnType {
     Float,
     Boolean,
     Blob,
+    SparseVector,  // SPARSE_VECTOR: (index, weight) pairs
+    MultiVector(usize),  // VECTOR(dimensions) MULTI: several vectors per row
+    Uuid,  // 16 bytes, read and written as text
 }
 
 impl ColumnType {
@@ -26,6 +36,127 @@ impl ColumnType {
             ColumnType::Float => "FLOAT".to_string(),
             ColumnType::Boolean => "BOOLEAN".to_string(),
             ColumnType::Blob => "BLOB".to_string(),
+            ColumnType::SparseVector => "SPARSE_VECTOR".to_string(),
+            ColumnType::MultiVector(dim) => format!("VECTOR({}) MULTI", dim),
+            ColumnType::Uuid => "UUID".to_string(),
+        }
+    }
+}
+
+/// How TEXT values of a column are compared (`COLLATE ...`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Collation {
+    #[default]
+    Binary,     // Byte-exact
+    NoCase,     // ASCII letters compared case-insensitively, like SQLite
+    UnicodeCi,  // Full Unicode lowercase folding
+}
+
+impl Collation {
+    /// Parse a collation name: BINARY, NOCASE or UNICODE_CI (also UNICODE-CI)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().replace('-', "_").as_str() {
+            "BINARY" => Some(Collation::Binary),
+            "NOCASE" => Some(Collation::NoCase),
+            "UNICODE_CI" => Some(Collation::UnicodeCi),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Collation::Binary => "BINARY",
+            Collation::NoCase => "NOCASE",
+            Collation::UnicodeCi => "UNICODE_CI",
+        }
+    }
+
+    /// Folded form of a string; equal keys compare equal under this collation
+    pub fn key<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        match self {
+            Collation::Binary => Cow::Borrowed(s),
+            Collation::NoCase => Cow::Owned(s.to_ascii_lowercase()),
+            Collation::UnicodeCi => Cow::Owned(s.to_lowercase()),
+        }
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            _ => self.key(a).cmp(&self.key(b)),
+        }
+    }
+}
+
+/// How a masked column is shown to restricted connections (`MASK ...`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mask {
+    Redact,           // Always NULL
+    Hash,             // Stable hex digest, so equal values still match
+    Truncate(usize),  // First n characters of TEXT
+}
+
+impl Mask {
+    /// Masked form of a value. NULL stays NULL.
+    pub fn apply(&self, value: &Value) -> Value {
+        match (self, value) {
+            (_, Value::Null) | (Mask::Redact, _) => Value::Null,
+            (Mask::Hash, value) => Value::Text(format!("{:016x}", value.stable_hash())),
+            (Mask::Truncate(n), Value::Text(s)) => Value::Text(s.chars().take(*n).collect()),
+            (Mask::Truncate(_), _) => Value::Null,
+        }
+    }
+}
+
+/// What deleting a referenced row does to the rows referencing it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnDelete {
+    #[default]
+    Restrict,  // The delete fails
+    Cascade,   // Referencing rows are deleted too
+    SetNull,   // The referencing column is set to NULL
+}
+
+/// `column REFERENCES parent_table(parent_column) ON DELETE ...`: every
+/// non-NULL value of `column` must match a row of the parent table
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub column: String,
+    pub parent_table: String,
+    /// `id` or a UNIQUE column of the parent table
+    pub parent_column: String,
+    pub on_delete: OnDelete,
+}
+
+/// How a table numbers new rows (`AUTOINCREMENT`). Tables without one
+/// number rows 1, 2, 3, ...
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AutoIncrement {
+    /// Row IDs `start`, `start + step`, ..., also written to the `id` column
+    Sequence { start: u64, step: u64 },
+    /// A random UUID in this UUID or TEXT column whenever a row leaves it
+    /// NULL (`AUTOINCREMENT` or `DEFAULT gen_random_uuid()`)
+    Uuid { column: String },
+}
+
+/// FNV-1a; unlike `DefaultHasher`, its output is stable across builds, so
+/// hashed values can be compared between sessions
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
         }
     }
 }
@@ -38,6 +169,7 @@ pub struct Column {
     pub primary_key: bool,
     pub nullable: bool,
     pub unique: bool,  // NEW: UNIQUE constraint
+    pub collation: Collation,
 }
 
 impl Column {
@@ -48,6 +180,7 @@ impl Column {
             primary_key: false,
             nullable: true,
             unique: false,
+            collation: Collation::Binary,
         }
     }
 
@@ -66,6 +199,11 @@ impl Column {
         self.unique = true;
         self
     }
+
+    pub fn collate(mut self, collation: Collation) -> Self {
+        self.collation = collation;
+        self
+    }
 }
 
 /// A table schema definition
@@ -76,6 +214,43 @@ pub struct Schema {
     pub vector_column: Option<String>,
 }
 
+/// Column definition as format version 1 stored it, before collations
+#[derive(Deserialize)]
+pub(crate) struct ColumnV1 {
+    pub name: String,
+    pub data_type: ColumnType,
+    pub primary_key: bool,
+    pub nullable: bool,
+    pub unique: bool,
+}
+
+/// Table schema as format version 1 stored it
+#[derive(Deserialize)]
+pub(crate) struct SchemaV1 {
+    pub name: String,
+    pub columns: Vec<ColumnV1>,
+    pub vector_column: Option<String>,
+}
+
+impl From<SchemaV1> for Schema {
+    fn from(v1: SchemaV1) -> Self {
+        Schema {
+            name: v1.name,
+            columns: v1.columns.into_iter()
+                .map(|c| Column {
+                    name: c.name,
+                    data_type: c.data_type,
+                    primary_key: c.primary_key,
+                    nullable: c.nullable,
+                    unique: c.unique,
+                    collation: Collation::Binary,
+                })
+                .collect(),
+            vector_column: v1.vector_column,
+        }
+    }
+}
+
 impl Schema {
     pub fn new(name: &str) -> Self {
         Schema {
@@ -99,6 +274,14 @@ impl Schema {
         })
     }
 
+    /// Check for SPARSE_VECTOR or multi-vector columns, which are searched
+    /// without the graph index
+    pub fn has_unindexed_vector(&self) -> bool {
+        self.columns.iter().any(|c| {
+            matches!(c.data_type, ColumnType::SparseVector | ColumnType::MultiVector(_))
+        })
+    }
+
     pub fn get_vector_dimension(&self) -> Option<usize> {
         self.get_vector_column().and_then(|c| {
             if let ColumnType::Vector(dim) = c.data_type {
@@ -121,6 +304,10 @@ impl Schema {
             if !c.nullable && !c.primary_key {
                 s.push_str(" NOT NULL");
             }
+            if c.collation != Collation::Binary {
+                s.push_str(" COLLATE ");
+                s.push_str(c.collation.name());
+            }
             s
         }).collect();
 
@@ -129,7 +316,7 @@ impl Schema {
 }
 
 /// A row value - can hold different types
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub enum Value {
     Null,
     Vector(Vec<f32>),
@@ -138,9 +325,157 @@ pub enum Value {
     Float(f64),
     Boolean(bool),
     Blob(Vec<u8>),
+    /// Sparse vector as (index, weight) pairs sorted by index
+    SparseVector(Vec<(u32, f32)>),
+    /// Several same-dimension vectors, e.g. per-token embeddings
+    MultiVector(Vec<Vec<f32>>),
+    Uuid(Uuid),
+}
+
+/// Binary formats such as the database file get the tagged encoding a
+/// derive would give. Human-readable ones such as JSON get plain values:
+/// NULL is `null`, vectors are arrays of numbers (sparse ones arrays of
+/// `[index, weight]` pairs), UUIDs are strings and blobs hex strings.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return StoredValue::from(self).serialize(serializer);
+        }
+        match self {
+            Value::Null => serializer.serialize_none(),
+            Value::Vector(v) => v.serialize(serializer),
+            Value::Text(s) => serializer.serialize_str(s),
+            Value::Integer(i) => serializer.serialize_i64(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::Boolean(b) => serializer.serialize_bool(*b),
+            Value::Blob(bytes) => {
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                serializer.serialize_str(&hex)
+            }
+            Value::SparseVector(pairs) => pairs.serialize(serializer),
+            Value::MultiVector(vs) => vs.serialize(serializer),
+            Value::Uuid(u) => serializer.collect_str(u),
+        }
+    }
+}
+
+/// `Value` as the derived encoding sees it: same variants in the same
+/// order, so binary output matches what `Deserialize` reads
+#[derive(Serialize)]
+#[serde(rename = "Value")]
+enum StoredValue<'a> {
+    Null,
+    Vector(&'a [f32]),
+    Text(&'a str),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Blob(&'a [u8]),
+    SparseVector(&'a [(u32, f32)]),
+    MultiVector(&'a [Vec<f32>]),
+    Uuid(Uuid),
+}
+
+impl<'a> From<&'a Value> for StoredValue<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Null => StoredValue::Null,
+            Value::Vector(v) => StoredValue::Vector(v),
+            Value::Text(s) => StoredValue::Text(s),
+            Value::Integer(i) => StoredValue::Integer(*i),
+            Value::Float(f) => StoredValue::Float(*f),
+            Value::Boolean(b) => StoredValue::Boolean(*b),
+            Value::Blob(bytes) => StoredValue::Blob(bytes),
+            Value::SparseVector(pairs) => StoredValue::SparseVector(pairs),
+            Value::MultiVector(vs) => StoredValue::MultiVector(vs),
+            Value::Uuid(u) => StoredValue::Uuid(*u),
+        }
+    }
+}
+
+/// Consistent with `PartialEq`: floats hash by bit pattern with -0.0 and
+/// 0.0 treated as the same value
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        fn bits32(x: f32) -> u32 {
+            if x == 0.0 { 0 } else { x.to_bits() }
+        }
+
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Null => {}
+            Value::Vector(v) => v.iter().for_each(|x| bits32(*x).hash(state)),
+            Value::Text(s) => s.hash(state),
+            Value::Integer(i) => i.hash(state),
+            Value::Float(f) => (if *f == 0.0 { 0 } else { f.to_bits() }).hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Blob(b) => b.hash(state),
+            Value::SparseVector(v) => v.iter().for_each(|(i, w)| (*i, bits32(*w)).hash(state)),
+            Value::MultiVector(v) => v.iter().for_each(|row| {
+                row.len().hash(state);
+                row.iter().for_each(|x| bits32(*x).hash(state));
+            }),
+            Value::Uuid(u) => u.hash(state),
+        }
+    }
 }
 
 impl Value {
+    /// FNV-1a over an explicit byte encoding: a tag byte per variant, then
+    /// little-endian numbers and UTF-8 text. Unlike the `Hash` impl, it
+    /// does not depend on enum layout or platform endianness, so the result
+    /// can be stored or compared between builds and machines.
+    pub(crate) fn stable_hash(&self) -> u64 {
+        fn f32_bytes(x: f32) -> [u8; 4] {
+            if x == 0.0 { [0; 4] } else { x.to_le_bytes() }
+        }
+
+        let mut hasher = Fnv1a::new();
+        match self {
+            Value::Null => hasher.write(&[0]),
+            Value::Vector(v) => {
+                hasher.write(&[1]);
+                v.iter().for_each(|x| hasher.write(&f32_bytes(*x)));
+            }
+            Value::Text(s) => {
+                hasher.write(&[2]);
+                hasher.write(s.as_bytes());
+            }
+            Value::Integer(i) => {
+                hasher.write(&[3]);
+                hasher.write(&i.to_le_bytes());
+            }
+            Value::Float(f) => {
+                hasher.write(&[4]);
+                hasher.write(&if *f == 0.0 { [0; 8] } else { f.to_le_bytes() });
+            }
+            Value::Boolean(b) => hasher.write(&[5, *b as u8]),
+            Value::Blob(b) => {
+                hasher.write(&[6]);
+                hasher.write(b);
+            }
+            Value::SparseVector(v) => {
+                hasher.write(&[7]);
+                v.iter().for_each(|(i, w)| {
+                    hasher.write(&i.to_le_bytes());
+                    hasher.write(&f32_bytes(*w));
+                });
+            }
+            Value::MultiVector(v) => {
+                hasher.write(&[8]);
+                v.iter().for_each(|row| {
+                    hasher.write(&(row.len() as u64).to_le_bytes());
+                    row.iter().for_each(|x| hasher.write(&f32_bytes(*x)));
+                });
+            }
+            Value::Uuid(u) => {
+                hasher.write(&[9]);
+                hasher.write(u.as_bytes());
+            }
+        }
+        hasher.finish()
+    }
+
     pub fn is_null(&self) -> bool {
         matches!(self, Value::Null)
     }
@@ -152,6 +487,20 @@ impl Value {
         }
     }
 
+    pub fn as_sparse_vector(&self) -> Option<&[(u32, f32)]> {
+        match self {
+            Value::SparseVector(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_multi_vector(&self) -> Option<&[Vec<f32>]> {
+        match self {
+            Value::MultiVector(v) => Some(v),
+            _ => None,
+        }
+    }
+
     pub fn as_text(&self) -> Option<&str> {
         match self {
             Value::Text(s) => Some(s),
@@ -159,6 +508,15 @@ impl Value {
         }
     }
 
+    /// A UUID value, or UUID text parsed
+    pub fn as_uuid(&self) -> Option<Uuid> {
+        match self {
+            Value::Uuid(u) => Some(*u),
+            Value::Text(s) => Uuid::parse(s),
+            _ => None,
+        }
+    }
+
     pub fn as_integer(&self) -> Option<i64> {
         match self {
             Value::Integer(i) => Some(*i),
@@ -192,10 +550,237 @@ impl Row {
     }
 }
 
+/// Name and type of a column in a query result. `data_type` is None for
+/// computed values whose type depends on the data, e.g. COALESCE.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ResultColumn {
+    pub name: String,
+    pub data_type: Option<ColumnType>,
+}
+
+impl ResultColumn {
+    pub fn new(name: &str, data_type: Option<ColumnType>) -> Self {
+        ResultColumn { name: name.to_string(), data_type }
+    }
+}
+
+/// Column metadata of a row-returning result
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ResultSchema {
+    pub columns: Vec<ResultColumn>,
+}
+
+impl ResultSchema {
+    /// Schema listing every column of a table
+    pub fn from_schema(schema: &Schema) -> Self {
+        ResultSchema {
+            columns: schema.columns.iter()
+                .map(|c| ResultColumn::new(&c.name, Some(c.data_type.clone())))
+                .collect(),
+        }
+    }
+
+    /// Position of a column (case-insensitive)
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.columns.iter().map(|c| c.name.as_str()).collect()
+    }
+}
+
+/// A vector passed to the direct insert APIs. Rows own their vectors, so an
+/// owned `Vec<f32>` is moved into the row, while a slice or an `Arc<[f32]>`
+/// (e.g. an inference runtime's output buffer) is copied once, straight
+/// into it.
+pub trait IntoVector {
+    fn into_vector(self) -> Vec<f32>;
+}
+
+impl IntoVector for Vec<f32> {
+    fn into_vector(self) -> Vec<f32> {
+        self
+    }
+}
+
+impl IntoVector for &[f32] {
+    fn into_vector(self) -> Vec<f32> {
+        self.to_vec()
+    }
+}
+
+impl IntoVector for &Vec<f32> {
+    fn into_vector(self) -> Vec<f32> {
+        self.clone()
+    }
+}
+
+impl<const N: usize> IntoVector for [f32; N] {
+    fn into_vector(self) -> Vec<f32> {
+        self.to_vec()
+    }
+}
+
+impl IntoVector for Box<[f32]> {
+    fn into_vector(self) -> Vec<f32> {
+        self.into_vec()
+    }
+}
+
+impl IntoVector for std::sync::Arc<[f32]> {
+    fn into_vector(self) -> Vec<f32> {
+        self.to_vec()
+    }
+}
+
+impl IntoVector for &std::sync::Arc<[f32]> {
+    fn into_vector(self) -> Vec<f32> {
+        self.to_vec()
+    }
+}
+
+/// Conversion from a stored value, used by `ResultRow::get`
+pub trait FromValue: Sized {
+    /// Rust type name used in error messages
+    const TYPE_NAME: &'static str;
+
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for i64 {
+    const TYPE_NAME: &'static str = "i64";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_integer()
+    }
+}
+
+impl FromValue for f64 {
+    const TYPE_NAME: &'static str = "f64";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_float()
+    }
+}
+
+impl FromValue for String {
+    const TYPE_NAME: &'static str = "String";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_text().map(str::to_string)
+    }
+}
+
+impl FromValue for bool {
+    const TYPE_NAME: &'static str = "bool";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Vec<f32> {
+    const TYPE_NAME: &'static str = "Vec<f32>";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_vector().map(<[f32]>::to_vec)
+    }
+}
+
+impl FromValue for Vec<u8> {
+    const TYPE_NAME: &'static str = "Vec<u8>";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Blob(b) => Some(b.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Value {
+    const TYPE_NAME: &'static str = "Value";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+/// NULL reads as None instead of failing
+impl<T: FromValue> FromValue for Option<T> {
+    const TYPE_NAME: &'static str = T::TYPE_NAME;
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            v => T::from_value(v).map(Some),
+        }
+    }
+}
+
+/// A result row paired with its column metadata
+#[derive(Clone, Copy, Debug)]
+pub struct ResultRow<'a> {
+    pub row: &'a Row,
+    /// Distance to the query for similarity results
+    pub distance: Option<f32>,
+    /// The distance as the metric reports it (see `metric_score`), e.g.
+    /// the cosine similarity for cosine tables
+    pub score: Option<f32>,
+    pub schema: &'a ResultSchema,
+}
+
+impl<'a> ResultRow<'a> {
+    pub fn id(&self) -> u64 {
+        self.row.id
+    }
+
+    /// Raw value of a column, None if the result has no such column
+    pub fn value(&self, column: &str) -> Option<&'a Value> {
+        self.schema.index_of(column).and_then(|idx| self.row.values.get(idx))
+    }
+
+    /// Read a column as `T`, e.g. `row.get::<String>("title")`
+    pub fn get<T: FromValue>(&self, column: &str) -> Result<T> {
+        let value = self.value(column)
+            .ok_or_else(|| MarsError::ColumnNotFound(column.to_string()))?;
+        T::from_value(value).ok_or_else(|| {
+            MarsError::InvalidFormat(format!("Cannot read column '{}' ({:?}) as {}", column, value, T::TYPE_NAME))
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_value_serialization() {
+        let values = vec![
+            Value::Null,
+            Value::Vector(vec![0.5, -1.0]),
+            Value::Text("hi".into()),
+            Value::Integer(-3),
+            Value::Float(2.5),
+            Value::Boolean(true),
+            Value::Blob(vec![0x0f, 0xa0]),
+            Value::SparseVector(vec![(3, 0.25)]),
+            Value::MultiVector(vec![vec![1.0], vec![2.0]]),
+            Value::Uuid(Uuid::parse("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap()),
+        ];
+        // The database file's binary encoding still round-trips
+        let bytes = bincode::serialize(&values).unwrap();
+        assert_eq!(bincode::deserialize::<Vec<Value>>(&bytes).unwrap(), values);
+
+        assert_eq!(serde_json::to_string(&values).unwrap(),
+            r#"[null,[0.5,-1.0],"hi",-3,2.5,true,"0fa0",[[3,0.25]],[[1.0],[2.0]],"67e55044-10b1-426f-9247-bb680e5fe0c8"]"#);
+        assert_eq!(serde_json::to_string(&Row::new(7, vec![Value::Integer(1)])).unwrap(), r#"{"id":7,"values":[1]}"#);
+    }
+
     #[test]
     fn test_schema_creation() {
         let schema = Schema::new("documents")
@@ -219,4 +804,55 @@ mod tests {
         assert!(sql.contains("CREATE TABLE documents"));
         assert!(sql.contains("VECTOR(768)"));
     }
+
+    #[test]
+    fn test_collation() {
+        assert_eq!(Collation::from_name("unicode-ci"), Some(Collation::UnicodeCi));
+        assert_eq!(Collation::from_name("latin1"), None);
+
+        assert!(Collation::Binary.compare("abc", "ABC").is_gt());
+        assert!(Collation::NoCase.compare("abc", "ABC").is_eq());
+        // NOCASE only folds ASCII, UNICODE_CI folds everything
+        assert!(Collation::NoCase.compare("Émile", "émile").is_ne());
+        assert!(Collation::UnicodeCi.compare("Émile", "émile").is_eq());
+
+        let mut schema = Schema::new("people").column("name", ColumnType::Text);
+        schema.columns[0] = schema.columns[0].clone().collate(Collation::NoCase);
+        assert!(schema.to_sql().contains("name TEXT COLLATE NOCASE"));
+    }
+
+    #[test]
+    fn test_mask_apply() {
+        let text = Value::Text("Jane Doe, 42 Main St".into());
+        assert_eq!(Mask::Redact.apply(&text), Value::Null);
+        assert_eq!(Mask::Truncate(4).apply(&text), Value::Text("Jane".into()));
+        assert_eq!(Mask::Truncate(4).apply(&Value::Integer(7)), Value::Null);
+        assert_eq!(Mask::Hash.apply(&Value::Null), Value::Null);
+
+        let hashed = Mask::Hash.apply(&text);
+        assert_eq!(hashed, Mask::Hash.apply(&Value::Text("Jane Doe, 42 Main St".into())));
+        assert_ne!(hashed, Mask::Hash.apply(&Value::Text("John Doe".into())));
+        assert_eq!(hashed, Value::Text("15d99a470dd59f63".into()));
+    }
+
+    #[test]
+    fn test_result_row_get() {
+        let schema = ResultSchema {
+            columns: vec![
+                ResultColumn::new("title", Some(ColumnType::Text)),
+                ResultColumn::new("score", Some(ColumnType::Integer)),
+                ResultColumn::new("note", Some(ColumnType::Text)),
+            ],
+        };
+        let row = Row::new(1, vec![Value::Text("a".into()), Value::Integer(3), Value::Null]);
+        let r = ResultRow { row: &row, distance: None, score: None, schema: &schema };
+
+        assert_eq!(r.get::<String>("TITLE").unwrap(), "a");
+        assert_eq!(r.get::<i64>("score").unwrap(), 3);
+        assert_eq!(r.get::<f64>("score").unwrap(), 3.0);
+        assert_eq!(r.get::<Option<String>>("note").unwrap(), None);
+        assert!(r.get::<String>("note").is_err());
+        assert!(r.get::<bool>("title").is_err());
+        assert!(matches!(r.get::<i64>("missing"), Err(MarsError::ColumnNotFound(_))));
+    }
 }
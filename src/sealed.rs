@@ -0,0 +1,255 @@
+//! Sealed snapshots: an immutable, read-optimized export of a database.
+//!
+//! The regular file format stores rows only and rebuilds each vector index
+//! when the file is loaded. A sealed file also stores the indexes, as flat
+//! arrays, so opening it skips the rebuild:
+//!
+//! ```text
+//! MAGIC | table_count: u32 | format_version: u32
+//! per table, starting 8-byte aligned:
+//!   meta_len: u64 | meta: LZ4-compressed table (rows, schema, settings)
+//!   zero padding to 8 bytes
+//!   dimension: u32 | nodes: u32 | edges: u32 | entry_points: u32
+//!   node_rows: [u64; nodes]             row id of each graph node
+//!   vectors: [f32; nodes * dimension]   node vectors back to back
+//!   offsets: [u32; nodes + 1]           CSR offsets into neighbors
+//!   neighbors: [u32; edges]
+//!   entry_points: [u32; entry_points]
+//!   centroid: [f32; dimension]
+//! ```
+//!
+//! Numbers are little-endian and every array starts aligned to its element
+//! size, so the arrays can be read straight out of a memory map. Deleted
+//! graph nodes are dropped and the rest renumbered densely.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::database::{FORMAT_VERSION, TableData};
+use crate::error::{MarsError, Result};
+use crate::graph::CsrGraph;
+use crate::table::Table;
+
+/// First bytes of a sealed snapshot. Never a valid plain or encrypted header.
+pub(crate) const MAGIC: &[u8; 8] = b"PARDUSS\x01";
+
+/// `node_rows` entry of a node with no row, which a consistent table never has
+const NO_ROW: u64 = u64::MAX;
+
+/// Check whether file contents start like a sealed snapshot
+pub(crate) fn is_sealed(header: &[u8]) -> bool {
+    header.starts_with(MAGIC)
+}
+
+/// Write `tables` as a sealed snapshot
+pub(crate) fn write(tables: &HashMap<String, Table>, out: &mut impl Write) -> Result<()> {
+    let mut writer = AlignedWriter { out, pos: 0 };
+    writer.bytes(MAGIC)?;
+    writer.u32s(&[tables.len() as u32, FORMAT_VERSION])?;
+
+    let mut names: Vec<&String> = tables.keys().collect();
+    names.sort();
+    for name in names {
+        let table = &tables[name];
+        let meta = bincode::serialize(&TableData::from_table(table).compress_text().encode_vectors())
+            .map_err(|e| MarsError::InvalidFormat(format!("Failed to serialize table: {}", e)))?;
+        let meta = lz4_flex::compress_prepend_size(&meta);
+
+        let csr = table.graph.to_csr();
+        let node_rows: Vec<u64> = csr.ids.iter().map(|&id| table.node_row(id).unwrap_or(NO_ROW)).collect();
+
+        writer.pad(8)?;
+        writer.u64s(&[meta.len() as u64])?;
+        writer.bytes(&meta)?;
+        writer.pad(8)?;
+        writer.u32s(&[
+            csr.centroid.len() as u32,
+            csr.ids.len() as u32,
+            csr.neighbors.len() as u32,
+            csr.entry_points.len() as u32,
+        ])?;
+        writer.u64s(&node_rows)?;
+        writer.f32s(&csr.vectors)?;
+        writer.u32s(&csr.offsets)?;
+        writer.u32s(&csr.neighbors)?;
+        writer.u32s(&csr.entry_points)?;
+        writer.f32s(&csr.centroid)?;
+    }
+    Ok(())
+}
+
+/// Load the tables of a sealed snapshot
+pub(crate) fn read(data: &[u8]) -> Result<HashMap<String, Table>> {
+    let mut reader = Reader { data, pos: 0 };
+    if !is_sealed(reader.take(MAGIC.len())?) {
+        return Err(MarsError::InvalidFormat("not a sealed snapshot".into()));
+    }
+    let table_count = reader.u32()?;
+    let version = reader.u32()?;
+
+    let mut tables = HashMap::new();
+    for _ in 0..table_count {
+        reader.align(8);
+        let meta_len = reader.u64()? as usize;
+        let meta = lz4_flex::decompress_size_prepended(reader.take(meta_len)?)
+            .map_err(|e| MarsError::InvalidFormat(format!("corrupted sealed snapshot: {}", e)))?;
+        let data = TableData::decode(version, &meta)?;
+
+        reader.align(8);
+        let dimension = reader.u32()? as usize;
+        let nodes = reader.u32()? as usize;
+        let edges = reader.u32()? as usize;
+        let entry_points = reader.u32()? as usize;
+        let node_rows = reader.u64s(nodes)?;
+        let vector_len = nodes.checked_mul(dimension).ok_or_else(corrupted)?;
+        let csr = CsrGraph {
+            ids: Vec::new(),
+            vectors: reader.f32s(vector_len)?,
+            offsets: reader.u32s(nodes + 1)?,
+            neighbors: reader.u32s(edges)?,
+            entry_points: reader.u32s(entry_points)?,
+            centroid: reader.f32s(dimension)?,
+        };
+        check_csr(&csr, nodes)?;
+
+        let table = data.into_table_with_index(Some((csr, node_rows)))?;
+        tables.insert(table.name().to_string(), table);
+    }
+    Ok(tables)
+}
+
+/// Reject offsets and node ids that would index out of bounds
+fn check_csr(csr: &CsrGraph<f32>, nodes: usize) -> Result<()> {
+    let offsets_ok = csr.offsets.first() == Some(&0)
+        && csr.offsets.windows(2).all(|w| w[0] <= w[1])
+        && csr.offsets.last().map(|&end| end as usize) == Some(csr.neighbors.len());
+    let ids_ok = csr.neighbors.iter().chain(&csr.entry_points).all(|&id| (id as usize) < nodes);
+    if offsets_ok && ids_ok { Ok(()) } else { Err(corrupted()) }
+}
+
+fn corrupted() -> MarsError {
+    MarsError::InvalidFormat("corrupted sealed snapshot".into())
+}
+
+/// Writes little-endian arrays, tracking the offset for alignment padding
+struct AlignedWriter<'w, W: Write> {
+    out: &'w mut W,
+    pos: usize,
+}
+
+impl<W: Write> AlignedWriter<'_, W> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.pos += bytes.len();
+        Ok(())
+    }
+
+    fn pad(&mut self, align: usize) -> Result<()> {
+        let padding = self.pos.next_multiple_of(align) - self.pos;
+        self.bytes(&[0; 8][..padding])
+    }
+
+    fn u32s(&mut self, values: &[u32]) -> Result<()> {
+        values.iter().try_for_each(|v| self.bytes(&v.to_le_bytes()))
+    }
+
+    fn u64s(&mut self, values: &[u64]) -> Result<()> {
+        values.iter().try_for_each(|v| self.bytes(&v.to_le_bytes()))
+    }
+
+    fn f32s(&mut self, values: &[f32]) -> Result<()> {
+        values.iter().try_for_each(|v| self.bytes(&v.to_le_bytes()))
+    }
+}
+
+/// Reads little-endian arrays out of a sealed snapshot
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or_else(corrupted)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn align(&mut self, align: usize) {
+        self.pos = self.pos.next_multiple_of(align);
+    }
+
+    fn array<const N: usize, T>(&mut self, count: usize, decode: fn([u8; N]) -> T) -> Result<Vec<T>> {
+        let bytes = self.take(count.checked_mul(N).ok_or_else(corrupted)?)?;
+        Ok(bytes.chunks_exact(N).map(|chunk| decode(chunk.try_into().unwrap())).collect())
+    }
+
+    fn u32s(&mut self, count: usize) -> Result<Vec<u32>> {
+        self.array(count, u32::from_le_bytes)
+    }
+
+    fn u64s(&mut self, count: usize) -> Result<Vec<u64>> {
+        self.array(count, u64::from_le_bytes)
+    }
+
+    fn f32s(&mut self, count: usize) -> Result<Vec<f32>> {
+        self.array(count, f32::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.u32s(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(self.u64s(1)?[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::schema::Row;
+
+    fn sealed_bytes(db: &Database) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.sealed");
+        db.export_sealed(&path).unwrap();
+        std::fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_keeps_index() {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (embedding VECTOR(3), title TEXT);").unwrap();
+        for i in 0..50 {
+            let x = i as f32;
+            db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}, {}, 1.0], 't{}');", x.sin(), x.cos(), i)).unwrap();
+        }
+        db.execute("DELETE FROM docs WHERE title = 't7';").unwrap();
+
+        let tables = read(&sealed_bytes(&db)).unwrap();
+        let sealed = &tables["docs"];
+        assert_eq!(sealed.len(), 49);
+        assert_eq!(sealed.graph.len(), 49);
+
+        let query = [0.5f32.sin(), 0.5f32.cos(), 1.0];
+        let ids = |results: Vec<(Row, f32)>| results.into_iter().map(|(row, _)| row.id).collect::<Vec<_>>();
+        let table = db.get_table("docs").unwrap();
+        assert_eq!(ids(sealed.select_by_similarity(&query, 5, 50)), ids(table.select_by_similarity(&query, 5, 50)));
+    }
+
+    #[test]
+    fn test_rejects_truncated_and_foreign_files() {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (embedding VECTOR(2));").unwrap();
+        db.execute("INSERT INTO docs (embedding) VALUES ([1.0, 0.0]);").unwrap();
+        let file = sealed_bytes(&db);
+
+        for len in [0, 8, 20, file.len() - 1] {
+            assert!(matches!(read(&file[..len]), Err(MarsError::InvalidFormat(_))), "{}", len);
+        }
+        assert!(matches!(read(b"not a sealed snapshot"), Err(MarsError::InvalidFormat(_))));
+    }
+}
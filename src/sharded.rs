@@ -0,0 +1,172 @@
+//! A collection split across several database files.
+//!
+//! `ShardedDatabase` keeps one `Database` per shard file and routes each
+//! insert by a hash of its shard key column, so every shard holds a stable
+//! share of the rows and has its own file and writer lock. Similarity
+//! searches run on every shard and the results are merged by distance.
+
+use std::path::Path;
+
+use crate::database::{Database, ExecuteResult};
+use crate::error::{MarsError, Result};
+use crate::schema::{IntoVector, Value};
+
+/// Extension of shard files
+const SHARD_EXTENSION: &str = "pardus";
+
+/// A similarity match from a sharded search: (shard, row id, values,
+/// distance). Row IDs are only unique within a shard.
+pub type ShardedResult = (usize, u64, Vec<Value>, f32);
+
+/// Tables split across several database files by a hash of a key column
+pub struct ShardedDatabase {
+    shards: Vec<Database>,
+    /// Column whose value picks the shard of a row
+    key_column: String,
+}
+
+impl ShardedDatabase {
+    /// Open or create `count` shard files `shard-0.pardus`, `shard-1.pardus`,
+    /// ... in `dir`, creating the directory if needed. Rows are routed by
+    /// `key_column`. Fails if `dir` already holds a different number of
+    /// shards, since that would send existing keys to other shards.
+    pub fn open<P: AsRef<Path>>(dir: P, count: usize, key_column: &str) -> Result<Self> {
+        let dir = dir.as_ref();
+        if count == 0 {
+            return Err(MarsError::InvalidConfig("a sharded database needs at least one shard".into()));
+        }
+        std::fs::create_dir_all(dir)?;
+
+        let existing = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| shard_index(&entry.file_name().to_string_lossy()).is_some())
+            .count();
+        if existing != 0 && existing != count {
+            return Err(MarsError::InvalidConfig(format!(
+                "{} holds {} shards, not {}", dir.display(), existing, count,
+            )));
+        }
+
+        let shards = (0..count)
+            .map(|i| Database::open(dir.join(format!("shard-{}.{}", i, SHARD_EXTENSION))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ShardedDatabase { shards, key_column: key_column.to_string() })
+    }
+
+    /// Create `count` in-memory shards, at least one
+    pub fn in_memory(count: usize, key_column: &str) -> Self {
+        ShardedDatabase {
+            shards: (0..count.max(1)).map(|_| Database::in_memory()).collect(),
+            key_column: key_column.to_string(),
+        }
+    }
+
+    /// Get the number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Get the shard key column
+    pub fn key_column(&self) -> &str {
+        &self.key_column
+    }
+
+    /// The shards, in shard order
+    pub fn shards(&self) -> &[Database] {
+        &self.shards
+    }
+
+    /// Borrow one shard, e.g. to run a query on it alone
+    pub fn shard_mut(&mut self, shard: usize) -> Option<&mut Database> {
+        self.shards.get_mut(shard)
+    }
+
+    /// Shard a row with this key value goes to. Stable across sessions,
+    /// builds and platforms for the same shard count.
+    pub fn shard_for(&self, key: &Value) -> usize {
+        (key.stable_hash() % self.shards.len() as u64) as usize
+    }
+
+    /// Run a statement on every shard, such as CREATE TABLE, returning one
+    /// result per shard. Stops at the first shard that fails.
+    pub fn execute_all(&mut self, sql: &str) -> Result<Vec<ExecuteResult>> {
+        self.shards.iter_mut().map(|shard| shard.execute(sql)).collect()
+    }
+
+    /// Insert a row into the shard its key column value hashes to, like
+    /// `Database::insert_direct`. Returns the shard and the row ID there.
+    pub fn insert(&mut self, table_name: &str, vector: impl IntoVector, metadata: Vec<(&str, Value)>) -> Result<(usize, u64)> {
+        let key = metadata.iter()
+            .find(|(column, _)| *column == self.key_column)
+            .map(|(_, value)| value)
+            .ok_or_else(|| MarsError::ConstraintViolation(format!("missing shard key column '{}'", self.key_column)))?;
+        let shard = self.shard_for(key);
+        let id = self.shards[shard].insert_direct(table_name, vector, metadata)?;
+        Ok((shard, id))
+    }
+
+    /// Nearest `k` rows over all shards, closest first. Each shard is
+    /// searched for its own top `k` and the lists are merged by distance.
+    pub fn search_similar(&self, table_name: &str, query: &[f32], k: usize, ef_search: usize) -> Result<Vec<ShardedResult>> {
+        let mut results = Vec::new();
+        for (shard, db) in self.shards.iter().enumerate() {
+            results.extend(db.search_similar(table_name, query, k, ef_search)?
+                .into_iter()
+                .map(|(id, values, distance)| (shard, id, values, distance)));
+        }
+        results.sort_by(|a, b| a.3.total_cmp(&b.3).then(a.0.cmp(&b.0)).then(a.1.cmp(&b.1)));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    /// Number of rows in a table across all shards
+    pub fn count(&self, table_name: &str) -> Result<usize> {
+        self.shards.iter()
+            .map(|db| db.get_table(table_name).map(|t| t.len()).ok_or_else(|| MarsError::TableNotFound(table_name.to_string())))
+            .sum()
+    }
+
+    /// Save every shard to its file
+    pub fn save(&self) -> Result<()> {
+        self.shards.iter().try_for_each(Database::save)
+    }
+}
+
+/// Index of a shard file name such as `shard-3.pardus`
+fn shard_index(file_name: &str) -> Option<usize> {
+    file_name.strip_prefix("shard-")?
+        .strip_suffix(SHARD_EXTENSION)?
+        .strip_suffix('.')?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routing_is_stable_and_spread() {
+        let db = ShardedDatabase::in_memory(4, "doc");
+        let mut counts = [0; 4];
+        for i in 0..400 {
+            let key = Value::Text(format!("doc-{}", i));
+            let shard = db.shard_for(&key);
+            assert_eq!(shard, db.shard_for(&key));
+            counts[shard] += 1;
+        }
+        assert!(counts.iter().all(|&n| n > 50), "{:?}", counts);
+
+        // Routing hashes a fixed byte encoding, so these never move
+        assert_eq!(db.shard_for(&Value::Text("doc-1".into())), 3);
+        assert_eq!(db.shard_for(&Value::Integer(42)), 0);
+    }
+
+    #[test]
+    fn test_shard_index() {
+        assert_eq!(shard_index("shard-12.pardus"), Some(12));
+        assert_eq!(shard_index("shard-1.pardus-lock"), None);
+        assert_eq!(shard_index("shard-x.pardus"), None);
+        assert_eq!(shard_index("other.pardus"), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! Column statistics collected by `ANALYZE`.
+//!
+//! For every scalar column, `ANALYZE docs` records the number of NULLs, an
+//! estimate of the number of distinct values and, for numeric columns, an
+//! equi-depth histogram. The statistics are saved with the table and used by
+//! the planner to estimate how many rows a condition keeps. They are a
+//! snapshot: they are not updated as rows change, only by the next ANALYZE.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::schema::{ColumnType, Value};
+use crate::sketch::HyperLogLog;
+use crate::table::Table;
+
+/// Number of buckets in a numeric histogram
+const HISTOGRAM_BUCKETS: usize = 32;
+
+/// HyperLogLog precision for distinct counts (about 1.6% error)
+const DISTINCT_PRECISION: u8 = 12;
+
+/// Statistics of one column
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub nulls: usize,
+    /// Estimated number of distinct non-NULL values, compared with the
+    /// column's collation
+    pub distinct: usize,
+    /// Bucket boundaries of an equi-depth histogram over the numeric values:
+    /// each pair of neighbors holds about the same number of values. Empty
+    /// for non-numeric columns.
+    pub histogram: Vec<f64>,
+}
+
+/// Statistics of a table, as of its last ANALYZE
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableStats {
+    /// Live rows when the table was analyzed
+    pub rows: usize,
+    pub columns: BTreeMap<String, ColumnStats>,
+}
+
+impl TableStats {
+    /// Collect statistics over the live rows of a table. Vector and blob
+    /// columns are skipped.
+    pub fn collect(table: &Table) -> Self {
+        let mut columns = BTreeMap::new();
+        for (idx, column) in table.schema.columns.iter().enumerate() {
+            if matches!(column.data_type,
+                ColumnType::Vector(_) | ColumnType::SparseVector | ColumnType::MultiVector(_) | ColumnType::Blob)
+            {
+                continue;
+            }
+            let mut nulls = 0;
+            let mut distinct = HyperLogLog::new(DISTINCT_PRECISION);
+            let mut numbers = Vec::new();
+            for row in table.rows.values() {
+                let value = &row.values[idx];
+                match value {
+                    Value::Null => nulls += 1,
+                    Value::Integer(n) => numbers.push(*n as f64),
+                    Value::Float(f) if !f.is_nan() => numbers.push(*f),
+                    _ => {}
+                }
+                if !matches!(value, Value::Null) {
+                    distinct.insert(&table.collation_key(idx, value));
+                }
+            }
+            let values = table.len() - nulls;
+            let distinct = if values == 0 { 0 } else { (distinct.estimate().round() as usize).clamp(1, values) };
+            columns.insert(column.name.clone(), ColumnStats { nulls, distinct, histogram: histogram(numbers) });
+        }
+        TableStats { rows: table.len(), columns }
+    }
+}
+
+impl ColumnStats {
+    /// Fraction of rows that are NULL
+    pub fn null_fraction(&self, rows: usize) -> f64 {
+        if rows == 0 { 0.0 } else { self.nulls as f64 / rows as f64 }
+    }
+
+    /// Fraction of rows equal to one value
+    pub fn equal_fraction(&self, rows: usize) -> f64 {
+        if self.distinct == 0 { 0.0 } else { (1.0 - self.null_fraction(rows)) / self.distinct as f64 }
+    }
+
+    /// Fraction of rows below `value` (at or below when `inclusive`), or
+    /// None without a histogram
+    pub fn below_fraction(&self, rows: usize, value: f64, inclusive: bool) -> Option<f64> {
+        let (&first, &last) = (self.histogram.first()?, self.histogram.last()?);
+        let fraction = if value < first || (value == first && !inclusive) {
+            0.0
+        } else if value > last || (value == last && inclusive) {
+            1.0
+        } else {
+            // Whole buckets below the value, then a linear share of the
+            // bucket containing it. Repeated boundaries mark a common value,
+            // whose buckets count only when the value itself is included.
+            let buckets = self.histogram.len() - 1;
+            let bucket = self.histogram.windows(2)
+                .position(|w| if inclusive { value < w[1] } else { value <= w[1] })
+                .unwrap_or(buckets - 1);
+            let (low, high) = (self.histogram[bucket], self.histogram[bucket + 1]);
+            let within = if high > low { (value - low) / (high - low) } else { 0.5 };
+            (bucket as f64 + within) / buckets as f64
+        };
+        Some(fraction * (1.0 - self.null_fraction(rows)))
+    }
+}
+
+/// Equi-depth bucket boundaries of some numbers
+fn histogram(mut numbers: Vec<f64>) -> Vec<f64> {
+    if numbers.is_empty() {
+        return Vec::new();
+    }
+    numbers.sort_by(f64::total_cmp);
+    let buckets = HISTOGRAM_BUCKETS.min(numbers.len());
+    (0..=buckets)
+        .map(|i| numbers[(i * (numbers.len() - 1)) / buckets])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::GraphConfig;
+    use crate::schema::Schema;
+
+    #[test]
+    fn test_collects_column_statistics() {
+        let schema = Schema::new("docs")
+            .column("embedding", ColumnType::Vector(2))
+            .column("category", ColumnType::Text)
+            .column("score", ColumnType::Integer);
+        let mut table = Table::new(schema, GraphConfig::default()).unwrap();
+        for i in 0..100 {
+            let category = if i % 10 == 0 { Value::Null } else { Value::Text(format!("c{}", i % 4)) };
+            table.insert_row(vec![Value::Vector(vec![i as f32, 0.0]), category, Value::Integer(i)]).unwrap();
+        }
+
+        let stats = TableStats::collect(&table);
+        assert_eq!(stats.rows, 100);
+        assert!(!stats.columns.contains_key("embedding"));
+        let category = &stats.columns["category"];
+        assert_eq!((category.nulls, category.distinct), (10, 4));
+        assert!(category.histogram.is_empty());
+        assert!((category.equal_fraction(100) - 0.225).abs() < 1e-9);
+
+        let score = &stats.columns["score"];
+        assert_eq!(score.distinct, 100);
+        assert_eq!((score.histogram[0], score.histogram[HISTOGRAM_BUCKETS]), (0.0, 99.0));
+        assert_eq!(score.below_fraction(100, -1.0, true), Some(0.0));
+        assert_eq!(score.below_fraction(100, 99.0, true), Some(1.0));
+        let quarter = score.below_fraction(100, 25.0, false).unwrap();
+        assert!((quarter - 0.25).abs() < 0.02, "{}", quarter);
+    }
+}
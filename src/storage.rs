@@ -1,10 +1,11 @@
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use memmap2::MmapMut;
+use memmap2::{Mmap, MmapMut};
 
 use crate::error::{MarsError, Result};
+use crate::schema::Value;
 
 /// Magic bytes for file format identification
 const MAGIC: &[u8; 4] = b"MARS";
@@ -129,9 +130,78 @@ impl IndexEntry {
     }
 }
 
+/// How hard saves and syncs work to get data onto stable storage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Synchronous {
+    /// Leave flushing to the OS; a crash may lose recent saves
+    Off,
+    /// fdatasync the file after each save
+    #[default]
+    Normal,
+    /// fsync the file and its metadata, then the directory holding it, so
+    /// a newly created file also survives a power loss
+    Full,
+}
+
+impl Synchronous {
+    /// Parse OFF/NORMAL/FULL or SQLite's 0/1/2
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Integer(0) => Some(Synchronous::Off),
+            Value::Integer(1) => Some(Synchronous::Normal),
+            Value::Integer(2) => Some(Synchronous::Full),
+            Value::Text(name) => match name.to_uppercase().as_str() {
+                "OFF" => Some(Synchronous::Off),
+                "NORMAL" => Some(Synchronous::Normal),
+                "FULL" => Some(Synchronous::Full),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+        }
+    }
+
+    /// Flush a file just written at `path` as this mode asks
+    pub(crate) fn sync_file(self, file: &File, path: &Path) -> Result<()> {
+        match self {
+            Synchronous::Off => {}
+            Synchronous::Normal => file.sync_data()?,
+            Synchronous::Full => {
+                file.sync_all()?;
+                sync_dir(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// fsync the directory holding `path`, making its entry durable. Skipped
+/// on Windows, where directories cannot be opened as files.
+fn sync_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
 /// Storage manager for persistence
 pub struct Storage {
     file: File,
+    path: PathBuf,
     header: Header,
     mmap: Option<MmapMut>,
 }
@@ -144,12 +214,13 @@ impl Storage {
             .write(true)
             .create(true)
             .truncate(true)
-            .open(path)?;
+            .open(path.as_ref())?;
 
         let header = Header::new(dimension);
 
         let mut storage = Storage {
             file,
+            path: path.as_ref().to_path_buf(),
             header,
             mmap: None,
         };
@@ -165,7 +236,7 @@ impl Storage {
         let mut file = OpenOptions::new()
             .read(true)
             .write(true)
-            .open(path)?;
+            .open(path.as_ref())?;
 
         let mut header_buf = vec![0u8; Header::SIZE];
         file.read_exact(&mut header_buf)?;
@@ -177,6 +248,7 @@ impl Storage {
 
         Ok(Storage {
             file,
+            path: path.as_ref().to_path_buf(),
             header,
             mmap,
         })
@@ -245,11 +317,213 @@ impl Storage {
         Ok(())
     }
 
-    /// Sync data to disk
-    pub fn sync(&self) -> Result<()> {
-        self.file.sync_all()?;
+    /// Sync data to disk as `mode` asks
+    pub fn sync(&self, mode: Synchronous) -> Result<()> {
+        mode.sync_file(&self.file, &self.path)
+    }
+}
+
+/// Magic bytes of a vector file
+const VECTOR_MAGIC: &[u8; 4] = b"PVEC";
+
+/// Vector file header: magic + dimension + sketch range + slots in use
+const VECTOR_HEADER_SIZE: usize = 4 + 4 + 4 + 8;
+
+/// Slots the file grows by at least, so that growing it and remapping is
+/// rare
+const VECTOR_MIN_GROWTH: usize = 1024;
+
+/// Fixed-size slots of f32 vectors addressed by node ID, read through a
+/// memory map so that only the pages touched stay in RAM.
+/// Each slot is a live flag byte followed by `dimension` little-endian f32s.
+/// The file grows ahead of the slots in use and is remapped as it grows,
+/// so reads never change the mapping.
+pub struct VectorFile {
+    file: File,
+    path: PathBuf,
+    dimension: usize,
+    /// Range the `DiskVectorDB` sketches of these vectors assume
+    range: f32,
+    slots: usize,
+    /// Slots the file has room for
+    capacity: usize,
+    mmap: Option<Mmap>,
+}
+
+impl VectorFile {
+    /// Create a new, empty vector file
+    pub fn create<P: AsRef<Path>>(path: P, dimension: usize, range: f32) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path.as_ref())?;
+
+        let mut vectors = VectorFile { file, path: path.as_ref().to_path_buf(), dimension, range, slots: 0, capacity: 0, mmap: None };
+        vectors.write_header()?;
+        Ok(vectors)
+    }
+
+    /// Open an existing vector file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut header = [0u8; VECTOR_HEADER_SIZE];
+        file.read_exact(&mut header)
+            .map_err(|_| MarsError::InvalidFormat("Vector file too small".into()))?;
+        if &header[..4] != VECTOR_MAGIC {
+            return Err(MarsError::InvalidFormat("Not a vector file".into()));
+        }
+        let dimension = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let range = f32::from_le_bytes(header[8..12].try_into().unwrap());
+        let slots = u64::from_le_bytes(header[12..20].try_into().unwrap()) as usize;
+
+        let len = file.metadata()?.len() as usize;
+        let slot_size = 1 + dimension * std::mem::size_of::<f32>();
+        let capacity = (len - VECTOR_HEADER_SIZE) / slot_size;
+        if !(range.is_finite() && range > 0.0) || slots > capacity {
+            return Err(MarsError::InvalidFormat("Corrupted vector file header".into()));
+        }
+
+        let mut vectors = VectorFile { file, path: path.as_ref().to_path_buf(), dimension, range, slots, capacity, mmap: None };
+        vectors.remap()?;
+        Ok(vectors)
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn range(&self) -> f32 {
+        self.range
+    }
+
+    /// Change the sketch range; only allowed while the file is empty
+    pub fn set_range(&mut self, range: f32) -> Result<()> {
+        if !(range.is_finite() && range > 0.0) {
+            return Err(MarsError::InvalidConfig(format!("Invalid sketch range: {}", range)));
+        }
+        if self.slots > 0 {
+            return Err(MarsError::InvalidConfig("The sketch range can't change once vectors are stored".into()));
+        }
+        self.range = range;
+        self.write_header()
+    }
+
+    /// Number of slots, live or not
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    fn slot_size(&self) -> usize {
+        1 + self.dimension * std::mem::size_of::<f32>()
+    }
+
+    fn slot_offset(&self, id: u32) -> u64 {
+        (VECTOR_HEADER_SIZE + id as usize * self.slot_size()) as u64
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let mut header = Vec::with_capacity(VECTOR_HEADER_SIZE);
+        header.extend_from_slice(VECTOR_MAGIC);
+        header.extend_from_slice(&(self.dimension as u32).to_le_bytes());
+        header.extend_from_slice(&self.range.to_le_bytes());
+        header.extend_from_slice(&(self.slots as u64).to_le_bytes());
+        self.file.seek(std::io::SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        Ok(())
+    }
+
+    /// Write `vector` into slot `id`, growing the file as needed
+    pub fn put(&mut self, id: u32, vector: &[f32]) -> Result<()> {
+        if vector.len() != self.dimension {
+            return Err(MarsError::DimensionMismatch { expected: self.dimension, actual: vector.len() });
+        }
+
+        if id as usize >= self.capacity {
+            let capacity = (id as usize + 1).max(self.capacity * 2).max(VECTOR_MIN_GROWTH);
+            self.file.set_len(self.slot_offset(capacity as u32))?;
+            self.capacity = capacity;
+            self.remap()?;
+        }
+
+        let mut buffer = Vec::with_capacity(self.slot_size());
+        buffer.push(1u8);
+        for &value in vector {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+
+        self.file.seek(std::io::SeekFrom::Start(self.slot_offset(id)))?;
+        self.file.write_all(&buffer)?;
+        if id as usize >= self.slots {
+            self.slots = id as usize + 1;
+            self.write_header()?;
+        }
+        Ok(())
+    }
+
+    /// Mark slot `id` as free; its vector is no longer returned
+    pub fn remove(&mut self, id: u32) -> Result<()> {
+        if id as usize >= self.slots {
+            return Ok(());
+        }
+        self.file.seek(std::io::SeekFrom::Start(self.slot_offset(id)))?;
+        self.file.write_all(&[0u8])?;
         Ok(())
     }
+
+    /// Read the vector in slot `id`, or None for free and unknown slots
+    pub fn get(&self, id: u32) -> Result<Option<Vec<f32>>> {
+        if id as usize >= self.slots {
+            return Ok(None);
+        }
+
+        let start = self.slot_offset(id) as usize;
+        let slot = match &self.mmap {
+            Some(mmap) => &mmap[start..start + self.slot_size()],
+            None => return Ok(None),
+        };
+        if slot[0] == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(slot[1..].chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect()))
+    }
+
+    /// Fault in every page of the file: the OS is asked to read the mapping
+    /// ahead where supported, then each page is touched. Returns the bytes
+    /// mapped.
+    pub fn prefetch(&self) -> Result<usize> {
+        let Some(mmap) = &self.mmap else {
+            return Ok(0);
+        };
+        #[cfg(unix)]
+        mmap.advise(memmap2::Advice::WillNeed)?;
+        let checksum = mmap.iter().step_by(4096).fold(0u8, |sum, &b| sum.wrapping_add(b));
+        std::hint::black_box(checksum);
+        Ok(mmap.len())
+    }
+
+    fn remap(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.mmap = if self.capacity == 0 {
+            None
+        } else {
+            Some(unsafe { Mmap::map(&self.file)? })
+        };
+        Ok(())
+    }
+
+    /// Sync data to disk as `mode` asks
+    pub fn sync(&self, mode: Synchronous) -> Result<()> {
+        mode.sync_file(&self.file, &self.path)
+    }
 }
 
 #[cfg(test)]
@@ -272,6 +546,20 @@ mod tests {
         assert_eq!(deserialized.dimension, 128);
     }
 
+    #[test]
+    fn test_synchronous_modes() {
+        let temp = NamedTempFile::new().unwrap();
+        let storage = Storage::create(temp.path(), 8).unwrap();
+        for mode in [Synchronous::Off, Synchronous::Normal, Synchronous::Full] {
+            storage.sync(mode).unwrap();
+        }
+        assert_eq!(Synchronous::from_value(&Value::Integer(2)), Some(Synchronous::Full));
+        assert_eq!(Synchronous::from_value(&Value::Text("off".into())), Some(Synchronous::Off));
+
+        // A bare file name syncs the current directory
+        assert!(sync_dir(Path::new("pardus.db")).is_ok());
+    }
+
     #[test]
     fn test_storage_create() {
         let temp = NamedTempFile::new().unwrap();
@@ -292,4 +580,31 @@ mod tests {
         let read = storage.read_centroid().unwrap();
         assert_eq!(read, centroid);
     }
+
+    #[test]
+    fn test_vector_file() {
+        let temp = NamedTempFile::new().unwrap();
+        let mut vectors = VectorFile::create(temp.path(), 3, 1.0).unwrap();
+        vectors.set_range(2.0).unwrap();
+
+        vectors.put(0, &[1.0, 2.0, 3.0]).unwrap();
+        vectors.put(2, &[7.0, 8.0, 9.0]).unwrap();
+        assert_eq!(vectors.get(0).unwrap(), Some(vec![1.0, 2.0, 3.0]));
+        assert_eq!(vectors.get(1).unwrap(), None);
+        assert!(vectors.put(3, &[1.0]).is_err());
+
+        vectors.put(0, &[4.0, 5.0, 6.0]).unwrap();
+        vectors.remove(2).unwrap();
+        assert!(vectors.set_range(1.0).is_err());
+        drop(vectors);
+
+        let vectors = VectorFile::open(temp.path()).unwrap();
+        assert_eq!(vectors.dimension(), 3);
+        assert_eq!(vectors.range(), 2.0);
+        assert_eq!(vectors.slots(), 3);
+        assert_eq!(vectors.prefetch().unwrap(), VECTOR_HEADER_SIZE + VECTOR_MIN_GROWTH * 13);
+        assert_eq!(vectors.get(3).unwrap(), None);
+        assert_eq!(vectors.get(0).unwrap(), Some(vec![4.0, 5.0, 6.0]));
+        assert_eq!(vectors.get(2).unwrap(), None);
+    }
 }
@@ -1,47 +1,479 @@
 use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::distance::{Distance, Euclidean};
+use serde::{Deserialize, Serialize};
+
+use crate::columnar::ColumnStore;
+use crate::compression::DEFAULT_COMPRESS_TEXT;
+use crate::distance::{lookup_metric, max_sim, normalize, sparse_dot, Cosine, Distance, DynamicMetric, Euclidean, UnitCosine};
 use crate::error::{MarsError, Result};
-use crate::graph::{Graph, GraphConfig};
-use crate::node::NodeId;
-use crate::parser::{BoolConnector, ComparisonOp, ConditionValue, OrderBy, SelectColumn, WhereClause};
-use crate::schema::{Column, ColumnType, Row, Schema, Value};
+use crate::functions::{self, ScalarExpr};
+use crate::interrupt::interrupted;
+use crate::graph::{EdgeStats, Graph, GraphConfig, GraphStats};
+use crate::node::{Candidate, NodeId};
+use crate::parser::{AlterAction, BoolConnector, ComparisonOp, Condition, ConditionValue, Diversity, OnConflict, OrderBy, SelectColumn, VectorStorage, WhereClause};
+use crate::planner::{self, AccessPath, QueryPlan};
+use crate::projection::{Projection, ProjectionMethod};
+use crate::quantize::{self, CalibrationReport, Quantizer};
+use crate::schema::{AutoIncrement, Collation, Column, ColumnType, ForeignKey, Mask, OnDelete, ResultColumn, ResultSchema, Row, Schema, Value};
+use crate::stats::TableStats;
+use crate::uuid::Uuid;
+use crate::view::{MaterializedView, ViewDef};
 
 /// A table in the database containing vectors and metadata
 pub struct Table {
     pub schema: Schema,
-    pub graph: Graph<f32, Euclidean>,
+    /// Vector index, searched with the table's metric function
+    pub graph: Graph<f32, DynamicMetric>,
+    /// Name of the distance metric (see `distance::lookup_metric`)
+    metric: String,
+    /// L2-normalize vectors on insert and search with `UnitCosine`
+    normalize: bool,
     pub(crate) rows: HashMap<u64, Row>,
     pub(crate) next_id: u64,
-    /// Unique constraint indexes: column_name -> set of values
-    unique_indexes: HashMap<String, HashSet<String>>,  // Store values as strings for hashing
+    /// Unique constraint indexes: column_name -> collation key -> live row
+    /// id. Also serve `col = value` lookups.
+    unique_indexes: HashMap<String, HashMap<String, u64>>,
+    /// Insert/delete timestamps per row, used for AS OF queries
+    pub(crate) history: HashMap<u64, RowHistory>,
+    /// Soft-deleted rows, kept so they can still be read with AS OF
+    pub(crate) tombstones: HashMap<u64, Row>,
+    /// Row ID -> graph node ID
+    node_ids: HashMap<u64, NodeId>,
+    /// Graph node ID -> row ID
+    row_ids: HashMap<NodeId, u64>,
+    /// Columnar copy of numeric columns (`STORAGE COLUMNAR`)
+    columnar: Option<ColumnStore>,
+    /// Column name -> how restricted connections see it
+    masks: HashMap<String, Mask>,
+    /// Materialized aggregate views over this table, kept up to date on
+    /// every row change
+    views: Vec<MaterializedView>,
+    /// Column statistics from the last ANALYZE
+    stats: Option<TableStats>,
+    /// REFERENCES constraints of this table's columns, enforced by the
+    /// database since they span tables
+    foreign_keys: Vec<ForeignKey>,
+    /// How new rows are numbered; None counts 1, 2, 3, ...
+    auto_increment: Option<AutoIncrement>,
+    /// TEXT values at least this many bytes long are compressed when the
+    /// table is saved; 0 turns compression off
+    compress_text: usize,
+    /// Earlier versions of each row kept for AS OF queries; 0 keeps none
+    keep_history: usize,
+    /// TEXT EXTERNAL columns, saved in a text file next to the database
+    /// file rather than in it
+    external_columns: Vec<String>,
+    /// Scale and offset of INT8 vector storage; None stores full precision
+    quantizer: Option<Quantizer>,
+    /// Maps vectors to the smaller ones stored and indexed
+    projection: Option<Projection>,
+    /// Bulk load mode: new vectors are left out of the graph until
+    /// `build_deferred_index`
+    deferred_index: bool,
+}
+
+/// Estimated heap memory of a table, in bytes. Vector columns count under
+/// `vectors`, other values and per-row bookkeeping under `rows`, and the
+/// vector index under `graph`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub rows: usize,
+    pub vectors: usize,
+    pub graph: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.rows + self.vectors + self.graph
+    }
+}
+
+impl std::ops::AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.rows += other.rows;
+        self.vectors += other.vectors;
+        self.graph += other.graph;
+    }
+}
+
+/// Per-row overhead: the row itself, its history entry and map slots
+const ROW_OVERHEAD: usize = size_of::<Row>() + size_of::<RowHistory>() + 4 * size_of::<u64>();
+
+/// Sampled vectors searched for when estimating the recall of INT8 storage
+const CALIBRATION_QUERIES: usize = 100;
+/// k of the INT8 recall estimate
+const CALIBRATION_K: usize = 10;
+
+/// Candidates kept per result by the truncated first stage of
+/// `USING DIMS`, before re-ranking them on full vectors
+const DIMS_OVERSAMPLE: usize = 4;
+
+/// Neighbors of each row searched for near-duplicates
+const DEDUPE_CANDIDATES: usize = 32;
+
+/// Projected row values as a DISTINCT key. Equality is `Value`'s, so a
+/// NaN float never matches another row.
+#[derive(PartialEq, Hash)]
+struct DistinctKey(Vec<Value>);
+
+impl Eq for DistinctKey {}
+
+/// Lifetime of a row in unix milliseconds, its update counter and the
+/// values updates replaced
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RowHistory {
+    pub created_at: i64,
+    pub deleted_at: Option<i64>,
+    /// Starts at 1 and is bumped on every update, for optimistic concurrency
+    pub version: u64,
+    /// Earlier values of the row, oldest first, for AS OF queries. Only
+    /// kept on tables with `keep_history` set.
+    pub previous: Vec<RowVersion>,
+}
+
+/// Values a row held until an update replaced them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RowVersion {
+    /// When the update happened, in unix milliseconds
+    pub replaced_at: i64,
+    pub values: Vec<Value>,
+}
+
+impl RowHistory {
+    /// Check if the row existed at the given point in time
+    pub fn visible_at(&self, as_of: i64) -> bool {
+        self.created_at <= as_of && self.deleted_at.is_none_or(|d| d > as_of)
+    }
+
+    /// Values the row held at `as_of`, given its current ones
+    pub fn values_at<'a>(&'a self, as_of: i64, current: &'a [Value]) -> &'a [Value] {
+        self.previous.iter()
+            .find(|v| v.replaced_at > as_of)
+            .map_or(current, |v| &v.values)
+    }
+}
+
+/// LIMIT, OFFSET, ORDER BY and DISTINCT of a SELECT
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SelectOptions<'a> {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub order_by: Option<&'a OrderBy>,
+    pub distinct: bool,
+}
+
+/// Position in a similarity result list: the last row of a page.
+/// Results are ordered by (distance, row id), so resuming strictly after
+/// the cursor never repeats a row.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchCursor {
+    pub distance: f32,
+    pub id: u64,
+}
+
+impl SearchCursor {
+    /// Check if a result at (`distance`, `id`) comes after this cursor
+    pub fn precedes(&self, distance: f32, id: u64) -> bool {
+        distance.total_cmp(&self.distance).then(id.cmp(&self.id)).is_gt()
+    }
+}
+
+/// Result of a recall self-check: graph searches for sampled stored
+/// vectors compared with exact searches
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RecallReport {
+    /// Vectors sampled as queries
+    pub queries: usize,
+    pub k: usize,
+    /// Mean recall@k. A graph result counts as a hit when it is no farther
+    /// than the exact k-th result, so ties are not penalized.
+    pub recall: f64,
+    /// Worst recall@k of a single query
+    pub min_recall: f64,
+    /// Mean graph search time per query
+    pub graph_latency: Duration,
+    /// Mean exact search time per query
+    pub exact_latency: Duration,
+}
+
+/// Nodes (row id, label) and edges (row id pairs) of a table's vector index
+type GraphTopology = (Vec<(u64, Option<String>)>, Vec<(u64, u64)>);
+
+/// A string as a double-quoted literal, escaped for JSON (and DOT)
+fn quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// One page of similarity results as (row id, values, distance)
+#[derive(Clone, Debug)]
+pub struct SearchPage {
+    pub results: Vec<(u64, Vec<Value>, f32)>,
+    /// Pass to the next call to continue; `None` once results run out
+    pub next: Option<SearchCursor>,
+}
+
+/// Best matches sharing one value of the grouping column
+#[derive(Clone, Debug)]
+pub struct SearchGroup {
+    pub key: Value,
+    /// (row id, values, distance), closest first
+    pub results: Vec<(u64, Vec<Value>, f32)>,
+}
+
+/// A row whose vector lies within the threshold of an earlier row's
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Duplicate {
+    /// The earlier row, which dedupe keeps
+    pub kept: u64,
+    pub duplicate: u64,
+    pub distance: f32,
+}
+
+/// A row for `Database::ingest`: the vector and (column, value) metadata
+pub type IngestRow<'m> = (Vec<f32>, Vec<(&'m str, Value)>);
+
+/// The (column, value) assignments an UPDATE makes to one row
+pub(crate) type RowUpdate = (u64, Vec<(String, Value)>);
+
+/// Assignments by column index (None for unknown columns), values as stored
+type IndexedAssignments = Vec<(Option<usize>, Value)>;
+
+/// Rows validated by `Table::prepare_segment`, with a graph of their vectors
+pub(crate) struct Segment {
+    pub(crate) rows: Vec<Vec<Value>>,
+    graph: Graph<f32, DynamicMetric>,
+}
+
+/// Current time in unix milliseconds
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
 }
 
 impl Table {
     pub fn new(schema: Schema, config: GraphConfig) -> Result<Self> {
-        let dimension = schema.get_vector_dimension()
-            .ok_or_else(|| MarsError::InvalidConfig("Table must have a VECTOR column".into()))?;
+        Self::with_metric(schema, config, "euclidean")
+    }
+
+    /// Create a table whose vector index uses a built-in or registered metric
+    pub fn with_metric(schema: Schema, config: GraphConfig, metric: &str) -> Result<Self> {
+        let metric_fn = lookup_metric(metric)
+            .ok_or_else(|| MarsError::InvalidConfig(format!("Unknown metric '{}'", metric)))?;
+
+        // Sparse/multi-vector-only tables have no dense index; the graph just stays empty
+        let dimension = match schema.get_vector_dimension() {
+            Some(dim) => dim,
+            None if schema.has_unindexed_vector() => 0,
+            None => return Err(MarsError::InvalidConfig(
+                "Table must have a VECTOR, VECTOR MULTI or SPARSE_VECTOR column".into()
+            )),
+        };
 
         // Initialize unique indexes for columns with UNIQUE constraint
-        let unique_indexes: HashMap<String, HashSet<String>> = schema.columns.iter()
+        let unique_indexes: HashMap<String, HashMap<String, u64>> = schema.columns.iter()
             .filter(|c| c.unique)
-            .map(|c| (c.name.clone(), HashSet::new()))
+            .map(|c| (c.name.clone(), HashMap::new()))
             .collect();
 
         Ok(Table {
             schema,
-            graph: Graph::new(dimension, config),
+            graph: Graph::with_metric(dimension, config, metric_fn),
+            metric: metric.to_lowercase(),
+            normalize: false,
             rows: HashMap::new(),
             next_id: 1,
             unique_indexes,
+            history: HashMap::new(),
+            tombstones: HashMap::new(),
+            node_ids: HashMap::new(),
+            row_ids: HashMap::new(),
+            columnar: None,
+            masks: HashMap::new(),
+            views: Vec::new(),
+            stats: None,
+            foreign_keys: Vec::new(),
+            auto_increment: None,
+            compress_text: DEFAULT_COMPRESS_TEXT,
+            keep_history: 0,
+            external_columns: Vec::new(),
+            quantizer: None,
+            projection: None,
+            deferred_index: false,
         })
     }
 
+    /// Create a table with no vector column and an empty index, for
+    /// results such as materialized view contents
+    pub(crate) fn without_index(schema: Schema) -> Self {
+        Table {
+            unique_indexes: HashMap::new(),
+            graph: Graph::with_metric(0, GraphConfig::default(), Euclidean::compute),
+            metric: "euclidean".into(),
+            normalize: false,
+            rows: HashMap::new(),
+            next_id: 1,
+            history: HashMap::new(),
+            tombstones: HashMap::new(),
+            node_ids: HashMap::new(),
+            row_ids: HashMap::new(),
+            columnar: None,
+            masks: HashMap::new(),
+            views: Vec::new(),
+            stats: None,
+            foreign_keys: Vec::new(),
+            auto_increment: None,
+            compress_text: DEFAULT_COMPRESS_TEXT,
+            keep_history: 0,
+            external_columns: Vec::new(),
+            quantizer: None,
+            projection: None,
+            deferred_index: false,
+            schema,
+        }
+    }
+
     /// Get the table name
     pub fn name(&self) -> &str {
         &self.schema.name
     }
 
+    /// Start the table in bulk load mode (see `defer_index`)
+    pub fn with_deferred_index(mut self) -> Self {
+        self.deferred_index = true;
+        self
+    }
+
+    /// Enter bulk load mode: rows inserted from now on are stored without
+    /// touching the vector index, and similarity search does not find them
+    /// until `build_deferred_index` indexes them all at once
+    pub fn defer_index(&mut self) {
+        self.deferred_index = true;
+    }
+
+    /// Whether the table is in bulk load mode
+    pub fn is_index_deferred(&self) -> bool {
+        self.deferred_index
+    }
+
+    /// Leave bulk load mode, indexing every row whose vector is not in the
+    /// graph yet on up to `threads` threads. Returns the number of rows
+    /// indexed.
+    pub fn build_deferred_index(&mut self, threads: usize) -> usize {
+        self.deferred_index = false;
+        let mut ids: Vec<u64> = self.rows.keys()
+            .filter(|id| !self.node_ids.contains_key(id))
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        self.index_rows(&ids, threads)
+    }
+
+    /// Add the vectors of rows `ids` to the graph: `threads` workers each
+    /// build a segment graph over a share of the rows, and the segments are
+    /// then merged in. Rows without a valid vector are skipped. Returns the
+    /// number of rows indexed.
+    fn index_rows(&mut self, ids: &[u64], threads: usize) -> usize {
+        if self.schema.vector_column.is_none() || ids.is_empty() {
+            return 0;
+        }
+        let threads = threads.max(1);
+        let chunks: Vec<&[u64]> = ids.chunks(ids.len().div_ceil(threads)).collect();
+        let shared = &*self;
+        let segments: Vec<(Vec<u64>, Graph<f32, DynamicMetric>)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = chunks.into_iter()
+                .map(|chunk| scope.spawn(move || {
+                    let graph = &shared.graph;
+                    let mut segment = Graph::with_metric(graph.dimension(), graph.config().clone(), graph.metric());
+                    let mut indexed = Vec::with_capacity(chunk.len());
+                    for &id in chunk {
+                        if let Ok(vector) = shared.extract_vector(&shared.rows[&id].values) {
+                            segment.insert(vector);
+                            indexed.push(id);
+                        }
+                    }
+                    (indexed, segment)
+                }))
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+
+        let mut count = 0;
+        for (indexed, segment) in segments {
+            let node_ids = self.graph.merge(&segment, threads);
+            for (id, node_id) in indexed.into_iter().zip(node_ids) {
+                self.link_node(id, node_id);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Store vectors L2-normalized and compare them by dot product.
+    /// Only valid for empty cosine tables.
+    pub fn normalized(mut self) -> Result<Self> {
+        if self.metric != "cosine" {
+            return Err(MarsError::InvalidConfig("NORMALIZE requires METRIC cosine".into()));
+        }
+        if !self.rows.is_empty() {
+            return Err(MarsError::InvalidConfig("Cannot enable NORMALIZE on a non-empty table".into()));
+        }
+
+        self.graph = Graph::with_metric(self.graph.dimension(), self.graph.config().clone(), UnitCosine::compute);
+        self.normalize = true;
+        Ok(self)
+    }
+
+    /// Get the distance metric name
+    pub fn metric(&self) -> &str {
+        &self.metric
+    }
+
+    /// Check if vectors are normalized on insert
+    pub fn is_normalized(&self) -> bool {
+        self.normalize
+    }
+
+    /// Also keep INTEGER and FLOAT columns in columnar arrays, which WHERE
+    /// filters and aggregates scan instead of the rows.
+    /// Only valid for empty tables.
+    pub fn columnar(mut self) -> Result<Self> {
+        if !self.rows.is_empty() {
+            return Err(MarsError::InvalidConfig("Cannot enable STORAGE COLUMNAR on a non-empty table".into()));
+        }
+        self.columnar = Some(ColumnStore::new(&self.schema));
+        Ok(self)
+    }
+
+    /// Check if the table keeps a columnar copy of its numeric columns
+    pub fn is_columnar(&self) -> bool {
+        self.columnar.is_some()
+    }
+
+    /// The columnar copy of numeric columns, if enabled
+    pub fn column_store(&self) -> Option<&ColumnStore> {
+        self.columnar.as_ref()
+    }
+
     /// Get the number of rows
     pub fn len(&self) -> usize {
         self.rows.len()
@@ -52,6 +484,50 @@ impl Table {
         self.rows.is_empty()
     }
 
+    /// Estimated memory held by rows, tombstones and the vector index
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage { graph: self.graph.memory_usage(), ..Default::default() };
+        for row in self.rows.values().chain(self.tombstones.values()) {
+            usage += Self::values_memory(&row.values);
+        }
+        for version in self.history.values().flat_map(|h| &h.previous) {
+            usage += Self::values_memory(&version.values);
+        }
+        usage.rows += self.columnar.as_ref().map_or(0, ColumnStore::memory_usage);
+        usage
+    }
+
+    /// Estimated memory inserting a row with these values would add
+    pub fn row_memory(&self, values: &[Value]) -> MemoryUsage {
+        let mut usage = Self::values_memory(values);
+        usage.rows += self.columnar.as_ref().map_or(0, ColumnStore::row_memory);
+        let dimension = self.schema.columns.iter().find_map(|c| match c.data_type {
+            ColumnType::Vector(dim) if self.schema.vector_column.as_deref() == Some(c.name.as_str()) => Some(dim),
+            _ => None,
+        });
+        if let Some(dim) = dimension {
+            usage.graph = self.graph.node_memory(dim);
+        }
+        usage
+    }
+
+    fn values_memory(values: &[Value]) -> MemoryUsage {
+        let mut usage = MemoryUsage { rows: ROW_OVERHEAD + size_of_val(values), ..Default::default() };
+        for value in values {
+            match value {
+                Value::Vector(v) => usage.vectors += v.len() * size_of::<f32>(),
+                Value::SparseVector(v) => usage.vectors += v.len() * size_of::<(u32, f32)>(),
+                Value::MultiVector(vs) => {
+                    usage.vectors += vs.iter().map(|v| size_of::<Vec<f32>>() + v.len() * size_of::<f32>()).sum::<usize>();
+                }
+                Value::Text(s) => usage.rows += s.len(),
+                Value::Blob(b) => usage.rows += b.len(),
+                _ => {}
+            }
+        }
+        usage
+    }
+
     /// Insert a row with values
     pub fn insert(&mut self, columns: &[String], values: Vec<Value>) -> Result<u64> {
         // Validate and build complete row
@@ -61,32 +537,90 @@ impl Table {
 
     /// Insert a row with pre-built values (faster, no validation)
     pub fn insert_row(&mut self, mut row_values: Vec<Value>) -> Result<u64> {
+        self.normalize_vector(&mut row_values);
+
         // Check unique constraints before inserting
         self.check_unique_constraints(&row_values)?;
+        self.check_multi_vectors(&row_values)?;
 
-        // Auto-generate ID
-        let id = self.next_id;
-        self.next_id += 1;
+        let id = self.allocate_id();
+        self.assign_keys(id, &mut row_values);
 
-        // If there's an 'id' column, set it to the auto-generated ID
-        if let Some(idx) = self.column_index("id") {
-            row_values[idx] = Value::Integer(id as i64);
+        // Insert dense vector into graph
+        if self.schema.vector_column.is_some() {
+            let vector = self.extract_vector(&row_values)?;
+            if !self.deferred_index {
+                let node_id = self.graph.insert(vector);
+                self.link_node(id, node_id);
+            }
+        }
+
+        self.store_row(id, row_values, now_millis());
+        Ok(id)
+    }
+
+    /// Insert a row with pre-built values, resolving UNIQUE conflicts as
+    /// `on_conflict` says. Returns the id of the inserted or replaced row,
+    /// or None if the row was ignored. REPLACE overwrites the lowest
+    /// conflicting row and deletes any others.
+    pub(crate) fn insert_or(&mut self, row_values: Vec<Value>, on_conflict: OnConflict) -> Result<Option<u64>> {
+        let conflicts = match on_conflict {
+            OnConflict::Abort => Vec::new(),
+            OnConflict::Ignore | OnConflict::Replace => self.conflicting_rows(&row_values),
+        };
+        match (on_conflict, conflicts.split_first()) {
+            (OnConflict::Ignore, Some(_)) => Ok(None),
+            (OnConflict::Replace, Some((&id, others))) => {
+                self.overwrite_row(id, others, row_values)?;
+                Ok(Some(id))
+            }
+            _ => self.insert_row(row_values).map(Some),
         }
+    }
 
-        // Extract vector
-        let vector = self.extract_vector(&row_values)?;
+    /// Take the next row ID
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += match self.auto_increment {
+            Some(AutoIncrement::Sequence { step, .. }) => step,
+            _ => 1,
+        };
+        id
+    }
 
-        // Insert into graph
-        let _graph_id = self.graph.insert(vector);
+    /// Index of an INTEGER `id` column, which mirrors the row IDs
+    pub(crate) fn row_id_column(&self) -> Option<usize> {
+        self.column_index("id").filter(|&idx| self.schema.columns[idx].data_type == ColumnType::Integer)
+    }
 
-        // Update unique indexes
-        self.update_unique_indexes(&row_values);
+    /// Copy a new row's ID into the `id` column, if any, and fill a UUID
+    /// key column left NULL with a random UUID
+    fn assign_keys(&self, id: u64, row_values: &mut [Value]) {
+        if let Some(idx) = self.row_id_column() {
+            row_values[idx] = Value::Integer(id as i64);
+        }
+        if let Some(AutoIncrement::Uuid { column }) = &self.auto_increment
+            && let Some(idx) = self.column_index(column)
+            && matches!(row_values[idx], Value::Null)
+        {
+            let uuid = Uuid::new_v4();
+            row_values[idx] = match self.schema.columns[idx].data_type {
+                ColumnType::Uuid => Value::Uuid(uuid),
+                _ => Value::Text(uuid.to_string()),
+            };
+        }
+    }
 
-        // Create row
+    /// Record a new live row in the row store and its side indexes
+    fn store_row(&mut self, id: u64, row_values: Vec<Value>, created_at: i64) {
         let row = Row::new(id, row_values);
+        self.update_unique_indexes(&row);
+        if let Some(store) = &mut self.columnar {
+            store.insert(&row);
+        }
+        self.update_views(&row, 1);
         self.rows.insert(id, row);
-
-        Ok(id)
+        self.history.insert(id, RowHistory { created_at, deleted_at: None, version: 1, previous: Vec::new() });
     }
 
     /// Batch insert multiple rows for better performance.
@@ -96,171 +630,1808 @@ impl Table {
             return Ok(Vec::new());
         }
 
-        // Check all unique constraints first
+        // Check all unique constraints first, against stored rows and
+        // between the new ones
+        let mut batch_keys: HashSet<(usize, String)> = HashSet::new();
         for row_values in &rows {
             self.check_unique_constraints(row_values)?;
+            self.check_multi_vectors(row_values)?;
+            for col_name in self.unique_indexes.keys() {
+                let Some(col_idx) = self.column_index(col_name) else { continue };
+                let key = self.collation_key(col_idx, &row_values[col_idx]);
+                if key != "NULL" && !batch_keys.insert((col_idx, key)) {
+                    return Err(MarsError::ConstraintViolation(format!(
+                        "Duplicate value for UNIQUE column '{}'", col_name
+                    )));
+                }
+            }
         }
 
         let batch_size = rows.len();
-        let start_id = self.next_id;
-        let ids: Vec<u64> = (start_id..start_id + batch_size as u64).collect();
-        self.next_id += batch_size as u64;
+        let ids: Vec<u64> = (0..batch_size).map(|_| self.allocate_id()).collect();
 
-        // Prepare vectors and rows
-        let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(batch_size);
+        // Prepare rows; the graph copies vectors straight out of them
         let mut prepared_rows: Vec<(u64, Vec<Value>)> = Vec::with_capacity(batch_size);
 
         for (i, mut row_values) in rows.into_iter().enumerate() {
             let id = ids[i];
-
-            // If there's an 'id' column, set it to the auto-generated ID
-            if let Some(idx) = self.column_index("id") {
-                row_values[idx] = Value::Integer(id as i64);
-            }
-
-            // Extract vector
-            let vector = self.extract_vector(&row_values)?;
-            vectors.push(vector);
-
+            self.normalize_vector(&mut row_values);
+            self.assign_keys(id, &mut row_values);
             prepared_rows.push((id, row_values));
         }
 
         // Batch insert into graph
-        let _graph_ids = self.graph.insert_batch(vectors);
+        if self.schema.vector_column.is_some() {
+            let vectors = prepared_rows.iter()
+                .map(|(_, row_values)| self.extract_vector(row_values))
+                .collect::<Result<Vec<_>>>()?;
+            if !self.deferred_index {
+                let node_ids = self.graph.insert_batch(vectors);
+                for (&id, node_id) in ids.iter().zip(node_ids) {
+                    self.link_node(id, node_id);
+                }
+            }
+        }
 
         // Insert all rows and update unique indexes
+        let created_at = now_millis();
         for (id, row_values) in prepared_rows {
-            self.update_unique_indexes(&row_values);
-            let row = Row::new(id, row_values);
-            self.rows.insert(id, row);
+            self.store_row(id, row_values, created_at);
+        }
+
+        Ok(ids)
+    }
+
+    /// Validate rows for the direct insert APIs and index their vectors in
+    /// a standalone graph. Only reads the table, so several segments can be
+    /// prepared at once; `merge_segment` then adds them.
+    pub(crate) fn prepare_segment(&self, rows: Vec<IngestRow<'_>>) -> Result<Segment> {
+        let mut graph = Graph::with_metric(self.graph.dimension(), self.graph.config().clone(), self.graph.metric());
+        let rows = rows.into_iter()
+            .map(|(vector, metadata)| {
+                let mut row_values = self.direct_row_values(vector, metadata)?;
+                self.normalize_vector(&mut row_values);
+                self.check_multi_vectors(&row_values)?;
+                if self.schema.vector_column.is_some() {
+                    graph.insert(self.extract_vector(&row_values)?);
+                }
+                Ok(row_values)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Segment { rows, graph })
+    }
+
+    /// Add a prepared segment's rows, merging its graph into the vector
+    /// index on up to `threads` threads. Returns the new row IDs in order.
+    pub(crate) fn merge_segment(&mut self, segment: Segment, threads: usize) -> Result<Vec<u64>> {
+        for row_values in &segment.rows {
+            self.check_unique_constraints(row_values)?;
         }
 
+        let node_ids = if self.schema.vector_column.is_some() && !self.deferred_index {
+            self.graph.merge(&segment.graph, threads)
+        } else {
+            Vec::new()
+        };
+
+        let created_at = now_millis();
+        let mut ids = Vec::with_capacity(segment.rows.len());
+        for (i, mut row_values) in segment.rows.into_iter().enumerate() {
+            let id = self.allocate_id();
+            self.assign_keys(id, &mut row_values);
+            if let Some(&node_id) = node_ids.get(i) {
+                self.link_node(id, node_id);
+            }
+            self.store_row(id, row_values, created_at);
+            ids.push(id);
+        }
         Ok(ids)
     }
 
-    /// Select rows matching conditions
+    /// Copy the live rows of another table into a segment for
+    /// `merge_segment`. Columns are matched by name, and ones the source
+    /// lacks are NULL. The source's vector index is reused when it ranks
+    /// with the same metric, so merging skips re-inserting every vector.
+    pub(crate) fn segment_from(&self, source: &Table) -> Result<Segment> {
+        if let Some(column) = source.schema.columns.iter().find(|c| self.column_index(&c.name).is_none()) {
+            return Err(MarsError::ColumnNotFound(format!("{}.{}", self.name(), column.name)));
+        }
+        let mut mapping = Vec::with_capacity(self.schema.columns.len());
+        for column in &self.schema.columns {
+            let index = source.column_index(&column.name);
+            if let Some(i) = index
+                && source.schema.columns[i].data_type != column.data_type
+            {
+                return Err(MarsError::InvalidFormat(format!(
+                    "Column '{}' is {:?} in '{}' but {:?} in '{}'",
+                    column.name, source.schema.columns[i].data_type, source.name(), column.data_type, self.name(),
+                )));
+            }
+            mapping.push(index);
+        }
+        let copy = |row: &Row| -> Vec<Value> {
+            mapping.iter().map(|index| index.map_or(Value::Null, |i| row.values[i].clone())).collect()
+        };
+
+        let same_index = self.schema.vector_column.is_some()
+            && self.schema.vector_column == source.schema.vector_column
+            && self.metric == source.metric
+            && self.normalize == source.normalize
+            && self.quantizer == source.quantizer
+            && self.projection == source.projection;
+        if same_index {
+            let csr = source.graph.to_csr();
+            let indexed: Option<Vec<&Row>> = csr.ids.iter()
+                .map(|&node_id| source.node_row(node_id).and_then(|id| source.rows.get(&id)))
+                .collect();
+            if let Some(indexed) = indexed {
+                // Graph nodes line up with the first rows; rows without a
+                // vector follow
+                let mut rows: Vec<Vec<Value>> = indexed.into_iter().map(copy).collect();
+                let mut unindexed: Vec<&Row> = source.rows.values()
+                    .filter(|row| !source.node_ids.contains_key(&row.id))
+                    .collect();
+                unindexed.sort_by_key(|row| row.id);
+                rows.extend(unindexed.into_iter().map(copy));
+                let graph = Graph::from_csr(csr, self.graph.config().clone(), self.graph.metric());
+                return Ok(Segment { rows, graph });
+            }
+        }
+
+        let mut source_rows: Vec<&Row> = source.rows.values().collect();
+        source_rows.sort_by_key(|row| row.id);
+        let mut graph = Graph::with_metric(self.graph.dimension(), self.graph.config().clone(), self.graph.metric());
+        let rows = source_rows.into_iter()
+            .map(|row| {
+                let mut row_values = copy(row);
+                self.normalize_vector(&mut row_values);
+                self.check_multi_vectors(&row_values)?;
+                if self.schema.vector_column.is_some() {
+                    graph.insert(self.extract_vector(&row_values)?);
+                }
+                Ok(row_values)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Segment { rows, graph })
+    }
+
+    /// Select rows matching conditions. An empty `columns` slice selects
+    /// every column.
     pub fn select(
         &self,
-        columns: &[String],
+        columns: &[SelectColumn],
         where_clause: Option<&WhereClause>,
         limit: Option<usize>,
         offset: Option<usize>,
         order_by: Option<&OrderBy>,
         distinct: bool,
     ) -> Vec<Row> {
-        let mut results: Vec<&Row> = self.rows.values()
+        let results: Vec<&Row> = self.scan(where_clause)
+            .take(Self::scan_limit(limit, offset, order_by, distinct))
+            .collect();
+
+        self.finish_select(results, columns, SelectOptions { limit, offset, order_by, distinct })
+    }
+
+    /// Borrow the live rows matching `where_clause` without copying them,
+    /// found the way `plan` chooses. Stops early when the statement is
+    /// interrupted. Columnar scans yield rows in column order, lookups in
+    /// id order and full scans in storage order.
+    pub fn scan<'a>(&'a self, where_clause: Option<&'a WhereClause>) -> Box<dyn Iterator<Item = &'a Row> + 'a> {
+        Box::new(self.planned_rows(self.plan(where_clause), where_clause).take_while(|_| !interrupted()))
+    }
+
+    /// Choose how to find the rows matching `where_clause`: see `planner`
+    pub fn plan(&self, where_clause: Option<&WhereClause>) -> QueryPlan {
+        planner::plan(self, where_clause)
+    }
+
+    /// Live rows matching `where_clause`, read along the plan's access path
+    /// with its remaining filters in the plan's order
+    fn planned_rows<'a>(&'a self, plan: QueryPlan, where_clause: Option<&'a WhereClause>) -> Box<dyn Iterator<Item = &'a Row> + 'a> {
+        let candidates: Box<dyn Iterator<Item = &'a Row> + 'a> = match plan.access {
+            AccessPath::RowIdLookup { ids } | AccessPath::UniqueIndex { ids, .. } => {
+                Box::new(ids.into_iter().filter_map(|id| self.rows.get(&id)))
+            }
+            AccessPath::ColumnarScan => {
+                let ids = self.columnar.as_ref().map_or(&[][..], ColumnStore::ids);
+                let selection = self.columnar_selection(where_clause).unwrap_or_default();
+                Box::new(ids.iter().zip(selection)
+                    .filter(|(_, selected)| *selected)
+                    .filter_map(|(id, _)| self.rows.get(id)))
+            }
+            _ => Box::new(self.rows.values()),
+        };
+
+        let conditions = where_clause.map_or(&[][..], |wc| &wc.conditions);
+        match plan.order {
+            Some(order) => Box::new(candidates.filter(move |row| {
+                order.iter().all(|&i| self.matches_condition(row, &conditions[i]) == Some(true))
+            })),
+            None => Box::new(candidates.filter(move |row| self.matches_where(row, where_clause))),
+        }
+    }
+
+    /// Rows matching `where_clause` as a mask over `ColumnStore::ids`, if
+    /// the table is columnar and the clause only tests numeric columns
+    pub fn columnar_selection(&self, where_clause: Option<&WhereClause>) -> Option<Vec<bool>> {
+        self.columnar.as_ref()?.filter(where_clause)
+    }
+
+    /// Matches a scan needs to collect: without ORDER BY or DISTINCT the
+    /// first OFFSET + LIMIT rows are the result
+    fn scan_limit(limit: Option<usize>, offset: Option<usize>, order_by: Option<&OrderBy>, distinct: bool) -> usize {
+        match limit {
+            Some(limit) if order_by.is_none() && !distinct => offset.unwrap_or(0).saturating_add(limit),
+            _ => usize::MAX,
+        }
+    }
+
+    /// Select rows as they existed at `as_of` (unix milliseconds),
+    /// including rows that have since been soft-deleted, with the values
+    /// they held before any later update
+    pub fn select_as_of(
+        &self,
+        as_of: i64,
+        columns: &[SelectColumn],
+        where_clause: Option<&WhereClause>,
+        options: SelectOptions,
+    ) -> Vec<Row> {
+        let SelectOptions { limit, offset, order_by, distinct } = options;
+        let snapshots: Vec<Row> = self.rows.values()
+            .chain(self.tombstones.values())
+            .take_while(|_| !interrupted())
+            .filter_map(|row| {
+                let Some(history) = self.history.get(&row.id) else {
+                    return Some(row.clone());
+                };
+                history.visible_at(as_of)
+                    .then(|| Row::new(row.id, history.values_at(as_of, &row.values).to_vec()))
+            })
             .filter(|row| self.matches_where(row, where_clause))
+            .take(Self::scan_limit(limit, offset, order_by, distinct))
             .collect();
 
-        // Apply ORDER BY
+        self.finish_select(snapshots.iter().collect(), columns, options)
+    }
+
+    /// Apply ORDER BY, projection, DISTINCT, OFFSET and LIMIT to filtered rows
+    fn finish_select(&self, mut results: Vec<&Row>, columns: &[SelectColumn], options: SelectOptions) -> Vec<Row> {
+        let SelectOptions { limit, offset, order_by, distinct } = options;
+        // Sort the full rows so ORDER BY may use columns that are not
+        // selected; the sort is stable, so DISTINCT keeps the first row of
+        // each duplicate run in that order
         if let Some(ob) = order_by {
             if let Some(idx) = self.column_index(&ob.column) {
+                let collation = self.schema.columns[idx].collation;
                 results.sort_by(|a, b| {
-                    let cmp = self.values_compare(&a.values[idx], &b.values[idx])
+                    let cmp = self.values_compare_collated(&a.values[idx], &b.values[idx], collation)
                         .unwrap_or(std::cmp::Ordering::Equal);
                     if ob.ascending { cmp } else { cmp.reverse() }
                 });
             }
         }
 
-        // Apply OFFSET
-        if let Some(n) = offset {
-            results = results.into_iter().skip(n).collect();
+        // Rows are copied lazily, so only those within OFFSET and LIMIT are
+        // projected (and, for DISTINCT, those examined to find them)
+        let project = |row: &Row| if columns.is_empty() { row.clone() } else { self.project_expressions(row, columns) };
+        let skip = offset.unwrap_or(0);
+        let take = limit.unwrap_or(usize::MAX);
+        if !distinct {
+            return results.into_iter().skip(skip).take(take).map(project).collect();
+        }
+
+        // DISTINCT compares the projected values, before OFFSET and LIMIT
+        let collations = self.projected_collations(columns);
+        let mut seen = HashSet::new();
+        results.into_iter()
+            .map(project)
+            .filter(|row| {
+                let key: Vec<Value> = row.values.iter().zip(&collations)
+                    .map(|(v, collation)| match v {
+                        Value::Text(s) => Value::Text(collation.key(s).into_owned()),
+                        _ => v.clone(),
+                    })
+                    .collect();
+                seen.insert(DistinctKey(key))
+            })
+            .skip(skip)
+            .take(take)
+            .collect()
+    }
+
+    /// Collation of each column produced by `project_expressions`
+    fn projected_collations(&self, columns: &[SelectColumn]) -> Vec<Collation> {
+        if columns.is_empty() {
+            return self.schema.columns.iter().map(|c| c.collation).collect();
+        }
+        let mut collations = Vec::new();
+        for col in columns {
+            match col {
+                SelectColumn::All => collations.extend(self.schema.columns.iter().map(|c| c.collation)),
+                SelectColumn::Column(name) => collations.push(self.column_collation(name)),
+                SelectColumn::Function { .. } => collations.push(Collation::Binary),
+                SelectColumn::Aggregate { .. } => {}
+            }
+        }
+        collations
+    }
+
+    /// Select by vector similarity
+    pub fn select_by_similarity(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        ef_search: usize,
+    ) -> Vec<(Row, f32)> {
+        let mut normalized = Vec::new();
+        let query_vector = self.prepare_query(query_vector, &mut normalized);
+
+        let candidates = self.graph.query(query_vector, k, ef_search);
+
+        candidates.into_iter()
+            .filter_map(|c| {
+                let row_id = self.row_ids.get(&c.id)?;
+                self.rows.get(row_id).map(|row| {
+                    (self.project_row(row, &[]), c.distance)
+                })
+            })
+            .collect()
+    }
+
+    /// Next page of similarity results after `cursor` (or the first page).
+    ///
+    /// The graph is searched with a growing candidate list until `k` results
+    /// past the cursor are found or the table is exhausted. Also returns the
+    /// cursor for the following page, or `None` once results run out.
+    pub fn select_by_similarity_after(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        cursor: Option<SearchCursor>,
+    ) -> (Vec<(Row, f32)>, Option<SearchCursor>) {
+        let mut normalized = Vec::new();
+        let query_vector = self.prepare_query(query_vector, &mut normalized);
+
+        let total = self.graph.len();
+        let mut fetch = k.max(1) * 2;
+        let page = loop {
+            let ef = fetch.max(self.graph.config().search_buffer);
+            let candidates = self.graph.query(query_vector, fetch, ef);
+            let exhausted = candidates.len() < fetch || fetch >= total;
+            // Rows tied with the last candidate may not all have fit in a full list
+            let cutoff = match candidates.last() {
+                Some(last) if !exhausted => last.distance,
+                _ => f32::INFINITY,
+            };
+
+            let mut page: Vec<(u64, f32)> = candidates.into_iter()
+                .filter(|c| c.distance < cutoff)
+                .filter_map(|c| Some((*self.row_ids.get(&c.id)?, c.distance)))
+                .filter(|&(id, dist)| cursor.is_none_or(|cur| cur.precedes(dist, id)))
+                .collect();
+
+            if page.len() >= k || exhausted {
+                page.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+                page.truncate(k);
+                break page;
+            }
+            fetch *= 2;
+        };
+
+        let next = match page.last() {
+            Some(&(id, distance)) if page.len() == k => Some(SearchCursor { distance, id }),
+            _ => None,
+        };
+        let rows = page.into_iter()
+            .filter_map(|(id, dist)| self.rows.get(&id).map(|row| (self.project_row(row, &[]), dist)))
+            .collect();
+        (rows, next)
+    }
+
+    /// Similarity search with diversity constraints: at most `n` rows per
+    /// value of the `MAX PER` column, and/or maximal marginal relevance
+    /// re-ranking that trades query distance for distance to rows already
+    /// picked. The candidate pool grows until `k` rows qualify.
+    pub fn select_by_similarity_diverse(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        ef_search: usize,
+        diversity: &Diversity,
+    ) -> Result<Vec<(Row, f32)>> {
+        let group = match &diversity.max_per {
+            Some((column, n)) => {
+                let idx = self.column_index(column)
+                    .ok_or_else(|| MarsError::ColumnNotFound(column.to_string()))?;
+                Some((idx, *n))
+            }
+            None => None,
+        };
+
+        let mut normalized = Vec::new();
+        let query_vector = self.prepare_query(query_vector, &mut normalized);
+
+        let total = self.graph.len();
+        // MMR needs a wider pool to have alternatives to trade for
+        let mut fetch = k.max(1) * if diversity.mmr_lambda.is_some() { 4 } else { 2 };
+        loop {
+            let pool = self.graph.query(query_vector, fetch, fetch.max(ef_search));
+            let exhausted = pool.len() < fetch || fetch >= total;
+
+            let picked = self.pick_diverse(pool, k, group, diversity.mmr_lambda);
+            if picked.len() >= k || exhausted {
+                return Ok(picked.into_iter()
+                    .map(|(row, dist)| (self.project_row(row, &[]), dist))
+                    .collect());
+            }
+            fetch *= 2;
+        }
+    }
+
+    /// Greedily pick up to `k` rows from a distance-sorted candidate pool,
+    /// skipping groups that reached their cap and applying MMR if requested
+    fn pick_diverse(
+        &self,
+        pool: Vec<Candidate>,
+        k: usize,
+        group: Option<(usize, usize)>,
+        mmr_lambda: Option<f32>,
+    ) -> Vec<(&Row, f32)> {
+        let metric = self.graph.metric();
+        let mut remaining: Vec<(Candidate, &Row)> = pool.into_iter()
+            .filter_map(|c| {
+                let row = self.rows.get(self.row_ids.get(&c.id)?)?;
+                Some((c, row))
+            })
+            .collect();
+
+        let mut picked: Vec<(Candidate, &Row)> = Vec::with_capacity(k.min(remaining.len()));
+        let mut per_group: HashMap<String, usize> = HashMap::new();
+
+        while picked.len() < k {
+            if let Some((idx, cap)) = group {
+                remaining.retain(|(_, row)| {
+                    per_group.get(&Self::value_to_string(&row.values[idx])).is_none_or(|&n| n < cap)
+                });
+            }
+
+            let best = match mmr_lambda {
+                None => 0,
+                Some(lambda) => {
+                    let score = |c: &Candidate| {
+                        let vector = self.graph.vector(c.id);
+                        let novelty = picked.iter()
+                            .filter_map(|(p, _)| Some(metric(vector?, self.graph.vector(p.id)?)))
+                            .fold(None, |min: Option<f32>, d| Some(min.map_or(d, |m| m.min(d))))
+                            .unwrap_or(0.0);
+                        (1.0 - lambda) * novelty - lambda * c.distance
+                    };
+                    match remaining.iter()
+                        .enumerate()
+                        .max_by(|a, b| score(&a.1.0).total_cmp(&score(&b.1.0)))
+                    {
+                        Some((i, _)) => i,
+                        None => break,
+                    }
+                }
+            };
+            if best >= remaining.len() {
+                break;
+            }
+
+            let (candidate, row) = remaining.remove(best);
+            if let Some((idx, _)) = group {
+                *per_group.entry(Self::value_to_string(&row.values[idx])).or_default() += 1;
+            }
+            picked.push((candidate, row));
+        }
+
+        picked.into_iter().map(|(c, row)| (row, c.distance)).collect()
+    }
+
+    /// Top `k` rows per distinct value of `group_column`, by exact distance.
+    /// Groups are ordered by their closest row, so the first group holds the
+    /// overall best match.
+    pub fn select_by_similarity_grouped(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        group_column: &str,
+    ) -> Result<Vec<SearchGroup>> {
+        let group_idx = self.column_index(group_column)
+            .ok_or_else(|| MarsError::ColumnNotFound(group_column.to_string()))?;
+
+        let mut normalized = Vec::new();
+        let query_vector = self.prepare_query(query_vector, &mut normalized);
+        let metric = self.graph.metric();
+
+        let mut groups: HashMap<String, Vec<(&Row, f32)>> = HashMap::new();
+        for (row, v) in self.dense_vectors().filter(|(_, v)| v.len() == query_vector.len()) {
+            let key = Self::value_to_string(&row.values[group_idx]);
+            groups.entry(key).or_default().push((row, metric(query_vector, v)));
+        }
+
+        let mut groups: Vec<SearchGroup> = groups.into_values()
+            .map(|scored| {
+                let key = scored[0].0.values[group_idx].clone();
+                let results = self.top_k(scored, k).into_iter()
+                    .map(|(row, dist)| (row.id, row.values, dist))
+                    .collect();
+                SearchGroup { key, results }
+            })
+            .collect();
+
+        let best = |g: &SearchGroup| g.results.first().map_or((f32::INFINITY, 0), |r| (r.2, r.0));
+        groups.sort_by(|a, b| {
+            let (a, b) = (best(a), best(b));
+            a.0.total_cmp(&b.0).then(a.1.cmp(&b.1))
+        });
+        Ok(groups)
+    }
+
+    /// Exact top-k by scanning every row's vector with the table metric.
+    /// Slower than `select_by_similarity` but returns ground-truth results.
+    pub fn select_by_similarity_exact(&self, query_vector: &[f32], k: usize) -> Vec<(Row, f32)> {
+        let mut normalized = Vec::new();
+        let query_vector = self.prepare_query(query_vector, &mut normalized);
+
+        let metric = self.graph.metric();
+        let scored: Vec<(&Row, f32)> = self.dense_vectors()
+            .filter(|(_, v)| v.len() == query_vector.len())
+            .map(|(row, v)| (row, metric(query_vector, v)))
+            .collect();
+        self.top_k(scored, k)
+    }
+
+    /// Top-k by the first `dims` components of each vector, for embeddings
+    /// trained so that their prefixes are usable on their own. A query with
+    /// every component re-ranks the best `k * DIMS_OVERSAMPLE` prefix
+    /// matches on full vectors; a query of `dims` components is scored on
+    /// the prefixes alone.
+    pub fn select_by_similarity_dims(&self, query_vector: &[f32], dims: usize, k: usize) -> Result<Vec<(Row, f32)>> {
+        let dimension = self.graph.dimension();
+        if dims == 0 || dims > dimension {
+            return Err(MarsError::InvalidConfig(format!(
+                "USING DIMS must be between 1 and {}, got {}", dimension, dims,
+            )));
+        }
+        let mut normalized = Vec::new();
+        let query_vector = self.prepare_query(query_vector, &mut normalized);
+        if query_vector.len() != dimension && query_vector.len() != dims {
+            return Err(MarsError::DimensionMismatch { expected: dimension, actual: query_vector.len() });
+        }
+
+        // Prefixes of unit vectors are not unit length, so UnitCosine
+        // would be wrong on them
+        let metric = self.graph.metric();
+        let prefix_metric = if self.normalize { Cosine::compute } else { metric };
+        let mut scored: Vec<(&Row, f32)> = self.dense_vectors()
+            .filter(|(_, v)| v.len() == dimension)
+            .map(|(row, v)| (row, prefix_metric(&query_vector[..dims], &v[..dims])))
+            .collect();
+        if query_vector.len() == dimension && dims < dimension {
+            scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.id.cmp(&b.0.id)));
+            scored.truncate(k.saturating_mul(DIMS_OVERSAMPLE));
+            let idx = self.schema.vector_column.as_ref().and_then(|c| self.column_index(c));
+            for (row, dist) in &mut scored {
+                if let Some(v) = idx.and_then(|i| row.values.get(i)?.as_vector()) {
+                    *dist = metric(query_vector, v);
+                }
+            }
+        }
+        Ok(self.top_k(scored, k))
+    }
+
+    /// Write the vector index as a Graphviz DOT digraph, for inspecting its
+    /// topology. Nodes are named by row id and labeled with the row's
+    /// `label_column` value when given; each neighbor link is an edge.
+    pub fn export_graph_dot<P: AsRef<Path>>(&self, path: P, label_column: Option<&str>) -> Result<()> {
+        let (nodes, edges) = self.graph_topology(label_column)?;
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "digraph {} {{", quoted(self.name()))?;
+        for (id, label) in &nodes {
+            match label {
+                Some(label) => writeln!(out, "  {} [label={}];", id, quoted(label))?,
+                None => writeln!(out, "  {};", id)?,
+            }
+        }
+        for (from, to) in &edges {
+            writeln!(out, "  {} -> {};", from, to)?;
+        }
+        writeln!(out, "}}")?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Write the vector index as JSON, as `export_graph_dot` does for DOT:
+    /// `{"nodes": [{"id": 1, "label": "..."}], "edges": [{"source": 1, "target": 2}]}`
+    pub fn export_graph_json<P: AsRef<Path>>(&self, path: P, label_column: Option<&str>) -> Result<()> {
+        let (nodes, edges) = self.graph_topology(label_column)?;
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "{{\"nodes\":[")?;
+        for (i, (id, label)) in nodes.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            match label {
+                Some(label) => write!(out, "{}{{\"id\":{},\"label\":{}}}", sep, id, quoted(label))?,
+                None => write!(out, "{}{{\"id\":{}}}", sep, id)?,
+            }
+        }
+        write!(out, "],\"edges\":[")?;
+        for (i, (from, to)) in edges.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(out, "{}{{\"source\":{},\"target\":{}}}", sep, from, to)?;
+        }
+        writeln!(out, "]}}")?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Indexed rows (row id and optional label) in id order, and the graph's
+    /// neighbor links between them as row id pairs
+    fn graph_topology(&self, label_column: Option<&str>) -> Result<GraphTopology> {
+        let label_idx = label_column
+            .map(|name| self.column_index(name).ok_or_else(|| MarsError::ColumnNotFound(name.to_string())))
+            .transpose()?;
+        let mut ids: Vec<u64> = self.node_ids.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut nodes = Vec::with_capacity(ids.len());
+        let mut edges = Vec::new();
+        for id in ids {
+            let label = label_idx.and_then(|idx| functions::text(&self.rows.get(&id)?.values[idx]));
+            nodes.push((id, label));
+            let Some(node) = self.graph.get(self.node_ids[&id]) else { continue };
+            edges.extend(node.neighbors.iter().filter_map(|n| Some((id, *self.row_ids.get(n)?))));
+        }
+        Ok((nodes, edges))
+    }
+
+    /// Touch the vector index's memory ahead of the first searches; see
+    /// `Graph::warmup`. Returns the bytes touched.
+    pub fn warmup(&self) -> usize {
+        self.graph.warmup()
+    }
+
+    /// Degree distribution and connectivity of the vector index; see
+    /// `Graph::graph_stats`
+    pub fn graph_stats(&self) -> GraphStats {
+        self.graph.graph_stats()
+    }
+
+    /// Link parts of the vector index that searches cannot reach back into
+    /// it; see `Graph::bridge_components`. Returns the edges added.
+    pub fn bridge_components(&mut self) -> usize {
+        self.graph.bridge_components()
+    }
+
+    /// Measure the graph's recall@k: up to `sample` stored vectors, spread
+    /// evenly over the row ids, are searched both through the graph (with
+    /// `ef_search`) and exactly
+    pub fn check_recall(&self, sample: usize, k: usize, ef_search: usize) -> RecallReport {
+        let mut vectors: Vec<(u64, &[f32])> = self.dense_vectors().map(|(row, v)| (row.id, v)).collect();
+        vectors.sort_unstable_by_key(|(id, _)| *id);
+        let step = vectors.len().div_ceil(sample.max(1)).max(1);
+        let queries: Vec<&[f32]> = vectors.iter().step_by(step).map(|(_, v)| *v).collect();
+
+        let mut report = RecallReport {
+            queries: queries.len(),
+            k,
+            recall: 1.0,
+            min_recall: 1.0,
+            graph_latency: Duration::ZERO,
+            exact_latency: Duration::ZERO,
+        };
+        if queries.is_empty() || k == 0 {
+            return report;
+        }
+        let (mut total, mut graph_time, mut exact_time) = (0.0, Duration::ZERO, Duration::ZERO);
+        for query in &queries {
+            let start = Instant::now();
+            let found = self.select_by_similarity(query, k, ef_search.max(k));
+            graph_time += start.elapsed();
+            let start = Instant::now();
+            let exact = self.select_by_similarity_exact(query, k);
+            exact_time += start.elapsed();
+
+            let recall = match exact.last() {
+                Some(&(_, cutoff)) => {
+                    let hits = found.iter().filter(|(_, dist)| *dist <= cutoff).count().min(exact.len());
+                    hits as f64 / exact.len() as f64
+                }
+                None => 1.0,
+            };
+            total += recall;
+            report.min_recall = report.min_recall.min(recall);
+        }
+        let n = queries.len() as u32;
+        report.recall = total / queries.len() as f64;
+        report.graph_latency = graph_time / n;
+        report.exact_latency = exact_time / n;
+        report
+    }
+
+    /// Exact top-k like `select_by_similarity_exact`, with distances computed
+    /// by the GPU batch kernels. Metrics without a kernel fall back to the CPU.
+    #[cfg(feature = "gpu")]
+    pub fn select_by_similarity_gpu(
+        &self,
+        gpu: &crate::gpu::GpuDistance,
+        query_vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<(Row, f32)>> {
+        if !matches!(self.metric.as_str(), "euclidean" | "cosine" | "dot") {
+            return Ok(self.select_by_similarity_exact(query_vector, k));
+        }
+        let mut normalized = Vec::new();
+        let query_vector = self.prepare_query(query_vector, &mut normalized);
+
+        let (rows, vectors): (Vec<&Row>, Vec<Vec<f32>>) = self.dense_vectors()
+            .filter(|(_, v)| v.len() == query_vector.len())
+            .map(|(row, v)| (row, v.to_vec()))
+            .unzip();
+        if rows.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let to_err = |e: crate::gpu::GpuError| MarsError::InvalidFormat(format!("GPU search failed: {}", e));
+        let distances = match self.metric.as_str() {
+            // The kernel returns the true L2 distance; the CPU metric is squared
+            "euclidean" => gpu.euclidean_batch(query_vector, &vectors).map_err(to_err)?
+                .into_iter().map(|d| d * d).collect(),
+            "cosine" => gpu.cosine_batch(query_vector, &vectors).map_err(to_err)?,
+            _ => gpu.dot_batch(query_vector, &vectors).map_err(to_err)?,
+        };
+
+        Ok(self.top_k(rows.into_iter().zip(distances).collect(), k))
+    }
+
+    /// The query as the graph sees it: projected and, for NORMALIZE tables,
+    /// normalized into `buf`
+    fn prepare_query<'a>(&self, query_vector: &'a [f32], buf: &'a mut Vec<f32>) -> &'a [f32] {
+        let projection = self.projection.as_ref().filter(|p| p.input_dimension() == query_vector.len());
+        if !self.normalize && projection.is_none() {
+            return query_vector;
+        }
+        match projection {
+            Some(projection) => *buf = projection.project(query_vector),
+            None => buf.extend_from_slice(query_vector),
+        }
+        if self.normalize {
+            normalize(buf);
+        }
+        buf
+    }
+
+    /// Rows paired with their dense vector column value
+    fn dense_vectors(&self) -> impl Iterator<Item = (&Row, &[f32])> {
+        let idx = self.schema.vector_column.as_ref().and_then(|c| self.column_index(c));
+        self.rows.values()
+            .take_while(|_| !interrupted())
+            .filter_map(move |row| Some((row, row.values.get(idx?)?.as_vector()?)))
+    }
+
+    /// Sort scored rows by distance (then id) and keep the best `k`
+    fn top_k(&self, mut scored: Vec<(&Row, f32)>, k: usize) -> Vec<(Row, f32)> {
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.id.cmp(&b.0.id)));
+        scored.truncate(k);
+        scored.into_iter()
+            .map(|(row, dist)| (self.project_row(row, &[]), dist))
+            .collect()
+    }
+
+    /// Exact search over a SPARSE_VECTOR column by dot product.
+    /// Returned distances are negated dot products (lower is more similar),
+    /// matching the `dot` metric; rows with no overlapping index are skipped.
+    pub fn select_by_sparse_similarity(
+        &self,
+        column: &str,
+        query: &[(u32, f32)],
+        k: usize,
+    ) -> Result<Vec<(Row, f32)>> {
+        let idx = self.column_index(column)
+            .ok_or_else(|| MarsError::ColumnNotFound(column.to_string()))?;
+        if self.schema.columns[idx].data_type != ColumnType::SparseVector {
+            return Err(MarsError::InvalidFormat(format!("'{}' is not a SPARSE_VECTOR column", column)));
+        }
+
+        let results: Vec<(&Row, f32)> = self.rows.values()
+            .take_while(|_| !interrupted())
+            .filter_map(|row| {
+                let score = sparse_dot(row.values[idx].as_sparse_vector()?, query);
+                (score != 0.0).then_some((row, -score))
+            })
+            .collect();
+
+        Ok(self.top_k(results, k))
+    }
+
+    /// Exact late-interaction search over a multi-vector column.
+    /// Returned distances are negated MaxSim scores (lower is more similar).
+    pub fn select_by_max_sim(
+        &self,
+        column: &str,
+        query: &[Vec<f32>],
+        k: usize,
+    ) -> Result<Vec<(Row, f32)>> {
+        let idx = self.column_index(column)
+            .ok_or_else(|| MarsError::ColumnNotFound(column.to_string()))?;
+        if !matches!(self.schema.columns[idx].data_type, ColumnType::MultiVector(_)) {
+            return Err(MarsError::InvalidFormat(format!("'{}' is not a multi-vector column", column)));
+        }
+
+        let results: Vec<(&Row, f32)> = self.rows.values()
+            .take_while(|_| !interrupted())
+            .filter_map(|row| {
+                let doc = row.values[idx].as_multi_vector()?;
+                Some((row, -max_sim(query, doc)))
+            })
+            .collect();
+
+        Ok(self.top_k(results, k))
+    }
+
+    /// Rows whose vectors lie within `threshold` of a lower-id row, each
+    /// paired with the first such row that is not itself a duplicate.
+    /// Candidates come from the vector index, so like other approximate
+    /// searches a few may be missed.
+    pub fn near_duplicates(&self, threshold: f32) -> Result<Vec<Duplicate>> {
+        if threshold.is_nan() || threshold < 0.0 {
+            return Err(MarsError::InvalidConfig(format!("Duplicate threshold must not be negative, got {}", threshold)));
+        }
+        let idx = self.vector_column_index()
+            .ok_or_else(|| MarsError::InvalidConfig(format!("Table '{}' has no VECTOR column", self.name())))?;
+        let mut ids: Vec<u64> = self.rows.keys().copied().collect();
+        ids.sort_unstable();
+        let ef_search = self.graph.config().search_buffer.max(DEDUPE_CANDIDATES);
+
+        let mut duplicates = Vec::new();
+        let mut removed = HashSet::new();
+        for id in ids {
+            if removed.contains(&id) {
+                continue;
+            }
+            let Some(vector) = self.rows[&id].values[idx].as_vector() else { continue };
+            let mut found: Vec<Duplicate> = self.graph.query(vector, DEDUPE_CANDIDATES, ef_search).into_iter()
+                .filter_map(|c| {
+                    let other = *self.row_ids.get(&c.id)?;
+                    let distance = self.reported_distance(c.distance);
+                    (other > id && distance <= threshold && !removed.contains(&other))
+                        .then_some(Duplicate { kept: id, duplicate: other, distance })
+                })
+                .collect();
+            found.sort_by_key(|d| d.duplicate);
+            removed.extend(found.iter().map(|d| d.duplicate));
+            duplicates.extend(found);
+        }
+        Ok(duplicates)
+    }
+
+    /// A search distance as SQL reports it: the L2 distance on euclidean
+    /// tables, which are searched squared, else the metric's own
+    fn reported_distance(&self, distance: f32) -> f32 {
+        if self.metric == "euclidean" { distance.max(0.0).sqrt() } else { distance }
+    }
+
+    /// Distance from a query to a vector stored in column `idx`, for
+    /// `SIMILARITY .. WITHIN`. None if their sizes differ.
+    fn vector_distance(&self, idx: usize, query: &[f32], vector: &[f32]) -> Option<f32> {
+        let mut prepared = Vec::new();
+        let (query, metric) = if Some(idx) == self.vector_column_index() {
+            (self.prepare_query(query, &mut prepared), self.graph.metric())
+        } else {
+            // Only the indexed column is normalized on insert
+            (query, if self.normalize { Cosine::compute } else { self.graph.metric() })
+        };
+        (query.len() == vector.len()).then(|| self.reported_distance(metric(query, vector)))
+    }
+
+    /// Check that a SCORE expression gives a number: every column it reads
+    /// is numeric (or `distance`), every literal a number or NULL and every
+    /// function returns a number
+    pub fn check_score(&self, score: &ScalarExpr) -> Result<()> {
+        let invalid = |what: String| Err(MarsError::InvalidFormat(format!("SCORE must be numeric, but {} is not", what)));
+        match score {
+            ScalarExpr::Column(name) => match self.column_index(name) {
+                Some(idx) => match self.schema.columns[idx].data_type {
+                    ColumnType::Integer | ColumnType::Float => Ok(()),
+                    _ => invalid(format!("column '{}'", name)),
+                },
+                None if name.eq_ignore_ascii_case("distance") || name == "_version" => Ok(()),
+                None => Err(MarsError::ColumnNotFound(name.clone())),
+            },
+            ScalarExpr::Literal(Value::Integer(_) | Value::Float(_) | Value::Null) => Ok(()),
+            ScalarExpr::Literal(_) => invalid(score.to_string()),
+            ScalarExpr::Binary { left, right, .. } => {
+                self.check_score(left)?;
+                self.check_score(right)
+            }
+            ScalarExpr::Call { func, args } => match func.result_type() {
+                Some(ColumnType::Integer | ColumnType::Float) => Ok(()),
+                None => args.iter().try_for_each(|arg| self.check_score(arg)),
+                Some(_) => invalid(format!("{}()", func.name())),
+            },
+        }
+    }
+
+    /// Re-rank similarity results by a SCORE expression of `distance` and
+    /// the row's columns, lowest first, keeping `k`. Rows it gives no
+    /// number, as from a NULL column, rank after all others with an
+    /// infinite score.
+    pub fn rescore(&self, results: Vec<(Row, f32)>, score: &ScalarExpr, k: usize) -> Vec<(Row, f32)> {
+        let mut scored: Vec<(Row, f32)> = results.into_iter()
+            .map(|(row, distance)| {
+                let value = score.eval(&|name| {
+                    if name.eq_ignore_ascii_case("distance") && self.column_index(name).is_none() {
+                        Value::Float(distance as f64)
+                    } else {
+                        self.column_value(&row, name)
+                    }
+                });
+                let score = value.as_float().map_or(f32::INFINITY, |score| score as f32);
+                (row, score)
+            })
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.id.cmp(&b.0.id)));
+        scored.truncate(k);
+        scored
+    }
+
+    /// Exact top-k by a weighted sum of one query's distances to several
+    /// VECTOR columns, e.g. `0.7*title_emb + 0.3*body_emb`. Rows missing
+    /// any of the vectors are skipped.
+    pub fn select_by_weighted_similarity(
+        &self,
+        terms: &[(String, f32)],
+        query_vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<(Row, f32)>> {
+        let mut normalized = Vec::new();
+        let prepared = self.prepare_query(query_vector, &mut normalized);
+        let metric = self.graph.metric();
+        // Only the indexed column is projected and normalized on insert, so
+        // the others are compared to the raw query, and by plain cosine
+        // when the table normalizes
+        let other_metric = if self.normalize { Cosine::compute } else { metric };
+
+        let columns = terms.iter()
+            .map(|(column, weight)| {
+                let idx = self.column_index(column).ok_or_else(|| MarsError::ColumnNotFound(column.clone()))?;
+                let ColumnType::Vector(dim) = self.schema.columns[idx].data_type else {
+                    return Err(MarsError::InvalidFormat(format!("'{}' is not a VECTOR column", column)));
+                };
+                let (query, metric, expected) = if self.schema.vector_column.as_deref() == Some(column.as_str()) {
+                    (prepared, metric, self.graph.dimension())
+                } else {
+                    (query_vector, other_metric, dim)
+                };
+                if query.len() != expected {
+                    return Err(MarsError::DimensionMismatch { expected, actual: query_vector.len() });
+                }
+                Ok((idx, *weight, query, metric))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let results: Vec<(&Row, f32)> = self.rows.values()
+            .take_while(|_| !interrupted())
+            .filter_map(|row| {
+                let mut total = 0.0;
+                for &(idx, weight, query, metric) in &columns {
+                    let v = row.values.get(idx)?.as_vector().filter(|v| v.len() == query.len())?;
+                    total += weight * metric(query, v);
+                }
+                Some((row, total))
+            })
+            .collect();
+
+        Ok(self.top_k(results, k))
+    }
+
+    /// Update rows matching conditions. Each assignment is evaluated
+    /// against the row's values before the update.
+    pub fn update(
+        &mut self,
+        assignments: &[(String, ScalarExpr)],
+        where_clause: Option<&WhereClause>,
+    ) -> Result<usize> {
+        let matching_ids = self.matching_row_ids(where_clause);
+        let updates = self.assigned_values(&matching_ids, assignments)?;
+        self.update_rows(&updates)
+    }
+
+    /// The values assignments give each of the live rows `ids`, evaluated
+    /// against its current values
+    pub(crate) fn assigned_values(
+        &self,
+        ids: &[u64],
+        assignments: &[(String, ScalarExpr)],
+    ) -> Result<Vec<RowUpdate>> {
+        for (_, expr) in assignments {
+            if let Some(name) = expr.find_column(&|name| self.column_index(name).is_none() && name != "_version") {
+                return Err(MarsError::ColumnNotFound(name.to_string()));
+            }
+        }
+
+        ids.iter()
+            .filter_map(|&id| Some((id, self.rows.get(&id)?)))
+            .map(|(id, row)| {
+                let values = assignments.iter()
+                    .map(|(column, expr)| Ok((column.clone(), expr.try_eval(&|name| self.column_value(row, name))?)))
+                    .collect::<Result<_>>()?;
+                Ok((id, values))
+            })
+            .collect()
+    }
+
+    /// Apply per-row assignments to live rows
+    pub(crate) fn update_rows(&mut self, updates: &[RowUpdate]) -> Result<usize> {
+        let updates = updates.iter()
+            .map(|(id, assignments)| Ok((*id, self.assignment_indices(assignments)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.check_unique_assignments(&updates)?;
+        for (id, assignment_indices) in &updates {
+            self.apply_assignments(*id, assignment_indices);
+        }
+
+        Ok(updates.len())
+    }
+
+    /// Update a single row only if its version still equals `expected_version`.
+    /// Returns the new version, or `MarsError::VersionConflict` if another
+    /// writer updated the row first.
+    pub fn update_if_version(
+        &mut self,
+        id: u64,
+        assignments: &[(String, Value)],
+        expected_version: u64,
+    ) -> Result<u64> {
+        let actual = self.row_version(id)
+            .ok_or_else(|| MarsError::InvalidFormat(format!("Row {} does not exist", id)))?;
+
+        if actual != expected_version {
+            return Err(MarsError::VersionConflict { id, expected: expected_version, actual });
+        }
+
+        let updates = [(id, self.assignment_indices(assignments)?)];
+        self.check_unique_assignments(&updates)?;
+        Ok(self.apply_assignments(id, &updates[0].1))
+    }
+
+    /// Column index of each assignment, with its value as stored
+    fn assignment_indices(&self, assignments: &[(String, Value)]) -> Result<IndexedAssignments> {
+        let vector_idx = self.vector_column_index();
+        assignments.iter()
+            .map(|(col_name, value)| {
+                let idx = self.column_index(col_name);
+                let mut value = match idx {
+                    Some(idx) => self.stored_value(idx, value.clone())?,
+                    None => value.clone(),
+                };
+                if idx.is_some() && idx == vector_idx
+                    && let Value::Vector(v) = &mut value
+                {
+                    let expected = self.projection.as_ref().map_or(self.graph.dimension(), |p| p.input_dimension());
+                    if v.len() != expected {
+                        return Err(MarsError::DimensionMismatch { expected, actual: v.len() });
+                    }
+                    self.encode_vector(v);
+                }
+                Ok((idx, value))
+            })
+            .collect()
+    }
+
+    /// Live rows holding one of the non-NULL UNIQUE values of a new row,
+    /// in id order
+    pub(crate) fn conflicting_rows(&self, row_values: &[Value]) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.unique_indexes.iter()
+            .filter_map(|(column, unique_index)| {
+                let idx = self.column_index(column)?;
+                let key = self.collation_key(idx, &row_values[idx]);
+                if key == "NULL" { None } else { unique_index.get(&key).copied() }
+            })
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Overwrite every column of live row `id` with a new row's values and
+    /// delete the rows `others`, for INSERT OR REPLACE. The row keeps its
+    /// id; a changed vector replaces its graph node.
+    fn overwrite_row(&mut self, id: u64, others: &[u64], mut row_values: Vec<Value>) -> Result<()> {
+        if self.schema.vector_column.is_some() {
+            self.extract_vector(&row_values)?;
+        }
+        self.check_multi_vectors(&row_values)?;
+        self.assign_keys(id, &mut row_values);
+
+        let assignments: Vec<(String, Value)> = self.schema.columns.iter()
+            .map(|column| column.name.clone())
+            .zip(row_values)
+            .collect();
+        // The deleted rows give up their values, so count them as updated
+        let mut updates = vec![(id, self.assignment_indices(&assignments)?)];
+        updates.extend(others.iter().map(|&other| (other, Vec::new())));
+        self.check_unique_assignments(&updates)?;
+
+        self.delete_rows(others);
+        self.apply_assignments(id, &updates[0].1);
+        Ok(())
+    }
+
+    /// Get the current version of a live row
+    pub fn row_version(&self, id: u64) -> Option<u64> {
+        if !self.rows.contains_key(&id) {
+            return None;
+        }
+        Some(self.history.get(&id).map_or(0, |h| h.version))
+    }
+
+    /// Write assignments into a row and bump its version. A changed
+    /// vector replaces the row's graph node.
+    fn apply_assignments(&mut self, id: u64, assignment_indices: &[(Option<usize>, Value)]) -> u64 {
+        let vector_idx = self.vector_column_index();
+        let mut vector_changed = false;
+        let old = self.rows.get(&id).cloned();
+        if let Some(old) = &old {
+            self.remove_unique_indexes(old);
+            self.update_views(old, -1);
+            vector_changed = assignment_indices.iter()
+                .any(|(idx, value)| matches!(idx, Some(i) if Some(*i) == vector_idx && old.values[*i] != *value));
+        }
+        if let Some(row) = self.rows.get_mut(&id) {
+            for (idx_opt, value) in assignment_indices {
+                if let Some(idx) = idx_opt {
+                    row.values[*idx] = value.clone();
+                }
+            }
+            if let Some(store) = &mut self.columnar {
+                store.insert(row);
+            }
+        }
+        if let Some(new) = self.rows.get(&id).cloned() {
+            self.update_unique_indexes(&new);
+            self.update_views(&new, 1);
+            if vector_changed {
+                self.delete_nodes(&[id]);
+                if !self.deferred_index
+                    && let Ok(vector) = self.extract_vector(&new.values)
+                {
+                    let node_id = self.graph.insert(vector);
+                    self.link_node(id, node_id);
+                }
+            }
+        }
+
+        let history = self.history.entry(id).or_default();
+        if let Some(old) = old.filter(|_| self.keep_history > 0) {
+            history.previous.push(RowVersion { replaced_at: now_millis(), values: old.values });
+            let excess = history.previous.len().saturating_sub(self.keep_history);
+            history.previous.drain(..excess);
+        }
+        history.version += 1;
+        history.version
+    }
+
+    /// Delete rows matching conditions.
+    /// Soft-deleted rows that match are purged as well.
+    pub fn delete(
+        &mut self,
+        where_clause: Option<&WhereClause>,
+    ) -> Result<usize> {
+        let matching_ids = self.matching_row_ids(where_clause);
+
+        let purged_ids: Vec<u64> = self.tombstones.values()
+            .filter(|row| self.matches_where(row, where_clause))
+            .map(|row| row.id)
+            .collect();
+
+        let count = matching_ids.len() + purged_ids.len();
+        self.delete_rows(&matching_ids);
+
+        for id in &purged_ids {
+            self.tombstones.remove(id);
+            self.history.remove(id);
+        }
+
+        Ok(count)
+    }
+
+    /// Delete live rows by id
+    pub(crate) fn delete_rows(&mut self, ids: &[u64]) {
+        for id in ids {
+            if let Some(row) = self.rows.remove(id) {
+                self.remove_unique_indexes(&row);
+                self.update_views(&row, -1);
+            }
+            self.history.remove(id);
+            if let Some(store) = &mut self.columnar {
+                store.remove(*id);
+            }
+        }
+        self.delete_nodes(ids);
+    }
+
+    /// Soft-delete rows matching conditions.
+    /// Rows disappear from normal queries and similarity search but remain
+    /// readable through `select_as_of` until they are hard-deleted.
+    pub fn soft_delete(
+        &mut self,
+        where_clause: Option<&WhereClause>,
+    ) -> Result<usize> {
+        let matching_ids = self.matching_row_ids(where_clause);
+        self.soft_delete_rows(&matching_ids);
+        Ok(matching_ids.len())
+    }
+
+    /// Soft-delete live rows by id
+    pub(crate) fn soft_delete_rows(&mut self, ids: &[u64]) {
+        let deleted_at = now_millis();
+
+        for id in ids {
+            if let Some(row) = self.rows.remove(id) {
+                self.remove_unique_indexes(&row);
+                self.update_views(&row, -1);
+                self.tombstones.insert(*id, row);
+            }
+            if let Some(store) = &mut self.columnar {
+                store.remove(*id);
+            }
+            self.history.entry(*id).or_default().deleted_at = Some(deleted_at);
+        }
+        self.delete_nodes(ids);
+    }
+
+    /// IDs of live rows matching the where clause, found as `plan` chooses
+    pub(crate) fn matching_row_ids(&self, where_clause: Option<&WhereClause>) -> Vec<u64> {
+        self.planned_rows(self.plan(where_clause), where_clause)
+            .map(|row| row.id)
+            .collect()
+    }
+
+    /// Rows a condition selects through an index, if it can use one:
+    /// `id = n` or `id IN (...)` on the auto-assigned id column, or the same
+    /// on a UNIQUE column. IDs are live rows, in ascending order.
+    pub(crate) fn index_lookup(&self, cond: &Condition) -> Option<AccessPath> {
+        if cond.expr.is_some() {
+            return None;
+        }
+        let values: Vec<&Value> = match (&cond.operator, &cond.value) {
+            (ComparisonOp::Eq, ConditionValue::Single(v)) => vec![v],
+            (ComparisonOp::In, ConditionValue::List(list)) => list.iter().collect(),
+            _ => return None,
+        };
+        let ids = self.lookup_rows(&cond.column, &values)?;
+        Some(if self.column_index(&cond.column).is_some_and(|idx| Some(idx) == self.row_id_column()) {
+            AccessPath::RowIdLookup { ids }
+        } else {
+            AccessPath::UniqueIndex { column: cond.column.clone(), ids }
+        })
+    }
+
+    /// Live rows whose `column` equals one of `values`, found through the
+    /// row id for an INTEGER `id` column or the index of a UNIQUE column;
+    /// None for other columns. IDs are in ascending order.
+    pub(crate) fn lookup_rows(&self, column: &str, values: &[&Value]) -> Option<Vec<u64>> {
+        let idx = self.column_index(column)?;
+        let mut ids: Vec<u64> = if Some(idx) == self.row_id_column() {
+            values.iter()
+                .filter_map(|v| match v {
+                    Value::Integer(n) if *n > 0 => Some(*n as u64),
+                    _ => None,
+                })
+                .filter(|id| self.rows.contains_key(id))
+                .collect()
+        } else {
+            let unique_index = self.unique_indexes.get(column)?;
+            values.iter()
+                .flat_map(|v| self.lookup_keys(idx, v))
+                .filter_map(|key| unique_index.get(&key).copied())
+                .collect()
+        };
+        ids.sort_unstable();
+        ids.dedup();
+        Some(ids)
+    }
+
+    /// Unique index keys of the stored values `value` compares equal to:
+    /// integers and floats of the same number are equal but keyed apart
+    fn lookup_keys(&self, idx: usize, value: &Value) -> Vec<String> {
+        if self.schema.columns[idx].data_type == ColumnType::Uuid {
+            return value.as_uuid().map(|u| Self::value_to_string(&Value::Uuid(u))).into_iter().collect();
+        }
+        let mut keys = vec![self.collation_key(idx, value)];
+        match value {
+            Value::Integer(n) => keys.push(Self::value_to_string(&Value::Float(*n as f64))),
+            Value::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+                keys.push(Self::value_to_string(&Value::Integer(*f as i64)));
+            }
+            _ => {}
+        }
+        keys
+    }
+
+    /// Remove rows' vectors from the graph in one batch
+    fn delete_nodes(&mut self, row_ids: &[u64]) {
+        let node_ids: Vec<NodeId> = row_ids.iter()
+            .filter_map(|id| self.node_ids.remove(id))
+            .collect();
+
+        for node_id in &node_ids {
+            self.row_ids.remove(node_id);
+        }
+
+        self.graph.delete_batch(&node_ids);
+    }
+
+    /// Row whose vector is graph node `node_id`
+    pub(crate) fn node_row(&self, node_id: NodeId) -> Option<u64> {
+        self.row_ids.get(&node_id).copied()
+    }
+
+    /// Record which graph node holds a row's vector
+    fn link_node(&mut self, row_id: u64, node_id: NodeId) {
+        self.node_ids.insert(row_id, node_id);
+        self.row_ids.insert(node_id, row_id);
+    }
+
+    /// Re-add a persisted row, indexing its vector.
+    /// Used when loading a table from disk.
+    pub(crate) fn restore_row(&mut self, row: Row) {
+        let node_id = match self.extract_vector(&row.values) {
+            Ok(vector) => Some(self.graph.insert(vector)),
+            Err(_) => None,
+        };
+        self.restore_indexed_row(row, node_id);
+    }
+
+    /// Re-add a persisted row whose vector is already graph node `node_id`.
+    /// Used when loading a sealed snapshot, which stores the graph.
+    pub(crate) fn restore_indexed_row(&mut self, row: Row, node_id: Option<NodeId>) {
+        if let Some(node_id) = node_id {
+            self.link_node(row.id, node_id);
+        }
+        self.update_unique_indexes(&row);
+        if let Some(store) = &mut self.columnar {
+            store.insert(&row);
+        }
+        self.update_views(&row, 1);
+        self.rows.insert(row.id, row);
+    }
+
+    /// Put a persisted row in place of the row with its id, live or
+    /// soft-deleted, re-indexing its vector. Used when applying a delta file.
+    pub(crate) fn replace_row(&mut self, row: Row, history: RowHistory) {
+        self.discard_row(row.id);
+        self.tombstones.remove(&row.id);
+        self.history.insert(row.id, history);
+        self.restore_row(row);
+    }
+
+    /// Take a live row out of the table and its index without recording a
+    /// deletion. Used when applying a delta file.
+    pub(crate) fn discard_row(&mut self, id: u64) -> Option<Row> {
+        let row = self.rows.remove(&id)?;
+        if let Some(store) = &mut self.columnar {
+            store.remove(id);
+        }
+        self.remove_unique_indexes(&row);
+        self.update_views(&row, -1);
+        self.delete_nodes(&[id]);
+        Some(row)
+    }
+
+    /// Reconnect graph nodes that lost neighbors to deletions.
+    /// Returns the number of nodes repaired.
+    pub fn repair_index(&mut self) -> usize {
+        self.graph.repair()
+    }
+
+    /// `repair_index` with a `(done, total)` callback that may stop it early
+    pub fn repair_index_with_progress(&mut self, progress: &mut dyn FnMut(usize, usize) -> bool) -> usize {
+        self.graph.repair_with_progress(progress)
+    }
+
+    /// Get the vector index configuration
+    pub fn graph_config(&self) -> &GraphConfig {
+        self.graph.config()
+    }
+
+    /// Apply `ALTER TABLE ... SET (name = value, ...)` index settings,
+    /// `compress_text` and `keep_history`. Returns whether the index had to
+    /// be rebuilt.
+    pub fn set_index_options(&mut self, settings: &[(String, Value)]) -> Result<bool> {
+        let mut config = self.graph.config().clone();
+        let mut compress_text = self.compress_text;
+        let mut keep_history = self.keep_history;
+        for (name, value) in settings {
+            let invalid = || MarsError::InvalidConfig(format!("Invalid value for {}: {:?}", name, value));
+            let count = || value.as_integer().filter(|n| *n >= 1).map(|n| n as usize).ok_or_else(invalid);
+            let alpha = || value.as_float().filter(|a| *a >= 1.0).map(|a| a as f32).ok_or_else(invalid);
+            match name.as_str() {
+                "max_neighbors" => config.max_neighbors = count()?,
+                "search_buffer" => config.search_buffer = count()?,
+                "entry_points" => config.entry_points = count()?,
+                "alpha_strict" => config.alpha_strict = alpha()?,
+                "alpha_relaxed" => config.alpha_relaxed = alpha()?,
+                "seed" => config.seed = match value {
+                    Value::Null => None,
+                    _ => Some(value.as_integer().filter(|n| *n >= 0).ok_or_else(invalid)? as u64),
+                },
+                "compress_text" => compress_text = value.as_integer().filter(|n| *n >= 0).ok_or_else(invalid)? as usize,
+                "keep_history" => keep_history = value.as_integer().filter(|n| *n >= 0).ok_or_else(invalid)? as usize,
+                _ => return Err(MarsError::InvalidConfig(format!("Unknown index setting: {}", name))),
+            }
+        }
+        self.compress_text = compress_text;
+        self.set_keep_history(keep_history);
+        Ok(self.set_graph_config(config))
+    }
+
+    /// Size in bytes from which TEXT values are compressed on save; 0 if
+    /// compression is off
+    pub fn compress_text(&self) -> usize {
+        self.compress_text
+    }
+
+    /// Compress TEXT values of at least `threshold` bytes on save; 0 turns
+    /// compression off
+    pub fn set_compress_text(&mut self, threshold: usize) {
+        self.compress_text = threshold;
+    }
+
+    /// Earlier versions of each row kept for AS OF queries; 0 if updates
+    /// keep none
+    pub fn keep_history(&self) -> usize {
+        self.keep_history
+    }
+
+    /// Keep the last `versions` values each row had before an update, so
+    /// AS OF sees them; versions beyond that are dropped now and on later
+    /// updates. 0 (the default) keeps none.
+    pub fn set_keep_history(&mut self, versions: usize) {
+        self.keep_history = versions;
+        for history in self.history.values_mut() {
+            let excess = history.previous.len().saturating_sub(versions);
+            history.previous.drain(..excess);
+        }
+    }
+
+    /// VACUUM: drop every earlier row version kept for AS OF. Returns the
+    /// number of versions dropped.
+    pub fn vacuum(&mut self) -> usize {
+        self.history.values_mut()
+            .map(|history| std::mem::take(&mut history.previous).len())
+            .sum()
+    }
+
+    /// Apply an ALTER TABLE action. Returns whether the index was rebuilt.
+    pub fn alter(&mut self, action: &AlterAction) -> Result<bool> {
+        match action {
+            AlterAction::SetIndexOptions(settings) => self.set_index_options(settings),
+            AlterAction::SetMask { column, mask } => self.set_mask(column, *mask).map(|_| false),
+            AlterAction::SetVectorStorage(VectorStorage::Float32) => {
+                self.quantizer = None;
+                Ok(false)
+            }
+            AlterAction::SetVectorStorage(VectorStorage::Int8 { sample }) => self.calibrate_int8(*sample).map(|_| true),
+        }
+    }
+
+    /// Switch to INT8 vector storage: fit a quantizer to up to `sample`
+    /// stored vectors, spread evenly over the row ids, round every stored
+    /// vector with it and rebuild the index. The report estimates the
+    /// recall cost on the sample. Vectors already rounded by an earlier
+    /// calibration stay rounded.
+    pub fn calibrate_int8(&mut self, sample: usize) -> Result<CalibrationReport> {
+        let mut vectors: Vec<(u64, &[f32])> = self.dense_vectors().map(|(row, v)| (row.id, v)).collect();
+        vectors.sort_unstable_by_key(|(id, _)| *id);
+        let step = vectors.len().div_ceil(sample.max(1)).max(1);
+        let sampled: Vec<&[f32]> = vectors.iter().step_by(step).map(|(_, v)| *v).collect();
+        let (quantizer, report) = quantize::calibrate(&sampled, self.graph.metric(), CALIBRATION_QUERIES, CALIBRATION_K)
+            .ok_or_else(|| MarsError::InvalidConfig(format!("Cannot calibrate INT8 storage: '{}' has no vectors", self.name())))?;
+
+        if let Some(idx) = self.vector_column_index() {
+            for row in self.rows.values_mut() {
+                if let Some(Value::Vector(v)) = row.values.get_mut(idx) {
+                    quantizer.round(v);
+                }
+            }
+        }
+        self.quantizer = Some(quantizer);
+        self.rebuild_index(self.graph.config().clone());
+        Ok(report)
+    }
+
+    /// Train a projection of the dense vector column to `dimension`
+    /// components, on up to `sample` stored vectors for PCA, then project
+    /// every stored vector and rebuild the index. Later inserts and queries
+    /// are projected too. Returns the share of the sample's squared length
+    /// a PCA projection keeps.
+    pub fn create_projection(
+        &mut self,
+        name: &str,
+        column: &str,
+        dimension: usize,
+        method: ProjectionMethod,
+        sample: usize,
+    ) -> Result<Option<f64>> {
+        if self.column_index(column).is_none() {
+            return Err(MarsError::ColumnNotFound(column.to_string()));
+        }
+        if self.schema.vector_column.as_deref() != Some(column) {
+            return Err(MarsError::InvalidConfig(format!("Projections apply to the dense VECTOR column, not '{}'", column)));
+        }
+        if let Some(existing) = &self.projection {
+            return Err(MarsError::InvalidConfig(format!("'{}' already has projection '{}'", self.name(), existing.name)));
+        }
+        if self.quantizer.is_some() {
+            return Err(MarsError::InvalidConfig("Create projections before switching to INT8 vector storage".into()));
+        }
+
+        let input = self.graph.dimension();
+        let mut vectors: Vec<(u64, &[f32])> = self.dense_vectors().map(|(row, v)| (row.id, v)).collect();
+        vectors.sort_unstable_by_key(|(id, _)| *id);
+        let step = vectors.len().div_ceil(sample.max(1)).max(1);
+        let sampled: Vec<&[f32]> = vectors.iter().step_by(step).map(|(_, v)| *v).collect();
+        let (projection, retained) = Projection::train(name, method, input, dimension, &sampled)?;
+
+        self.projection = Some(projection);
+        if let Some(idx) = self.vector_column_index() {
+            let mut rows = std::mem::take(&mut self.rows);
+            for row in rows.values_mut() {
+                if let Some(Value::Vector(v)) = row.values.get_mut(idx) {
+                    self.encode_vector(v);
+                }
+            }
+            self.rows = rows;
+        }
+        let config = self.graph.config().clone();
+        self.graph = Graph::with_metric(dimension, config.clone(), self.graph.metric());
+        self.rebuild_index(config);
+        Ok(retained)
+    }
+
+    /// The projection applied to new vectors and queries, if any
+    pub fn projection(&self) -> Option<&Projection> {
+        self.projection.as_ref()
+    }
+
+    /// Set the projection of an empty table loaded from a file, whose rows
+    /// hold projected vectors
+    pub(crate) fn restore_projection(&mut self, projection: Projection) {
+        self.graph = Graph::with_metric(projection.dimension(), self.graph.config().clone(), self.graph.metric());
+        self.projection = Some(projection);
+    }
+
+    /// Scale and offset of INT8 vector storage, None for full precision
+    pub fn quantizer(&self) -> Option<&Quantizer> {
+        self.quantizer.as_ref()
+    }
+
+    /// Store vectors rounded by `quantizer`, as loaded from a file. Stored
+    /// vectors are left as they are.
+    pub(crate) fn set_quantizer(&mut self, quantizer: Option<Quantizer>) {
+        self.quantizer = quantizer;
+    }
+
+    /// Replace the vector index configuration. Settings that shape the
+    /// graph's edges rebuild the index; `search_buffer` alone does not.
+    /// Returns whether the index was rebuilt.
+    pub fn set_graph_config(&mut self, config: GraphConfig) -> bool {
+        let current = self.graph.config();
+        let rebuild = config.max_neighbors != current.max_neighbors
+            || config.alpha_strict != current.alpha_strict
+            || config.alpha_relaxed != current.alpha_relaxed
+            || config.entry_points != current.entry_points
+            || config.seed != current.seed;
+        if rebuild {
+            self.rebuild_index(config);
+        } else {
+            self.graph.set_config(config);
+        }
+        rebuild
+    }
+
+    /// Rebuild the vector index from the current rows with the current
+    /// configuration, on up to `threads` threads. Drops deleted slots and
+    /// any edges left by churn. Returns the edge statistics before and
+    /// after.
+    pub fn reindex(&mut self, threads: usize) -> (EdgeStats, EdgeStats) {
+        let before = self.graph.edge_stats();
+        self.graph = Graph::with_metric(self.graph.dimension(), self.graph.config().clone(), self.graph.metric());
+        self.node_ids.clear();
+        self.row_ids.clear();
+        let mut ids: Vec<u64> = self.rows.keys().copied().collect();
+        ids.sort_unstable();
+        self.index_rows(&ids, threads);
+        (before, self.graph.edge_stats())
+    }
+
+    /// Re-insert every row's vector into a fresh graph
+    fn rebuild_index(&mut self, config: GraphConfig) {
+        let mut graph = Graph::with_metric(self.graph.dimension(), config, self.graph.metric());
+        let mut ids: Vec<u64> = self.rows.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut links = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Ok(vector) = self.extract_vector(&self.rows[&id].values) {
+                links.push((id, graph.insert(vector)));
+            }
+        }
+
+        self.graph = graph;
+        self.node_ids.clear();
+        self.row_ids.clear();
+        for (row_id, node_id) in links {
+            self.link_node(row_id, node_id);
+        }
+    }
+
+    /// Add a materialized view over this table, computed from the current
+    /// rows
+    pub(crate) fn add_view(&mut self, def: ViewDef) -> Result<()> {
+        if self.view(&def.name).is_some() {
+            return Err(MarsError::InvalidConfig(format!("Materialized view '{}' already exists", def.name)));
+        }
+        let view = MaterializedView::new(def, self)?;
+        self.views.push(view);
+        Ok(())
+    }
+
+    /// Remove a materialized view. Returns whether it existed.
+    pub(crate) fn drop_view(&mut self, name: &str) -> bool {
+        let before = self.views.len();
+        self.views.retain(|view| view.def.name != name);
+        self.views.len() != before
+    }
+
+    /// Current contents of a materialized view over this table
+    pub(crate) fn view(&self, name: &str) -> Option<Table> {
+        self.views.iter().find(|view| view.def.name == name).map(|view| view.snapshot(self))
+    }
+
+    /// Definitions of the materialized views over this table
+    pub fn view_defs(&self) -> Vec<&ViewDef> {
+        self.views.iter().map(|view| &view.def).collect()
+    }
+
+    /// Add a REFERENCES constraint on one of this table's columns. The
+    /// parent side is checked by the database.
+    pub(crate) fn add_foreign_key(&mut self, foreign_key: ForeignKey) -> Result<()> {
+        let idx = self.column_index(&foreign_key.column)
+            .ok_or_else(|| MarsError::ColumnNotFound(foreign_key.column.clone()))?;
+        if foreign_key.on_delete == OnDelete::SetNull && !self.schema.columns[idx].nullable {
+            return Err(MarsError::InvalidConfig(format!(
+                "ON DELETE SET NULL needs a nullable column, but '{}' is NOT NULL", foreign_key.column,
+            )));
         }
+        self.foreign_keys.push(foreign_key);
+        Ok(())
+    }
 
-        // Apply LIMIT
-        if let Some(n) = limit {
-            results.truncate(n);
-        }
+    /// REFERENCES constraints of this table's columns
+    pub fn foreign_keys(&self) -> &[ForeignKey] {
+        &self.foreign_keys
+    }
 
-        // Apply DISTINCT
-        if distinct {
-            let mut seen = std::collections::HashSet::new();
-            results = results.into_iter()
-                .filter(|row| {
-                    let key = format!("{:?}", row.values);
-                    seen.insert(key)
-                })
-                .collect();
+    /// Number new rows as `auto_increment` says. A sequence's start only
+    /// applies to a table that has never held a row.
+    pub(crate) fn set_auto_increment(&mut self, auto_increment: AutoIncrement) -> Result<()> {
+        match &auto_increment {
+            AutoIncrement::Sequence { start, step } => {
+                if *start == 0 || *step == 0 {
+                    return Err(MarsError::InvalidConfig("AUTOINCREMENT START and STEP must be at least 1".into()));
+                }
+                if self.next_id == 1 && self.rows.is_empty() && self.tombstones.is_empty() {
+                    self.next_id = *start;
+                }
+            }
+            AutoIncrement::Uuid { column } => {
+                let idx = self.column_index(column).ok_or_else(|| MarsError::ColumnNotFound(column.clone()))?;
+                if !matches!(self.schema.columns[idx].data_type, ColumnType::Text | ColumnType::Uuid) {
+                    return Err(MarsError::InvalidConfig(format!("UUID keys need a UUID or TEXT column, but '{}' is neither", column)));
+                }
+            }
         }
+        self.auto_increment = Some(auto_increment);
+        Ok(())
+    }
 
-        // Project columns
-        results.into_iter()
-            .map(|row| self.project_row(row, columns))
-            .collect()
+    /// How new rows are numbered, if set with AUTOINCREMENT
+    pub fn auto_increment(&self) -> Option<&AutoIncrement> {
+        self.auto_increment.as_ref()
     }
 
-    /// Select by vector similarity
-    pub fn select_by_similarity(
-        &self,
-        query_vector: &[f32],
-        k: usize,
-        ef_search: usize,
-    ) -> Vec<(Row, f32)> {
-        let candidates = self.graph.query(query_vector, k, ef_search);
+    /// Recollect the column statistics used by the planner
+    pub fn analyze(&mut self) {
+        self.stats = Some(TableStats::collect(self));
+    }
 
-        candidates.into_iter()
-            .filter_map(|c| {
-                // Graph node ID corresponds to row ID - 1 (first insert gets graph_id=0, row_id=1)
-                let row_id = (c.id as u64) + 1;
-                self.rows.get(&row_id).map(|row| {
-                    (self.project_row(row, &[]), c.distance)
-                })
-            })
-            .collect()
+    /// Column statistics from the last ANALYZE, if any
+    pub fn stats(&self) -> Option<&TableStats> {
+        self.stats.as_ref()
     }
 
-    /// Update rows matching conditions
-    pub fn update(
-        &mut self,
-        assignments: &[(String, Value)],
-        where_clause: Option<&WhereClause>,
-    ) -> Result<usize> {
-        let matching_ids: Vec<u64> = self.rows.values()
-            .filter(|row| self.matches_where(row, where_clause))
-            .map(|row| row.id)
-            .collect();
+    pub(crate) fn set_stats(&mut self, stats: Option<TableStats>) {
+        self.stats = stats;
+    }
 
-        // Precompute column indices
-        let assignment_indices: Vec<(Option<usize>, Value)> = assignments.iter()
-            .map(|(col_name, value)| (self.column_index(col_name), value.clone()))
-            .collect();
+    /// Add (`sign` 1) or remove (`sign` -1) a row's contribution to every
+    /// materialized view
+    fn update_views(&mut self, row: &Row, sign: i64) {
+        if self.views.is_empty() {
+            return;
+        }
+        let mut views = std::mem::take(&mut self.views);
+        for view in &mut views {
+            view.apply(self, row, sign);
+        }
+        self.views = views;
+    }
 
-        let count = matching_ids.len();
+    /// Mask applied to a column for restricted connections
+    pub fn mask(&self, column: &str) -> Option<Mask> {
+        self.masks.get(column).copied()
+    }
 
-        for id in matching_ids {
-            if let Some(row) = self.rows.get_mut(&id) {
-                for (idx_opt, value) in &assignment_indices {
-                    if let Some(idx) = idx_opt {
-                        row.values[*idx] = value.clone();
-                    }
-                }
+    pub fn masks(&self) -> &HashMap<String, Mask> {
+        &self.masks
+    }
+
+    /// Set or clear (`None`) the mask of a column. `TRUNCATE` needs a TEXT column.
+    pub fn set_mask(&mut self, column: &str, mask: Option<Mask>) -> Result<()> {
+        let col = self.schema.columns.iter().find(|c| c.name == column)
+            .ok_or_else(|| MarsError::ColumnNotFound(column.to_string()))?;
+        match mask {
+            Some(Mask::Truncate(_)) if col.data_type != ColumnType::Text => Err(MarsError::InvalidConfig(
+                format!("MASK TRUNCATE requires a TEXT column, {} is {}", column, col.data_type.to_sql())
+            )),
+            Some(mask) => {
+                self.masks.insert(column.to_string(), mask);
+                Ok(())
+            }
+            None => {
+                self.masks.remove(column);
+                Ok(())
             }
         }
-
-        Ok(count)
     }
 
-    /// Delete rows matching conditions
-    pub fn delete(
-        &mut self,
-        where_clause: Option<&WhereClause>,
-    ) -> Result<usize> {
-        let matching_ids: Vec<u64> = self.rows.values()
-            .filter(|row| self.matches_where(row, where_clause))
-            .map(|row| row.id)
-            .collect();
+    /// Keep the values of a TEXT column in a text file next to the database
+    /// file (`TEXT EXTERNAL`)
+    pub fn set_external(&mut self, column: &str) -> Result<()> {
+        let col = self.schema.columns.iter().find(|c| c.name == column)
+            .ok_or_else(|| MarsError::ColumnNotFound(column.to_string()))?;
+        if col.data_type != ColumnType::Text {
+            return Err(MarsError::InvalidConfig(
+                format!("EXTERNAL requires a TEXT column, {} is {}", column, col.data_type.to_sql())
+            ));
+        }
+        if !self.external_columns.iter().any(|c| c == column) {
+            self.external_columns.push(column.to_string());
+        }
+        Ok(())
+    }
 
-        let count = matching_ids.len();
+    /// Columns declared `TEXT EXTERNAL`
+    pub fn external_columns(&self) -> &[String] {
+        &self.external_columns
+    }
 
-        for id in &matching_ids {
-            self.rows.remove(id);
-            // Note: We should also delete from graph, but need to map row ID to graph ID
-            let graph_id = (*id - 1) as NodeId;
-            self.graph.delete(graph_id);
+    /// Mask the values of a full row, in schema column order
+    pub fn mask_row(&self, mut values: Vec<Value>) -> Vec<Value> {
+        if !self.masks.is_empty() {
+            for (col, value) in self.schema.columns.iter().zip(values.iter_mut()) {
+                if let Some(mask) = self.masks.get(&col.name) {
+                    *value = mask.apply(value);
+                }
+            }
         }
-
-        Ok(count)
+        values
     }
 
     /// Get a row by ID
@@ -268,53 +2439,202 @@ impl Table {
         self.rows.get(&id)
     }
 
+    /// Build row values for the direct insert APIs. The vector is moved
+    /// into the last VECTOR column (cloned only for any others) and unknown
+    /// metadata columns are ignored. The row and the graph each hold their
+    /// own copy of the vector once it is inserted.
+    pub(crate) fn direct_row_values<'m>(
+        &self,
+        vector: Vec<f32>,
+        metadata: impl IntoIterator<Item = (&'m str, Value)>,
+    ) -> Result<Vec<Value>> {
+        let mut row_values: Vec<Value> = self.schema.columns.iter()
+            .map(|_| Value::Null)
+            .collect();
+
+        let vector_columns: Vec<usize> = self.schema.columns.iter()
+            .enumerate()
+            .filter(|(_, col)| matches!(col.data_type, ColumnType::Vector(_)))
+            .map(|(i, _)| i)
+            .collect();
+        if let Some((&last, others)) = vector_columns.split_last() {
+            for &i in others {
+                row_values[i] = Value::Vector(vector.clone());
+            }
+            row_values[last] = Value::Vector(vector);
+        }
+
+        for (col_name, value) in metadata {
+            if let Some(idx) = self.column_index(col_name) {
+                row_values[idx] = self.stored_value(idx, value)?;
+            }
+        }
+        Ok(row_values)
+    }
+
     /// Build row values from column names and provided values
-    fn build_row_values(&self, columns: &[String], values: Vec<Value>) -> Result<Vec<Value>> {
+    pub(crate) fn build_row_values(&self, columns: &[String], values: Vec<Value>) -> Result<Vec<Value>> {
         let mut row_values: Vec<Value> = self.schema.columns.iter()
             .map(|_| Value::Null)
             .collect();
 
         for (i, col_name) in columns.iter().enumerate() {
             let idx = self.column_index(col_name)
-                .ok_or_else(|| MarsError::InvalidFormat(format!("Unknown column: {}", col_name)))?;
+                .ok_or_else(|| MarsError::ColumnNotFound(col_name.to_string()))?;
 
-            row_values[idx] = values.get(i)
-                .ok_or_else(|| MarsError::InvalidFormat(format!("Missing value for column: {}", col_name)))?
-                .clone();
+            let value = values.get(i)
+                .ok_or_else(|| MarsError::InvalidFormat(format!("Missing value for column: {}", col_name)))?;
+            row_values[idx] = self.stored_value(idx, value.clone())?;
         }
 
         Ok(row_values)
     }
 
+    /// A value as column `idx` stores it: UUID columns take UUID text
+    pub(crate) fn stored_value(&self, idx: usize, value: Value) -> Result<Value> {
+        match (&self.schema.columns[idx].data_type, value) {
+            (ColumnType::Uuid, Value::Text(s)) => Uuid::parse(&s).map(Value::Uuid).ok_or_else(|| {
+                MarsError::InvalidFormat(format!("Invalid UUID '{}' for column '{}'", s, self.schema.columns[idx].name))
+            }),
+            (_, value) => Ok(value),
+        }
+    }
+
     /// Extract vector from row values, validating dimension matches schema
-    fn extract_vector(&self, values: &[Value]) -> Result<Vec<f32>> {
+    fn extract_vector<'v>(&self, values: &'v [Value]) -> Result<&'v [f32]> {
         let vec_col = self.schema.vector_column.as_ref()
             .ok_or_else(|| MarsError::InvalidConfig("No vector column defined".into()))?;
 
         let idx = self.column_index(vec_col)
-            .ok_or_else(|| MarsError::InvalidConfig("Vector column not found".into()))?;
+            .ok_or_else(|| MarsError::ColumnNotFound(vec_col.clone()))?;
 
         match &values[idx] {
             Value::Vector(v) => {
                 let expected_dim = self.graph.dimension();
                 if v.len() != expected_dim {
-                    return Err(MarsError::InvalidFormat(format!(
-                        "Vector dimension mismatch: expected {}, got {}",
-                        expected_dim, v.len()
-                    )));
+                    return Err(MarsError::ColumnDimensionMismatch {
+                        column: vec_col.clone(),
+                        expected: expected_dim,
+                        actual: v.len(),
+                    });
                 }
-                Ok(v.clone())
+                Ok(v)
             }
             _ => Err(MarsError::InvalidFormat("Vector column must contain a vector".into())),
         }
     }
 
+    /// Index of the dense vector column, if any
+    pub(crate) fn vector_column_index(&self) -> Option<usize> {
+        self.schema.vector_column.as_ref().and_then(|c| self.column_index(c))
+    }
+
+    /// Encode the dense vector of new row values in place; see
+    /// `encode_vector`
+    fn normalize_vector(&self, values: &mut [Value]) {
+        if !self.normalize && self.quantizer.is_none() && self.projection.is_none() {
+            return;
+        }
+        if let Some(Value::Vector(v)) = self.vector_column_index().and_then(|i| values.get_mut(i)) {
+            self.encode_vector(v);
+        }
+    }
+
+    /// A new vector as the table stores it: projected, L2-normalized if the
+    /// table normalizes on insert, and rounded to the levels of INT8
+    /// storage. Vectors that are not the projection's input size are taken
+    /// as already projected.
+    fn encode_vector(&self, v: &mut Vec<f32>) {
+        if let Some(projection) = self.projection.as_ref().filter(|p| p.input_dimension() == v.len()) {
+            *v = projection.project(v);
+        }
+        if self.normalize {
+            normalize(v);
+        }
+        if let Some(quantizer) = &self.quantizer {
+            quantizer.round(v);
+        }
+    }
+
+    /// Validate that every vector in a multi-vector column has the declared dimension
+    fn check_multi_vectors(&self, values: &[Value]) -> Result<()> {
+        for (col, value) in self.schema.columns.iter().zip(values) {
+            let (ColumnType::MultiVector(dim), Value::MultiVector(vectors)) = (&col.data_type, value) else {
+                continue;
+            };
+            if let Some(v) = vectors.iter().find(|v| v.len() != *dim) {
+                return Err(MarsError::ColumnDimensionMismatch { column: col.name.clone(), expected: *dim, actual: v.len() });
+            }
+        }
+        Ok(())
+    }
+
     /// Get column index by name
     pub fn column_index(&self, name: &str) -> Option<usize> {
         self.schema.columns.iter().position(|c| c.name == name)
     }
 
-    /// Check if a row matches where clause (supports AND/OR)
+    /// Value of a named column (or `_version`) in a row; NULL if unknown
+    fn column_value(&self, row: &Row, name: &str) -> Value {
+        match self.column_index(name) {
+            Some(idx) => row.values[idx].clone(),
+            None if name == "_version" => {
+                Value::Integer(self.history.get(&row.id).map_or(0, |h| h.version) as i64)
+            }
+            None => Value::Null,
+        }
+    }
+
+    /// Project a full row onto SELECT columns, evaluating function calls.
+    /// Aggregate columns are skipped.
+    pub fn project_expressions(&self, row: &Row, columns: &[SelectColumn]) -> Row {
+        let mut values = Vec::new();
+        for col in columns {
+            match col {
+                SelectColumn::All => values.extend(row.values.iter().cloned()),
+                SelectColumn::Column(name) => values.push(self.column_value(row, name)),
+                SelectColumn::Function { expr, .. } => values.push(expr.eval(&|name| self.column_value(row, name))),
+                SelectColumn::Aggregate { .. } => {}
+            }
+        }
+        Row::new(row.id, values)
+    }
+
+    /// Column names and types of rows produced by `project_expressions`;
+    /// no columns means the full row
+    pub fn result_schema(&self, columns: &[SelectColumn]) -> ResultSchema {
+        if columns.is_empty() {
+            return ResultSchema::from_schema(&self.schema);
+        }
+        let mut result = ResultSchema::default();
+        for col in columns {
+            match col {
+                SelectColumn::All => result.columns.extend(ResultSchema::from_schema(&self.schema).columns),
+                SelectColumn::Column(name) => {
+                    let data_type = match self.column_index(name) {
+                        Some(idx) => Some(self.schema.columns[idx].data_type.clone()),
+                        None if name == "_version" => Some(ColumnType::Integer),
+                        None => None,
+                    };
+                    result.columns.push(ResultColumn::new(name, data_type));
+                }
+                SelectColumn::Function { expr, alias } => {
+                    let name = alias.clone().unwrap_or_else(|| expr.to_string());
+                    let data_type = match expr {
+                        ScalarExpr::Call { func, .. } => func.result_type(),
+                        _ => None,
+                    };
+                    result.columns.push(ResultColumn::new(&name, data_type));
+                }
+                SelectColumn::Aggregate { .. } => {}
+            }
+        }
+        result
+    }
+
+    /// Check if a row matches where clause (supports AND/OR). Conditions
+    /// use SQL three-valued logic and rows whose clause is UNKNOWN (NULL)
+    /// do not match.
     pub fn matches_where(&self, row: &Row, where_clause: Option<&WhereClause>) -> bool {
         match where_clause {
             None => true,
@@ -323,129 +2643,123 @@ impl Table {
                     return true;
                 }
 
-                // Evaluate conditions with AND/OR connectors
+                // Evaluate conditions with AND/OR connectors; None is UNKNOWN
                 let mut result = self.matches_condition(row, &wc.conditions[0]);
 
                 for (i, connector) in wc.connectors.iter().enumerate() {
                     let cond_result = self.matches_condition(row, &wc.conditions[i + 1]);
-                    result = match connector {
-                        BoolConnector::And => result && cond_result,
-                        BoolConnector::Or => result || cond_result,
+                    result = match (connector, result, cond_result) {
+                        (BoolConnector::And, Some(false), _) | (BoolConnector::And, _, Some(false)) => Some(false),
+                        (BoolConnector::And, Some(true), Some(true)) => Some(true),
+                        (BoolConnector::Or, Some(true), _) | (BoolConnector::Or, _, Some(true)) => Some(true),
+                        (BoolConnector::Or, Some(false), Some(false)) => Some(false),
+                        _ => None,
                     };
                 }
 
-                result
+                result == Some(true)
             }
         }
     }
 
-    /// Check if a row matches a single condition
-    fn matches_condition(&self, row: &Row, cond: &crate::parser::Condition) -> bool {
+    /// Evaluate a single condition; `None` is UNKNOWN
+    fn matches_condition(&self, row: &Row, cond: &Condition) -> Option<bool> {
+        if let Some(expr) = &cond.expr {
+            let value = expr.eval(&|name| self.column_value(row, name));
+            return self.evaluate_condition(&value, &cond.operator, &cond.value, Collation::Binary);
+        }
+
         let idx = match self.column_index(&cond.column) {
             Some(i) => i,
-            None => return false,
+            // `_version` is a pseudo-column exposing the row's update counter
+            None if cond.column == "_version" => {
+                let version = self.history.get(&row.id).map_or(0, |h| h.version);
+                return self.evaluate_condition(&Value::Integer(version as i64), &cond.operator, &cond.value, Collation::Binary);
+            }
+            None => return Some(false),
         };
 
         let row_val = &row.values[idx];
-        self.evaluate_condition(row_val, &cond.operator, &cond.value)
+        if let (ComparisonOp::SimilarWithin(radius), ConditionValue::Single(Value::Vector(query))) = (&cond.operator, &cond.value) {
+            let vector = row_val.as_vector()?;
+            return Some(self.vector_distance(idx, query, vector)? <= *radius);
+        }
+        self.evaluate_condition(row_val, &cond.operator, &cond.value, self.schema.columns[idx].collation)
     }
 
-    /// Evaluate a condition against a value
-    fn evaluate_condition(&self, row_val: &Value, op: &ComparisonOp, cond_val: &ConditionValue) -> bool {
+    /// Evaluate a condition against a value; text is compared under `collation`.
+    /// Any comparison with NULL is UNKNOWN (`None`); only IS [NOT] NULL
+    /// tests for it.
+    fn evaluate_condition(&self, row_val: &Value, op: &ComparisonOp, cond_val: &ConditionValue, collation: Collation) -> Option<bool> {
+        use std::cmp::Ordering;
+
         match op {
-            ComparisonOp::Eq => {
-                if let ConditionValue::Single(v) = cond_val {
-                    self.values_equal(row_val, v)
-                } else {
-                    false
-                }
-            }
-            ComparisonOp::Ne => {
-                if let ConditionValue::Single(v) = cond_val {
-                    !self.values_equal(row_val, v)
-                } else {
-                    true
-                }
-            }
-            ComparisonOp::Lt => {
-                if let ConditionValue::Single(v) = cond_val {
-                    self.values_compare(row_val, v) == Some(std::cmp::Ordering::Less)
-                } else {
-                    false
-                }
-            }
-            ComparisonOp::Le => {
-                if let ConditionValue::Single(v) = cond_val {
-                    self.values_compare(row_val, v).map(|o| o != std::cmp::Ordering::Greater).unwrap_or(false)
-                } else {
-                    false
-                }
-            }
-            ComparisonOp::Gt => {
-                if let ConditionValue::Single(v) = cond_val {
-                    self.values_compare(row_val, v) == Some(std::cmp::Ordering::Greater)
-                } else {
-                    false
-                }
-            }
-            ComparisonOp::Ge => {
-                if let ConditionValue::Single(v) = cond_val {
-                    self.values_compare(row_val, v).map(|o| o != std::cmp::Ordering::Less).unwrap_or(false)
-                } else {
-                    false
-                }
+            ComparisonOp::IsNull => return Some(row_val.is_null()),
+            ComparisonOp::IsNotNull => return Some(!row_val.is_null()),
+            ComparisonOp::Similar
+            | ComparisonOp::SimilarExact
+            | ComparisonOp::SimilarDims(_)
+            | ComparisonOp::SimilarWeighted(_)
+            | ComparisonOp::SimilarWithin(_) => return Some(false), // Handled separately
+            _ => {}
+        }
+        if row_val.is_null() {
+            return None;
+        }
+
+        let compare = |v: &Value| -> Option<Option<Ordering>> {
+            if v.is_null() { None } else { Some(self.values_compare_collated(row_val, v, collation)) }
+        };
+
+        match (op, cond_val) {
+            (ComparisonOp::Eq, ConditionValue::Single(v)) => {
+                if v.is_null() { None } else { Some(self.values_equal(row_val, v, collation)) }
             }
-            ComparisonOp::Similar => false, // Handled separately
-            ComparisonOp::Like => {
-                if let ConditionValue::Single(Value::Text(pattern)) = cond_val {
-                    self.match_like(row_val, pattern)
-                } else {
-                    false
-                }
+            (ComparisonOp::Ne, ConditionValue::Single(v)) => {
+                if v.is_null() { None } else { Some(!self.values_equal(row_val, v, collation)) }
             }
-            ComparisonOp::NotLike => {
-                if let ConditionValue::Single(Value::Text(pattern)) = cond_val {
-                    !self.match_like(row_val, pattern)
-                } else {
-                    true
-                }
+            (ComparisonOp::Lt, ConditionValue::Single(v)) => compare(v).map(|o| o == Some(Ordering::Less)),
+            (ComparisonOp::Le, ConditionValue::Single(v)) => {
+                compare(v).map(|o| o.is_some_and(|o| o != Ordering::Greater))
             }
-            ComparisonOp::In => {
-                if let ConditionValue::List(values) = cond_val {
-                    values.iter().any(|v| self.values_equal(row_val, v))
-                } else {
-                    false
-                }
+            (ComparisonOp::Gt, ConditionValue::Single(v)) => compare(v).map(|o| o == Some(Ordering::Greater)),
+            (ComparisonOp::Ge, ConditionValue::Single(v)) => {
+                compare(v).map(|o| o.is_some_and(|o| o != Ordering::Less))
             }
-            ComparisonOp::NotIn => {
-                if let ConditionValue::List(values) = cond_val {
-                    !values.iter().any(|v| self.values_equal(row_val, v))
-                } else {
-                    true
-                }
+            (ComparisonOp::Like | ComparisonOp::NotLike, ConditionValue::Single(Value::Null)) => None,
+            (ComparisonOp::Like, ConditionValue::Single(Value::Text(pattern))) => {
+                Some(self.match_like(row_val, pattern))
             }
-            ComparisonOp::Between => {
-                if let ConditionValue::Range(low, high) = cond_val {
-                    self.values_compare(row_val, low).map(|o| o != std::cmp::Ordering::Less).unwrap_or(false)
-                        && self.values_compare(row_val, high).map(|o| o != std::cmp::Ordering::Greater).unwrap_or(false)
-                } else {
-                    false
-                }
+            (ComparisonOp::NotLike, ConditionValue::Single(Value::Text(pattern))) => {
+                Some(!self.match_like(row_val, pattern))
             }
-            ComparisonOp::NotBetween => {
-                if let ConditionValue::Range(low, high) = cond_val {
-                    !(self.values_compare(row_val, low).map(|o| o != std::cmp::Ordering::Less).unwrap_or(false)
-                        && self.values_compare(row_val, high).map(|o| o != std::cmp::Ordering::Greater).unwrap_or(false))
+            (ComparisonOp::Regexp, ConditionValue::Single(Value::Text(pattern))) => match row_val {
+                Value::Text(s) => Some(crate::functions::regex_match(s, pattern)),
+                _ => Some(false),
+            },
+            (ComparisonOp::NotRegexp, ConditionValue::Single(Value::Text(pattern))) => match row_val {
+                Value::Text(s) => Some(!crate::functions::regex_match(s, pattern)),
+                _ => Some(true),
+            },
+            // x IN (..., NULL) is UNKNOWN rather than false when nothing matches
+            (ComparisonOp::In | ComparisonOp::NotIn, ConditionValue::List(values)) => {
+                let found = if values.iter().any(|v| self.values_equal(row_val, v, collation)) {
+                    Some(true)
+                } else if values.iter().any(|v| v.is_null()) {
+                    None
                 } else {
-                    true
-                }
-            }
-            ComparisonOp::IsNull => {
-                matches!(row_val, Value::Null)
+                    Some(false)
+                };
+                if *op == ComparisonOp::In { found } else { found.map(|f| !f) }
             }
-            ComparisonOp::IsNotNull => {
-                !matches!(row_val, Value::Null)
+            (ComparisonOp::Between | ComparisonOp::NotBetween, ConditionValue::Range(low, high)) => {
+                let above = compare(low)?.is_some_and(|o| o != Ordering::Less);
+                let below = compare(high)?.is_some_and(|o| o != Ordering::Greater);
+                if *op == ComparisonOp::Between { Some(above && below) } else { Some(!(above && below)) }
             }
+            // Malformed value for the operator
+            (ComparisonOp::NotLike | ComparisonOp::NotRegexp | ComparisonOp::NotIn | ComparisonOp::NotBetween, _) => Some(true),
+            _ => Some(false),
         }
     }
 
@@ -519,26 +2833,34 @@ impl Table {
         pi == pattern_chars.len()
     }
 
-    fn values_equal(&self, a: &Value, b: &Value) -> bool {
+    fn values_equal(&self, a: &Value, b: &Value, collation: Collation) -> bool {
         match (a, b) {
             (Value::Null, Value::Null) => true,
             (Value::Integer(i1), Value::Integer(i2)) => i1 == i2,
             (Value::Float(f1), Value::Float(f2)) => f1 == f2,
-            (Value::Text(s1), Value::Text(s2)) => s1 == s2,
+            (Value::Text(s1), Value::Text(s2)) => collation.compare(s1, s2).is_eq(),
             (Value::Boolean(b1), Value::Boolean(b2)) => b1 == b2,
             (Value::Integer(i), Value::Float(f)) => (*i as f64) == *f,
             (Value::Float(f), Value::Integer(i)) => *f == (*i as f64),
+            (Value::Uuid(u), other) | (other, Value::Uuid(u)) => other.as_uuid() == Some(*u),
             _ => false,
         }
     }
 
     pub fn values_compare(&self, a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+        self.values_compare_collated(a, b, Collation::Binary)
+    }
+
+    /// Like `values_compare`, with text ordered by `collation`
+    pub fn values_compare_collated(&self, a: &Value, b: &Value, collation: Collation) -> Option<std::cmp::Ordering> {
         match (a, b) {
             (Value::Integer(i1), Value::Integer(i2)) => i1.partial_cmp(i2),
             (Value::Float(f1), Value::Float(f2)) => f1.partial_cmp(f2),
-            (Value::Text(s1), Value::Text(s2)) => s1.partial_cmp(s2),
+            (Value::Text(s1), Value::Text(s2)) => Some(collation.compare(s1, s2)),
             (Value::Integer(i), Value::Float(f)) => (*i as f64).partial_cmp(f),
             (Value::Float(f), Value::Integer(i)) => f.partial_cmp(&(*i as f64)),
+            (Value::Uuid(u), other) => other.as_uuid().map(|o| u.cmp(&o)),
+            (other, Value::Uuid(u)) => other.as_uuid().map(|o| o.cmp(u)),
             _ => None,
         }
     }
@@ -558,6 +2880,20 @@ impl Table {
         Row::new(row.id, values)
     }
 
+    /// Collation of a column; BINARY for unknown names
+    pub fn column_collation(&self, name: &str) -> Collation {
+        self.column_index(name).map_or(Collation::Binary, |idx| self.schema.columns[idx].collation)
+    }
+
+    /// Hash key for the value of column `idx`: equal under the column's
+    /// collation means equal keys. Used for GROUP BY, DISTINCT and UNIQUE.
+    pub fn collation_key(&self, idx: usize, value: &Value) -> String {
+        match (value, self.schema.columns.get(idx).map(|c| c.collation)) {
+            (Value::Text(s), Some(collation)) => format!("T:{}", collation.key(s)),
+            _ => Self::value_to_string(value),
+        }
+    }
+
     // ==================== UNIQUE CONSTRAINT HELPERS ====================
 
     /// Convert a Value to a string for hashing in unique index
@@ -570,17 +2906,29 @@ impl Table {
             Value::Boolean(b) => format!("B:{}", b),
             Value::Vector(v) => format!("V:{}", v.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",")),
             Value::Blob(b) => format!("L:{}", b.len()),
+            Value::Uuid(u) => format!("U:{}", u),
+            Value::SparseVector(v) => format!(
+                "S:{}",
+                v.iter().map(|(i, w)| format!("{}={}", i, w)).collect::<Vec<_>>().join(",")
+            ),
+            Value::MultiVector(v) => format!(
+                "M:{}",
+                v.iter()
+                    .map(|x| x.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(","))
+                    .collect::<Vec<_>>()
+                    .join(";")
+            ),
         }
     }
 
     /// Check if inserting these values would violate any unique constraints
     fn check_unique_constraints(&self, row_values: &[Value]) -> Result<()> {
-        for (col_name, unique_set) in &self.unique_indexes {
+        for (col_name, unique_index) in &self.unique_indexes {
             if let Some(col_idx) = self.column_index(col_name) {
-                let value_str = Self::value_to_string(&row_values[col_idx]);
+                let value_str = self.collation_key(col_idx, &row_values[col_idx]);
                 // Skip NULL values - they don't count for uniqueness
-                if value_str != "NULL" && unique_set.contains(&value_str) {
-                    return Err(MarsError::InvalidFormat(format!(
+                if value_str != "NULL" && unique_index.contains_key(&value_str) {
+                    return Err(MarsError::ConstraintViolation(format!(
                         "Duplicate value for UNIQUE column '{}'", col_name
                     )));
                 }
@@ -589,31 +2937,69 @@ impl Table {
         Ok(())
     }
 
-    /// Update unique indexes after a successful insert
-    fn update_unique_indexes(&mut self, row_values: &[Value]) {
-        // First, collect the (column_name, column_index) pairs
-        let col_indices: Vec<(String, usize, String)> = self.unique_indexes.keys()
-            .filter_map(|col_name| {
-                self.column_index(col_name).map(|idx| {
-                    (col_name.clone(), idx, Self::value_to_string(&row_values[idx]))
-                })
-            })
-            .collect();
-
-        // Then, update the unique indexes
-        for (col_name, _idx, value_str) in col_indices {
-            if value_str != "NULL" {
-                if let Some(unique_set) = self.unique_indexes.get_mut(&col_name) {
-                    unique_set.insert(value_str);
+    /// Check that per-row UPDATE assignments keep UNIQUE columns unique:
+    /// the updated rows get distinct values, none held by another row
+    fn check_unique_assignments(&self, updates: &[(u64, IndexedAssignments)]) -> Result<()> {
+        let ids: HashSet<u64> = updates.iter().map(|(id, _)| *id).collect();
+        let mut assigned: HashMap<(usize, String), u64> = HashMap::new();
+        for (id, assignment_indices) in updates {
+            let id = *id;
+            for (idx, value) in assignment_indices {
+                let Some(idx) = *idx else { continue };
+                let column = &self.schema.columns[idx].name;
+                let Some(unique_index) = self.unique_indexes.get(column) else { continue };
+                let key = self.collation_key(idx, value);
+                if key == "NULL" {
+                    continue;
                 }
+                let taken = unique_index.get(&key).is_some_and(|owner| !ids.contains(owner));
+                if taken || *assigned.entry((idx, key)).or_insert(id) != id {
+                    return Err(MarsError::ConstraintViolation(format!(
+                        "Duplicate value for UNIQUE column '{}'", column
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a live row's values to the unique indexes
+    fn update_unique_indexes(&mut self, row: &Row) {
+        for (col_name, key) in self.unique_keys(row) {
+            if key != "NULL"
+                && let Some(unique_index) = self.unique_indexes.get_mut(&col_name)
+            {
+                unique_index.insert(key, row.id);
+            }
+        }
+    }
+
+    /// Remove a row that is no longer live from the unique indexes
+    fn remove_unique_indexes(&mut self, row: &Row) {
+        for (col_name, key) in self.unique_keys(row) {
+            if let Some(unique_index) = self.unique_indexes.get_mut(&col_name)
+                && unique_index.get(&key) == Some(&row.id)
+            {
+                unique_index.remove(&key);
             }
         }
     }
+
+    /// (column, collation key) of each of a row's UNIQUE column values
+    fn unique_keys(&self, row: &Row) -> Vec<(String, String)> {
+        self.unique_indexes.keys()
+            .filter_map(|col_name| {
+                let idx = self.column_index(col_name)?;
+                Some((col_name.clone(), self.collation_key(idx, &row.values[idx])))
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::functions::ArithOp;
     use crate::schema::ColumnType;
 
     fn create_test_schema() -> Schema {
@@ -646,6 +3032,65 @@ mod tests {
         assert_eq!(table.len(), 1);
     }
 
+    #[test]
+    fn test_select_limit_and_scan() {
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        for i in 0..20 {
+            table.insert(
+                &["embedding".to_string(), "title".to_string()],
+                vec![Value::Vector(vec![i as f32, 0.0, 0.0]), Value::Text(format!("t{}", i % 4))],
+            ).unwrap();
+        }
+
+        assert_eq!(table.scan(None).count(), 20);
+        assert_eq!(table.select(&[], None, Some(5), Some(3), None, false).len(), 5);
+        assert_eq!(table.select(&[], None, Some(5), Some(18), None, false).len(), 2);
+
+        let order = OrderBy { column: "id".to_string(), ascending: false };
+        let ids: Vec<u64> = table.select(&[], None, Some(3), Some(1), Some(&order), false)
+            .iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![19, 18, 17]);
+
+        let titles = [SelectColumn::Column("title".to_string())];
+        assert_eq!(table.select(&titles, None, Some(3), None, None, true).len(), 3);
+        assert_eq!(table.select(&titles, None, Some(10), Some(2), None, true).len(), 2);
+    }
+
+    #[test]
+    fn test_direct_rows_match_graph_vectors() {
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        let values = table.direct_row_values(vec![1.0, 2.0, 3.0], [("title", Value::Text("a".into())), ("missing", Value::Integer(1))]).unwrap();
+        assert_eq!(values[1], Value::Vector(vec![1.0, 2.0, 3.0]));
+        assert_eq!(values[2], Value::Text("a".into()));
+        let id = table.insert_row(values).unwrap();
+
+        let batch = (0..3).map(|i| table.direct_row_values(vec![i as f32, 1.0, 1.0], []).unwrap()).collect();
+        let ids = table.insert_batch(batch).unwrap();
+        assert!(table.insert_batch(vec![table.direct_row_values(vec![1.0], []).unwrap()]).is_err());
+
+        for id in std::iter::once(id).chain(ids) {
+            let Value::Vector(stored) = &table.get(id).unwrap().values[1] else { panic!("Expected vector") };
+            assert_eq!(table.graph.vector(table.node_ids[&id]), Some(stored.as_slice()));
+        }
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let schema = create_test_schema();
+        let mut table = Table::new(schema, GraphConfig::default()).unwrap();
+        let empty = table.memory_usage();
+
+        let values = vec![Value::Null, Value::Vector(vec![1.0, 2.0, 3.0]), Value::Text("hello".to_string())];
+        let estimate = table.row_memory(&values);
+        assert_eq!(estimate.vectors, 3 * size_of::<f32>());
+        assert!(estimate.rows > 5 && estimate.graph > 0);
+
+        table.insert_row(values).unwrap();
+        let used = table.memory_usage();
+        assert_eq!(used.vectors, empty.vectors + 3 * size_of::<f32>());
+        assert!(used.rows > empty.rows && used.graph > empty.graph);
+    }
+
     #[test]
     fn test_select() {
         let schema = create_test_schema();
@@ -664,4 +3109,247 @@ mod tests {
         let rows = table.select(&[], None, None, None, None, false);
         assert_eq!(rows.len(), 2);
     }
+
+    #[test]
+    fn test_update_if_version() {
+        let schema = create_test_schema();
+        let mut table = Table::new(schema, GraphConfig::default()).unwrap();
+
+        let id = table.insert(
+            &["embedding".to_string(), "title".to_string()],
+            vec![Value::Vector(vec![1.0, 0.0, 0.0]), Value::Text("First".to_string())],
+        ).unwrap();
+        assert_eq!(table.row_version(id), Some(1));
+
+        let assignments = vec![("title".to_string(), Value::Text("Second".to_string()))];
+        assert_eq!(table.update_if_version(id, &assignments, 1).unwrap(), 2);
+
+        // A stale writer gets a conflict and nothing changes
+        let stale = vec![("title".to_string(), Value::Text("Stale".to_string()))];
+        assert!(matches!(
+            table.update_if_version(id, &stale, 1),
+            Err(MarsError::VersionConflict { expected: 1, actual: 2, .. })
+        ));
+        assert_eq!(table.get(id).unwrap().values[2], Value::Text("Second".to_string()));
+
+        // Plain updates bump the version and `_version` is usable in WHERE
+        let where_clause = WhereClause {
+            conditions: vec![crate::parser::Condition::new(
+                "_version".to_string(),
+                ComparisonOp::Eq,
+                ConditionValue::Single(Value::Integer(2)),
+            )],
+            connectors: vec![],
+        };
+        let stale = vec![("title".to_string(), ScalarExpr::Literal(Value::Text("Stale".to_string())))];
+        assert_eq!(table.update(&stale, Some(&where_clause)).unwrap(), 1);
+        assert_eq!(table.update(&stale, Some(&where_clause)).unwrap(), 0);
+        assert_eq!(table.row_version(id), Some(3));
+    }
+
+    #[test]
+    fn test_update_expressions() {
+        let mut schema = create_test_schema().column("rank", ColumnType::Integer);
+        schema.columns[3] = Column::new("rank", ColumnType::Integer).unique();
+        let mut table = Table::new(schema, GraphConfig::default()).unwrap();
+        for rank in 1..=3 {
+            table.insert_row(vec![Value::Null, Value::Vector(vec![1.0, 0.0, 0.0]), Value::Null, Value::Integer(rank)]).unwrap();
+        }
+        let expr = |op, column: &str, n| ScalarExpr::Binary {
+            op,
+            left: Box::new(ScalarExpr::Column(column.to_string())),
+            right: Box::new(ScalarExpr::Literal(Value::Integer(n))),
+        };
+
+        // Each row sees its own old values, so shifting a UNIQUE column is fine
+        assert_eq!(table.update(&[("rank".to_string(), expr(ArithOp::Add, "rank", 1))], None).unwrap(), 3);
+        let ranks: Vec<Value> = (1..=3).map(|id| table.get(id).unwrap().values[3].clone()).collect();
+        assert_eq!(ranks, [Value::Integer(2), Value::Integer(3), Value::Integer(4)]);
+
+        // Collapsing rows onto one value is not
+        assert!(matches!(
+            table.update(&[("rank".to_string(), expr(ArithOp::Rem, "rank", 2))], None),
+            Err(MarsError::ConstraintViolation(_))
+        ));
+        assert_eq!(table.get(1).unwrap().values[3], Value::Integer(2));
+        assert!(matches!(
+            table.update(&[("rank".to_string(), expr(ArithOp::Add, "missing", 1))], None),
+            Err(MarsError::ColumnNotFound(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_insert_or() {
+        let mut schema = create_test_schema().column("code", ColumnType::Integer);
+        schema.columns[2] = Column::new("title", ColumnType::Text).unique();
+        schema.columns[3] = Column::new("code", ColumnType::Integer).unique();
+        let mut table = Table::new(schema, GraphConfig::default()).unwrap();
+        let row = |v: [f32; 3], title: &str, code: i64| {
+            vec![Value::Null, Value::Vector(v.to_vec()), Value::Text(title.into()), Value::Integer(code)]
+        };
+        let a = table.insert_row(row([1.0, 0.0, 0.0], "a", 1)).unwrap();
+        let b = table.insert_row(row([0.0, 1.0, 0.0], "b", 2)).unwrap();
+
+        assert_eq!(table.insert_or(row([0.0, 0.0, 1.0], "a", 9), OnConflict::Ignore).unwrap(), None);
+        assert!(matches!(table.insert_or(row([0.0, 0.0, 1.0], "a", 9), OnConflict::Abort), Err(MarsError::ConstraintViolation(_))));
+        assert_eq!(table.len(), 2);
+
+        // The replaced row keeps its id and its vector is re-indexed
+        assert_eq!(table.insert_or(row([0.0, 0.0, 1.0], "a", 3), OnConflict::Replace).unwrap(), Some(a));
+        assert_eq!(table.get(a).unwrap().values[0], Value::Integer(a as i64));
+        assert_eq!(table.get(a).unwrap().values[3], Value::Integer(3));
+        assert_eq!(table.graph.len(), 2);
+        let nearest = table.select_by_similarity(&[0.0, 0.0, 1.0], 1, 10);
+        assert_eq!((nearest[0].0.id, nearest[0].1), (a, 0.0));
+
+        // A row conflicting with two rows replaces the first and deletes the other
+        assert_eq!(table.insert_or(row([1.0, 1.0, 0.0], "a", 2), OnConflict::Replace).unwrap(), Some(a));
+        assert!(table.get(b).is_none());
+        assert_eq!((table.len(), table.graph.len()), (1, 1));
+        assert_eq!(table.lookup_rows("code", &[&Value::Integer(2)]), Some(vec![a]));
+
+        // Rows of one batch must not collide with each other either
+        let batch = vec![row([1.0, 0.0, 0.0], "c", 7), row([0.0, 1.0, 0.0], "c", 8)];
+        assert!(matches!(table.insert_batch(batch), Err(MarsError::ConstraintViolation(_))));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_deferred_index() {
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap().with_deferred_index();
+        let row = |i: usize| vec![Value::Null, Value::Vector(vec![i as f32, 1.0, 0.0]), Value::Text(format!("t{}", i))];
+        table.insert_row(row(0)).unwrap();
+        table.insert_batch((1..200).map(row).collect()).unwrap();
+        assert_eq!((table.len(), table.graph.len()), (200, 0));
+        assert!(table.is_index_deferred());
+
+        assert_eq!(table.build_deferred_index(4), 200);
+        assert!(!table.is_index_deferred());
+        assert_eq!(table.graph.len(), 200);
+        let nearest = table.select_by_similarity(&[57.0, 1.0, 0.0], 1, 50);
+        assert_eq!((nearest[0].0.values[2].clone(), nearest[0].1), (Value::Text("t57".into()), 0.0));
+
+        // Later inserts are indexed as they arrive
+        table.insert_row(row(500)).unwrap();
+        assert_eq!(table.graph.len(), 201);
+        assert_eq!(table.build_deferred_index(4), 0);
+    }
+
+    #[test]
+    fn test_reindex() {
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        let row = |i: usize| vec![Value::Null, Value::Vector(vec![(i % 20) as f32, (i / 20) as f32, 0.0]), Value::Null];
+        let ids = table.insert_batch((0..300).map(row).collect()).unwrap();
+        table.delete_rows(&ids[..200]);
+
+        let (before, after) = table.reindex(3);
+        assert_eq!((before.nodes, before.free_slots), (100, 200));
+        assert_eq!((after.nodes, after.free_slots, after.dangling, after.isolated), (100, 0, 0, 0));
+        assert_eq!(table.graph.len(), 100);
+        for &id in &ids[200..] {
+            let vector = table.extract_vector(&table.get(id).unwrap().values).unwrap().to_vec();
+            assert_eq!(table.select_by_similarity(&vector, 1, 64)[0].0.id, id);
+        }
+    }
+
+    #[test]
+    fn test_check_recall() {
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        let row = |i: usize| vec![Value::Null, Value::Vector(vec![(i % 20) as f32, (i / 20) as f32, 0.0]), Value::Null];
+        for i in 0..400 {
+            table.insert_row(row(i)).unwrap();
+        }
+
+        let report = table.check_recall(50, 5, 64);
+        assert_eq!((report.queries, report.k), (50, 5));
+        assert!(report.recall > 0.9 && report.min_recall <= report.recall, "{:?}", report);
+        assert_eq!(table.check_recall(1000, 5, 64).queries, 400);
+
+        // Without edges the graph only finds what its entry points reach
+        for id in 0..400 {
+            table.graph.get_mut(id).unwrap().neighbors.clear();
+        }
+        assert!(table.check_recall(50, 5, 64).recall < 0.5);
+
+        let empty = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        assert_eq!(empty.check_recall(10, 5, 64).queries, 0);
+    }
+
+    #[test]
+    fn test_export_graph() {
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        for i in 0..20 {
+            let title = if i == 3 { Value::Null } else { Value::Text(format!("doc \"{}\"", i)) };
+            table.insert_row(vec![Value::Null, Value::Vector(vec![i as f32, 0.0, 0.0]), title]).unwrap();
+        }
+        table.delete_rows(&[5]);
+        let dir = tempfile::tempdir().unwrap();
+
+        let json_path = dir.path().join("graph.json");
+        table.export_graph_json(&json_path, Some("title")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+        let nodes = json["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 19);
+        assert_eq!(nodes[0], serde_json::json!({"id": 1, "label": "doc \"0\""}));
+        assert_eq!(nodes[2], serde_json::json!({"id": 3, "label": "doc \"2\""}));
+        assert_eq!(nodes[3], serde_json::json!({"id": 4}));
+        let edges = json["edges"].as_array().unwrap();
+        assert!(!edges.is_empty());
+        assert!(edges.iter().all(|e| e["target"] != 5 && e["source"] != 5 && e["source"] != e["target"]));
+
+        let dot_path = dir.path().join("graph.dot");
+        table.export_graph_dot(&dot_path, None).unwrap();
+        let dot = std::fs::read_to_string(&dot_path).unwrap();
+        assert!(dot.starts_with("digraph \"test\" {\n  1;\n"));
+        assert_eq!(dot.matches(" -> ").count(), edges.len());
+        assert!(matches!(table.export_graph_dot(&dot_path, Some("missing")), Err(MarsError::ColumnNotFound(_))));
+    }
+
+    #[test]
+    fn test_auto_increment() {
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        table.set_auto_increment(AutoIncrement::Sequence { start: 100, step: 10 }).unwrap();
+        let row = |title: Value| vec![Value::Null, Value::Vector(vec![1.0, 0.0, 0.0]), title];
+        assert_eq!(table.insert_row(row(Value::Null)).unwrap(), 100);
+        assert_eq!(table.insert_batch(vec![row(Value::Null), row(Value::Null)]).unwrap(), [110, 120]);
+        assert_eq!(table.get(120).unwrap().values[0], Value::Integer(120));
+        assert!(table.set_auto_increment(AutoIncrement::Sequence { start: 1, step: 0 }).is_err());
+
+        let mut table = Table::new(create_test_schema(), GraphConfig::default()).unwrap();
+        table.set_auto_increment(AutoIncrement::Uuid { column: "title".into() }).unwrap();
+        let id = table.insert_row(row(Value::Null)).unwrap();
+        assert!(matches!(&table.get(id).unwrap().values[2], Value::Text(key) if key.len() == 36));
+        let id = table.insert_row(row(Value::Text("given".into()))).unwrap();
+        assert_eq!(table.get(id).unwrap().values[2], Value::Text("given".into()));
+        assert!(table.set_auto_increment(AutoIncrement::Uuid { column: "embedding".into() }).is_err());
+    }
+
+    #[test]
+    fn test_uuid_column() {
+        let mut id = Column::new("id", ColumnType::Uuid);
+        id.unique = true;
+        let mut schema = Schema::new("docs").column("embedding", ColumnType::Vector(2));
+        schema.columns.push(id);
+        let mut table = Table::new(schema, GraphConfig::default()).unwrap();
+        table.set_auto_increment(AutoIncrement::Uuid { column: "id".into() }).unwrap();
+        let columns = ["embedding".to_string(), "id".to_string()];
+        let row = |id: &str| vec![Value::Vector(vec![1.0, 0.0]), Value::Text(id.into())];
+
+        // UUID text is stored as 16 bytes, and the id column keeps it
+        let text = "6F1C2A9E-3B4D-4E5F-8A7B-1C2D3E4F5A6B";
+        let first = table.insert(&columns, row(text)).unwrap();
+        let uuid = Uuid::parse(text).unwrap();
+        assert_eq!(table.get(first).unwrap().values[1], Value::Uuid(uuid));
+        assert!(matches!(table.insert(&columns, row("nope")), Err(MarsError::InvalidFormat(_))));
+        assert!(matches!(table.insert(&columns, row(&uuid.to_string())), Err(MarsError::ConstraintViolation(_))));
+        let generated = table.insert(&columns[..1], vec![Value::Vector(vec![0.0, 1.0])]).unwrap();
+        assert!(matches!(table.get(generated).unwrap().values[1], Value::Uuid(u) if u != uuid));
+
+        // Text compares equal to the UUID it spells, through the unique index
+        let lowercase = Value::Text(text.to_lowercase());
+        assert_eq!(table.lookup_rows("id", &[&lowercase]), Some(vec![first]));
+        let cond = Condition::new("id".into(), ComparisonOp::Eq, ConditionValue::Single(lowercase));
+        let where_clause = WhereClause { conditions: vec![cond], connectors: vec![] };
+        assert_eq!(table.matching_row_ids(Some(&where_clause)), [first]);
+    }
 }
@@ -0,0 +1,120 @@
+//! UUIDs: parsing, formatting and random (version 4) generation without
+//! an RNG dependency.
+//!
+//! Each random UUID hashes a process-wide counter and the current time with
+//! a freshly keyed SipHash: `RandomState` keys come from the OS, so the bits
+//! are unpredictable across processes and distinct within one.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// A 128-bit UUID, stored as its 16 bytes and shown in the canonical
+/// lowercase `8-4-4-4-12` text form
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Uuid([u8; 16]);
+
+impl Uuid {
+    /// A random (version 4) UUID
+    pub fn new_v4() -> Self {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&random_u64().to_le_bytes());
+        bytes[8..].copy_from_slice(&random_u64().to_le_bytes());
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Uuid(bytes)
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Uuid(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Parse the 32 hex digits of a UUID, with or without the dashes of the
+    /// canonical form and optionally in braces
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let text = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')).unwrap_or(text);
+        let digits: Vec<u8> = text.bytes().filter(|&b| b != b'-').collect();
+        let dashes = text.len() - digits.len();
+        if digits.len() != 32 || !digits.iter().all(u8::is_ascii_hexdigit)
+            || (dashes != 0 && (dashes != 4 || misplaced_dashes(text)))
+        {
+            return None;
+        }
+        let mut bytes = [0u8; 16];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(Uuid(bytes))
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Uuid({})", self)
+    }
+}
+
+/// Whether dashed text has its dashes anywhere but the canonical places
+fn misplaced_dashes(text: &str) -> bool {
+    text.char_indices().any(|(i, c)| (c == '-') != matches!(i, 8 | 13 | 18 | 23))
+}
+
+/// 64 random bits
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(nanos);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_uuids() {
+        let a = Uuid::new_v4().to_string();
+        assert_eq!(a.len(), 36);
+        assert_eq!(&a[14..15], "4");
+        assert!(matches!(&a[19..20], "8" | "9" | "a" | "b"));
+        assert_eq!(a.matches('-').count(), 4);
+        assert_ne!(a, Uuid::new_v4().to_string());
+    }
+
+    #[test]
+    fn test_parse() {
+        let uuid = Uuid::new_v4();
+        let text = uuid.to_string();
+        assert_eq!(Uuid::parse(&text), Some(uuid));
+        assert_eq!(Uuid::parse(&text.to_uppercase()), Some(uuid));
+        assert_eq!(Uuid::parse(&text.replace('-', "")), Some(uuid));
+        assert_eq!(Uuid::parse(&format!("{{{}}}", text)), Some(uuid));
+        assert_eq!(Uuid::parse("not-a-uuid"), None);
+        assert_eq!(Uuid::parse(&text.replacen(|c: char| c.is_ascii_hexdigit(), "+", 1)), None);
+        assert_eq!(Uuid::parse(&text[1..]), None);
+        assert_eq!(Uuid::parse("0123456789abcdef-0123456789abcdef"), None);
+    }
+}
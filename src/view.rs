@@ -0,0 +1,276 @@
+//! Materialized aggregate views.
+//!
+//! `CREATE MATERIALIZED VIEW stats AS SELECT category, COUNT(*) FROM docs
+//! GROUP BY category` keeps one running state per group inside the base
+//! table. Inserts, updates and deletes adjust the state of the groups they
+//! touch, so reading the view never scans the base table. Only aggregates
+//! that can be undone on delete are allowed: COUNT, SUM and AVG.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MarsError, Result};
+use crate::parser::{AggregateFunc, Command, SelectColumn};
+use crate::schema::{Column, ColumnType, Row, Schema, Value};
+use crate::table::Table;
+
+/// An aggregate a materialized view can maintain incrementally
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ViewAggregate {
+    Count,
+    Sum,
+    Avg,
+}
+
+/// An output column of a materialized view
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ViewColumn {
+    /// A GROUP BY column
+    Group(String),
+    /// `column` is `*` for COUNT(*); `name` is the output column name
+    Aggregate { func: ViewAggregate, column: String, name: String },
+}
+
+/// Definition of a materialized view, as stored with its base table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewDef {
+    pub name: String,
+    pub group_by: Vec<String>,
+    pub columns: Vec<ViewColumn>,
+}
+
+impl ViewDef {
+    /// Definition from the SELECT of a CREATE MATERIALIZED VIEW, with the
+    /// base table's name
+    pub(crate) fn from_select(name: &str, select: &Command) -> Result<(String, Self)> {
+        let Command::Select { table, columns, where_clause, group_by, having, order_by, limit, offset, distinct, as_of, diversity, score } = select else {
+            return Err(MarsError::InvalidFormat("a materialized view must be defined by a SELECT".into()));
+        };
+        if where_clause.is_some() || having.is_some() || order_by.is_some() || limit.is_some() || offset.is_some()
+            || *distinct || as_of.is_some() || diversity.is_some() || score.is_some()
+        {
+            return Err(MarsError::InvalidFormat(
+                "a materialized view supports only SELECT columns and aggregates FROM table GROUP BY columns".into(),
+            ));
+        }
+        let group_by = group_by.clone().unwrap_or_default();
+
+        let columns = columns.iter()
+            .map(|column| match column {
+                SelectColumn::Column(name) if group_by.contains(name) => Ok(ViewColumn::Group(name.clone())),
+                SelectColumn::Column(name) => Err(MarsError::InvalidFormat(format!(
+                    "column '{}' of a materialized view must be in its GROUP BY", name,
+                ))),
+                SelectColumn::Aggregate { func, column, alias } => {
+                    let view_func = match func {
+                        AggregateFunc::Count => ViewAggregate::Count,
+                        AggregateFunc::Sum => ViewAggregate::Sum,
+                        AggregateFunc::Avg => ViewAggregate::Avg,
+                        _ => return Err(MarsError::InvalidFormat(format!(
+                            "{:?} cannot be maintained incrementally; materialized views support COUNT, SUM and AVG", func,
+                        ))),
+                    };
+                    let name = alias.clone().unwrap_or_else(|| format!("{:?}({})", func, column));
+                    Ok(ViewColumn::Aggregate { func: view_func, column: column.clone(), name })
+                }
+                _ => Err(MarsError::InvalidFormat(
+                    "a materialized view selects GROUP BY columns and aggregates only".into(),
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((table.clone(), ViewDef { name: name.to_string(), group_by, columns }))
+    }
+}
+
+/// Running totals of one group
+struct GroupState {
+    /// Values of the GROUP BY columns, from the first row seen
+    key_values: Vec<Value>,
+    rows: i64,
+    /// Per aggregate column: sum of its numbers, and the number of values
+    /// it counts
+    sums: Vec<f64>,
+    counts: Vec<i64>,
+}
+
+/// A view definition with its per-group state
+pub(crate) struct MaterializedView {
+    pub(crate) def: ViewDef,
+    /// Base table column index of each GROUP BY column
+    group_indices: Vec<usize>,
+    /// Each aggregate with the base table column index of its argument;
+    /// None for `*`
+    aggregates: Vec<(ViewAggregate, Option<usize>)>,
+    groups: HashMap<Vec<String>, GroupState>,
+}
+
+impl MaterializedView {
+    /// Resolve a definition against its base table and compute it from the
+    /// table's live rows
+    pub(crate) fn new(def: ViewDef, table: &Table) -> Result<Self> {
+        let index = |name: &str| table.column_index(name).ok_or_else(|| MarsError::ColumnNotFound(name.to_string()));
+        let group_indices = def.group_by.iter().map(|name| index(name)).collect::<Result<Vec<_>>>()?;
+        let aggregates = def.columns.iter()
+            .filter_map(|column| match column {
+                ViewColumn::Aggregate { func, column, .. } if column == "*" => Some(Ok((*func, None))),
+                ViewColumn::Aggregate { func, column, .. } => Some(index(column).map(|idx| (*func, Some(idx)))),
+                ViewColumn::Group(_) => None,
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut view = MaterializedView { def, group_indices, aggregates, groups: HashMap::new() };
+        for row in table.rows.values() {
+            view.apply(table, row, 1);
+        }
+        Ok(view)
+    }
+
+    /// Add (`sign` 1) or remove (`sign` -1) a row's contribution
+    pub(crate) fn apply(&mut self, table: &Table, row: &Row, sign: i64) {
+        let key: Vec<String> = self.group_indices.iter()
+            .map(|&idx| table.collation_key(idx, &row.values[idx]))
+            .collect();
+        let aggregates = self.aggregates.len();
+        let group = self.groups.entry(key.clone()).or_insert_with(|| GroupState {
+            key_values: self.group_indices.iter().map(|&idx| row.values[idx].clone()).collect(),
+            rows: 0,
+            sums: vec![0.0; aggregates],
+            counts: vec![0; aggregates],
+        });
+
+        group.rows += sign;
+        for (i, (func, index)) in self.aggregates.iter().enumerate() {
+            let Some(index) = index else { continue };
+            // COUNT(col) counts non-NULL values; SUM and AVG only numbers
+            match (&row.values[*index], func) {
+                (Value::Null, _) => {}
+                (Value::Integer(n), _) => {
+                    group.counts[i] += sign;
+                    group.sums[i] += sign as f64 * *n as f64;
+                }
+                (Value::Float(f), _) => {
+                    group.counts[i] += sign;
+                    group.sums[i] += sign as f64 * f;
+                }
+                (_, ViewAggregate::Count) => group.counts[i] += sign,
+                _ => {}
+            }
+        }
+        if group.rows <= 0 {
+            self.groups.remove(&key);
+        }
+    }
+
+    /// The view's columns, typed after the base table's
+    fn schema(&self, table: &Table) -> Schema {
+        let mut schema = Schema::new(&self.def.name);
+        for column in &self.def.columns {
+            let (name, data_type) = match column {
+                ViewColumn::Group(name) => {
+                    let base = &table.schema.columns[table.column_index(name).unwrap_or_default()];
+                    (name, base.data_type.clone())
+                }
+                ViewColumn::Aggregate { func: ViewAggregate::Count, name, .. } => (name, ColumnType::Integer),
+                ViewColumn::Aggregate { name, .. } => (name, ColumnType::Float),
+            };
+            schema.columns.push(Column::new(name, data_type));
+        }
+        schema
+    }
+
+    /// Current contents of the view as a table without a vector index, so
+    /// it can be queried like any other table
+    pub(crate) fn snapshot(&self, table: &Table) -> Table {
+        let mut snapshot = Table::without_index(self.schema(table));
+        let mut groups: Vec<&GroupState> = self.groups.values().collect();
+        groups.sort_by(|a, b| {
+            a.key_values.iter().zip(&b.key_values)
+                .map(|(x, y)| table.values_compare(x, y).unwrap_or(std::cmp::Ordering::Equal))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        for (id, group) in (1..).zip(groups) {
+            let mut key_values = group.key_values.iter();
+            let mut aggregate = 0;
+            let values = self.def.columns.iter()
+                .map(|column| match column {
+                    ViewColumn::Group(_) => key_values.next().cloned().unwrap_or(Value::Null),
+                    ViewColumn::Aggregate { func, column, .. } => {
+                        let i = aggregate;
+                        aggregate += 1;
+                        match func {
+                            ViewAggregate::Count if column == "*" => Value::Integer(group.rows),
+                            ViewAggregate::Count => Value::Integer(group.counts[i]),
+                            ViewAggregate::Sum => Value::Float(group.sums[i]),
+                            ViewAggregate::Avg if group.counts[i] == 0 => Value::Null,
+                            ViewAggregate::Avg => Value::Float(group.sums[i] / group.counts[i] as f64),
+                        }
+                    }
+                })
+                .collect();
+            snapshot.restore_indexed_row(Row::new(id, values), None);
+        }
+        snapshot.next_id = snapshot.len() as u64 + 1;
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::GraphConfig;
+    use crate::parser::parse;
+
+    fn table() -> Table {
+        let schema = Schema::new("docs")
+            .column("embedding", ColumnType::Vector(2))
+            .column("category", ColumnType::Text)
+            .column("score", ColumnType::Integer);
+        Table::new(schema, GraphConfig::default()).unwrap()
+    }
+
+    fn view(sql: &str) -> Result<ViewDef> {
+        ViewDef::from_select("stats", &parse(sql).unwrap()).map(|(_, def)| def)
+    }
+
+    #[test]
+    fn test_maintains_groups() {
+        let mut table = table();
+        let def = view("SELECT category, COUNT(*), AVG(score) AS mean FROM docs GROUP BY category;").unwrap();
+        let row = |category: &str, score: Value| vec![Value::Vector(vec![0.0, 1.0]), Value::Text(category.into()), score];
+        table.insert_row(row("a", Value::Integer(1))).unwrap();
+        let mut view = MaterializedView::new(def, &table).unwrap();
+
+        let inserted = [row("a", Value::Integer(3)), row("b", Value::Null)];
+        for values in inserted {
+            let id = table.insert_row(values).unwrap();
+            view.apply(&table, &table.rows[&id].clone(), 1);
+        }
+        let snapshot = |view: &MaterializedView| {
+            let snapshot = view.snapshot(&table);
+            let mut rows: Vec<_> = snapshot.rows.values().map(|row| row.values.clone()).collect();
+            rows.sort_by_key(|values| format!("{:?}", values));
+            rows
+        };
+        assert_eq!(snapshot(&view), [
+            vec![Value::Text("a".into()), Value::Integer(2), Value::Float(2.0)],
+            vec![Value::Text("b".into()), Value::Integer(1), Value::Null],
+        ]);
+
+        let first = table.rows[&1].clone();
+        view.apply(&table, &first, -1);
+        let last = table.rows[&3].clone();
+        view.apply(&table, &last, -1);
+        assert_eq!(snapshot(&view), [vec![Value::Text("a".into()), Value::Integer(1), Value::Float(3.0)]]);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_definitions() {
+        assert!(view("SELECT category, MAX(score) FROM docs GROUP BY category;").is_err());
+        assert!(view("SELECT category, score FROM docs GROUP BY category;").is_err());
+        assert!(view("SELECT category, COUNT(*) FROM docs WHERE score > 1 GROUP BY category;").is_err());
+        assert!(view("SELECT COUNT(*) FROM docs;").is_ok());
+    }
+}
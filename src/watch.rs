@@ -0,0 +1,55 @@
+//! Watching a database file for saves by other processes.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error::{MarsError, Result};
+
+/// Sets a flag whenever the watched file changes; stops watching on drop
+pub(crate) struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    changed: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+    /// Watch `path` and its delta files. Its directory is watched rather
+    /// than the file itself, so a file replaced by renaming another over it
+    /// is noticed too.
+    pub(crate) fn start(path: &Path) -> Result<Self> {
+        let changed = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&changed);
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let delta_prefix = format!("{}-delta-", name);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            // After an error events may have been lost, so assume a change
+            let relevant = event.map_or(true, |e| {
+                !e.kind.is_access() && e.paths.iter().any(|p| {
+                    let file = p.file_name().unwrap_or_default().to_string_lossy();
+                    file == name || file.starts_with(&delta_prefix)
+                })
+            });
+            if relevant {
+                flag.store(true, Ordering::Release);
+            }
+        }).map_err(watch_error)?;
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+        Ok(FileWatcher { _watcher: watcher, changed })
+    }
+
+    /// Check whether the file changed since the last call
+    pub(crate) fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::AcqRel)
+    }
+}
+
+fn watch_error(e: notify::Error) -> MarsError {
+    MarsError::Io(std::io::Error::other(e))
+}
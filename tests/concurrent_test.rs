@@ -1,7 +1,7 @@
 //! Integration tests for concurrent database operations
 
 use pardusdb::concurrent::{ConcurrentDatabase, DatabasePool};
-use pardusdb::{ExecuteResult, Value};
+use pardusdb::{ExecuteResult, MarsError, Value};
 use std::sync::Arc;
 use std::thread;
 
@@ -37,7 +37,7 @@ fn test_concurrent_multi_thread_insert() {
 
     // Verify all 100 items were inserted
     let result = conn.execute("SELECT * FROM items;").unwrap();
-    if let ExecuteResult::Select { rows } = result {
+    if let ExecuteResult::Select { rows, .. } = result {
         assert_eq!(rows.len(), 100);
     } else {
         panic!("Expected Select result");
@@ -78,7 +78,7 @@ fn test_concurrent_read_while_writing() {
     let reader = thread::spawn(move || {
         let mut conn = db_reader.connect();
         let result = conn.execute("SELECT * FROM data;").unwrap();
-        if let ExecuteResult::Select { rows } = result {
+        if let ExecuteResult::Select { rows, .. } = result {
             rows.len()
         } else {
             0
@@ -114,7 +114,7 @@ fn test_transaction_atomicity() {
 
     // Verify balances
     let result = conn.execute("SELECT * FROM accounts WHERE name = 'Alice';").unwrap();
-    if let ExecuteResult::Select { rows } = result {
+    if let ExecuteResult::Select { rows, .. } = result {
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].values[1], Value::Integer(70));
     }
@@ -137,7 +137,7 @@ fn test_transaction_rollback_on_error() {
 
     // Verify original value is preserved
     let result = conn.execute("SELECT * FROM test;").unwrap();
-    if let ExecuteResult::Select { rows } = result {
+    if let ExecuteResult::Select { rows, .. } = result {
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].values[1], Value::Integer(10));
     }
@@ -173,7 +173,7 @@ fn test_database_pool_clone() {
     // Verify all inserts
     let mut conn = pool.connect();
     let result = conn.execute("SELECT * FROM shared;").unwrap();
-    if let ExecuteResult::Select { rows } = result {
+    if let ExecuteResult::Select { rows, .. } = result {
         assert_eq!(rows.len(), 5);
     }
 }
@@ -211,6 +211,54 @@ fn test_direct_api_thread_safety() {
     assert_eq!(results.len(), 4);
 }
 
+#[test]
+fn test_optimistic_concurrency_counter() {
+    let db = Arc::new(ConcurrentDatabase::in_memory());
+    let mut conn = db.connect();
+
+    conn.execute("CREATE TABLE counters (embedding VECTOR(2), hits INTEGER);")
+        .unwrap();
+    conn.execute("INSERT INTO counters (embedding, hits) VALUES ([1.0, 0.0], 0);")
+        .unwrap();
+
+    // Each thread does read-modify-write with retry on conflict
+    let mut handles = vec![];
+
+    for _ in 0..4 {
+        let db_clone = Arc::clone(&db);
+        let handle = thread::spawn(move || {
+            let mut conn = db_clone.connect();
+            for _ in 0..25 {
+                loop {
+                    let version = conn.row_version("counters", 1).unwrap().unwrap();
+                    let hits = db_clone.with_read(|inner| {
+                        match inner.tables["counters"].get(1).unwrap().values[1] {
+                            Value::Integer(n) => n,
+                            _ => unreachable!(),
+                        }
+                    });
+                    match conn.update_if_version("counters", 1, vec![("hits", Value::Integer(hits + 1))], version) {
+                        Ok(_) => break,
+                        Err(MarsError::VersionConflict { .. }) => continue,
+                        Err(e) => panic!("unexpected error: {}", e),
+                    }
+                }
+            }
+        });
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // No lost updates
+    let conn = db.connect();
+    assert_eq!(conn.row_version("counters", 1).unwrap(), Some(101));
+    let hits = db.with_read(|inner| inner.tables["counters"].get(1).unwrap().values[1].clone());
+    assert_eq!(hits, Value::Integer(100));
+}
+
 #[test]
 fn test_concurrent_similarity_search() {
     let db = Arc::new(ConcurrentDatabase::in_memory());
@@ -288,10 +336,227 @@ fn test_concurrent_persistence() {
         let mut conn = db.connect();
 
         let result = conn.execute("SELECT * FROM items;").unwrap();
-        if let ExecuteResult::Select { rows } = result {
+        if let ExecuteResult::Select { rows, .. } = result {
             assert_eq!(rows.len(), 10);
         }
     }
 
     let _ = std::fs::remove_file(&temp_path);
 }
+
+#[test]
+fn test_restricted_connection_masks_columns() {
+    let temp_path = std::env::temp_dir().join("pardusdb_masking_test.pardus");
+    let _ = std::fs::remove_file(&temp_path);
+
+    {
+        let db = ConcurrentDatabase::open(&temp_path).unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE notes (embedding VECTOR(2), author TEXT MASK HASH, content TEXT MASK TRUNCATE(5), n INTEGER);").unwrap();
+        conn.execute("INSERT INTO notes (embedding, author, content, n) VALUES ([1.0, 0.0], 'alice', 'secret diagnosis', 1), ([0.0, 1.0], 'alice', 'another note', 2);").unwrap();
+        conn.execute("ALTER TABLE notes ALTER COLUMN n SET MASK REDACT;").unwrap();
+        conn.execute("ALTER TABLE notes ALTER COLUMN n DROP MASK;").unwrap();
+        db.save().unwrap();
+    }
+
+    let db = ConcurrentDatabase::open(&temp_path).unwrap();
+
+    // Unrestricted connections still see raw values
+    let mut conn = db.connect();
+    let result = conn.execute("SELECT content FROM notes WHERE n = 1;").unwrap();
+    if let ExecuteResult::Select { rows, .. } = result {
+        assert_eq!(rows[0].values, vec![Value::Text("secret diagnosis".into())]);
+    } else {
+        panic!("Expected Select");
+    }
+
+    let mut restricted = db.connect_restricted();
+    assert!(restricted.is_restricted());
+    let result = restricted.execute("SELECT author, content, n FROM notes ORDER BY n;").unwrap();
+    if let ExecuteResult::Select { rows, .. } = result {
+        assert_eq!(rows[0].values[1], Value::Text("secre".into()));
+        assert_eq!(rows[0].values[2], Value::Integer(1));
+        // Hashes are stable, so equal authors still match
+        assert_eq!(rows[0].values[0], rows[1].values[0]);
+        assert_ne!(rows[0].values[0], Value::Text("alice".into()));
+    } else {
+        panic!("Expected Select");
+    }
+
+    let results = restricted.search_similar("notes", &[1.0, 0.0], 1, 10).unwrap();
+    assert_eq!(results[0].1[2], Value::Text("secre".into()));
+    let result = restricted.execute("SELECT * FROM notes WHERE embedding SIMILARITY [1.0, 0.0] LIMIT 1;").unwrap();
+    if let ExecuteResult::SelectSimilar { results, .. } = result {
+        assert_eq!(results[0].0.values[2], Value::Text("secre".into()));
+    } else {
+        panic!("Expected SelectSimilar");
+    }
+
+    for sql in [
+        "SELECT * FROM notes WHERE content LIKE 'secret%';",
+        "SELECT * FROM notes WHERE LOWER(author) = 'alice';",
+        "SELECT * FROM notes ORDER BY content;",
+        "SELECT UPPER(content) FROM notes;",
+        "DELETE FROM notes WHERE author = 'alice';",
+        "ALTER TABLE notes ALTER content DROP MASK;",
+        "INSERT INTO copy SELECT * FROM notes;",
+        "CREATE MATERIALIZED VIEW by_author AS SELECT author, COUNT(*) AS n FROM notes GROUP BY author;",
+    ] {
+        assert!(matches!(restricted.execute(sql), Err(MarsError::AccessDenied(_))), "{}", sql);
+    }
+    assert!(restricted.execute("SELECT COUNT(content) FROM notes WHERE n > 0;").is_ok());
+
+    let _ = std::fs::remove_file(&temp_path);
+}
+
+#[test]
+fn test_panicked_write_poisons_writes_only() {
+    let db = Arc::new(ConcurrentDatabase::in_memory());
+    db.connect().execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    db.connect().execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+
+    let writer = Arc::clone(&db);
+    let panicked = thread::spawn(move || {
+        let _guard = writer.write().unwrap();
+        panic!("writer failed part way through");
+    });
+    assert!(panicked.join().is_err());
+    assert!(db.is_poisoned());
+
+    // Reads carry on; writes and saves are refused with a typed error
+    let mut conn = db.connect();
+    assert!(matches!(conn.execute("SELECT * FROM docs;"), Ok(ExecuteResult::Select { .. })));
+    assert_eq!(conn.search_similar("docs", &[1.0, 0.0], 1, 10).unwrap().len(), 1);
+    assert!(matches!(
+        conn.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'b');"),
+        Err(MarsError::LockPoisoned(_))
+    ));
+    assert!(matches!(db.save(), Err(MarsError::LockPoisoned(_))));
+    assert!(matches!(db.with_write(|inner| inner.tables.len()), Err(MarsError::LockPoisoned(_))));
+
+    db.clear_poison();
+    assert!(!db.is_poisoned());
+    conn.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'b');").unwrap();
+    assert_eq!(db.with_read(|inner| inner.tables["docs"].len()), 2);
+}
+
+#[test]
+fn test_connection_settings() {
+    use std::time::Duration;
+
+    let pool = DatabasePool::in_memory();
+    let mut reporting = pool.connect();
+    let mut serving = pool.connect();
+    reporting.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    for i in 0..200 {
+        reporting.execute(&format!("INSERT INTO docs (embedding, n) VALUES ([{}.0, 1.0], {});", i, i)).unwrap();
+    }
+
+    // Settings stay on the connection that set them
+    serving.execute("SET max_rows = 3;").unwrap();
+    serving.execute("SET ef_search = 200;").unwrap();
+    assert_eq!((serving.max_rows(), serving.ef_search()), (Some(3), 200));
+    assert_eq!((reporting.max_rows(), reporting.ef_search()), (None, 100));
+    assert_eq!(serving.execute("SELECT * FROM docs;").unwrap().rows().len(), 3);
+    assert_eq!(serving.execute("SELECT * FROM docs LIMIT 2;").unwrap().rows().len(), 2);
+    assert_eq!(reporting.execute("SELECT * FROM docs;").unwrap().rows().len(), 200);
+    let similar = serving.execute("SELECT * FROM docs WHERE embedding SIMILARITY [3.0, 1.0] LIMIT 50;").unwrap();
+    assert!(matches!(similar, ExecuteResult::SelectSimilar { results, .. } if results.len() == 3));
+
+    serving.set_statement_timeout(Some(Duration::from_nanos(1)));
+    assert!(matches!(serving.execute("SELECT * FROM docs WHERE n > 5;"), Err(MarsError::Timeout(_))));
+    assert!(reporting.execute("SELECT * FROM docs WHERE n > 5;").is_ok());
+    serving.execute("SET statement_timeout = 0;").unwrap();
+    serving.execute("SET max_rows = 0;").unwrap();
+    assert_eq!((serving.statement_timeout(), serving.max_rows()), (None, None));
+    assert_eq!(serving.execute("SELECT * FROM docs WHERE n > 5;").unwrap().rows().len(), 194);
+
+    assert!(matches!(serving.execute("SET max_rows = 'many';"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(serving.execute("SET no_such_setting = 1;"), Err(MarsError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_concurrent_join_and_group_by() {
+    let db = ConcurrentDatabase::in_memory();
+    let mut conn = db.connect();
+    let mut single = pardusdb::Database::in_memory();
+    let setup = [
+        "CREATE TABLE docs (embedding VECTOR(2), author_id INTEGER, score INTEGER);",
+        "CREATE TABLE authors (embedding VECTOR(2), author_id INTEGER, name TEXT MASK REDACT);",
+        "INSERT INTO authors (embedding, author_id, name) VALUES ([1.0, 0.0], 1, 'ada'), ([0.0, 1.0], 2, 'grace');",
+        "INSERT INTO docs (embedding, author_id, score) VALUES ([1.0, 0.0], 1, 10), ([0.5, 0.5], 1, 20), ([0.0, 1.0], 2, 5), ([0.2, 0.8], 3, 7);",
+    ];
+    for sql in setup {
+        conn.execute(sql).unwrap();
+        single.execute(sql).unwrap();
+    }
+
+    // Both front-ends share one engine, so they answer alike
+    for sql in [
+        "SELECT author_id, COUNT(*) AS n, SUM(score) FROM docs GROUP BY author_id HAVING n > 0 ORDER BY author_id;",
+        "SELECT author_id, COUNT(*) AS n FROM docs GROUP BY author_id HAVING n > 1;",
+        "SELECT COUNT(*), MAX(score) FROM docs WHERE score > 6;",
+    ] {
+        let expected = single.execute(sql).unwrap();
+        let actual = conn.execute(sql).unwrap();
+        assert!(matches!(actual, ExecuteResult::Aggregate { .. }), "{}", sql);
+        assert_eq!(format!("{:?}", actual), format!("{:?}", expected), "{}", sql);
+    }
+    for sql in [
+        "SELECT docs.score, authors.name FROM docs JOIN authors ON docs.author_id = authors.author_id;",
+        "SELECT docs.score, authors.name FROM docs LEFT JOIN authors ON docs.author_id = authors.author_id;",
+    ] {
+        let sorted = |result: ExecuteResult| {
+            let mut rows: Vec<String> = result.rows().iter().map(|r| format!("{:?}", r.row.values)).collect();
+            rows.sort();
+            rows
+        };
+        assert_eq!(sorted(conn.execute(sql).unwrap()), sorted(single.execute(sql).unwrap()), "{}", sql);
+    }
+    let joined = conn.execute("SELECT docs.score, authors.name FROM docs JOIN authors ON docs.author_id = authors.author_id;").unwrap();
+    assert_eq!(joined.rows().len(), 3);
+
+    // Restricted connections get masked joins and cannot join on masked columns
+    let mut restricted = db.connect_restricted();
+    let joined = restricted.execute("SELECT docs.score, authors.name FROM docs JOIN authors ON docs.author_id = authors.author_id;").unwrap();
+    assert!(joined.rows().iter().all(|r| !matches!(r.value("authors.name"), Some(Value::Text(name)) if name == "ada" || name == "grace")));
+    assert!(matches!(
+        restricted.execute("SELECT * FROM docs JOIN authors ON docs.author_id = authors.name;"),
+        Err(MarsError::AccessDenied(_))
+    ));
+    let grouped = restricted.execute("SELECT name, COUNT(*) FROM authors GROUP BY author_id ORDER BY author_id;").unwrap();
+    if let ExecuteResult::Aggregate { results } = grouped {
+        assert_eq!(results.len(), 4);
+        assert_ne!(results[0].1, Value::Text("ada".into()));
+    } else {
+        panic!("Expected Aggregate");
+    }
+}
+
+#[test]
+fn test_concurrent_constraints() {
+    let db = ConcurrentDatabase::in_memory();
+    let mut conn = db.connect();
+    conn.execute("CREATE TABLE users (embedding VECTOR(2), email TEXT UNIQUE);").unwrap();
+    conn.execute("CREATE TABLE posts (embedding VECTOR(2), author TEXT REFERENCES users(email) ON DELETE CASCADE);").unwrap();
+    conn.execute("INSERT INTO users (embedding, email) VALUES ([1.0, 0.0], 'a@example.com');").unwrap();
+    assert!(matches!(
+        conn.execute("INSERT INTO users (embedding, email) VALUES ([0.0, 1.0], 'a@example.com');"),
+        Err(MarsError::ConstraintViolation(_))
+    ));
+
+    conn.execute("INSERT INTO posts (embedding, author) VALUES ([1.0, 0.0], 'a@example.com');").unwrap();
+    assert!(matches!(
+        conn.execute("INSERT INTO posts (embedding, author) VALUES ([1.0, 0.0], 'b@example.com');"),
+        Err(MarsError::ConstraintViolation(_))
+    ));
+    assert!(conn.insert_direct("posts", vec![0.0, 1.0], vec![("author", Value::Text("b@example.com".into()))]).is_err());
+    assert!(matches!(conn.execute("DROP TABLE users;"), Err(MarsError::ConstraintViolation(_))));
+
+    // Queued writes go through the same checks at commit
+    conn.begin().unwrap();
+    conn.execute("INSERT INTO posts (embedding, author) VALUES ([0.5, 0.5], 'a@example.com');").unwrap();
+    conn.commit().unwrap();
+    conn.execute("DELETE FROM users WHERE email = 'a@example.com';").unwrap();
+    assert_eq!(conn.execute("SELECT * FROM posts;").unwrap().rows().len(), 0);
+}
@@ -1,6 +1,6 @@
 //! Integration tests for database operations
 
-use pardusdb::{Database, ExecuteResult, Value};
+use pardusdb::{AccessPath, Collation, ColumnType, ConcurrentDatabase, Cosine, Database, Duplicate, Euclidean, ExecuteResult, IngestOptions, MarsError, MergeOptions, ParseLimits, PreparedStatement, Schema, ShardedDatabase, Synchronous, Value, VectorDB};
 
 #[test]
 fn test_create_table() {
@@ -51,7 +51,7 @@ fn test_insert_and_select() {
     let result = db.execute("SELECT * FROM docs;").unwrap();
 
     match result {
-        ExecuteResult::Select { rows } => assert_eq!(rows.len(), 3),
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 3),
         _ => panic!("Expected Select result"),
     }
 }
@@ -69,7 +69,7 @@ fn test_select_with_where() {
     let result = db.execute("SELECT * FROM items WHERE category = 'A';").unwrap();
 
     match result {
-        ExecuteResult::Select { rows } => assert_eq!(rows.len(), 2),
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 2),
         _ => panic!("Expected Select result"),
     }
 }
@@ -90,7 +90,7 @@ fn test_select_with_limit() {
     let result = db.execute("SELECT * FROM items LIMIT 5;").unwrap();
 
     match result {
-        ExecuteResult::Select { rows } => assert_eq!(rows.len(), 5),
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 5),
         _ => panic!("Expected Select result"),
     }
 }
@@ -113,7 +113,7 @@ fn test_similarity_search() {
     ).unwrap();
 
     match result {
-        ExecuteResult::SelectSimilar { results } => {
+        ExecuteResult::SelectSimilar { results, .. } => {
             assert_eq!(results.len(), 2);
             // First result should be 'X axis' as it's closest
             let (first_row, first_dist) = &results[0];
@@ -158,7 +158,7 @@ fn test_delete() {
     // Verify deletion
     let result = db.execute("SELECT * FROM items;").unwrap();
     match result {
-        ExecuteResult::Select { rows } => assert_eq!(rows.len(), 1),
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 1),
         _ => panic!("Expected Select result"),
     }
 }
@@ -201,7 +201,7 @@ fn test_direct_api_insert() {
     // Verify the insert
     let result = db.execute("SELECT * FROM docs;").unwrap();
     match result {
-        ExecuteResult::Select { rows } => {
+        ExecuteResult::Select { rows, .. } => {
             assert_eq!(rows.len(), 1);
             assert_eq!(rows[0].values[1], Value::Text("Direct insert".to_string()));
         }
@@ -228,6 +228,35 @@ fn test_direct_api_search() {
     assert_eq!(results[0].1[1], Value::Text("X".to_string()));
 }
 
+#[test]
+fn test_direct_insert_borrowed_and_shared_vectors() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(3), title TEXT);").unwrap();
+
+    // Rows borrowed from one output buffer, and a vector shared with other code
+    let buffer = [0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+    let shared: std::sync::Arc<[f32]> = std::sync::Arc::from(&[1.0, 1.0, 0.0][..]);
+    db.insert_direct("docs", &buffer[..3], vec![("title", Value::Text("Y".to_string()))]).unwrap();
+    db.insert_direct("docs", &buffer[3..], vec![("title", Value::Text("Z".to_string()))]).unwrap();
+    db.insert_direct("docs", &shared, vec![("title", Value::Text("S".to_string()))]).unwrap();
+    db.insert_direct("docs", [1.0, 0.0, 0.0], vec![("title", Value::Text("X".to_string()))]).unwrap();
+    assert_eq!(std::sync::Arc::strong_count(&shared), 1);
+
+    let title = |db: &Database, query: &[f32]| db.search_similar("docs", query, 1, 10).unwrap()[0].1[1].clone();
+    assert_eq!(title(&db, &[0.0, 0.0, 1.0]), Value::Text("Z".to_string()));
+    assert_eq!(title(&db, &[0.0, 1.0, 0.0]), Value::Text("Y".to_string()));
+    assert_eq!(title(&db, &[1.0, 1.0, 0.0]), Value::Text("S".to_string()));
+
+    // The concurrent connection takes the same inputs
+    let db = ConcurrentDatabase::in_memory();
+    let mut conn = db.connect();
+    conn.execute("CREATE TABLE docs (embedding VECTOR(3), title TEXT);").unwrap();
+    conn.insert_direct("docs", shared.clone(), vec![]).unwrap();
+    conn.insert_batch_direct("docs", buffer.chunks(3).collect(), vec![]).unwrap();
+    conn.insert_batch_direct("docs", vec![shared.clone(), shared], vec![]).unwrap();
+    assert_eq!(db.read().tables["docs"].len(), 5);
+}
+
 #[test]
 fn test_persistence() {
     let temp_path = std::env::temp_dir().join("marsdb_test_persistence.mars");
@@ -248,7 +277,7 @@ fn test_persistence() {
         let mut db = Database::open(&temp_path).unwrap();
         let result = db.execute("SELECT * FROM docs;").unwrap();
         match result {
-            ExecuteResult::Select { rows } => assert_eq!(rows.len(), 1),
+            ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 1),
             _ => panic!("Expected Select result"),
         }
     }
@@ -257,6 +286,30 @@ fn test_persistence() {
     let _ = std::fs::remove_file(&temp_path);
 }
 
+#[test]
+fn test_open_baseline_format() {
+    // Written by the original release, format version 1, before collations
+    let fixture = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/baseline_v1.db");
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("baseline.db");
+    std::fs::copy(&fixture, &path).unwrap();
+
+    for _ in 0..2 {
+        let mut db = Database::open(&path).unwrap();
+        let schema = db.get_table("documents").unwrap().schema.clone();
+        let names: Vec<&str> = schema.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["id", "embedding", "title", "score"]);
+        assert!(schema.columns.iter().all(|c| c.collation == Collation::Binary));
+
+        let result = db.execute("SELECT * FROM documents;").unwrap();
+        let ExecuteResult::Select { rows, .. } = result else { panic!("Expected Select result") };
+        assert!(!rows.is_empty());
+
+        // Saving upgrades the file to the current format
+        db.save().unwrap();
+    }
+}
+
 #[test]
 fn test_multiple_tables() {
     let mut db = Database::in_memory();
@@ -299,8 +352,2393 @@ fn test_comparison_operators() {
     for (condition, expected_count) in cases {
         let result = db.execute(&format!("SELECT * FROM nums WHERE {};", condition)).unwrap();
         match result {
-            ExecuteResult::Select { rows } => assert_eq!(rows.len(), expected_count, "Failed for: {}", condition),
+            ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), expected_count, "Failed for: {}", condition),
             _ => panic!("Expected Select result"),
         }
     }
 }
+
+#[test]
+fn test_soft_delete_and_as_of() {
+    let mut db = Database::in_memory();
+
+    db.execute("CREATE TABLE docs (id INTEGER, embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'keep');").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'oops');").unwrap();
+
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let before_delete = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+
+    let result = db.execute("DELETE FROM docs WHERE id = 2 SOFT;").unwrap();
+    assert!(matches!(result, ExecuteResult::Delete { count: 1 }));
+
+    // Gone from normal reads and similarity search
+    match db.execute("SELECT * FROM docs;").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 1),
+        _ => panic!("Expected Select result"),
+    }
+    let results = db.search_similar("docs", &[0.0, 1.0], 5, 10).unwrap();
+    assert!(results.iter().all(|(id, _, _)| *id != 2));
+
+    // Still readable as of a time before the delete
+    match db.execute(&format!("SELECT * FROM docs AS OF {} WHERE id = 2;", before_delete)).unwrap() {
+        ExecuteResult::Select { rows, .. } => {
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].values[2], Value::Text("oops".to_string()));
+        }
+        _ => panic!("Expected Select result"),
+    }
+
+    // Nothing existed before the table was populated
+    match db.execute("SELECT * FROM docs AS OF '2000-01-01';").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert!(rows.is_empty()),
+        _ => panic!("Expected Select result"),
+    }
+
+    // A hard delete purges the soft-deleted row
+    db.execute("DELETE FROM docs WHERE id = 2;").unwrap();
+    match db.execute(&format!("SELECT * FROM docs AS OF {};", before_delete)).unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 1),
+        _ => panic!("Expected Select result"),
+    }
+}
+
+#[test]
+fn test_as_of_sees_values_before_update() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (id INTEGER, embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("ALTER TABLE docs SET (keep_history = 5);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'draft');").unwrap();
+
+    let now = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let before_first = now();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    db.execute("UPDATE docs SET title = 'review' WHERE id = 1;").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let before_second = now();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    db.execute("UPDATE docs SET title = 'final' WHERE id = 1;").unwrap();
+
+    let title_as_of = |db: &mut Database, ts: f64| match db.execute(&format!("SELECT title FROM docs AS OF {};", ts)).unwrap() {
+        ExecuteResult::Select { rows, .. } => rows[0].values[0].clone(),
+        _ => panic!("Expected Select result"),
+    };
+    assert_eq!(title_as_of(&mut db, before_first), Value::Text("draft".to_string()));
+    assert_eq!(title_as_of(&mut db, before_second), Value::Text("review".to_string()));
+    assert_eq!(title_as_of(&mut db, now()), Value::Text("final".to_string()));
+
+    // WHERE is evaluated against the old values
+    match db.execute(&format!("SELECT * FROM docs AS OF {} WHERE title = 'draft';", before_first)).unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 1),
+        _ => panic!("Expected Select result"),
+    }
+}
+
+#[test]
+fn test_row_history_is_bounded_and_vacuumed() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (id INTEGER, embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'v0');").unwrap();
+    let vacuum = |db: &mut Database| match db.execute("VACUUM docs;").unwrap() {
+        ExecuteResult::Vacuum { versions } => versions,
+        _ => panic!("Expected Vacuum result"),
+    };
+
+    // Without keep_history, updates keep no earlier versions
+    db.execute("UPDATE docs SET title = 'v1' WHERE id = 1;").unwrap();
+    assert_eq!(vacuum(&mut db), 0);
+
+    // Only the last keep_history versions are kept, and VACUUM drops them
+    db.execute("ALTER TABLE docs SET (keep_history = 2);").unwrap();
+    for i in 2..6 {
+        db.execute(&format!("UPDATE docs SET title = 'v{}' WHERE id = 1;", i)).unwrap();
+    }
+    assert_eq!(vacuum(&mut db), 2);
+    assert_eq!(vacuum(&mut db), 0);
+
+    // Lowering the limit trims what was already kept
+    db.execute("UPDATE docs SET title = 'v6' WHERE id = 1;").unwrap();
+    db.execute("UPDATE docs SET title = 'v7' WHERE id = 1;").unwrap();
+    db.execute("ALTER TABLE docs SET (keep_history = 1);").unwrap();
+    assert_eq!(vacuum(&mut db), 1);
+
+    assert!(matches!(db.execute("VACUUM;").unwrap(), ExecuteResult::Vacuum { versions: 0 }));
+    assert!(db.execute("VACUUM missing;").is_err());
+}
+
+#[test]
+fn test_bulk_delete_keeps_similarity_search_consistent() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bulk.db");
+    let mut db = Database::open(&path).unwrap();
+
+    db.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY, embedding VECTOR(2), title TEXT);").unwrap();
+    for i in 0..50 {
+        db.execute(&format!(
+            "INSERT INTO docs (embedding, title) VALUES ([{}.0, 0.0], 'doc_{}');",
+            i, i + 1
+        )).unwrap();
+    }
+
+    // Primary-key fast path, including ids that do not exist
+    let ids: Vec<String> = (1..=40).map(|i| i.to_string()).chain(["999".to_string()]).collect();
+    let result = db.execute(&format!("DELETE FROM docs WHERE id IN ({});", ids.join(", "))).unwrap();
+    assert!(matches!(result, ExecuteResult::Delete { count: 40 }));
+
+    // New rows reuse freed graph slots; search must still map to the right rows
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([100.0, 0.0], 'doc_51');").unwrap();
+
+    let check = |db: &Database| {
+        let results = db.search_similar("docs", &[100.0, 0.0], 1, 64).unwrap();
+        assert_eq!(results[0].0, 51);
+        assert_eq!(results[0].1[2], Value::Text("doc_51".to_string()));
+
+        let results = db.search_similar("docs", &[45.0, 0.0], 1, 64).unwrap();
+        assert_eq!(results[0].0, 46);
+        assert_eq!(results[0].1[2], Value::Text("doc_46".to_string()));
+
+        let results = db.search_similar("docs", &[0.0, 0.0], 20, 64).unwrap();
+        assert_eq!(results.len(), 11);
+    };
+
+    check(&db);
+    db.save().unwrap();
+    drop(db);
+
+    let db = Database::open(&path).unwrap();
+    check(&db);
+}
+
+#[test]
+fn test_repair_index() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (id INTEGER, embedding VECTOR(2), title TEXT);").unwrap();
+    for i in 0..30 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, 0.0], 'doc');", i)).unwrap();
+    }
+
+    db.execute("DELETE FROM docs WHERE id <= 10;").unwrap();
+    assert!(db.get_table("docs").unwrap().graph.needs_repair());
+
+    db.repair_index("docs").unwrap();
+    assert!(!db.get_table("docs").unwrap().graph.needs_repair());
+    assert!(db.repair_index("missing").is_err());
+}
+
+#[test]
+fn test_table_metric() {
+    fn first_component(a: &[f32], b: &[f32]) -> f32 {
+        (a[0] - b[0]).abs()
+    }
+    pardusdb::register_metric("first_component", first_component).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("metric.db");
+    let mut db = Database::open(&path).unwrap();
+
+    assert!(db.execute("CREATE TABLE bad (embedding VECTOR(2)) METRIC nope;").is_err());
+
+    db.execute("CREATE TABLE bits (embedding VECTOR(4), label TEXT) METRIC hamming;").unwrap();
+    db.execute("INSERT INTO bits (embedding, label) VALUES ([1.0, 1.0, 0.0, 0.0], 'a');").unwrap();
+    db.execute("INSERT INTO bits (embedding, label) VALUES ([1.0, 0.0, 0.0, 0.0], 'b');").unwrap();
+    db.execute("INSERT INTO bits (embedding, label) VALUES ([0.0, 0.0, 1.0, 1.0], 'c');").unwrap();
+
+    db.execute("CREATE TABLE custom (embedding VECTOR(2), label TEXT) METRIC first_component;").unwrap();
+    db.execute("INSERT INTO custom (embedding, label) VALUES ([0.0, 100.0], 'near');").unwrap();
+    db.execute("INSERT INTO custom (embedding, label) VALUES ([5.0, 0.0], 'far');").unwrap();
+
+    let check = |db: &Database| {
+        assert_eq!(db.get_table("bits").unwrap().metric(), "hamming");
+        let results = db.search_similar("bits", &[1.0, 1.0, 0.0, 1.0], 3, 16).unwrap();
+        assert_eq!(results[0].1[1], Value::Text("a".to_string()));
+        assert_eq!(results[0].2, 1.0);
+        assert_eq!(results[2].1[1], Value::Text("c".to_string()));
+
+        let results = db.search_similar("custom", &[0.0, 0.0], 1, 16).unwrap();
+        assert_eq!(results[0].1[1], Value::Text("near".to_string()));
+    };
+
+    check(&db);
+    db.save().unwrap();
+    drop(db);
+    check(&Database::open(&path).unwrap());
+}
+
+#[test]
+fn test_normalized_cosine_table() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("normalized.db");
+    let mut db = Database::open(&path).unwrap();
+
+    assert!(db.execute("CREATE TABLE bad (embedding VECTOR(2)) METRIC dot NORMALIZE;").is_err());
+
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), label TEXT) METRIC cosine NORMALIZE;").unwrap();
+    db.execute("INSERT INTO docs (embedding, label) VALUES ([3.0, 4.0], 'diagonal');").unwrap();
+    db.execute("INSERT INTO docs (embedding, label) VALUES ([10.0, 0.0], 'x');").unwrap();
+    db.execute("INSERT INTO docs (embedding, label) VALUES ([0.0, 0.5], 'y');").unwrap();
+
+    let check = |db: &Database| {
+        assert!(db.get_table("docs").unwrap().is_normalized());
+        let results = db.search_similar("docs", &[0.0, 100.0], 3, 16).unwrap();
+        assert_eq!(results[0].1[1], Value::Text("y".to_string()));
+        assert!(results[0].2.abs() < 1e-6);
+        assert_eq!(results[1].1[1], Value::Text("diagonal".to_string()));
+        assert!((results[1].2 - 0.2).abs() < 1e-6);
+
+        // Stored vectors are unit length
+        match &results[1].1[0] {
+            Value::Vector(v) => assert!((v[0] - 0.6).abs() < 1e-6 && (v[1] - 0.8).abs() < 1e-6),
+            other => panic!("Expected vector, got {:?}", other),
+        }
+    };
+
+    check(&db);
+    db.save().unwrap();
+    drop(db);
+    check(&Database::open(&path).unwrap());
+}
+
+#[test]
+fn test_exact_similarity_search() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE points (embedding VECTOR(2), label INTEGER);").unwrap();
+    for i in 0..100 {
+        let (x, y) = ((i % 10) as f32, (i / 10) as f32);
+        db.execute(&format!("INSERT INTO points (embedding, label) VALUES ([{}, {}], {});", x, y, i)).unwrap();
+    }
+
+    let result = db.execute("SELECT * FROM points WHERE embedding SIMILARITY [4.1, 6.2] EXACT LIMIT 3;").unwrap();
+    match result {
+        ExecuteResult::SelectSimilar { results, .. } => {
+            let labels: Vec<&Value> = results.iter().map(|(row, _)| &row.values[1]).collect();
+            assert_eq!(labels, vec![&Value::Integer(64), &Value::Integer(74), &Value::Integer(65)]);
+            assert!((results[0].1 - 0.05).abs() < 1e-5);
+        }
+        _ => panic!("Expected SelectSimilar"),
+    }
+
+    // The direct API agrees with SQL and with the graph on easy data
+    let exact = db.search_exact("points", &[4.1, 6.2], 3).unwrap();
+    let approx = db.search_similar("points", &[4.1, 6.2], 3, 64).unwrap();
+    let ids = |r: &[(u64, Vec<Value>, f32)]| r.iter().map(|(id, _, _)| *id).collect::<Vec<_>>();
+    assert_eq!(ids(&exact), ids(&approx));
+    assert_eq!(exact[0].1[1], Value::Integer(64));
+}
+
+#[test]
+fn test_similarity_using_dims() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE points (embedding VECTOR(3), label INTEGER);").unwrap();
+    for (label, v) in [(1, "[0.0, 0.0, 0.0]"), (2, "[0.1, 0.0, 5.0]"), (3, "[1.0, 0.0, 0.0]"), (4, "[5.0, 5.0, 5.0]")] {
+        db.execute(&format!("INSERT INTO points (embedding, label) VALUES ({}, {});", v, label)).unwrap();
+    }
+    let labels = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => results.iter().map(|(row, _)| row.values[1].clone()).collect::<Vec<_>>(),
+        other => panic!("Expected SelectSimilar, got {:?}", other),
+    };
+
+    // A prefix query is scored on prefixes only
+    assert_eq!(
+        labels(&mut db, "SELECT * FROM points WHERE embedding SIMILARITY [0.1] USING DIMS 1 LIMIT 3;"),
+        vec![Value::Integer(2), Value::Integer(1), Value::Integer(3)],
+    );
+    // A full query re-ranks the prefix matches on every component
+    assert_eq!(
+        labels(&mut db, "SELECT * FROM points WHERE embedding SIMILARITY [0.0, 0.0, 5.0] USING DIMS 1 LIMIT 2;"),
+        vec![Value::Integer(2), Value::Integer(1)],
+    );
+    assert_eq!(
+        labels(&mut db, "SELECT * FROM points WHERE embedding SIMILARITY [0.0, 0.0, 5.0] USING DIMS 3 LIMIT 1;"),
+        vec![Value::Integer(2)],
+    );
+
+    assert!(db.execute("SELECT * FROM points WHERE embedding SIMILARITY [0.0, 0.0, 5.0] USING DIMS 4;").is_err());
+    assert!(db.execute("SELECT * FROM points WHERE embedding SIMILARITY [0.0, 0.0] USING DIMS 1;").is_err());
+}
+
+#[test]
+fn test_weighted_similarity() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (title_emb VECTOR(2), body_emb VECTOR(2), label INTEGER);").unwrap();
+    for (label, title, body) in [(1, "[1.0, 0.0]", "[0.0, 1.0]"), (2, "[0.0, 1.0]", "[1.0, 0.0]"), (3, "[0.9, 0.1]", "[0.5, 0.5]")] {
+        db.execute(&format!("INSERT INTO docs (title_emb, body_emb, label) VALUES ({}, {}, {});", title, body, label)).unwrap();
+    }
+    let labels = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => results.iter().map(|(row, _)| row.values[2].clone()).collect::<Vec<_>>(),
+        other => panic!("Expected SelectSimilar, got {:?}", other),
+    };
+
+    assert_eq!(
+        labels(&mut db, "SELECT * FROM docs WHERE SIMILARITY 0.7*title_emb + 0.3*body_emb AGAINST [1.0, 0.0] LIMIT 3;"),
+        vec![Value::Integer(3), Value::Integer(1), Value::Integer(2)],
+    );
+    assert_eq!(
+        labels(&mut db, "SELECT * FROM docs WHERE SIMILARITY 0.1*title_emb + 0.9*body_emb AGAINST [1.0, 0.0] LIMIT 3;"),
+        vec![Value::Integer(2), Value::Integer(3), Value::Integer(1)],
+    );
+
+    // Reported as a weighted sum of the table metric's raw distances
+    match db.execute("SELECT * FROM docs WHERE SIMILARITY 0.7*title_emb + 0.3*body_emb AGAINST [1.0, 0.0] LIMIT 3;").unwrap() {
+        ExecuteResult::SelectSimilar { results, metric, .. } => {
+            assert_eq!(metric, "weighted");
+            assert!((results[1].1 - 0.3 * 2.0).abs() < 1e-6);
+            assert_eq!(pardusdb::metric_score(&metric, results[1].1), ("weighted", results[1].1));
+        }
+        other => panic!("Expected SelectSimilar, got {:?}", other),
+    }
+
+    assert!(db.execute("SELECT * FROM docs WHERE SIMILARITY 0.5*title_emb + 0.5*label AGAINST [1.0, 0.0];").is_err());
+    assert!(db.execute("SELECT * FROM docs WHERE SIMILARITY 0*title_emb + 1*body_emb AGAINST [1.0, 0.0];").is_err());
+    assert!(db.execute("SELECT * FROM docs WHERE SIMILARITY 0.5*title_emb + 0.5*body_emb AGAINST [1.0, 0.0, 0.0];").is_err());
+}
+
+#[test]
+fn test_similarity_score() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), boost FLOAT, label INTEGER);").unwrap();
+    for (label, v, boost) in [(1, "[1.0, 0.0]", "0.0"), (2, "[0.9, 0.1]", "2.0"), (3, "[0.0, 1.0]", "5.0"), (4, "[0.95, 0.05]", "NULL")] {
+        db.execute(&format!("INSERT INTO docs (embedding, boost, label) VALUES ({}, {}, {});", v, boost, label)).unwrap();
+    }
+    let ranked = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+        ExecuteResult::SelectSimilar { results, metric, .. } => {
+            assert_eq!(metric, "score");
+            results.iter().map(|(row, score)| (row.values[2].clone(), *score)).collect::<Vec<_>>()
+        }
+        other => panic!("Expected SelectSimilar, got {:?}", other),
+    };
+
+    // The boost lifts row 2 over the closer row 1; row 4 has no boost
+    let results = ranked(&mut db, "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] SCORE distance - 0.1 * boost LIMIT 2;");
+    assert_eq!((results[0].0.clone(), results[1].0.clone()), (Value::Integer(2), Value::Integer(1)));
+    assert!((results[0].1 - (0.02f32.sqrt() - 0.2)).abs() < 1e-5, "{:?}", results);
+
+    let results = ranked(&mut db, "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] EXACT SCORE distance - 0.1 * COALESCE(boost, 0) LIMIT 3;");
+    let labels: Vec<Value> = results.into_iter().map(|(label, _)| label).collect();
+    assert_eq!(labels, vec![Value::Integer(2), Value::Integer(1), Value::Integer(4)]);
+
+    // A NULL score ranks last instead of dropping the row
+    let results = ranked(&mut db, "SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] EXACT SCORE distance - 0.1 * boost LIMIT 4;");
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[3], (Value::Integer(4), f32::INFINITY));
+
+    assert!(db.execute("SELECT * FROM docs WHERE label = 1 SCORE boost;").is_err());
+    for score in ["embedding", "distance + 'a'", "UPPER('a')", "distance + missing"] {
+        let sql = format!("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] SCORE {} LIMIT 2;", score);
+        assert!(db.execute(&sql).is_err(), "{}", score);
+    }
+}
+
+#[test]
+fn test_delete_within_distance() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE points (embedding VECTOR(2), label INTEGER);").unwrap();
+    for i in 0..10 {
+        db.execute(&format!("INSERT INTO points (embedding, label) VALUES ([{}.0, 0.0], {});", i, i)).unwrap();
+    }
+
+    // WITHIN is a filter on the L2 distance, so it also works in SELECT
+    match db.execute("SELECT label FROM points WHERE embedding SIMILARITY [3.0, 0.0] WITHIN 1.0 AND label != 4 ORDER BY label;").unwrap() {
+        ExecuteResult::Select { rows, .. } => {
+            let labels: Vec<&Value> = rows.iter().map(|row| &row.values[0]).collect();
+            assert_eq!(labels, vec![&Value::Integer(2), &Value::Integer(3)]);
+        }
+        other => panic!("Expected Select, got {:?}", other),
+    }
+
+    let result = db.execute("DELETE FROM points WHERE embedding SIMILARITY [3.0, 0.0] WITHIN 1.0;").unwrap();
+    assert!(matches!(result, ExecuteResult::Delete { count: 3 }));
+    assert_eq!(db.get_table("points").unwrap().len(), 7);
+    let nearest = db.search_similar("points", &[3.0, 0.0], 1, 64).unwrap();
+    assert_eq!(nearest[0].1[1], Value::Integer(1));
+}
+
+#[test]
+fn test_dedupe() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), label INTEGER);").unwrap();
+    for (label, v) in ["[0.0, 0.0]", "[0.01, 0.0]", "[5.0, 5.0]", "[5.0, 5.001]", "[5.002, 5.0]", "[10.0, 0.0]"].iter().enumerate() {
+        db.execute(&format!("INSERT INTO docs (embedding, label) VALUES ({}, {});", v, label)).unwrap();
+    }
+
+    let pairs = |found: &[Duplicate]| found.iter().map(|d| (d.kept, d.duplicate)).collect::<Vec<_>>();
+    let found = db.find_duplicates("docs", 0.05).unwrap();
+    assert_eq!(pairs(&found), vec![(1, 2), (3, 4), (3, 5)]);
+    assert!((found[0].distance - 0.01).abs() < 1e-5);
+    assert_eq!(db.get_table("docs").unwrap().len(), 6);
+
+    let removed = db.dedupe("docs", 0.05).unwrap();
+    assert_eq!(pairs(&removed), pairs(&found));
+    assert_eq!(db.get_table("docs").unwrap().len(), 3);
+    assert!(db.find_duplicates("docs", 0.05).unwrap().is_empty());
+
+    assert!(db.dedupe("docs", -1.0).is_err());
+    assert!(matches!(db.dedupe("nope", 0.05), Err(MarsError::TableNotFound(_))));
+}
+
+#[test]
+fn test_similarity_pagination() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE points (embedding VECTOR(2), label INTEGER);").unwrap();
+    for i in 0..100 {
+        let (x, y) = ((i % 10) as f32, (i / 10) as f32);
+        db.execute(&format!("INSERT INTO points (embedding, label) VALUES ([{}, {}], {});", x, y, i)).unwrap();
+    }
+    let query = [4.5, 4.5];
+
+    let first = db.search_similar("points", &query, 7, 64).unwrap();
+
+    let mut seen = Vec::new();
+    let mut cursor = None;
+    let mut pages = 0;
+    loop {
+        let page = db.search_similar_after("points", &query, 7, cursor).unwrap();
+        if pages == 0 {
+            let ids: Vec<u64> = first.iter().map(|(id, _, _)| *id).collect();
+            assert_eq!(page.results.iter().map(|(id, _, _)| *id).collect::<Vec<_>>(), ids);
+        }
+        assert!(page.results.len() <= 7);
+        seen.extend(page.results.into_iter().map(|(id, _, dist)| (id, dist)));
+        pages += 1;
+        cursor = page.next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    // Every row exactly once, in distance order
+    assert_eq!(pages, 15);
+    assert!(seen.windows(2).all(|w| w[0].1 <= w[1].1));
+    let mut ids: Vec<u64> = seen.iter().map(|(id, _)| *id).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 100);
+}
+
+#[test]
+fn test_similarity_diversity() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE chunks (embedding VECTOR(2), source TEXT, label INTEGER);").unwrap();
+    // Source 'a' holds the closest chunks, so plain top-k is all 'a'
+    for i in 0..20 {
+        let source = if i < 10 { "a" } else if i < 15 { "b" } else { "c" };
+        db.execute(&format!(
+            "INSERT INTO chunks (embedding, source, label) VALUES ([{}, 0.0], '{}', {});",
+            i as f32, source, i
+        )).unwrap();
+    }
+
+    let sources = |result: ExecuteResult| -> Vec<Value> {
+        match result {
+            ExecuteResult::SelectSimilar { results, .. } => results.into_iter().map(|(row, _)| row.values[1].clone()).collect(),
+            _ => panic!("Expected SelectSimilar"),
+        }
+    };
+    let text = |s: &str| Value::Text(s.to_string());
+
+    let plain = db.execute("SELECT * FROM chunks WHERE embedding SIMILARITY [0.0, 0.0] LIMIT 5;").unwrap();
+    assert!(sources(plain).iter().all(|s| *s == text("a")));
+
+    let capped = db.execute(
+        "SELECT * FROM chunks WHERE embedding SIMILARITY [0.0, 0.0] LIMIT 5 MAX PER (source) 2;"
+    ).unwrap();
+    assert_eq!(sources(capped), vec![text("a"), text("a"), text("b"), text("b"), text("c")]);
+
+    // MMR skips near-duplicates of what was already picked
+    db.execute("CREATE TABLE dups (embedding VECTOR(2), label TEXT);").unwrap();
+    for label in ["x1", "x2", "x3"] {
+        db.execute(&format!("INSERT INTO dups (embedding, label) VALUES ([1.0, 0.0], '{}');", label)).unwrap();
+    }
+    db.execute("INSERT INTO dups (embedding, label) VALUES ([0.0, 1.5], 'y');").unwrap();
+
+    let mmr = db.execute("SELECT * FROM dups WHERE embedding SIMILARITY [1.0, 0.2] LIMIT 2 MMR 0.5;").unwrap();
+    let labels = sources(mmr);
+    assert!(labels[0] != text("y"));
+    assert_eq!(labels[1], text("y"));
+
+    assert!(db.execute("SELECT * FROM chunks LIMIT 5 MAX PER (source) 1;").is_err());
+    assert!(db.execute(
+        "SELECT * FROM chunks WHERE embedding SIMILARITY [0.0, 0.0] LIMIT 5 MAX PER (missing) 1;"
+    ).is_err());
+}
+
+#[test]
+fn test_grouped_similarity_search() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE chunks (embedding VECTOR(2), doc_id INTEGER);").unwrap();
+    for i in 0..12 {
+        db.execute(&format!(
+            "INSERT INTO chunks (embedding, doc_id) VALUES ([{}, 0.0], {});",
+            i as f32, i % 3
+        )).unwrap();
+    }
+
+    let groups = db.search_similar_grouped("chunks", &[10.0, 0.0], 2, "doc_id").unwrap();
+    assert_eq!(groups.len(), 3);
+
+    // Doc 1 owns the closest chunk (x = 10); docs 0 (x = 9) and 2 (x = 11)
+    // tie on distance and are ordered by row id
+    let keys: Vec<&Value> = groups.iter().map(|g| &g.key).collect();
+    assert_eq!(keys, vec![&Value::Integer(1), &Value::Integer(0), &Value::Integer(2)]);
+    for group in &groups {
+        assert_eq!(group.results.len(), 2);
+        assert!(group.results.iter().all(|(_, values, _)| values[1] == group.key));
+        assert!(group.results[0].2 <= group.results[1].2);
+    }
+    assert_eq!(groups[0].results[0].2, 0.0);
+    assert_eq!(groups[0].results[1].2, 9.0);
+
+    assert!(db.search_similar_grouped("chunks", &[0.0, 0.0], 2, "missing").is_err());
+}
+
+#[test]
+fn test_sparse_vector_search() {
+    let mut db = Database::in_memory();
+
+    // Sparse-only table: no dense index needed
+    db.execute("CREATE TABLE terms (id INTEGER, title TEXT, bm25 SPARSE_VECTOR);").unwrap();
+    db.execute("INSERT INTO terms (title, bm25) VALUES ('rust', {10: 2.0, 20: 1.0});").unwrap();
+    db.execute("INSERT INTO terms (title, bm25) VALUES ('python', {30: 3.0});").unwrap();
+    db.execute("INSERT INTO terms (title, bm25) VALUES ('both', {10: 0.5, 30: 0.5});").unwrap();
+
+    let result = db.execute("SELECT * FROM terms WHERE bm25 SIMILARITY {10: 1.0, 30: 0.1} LIMIT 5;").unwrap();
+    match result {
+        ExecuteResult::SelectSimilar { results, .. } => {
+            let titles: Vec<&Value> = results.iter().map(|(row, _)| &row.values[1]).collect();
+            assert_eq!(titles, vec![
+                &Value::Text("rust".to_string()),
+                &Value::Text("both".to_string()),
+                &Value::Text("python".to_string()),
+            ]);
+            assert_eq!(results[0].1, -2.0);
+        }
+        _ => panic!("Expected SelectSimilar"),
+    }
+
+    // Rows without overlap are not returned
+    let results = db.search_sparse("terms", "bm25", &[(20, 1.0)], 10).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, 1);
+
+    assert!(db.search_sparse("terms", "title", &[(20, 1.0)], 10).is_err());
+    assert!(db.execute("CREATE TABLE nothing (title TEXT);").is_err());
+
+    // Dense and sparse columns side by side
+    db.execute("CREATE TABLE hybrid (embedding VECTOR(2), terms SPARSE_VECTOR);").unwrap();
+    db.execute("INSERT INTO hybrid (embedding, terms) VALUES ([1.0, 0.0], {1: 1.0});").unwrap();
+    db.execute("INSERT INTO hybrid (embedding, terms) VALUES ([0.0, 1.0], {2: 1.0});").unwrap();
+    assert_eq!(db.search_similar("hybrid", &[0.0, 1.0], 1, 16).unwrap()[0].0, 2);
+    assert_eq!(db.search_sparse("hybrid", "terms", &[(1, 1.0)], 1).unwrap()[0].0, 1);
+}
+
+#[test]
+fn test_multi_vector_max_sim() {
+    let mut db = Database::in_memory();
+
+    db.execute("CREATE TABLE docs (id INTEGER, title TEXT, tokens VECTOR(2) MULTI);").unwrap();
+    db.execute("INSERT INTO docs (title, tokens) VALUES ('cats', [[1.0, 0.0], [0.9, 0.1]]);").unwrap();
+    db.execute("INSERT INTO docs (title, tokens) VALUES ('dogs', [[0.0, 1.0]]);").unwrap();
+    db.execute("INSERT INTO docs (title, tokens) VALUES ('pets', [[1.0, 0.0], [0.0, 1.0]]);").unwrap();
+
+    // Every token vector must match the declared dimension
+    assert!(db.execute("INSERT INTO docs (title, tokens) VALUES ('bad', [[1.0, 0.0], [1.0]]);").is_err());
+
+    let result = db.execute("SELECT * FROM docs WHERE tokens SIMILARITY [[1.0, 0.0], [0.0, 1.0]] LIMIT 2;").unwrap();
+    match result {
+        ExecuteResult::SelectSimilar { results, .. } => {
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].0.values[1], Value::Text("pets".to_string()));
+            assert_eq!(results[0].1, -2.0);
+            assert_eq!(results[1].0.values[1], Value::Text("cats".to_string()));
+        }
+        _ => panic!("Expected SelectSimilar"),
+    }
+
+    let results = db.search_max_sim("docs", "tokens", &[vec![0.0, 1.0]], 1).unwrap();
+    assert_eq!(results[0].1[1], Value::Text("dogs".to_string()));
+    assert!(db.search_max_sim("docs", "title", &[vec![0.0, 1.0]], 1).is_err());
+}
+
+#[test]
+fn test_execute_script() {
+    let mut db = Database::in_memory();
+
+    let results = db.execute_script(
+        "CREATE TABLE docs (embedding VECTOR(2), title TEXT);
+         INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'first; part');
+         INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'second');
+         SELECT title FROM docs WHERE title LIKE '%;%';"
+    ).unwrap();
+
+    assert_eq!(results.len(), 4);
+    match &results[3] {
+        ExecuteResult::Select { rows, .. } => {
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].values[0], Value::Text("first; part".to_string()));
+        }
+        _ => panic!("Expected Select result"),
+    }
+
+    // Stops at the first failing statement; earlier ones stay applied
+    let err = db.execute_script(
+        "INSERT INTO docs (embedding, title) VALUES ([1.0, 1.0], 'third');
+         INSERT INTO missing (embedding) VALUES ([1.0, 1.0]);
+         INSERT INTO docs (embedding, title) VALUES ([1.0, 1.0], 'fourth');"
+    );
+    assert!(err.is_err());
+    assert_eq!(db.get_table("docs").unwrap().len(), 3);
+}
+
+#[test]
+fn test_typed_errors() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), slug TEXT UNIQUE);").unwrap();
+    db.execute("INSERT INTO docs (embedding, slug) VALUES ([1.0, 0.0], 'a');").unwrap();
+
+    assert!(matches!(
+        db.execute("SELECT * FROM missing;"),
+        Err(MarsError::TableNotFound(name)) if name == "missing"
+    ));
+    assert!(matches!(
+        db.execute("INSERT INTO docs (embedding, nope) VALUES ([1.0, 0.0], 1);"),
+        Err(MarsError::ColumnNotFound(name)) if name == "nope"
+    ));
+    assert!(matches!(
+        db.execute("INSERT INTO docs (embedding) VALUES ([1.0, 0.0, 0.0]);"),
+        Err(MarsError::ColumnDimensionMismatch { column, expected: 2, actual: 3 }) if column == "embedding"
+    ));
+    assert_eq!(
+        MarsError::DimensionMismatch { expected: 2, actual: 3 }.to_string(),
+        "Dimension mismatch: expected 2, got 3"
+    );
+    assert!(matches!(
+        db.execute("SELECT VEC_AVG(nope) FROM docs;"),
+        Err(MarsError::ColumnNotFound(name)) if name == "nope"
+    ));
+    assert!(matches!(
+        db.execute("INSERT INTO docs (embedding, slug) VALUES ([0.0, 1.0], 'a');"),
+        Err(MarsError::ConstraintViolation(_))
+    ));
+
+    let err = db.execute("SELECT * FROM docs\nWHERE slug ~ 'a';").unwrap_err();
+    match &err {
+        MarsError::ParseError { line, column, .. } => assert_eq!((*line, *column), (2, 12)),
+        other => panic!("Expected ParseError, got {:?}", other),
+    }
+    assert!(err.to_string().contains("got '~' at line 2, column 12"));
+}
+
+#[test]
+fn test_result_schema_and_typed_get() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, views INTEGER);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title, views) VALUES ([1.0, 0.0], 'Intro', 7);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'Draft');").unwrap();
+
+    let result = db.execute("SELECT title, views, UPPER(title) AS shout FROM docs ORDER BY title;").unwrap();
+    let schema = result.schema().unwrap();
+    assert_eq!(schema.names(), vec!["title", "views", "shout"]);
+    assert_eq!(schema.columns[1].data_type, Some(ColumnType::Integer));
+    assert_eq!(schema.columns[2].data_type, Some(ColumnType::Text));
+
+    let rows = result.rows();
+    assert_eq!(rows[0].get::<String>("shout").unwrap(), "DRAFT");
+    assert_eq!(rows[0].get::<Option<i64>>("views").unwrap(), None);
+    assert_eq!(rows[1].get::<i64>("views").unwrap(), 7);
+    assert!(rows[1].get::<String>("views").is_err());
+    assert!(matches!(rows[1].get::<i64>("embedding"), Err(MarsError::ColumnNotFound(_))));
+
+    let result = db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] LIMIT 1;").unwrap();
+    let rows = result.rows();
+    assert_eq!(rows[0].get::<Vec<f32>>("embedding").unwrap(), vec![1.0, 0.0]);
+    assert!(rows[0].distance.is_some());
+
+    assert!(db.execute("DELETE FROM docs WHERE views = 7;").unwrap().schema().is_none());
+}
+
+#[test]
+fn test_tokenize() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), body TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, body) VALUES ([1.0, 0.0], 'The dogs were running');").unwrap();
+    db.execute("INSERT INTO docs (embedding, body) VALUES ([0.0, 1.0], '東京の天気');").unwrap();
+
+    let result = db.execute("SELECT TOKENIZE(body, 'english') AS terms FROM docs WHERE TOKENIZE(body, 'english') LIKE '%run%';").unwrap();
+    let rows = result.rows();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].get::<String>("terms").unwrap(), "dog were run");
+
+    let result = db.execute("SELECT TOKENIZE(body) AS terms FROM docs WHERE body LIKE '東京%';").unwrap();
+    assert_eq!(result.rows()[0].get::<String>("terms").unwrap(), "東京 京の の天 天気");
+
+    assert!(matches!(
+        db.execute("SELECT TOKENIZE(body, 'english, ngram=0') FROM docs;"),
+        Err(MarsError::ParseError { .. })
+    ));
+}
+
+#[test]
+fn test_snippet() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, body TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title, body) VALUES ([1.0, 0.0], 'Returns', 'Items must be unused. Refunds are issued within 30 days of a return.');").unwrap();
+    db.execute("INSERT INTO docs (embedding, title, body) VALUES ([0.0, 1.0], 'Shipping', 'Orders ship within two days.');").unwrap();
+
+    let result = db.execute("SELECT title, SNIPPET(body, 'refunds issued', 4) AS excerpt FROM docs WHERE embedding SIMILARITY [1.0, 0.1] LIMIT 1;").unwrap();
+    let rows = result.rows();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].row.values.len(), 2);
+    assert_eq!(rows[0].get::<String>("title").unwrap(), "Returns");
+    assert_eq!(rows[0].get::<String>("excerpt").unwrap(), "...<b>Refunds</b> are <b>issued</b> within...");
+    assert!(rows[0].score.is_some());
+
+    let result = db.execute("SELECT SNIPPET(body, 'ship') AS excerpt FROM docs WHERE title = 'Shipping';").unwrap();
+    assert_eq!(result.rows()[0].get::<String>("excerpt").unwrap(), "Orders <b>ship</b> within two days.");
+
+    assert!(matches!(
+        db.execute("SELECT SNIPPET(body, 'ship', 10, 'klingon') FROM docs;"),
+        Err(MarsError::ParseError { .. })
+    ));
+}
+
+#[test]
+fn test_last_query_stats() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    for i in 0..20 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, 1.0], 't{}');", i, i)).unwrap();
+    }
+    assert!(db.last_query_stats().is_none());
+
+    db.execute("SELECT * FROM docs WHERE title = 't3';").unwrap();
+    let stats = db.last_query_stats().unwrap();
+    assert_eq!((stats.rows_scanned, stats.rows_returned, stats.index.clone()), (20, 1, None));
+
+    db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [3.0, 1.0] LIMIT 2;").unwrap();
+    let stats = db.last_query_stats().unwrap();
+    assert_eq!(stats.index.as_deref(), Some("graph"));
+    assert_eq!(stats.rows_returned, 2);
+    assert!(stats.rows_scanned >= 2 && stats.rows_scanned <= 20);
+}
+
+#[test]
+fn test_progress_handler() {
+    use std::sync::{Arc, Mutex};
+
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    let values: Vec<String> = (0..25).map(|i| format!("([{}.0, 1.0], {})", i, i)).collect();
+    let insert = format!("INSERT INTO docs (embedding, n) VALUES {};", values.join(", "));
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&seen);
+    db.set_progress_handler(10, move |p| {
+        log.lock().unwrap().push((p.operation, p.done, p.total));
+        true
+    });
+    db.execute(&insert).unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![("insert", 10, 25), ("insert", 20, 25)]);
+
+    // Returning false cancels; rows inserted before that point are kept
+    db.set_progress_handler(10, |p| p.done < 20);
+    assert!(matches!(db.execute(&insert), Err(MarsError::Interrupted)));
+    assert_eq!(db.get_table("docs").unwrap().len(), 45);
+
+    db.clear_progress_handler();
+    db.execute(&insert).unwrap();
+    assert_eq!(db.get_table("docs").unwrap().len(), 70);
+}
+
+#[test]
+fn test_interrupt_handle() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    let values: Vec<String> = (0..30).map(|i| format!("([{}.0, 1.0], {})", i, i)).collect();
+    let insert = format!("INSERT INTO docs (embedding, n) VALUES {};", values.join(", "));
+
+    // Interrupt from inside the running statement, as another thread would
+    let handle = db.interrupt_handle();
+    db.set_progress_handler(10, move |_| {
+        handle.interrupt();
+        true
+    });
+    assert!(matches!(db.execute(&insert), Err(MarsError::Interrupted)));
+    assert_eq!(db.get_table("docs").unwrap().len(), 10);
+
+    // A new statement starts with the flag cleared
+    db.clear_progress_handler();
+    db.execute(&insert).unwrap();
+    assert_eq!(db.get_table("docs").unwrap().len(), 40);
+
+    let handle = db.interrupt_handle();
+    db.set_progress_handler(1, move |_| {
+        handle.interrupt();
+        true
+    });
+    let script = "SELECT * FROM docs; SELECT * FROM docs;";
+    assert!(matches!(db.execute_script(script), Err(MarsError::Interrupted)));
+}
+
+#[test]
+fn test_statement_timeout() {
+    use std::time::Duration;
+
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    for i in 0..200 {
+        db.execute(&format!("INSERT INTO docs (embedding, n) VALUES ([{}.0, 1.0], {});", i, i)).unwrap();
+    }
+
+    db.execute("SET statement_timeout = 500;").unwrap();
+    assert_eq!(db.statement_timeout(), Some(Duration::from_millis(500)));
+    assert_eq!(db.execute("SELECT * FROM docs;").unwrap().rows().len(), 200);
+
+    db.set_statement_timeout(Some(Duration::from_nanos(1)));
+    assert!(matches!(db.execute("SELECT * FROM docs WHERE n > 5;"), Err(MarsError::Timeout(_))));
+    assert!(matches!(
+        db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [3.0, 1.0] LIMIT 5;"),
+        Err(MarsError::Timeout(_))
+    ));
+
+    db.execute("SET statement_timeout = 0;").unwrap();
+    assert_eq!(db.statement_timeout(), None);
+    assert!(db.execute("SELECT * FROM docs WHERE n > 5;").is_ok());
+
+    assert!(db.execute("SET statement_timeout = 'soon';").is_err());
+    assert!(db.execute("SET no_such_setting = 1;").is_err());
+}
+
+#[test]
+fn test_memory_limit() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(64), title TEXT);").unwrap();
+    let insert = |i: usize| format!("INSERT INTO docs (embedding, title) VALUES ([{}], 'doc {}');", vec!["0.5"; 64].join(", "), i);
+
+    db.execute(&insert(0)).unwrap();
+    let per_row = db.memory_usage().total();
+    assert!(db.memory_usage().vectors >= 64 * 4);
+
+    db.execute(&format!("SET memory_limit = {};", per_row * 5)).unwrap();
+    assert_eq!(db.memory_limit(), Some(per_row * 5));
+
+    let mut inserted = 1;
+    let err = loop {
+        match db.execute(&insert(inserted)) {
+            Ok(_) => inserted += 1,
+            Err(e) => break e,
+        }
+        assert!(inserted < 100, "memory limit never reached");
+    };
+    assert!(matches!(err, MarsError::MemoryLimit(_)));
+    assert!(inserted >= 2);
+    assert!(db.memory_usage().total() <= per_row * 5);
+
+    // Deleting rows frees room for new ones
+    db.execute("DELETE FROM docs WHERE title = 'doc 0';").unwrap();
+    db.execute(&insert(100)).unwrap();
+
+    db.execute("SET memory_limit = 0;").unwrap();
+    assert_eq!(db.memory_limit(), None);
+    for i in 0..10 {
+        db.execute(&insert(200 + i)).unwrap();
+    }
+}
+
+#[test]
+fn test_columnar_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("columnar.db");
+    let mut db = Database::open(&path).unwrap();
+
+    db.execute("CREATE TABLE plain (embedding VECTOR(2), n INTEGER, score FLOAT, tag TEXT);").unwrap();
+    db.execute("CREATE TABLE cols (embedding VECTOR(2), n INTEGER, score FLOAT, tag TEXT) STORAGE COLUMNAR;").unwrap();
+    for i in 0..200 {
+        let n = match i % 7 { 0 => "NULL".to_string(), 3 => "'odd'".to_string(), _ => i.to_string() };
+        let score = if i % 5 == 0 { format!("{}", i / 5) } else { format!("{}.5", i % 13) };
+        for table in ["plain", "cols"] {
+            db.execute(&format!(
+                "INSERT INTO {} (embedding, n, score, tag) VALUES ([{}.0, 1.0], {}, {}, 'g{}');",
+                table, i, n, score, i % 3
+            )).unwrap();
+        }
+    }
+    for table in ["plain", "cols"] {
+        db.execute(&format!("UPDATE {} SET score = 99.5 WHERE n > 190;", table)).unwrap();
+        db.execute(&format!("DELETE FROM {} WHERE n BETWEEN 40 AND 60;", table)).unwrap();
+        db.execute(&format!("DELETE FROM {} WHERE n = 100 SOFT;", table)).unwrap();
+    }
+
+    let queries = [
+        "SELECT COUNT(*), COUNT(n), SUM(n), AVG(score), MIN(score), MAX(score) FROM {} WHERE score >= 3;",
+        "SELECT COUNT(*), SUM(score), MIN(n), MAX(n) FROM {} WHERE n != 5 AND n < 150 OR score IS NULL;",
+        "SELECT COUNT(*), AVG(n) FROM {} WHERE n NOT IN (1, 2, 8) AND n NOT BETWEEN 10 AND 20;",
+        "SELECT COUNT(*) FROM {} WHERE tag = 'g1' AND n > 10;",
+        "SELECT * FROM {} WHERE n >= 100 AND score < 50;",
+        "SELECT * FROM {} WHERE n IS NULL;",
+        "SELECT tag, COUNT(*), SUM(score) FROM {} WHERE n > 20 GROUP BY tag;",
+    ];
+    let run = |db: &mut Database, sql: &str, table: &str| -> Vec<String> {
+        let mut db_rows: Vec<String> = match db.execute(&sql.replace("{}", table)).unwrap() {
+            ExecuteResult::Select { rows, .. } => rows.iter().map(|r| format!("{:?}", r.values)).collect(),
+            ExecuteResult::Aggregate { results } => results.iter().map(|r| format!("{:?}", r)).collect(),
+            other => panic!("Unexpected result {:?}", other),
+        };
+        db_rows.sort();
+        db_rows
+    };
+    let check = |db: &mut Database| {
+        let cols = db.get_table("cols").unwrap();
+        assert!(cols.is_columnar());
+        assert_eq!(cols.column_store().unwrap().len(), cols.len());
+        assert!(!db.get_table("plain").unwrap().is_columnar());
+        for sql in queries {
+            let expected = run(db, sql, "plain");
+            assert!(!expected.is_empty(), "{}", sql);
+            assert_eq!(run(db, sql, "cols"), expected, "{}", sql);
+        }
+    };
+
+    check(&mut db);
+    db.save().unwrap();
+    drop(db);
+    check(&mut Database::open(&path).unwrap());
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_encrypted_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secret.db");
+
+    let mut db = Database::open_encrypted(&path, "hunter2").unwrap();
+    assert!(db.is_encrypted());
+    db.execute("CREATE TABLE notes (embedding VECTOR(2), body TEXT);").unwrap();
+    db.execute("INSERT INTO notes (embedding, body) VALUES ([1.0, 2.0], 'launch codes');").unwrap();
+    db.save().unwrap();
+    drop(db);
+
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!raw.windows(12).any(|w| w == b"launch codes"));
+    assert!(matches!(Database::open(&path), Err(MarsError::Encryption(_))));
+    assert!(matches!(ConcurrentDatabase::open(&path), Err(MarsError::Encryption(_))));
+    assert!(matches!(Database::open_encrypted(&path, "hunter3"), Err(MarsError::Encryption(_))));
+
+    // Delta files are encrypted too
+    let mut db = Database::open_encrypted(&path, "hunter2").unwrap();
+    db.execute("INSERT INTO notes (embedding, body) VALUES ([3.0, 4.0], 'more codes');").unwrap();
+    db.save_delta().unwrap();
+    drop(db);
+    let raw = std::fs::read(dir.path().join("secret.db-delta-1")).unwrap();
+    assert!(!raw.windows(10).any(|w| w == b"more codes"));
+
+    // Re-key, then decrypt back to a plain file
+    let mut db = Database::open_encrypted(&path, "hunter2").unwrap();
+    assert_eq!(db.get_table("notes").unwrap().len(), 2);
+    db.rekey(Some("correct horse")).unwrap();
+    drop(db);
+    assert!(matches!(Database::open_encrypted(&path, "hunter2"), Err(MarsError::Encryption(_))));
+    let mut db = Database::open_encrypted(&path, "correct horse").unwrap();
+    db.rekey(None).unwrap();
+    assert!(!db.is_encrypted());
+    drop(db);
+    assert_eq!(Database::open(&path).unwrap().get_table("notes").unwrap().len(), 2);
+    assert!(matches!(Database::open_encrypted(&path, "correct horse"), Err(MarsError::Encryption(_))));
+}
+
+#[test]
+fn test_readonly_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("replica.db");
+    assert!(Database::open_readonly(&path).is_err());
+
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+    db.save().unwrap();
+    drop(db);
+    let before = std::fs::read(&path).unwrap();
+
+    let mut db = Database::open_readonly(&path).unwrap();
+    assert!(db.is_readonly());
+    assert!(matches!(db.execute("SELECT * FROM docs;").unwrap(), ExecuteResult::Select { ref rows, .. } if rows.len() == 1));
+    assert_eq!(db.search_similar("docs", &[1.0, 0.0], 1, 10).unwrap().len(), 1);
+    for sql in [
+        "INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'b');",
+        "UPDATE docs SET title = 'c';",
+        "DELETE FROM docs;",
+        "DROP TABLE docs;",
+        "CREATE TABLE more (embedding VECTOR(2));",
+        "ALTER TABLE docs SET (search_buffer = 10);",
+    ] {
+        assert!(matches!(db.execute(sql), Err(MarsError::ReadOnly)), "{}", sql);
+    }
+    assert!(matches!(db.insert_direct("docs", vec![0.0, 1.0], vec![]), Err(MarsError::ReadOnly)));
+    assert!(matches!(db.save(), Err(MarsError::ReadOnly)));
+    assert_eq!(db.get_table("docs").unwrap().len(), 1);
+
+    let concurrent = ConcurrentDatabase::open_readonly(&path).unwrap();
+    assert!(concurrent.is_readonly());
+    let mut conn = concurrent.connect();
+    assert!(conn.execute("SELECT * FROM docs;").is_ok());
+    conn.begin().unwrap();
+    assert!(matches!(conn.execute("DELETE FROM docs;"), Err(MarsError::ReadOnly)));
+    assert!(matches!(conn.insert_direct("docs", vec![0.0, 1.0], vec![]), Err(MarsError::ReadOnly)));
+    assert!(matches!(concurrent.save(), Err(MarsError::ReadOnly)));
+
+    assert_eq!(std::fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn test_file_locking() {
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("locked.db");
+
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    db.save().unwrap();
+
+    // One writer at a time, any number of readers
+    assert!(matches!(Database::open(&path), Err(MarsError::Locked(_))));
+    assert!(matches!(ConcurrentDatabase::open(&path), Err(MarsError::Locked(_))));
+    let reader = Database::open_readonly(&path).unwrap();
+    assert_eq!(reader.table_names().len(), 1);
+
+    // Saving waits for readers of the file, up to busy_timeout
+    db.execute("PRAGMA busy_timeout = 50;").unwrap();
+    assert_eq!(db.busy_timeout(), Duration::from_millis(50));
+    let file = std::fs::File::open(&path).unwrap();
+    file.lock_shared().unwrap();
+    let start = Instant::now();
+    assert!(matches!(db.save(), Err(MarsError::Locked(_))));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    drop(file);
+    db.save().unwrap();
+
+    drop(db);
+    let concurrent = ConcurrentDatabase::open(&path).unwrap();
+    assert!(matches!(Database::open(&path), Err(MarsError::Locked(_))));
+    drop(concurrent);
+    assert!(Database::open(&path).is_ok());
+}
+
+#[test]
+fn test_pragma_settings() {
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("pragma.db");
+    let mut db = Database::open(&path).unwrap();
+    let pragma = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+        ExecuteResult::Pragma { value, .. } => value,
+        other => panic!("Unexpected result {:?}", other),
+    };
+
+    assert_eq!(pragma(&mut db, "PRAGMA synchronous;"), Value::Text("NORMAL".into()));
+    assert_eq!(pragma(&mut db, "PRAGMA synchronous = full;"), Value::Text("FULL".into()));
+    assert_eq!(db.synchronous(), Synchronous::Full);
+    assert_eq!(pragma(&mut db, "PRAGMA synchronous(0);"), Value::Text("OFF".into()));
+    assert_eq!(pragma(&mut db, "PRAGMA statement_timeout = 250;"), Value::Integer(250));
+    assert_eq!(db.statement_timeout(), Some(Duration::from_millis(250)));
+    assert_eq!(pragma(&mut db, "PRAGMA ef_search = 300;"), Value::Integer(300));
+    assert_eq!(pragma(&mut db, "PRAGMA memory_limit;"), Value::Integer(0));
+    assert_eq!(pragma(&mut db, "PRAGMA threads;"), Value::Integer(0));
+    assert_eq!(pragma(&mut db, "PRAGMA threads = 2;"), Value::Integer(2));
+    assert_eq!(db.threads(), Some(2));
+
+    assert!(matches!(db.execute("PRAGMA synchronous = sometimes;"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(db.execute("PRAGMA ef_search = -1;"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(db.execute("PRAGMA journal_mode;"), Err(MarsError::InvalidConfig(_))));
+
+    // SQL similarity queries use the pragma's ef_search
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    for i in 0..50 {
+        db.execute(&format!("INSERT INTO docs (embedding, n) VALUES ([{}.0, 0.0], {});", i, i)).unwrap();
+    }
+    match db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [20.0, 0.0] LIMIT 3;").unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => assert_eq!(results[0].0.values[1], Value::Integer(20)),
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    // Autosave writes the file without an explicit save()
+    assert_eq!(Database::open_readonly(&path).unwrap().table_names().len(), 0);
+    db.execute("PRAGMA autosave_interval = 1;").unwrap();
+    std::thread::sleep(Duration::from_millis(5));
+    db.execute("INSERT INTO docs (embedding, n) VALUES ([100.0, 0.0], 100);").unwrap();
+    assert_eq!(Database::open_readonly(&path).unwrap().get_table("docs").unwrap().len(), 51);
+}
+
+#[test]
+fn test_alter_table_index_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("alter.db");
+    let mut db = Database::open(&path).unwrap();
+
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    for i in 0..200 {
+        db.execute(&format!("INSERT INTO docs (embedding, n) VALUES ([{}.0, {}.0], {});", i % 20, i / 20, i)).unwrap();
+    }
+
+    let result = db.execute("ALTER TABLE docs SET (search_buffer = 128);").unwrap();
+    assert!(matches!(result, ExecuteResult::AlterTable { rebuilt: false, .. }));
+    let result = db.execute("ALTER TABLE docs SET (max_neighbors = 8, alpha_relaxed = 1.5);").unwrap();
+    assert!(matches!(result, ExecuteResult::AlterTable { rebuilt: true, .. }));
+    let result = db.execute("ALTER TABLE docs SET (seed = 42);").unwrap();
+    assert!(matches!(result, ExecuteResult::AlterTable { rebuilt: true, .. }));
+
+    let nearest = |db: &mut Database| match db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [7.0, 3.0] LIMIT 1;").unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => results[0].0.values[1].clone(),
+        other => panic!("Unexpected result {:?}", other),
+    };
+    assert_eq!(nearest(&mut db), Value::Integer(67));
+
+    assert!(matches!(db.execute("ALTER TABLE docs SET (ef = 10);"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(db.execute("ALTER TABLE docs SET (max_neighbors = 0);"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(db.execute("ALTER TABLE docs SET (seed = -1);"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(db.execute("ALTER TABLE nope SET (max_neighbors = 4);"), Err(MarsError::TableNotFound(_))));
+
+    db.save().unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    let config = db.get_table("docs").unwrap().graph_config().clone();
+    assert_eq!((config.max_neighbors, config.search_buffer, config.alpha_relaxed), (8, 128, 1.5));
+    assert_eq!(config.seed, Some(42));
+    assert_eq!(nearest(&mut db), Value::Integer(67));
+    drop(db);
+
+    // The concurrent engine reads the same format
+    let db = ConcurrentDatabase::open(&path).unwrap();
+    assert_eq!(db.read().tables["docs"].graph_config().max_neighbors, 8);
+}
+
+#[test]
+fn test_text_compression() {
+    let dir = tempfile::tempdir().unwrap();
+    let body = |i: i64| format!("Section {}. Refunds are issued within 30 days of purchase to the original payment method. {}", i, "Contact support for details. ".repeat(i as usize % 7 + 40));
+
+    let mut sizes = Vec::new();
+    for threshold in [0, 1024] {
+        let path = dir.path().join(format!("text{}.db", threshold));
+        let mut db = Database::open(&path).unwrap();
+        db.execute("CREATE TABLE chunks (embedding VECTOR(2), body TEXT, title TEXT);").unwrap();
+        db.execute(&format!("ALTER TABLE chunks SET (compress_text = {});", threshold)).unwrap();
+        for i in 0..100 {
+            db.insert_direct("chunks", vec![i as f32, 0.0], vec![
+                ("body", Value::Text(body(i))),
+                ("title", Value::Text(format!("Chapter {}", i))),
+            ]).unwrap();
+        }
+        db.save().unwrap();
+        drop(db);
+        sizes.push(std::fs::metadata(&path).unwrap().len());
+
+        // Reopened rows hold the plain text again, in both engines
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(db.get_table("chunks").unwrap().compress_text(), threshold);
+        match db.execute("SELECT body, title FROM chunks WHERE title = 'Chapter 42';").unwrap() {
+            ExecuteResult::Select { rows, .. } => {
+                assert_eq!(rows[0].values, vec![Value::Text(body(42)), Value::Text("Chapter 42".into())]);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        drop(db);
+        let db = ConcurrentDatabase::open(&path).unwrap();
+        assert_eq!(db.read().tables["chunks"].get(100).unwrap().values[1], Value::Text(body(99)));
+    }
+    assert!(sizes[1] * 3 < sizes[0], "compressed {} bytes, plain {} bytes", sizes[1], sizes[0]);
+
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE chunks (embedding VECTOR(2), body TEXT);").unwrap();
+    assert!(matches!(db.execute("ALTER TABLE chunks SET (compress_text = -1);"), Err(MarsError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_external_text_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("docs.db");
+    let text_file = |n: u32| dir.path().join(format!("docs.db-text-{}", n));
+    let body = |i: i64| format!("external body {} {}", i, "x".repeat(i as usize * 10));
+
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, body TEXT EXTERNAL);").unwrap();
+    assert_eq!(db.get_table("docs").unwrap().external_columns(), ["body"]);
+    for i in 0..20 {
+        db.insert_direct("docs", vec![i as f32, 0.0], vec![
+            ("title", Value::Text(format!("title {}", i))),
+            ("body", Value::Text(body(i))),
+        ]).unwrap();
+    }
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([99.0, 0.0], 'no body');").unwrap();
+    db.save().unwrap();
+
+    // The database file keeps titles but not bodies
+    let contents = std::fs::read(&path).unwrap();
+    let contains = |needle: &str| contents.windows(needle.len()).any(|w| w == needle.as_bytes());
+    assert!(contains("title 7"));
+    assert!(!contains("external body 7"));
+    assert!(text_file(1).exists());
+
+    db.execute("UPDATE docs SET body = 'rewritten' WHERE title = 'title 3';").unwrap();
+    db.save().unwrap();
+    assert!(!text_file(1).exists() && text_file(2).exists());
+    drop(db);
+
+    let mut db = Database::open(&path).unwrap();
+    let bodies = |db: &mut Database| match db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [2.9, 0.0] LIMIT 2;").unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => results.into_iter().map(|(row, _)| row.values[2].clone()).collect::<Vec<_>>(),
+        other => panic!("Unexpected result {:?}", other),
+    };
+    assert_eq!(bodies(&mut db), vec![Value::Text("rewritten".into()), Value::Text(body(2))]);
+    match db.execute("SELECT body FROM docs WHERE title = 'no body';").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows[0].values, vec![Value::Null]),
+        other => panic!("Unexpected result {:?}", other),
+    }
+    drop(db);
+
+    let db = ConcurrentDatabase::open(&path).unwrap();
+    assert_eq!(db.read().tables["docs"].get(10).unwrap().values[2], Value::Text(body(9)));
+    db.save().unwrap();
+    assert!(!text_file(2).exists() && text_file(3).exists());
+    drop(db);
+
+    // A missing text file is reported rather than loading empty values
+    std::fs::remove_file(text_file(3)).unwrap();
+    assert!(matches!(Database::open(&path), Err(MarsError::InvalidFormat(_))));
+
+    let mut db = Database::in_memory();
+    assert!(matches!(
+        db.execute("CREATE TABLE bad (embedding VECTOR(2), n INTEGER EXTERNAL);"),
+        Err(MarsError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_parallel_ingest() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER, label TEXT);").unwrap();
+    db.insert_direct("docs", vec![-1.0, -1.0], vec![("label", Value::Text("first".into()))]).unwrap();
+
+    let rows = (0..500).map(|i| (
+        vec![(i % 25) as f32, (i / 25) as f32],
+        vec![("n", Value::Integer(i)), ("label", Value::Text(format!("row {}", i)))],
+    ));
+    let ids = db.ingest("docs", rows, IngestOptions { parallelism: 3, batch_size: 64 }).unwrap();
+    assert_eq!(ids, 2..502);
+    assert_eq!(db.get_table("docs").unwrap().len(), 501);
+
+    // IDs follow input order and every vector is found again
+    for i in [0i64, 63, 64, 250, 499] {
+        let query = [(i % 25) as f32, (i / 25) as f32];
+        let (id, values, distance) = db.search_similar("docs", &query, 1, 64).unwrap().remove(0);
+        assert_eq!(distance, 0.0);
+        assert_eq!(id, 2 + i as u64);
+        assert_eq!(values[1], Value::Integer(i));
+    }
+
+    // A bad row fails its batch; batches merged before it stay
+    let rows = (0..300).map(|i| (vec![0.5; if i == 150 { 3 } else { 2 }], vec![]));
+    let err = db.ingest("docs", rows, IngestOptions { parallelism: 1, batch_size: 100 }).unwrap_err();
+    assert!(matches!(err, MarsError::ColumnDimensionMismatch { expected: 2, actual: 3, .. }));
+    assert_eq!(db.get_table("docs").unwrap().len(), 601);
+
+    assert!(matches!(db.ingest("nope", Vec::new(), IngestOptions::default()), Err(MarsError::TableNotFound(_))));
+}
+
+#[test]
+fn test_reload_if_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("batch.db");
+    let mut writer = Database::open(&path).unwrap();
+    writer.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    writer.save().unwrap();
+    assert!(!writer.reload_if_changed().unwrap());
+
+    let mut reader = Database::open_readonly(&path).unwrap();
+    reader.set_ef_search(Some(64));
+    assert!(!reader.reload_if_changed().unwrap());
+    assert_eq!(reader.get_table("docs").unwrap().len(), 0);
+
+    writer.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a'), ([0.0, 1.0], 'b');").unwrap();
+    writer.save().unwrap();
+    assert!(reader.reload_if_changed().unwrap());
+    assert_eq!(reader.get_table("docs").unwrap().len(), 2);
+    assert_eq!(reader.ef_search(), Some(64));
+    assert!(!reader.reload_if_changed().unwrap());
+
+    assert!(!Database::in_memory().reload_if_changed().unwrap());
+}
+
+#[cfg(feature = "watch")]
+#[test]
+fn test_watch_reloads_before_statements() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("batch.db");
+    let mut writer = Database::open(&path).unwrap();
+    writer.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    writer.save().unwrap();
+
+    let mut reader = Database::open_readonly(&path).unwrap();
+    reader.watch().unwrap();
+    assert!(Database::in_memory().watch().is_err());
+
+    writer.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+    writer.save().unwrap();
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        match reader.execute("SELECT * FROM docs;").unwrap() {
+            ExecuteResult::Select { rows, .. } if rows.len() == 1 => break,
+            _ => assert!(std::time::Instant::now() < deadline, "reader never reloaded"),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[cfg(feature = "object-store")]
+#[test]
+fn test_object_store_persistence() {
+    use pardusdb::object_store::{ObjectStore, memory::InMemory, path::Path};
+    use std::sync::Arc;
+
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let location = Path::from("jobs/docs.pardus");
+    let mut db = Database::open_object_store(Arc::clone(&store), location.clone()).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+    db.save().unwrap();
+
+    let open = || Database::open_object_store(Arc::clone(&store), location.clone()).unwrap().with_conflict_detection();
+    let (mut a, mut b) = (open(), open());
+    assert_eq!(a.get_table("docs").unwrap().len(), 1);
+    a.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'b');").unwrap();
+    a.save().unwrap();
+    b.execute("DELETE FROM docs;").unwrap();
+    assert!(matches!(b.save(), Err(MarsError::SaveConflict(_))));
+
+    assert_eq!(open().get_table("docs").unwrap().len(), 2);
+    assert!(matches!(Database::open_url("not a url"), Err(MarsError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_sealed_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT UNIQUE) METRIC cosine NORMALIZE;").unwrap();
+    db.execute("CREATE TABLE tags (embedding VECTOR(2), name TEXT);").unwrap();
+    for i in 0..30 {
+        let angle = i as f32 * 0.1;
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}, {}], 'd{}');", angle.cos() * 3.0, angle.sin() * 3.0, i)).unwrap();
+    }
+    db.execute("DELETE FROM docs WHERE title = 'd3';").unwrap();
+    let path = dir.path().join("edge.sealed");
+    db.export_sealed(&path).unwrap();
+
+    let mut sealed = Database::open_readonly_mmap(&path).unwrap();
+    assert!(sealed.is_readonly());
+    let mut names = sealed.table_names();
+    names.sort();
+    assert_eq!(names, ["docs", "tags"]);
+    assert_eq!(
+        sealed.search_similar("docs", &[1.0, 0.25], 3, 50).unwrap(),
+        db.search_similar("docs", &[1.0, 0.25], 3, 50).unwrap(),
+    );
+    assert!(matches!(sealed.execute("SELECT * FROM docs;").unwrap(), ExecuteResult::Select { ref rows, .. } if rows.len() == 29));
+    assert!(matches!(sealed.execute("DELETE FROM docs;"), Err(MarsError::ReadOnly)));
+
+    assert!(matches!(Database::open_readonly(&path), Err(MarsError::InvalidFormat(_))));
+    let plain = dir.path().join("plain.db");
+    Database::open(&plain).unwrap();
+    assert!(matches!(Database::open_readonly_mmap(&plain), Err(MarsError::InvalidFormat(_))));
+}
+
+#[test]
+fn test_save_delta() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.db");
+    let delta = |n: u32| dir.path().join(format!("big.db-delta-{}", n));
+    let titles = |db: &mut Database| match db.execute("SELECT title FROM docs ORDER BY title;").unwrap() {
+        ExecuteResult::Select { rows, .. } => rows.iter()
+            .map(|row| match &row.values[0] {
+                Value::Text(title) => title.clone(),
+                other => panic!("unexpected title {:?}", other),
+            })
+            .collect::<Vec<_>>(),
+        other => panic!("unexpected result {:?}", other),
+    };
+
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a'), ([0.0, 1.0], 'b'), ([1.0, 1.0], 'c');").unwrap();
+    db.save().unwrap();
+    let base = std::fs::read(&path).unwrap();
+
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([2.0, 0.0], 'd');").unwrap();
+    db.execute("UPDATE docs SET title = 'b2' WHERE title = 'b';").unwrap();
+    db.execute("DELETE FROM docs WHERE title = 'c';").unwrap();
+    db.save_delta().unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), base);
+    db.save_delta().unwrap();
+    assert!(delta(1).exists() && !delta(2).exists());
+
+    let mut reader = Database::open_readonly(&path).unwrap();
+    assert_eq!(titles(&mut reader), titles(&mut db));
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([3.0, 0.0], 'e');").unwrap();
+    db.execute("CREATE TABLE tags (embedding VECTOR(2));").unwrap();
+    db.save_delta().unwrap();
+    assert!(reader.reload_if_changed().unwrap());
+    assert!(!reader.reload_if_changed().unwrap());
+    assert_eq!(titles(&mut reader), ["a", "b2", "d", "e"]);
+    assert!(reader.get_table("tags").is_some());
+    assert!(reader.search_similar("docs", &[3.0, 0.1], 1, 10).unwrap()[0].1.contains(&Value::Text("e".into())));
+
+    let expected = titles(&mut db);
+    let stale = std::fs::read(delta(1)).unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(titles(&mut db), expected);
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([4.0, 0.0], 'f');").unwrap();
+    db.save().unwrap();
+    assert!(!delta(1).exists() && !delta(2).exists());
+
+    // A delta left over from before the last full save is ignored
+    std::fs::write(delta(1), stale).unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(titles(&mut db), ["a", "b2", "d", "e", "f"]);
+    assert_eq!(db.get_table("docs").unwrap().len(), 5);
+}
+
+#[test]
+fn test_merge_from() {
+    let shard = |offset: usize| {
+        let mut db = Database::in_memory();
+        db.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY, embedding VECTOR(2), title TEXT);").unwrap();
+        for i in offset..offset + 20 {
+            let angle = i as f32 * 0.1;
+            db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}, {}], 'd{}');", angle.cos(), angle.sin(), i)).unwrap();
+        }
+        db
+    };
+    let mut first = shard(0);
+    let mut second = shard(20);
+    second.execute("DELETE FROM docs WHERE title = 'd25';").unwrap();
+    second.execute("CREATE TABLE tags (embedding VECTOR(2), name TEXT);").unwrap();
+    second.execute("INSERT INTO tags (embedding, name) VALUES ([1.0, 0.0], 'x');").unwrap();
+
+    let merged = first.merge_from(&second, MergeOptions::default()).unwrap();
+    assert_eq!(merged, 20);
+    assert_eq!(first.get_table("docs").unwrap().len(), 39);
+    assert_eq!(first.get_table("tags").unwrap().len(), 1);
+
+    // Merged rows get fresh IDs after the existing ones
+    match first.execute("SELECT id FROM docs WHERE title = 'd20';").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows[0].values[0], Value::Integer(21)),
+        other => panic!("unexpected result {:?}", other),
+    }
+    let angle = 3.0f32;
+    let results = first.search_similar("docs", &[angle.cos(), angle.sin()], 1, 50).unwrap();
+    assert_eq!(results[0].1[2], Value::Text("d30".into()));
+    first.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}, {}], 'new');", angle.cos(), angle.sin())).unwrap();
+    assert_eq!(first.search_similar("docs", &[angle.cos(), angle.sin()], 2, 50).unwrap().len(), 2);
+
+    let only_missing = MergeOptions { tables: Some(vec!["nope".into()]), ..Default::default() };
+    assert!(matches!(first.merge_from(&second, only_missing), Err(MarsError::TableNotFound(_))));
+    let no_create = MergeOptions { create_missing: false, ..Default::default() };
+    assert!(matches!(shard(0).merge_from(&second, no_create), Err(MarsError::TableNotFound(_))));
+
+    let mut narrow = Database::in_memory();
+    narrow.execute("CREATE TABLE docs (embedding VECTOR(2));").unwrap();
+    let docs_only = MergeOptions { tables: Some(vec!["docs".into()]), ..Default::default() };
+    assert!(matches!(narrow.merge_from(&second, docs_only), Err(MarsError::ColumnNotFound(_))));
+}
+
+#[test]
+fn test_insert_select_from_attached_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard.db");
+    let mut shard = Database::open(&path).unwrap();
+    shard.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    shard.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a'), ([0.0, 1.0], 'b');").unwrap();
+    shard.save().unwrap();
+    drop(shard);
+
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT, score FLOAT) METRIC cosine;").unwrap();
+    db.execute("INSERT INTO docs (embedding, title, score) VALUES ([1.0, 1.0], 'c', 0.5);").unwrap();
+    db.execute(&format!("ATTACH DATABASE '{}' AS shard;", path.display())).unwrap();
+    assert!(matches!(db.execute("INSERT INTO docs SELECT * FROM shard.docs;").unwrap(), ExecuteResult::Insert { id: 3 }));
+    assert!(matches!(db.execute("INSERT INTO docs SELECT * FROM shard.missing;"), Err(MarsError::TableNotFound(_))));
+
+    match db.execute("SELECT title, score FROM docs ORDER BY title;").unwrap() {
+        ExecuteResult::Select { rows, .. } => {
+            let values: Vec<_> = rows.iter().map(|row| row.values.clone()).collect();
+            assert_eq!(values, [
+                vec![Value::Text("a".into()), Value::Null],
+                vec![Value::Text("b".into()), Value::Null],
+                vec![Value::Text("c".into()), Value::Float(0.5)],
+            ]);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    let results = db.search_similar("docs", &[0.0, 2.0], 1, 50).unwrap();
+    assert_eq!(results[0].1[1], Value::Text("b".into()));
+
+    db.execute("DETACH DATABASE shard;").unwrap();
+    assert!(db.execute("INSERT INTO docs SELECT * FROM shard.docs;").is_err());
+    assert!(db.execute("DETACH shard;").is_err());
+}
+
+#[test]
+fn test_sharded_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = ShardedDatabase::open(dir.path().join("docs"), 3, "doc").unwrap();
+    db.execute_all("CREATE TABLE chunks (embedding VECTOR(2), doc TEXT);").unwrap();
+    for i in 0..60 {
+        let angle = i as f32 * 0.05;
+        let (shard, _) = db.insert("chunks", vec![angle.cos(), angle.sin()], vec![("doc", Value::Text(format!("d{}", i)))]).unwrap();
+        assert_eq!(shard, db.shard_for(&Value::Text(format!("d{}", i))));
+    }
+    assert_eq!(db.count("chunks").unwrap(), 60);
+    assert!(db.shards().iter().all(|shard| shard.get_table("chunks").unwrap().len() < 60));
+    assert!(matches!(db.insert("chunks", vec![1.0, 0.0], vec![]), Err(MarsError::ConstraintViolation(_))));
+
+    let angle = 1.0f32;
+    let results = db.search_similar("chunks", &[angle.cos(), angle.sin()], 3, 50).unwrap();
+    let docs: Vec<_> = results.iter().map(|(_, _, values, _)| values[1].clone()).collect();
+    assert_eq!(docs, [Value::Text("d20".into()), Value::Text("d19".into()), Value::Text("d21".into())]);
+    assert!(results.windows(2).all(|w| w[0].3 <= w[1].3));
+
+    db.save().unwrap();
+    drop(db);
+    assert!(matches!(ShardedDatabase::open(dir.path().join("docs"), 4, "doc"), Err(MarsError::InvalidConfig(_))));
+    let db = ShardedDatabase::open(dir.path().join("docs"), 3, "doc").unwrap();
+    assert_eq!(db.count("chunks").unwrap(), 60);
+}
+
+#[test]
+fn test_result_cache() {
+    let mut db = Database::in_memory().with_result_cache(16);
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a'), ([0.0, 1.0], 'b');").unwrap();
+    let query = "SELECT * FROM docs WHERE embedding SIMILARITY [0.1, 0.9] LIMIT 1;";
+    let nearest = |db: &mut Database| match db.execute(query).unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => results[0].0.values[1].clone(),
+        other => panic!("unexpected result {:?}", other),
+    };
+    let index = |db: &Database| db.last_query_stats().unwrap().index.clone();
+
+    assert_eq!(nearest(&mut db), Value::Text("b".into()));
+    assert_ne!(index(&db).as_deref(), Some("result cache"));
+    assert_eq!(nearest(&mut db), Value::Text("b".into()));
+    assert_eq!(index(&db).as_deref(), Some("result cache"));
+
+    // Any change to the table drops its cached results
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([0.1, 0.9], 'c');").unwrap();
+    assert_eq!(nearest(&mut db), Value::Text("c".into()));
+    db.execute("DELETE FROM docs WHERE title = 'c';").unwrap();
+    assert_eq!(nearest(&mut db), Value::Text("b".into()));
+
+    assert_eq!(db.search_similar("docs", &[0.9, 0.1], 1, 50).unwrap()[0].1[1], Value::Text("a".into()));
+    db.insert_direct("docs", vec![0.9, 0.1], vec![("title", Value::Text("d".into()))]).unwrap();
+    assert_eq!(db.search_similar("docs", &[0.9, 0.1], 1, 50).unwrap()[0].1[1], Value::Text("d".into()));
+
+    match db.execute("PRAGMA result_cache_size = 0;").unwrap() {
+        ExecuteResult::Pragma { value, .. } => assert_eq!(value, Value::Integer(0)),
+        other => panic!("unexpected result {:?}", other),
+    }
+    nearest(&mut db);
+    nearest(&mut db);
+    assert_ne!(index(&db).as_deref(), Some("result cache"));
+}
+
+#[test]
+fn test_materialized_view() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("views.db");
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), category TEXT, score INTEGER);").unwrap();
+    db.execute("INSERT INTO docs (embedding, category, score) VALUES ([1.0, 0.0], 'a', 1), ([0.0, 1.0], 'a', 3), ([1.0, 1.0], 'b', 5);").unwrap();
+    db.execute("CREATE MATERIALIZED VIEW stats AS SELECT category, COUNT(*) AS n, SUM(score) AS total FROM docs GROUP BY category;").unwrap();
+
+    let stats = |db: &mut Database| match db.execute("SELECT * FROM stats ORDER BY category;").unwrap() {
+        ExecuteResult::Select { rows, .. } => rows.into_iter().map(|row| row.values).collect::<Vec<_>>(),
+        other => panic!("unexpected result {:?}", other),
+    };
+    let group = |category: &str, n: i64, total: f64| vec![Value::Text(category.into()), Value::Integer(n), Value::Float(total)];
+    assert_eq!(stats(&mut db), [group("a", 2, 4.0), group("b", 1, 5.0)]);
+    assert_eq!(db.last_query_stats().unwrap().index.as_deref(), Some("materialized view"));
+
+    db.execute("INSERT INTO docs (embedding, category, score) VALUES ([0.5, 0.5], 'c', 2);").unwrap();
+    db.execute("UPDATE docs SET category = 'b' WHERE score = 1;").unwrap();
+    db.execute("DELETE FROM docs WHERE score = 3;").unwrap();
+    assert_eq!(stats(&mut db), [group("b", 2, 6.0), group("c", 1, 2.0)]);
+    match db.execute("SELECT total FROM stats WHERE n > 1;").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows[0].values, [Value::Float(6.0)]),
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    db.save().unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(stats(&mut db), [group("b", 2, 6.0), group("c", 1, 2.0)]);
+
+    assert!(db.execute("CREATE TABLE stats (embedding VECTOR(2));").is_err());
+    assert!(db.execute("CREATE MATERIALIZED VIEW top AS SELECT category, MAX(score) FROM docs GROUP BY category;").is_err());
+    db.execute("DROP MATERIALIZED VIEW stats;").unwrap();
+    assert!(db.execute("SELECT * FROM stats;").is_err());
+    assert!(db.execute("DROP MATERIALIZED VIEW stats;").is_err());
+    db.execute("DROP MATERIALIZED VIEW IF EXISTS stats;").unwrap();
+}
+
+#[test]
+fn test_explain_and_index_lookups() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), slug TEXT UNIQUE, score INTEGER);").unwrap();
+    for i in 0..30 {
+        db.execute(&format!("INSERT INTO docs (embedding, slug, score) VALUES ([{}.0, 1.0], 's{}', {});", i, i, i % 3)).unwrap();
+    }
+    let plan = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+        ExecuteResult::Explain { plan } => plan,
+        other => panic!("unexpected result {:?}", other),
+    };
+
+    let explained = plan(&mut db, "EXPLAIN SELECT * FROM docs WHERE score = 1 AND slug = 's4' ORDER BY score DESC LIMIT 5;");
+    assert_eq!(explained.access, AccessPath::UniqueIndex { column: "slug".into(), ids: vec![5] });
+    assert_eq!(explained.filters, ["score = 1"]);
+    assert_eq!(explained.steps, ["sort by score DESC", "limit 5"]);
+    assert!(explained.to_string().starts_with("docs: unique index on slug (1 ids)\n  filter: score = 1\n"));
+
+    db.execute("SELECT * FROM docs WHERE slug = 's4';").unwrap();
+    let stats = db.last_query_stats().unwrap();
+    assert_eq!((stats.rows_scanned, stats.rows_returned, stats.index.as_deref()), (1, 1, Some("unique index on slug")));
+    assert_eq!(plan(&mut db, "EXPLAIN DELETE FROM docs WHERE score > 1;").access, AccessPath::FullScan);
+    assert_eq!(plan(&mut db, "EXPLAIN SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 1.0];").estimated_rows, 10);
+
+    // The index follows updates and deletes, and UPDATE keeps UNIQUE
+    db.execute("UPDATE docs SET slug = 'renamed' WHERE slug = 's4';").unwrap();
+    assert_eq!(plan(&mut db, "EXPLAIN SELECT * FROM docs WHERE slug = 's4';").estimated_rows, 0);
+    assert!(matches!(db.execute("UPDATE docs SET slug = 's5' WHERE slug = 'renamed';"), Err(MarsError::ConstraintViolation(_))));
+    assert!(matches!(db.execute("UPDATE docs SET slug = 'x' WHERE score = 1;"), Err(MarsError::ConstraintViolation(_))));
+    db.execute("DELETE FROM docs WHERE slug = 's5';").unwrap();
+    db.execute("INSERT INTO docs (embedding, slug, score) VALUES ([0.0, 0.0], 's5', 9);").unwrap();
+    match db.execute("SELECT score FROM docs WHERE slug IN ('renamed', 's5');").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 2),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_foreign_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("fk.db");
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY, embedding VECTOR(2), slug TEXT UNIQUE);").unwrap();
+    db.execute("CREATE TABLE chunks (embedding VECTOR(2), doc_id INTEGER REFERENCES docs ON DELETE CASCADE, \
+                slug TEXT REFERENCES docs(slug) ON DELETE SET NULL);").unwrap();
+    // The parent column must be id or UNIQUE
+    assert!(matches!(db.execute("CREATE TABLE notes (embedding VECTOR(2), chunk_id INTEGER REFERENCES chunks);"), Err(MarsError::ColumnNotFound(_))));
+    db.execute("CREATE TABLE pins (embedding VECTOR(2), doc_id INTEGER REFERENCES docs(id));").unwrap();
+    assert!(matches!(db.execute("CREATE TABLE bad (embedding VECTOR(2), ref INTEGER REFERENCES missing);"), Err(MarsError::TableNotFound(_))));
+    assert!(db.execute("CREATE TABLE bad (embedding VECTOR(2), ref INTEGER REFERENCES chunks(doc_id));").is_err());
+
+    db.execute("INSERT INTO docs (embedding, slug) VALUES ([0.0, 0.0], 'a'), ([1.0, 0.0], 'b');").unwrap();
+    db.execute("INSERT INTO chunks (embedding, doc_id, slug) VALUES ([0.0, 0.0], 1, 'a'), ([0.0, 1.0], 2, 'a'), ([1.0, 1.0], 2, NULL);").unwrap();
+    assert!(matches!(db.execute("INSERT INTO chunks (embedding, doc_id) VALUES ([0.0, 0.0], 9);"), Err(MarsError::ConstraintViolation(_))));
+    assert!(matches!(db.execute("INSERT INTO chunks (embedding, slug) VALUES ([0.0, 0.0], 'z');"), Err(MarsError::ConstraintViolation(_))));
+    assert!(matches!(db.execute("UPDATE chunks SET doc_id = 7 WHERE doc_id = 1;"), Err(MarsError::ConstraintViolation(_))));
+    assert!(matches!(db.execute("UPDATE docs SET slug = 'c' WHERE slug = 'a';"), Err(MarsError::ConstraintViolation(_))));
+    assert!(matches!(db.execute("DROP TABLE docs;"), Err(MarsError::ConstraintViolation(_))));
+
+    // RESTRICT on pins blocks the delete before any cascade happens
+    db.execute("INSERT INTO pins (embedding, doc_id) VALUES ([0.0, 0.0], 1);").unwrap();
+    assert!(matches!(db.execute("DELETE FROM docs WHERE id = 1;"), Err(MarsError::ConstraintViolation(_))));
+    assert_eq!(db.get_table("chunks").unwrap().len(), 3);
+    db.execute("DELETE FROM pins;").unwrap();
+
+    db.save().unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(db.get_table("chunks").unwrap().foreign_keys().len(), 2);
+
+    // Deleting doc 1 cascades to its chunk and clears the slug of doc 2's
+    db.execute("DELETE FROM docs WHERE slug = 'a';").unwrap();
+    match db.execute("SELECT doc_id, slug FROM chunks;").unwrap() {
+        ExecuteResult::Select { rows, .. } => {
+            assert_eq!(rows.len(), 2);
+            assert!(rows.iter().all(|row| row.values == [Value::Integer(2), Value::Null]));
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    db.execute("DELETE FROM docs;").unwrap();
+    assert_eq!(db.get_table("chunks").unwrap().len(), 0);
+}
+
+#[test]
+fn test_analyze_statistics() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("analyze.db");
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), category TEXT, score INTEGER);").unwrap();
+    db.execute("CREATE TABLE tags (embedding VECTOR(2), score INTEGER, tag TEXT);").unwrap();
+    for i in 0..40 {
+        db.execute(&format!("INSERT INTO docs (embedding, category, score) VALUES ([{}.0, 1.0], 'c{}', {});", i, i % 2, i)).unwrap();
+    }
+    db.execute("INSERT INTO tags (embedding, score, tag) VALUES ([0.0, 0.0], 3, 'three');").unwrap();
+    let estimate = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+        ExecuteResult::Explain { plan } => plan.estimated_rows,
+        other => panic!("unexpected result {:?}", other),
+    };
+    assert_eq!(estimate(&mut db, "EXPLAIN SELECT * FROM docs WHERE category = 'c1';"), 4);
+
+    assert!(matches!(db.execute("ANALYZE docs;").unwrap(), ExecuteResult::Analyze { tables } if tables == ["docs"]));
+    assert!(matches!(db.execute("ANALYZE missing;"), Err(MarsError::TableNotFound(_))));
+    assert_eq!(estimate(&mut db, "EXPLAIN SELECT * FROM docs WHERE category = 'c1';"), 20);
+    // The histogram places a quarter of the scores at 30 or above
+    assert!((9..=11).contains(&estimate(&mut db, "EXPLAIN SELECT * FROM docs WHERE score >= 30;")));
+
+    // Statistics are saved with the table
+    db.save().unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(db.get_table("docs").unwrap().stats().unwrap().columns["category"].distinct, 2);
+    assert!(db.get_table("tags").unwrap().stats().is_none());
+    assert!(matches!(db.execute("ANALYZE;").unwrap(), ExecuteResult::Analyze { tables } if tables == ["docs", "tags"]));
+
+    // The join builds its hash table from the smaller side either way round
+    for sql in ["SELECT * FROM docs JOIN tags ON docs.score = tags.score;", "SELECT * FROM tags JOIN docs ON tags.score = docs.score;"] {
+        match db.execute(sql).unwrap() {
+            ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 1),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_concurrent_statements() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard.db");
+    let mut shard = Database::open(&path).unwrap();
+    shard.execute("CREATE TABLE docs (embedding VECTOR(2), category TEXT, score FLOAT);").unwrap();
+    shard.execute("INSERT INTO docs (embedding, category, score) VALUES ([1.0, 0.0], 'a', 1.0), ([0.0, 1.0], 'b', 2.0);").unwrap();
+    shard.save().unwrap();
+    drop(shard);
+
+    let db = ConcurrentDatabase::in_memory();
+    let mut conn = db.connect();
+    let rows = |conn: &mut pardusdb::Connection, sql: &str| match conn.execute(sql).unwrap() {
+        ExecuteResult::Select { rows, .. } => rows.into_iter().map(|row| row.values).collect::<Vec<_>>(),
+        other => panic!("unexpected result {:?}", other),
+    };
+    conn.execute("CREATE TABLE docs (embedding VECTOR(2), category TEXT, score FLOAT);").unwrap();
+    assert_eq!(rows(&mut conn, "SELECT last_insert_rowid();"), [[Value::Null]]);
+
+    // BULK LOAD defers indexing, also for tables created during the load
+    assert!(matches!(conn.execute("BEGIN BULK LOAD;").unwrap(), ExecuteResult::BeginBulkLoad));
+    assert!(conn.execute("BEGIN BULK LOAD;").is_err());
+    conn.execute("CREATE TABLE notes (embedding VECTOR(2));").unwrap();
+    conn.execute("INSERT INTO docs (embedding, category, score) VALUES ([1.0, 1.0], 'a', 3.0);").unwrap();
+    conn.execute("INSERT INTO notes (embedding) VALUES ([1.0, 1.0]);").unwrap();
+    assert!(conn.search_similar("notes", &[1.0, 1.0], 1, 10).unwrap().is_empty());
+    assert!(matches!(conn.execute("END BULK LOAD;").unwrap(), ExecuteResult::EndBulkLoad { indexed: 2 }));
+    assert_eq!(conn.search_similar("notes", &[1.0, 1.0], 1, 10).unwrap().len(), 1);
+
+    // last_insert_rowid() is per connection
+    assert_eq!(rows(&mut conn, "SELECT last_insert_rowid();"), [[Value::Integer(1)]]);
+    assert_eq!(rows(&mut db.connect(), "SELECT last_insert_rowid();"), [[Value::Null]]);
+
+    conn.execute(&format!("ATTACH DATABASE '{}' AS shard;", path.display())).unwrap();
+    assert!(matches!(conn.execute("INSERT INTO docs SELECT * FROM shard.docs;").unwrap(), ExecuteResult::Insert { id: 3 }));
+    assert_eq!(conn.last_insert_id(), Some(3));
+    assert!(matches!(conn.execute("DETACH DATABASE shard;").unwrap(), ExecuteResult::Detach { .. }));
+    assert!(conn.execute("INSERT INTO docs SELECT * FROM shard.docs;").is_err());
+    // Attachments belong to the connection
+    assert!(db.connect().execute("DETACH DATABASE shard;").is_err());
+
+    conn.execute("CREATE MATERIALIZED VIEW stats AS SELECT category, COUNT(*) AS n, SUM(score) AS total FROM docs GROUP BY category;").unwrap();
+    let stats = rows(&mut db.connect(), "SELECT category, n, total FROM stats ORDER BY category;");
+    assert_eq!(stats, [
+        vec![Value::Text("a".into()), Value::Integer(2), Value::Float(4.0)],
+        vec![Value::Text("b".into()), Value::Integer(1), Value::Float(2.0)],
+    ]);
+    conn.execute("DROP MATERIALIZED VIEW stats;").unwrap();
+    assert!(matches!(conn.execute("SELECT * FROM stats;"), Err(MarsError::TableNotFound(_))));
+
+    assert!(matches!(conn.execute("ANALYZE;").unwrap(), ExecuteResult::Analyze { tables } if tables == ["docs", "notes"]));
+    match conn.execute("EXPLAIN SELECT * FROM docs WHERE category = 'a';").unwrap() {
+        ExecuteResult::Explain { plan } => assert_eq!(plan.estimated_rows, 2),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(matches!(conn.execute("REINDEX docs;").unwrap(), ExecuteResult::Reindex { .. }));
+    assert!(matches!(conn.execute("CHECK RECALL ON docs SAMPLE 3 LIMIT 1;").unwrap(), ExecuteResult::CheckRecall { .. }));
+
+    // PRAGMA reads and changes this connection's settings, like SET
+    assert!(matches!(conn.execute("PRAGMA max_rows;").unwrap(), ExecuteResult::Pragma { value: Value::Integer(0), .. }));
+    assert!(matches!(conn.execute("PRAGMA max_rows = 1;").unwrap(), ExecuteResult::Pragma { value: Value::Integer(1), .. }));
+    assert_eq!(conn.max_rows(), Some(1));
+    assert!(matches!(conn.execute("PRAGMA unknown;"), Err(MarsError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_auto_increment() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("autoincrement.db");
+    let mut db = Database::open(&path).unwrap();
+    let last_rowid = |db: &mut Database| match db.execute("SELECT last_insert_rowid();").unwrap() {
+        ExecuteResult::Select { rows, .. } => rows[0].values[0].clone(),
+        other => panic!("unexpected result {:?}", other),
+    };
+    assert_eq!(db.last_insert_id(), None);
+    assert_eq!(last_rowid(&mut db), Value::Null);
+
+    db.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY AUTOINCREMENT START 1000 STEP 5, embedding VECTOR(2));").unwrap();
+    db.execute("CREATE TABLE notes (key TEXT PRIMARY KEY AUTOINCREMENT, embedding VECTOR(2), body TEXT);").unwrap();
+    assert!(db.execute("CREATE TABLE bad (id INTEGER AUTOINCREMENT, key TEXT AUTOINCREMENT, embedding VECTOR(2));").is_err());
+
+    db.execute("INSERT INTO docs (embedding) VALUES ([0.0, 0.0]), ([1.0, 0.0]);").unwrap();
+    assert_eq!(db.last_insert_id(), Some(1005));
+    assert_eq!(last_rowid(&mut db), Value::Integer(1005));
+    assert_eq!(db.insert_direct("docs", vec![2.0, 0.0], Vec::new()).unwrap(), 1010);
+
+    // TEXT keys left out get a random UUID; given ones are kept and must be unique
+    db.execute("INSERT INTO notes (embedding, body) VALUES ([0.0, 0.0], 'first'), ([1.0, 1.0], 'second');").unwrap();
+    db.execute("INSERT INTO notes (key, embedding, body) VALUES ('mine', [2.0, 2.0], 'third');").unwrap();
+    assert!(matches!(db.execute("INSERT INTO notes (key, embedding) VALUES ('mine', [3.0, 3.0]);"), Err(MarsError::ConstraintViolation(_))));
+    match db.execute("SELECT key FROM notes WHERE body = 'first';").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert!(matches!(&rows[0].values[0], Value::Text(key) if key.len() == 36)),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(last_rowid(&mut db), Value::Integer(3));
+
+    // The sequence continues after a reopen
+    db.save().unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    db.execute("INSERT INTO docs (embedding) VALUES ([3.0, 0.0]);").unwrap();
+    assert_eq!(db.last_insert_id(), Some(1015));
+    match db.execute("SELECT id FROM docs WHERE id = 1015;").unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows.len(), 1),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_uuid_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("uuid.db");
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (id UUID PRIMARY KEY DEFAULT gen_random_uuid(), embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("CREATE TABLE chunks (embedding VECTOR(2), doc UUID REFERENCES docs ON DELETE CASCADE);").unwrap();
+
+    let given = "0b5e6b8a-1d2c-4f3e-9a8b-7c6d5e4f3a2b";
+    db.execute(&format!("INSERT INTO docs (id, embedding, title) VALUES ('{}', [0.0, 0.0], 'given');", given)).unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'generated');").unwrap();
+    assert!(matches!(db.execute("INSERT INTO docs (id, embedding) VALUES ('xyz', [0.0, 1.0]);"), Err(MarsError::InvalidFormat(_))));
+    let generated = match db.execute("SELECT id FROM docs WHERE title = 'generated';").unwrap() {
+        ExecuteResult::Select { rows, .. } => match &rows[0].values[0] {
+            Value::Uuid(uuid) => *uuid,
+            other => panic!("expected a UUID, got {:?}", other),
+        },
+        other => panic!("unexpected result {:?}", other),
+    };
+    assert_ne!(generated.to_string(), given);
+
+    db.execute(&format!("INSERT INTO chunks (embedding, doc) VALUES ([0.0, 0.0], '{}');", given.replace('-', ""))).unwrap();
+    assert!(matches!(
+        db.execute(&format!("INSERT INTO chunks (embedding, doc) VALUES ([0.0, 0.0], '{}');", pardusdb::Uuid::new_v4())),
+        Err(MarsError::ConstraintViolation(_))
+    ));
+
+    // UUIDs survive a reopen and match their text in WHERE
+    db.save().unwrap();
+    drop(db);
+    let mut db = Database::open(&path).unwrap();
+    match db.execute(&format!("SELECT title FROM docs WHERE id = '{}';", given.to_uppercase())).unwrap() {
+        ExecuteResult::Select { rows, .. } => assert_eq!(rows[0].values[0], Value::Text("given".into())),
+        other => panic!("unexpected result {:?}", other),
+    }
+    db.execute(&format!("UPDATE docs SET title = 'renamed' WHERE id IN ('{}', '{}');", given, generated)).unwrap();
+    assert!(db.execute("UPDATE docs SET id = 'bad' WHERE title = 'renamed';").is_err());
+    db.execute(&format!("DELETE FROM docs WHERE id = '{}';", given)).unwrap();
+    assert_eq!(db.get_table("chunks").unwrap().len(), 0);
+    assert_eq!(db.get_table("docs").unwrap().len(), 1);
+}
+
+#[test]
+fn test_update_expressions() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY, embedding VECTOR(2), title TEXT, views INTEGER, score FLOAT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title, views, score) VALUES ([0.0, 0.0], ' rust ', 10, 1.0), ([1.0, 0.0], 'go', 0, 0.5), ([0.0, 1.0], 'zig', NULL, 2.0);").unwrap();
+
+    let result = db.execute("UPDATE docs SET views = views + 1, score = score * 0.5 - (views % 4) WHERE score >= 1.0;").unwrap();
+    assert!(matches!(result, ExecuteResult::Update { count: 2 }));
+    db.execute("UPDATE docs SET title = UPPER(TRIM(title)) WHERE id = 1;").unwrap();
+
+    let values = |db: &mut Database, id: u64| match db.execute(&format!("SELECT title, views, score FROM docs WHERE id = {};", id)).unwrap() {
+        ExecuteResult::Select { rows, .. } => rows[0].values.clone(),
+        other => panic!("unexpected result {:?}", other),
+    };
+    // Both assignments saw the old `views`
+    assert_eq!(values(&mut db, 1), [Value::Text("RUST".into()), Value::Integer(11), Value::Float(-1.5)]);
+    assert_eq!(values(&mut db, 2), [Value::Text("go".into()), Value::Integer(0), Value::Float(0.5)]);
+    assert_eq!(values(&mut db, 3), [Value::Text("zig".into()), Value::Null, Value::Null]);
+
+    assert!(matches!(db.execute("UPDATE docs SET views = clicks + 1;"), Err(MarsError::ColumnNotFound(_))));
+
+    // Failed arithmetic is an error rather than a NULL, and changes no row
+    assert!(matches!(db.execute("UPDATE docs SET views = views / 0;"), Err(MarsError::Arithmetic(_))));
+    assert!(matches!(db.execute("UPDATE docs SET score = title * 2 WHERE id = 2;"), Err(MarsError::Arithmetic(_))));
+    assert_eq!(values(&mut db, 1), [Value::Text("RUST".into()), Value::Integer(11), Value::Float(-1.5)]);
+    assert_eq!(values(&mut db, 2), [Value::Text("go".into()), Value::Integer(0), Value::Float(0.5)]);
+
+    // Integer overflow carries on in floating point
+    db.execute("UPDATE docs SET score = views * 9223372036854775807 WHERE id = 1;").unwrap();
+    assert_eq!(values(&mut db, 1)[2], Value::Float(11.0 * i64::MAX as f64));
+}
+
+#[test]
+fn test_insert_or_ignore_replace() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY, embedding VECTOR(2), slug TEXT UNIQUE, title TEXT);").unwrap();
+    db.execute("CREATE TABLE chunks (embedding VECTOR(2), doc TEXT REFERENCES docs(slug));").unwrap();
+    db.execute("INSERT INTO docs (embedding, slug, title) VALUES ([1.0, 0.0], 'rust', 'Rust'), ([0.0, 1.0], 'go', 'Go');").unwrap();
+    db.execute("INSERT INTO chunks (embedding, doc) VALUES ([1.0, 0.0], 'rust');").unwrap();
+
+    assert!(matches!(db.execute("INSERT INTO docs (embedding, slug) VALUES ([0.0, 0.0], 'rust');"), Err(MarsError::ConstraintViolation(_))));
+    db.execute("INSERT OR IGNORE INTO docs (embedding, slug, title) VALUES ([0.0, 0.0], 'rust', 'ignored'), ([0.5, 0.5], 'zig', 'Zig');").unwrap();
+    assert_eq!(db.get_table("docs").unwrap().len(), 3);
+    assert_eq!(db.last_insert_id(), Some(3));
+
+    // A statement whose every row conflicts says so instead of reporting an id
+    let result = db.execute("INSERT OR IGNORE INTO docs (embedding, slug) VALUES ([0.0, 0.0], 'go');").unwrap();
+    assert!(matches!(result, ExecuteResult::Ignored { count: 1 }));
+    assert_eq!(db.last_insert_id(), Some(3));
+
+    // REPLACE keeps the row id, so the chunk referencing it stays valid
+    let result = db.execute("INSERT OR REPLACE INTO docs (embedding, slug, title) VALUES ([-1.0, 0.0], 'rust', 'Rust 2024');").unwrap();
+    assert!(matches!(result, ExecuteResult::Insert { id: 1 }));
+    assert_eq!(db.get_table("docs").unwrap().len(), 3);
+    match db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [-1.0, 0.0] LIMIT 1;").unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => {
+            assert_eq!((results[0].0.id, results[0].1), (1, 0.0));
+            assert_eq!(results[0].0.values[3], Value::Text("Rust 2024".into()));
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(db.get_table("chunks").unwrap().len(), 1);
+}
+
+#[test]
+fn test_bulk_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bulk_load.db");
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([0.0, 1.0], 'before');").unwrap();
+
+    assert!(matches!(db.execute("BEGIN BULK LOAD;").unwrap(), ExecuteResult::BeginBulkLoad));
+    assert!(db.execute("BEGIN BULK LOAD;").is_err());
+    db.execute("CREATE TABLE notes (embedding VECTOR(2));").unwrap();
+    for i in 0..100 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, 0.0], 'doc_{}');", i, i)).unwrap();
+    }
+    db.execute("INSERT INTO notes (embedding) VALUES ([1.0, 1.0]);").unwrap();
+    assert!(db.search_similar("notes", &[1.0, 1.0], 1, 10).unwrap().is_empty());
+
+    // Rows saved mid-load are indexed when the file is reopened
+    db.save().unwrap();
+    let reopened = Database::open_readonly(&path).unwrap();
+    assert_eq!(reopened.search_similar("docs", &[42.0, 0.0], 1, 64).unwrap()[0].0, 44);
+
+    assert!(matches!(db.execute("END BULK LOAD;").unwrap(), ExecuteResult::EndBulkLoad { indexed: 101 }));
+    assert!(db.execute("END BULK LOAD;").is_err());
+    assert_eq!(db.search_similar("docs", &[42.0, 0.0], 1, 64).unwrap()[0].0, 44);
+    assert_eq!(db.search_similar("notes", &[1.0, 1.0], 1, 10).unwrap().len(), 1);
+}
+
+#[test]
+fn test_reindex() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY, embedding VECTOR(2), title TEXT);").unwrap();
+    for i in 0..200 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, {}.0], 'doc_{}');", i % 20, i / 20, i)).unwrap();
+    }
+    assert!(matches!(db.execute("DELETE FROM docs WHERE id <= 150;").unwrap(), ExecuteResult::Delete { count: 150 }));
+
+    match db.execute("REINDEX docs;").unwrap() {
+        ExecuteResult::Reindex { table, before, after } => {
+            assert_eq!(table, "docs");
+            assert_eq!((before.nodes, after.nodes), (50, 50));
+            assert_eq!((after.free_slots, after.dangling, after.isolated), (0, 0, 0));
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(db.search_similar("docs", &[10.0, 9.0], 1, 64).unwrap()[0].0, 191);
+    assert!(matches!(db.execute("REINDEX missing;"), Err(MarsError::TableNotFound(_))));
+}
+
+#[test]
+fn test_check_recall() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    for i in 0..300 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, {}.0], 'doc_{}');", i % 20, i / 20, i)).unwrap();
+    }
+
+    match db.execute("CHECK RECALL ON docs SAMPLE 30 LIMIT 5;").unwrap() {
+        ExecuteResult::CheckRecall { table, report } => {
+            assert_eq!((table.as_str(), report.queries, report.k), ("docs", 30, 5));
+            assert!(report.recall > 0.9, "{:?}", report);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(matches!(db.execute("CHECK RECALL ON missing;"), Err(MarsError::TableNotFound(_))));
+}
+
+#[test]
+fn test_int8_vector_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let vector = |i: usize| -> Vec<f32> { (0..64).map(|d| ((i * 31 + d * 17) % 97) as f32 / 97.0 - 0.5).collect() };
+
+    let mut sizes = Vec::new();
+    for int8 in [false, true] {
+        let path = dir.path().join(format!("vectors{}.db", int8));
+        let mut db = Database::open(&path).unwrap();
+        db.execute("CREATE TABLE docs (embedding VECTOR(64), title TEXT);").unwrap();
+        assert!(matches!(db.execute("ALTER TABLE docs SET VECTOR STORAGE INT8;"), Err(MarsError::InvalidConfig(_))));
+        for i in 0..500 {
+            db.insert_direct("docs", vector(i), vec![("title", Value::Text(format!("doc_{}", i)))]).unwrap();
+        }
+        if int8 {
+            match db.execute("ALTER TABLE docs SET VECTOR STORAGE INT8 CALIBRATE SAMPLE 100;").unwrap() {
+                ExecuteResult::Calibrate { table, report } => {
+                    assert_eq!((table.as_str(), report.sampled, report.queries, report.k), ("docs", 100, 100, 10));
+                    assert!(report.recall > 0.8, "{:?}", report);
+                    assert!(report.mean_error > 0.0 && report.mean_error < 0.01, "{:?}", report);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        let stored = db.get_table("docs").unwrap().get(8).unwrap().values[0].clone();
+        let found = db.search_similar("docs", &vector(7), 1, 64).unwrap();
+        assert_eq!(found[0].0, 8);
+        db.save().unwrap();
+        drop(db);
+        sizes.push(std::fs::metadata(&path).unwrap().len());
+
+        // Reopened tables hold the same rounded vectors and keep rounding new ones
+        let mut db = Database::open(&path).unwrap();
+        let table = db.get_table("docs").unwrap();
+        assert_eq!(table.quantizer().is_some(), int8);
+        assert_eq!(table.get(8).unwrap().values[0], stored);
+        let mut rounded = vector(500);
+        if let Some(quantizer) = table.quantizer() {
+            quantizer.round(&mut rounded);
+            assert_ne!(rounded, vector(500));
+        }
+        let id = db.insert_direct("docs", vector(500), vec![]).unwrap();
+        assert_eq!(db.get_table("docs").unwrap().get(id).unwrap().values[0], Value::Vector(rounded));
+        drop(db);
+        let db = ConcurrentDatabase::open(&path).unwrap();
+        assert_eq!(db.read().tables["docs"].get(8).unwrap().values[0], stored);
+    }
+    assert!(sizes[1] * 2 < sizes[0], "int8 {} bytes, float32 {} bytes", sizes[1], sizes[0]);
+
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2));").unwrap();
+    db.execute("INSERT INTO docs (embedding) VALUES ([0.0, 0.0]);").unwrap();
+    db.execute("INSERT INTO docs (embedding) VALUES ([1.0, 2.0]);").unwrap();
+    db.execute("ALTER TABLE docs SET VECTOR STORAGE INT8;").unwrap();
+    assert!(matches!(db.execute("ALTER TABLE docs SET VECTOR STORAGE FLOAT32;").unwrap(), ExecuteResult::AlterTable { rebuilt: false, .. }));
+    assert!(db.get_table("docs").unwrap().quantizer().is_none());
+    db.execute("INSERT INTO docs (embedding) VALUES ([0.123, 9.0]);").unwrap();
+    assert_eq!(db.get_table("docs").unwrap().get(3).unwrap().values[0], Value::Vector(vec![0.123, 9.0]));
+}
+
+#[test]
+fn test_projection() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("docs.db");
+    // 32-dimensional vectors that only vary along 4 directions
+    let vector = |i: usize| -> Vec<f32> {
+        let weights = [(i % 7) as f32, (i % 11) as f32 * 0.5, (i % 13) as f32 * 0.25, (i / 50) as f32];
+        (0..32).map(|d| weights.iter().enumerate().map(|(w, x)| x * (((d * (w + 3)) % 5) as f32 - 2.0)).sum()).collect()
+    };
+
+    let mut db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE docs (embedding VECTOR(32), title TEXT);").unwrap();
+    for i in 0..300 {
+        db.insert_direct("docs", vector(i), vec![("title", Value::Text(format!("doc_{}", i)))]).unwrap();
+    }
+    match db.execute("CREATE PROJECTION small ON docs(embedding) DIM 8 METHOD PCA SAMPLE 200;").unwrap() {
+        ExecuteResult::CreateProjection { name, table, retained } => {
+            assert_eq!((name.as_str(), table.as_str()), ("small", "docs"));
+            assert!(retained.unwrap() > 0.999, "{:?}", retained);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    let table = db.get_table("docs").unwrap();
+    assert_eq!((table.graph.dimension(), table.projection().unwrap().input_dimension()), (8, 32));
+    assert_eq!(table.get(1).unwrap().values[0].as_vector().unwrap().len(), 8);
+
+    // Inserts and queries take full-size vectors
+    let id = db.insert_direct("docs", vector(1000), vec![("title", Value::Text("new".into()))]).unwrap();
+    db.execute(&format!("UPDATE docs SET embedding = {:?} WHERE title = 'doc_5';", vector(2000))).unwrap();
+    assert_eq!(db.search_similar("docs", &vector(1000), 1, 64).unwrap()[0].0, id);
+    match db.execute(&format!("SELECT title FROM docs WHERE embedding SIMILARITY {:?} LIMIT 1;", vector(2000))).unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => assert_eq!(results[0].0.values, vec![Value::Text("doc_5".into())]),
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    assert!(matches!(db.execute("CREATE PROJECTION again ON docs(embedding) DIM 4;"), Err(MarsError::InvalidConfig(_))));
+    db.save().unwrap();
+    drop(db);
+
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(db.get_table("docs").unwrap().projection().unwrap().name, "small");
+    assert_eq!(db.search_similar("docs", &vector(1000), 1, 64).unwrap()[0].0, id);
+    let id = db.insert_direct("docs", vector(3000), vec![]).unwrap();
+    assert_eq!(db.search_similar("docs", &vector(3000), 1, 64).unwrap()[0].0, id);
+
+    db.execute("CREATE TABLE other (embedding VECTOR(32), title TEXT);").unwrap();
+    assert!(matches!(db.execute("CREATE PROJECTION p ON other(embedding) DIM 8;"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(
+        db.execute("CREATE PROJECTION p ON other(embedding) DIM 8 METHOD RANDOM;").unwrap(),
+        ExecuteResult::CreateProjection { retained: None, .. }
+    ));
+    assert!(matches!(db.execute("CREATE PROJECTION p ON other(title) DIM 8;"), Err(MarsError::InvalidConfig(_))));
+    assert!(matches!(db.execute("CREATE PROJECTION p ON docs(missing) DIM 8;"), Err(MarsError::ColumnNotFound(_))));
+    db.execute("CREATE TABLE big (embedding VECTOR(4));").unwrap();
+    assert!(matches!(db.execute("CREATE PROJECTION p ON big(embedding) DIM 4 METHOD RANDOM;"), Err(MarsError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_warmup() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(4), title TEXT);").unwrap();
+    assert_eq!(db.warmup("docs").unwrap(), 0);
+    for i in 0..50 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, 1.0, 0.0, 0.0], 'doc');", i)).unwrap();
+    }
+    assert!(db.warmup("docs").unwrap() >= 50 * 4 * size_of::<f32>());
+    assert!(matches!(db.warmup("missing"), Err(MarsError::TableNotFound(_))));
+}
+
+#[test]
+fn test_graph_stats() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    assert_eq!(db.graph_stats("docs").unwrap().components, 0);
+    for i in 0..60 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, {}.0], 'doc');", i % 10, i / 10)).unwrap();
+    }
+    let stats = db.graph_stats("docs").unwrap();
+    assert_eq!((stats.edges.nodes, stats.components, stats.largest_component, stats.unreachable), (60, 1, 60, 0));
+    assert_eq!(stats.degree_histogram.iter().sum::<usize>(), 60);
+    assert!(stats.mean_neighbor_distance > 0.0);
+    assert!(matches!(db.graph_stats("missing"), Err(MarsError::TableNotFound(_))));
+}
+
+#[test]
+fn test_component_repair_on_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("docs.sealed");
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    for i in 0..200 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, {}.0], 'doc');", i % 20, i / 20)).unwrap();
+    }
+    db.export_sealed(&path).unwrap();
+
+    let mut db = Database::open_readonly_mmap(&path).unwrap().with_component_repair();
+    let stats = db.graph_stats("docs").unwrap();
+    assert_eq!((stats.edges.nodes, stats.components, stats.unreachable), (200, 1, 0));
+    for i in 0..200 {
+        let query = vec![(i % 20) as f32, (i / 20) as f32];
+        let results = db.search_similar("docs", &query, 1, 64).unwrap();
+        assert_eq!(results[0].2, 0.0, "vector {} not found", i);
+    }
+    assert_eq!(db.bridge_components(), 0);
+}
+
+#[test]
+fn test_execute_result_json() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    let insert = db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'a');").unwrap();
+    assert_eq!(serde_json::to_value(&insert).unwrap(), serde_json::json!({"type": "insert", "id": 1}));
+
+    let select = db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 0.0] LIMIT 1;").unwrap();
+    let json = serde_json::to_value(&select).unwrap();
+    assert_eq!(json["type"], "select_similar");
+    assert_eq!(json["results"][0][0]["values"], serde_json::json!([[1.0, 0.0], "a"]));
+    assert_eq!(json["results"][0][1], 0.0);
+    assert_eq!(json["metric"], "euclidean");
+    assert_eq!(json["schema"]["columns"][1]["name"], "title");
+
+    let tables = serde_json::to_value(db.execute("SHOW TABLES;").unwrap()).unwrap();
+    assert_eq!(tables["tables"][0], serde_json::json!({"name": "docs", "rows": 1, "dimension": 2}));
+}
+
+#[test]
+fn test_export_graph() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT);").unwrap();
+    for i in 0..30 {
+        db.execute(&format!("INSERT INTO docs (embedding, title) VALUES ([{}.0, 0.0], 'doc_{}');", i, i)).unwrap();
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("docs.dot");
+    db.get_table("docs").unwrap().export_graph_dot(&path, Some("title")).unwrap();
+    let dot = std::fs::read_to_string(&path).unwrap();
+    assert!(dot.contains("  30 [label=\"doc_29\"];"));
+    assert!(dot.contains(" -> ") && dot.trim_end().ends_with('}'));
+}
+
+#[test]
+fn test_vector_db_bridge() {
+    let vectors: VectorDB<f32, Euclidean> = VectorDB::in_memory(2);
+    for i in 0..20 {
+        vectors.insert([i as f32, 0.0]).unwrap();
+    }
+    let schema = Schema::new("docs")
+        .column("id", ColumnType::Integer)
+        .column("embedding", ColumnType::Vector(2))
+        .column("title", ColumnType::Text);
+    let mut db = Database::from_vector_db(schema, &vectors).unwrap();
+
+    db.execute("UPDATE docs SET title = 'five' WHERE id = 6;").unwrap();
+    match db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [5.1, 0.0] LIMIT 1;").unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => {
+            assert_eq!(results[0].0.values[2], Value::Text("five".into()));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([100.0, 0.0], 'new');").unwrap();
+    assert_eq!(db.get_table("docs").unwrap().len(), 21);
+
+    let index = db.vector_index::<Euclidean>("docs").unwrap();
+    let nearest = index.query(&[99.0, 0.0], 1).unwrap();
+    assert_eq!(nearest[0].0, 21);
+    assert_eq!(index.vector_db().len(), 21);
+    assert!(matches!(db.vector_index::<Cosine>("docs"), Err(MarsError::InvalidConfig(_))));
+
+    let wrong = Schema::new("docs").column("embedding", ColumnType::Vector(3));
+    assert!(matches!(Database::from_vector_db(wrong, &vectors), Err(MarsError::DimensionMismatch { .. })));
+}
+
+#[test]
+fn test_similarity_scores_follow_metric() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), title TEXT) METRIC cosine;").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([1.0, 0.0], 'same');").unwrap();
+    db.execute("INSERT INTO docs (embedding, title) VALUES ([-1.0, 0.0], 'opposite');").unwrap();
+    let result = db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [2.0, 0.0] LIMIT 2;").unwrap();
+    let rows = result.rows();
+    assert_eq!(rows[0].get::<String>("title").unwrap(), "same");
+    assert!((rows[0].score.unwrap() - 1.0).abs() < 1e-5);
+    assert!((rows[1].score.unwrap() + 1.0).abs() < 1e-5);
+    assert!((rows[1].distance.unwrap() - 2.0).abs() < 1e-5);
+    assert!(result.to_string().contains("(cosine)"));
+    assert!(result.to_string().contains("similarity=1.0000"));
+
+    db.execute("CREATE TABLE points (embedding VECTOR(2)) METRIC euclidean;").unwrap();
+    db.execute("INSERT INTO points (embedding) VALUES ([3.0, 4.0]);").unwrap();
+    let result = db.execute("SELECT * FROM points WHERE embedding SIMILARITY [0.0, 0.0] LIMIT 1;").unwrap();
+    assert_eq!(result.rows()[0].score, Some(5.0));
+    assert!(result.to_string().contains("distance=5.0000"));
+}
+
+#[test]
+fn test_parse_limits() {
+    let limits = ParseLimits { max_vector_len: 2, ..ParseLimits::default() };
+    let mut db = Database::in_memory().with_parse_limits(limits);
+    db.execute("CREATE TABLE docs (embedding VECTOR(3));").unwrap();
+    assert!(matches!(
+        db.execute("INSERT INTO docs (embedding) VALUES ([1.0, 2.0, 3.0]);"),
+        Err(MarsError::ParseError { .. })
+    ));
+
+    // A huge LIMIT must not reserve memory for that many results
+    db.set_parse_limits(ParseLimits::default());
+    db.execute("INSERT INTO docs (embedding) VALUES ([1.0, 2.0, 3.0]);").unwrap();
+    match db.execute("SELECT * FROM docs WHERE embedding SIMILARITY [1.0, 2.0, 3.0] LIMIT 999999999999999;").unwrap() {
+        ExecuteResult::SelectSimilar { results, .. } => assert_eq!(results.len(), 1),
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_execute_prepared() {
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    let insert = PreparedStatement::new("INSERT INTO docs (embedding, n) VALUES (?, ?);").unwrap();
+    for i in 0..20 {
+        db.execute_prepared(&insert, &[Value::Vector(vec![i as f32, 0.0]), Value::Integer(i)]).unwrap();
+    }
+
+    let search = PreparedStatement::new("SELECT * FROM docs WHERE embedding SIMILARITY ? LIMIT ?;").unwrap();
+    for (target, k) in [(3.0, 1), (15.0, 4)] {
+        match db.execute_prepared(&search, &[Value::Vector(vec![target, 0.0]), Value::Integer(k)]).unwrap() {
+            ExecuteResult::SelectSimilar { results, .. } => {
+                assert_eq!(results.len(), k as usize);
+                assert_eq!(results[0].0.values[1], Value::Integer(target as i64));
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+    assert!(db.execute_prepared(&search, &[Value::Vector(vec![1.0, 0.0])]).is_err());
+
+    let conn_db = ConcurrentDatabase::in_memory();
+    let mut conn = conn_db.connect();
+    conn.execute("CREATE TABLE docs (embedding VECTOR(2));").unwrap();
+    conn.execute_prepared(&PreparedStatement::new("INSERT INTO docs (embedding) VALUES ([?, ?]);").unwrap(), &[Value::Float(1.0), Value::Integer(2)]).unwrap();
+    assert_eq!(conn_db.read().tables["docs"].len(), 1);
+}
+
+#[test]
+fn test_statement_cache() {
+    // Off by default
+    let mut db = Database::in_memory();
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    db.execute("SELECT * FROM docs;").unwrap();
+    assert_eq!(db.statement_cache_size(), 0);
+    assert_eq!(db.statement_cache_stats(), Default::default());
+
+    let mut db = Database::in_memory().with_statement_cache(8);
+    db.execute("CREATE TABLE docs (embedding VECTOR(2), n INTEGER);").unwrap();
+    let insert = "INSERT INTO docs (embedding, n) VALUES ([1.0, 0.0], 1);";
+    for _ in 0..3 {
+        db.execute(insert).unwrap();
+    }
+    let stats = db.statement_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 2, 2));
+    assert_eq!(db.get_table("docs").unwrap().len(), 3);
+
+    // A hit runs the cached statement, not the one last parsed
+    let select = "SELECT n FROM docs WHERE n = 1;";
+    db.execute(select).unwrap();
+    db.execute("SELECT n FROM docs WHERE n = 2;").unwrap();
+    let hits = db.statement_cache_stats().hits;
+    let ExecuteResult::Select { rows, .. } = db.execute(select).unwrap() else { panic!("expected rows") };
+    assert_eq!(rows.len(), 3);
+    assert_eq!(db.statement_cache_stats().hits, hits + 1);
+
+    // Statements with placeholders still fail as plain SQL
+    assert!(db.execute("SELECT * FROM docs WHERE n = ?;").is_err());
+
+    db.execute("PRAGMA statement_cache_size = 1;").unwrap();
+    db.execute("SELECT * FROM docs;").unwrap();
+    db.execute("SELECT * FROM docs WHERE n = 1;").unwrap();
+    db.execute("SELECT * FROM docs;").unwrap();
+    let stats = db.statement_cache_stats();
+    assert_eq!((stats.hits, stats.entries), (0, 1));
+
+    db.set_statement_cache_size(0);
+    db.execute("SELECT * FROM docs;").unwrap();
+    assert_eq!(db.statement_cache_stats(), Default::default());
+}
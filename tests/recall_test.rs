@@ -0,0 +1,51 @@
+//! Recall regression tests: graph search quality against brute force
+
+use pardusdb::graph::{autotune, measure_recall};
+use pardusdb::{Euclidean, GraphConfig};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+fn random_vectors(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect())
+        .collect()
+}
+
+#[test]
+fn test_default_config_recall() {
+    let vectors = random_vectors(1000, 16, 42);
+    let queries = random_vectors(50, 16, 7);
+
+    let recall = measure_recall::<Euclidean>(&GraphConfig::default(), &vectors, &queries, 10);
+    println!("default recall@10: {:.3}", recall);
+    assert!(recall >= 0.9, "recall@10 regressed to {:.3}", recall);
+}
+
+#[test]
+fn test_autotuned_config_meets_target() {
+    let vectors = random_vectors(800, 8, 1);
+    let queries = random_vectors(30, 8, 2);
+
+    let config = autotune(&vectors, 0.95);
+    let recall = measure_recall::<Euclidean>(&config, &vectors, &queries, 10);
+    assert!(recall >= 0.9, "autotuned recall@10 was {:.3}", recall);
+}
+
+#[test]
+fn test_clustered_recall() {
+    // Tight, well separated clusters: a single entry point tends to get
+    // stuck in whichever cluster it starts in
+    let mut rng = StdRng::seed_from_u64(3);
+    let centers = random_vectors(20, 16, 11);
+    let around = |rng: &mut StdRng, center: &[f32]| -> Vec<f32> {
+        center.iter().map(|c| c * 50.0 + rng.gen_range(-0.5..0.5)).collect()
+    };
+
+    let vectors: Vec<Vec<f32>> = (0..1000).map(|i| around(&mut rng, &centers[i % 20])).collect();
+    let queries: Vec<Vec<f32>> = (0..40).map(|i| around(&mut rng, &centers[(i * 7) % 20])).collect();
+
+    let recall = measure_recall::<Euclidean>(&GraphConfig::default(), &vectors, &queries, 10);
+    println!("clustered recall@10: {:.3}", recall);
+    assert!(recall >= 0.9, "clustered recall@10 regressed to {:.3}", recall);
+}
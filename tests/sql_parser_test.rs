@@ -1,6 +1,6 @@
 //! Integration tests for SQL parsing
 
-use pardusdb::{parse, Command, ColumnType, Value, ComparisonOp, ConditionValue, SelectColumn};
+use pardusdb::{parse, parse_with_limits, Command, ColumnType, ScalarExpr, Value, ComparisonOp, ConditionValue, ParseLimits, SelectColumn};
 
 #[test]
 fn test_parse_create_table() {
@@ -8,7 +8,7 @@ fn test_parse_create_table() {
     let cmd = parse(sql).unwrap();
 
     match cmd {
-        Command::CreateTable { name, columns } => {
+        Command::CreateTable { name, columns, .. } => {
             assert_eq!(name, "users");
             assert_eq!(columns.len(), 2);
             assert_eq!(columns[0].name, "id");
@@ -26,7 +26,7 @@ fn test_parse_create_table_with_vector() {
     let cmd = parse(sql).unwrap();
 
     match cmd {
-        Command::CreateTable { name, columns } => {
+        Command::CreateTable { name, columns, .. } => {
             assert_eq!(name, "docs");
             assert_eq!(columns.len(), 2);
             assert_eq!(columns[0].data_type, ColumnType::Vector(768));
@@ -42,7 +42,7 @@ fn test_parse_insert() {
     let cmd = parse(sql).unwrap();
 
     match cmd {
-        Command::Insert { table, columns, values } => {
+        Command::Insert { table, columns, values, .. } => {
             assert_eq!(table, "users");
             assert_eq!(columns, vec!["name", "age"]);
             assert_eq!(values.len(), 1);  // One row
@@ -60,7 +60,7 @@ fn test_parse_insert_with_vector() {
     let cmd = parse(sql).unwrap();
 
     match cmd {
-        Command::Insert { table, columns, values } => {
+        Command::Insert { table, columns, values, .. } => {
             assert_eq!(table, "docs");
             assert_eq!(values[0][0], Value::Vector(vec![0.1, 0.2, 0.3]));
             assert_eq!(values[0][1], Value::Text("Test".to_string()));
@@ -75,7 +75,7 @@ fn test_parse_insert_multirow() {
     let cmd = parse(sql).unwrap();
 
     match cmd {
-        Command::Insert { table, columns, values } => {
+        Command::Insert { table, columns, values, .. } => {
             assert_eq!(table, "users");
             assert_eq!(values.len(), 2);  // Two rows
             assert_eq!(values[0][0], Value::Text("Alice".to_string()));
@@ -288,7 +288,7 @@ fn test_parse_update() {
             assert_eq!(table, "users");
             assert_eq!(assignments.len(), 1);
             assert_eq!(assignments[0].0, "name");
-            assert_eq!(assignments[0].1, Value::Text("Bob".to_string()));
+            assert_eq!(assignments[0].1, ScalarExpr::Literal(Value::Text("Bob".to_string())));
             assert!(where_clause.is_some());
         }
         _ => panic!("Expected Update"),
@@ -301,9 +301,10 @@ fn test_parse_delete() {
     let cmd = parse(sql).unwrap();
 
     match cmd {
-        Command::Delete { table, where_clause } => {
+        Command::Delete { table, where_clause, soft } => {
             assert_eq!(table, "users");
             assert!(where_clause.is_some());
+            assert!(!soft);
         }
         _ => panic!("Expected Delete"),
     }
@@ -407,3 +408,24 @@ fn test_parse_where_or() {
         _ => panic!("Expected Select"),
     }
 }
+
+#[test]
+fn test_parse_limits() {
+    let limits = ParseLimits { max_sql_len: 200, max_vector_len: 4, max_in_list: 3, max_depth: 8 };
+    let within = |sql: &str| parse_with_limits(sql, &limits).is_ok();
+
+    assert!(within("SELECT * FROM t WHERE e SIMILARITY [1, 2, 3, 4] LIMIT 1;"));
+    assert!(!within("SELECT * FROM t WHERE e SIMILARITY [1, 2, 3, 4, 5] LIMIT 1;"));
+    assert!(!within("SELECT * FROM t WHERE e SIMILARITY {1: 0.5, 2: 0.5, 3: 0.5, 4: 0.5, 5: 0.5} LIMIT 1;"));
+    assert!(within("SELECT * FROM t WHERE n IN (1, 2, 3);"));
+    assert!(!within("SELECT * FROM t WHERE n IN (1, 2, 3, 4);"));
+    assert!(!within(&format!("SELECT * FROM t WHERE title = '{}';", "x".repeat(200))));
+
+    assert!(within("UPDATE t SET n = ((n + 1) * 2);"));
+    assert!(!within(&format!("UPDATE t SET n = {}1{};", "(".repeat(9), ")".repeat(9))));
+    assert!(!within(&format!("SELECT * FROM t WHERE {}title{} = 'a';", "UPPER(".repeat(9), ")".repeat(9))));
+
+    // Defaults turn away nesting that would overflow the stack
+    let deep = format!("UPDATE t SET n = {}1{};", "(".repeat(100_000), ")".repeat(100_000));
+    assert!(parse(&deep).is_err());
+}